@@ -1,7 +1,23 @@
+use crate::cli::init::{self, InitOptions};
+use crate::cli::test_request::{TestRequest, TestResponse};
 use anyhow::Result;
 use clap::{ArgAction, Args, Parser, Subcommand};
 use log::{error, info};
-use minipx::config::{Config, RoutePatch};
+use minipx::acme_account::{self, AccountInfo};
+use minipx::config::manager::DrainStatus;
+use minipx::config::{Config, MAX_CAPTURE_BYTES, MAX_CAPTURE_DURATION_SECS, ProxyRoute, RoutePatch, SubrouteOverrides, SubroutePatch, caddyfile};
+use minipx::ipc::{self, IpcRequest, IpcResponse};
+use minipx::preflight::{self, Check, CheckStatus, PreflightOptions, PreflightReport};
+use minipx::proxy::capture::{CaptureOptions, CaptureStatus};
+use minipx::proxy::forwarder::{BindingState, forwarder_port};
+use minipx::proxy::route_table::{self, Resolution, RouteMatch, RouteTable};
+use minipx::proxy::unix_socket;
+use minipx::runtime_tuning::{self, RuntimeTuning};
+use minipx::systemd;
+use minipx::tls_events::{EXPIRY_WARNING_DAYS, TlsStatus};
+use minipx::utils::timestamp::{format_rfc3339, parse_rfc3339};
+use std::path::PathBuf;
+use time::OffsetDateTime;
 
 /// CLI-specific wrapper for ProxyRoute with clap Args support
 #[derive(Debug, Clone, Args)]
@@ -12,8 +28,16 @@ pub struct ProxyRouteArgs {
     #[arg(short = 'p', long = "path", default_value = "", help = "Path to route to (e.g. /api/v1)")]
     pub path: String,
 
-    #[arg(short = 'P', long = "port", help = "Port to route to, cannot be 80 or 443, and must be between 1 and 65535")]
-    pub port: u16,
+    #[arg(
+        short = 'P',
+        long = "port",
+        required_unless_present = "unix_socket",
+        help = "Port to route to, cannot be 80 or 443, and must be between 1 and 65535"
+    )]
+    pub port: Option<u16>,
+
+    #[arg(long = "unix-socket", conflicts_with = "port", help = "Unix domain socket the backend listens on (e.g. /run/app.sock), instead of a port")]
+    pub unix_socket: Option<PathBuf>,
 
     #[arg(short = 's', long = "ssl", default_value = "false", help = "Enable SSL")]
     pub ssl_enable: bool,
@@ -23,11 +47,58 @@ pub struct ProxyRouteArgs {
 
     #[arg(short = 'r', long = "redirect", default_value = "false", help = "Redirect HTTP to HTTPS")]
     pub redirect_to_https: bool,
+
+    #[arg(long = "upstream-host-header", help = "Host header sent to the backend (e.g. internal.company.lan); the connection still goes to --host")]
+    pub upstream_host_header: Option<String>,
+
+    #[arg(long = "upstream-sni", help = "TLS server name for an https backend, instead of --host")]
+    pub upstream_sni: Option<String>,
+
+    #[arg(
+        long = "allow-self-reference",
+        help = "Allow a backend that is one of minipx's own listeners on this machine (e.g. the web panel on localhost:6671)"
+    )]
+    pub allow_self_reference: bool,
+
+    #[arg(long = "active-from", value_parser = parse_timestamp, help = "Only serve the route from this RFC 3339 time on (e.g. 2026-12-24T18:00:00Z)")]
+    pub active_from: Option<String>,
+
+    #[arg(long = "active-until", value_parser = parse_timestamp, help = "Stop serving the route at this RFC 3339 time")]
+    pub active_until: Option<String>,
+}
+
+impl ProxyRouteArgs {
+    // Settings given on the command line, applied on top of a template. The switches can only turn
+    // settings on, and the default host is not treated as an override.
+    fn template_overrides(&self) -> RoutePatch {
+        RoutePatch {
+            host: Some(self.host.clone()).filter(|h| h != "localhost"),
+            path: Some(self.path.clone()).filter(|p| !p.is_empty()),
+            ssl_enable: self.ssl_enable.then_some(true),
+            redirect_to_https: self.redirect_to_https.then_some(true),
+            listen_port: self.listen_port,
+            upstream_host_header: self.upstream_host_header.clone(),
+            upstream_sni: self.upstream_sni.clone(),
+            allow_self_reference: self.allow_self_reference.then_some(true),
+            active_from: self.active_from.clone(),
+            active_until: self.active_until.clone(),
+            ..Default::default()
+        }
+    }
 }
 
-impl From<ProxyRouteArgs> for minipx::config::ProxyRoute {
+impl From<ProxyRouteArgs> for ProxyRoute {
     fn from(args: ProxyRouteArgs) -> Self {
-        minipx::config::ProxyRoute::new(args.host, args.path, args.port, args.ssl_enable, args.listen_port, args.redirect_to_https)
+        let mut route =
+            ProxyRoute::new(args.host, args.path, args.port.unwrap_or_default(), args.ssl_enable, args.listen_port, args.redirect_to_https);
+        route.set_unix_socket(args.unix_socket);
+        route.set_upstream_host_header(args.upstream_host_header);
+        route.set_upstream_sni(args.upstream_sni);
+        route.set_allow_self_reference(args.allow_self_reference);
+        // Already validated by the argument parser
+        route.set_active_from(args.active_from.as_deref().and_then(|at| parse_rfc3339(at).ok()));
+        route.set_active_until(args.active_until.as_deref().and_then(|at| parse_rfc3339(at).ok()));
+        route
     }
 }
 
@@ -40,6 +111,43 @@ pub struct MinipxArguments {
     pub(crate) verbose: bool,
     #[arg(short = 'w', long = "watch", help = "Watch the configuration file for changes")]
     pub(crate) watch_config: bool,
+    #[arg(
+        long = "init-default-config",
+        global = true,
+        help = "At startup, move a config file that fails to parse aside (<name>.corrupted.N) and start from the defaults instead of exiting"
+    )]
+    pub(crate) init_default_config: bool,
+    #[arg(
+        long = "fail-on-bind-error",
+        global = true,
+        help = "Exit with an error when port 80 or 443 cannot be bound after bind_failure_attempts, instead of retrying (bind_failure_policy: fail)"
+    )]
+    pub(crate) fail_on_bind_error: bool,
+    #[arg(
+        long = "log-file",
+        global = true,
+        help = "Also append log records to this file, which a separately running web panel can show (MINIPX_WEB_PROXY_LOG_FILE)"
+    )]
+    pub(crate) log_file: Option<PathBuf>,
+    #[arg(
+        long = "worker-threads",
+        global = true,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Threads running the proxy (overrides runtime.worker_threads; defaults to the number of cores, at most 32)"
+    )]
+    pub(crate) worker_threads: Option<u64>,
+    #[arg(
+        long = "max-blocking-threads",
+        global = true,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Most threads the proxy starts for blocking work such as file reads (overrides runtime.max_blocking_threads; default 512)"
+    )]
+    pub(crate) max_blocking_threads: Option<u64>,
+    #[arg(
+        long = "print-config-summary",
+        help = "Print the effective configuration summary (as logged at startup) and exit without starting servers"
+    )]
+    pub(crate) print_config_summary: bool,
     #[command(subcommand)]
     pub(crate) command: Option<MinipxCommands>,
 }
@@ -56,6 +164,73 @@ pub enum MinipxCommands {
         #[clap(subcommand)]
         command: ConfigCommands,
     },
+    #[clap(
+        name = "systemd-unit",
+        about = "Print a systemd unit for running minipx with the current config file",
+        long_about = "Print a reference minipx.service (Type=notify) that runs this binary with the current config file.\nWith --socket, print a companion minipx.socket instead: systemd then binds ports 80 and 443 and passes them to minipx."
+    )]
+    SystemdUnit {
+        /// Print the socket-activation unit (minipx.socket) instead of the service
+        #[arg(long = "socket")]
+        socket: bool,
+    },
+    #[clap(name = "status", about = "Show whether minipx is running (and the Windows service state)")]
+    Status {
+        /// Print the running instance's health report as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
+    #[clap(
+        name = "preflight",
+        about = "Check ports, cache_dir, DNS, the ACME email and the config before starting",
+        long_about = "Check that the configured ports can be bound, cache_dir is writable, backend hosts and ACME domains resolve\n(and point at this machine when a public IP check URL is set), the clock is in sync and the config is consistent.\nExits with 1 if any check fails."
+    )]
+    Preflight {
+        /// Print the report as JSON
+        #[arg(long = "json")]
+        json: bool,
+        /// URL answering with this machine's public IP (overrides public_ip_check_url)
+        #[arg(long = "public-ip-url")]
+        public_ip_url: Option<String>,
+    },
+    #[clap(
+        name = "upgrade",
+        about = "Warm restart: hand the running instance's ports to the binary now installed in its place (unix)",
+        long_about = "Start the binary now installed at the running instance's path with the same arguments and hand ports 80 and 443 over to it,\nso they never close. The old process stops accepting, finishes its requests and websocket tunnels (up to drain_timeout_secs) and exits.\nIf the new binary cannot take over, the old one keeps serving. Sending SIGUSR2 to the running instance does the same."
+    )]
+    Upgrade,
+    #[clap(name = "certs", about = "Inspect HTTPS certificates on the running instance")]
+    Certs {
+        #[clap(subcommand)]
+        command: CertCommands,
+    },
+    #[clap(name = "acme", about = "Manage the ACME (Let's Encrypt) account")]
+    Acme {
+        #[clap(subcommand)]
+        command: AcmeCommands,
+    },
+    #[cfg(windows)]
+    #[clap(name = "service", about = "Manage the minipx Windows service")]
+    Service {
+        #[clap(subcommand)]
+        command: ServiceCommands,
+    },
+}
+
+#[cfg(windows)]
+#[derive(Subcommand, Debug, Clone)]
+pub enum ServiceCommands {
+    #[clap(name = "install", about = "Register minipx as an auto-start service using the current config file")]
+    Install,
+    #[clap(name = "uninstall", about = "Stop and remove the service")]
+    Uninstall,
+    #[clap(name = "start", about = "Start the service")]
+    Start,
+    #[clap(name = "stop", about = "Stop the service gracefully")]
+    Stop,
+    /// Entry point used by the Service Control Manager
+    #[clap(name = "run", hide = true)]
+    Run,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -65,11 +240,18 @@ pub enum RouteCommands {
         #[clap(flatten)]
         routes: ProxyRouteArgs,
         domain: String,
+        /// Start from a route template (see `routes templates list`); other flags are applied on top
+        #[arg(short = 't', long = "template")]
+        template: Option<String>,
     },
     #[clap(name = "remove", about = "Remove a proxy route")]
     RemoveRoute { host: String },
     #[clap(name = "list", about = "List all proxy routes")]
-    ListRoutes,
+    ListRoutes {
+        /// Also ask the running instance whether each route's TCP/UDP forwarder is bound
+        #[arg(long = "check")]
+        check: bool,
+    },
     #[clap(name = "show", about = "Show a proxy route")]
     ShowRoute { host: String },
     #[clap(name = "update", about = "Update a proxy route (partial)")]
@@ -87,17 +269,214 @@ pub enum RouteCommands {
         path: String,
         /// Port to route the subroute to
         port: u16,
+        #[clap(flatten)]
+        overrides: SubrouteOverrideOptions,
+    },
+    #[clap(name = "updatesub", about = "Update a subroute of an existing proxy route (partial)")]
+    UpdateSubroute {
+        /// Domain of the existing route
+        domain: String,
+        /// Path of the subroute to update (e.g. /path/to/subroute)
+        path: String,
+        /// New port to route the subroute to
+        #[arg(short = 'P', long = "port")]
+        port: Option<u16>,
+        #[clap(flatten)]
+        overrides: SubrouteOverrideOptions,
+    },
+    #[clap(
+        name = "drain-status",
+        about = "Show requests still in flight to the previous backend of a route on the running instance",
+        long_about = "Show requests and websockets still pinned to previous generations of a route after a reload.\nExits with 0 when the route is drained (safe to stop the old backend) and 2 while it is still draining."
+    )]
+    DrainStatus {
+        /// Domain of the route (the route key, e.g., example.com)
+        domain: String,
+        /// Print the status as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
+    #[clap(
+        name = "dump",
+        about = "Print the routing table, or resolve where a request would be proxied to",
+        long_about = "Print the routing table after normalization: route keys, wildcard match order and per-route TLS/forwarder state.\nWith --effective the table is taken from the running instance's memory and includes generations and in-flight counts.\nWith --resolve, run the handler's route and subroute selection for a host (and --path) and print the chosen upstream; nothing is sent."
+    )]
+    Dump {
+        /// Use the running instance's in-memory table instead of the config file
+        #[arg(long = "effective")]
+        effective: bool,
+        /// Resolve a host (e.g. api.example.com) instead of printing the table
+        #[arg(long = "resolve", value_name = "HOST")]
+        resolve: Option<String>,
+        /// Request path (and query) to resolve
+        #[arg(long = "path", default_value = "/", requires = "resolve")]
+        path: String,
+        /// Print as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
+    #[clap(
+        name = "test-request",
+        about = "Send a request through the local proxy as if the domain already pointed at this machine",
+        long_about = "Connect to the local proxy (127.0.0.1 on port 80, or 443 with --https), send a request with the domain as Host (and SNI, accepting whatever certificate is served) and print the status, latency, the upstream that answered and the start of the body.\nThe upstream comes from the X-Upstream header when the proxy has debug_headers on, otherwise from resolving the route.\nExits with 0 for a 2xx/3xx response and 1 otherwise."
+    )]
+    TestRequest {
+        /// Domain to send as Host, e.g. app.example.com
+        domain: String,
+        /// Request path (and query)
+        #[arg(long = "path", default_value = "/")]
+        path: String,
+        /// Connect to the HTTPS listener
+        #[arg(long = "https")]
+        https: bool,
+        /// Request method
+        #[arg(long = "method", default_value = "GET")]
+        method: String,
+        /// Local port to connect to instead of 80/443
+        #[arg(long = "port")]
+        port: Option<u16>,
+        /// Bytes of the body to print (e.g. 512, 4k)
+        #[arg(long = "max-body", default_value = "512", value_parser = parse_size)]
+        max_body: usize,
+    },
+    #[clap(
+        name = "stats",
+        about = "Show concurrency limit occupancy, malformed-request rejections, websocket and transfer counters of routes on the running instance"
+    )]
+    Stats {
+        /// Print the stats as JSON
+        #[arg(long = "json")]
+        json: bool,
+        /// Start the transfer totals (bytes per route and per client) over after reading them
+        #[arg(long = "reset")]
+        reset: bool,
+    },
+    #[clap(
+        name = "capture",
+        about = "Show or toggle debug body capture for a route on the running instance",
+        long_about = "Show or toggle debug capture of request/response bodies for a route on the running instance, without editing the config file.\nSensitive headers are redacted. Capturing turns itself off after --duration or once the route's disk cap is reached."
+    )]
+    Capture {
+        /// Domain of the route (the route key, e.g., example.com)
+        domain: String,
+        /// Start capturing
+        #[arg(long = "enable", conflicts_with = "disable")]
+        enable: bool,
+        /// Stop capturing
+        #[arg(long = "disable")]
+        disable: bool,
+        /// Bytes of each body to capture (e.g. 4096, 64k, 1m)
+        #[arg(long = "max-bytes", value_parser = parse_capture_size)]
+        max_bytes: Option<usize>,
+        /// Turn capturing off after this long (e.g. 90s, 15m, 1h; plain numbers are seconds)
+        #[arg(long = "duration", value_parser = parse_capture_duration)]
+        duration: Option<u64>,
+        /// Print the status as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
+    #[clap(name = "templates", about = "List and inspect route templates")]
+    Templates {
+        #[clap(subcommand)]
+        command: TemplateCommands,
+    },
+    #[clap(
+        name = "import-caddy",
+        about = "Import routes from a Caddyfile",
+        long_about = "Import routes from a Caddyfile: site blocks (one route per hostname), reverse_proxy, redir to HTTPS, handle_path subroutes and the ACME email from tls or the global options.\nDirectives that cannot be imported are listed as warnings with their line numbers. Hostnames that already have a route are skipped."
+    )]
+    ImportCaddy {
+        /// Path to the Caddyfile
+        path: String,
+        /// Print the routes as JSON instead of adding them
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TemplateCommands {
+    #[clap(name = "list", about = "List built-in and configured route templates")]
+    List,
+    #[clap(name = "show", about = "Show a route template")]
+    Show { name: String },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CertCommands {
+    #[clap(name = "list", about = "List certificates with their expiry and renewal history")]
+    List {
+        /// Print the certificates as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AcmeCommands {
+    #[clap(name = "account", about = "Inspect, export or import the ACME account key in cache_dir")]
+    Account {
+        #[clap(subcommand)]
+        command: AcmeAccountCommands,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AcmeAccountCommands {
+    #[clap(name = "show", about = "Show the account used for the configured email: contact, directory, key fingerprint")]
+    Show {
+        /// Print the account as JSON
+        #[arg(long = "json")]
+        json: bool,
+        /// Ask the ACME directory for the account URL (network access)
+        #[arg(long = "lookup")]
+        lookup: bool,
+    },
+    #[clap(name = "export", about = "Write the account key to a file, to import it on another machine")]
+    Export {
+        /// File to write; it contains the private key
+        #[arg(long = "out", short = 'o')]
+        out: String,
+    },
+    #[clap(
+        name = "import",
+        about = "Use an exported account key for the configured email",
+        long_about = "Store an exported account key as the account for the configured email, then restart the running HTTPS server
+so it is used. An existing, different account key is only replaced with --force."
+    )]
+    Import {
+        /// File written by `acme account export`
+        file: String,
+        /// Replace a different existing account key
+        #[arg(long = "force")]
+        force: bool,
     },
 }
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum ConfigCommands {
+    #[clap(
+        name = "init",
+        about = "Create a config file, asking for the ACME email, cache directory and a first route",
+        long_about = "Create a config file with the ACME email, cache directory and optionally a first route, from flags or by asking on a terminal.\nEverything is validated before the file is written; without a terminal, missing required values are an error. An existing file is only overwritten with --force."
+    )]
+    Init(InitOptions),
     #[clap(name = "show", about = "Show the current configuration")]
     Show,
     #[clap(name = "email", about = "Set the email address to use for SSL certificates")]
     Email { email: String },
     #[clap(name = "show-path", about = "Show the path to the configuration file")]
     ShowPath,
+    #[clap(
+        name = "validate",
+        about = "Check the configuration file (exit 1 on errors)",
+        long_about = "Check the configuration file: unknown keys (usually typos) and values that fell back to defaults are errors, followed by the semantic checks preflight runs on the config.\nNothing is bound or resolved; see `minipx preflight` for the full environment checks."
+    )]
+    Validate {
+        /// Print the checks as JSON
+        #[arg(long = "json")]
+        json: bool,
+    },
 }
 
 // Optional fields for partial updates. Only provided flags will be applied.
@@ -126,6 +505,98 @@ pub struct UpdateRouteOptions {
     /// Disable HTTP to HTTPS redirect
     #[arg(long = "no-redirect", action = ArgAction::SetTrue)]
     pub no_redirect: bool,
+
+    /// Accept websocket upgrades for this route
+    #[arg(long = "websocket", action = ArgAction::SetTrue, conflicts_with = "no_websocket")]
+    pub websocket: bool,
+    /// Reject websocket upgrades for this route
+    #[arg(long = "no-websocket", action = ArgAction::SetTrue)]
+    pub no_websocket: bool,
+
+    /// Forward the client's Host header to the backend
+    #[arg(long = "preserve-host", action = ArgAction::SetTrue, conflicts_with = "no_preserve_host")]
+    pub preserve_host: bool,
+    /// Send the backend's own host:port as Host
+    #[arg(long = "no-preserve-host", action = ArgAction::SetTrue)]
+    pub no_preserve_host: bool,
+
+    /// Also serve the bare domain from this wildcard route (`*.example.com` matches `example.com`)
+    #[arg(long = "match-apex", action = ArgAction::SetTrue, conflicts_with = "no_match_apex")]
+    pub match_apex: bool,
+    /// Only serve subdomains from this wildcard route
+    #[arg(long = "no-match-apex", action = ArgAction::SetTrue)]
+    pub no_match_apex: bool,
+
+    /// Host header sent to the backend instead of the client's; "" removes the override
+    #[arg(long = "upstream-host-header")]
+    pub upstream_host_header: Option<String>,
+    /// TLS server name for an https backend; "" removes the override
+    #[arg(long = "upstream-sni")]
+    pub upstream_sni: Option<String>,
+
+    /// Allow a backend that is one of minipx's own listeners on this machine
+    #[arg(long = "allow-self-reference", action = ArgAction::SetTrue, conflicts_with = "no_allow_self_reference")]
+    pub allow_self_reference: bool,
+    /// Reject a backend that is one of minipx's own listeners again
+    #[arg(long = "no-allow-self-reference", action = ArgAction::SetTrue)]
+    pub no_allow_self_reference: bool,
+
+    /// Only serve the route from this RFC 3339 time on; "" removes the bound
+    #[arg(long = "active-from", value_parser = parse_timestamp)]
+    pub active_from: Option<String>,
+    /// Stop serving the route at this RFC 3339 time; "" removes the bound
+    #[arg(long = "active-until", value_parser = parse_timestamp)]
+    pub active_until: Option<String>,
+
+    /// Proxy to the backend on this unix domain socket; --port switches back to host:port
+    #[arg(long = "unix-socket", conflicts_with = "port")]
+    pub unix_socket: Option<String>,
+}
+
+// Per-subroute overrides of the parent route; omitted flags inherit the parent's setting.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SubrouteOverrideOptions {
+    /// Accept websocket upgrades on this subroute
+    #[arg(long = "websocket", action = ArgAction::SetTrue, conflicts_with = "no_websocket")]
+    pub websocket: bool,
+    /// Reject websocket upgrades on this subroute
+    #[arg(long = "no-websocket", action = ArgAction::SetTrue)]
+    pub no_websocket: bool,
+
+    /// Redirect HTTP to HTTPS on this subroute
+    #[arg(short = 'r', long = "redirect", action = ArgAction::SetTrue, conflicts_with = "no_redirect")]
+    pub redirect: bool,
+    /// Do not redirect HTTP to HTTPS on this subroute
+    #[arg(long = "no-redirect", action = ArgAction::SetTrue)]
+    pub no_redirect: bool,
+
+    /// Forward the client's Host header to the subroute backend
+    #[arg(long = "preserve-host", action = ArgAction::SetTrue, conflicts_with = "no_preserve_host")]
+    pub preserve_host: bool,
+    /// Send the subroute backend's own host:port as Host
+    #[arg(long = "no-preserve-host", action = ArgAction::SetTrue)]
+    pub no_preserve_host: bool,
+}
+
+// Map a pair of --flag/--no-flag switches to an optional setting
+fn flag_pair(enable: bool, disable: bool) -> Option<bool> {
+    if enable {
+        Some(true)
+    } else if disable {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+impl From<SubrouteOverrideOptions> for SubrouteOverrides {
+    fn from(o: SubrouteOverrideOptions) -> Self {
+        SubrouteOverrides {
+            allow_websocket: flag_pair(o.websocket, o.no_websocket),
+            redirect_to_https: flag_pair(o.redirect, o.no_redirect),
+            preserve_host: flag_pair(o.preserve_host, o.no_preserve_host),
+        }
+    }
 }
 
 impl From<UpdateRouteOptions> for RoutePatch {
@@ -134,27 +605,48 @@ impl From<UpdateRouteOptions> for RoutePatch {
             host: o.host,
             path: o.path,
             port: o.port,
-            ssl_enable: if o.ssl {
-                Some(true)
-            } else if o.no_ssl {
-                Some(false)
-            } else {
-                None
-            },
-            redirect_to_https: if o.redirect {
-                Some(true)
-            } else if o.no_redirect {
-                Some(false)
-            } else {
-                None
-            },
+            ssl_enable: flag_pair(o.ssl, o.no_ssl),
+            redirect_to_https: flag_pair(o.redirect, o.no_redirect),
             listen_port: None,
+            allow_websocket: flag_pair(o.websocket, o.no_websocket),
+            preserve_host: flag_pair(o.preserve_host, o.no_preserve_host),
+            match_apex: flag_pair(o.match_apex, o.no_match_apex),
+            upstream_host_header: o.upstream_host_header,
+            upstream_sni: o.upstream_sni,
+            allow_self_reference: flag_pair(o.allow_self_reference, o.no_allow_self_reference),
+            active_from: o.active_from,
+            active_until: o.active_until,
+            unix_socket: o.unix_socket,
         }
     }
 }
 
 impl MinipxArguments {
+    /// Thread counts to run with: the config file's `runtime` settings, overridden by the command line
+    pub(crate) fn runtime_tuning(&self) -> RuntimeTuning {
+        let config_path = self.config_path.as_deref().filter(|path| !path.trim().is_empty()).unwrap_or("./minipx.json");
+        let mut runtime = runtime_tuning::read_config(config_path);
+        if let Some(threads) = self.worker_threads {
+            runtime.set_worker_threads(Some(threads as usize));
+        }
+        if let Some(threads) = self.max_blocking_threads {
+            runtime.set_max_blocking_threads(Some(threads as usize));
+        }
+        RuntimeTuning::resolve(&runtime, runtime_tuning::available_cores(), cfg!(feature = "webui"))
+    }
+
+    /// True when launched by the Service Control Manager
+    #[cfg(windows)]
+    pub(crate) fn is_service_run(&self) -> bool {
+        matches!(&self.command, Some(MinipxCommands::Service { command: ServiceCommands::Run }))
+    }
+
     pub async fn handle_arguments(&self) -> Result<()> {
+        // Runs before the config is loaded, which would create an empty file at the path
+        if let Some(MinipxCommands::Config { command: ConfigCommands::Init(options) }) = &self.command {
+            init::run(options, self.config_path.as_deref()).await?;
+            std::process::exit(0);
+        }
         if let Some(command) = &self.command {
             let effective_config_path = Config::resolve_config_path(self.config_path.clone()).await;
             let mut config = Config::try_load(&effective_config_path).await?;
@@ -163,10 +655,59 @@ impl MinipxArguments {
                 // Routes subcommand
                 // ---
                 MinipxCommands::Routes { command } => match command {
-                    RouteCommands::AddRoute { domain, routes } => {
-                        config.add_route(domain.clone(), routes.clone()).await?;
+                    RouteCommands::AddRoute { domain, routes, template } => {
+                        match template {
+                            Some(template) => {
+                                let port = routes.port.ok_or_else(|| anyhow::anyhow!("--template needs a backend --port"))?;
+                                config.add_route_from_template(domain.clone(), template, port, routes.template_overrides()).await?
+                            }
+                            None => config.add_route(domain.clone(), routes.clone()).await?,
+                        }
+                        config.save().await?;
+                    }
+                    RouteCommands::ImportCaddy { path, dry_run } => {
+                        let contents = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?;
+                        let import = caddyfile::parse(&contents).map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path, e))?;
+                        for warning in &import.warnings {
+                            eprintln!("\x1b[1;33mwarning\x1b[0m: {}", warning);
+                        }
+                        if *dry_run {
+                            println!("{}", serde_json::to_string_pretty(&import.routes)?);
+                            if let Some(email) = &import.email {
+                                eprintln!("email: {}", email);
+                            }
+                            std::process::exit(0);
+                        }
+                        let mut imported = 0;
+                        for (domain, route) in import.routes {
+                            if config.get_routes().contains_key(&domain) {
+                                eprintln!("\x1b[1;33mskipped\x1b[0m {}: a route already exists", domain);
+                                continue;
+                            }
+                            config.add_route(domain, route).await?;
+                            imported += 1;
+                        }
+                        if let Some(email) = import.email.filter(|email| email != config.get_email()) {
+                            if !config.get_email().is_empty() {
+                                info!("Replacing ACME email {} with {} from the Caddyfile", config.get_email(), email);
+                            }
+                            config.set_email(email);
+                        }
                         config.save().await?;
+                        info!("Imported {} routes from {} ({} warnings)", imported, path, import.warnings.len());
                     }
+                    RouteCommands::Templates { command } => match command {
+                        TemplateCommands::List => {
+                            for (name, template) in config.available_templates() {
+                                let source = if config.get_templates().contains_key(&name) { "config" } else { "built-in" };
+                                println!("\x1b[1;36m{}\x1b[0m ({}): {}", name, source, template.description);
+                            }
+                        }
+                        TemplateCommands::Show { name } => match config.find_template(name) {
+                            Some(template) => println!("{}", serde_json::to_string_pretty(&template)?),
+                            None => error!("Unknown route template: {}", name),
+                        },
+                    },
                     RouteCommands::RemoveRoute { host } => {
                         config.remove_route(host).await?;
                         config.save().await?;
@@ -177,51 +718,228 @@ impl MinipxArguments {
                         config.save().await?;
                         info!("Updated route: {}", domain);
                     }
-                    RouteCommands::ListRoutes => {
+                    RouteCommands::ListRoutes { check } => {
+                        let forwarders = if *check {
+                            match ipc::send_request(IpcRequest::Status).await {
+                                Some(IpcResponse::Status { forwarders, .. }) => forwarders,
+                                _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                            }
+                        } else {
+                            Vec::new()
+                        };
                         for (domain, route) in config.get_routes() {
                             println!(
-                                "\x1b[1;36m{}\x1b[0m: \x1b[1;33m{}\x1b[0m -> \x1b[1;32m{}:{}\x1b[0m/\x1b[1;35m{}\x1b[0m",
+                                "\x1b[1;36m{}\x1b[0m: \x1b[1;33m{}\x1b[0m -> \x1b[1;32m{}\x1b[0m/\x1b[1;35m{}\x1b[0m",
                                 domain,
                                 match (route.get_listen_port(), route.is_ssl_enabled()) {
                                     (Some(port), _) => port.to_string(),
                                     (_, true) => "HTTPS".to_string(),
                                     (_, false) => "HTTP".to_string(),
                                 },
-                                route.get_host(),
-                                route.get_port(),
+                                describe_backend(route),
                                 route.get_path()
                             );
+                            if let Some(activation) = describe_activation(route, OffsetDateTime::now_utc()) {
+                                println!("  {}", activation);
+                            }
+                            let Some(listen_port) = forwarder_port(route).filter(|_| *check) else {
+                                continue;
+                            };
+                            for protocol in ["tcp", "udp"] {
+                                let state = forwarders.iter().find(|f| f.protocol == protocol && f.listen_port == listen_port).map(|f| &f.state);
+                                println!("  {} forwarder on port {}: {}", protocol, listen_port, describe_binding(state));
+                            }
                         }
                     }
                     RouteCommands::ShowRoute { host } => {
                         if let Some(route) = config.lookup_host(host) {
                             println!(
-                                "\x1b[1;36m{}\x1b[0m: \x1b[1;33m{}\x1b[0m -> \x1b[1;32m{}:{}\x1b[0m/\x1b[1;35m{}\x1b[0m",
+                                "\x1b[1;36m{}\x1b[0m: \x1b[1;33m{}\x1b[0m -> \x1b[1;32m{}\x1b[0m/\x1b[1;35m{}\x1b[0m",
                                 host,
                                 match (route.get_listen_port(), route.is_ssl_enabled()) {
                                     (Some(port), _) => port.to_string(),
                                     (_, true) => "HTTPS".to_string(),
                                     (_, false) => "HTTP".to_string(),
                                 },
-                                route.get_host(),
-                                route.get_port(),
+                                describe_backend(route),
                                 route.get_path()
                             );
                         } else {
                             error!("Route not found: {}", host);
                         }
                     }
-                    RouteCommands::AddSubroute { domain, path, port } => {
-                        config.add_subroute(domain, path.clone(), *port).await?;
+                    RouteCommands::AddSubroute { domain, path, port, overrides } => {
+                        config.add_subroute_with(domain, path.clone(), *port, overrides.clone().into()).await?;
                         config.save().await?;
                         info!("Added subroute to {}: {} -> port {}", domain, path, port);
                     }
+                    RouteCommands::UpdateSubroute { domain, path, port, overrides } => {
+                        let overrides: SubrouteOverrides = overrides.clone().into();
+                        let patch = SubroutePatch {
+                            port: *port,
+                            allow_websocket: overrides.allow_websocket,
+                            redirect_to_https: overrides.redirect_to_https,
+                            preserve_host: overrides.preserve_host,
+                        };
+                        config.update_subroute(domain, path, patch).await?;
+                        config.save().await?;
+                        info!("Updated subroute: {}{}", domain, path);
+                    }
+                    RouteCommands::DrainStatus { domain, json } => {
+                        let status = match ipc::send_request(IpcRequest::DrainStatus { domain: domain.clone() }).await {
+                            Some(IpcResponse::DrainStatus { status }) => status,
+                            Some(IpcResponse::Error { message }) => return Err(anyhow::anyhow!(message)),
+                            _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                        };
+                        let drained = status.as_ref().is_none_or(|s| s.drained);
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&status)?);
+                        } else {
+                            print_drain_status(domain, status.as_ref());
+                        }
+                        std::process::exit(if drained { 0 } else { 2 });
+                    }
+                    RouteCommands::TestRequest { domain, path, https, method, port, max_body } => {
+                        let request = TestRequest {
+                            domain: domain.clone(),
+                            path: if path.starts_with('/') { path.clone() } else { format!("/{}", path) },
+                            method: hyper::Method::from_bytes(method.to_ascii_uppercase().as_bytes())?,
+                            https: *https,
+                            port: *port,
+                            max_body: *max_body,
+                        };
+                        let response = request.send().await?;
+                        // Without the debug headers, say where the route sends the request instead
+                        let upstream = match &response.upstream {
+                            Some(upstream) => Some((upstream.clone(), "X-Upstream")),
+                            None => {
+                                let resolution =
+                                    match ipc::send_request(IpcRequest::Resolve { host: domain.clone(), path: request.path.clone() }).await {
+                                        Some(IpcResponse::Resolution { resolution }) => resolution,
+                                        _ => route_table::resolve(&config, domain, &request.path),
+                                    };
+                                resolution.map(|resolution| (resolution.upstream, "resolved; set debug_headers to see the one that answered"))
+                            }
+                        };
+                        print_test_response(&request, &response, upstream);
+                        std::process::exit(if response.is_success() { 0 } else { 1 });
+                    }
+                    RouteCommands::Dump { effective, resolve: Some(host), path, json } => {
+                        let resolution = if *effective {
+                            match ipc::send_request(IpcRequest::Resolve { host: host.clone(), path: path.clone() }).await {
+                                Some(IpcResponse::Resolution { resolution }) => resolution,
+                                Some(IpcResponse::Error { message }) => return Err(anyhow::anyhow!(message)),
+                                _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                            }
+                        } else {
+                            route_table::resolve(&config, host, path)
+                        };
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&resolution)?);
+                        } else {
+                            match &resolution {
+                                Some(resolution) => print_resolution(resolution),
+                                None => println!("No route serves {}", host),
+                            }
+                        }
+                        if resolution.is_none() {
+                            std::process::exit(2);
+                        }
+                    }
+                    RouteCommands::Dump { effective, resolve: None, json, .. } => {
+                        let table = if *effective {
+                            match ipc::send_request(IpcRequest::RouteTable).await {
+                                Some(IpcResponse::RouteTable { table }) => table,
+                                Some(IpcResponse::Error { message }) => return Err(anyhow::anyhow!(message)),
+                                _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                            }
+                        } else {
+                            route_table::route_table(&config)
+                        };
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&table)?);
+                        } else {
+                            print_route_table(&table, *effective);
+                        }
+                    }
+                    RouteCommands::Stats { json, reset } => {
+                        let (routes, malformed, websockets, transfer) = match ipc::send_request(IpcRequest::RouteStats { reset: *reset }).await {
+                            Some(IpcResponse::RouteStats { routes, malformed, websockets, transfer }) => (routes, malformed, websockets, transfer),
+                            Some(IpcResponse::Error { message }) => return Err(anyhow::anyhow!(message)),
+                            _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                        };
+                        if *json {
+                            println!(
+                                "{}",
+                                serde_json::to_string_pretty(
+                                    &serde_json::json!({ "routes": routes, "malformed": malformed, "websockets": websockets, "transfer": transfer })
+                                )?
+                            );
+                        } else {
+                            if routes.is_empty() {
+                                println!("No routes with a concurrency limit have received requests");
+                            }
+                            for stats in routes {
+                                println!(
+                                    "\x1b[1;36m{}\x1b[0m: \x1b[1;32m{}\x1b[0m/{} active, {} queued, {} rejected",
+                                    stats.domain, stats.active, stats.limit, stats.queued, stats.rejected
+                                );
+                            }
+                            for (domain, count) in malformed {
+                                println!("\x1b[1;36m{}\x1b[0m: \x1b[1;31m{}\x1b[0m malformed requests rejected (strict_http)", domain, count);
+                            }
+                            for (domain, ws) in websockets {
+                                println!(
+                                    "\x1b[1;36m{}\x1b[0m: \x1b[1;32m{}\x1b[0m websockets open, closed {} eof / {} error / {} idle, {} bytes to upstream, {} bytes to client",
+                                    domain, ws.active, ws.closed_eof, ws.closed_error, ws.closed_idle, ws.bytes_to_upstream, ws.bytes_to_client
+                                );
+                            }
+                            for (domain, totals) in &transfer.routes {
+                                println!(
+                                    "\x1b[1;36m{}\x1b[0m: {} requests, {} received, {} sent",
+                                    domain,
+                                    totals.requests,
+                                    format_bytes(totals.request_bytes),
+                                    format_bytes(totals.response_bytes)
+                                );
+                            }
+                            if !transfer.top_clients.is_empty() {
+                                println!("Top clients by bytes:");
+                            }
+                            for client in &transfer.top_clients {
+                                let error = if client.error > 0 { format!(" (at most {} over)", format_bytes(client.error)) } else { String::new() };
+                                println!("  {:<39} {}{}", client.ip, format_bytes(client.bytes), error);
+                            }
+                            if *reset {
+                                println!("Transfer totals reset");
+                            }
+                        }
+                    }
+                    RouteCommands::Capture { domain, enable, disable, max_bytes, duration, json } => {
+                        let request = IpcRequest::Capture {
+                            domain: domain.clone(),
+                            enabled: flag_pair(*enable, *disable),
+                            options: CaptureOptions { max_bytes: *max_bytes, duration_secs: *duration },
+                        };
+                        let status = match ipc::send_request(request).await {
+                            Some(IpcResponse::Capture { status: Some(status) }) => status,
+                            Some(IpcResponse::Capture { status: None }) => return Err(anyhow::anyhow!("Route not found: {}", domain)),
+                            Some(IpcResponse::Error { message }) => return Err(anyhow::anyhow!(message)),
+                            _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                        };
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&status)?);
+                        } else {
+                            print_capture_status(&status);
+                        }
+                    }
                 },
 
                 // ---
                 // Config subcommand
                 // ---
                 MinipxCommands::Config { command } => match command {
+                    ConfigCommands::Init(_) => unreachable!("config init is handled before the config is loaded"),
                     ConfigCommands::Show => {
                         println!("{}", config);
                     }
@@ -232,7 +950,212 @@ impl MinipxArguments {
                     ConfigCommands::ShowPath => {
                         println!("{}", config.get_path().to_string_lossy())
                     }
+                    ConfigCommands::Validate { json } => {
+                        let mut report = PreflightReport::default();
+                        preflight::check_config(&config, &mut report);
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&report)?);
+                        } else {
+                            print_preflight(&report);
+                        }
+                        std::process::exit(if report.has_failures() { 1 } else { 0 });
+                    }
+                },
+
+                // ---
+                // systemd unit
+                // ---
+                MinipxCommands::Status { json } => {
+                    #[cfg(windows)]
+                    match crate::service::status() {
+                        Some(state) => println!("Service: {:?}", state),
+                        None => println!("Service: not installed"),
+                    }
+                    let Some(IpcResponse::Status { report, tls, config_reload_error, forwarders }) = ipc::send_request(IpcRequest::Status).await
+                    else {
+                        println!("minipx is not running");
+                        std::process::exit(3);
+                    };
+                    if *json {
+                        let mut value = serde_json::to_value(&report)?;
+                        value["tls"] = serde_json::to_value(&tls)?;
+                        if let Some(error) = &config_reload_error {
+                            value["config_reload_error"] = serde_json::Value::from(error.as_str());
+                        }
+                        value["forwarders"] = serde_json::to_value(&forwarders)?;
+                        println!("{}", serde_json::to_string_pretty(&value)?);
+                    } else {
+                        println!(
+                            "\x1b[1;32mrunning\x1b[0m: minipx {} up {}s, {} routes, listening on {}",
+                            report.version,
+                            report.uptime_secs,
+                            report.routes,
+                            if report.listeners.is_empty() { "nothing yet".to_string() } else { report.listeners.join(", ") }
+                        );
+                        let expiring = tls.certificates.iter().filter(|c| c.days_to_expiry.is_some_and(|d| d < EXPIRY_WARNING_DAYS)).count();
+                        println!(
+                            "https: {}, {} certificates ({} expiring within {} days)",
+                            tls.state.as_str(),
+                            tls.certificates.len(),
+                            expiring,
+                            EXPIRY_WARNING_DAYS
+                        );
+                        if report.config_reload_failed {
+                            println!(
+                                "\x1b[1;33mconfig reload failed\x1b[0m, still serving the last-known-good config: {}",
+                                config_reload_error.as_deref().unwrap_or("see the logs")
+                            );
+                        }
+                        for frontend in &report.unbound_frontends {
+                            println!(
+                                "\x1b[1;33mdegraded\x1b[0m: {} port {} is not bound after {} attempts: {}",
+                                frontend.kind.to_uppercase(),
+                                frontend.port,
+                                frontend.attempts,
+                                frontend.last_error
+                            );
+                        }
+                        for forwarder in forwarders.iter().filter(|f| f.state != BindingState::Bound) {
+                            println!(
+                                "{} forwarder on port {}: {}",
+                                forwarder.protocol,
+                                forwarder.listen_port,
+                                describe_binding(Some(&forwarder.state))
+                            );
+                        }
+                    }
+                }
+
+                // ---
+                // Preflight
+                // ---
+                MinipxCommands::Preflight { json, public_ip_url } => {
+                    let mut options = PreflightOptions::for_config(&config);
+                    if public_ip_url.is_some() {
+                        options.public_ip_url = public_ip_url.clone();
+                    }
+                    // A running instance holds its own ports, so binding them would only report it
+                    let running = matches!(ipc::send_request(IpcRequest::Status).await, Some(IpcResponse::Status { .. }));
+                    options.check_ports = !running;
+                    let mut report = preflight::run(&config, &options).await;
+                    if running {
+                        report.push(
+                            Check::warn("port", "all", "minipx is already running; port checks skipped").with_hint("Stop it to check the ports"),
+                        );
+                    }
+                    if *json {
+                        println!("{}", serde_json::to_string_pretty(&report)?);
+                    } else {
+                        print_preflight(&report);
+                    }
+                    std::process::exit(if report.has_failures() { 1 } else { 0 });
+                }
+
+                // ---
+                // Certificates
+                // ---
+                MinipxCommands::Certs { command } => match command {
+                    CertCommands::List { json } => {
+                        let tls = match ipc::send_request(IpcRequest::Certificates).await {
+                            Some(IpcResponse::Certificates { tls }) => tls,
+                            _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                        };
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&tls)?);
+                        } else {
+                            print_certificates(&tls);
+                        }
+                    }
                 },
+
+                // ---
+                // ACME account
+                // ---
+                MinipxCommands::Acme { command: AcmeCommands::Account { command } } => match command {
+                    AcmeAccountCommands::Show { json, lookup } => {
+                        let mut info = acme_account::account_info(config.get_cache_dir(), config.get_email())?;
+                        #[allow(clippy::collapsible_if)]
+                        if *lookup {
+                            if let Some(file) = &info.file {
+                                let key = acme_account::AccountKey::from_pkcs8(&std::fs::read(file)?)?;
+                                info.account_url = Some(acme_account::lookup_account_url(&key, &info.contact).await?);
+                            }
+                        }
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&info)?);
+                        } else {
+                            print_acme_account(&info, *lookup);
+                        }
+                    }
+                    AcmeAccountCommands::Export { out } => {
+                        let account = acme_account::export(config.get_cache_dir(), config.get_email())?;
+                        write_private_file(out, &serde_json::to_string_pretty(&account)?)?;
+                        println!("Exported the account for {} (key {}) to {}", account.contact.join(", "), account.thumbprint, out);
+                        println!("\x1b[1;33mThe file contains the account's private key; keep it safe.\x1b[0m");
+                    }
+                    AcmeAccountCommands::Import { file, force } => {
+                        let account: acme_account::AccountExport = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+                        let contact = acme_account::contact_for(config.get_email());
+                        if account.contact != contact {
+                            println!(
+                                "\x1b[1;33mwarning\x1b[0m: the account was registered for {}, but the configured email is {}; the account keeps its registered contact",
+                                account.contact.join(", "),
+                                contact.join(", ")
+                            );
+                        }
+                        match acme_account::import(config.get_cache_dir(), config.get_email(), &account, *force)? {
+                            acme_account::ImportOutcome::Unchanged(path) => println!("{} already holds this account key", path.display()),
+                            acme_account::ImportOutcome::Imported(path) => {
+                                println!("Imported the account key {} to {}", account.thumbprint, path.display());
+                                match ipc::send_request(IpcRequest::RestartHttps).await {
+                                    Some(IpcResponse::HttpsRestart { restarted: true }) => println!("Restarted the running HTTPS server to use it"),
+                                    Some(IpcResponse::Error { message }) => {
+                                        println!(
+                                            "Could not restart the running HTTPS server ({}); restart minipx to use the imported account",
+                                            message
+                                        )
+                                    }
+                                    Some(_) => println!("HTTPS is not serving yet; it uses the imported account when it starts"),
+                                    None => println!("minipx is not running; the account is used when it starts"),
+                                }
+                            }
+                        }
+                    }
+                },
+
+                // ---
+                // Windows service
+                // ---
+                #[cfg(windows)]
+                MinipxCommands::Service { command } => match command {
+                    ServiceCommands::Install => {
+                        let config_path = std::fs::canonicalize(config.get_path()).unwrap_or_else(|_| config.get_path().clone());
+                        crate::service::install(&config_path)?;
+                    }
+                    ServiceCommands::Uninstall => crate::service::uninstall()?,
+                    ServiceCommands::Start => crate::service::start()?,
+                    ServiceCommands::Stop => crate::service::stop()?,
+                    // Dispatched in main before any command handling
+                    ServiceCommands::Run => unreachable!("service run is handled before argument handling"),
+                },
+
+                MinipxCommands::Upgrade => match ipc::send_request(IpcRequest::Upgrade).await {
+                    Some(IpcResponse::Upgraded { pid }) => {
+                        println!("minipx (pid {}) took over; the previous process exits once its requests have finished", pid)
+                    }
+                    Some(IpcResponse::Error { message }) => return Err(anyhow::anyhow!(message)),
+                    _ => return Err(anyhow::anyhow!("No running minipx instance found")),
+                },
+
+                MinipxCommands::SystemdUnit { socket } => {
+                    if *socket {
+                        print!("{}", systemd::socket_unit());
+                    } else {
+                        let exe = std::env::current_exe()?;
+                        let config_path = std::fs::canonicalize(config.get_path()).unwrap_or_else(|_| config.get_path().clone());
+                        print!("{}", systemd::service_unit(&exe, &config_path));
+                    }
+                }
             }
             // Exit after the command has been executed
             std::process::exit(0);
@@ -241,28 +1164,367 @@ impl MinipxArguments {
     }
 }
 
+fn print_route_table(table: &RouteTable, effective: bool) {
+    if effective {
+        println!("config generation {}", table.generation);
+    }
+    if !table.wildcard_order.is_empty() {
+        println!("wildcard match order: {}", table.wildcard_order.join(", "));
+    }
+    if table.routes.is_empty() {
+        println!("No routes");
+    }
+    for entry in &table.routes {
+        let route = &entry.route;
+        let mut flags = vec![if entry.tls_servable { "tls" } else { "no tls" }];
+        if route.is_match_apex() {
+            flags.push("match apex");
+        }
+        if entry.draining {
+            flags.push("\x1b[1;33mdraining\x1b[0m");
+        }
+        println!("\x1b[1;36m{}\x1b[0m -> \x1b[1;32m{}\x1b[0m ({})", entry.key, describe_backend(route), flags.join(", "));
+        for sub in route.get_subroutes() {
+            println!("  {} -> port {}", sub.get_path(), sub.get_port());
+        }
+        if let Some(forwarder) = &entry.forwarder {
+            let state = if !effective {
+                String::new()
+            } else if forwarder.listening {
+                " (listening)".to_string()
+            } else {
+                format!(" ({})", describe_binding(forwarder.binding.as_ref()))
+            };
+            println!("  forwarder on port {}{}", forwarder.listen_port, state);
+        }
+        if let Some(generation) = entry.generation.filter(|_| effective) {
+            println!("  generation {}: {} requests, {} websockets in flight", generation, entry.in_flight_requests, entry.in_flight_websockets);
+        }
+    }
+}
+
+fn describe_binding(state: Option<&BindingState>) -> String {
+    match state {
+        Some(BindingState::Bound) => "\x1b[1;32mbound\x1b[0m".to_string(),
+        Some(BindingState::Retrying { attempts, last_error, retry_in_secs }) => {
+            format!("\x1b[1;33mretrying\x1b[0m after {} failed attempts ({}), next in {}s", attempts, last_error, retry_in_secs)
+        }
+        Some(BindingState::Disabled { reason }) => format!("\x1b[1;31mdisabled\x1b[0m ({}), restart minipx once resolved", reason),
+        None => "\x1b[1;31mnot listening\x1b[0m".to_string(),
+    }
+}
+
+fn print_test_response(request: &TestRequest, response: &TestResponse, upstream: Option<(String, &str)>) {
+    let color = if response.is_success() { "32" } else { "31" };
+    println!(
+        "{} {} -> \x1b[1;{}m{}\x1b[0m in {:.1} ms",
+        request.method,
+        request.url(),
+        color,
+        response.status,
+        response.latency.as_secs_f64() * 1000.0
+    );
+    if let Some(route) = &response.route {
+        println!("  route: {}", route);
+    }
+    match upstream {
+        Some((upstream, source)) => println!("  upstream: \x1b[1;36m{}\x1b[0m ({})", upstream, source),
+        None => println!("  upstream: none (no route serves {})", request.domain),
+    }
+    if response.body.is_empty() {
+        println!("  body: empty");
+    } else {
+        let shown = if response.truncated { "first " } else { "" };
+        println!("  body ({}{} bytes):", shown, response.body.len());
+        println!("{}", String::from_utf8_lossy(&response.body));
+    }
+}
+
+fn print_resolution(resolution: &Resolution) {
+    let matched = match resolution.matched {
+        RouteMatch::Exact => "exact",
+        RouteMatch::Wildcard => "wildcard",
+        RouteMatch::Apex => "wildcard apex",
+    };
+    println!("\x1b[1;36m{}\x1b[0m matches route \x1b[1;36m{}\x1b[0m ({})", resolution.host, resolution.route_key, matched);
+    if let Some(subroute) = &resolution.subroute {
+        println!("  subroute: {}", subroute);
+    }
+    println!("  upstream: \x1b[1;32m{}\x1b[0m", resolution.upstream);
+    if resolution.redirects_to_https {
+        println!("  plain HTTP is redirected to HTTPS");
+    }
+    println!("  websockets: {}", if resolution.allow_websocket { "allowed" } else { "rejected" });
+    if let Some(preserve_host) = resolution.preserve_host {
+        println!("  host header: {}", if preserve_host { "preserved" } else { "rewritten to the backend" });
+    }
+}
+
+fn print_preflight(report: &PreflightReport) {
+    let width = report.checks.iter().map(|c| c.check.len() + c.subject.len() + 1).max().unwrap_or(0);
+    for check in &report.checks {
+        let status = match check.status {
+            CheckStatus::Pass => "\x1b[1;32mpass\x1b[0m",
+            CheckStatus::Warn => "\x1b[1;33mwarn\x1b[0m",
+            CheckStatus::Fail => "\x1b[1;31mfail\x1b[0m",
+        };
+        let name = format!("{} {}", check.check, check.subject);
+        println!("{}  {:<width$}  {}", status, name, check.detail, width = width);
+        if let Some(hint) = &check.hint {
+            println!("      {:<width$}  \x1b[2m-> {}\x1b[0m", "", hint, width = width);
+        }
+    }
+    println!(
+        "{} passed, {} warnings, {} failures",
+        report.count(CheckStatus::Pass),
+        report.count(CheckStatus::Warn),
+        report.count(CheckStatus::Fail)
+    );
+}
+
+// Written readable by the owner only where the platform allows it
+fn write_private_file(path: &str, contents: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, contents.as_bytes())?;
+    Ok(())
+}
+
+fn print_acme_account(info: &AccountInfo, looked_up: bool) {
+    println!("directory: {}", info.directory);
+    println!("contact:   {}", info.contact.join(", "));
+    match (&info.file, &info.thumbprint) {
+        (Some(file), Some(thumbprint)) => {
+            println!("key:       {} ({})", thumbprint, file.display());
+            match &info.account_url {
+                Some(url) => println!("account:   {}", url),
+                None if looked_up => {}
+                None => println!("account:   run with --lookup to ask the directory for the account URL"),
+            }
+        }
+        _ => println!("key:       none yet; an account is registered with the first certificate"),
+    }
+    if let Some(warning) = info.contact_warning() {
+        println!("\x1b[1;33mwarning\x1b[0m: {}", warning);
+    }
+    for file in &info.other_accounts {
+        println!("other:     {}", file.display());
+    }
+}
+
+fn print_certificates(tls: &TlsStatus) {
+    println!("https: {} ({} failed handshakes)", tls.state.as_str(), tls.handshake_errors);
+    if tls.certificates.is_empty() {
+        println!("No certificates");
+    }
+    for cert in &tls.certificates {
+        let expiry = match cert.days_to_expiry {
+            Some(days) if days < 0 => "\x1b[1;31mexpired\x1b[0m".to_string(),
+            Some(days) if days < EXPIRY_WARNING_DAYS => format!("\x1b[1;33mexpires in {} days\x1b[0m", days),
+            Some(days) => format!("expires in {} days", days),
+            None => "pending issuance".to_string(),
+        };
+        println!("\x1b[1;36m{}\x1b[0m: {}, {} of {} renewals succeeded", cert.domain, expiry, cert.renewals_succeeded, cert.renewals_attempted);
+        if let Some(error) = &cert.last_error {
+            println!("  last error: {}", error);
+        }
+    }
+}
+
+fn print_capture_status(status: &CaptureStatus) {
+    if status.enabled {
+        println!(
+            "\x1b[1;36m{}\x1b[0m: \x1b[1;33mcapturing\x1b[0m up to {} bytes per body to {} ({}s left)",
+            status.domain, status.max_bytes, status.dir, status.remaining_secs
+        );
+    } else {
+        println!("\x1b[1;36m{}\x1b[0m: capture off", status.domain);
+    }
+    println!("  {} of {} bytes captured on disk", status.captured_bytes, status.max_total_bytes);
+}
+
+/// Parse a byte size with an optional k/m/g suffix (binary multiples), e.g. `64k`
+fn parse_size(value: &str) -> std::result::Result<usize, String> {
+    let value = value.trim().to_ascii_lowercase();
+    let digits = value.trim_end_matches(['b', 'i']);
+    let (number, multiplier) = match digits.chars().last() {
+        Some('k') => (&digits[..digits.len() - 1], 1024),
+        Some('m') => (&digits[..digits.len() - 1], 1024 * 1024),
+        Some('g') => (&digits[..digits.len() - 1], 1024 * 1024 * 1024),
+        _ => (digits, 1),
+    };
+    number.trim().parse::<usize>().ok().and_then(|n| n.checked_mul(multiplier)).ok_or_else(|| format!("invalid size '{}'", value))
+}
+
+// `parse_size` within the most a capture may keep of each body
+fn parse_capture_size(value: &str) -> std::result::Result<usize, String> {
+    let size = parse_size(value)?;
+    if size > MAX_CAPTURE_BYTES {
+        return Err(format!("at most {} per body can be captured", format_bytes(MAX_CAPTURE_BYTES as u64)));
+    }
+    Ok(size)
+}
+
+/// Check an RFC 3339 timestamp argument; an empty value is kept, for removing a bound
+fn parse_timestamp(value: &str) -> std::result::Result<String, String> {
+    if value.trim().is_empty() {
+        return Ok(String::new());
+    }
+    parse_rfc3339(value).map(|_| value.trim().to_string()).map_err(|e| e.to_string())
+}
+
+// Byte counts for people: 1536 -> "1.5 KiB"
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// The backend of a route: `host:port`, or `unix:/path` for a unix socket
+fn describe_backend(route: &ProxyRoute) -> String {
+    match route.get_unix_socket() {
+        Some(socket) => unix_socket::describe(socket),
+        None => format!("{}:{}", route.get_host(), route.get_port()),
+    }
+}
+
+// Whether a route with an activation window is served at `now`, and its next transition
+fn describe_activation(route: &ProxyRoute, now: OffsetDateTime) -> Option<String> {
+    route.get_active_from().or(route.get_active_until())?;
+    let state = if route.is_active_at(now) { "\x1b[1;32mactive\x1b[0m" } else { "\x1b[1;31minactive\x1b[0m" };
+    Some(match route.next_transition(now) {
+        Some(at) if route.is_active_at(at) => format!("{}, turns on at {}", state, format_rfc3339(at)),
+        Some(at) => format!("{}, turns off at {}", state, format_rfc3339(at)),
+        None => format!("{}, no upcoming transitions", state),
+    })
+}
+
+/// Parse a duration in seconds with an optional s/m/h suffix, e.g. `15m`
+fn parse_duration_secs(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim().to_ascii_lowercase();
+    let (number, multiplier) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 60 * 60),
+        _ => (value.as_str(), 1),
+    };
+    number.trim().parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier)).ok_or_else(|| format!("invalid duration '{}'", value))
+}
+
+// `parse_duration_secs` within the longest a capture may run
+fn parse_capture_duration(value: &str) -> std::result::Result<u64, String> {
+    let secs = parse_duration_secs(value)?;
+    if secs > MAX_CAPTURE_DURATION_SECS {
+        return Err(format!("captures run for at most {}h", MAX_CAPTURE_DURATION_SECS / 3600));
+    }
+    Ok(secs)
+}
+
+fn print_drain_status(domain: &str, status: Option<&DrainStatus>) {
+    let Some(status) = status else {
+        println!("\x1b[1;36m{}\x1b[0m: no route or in-flight requests", domain);
+        return;
+    };
+    match &status.current {
+        Some(current) => println!(
+            "\x1b[1;36m{}\x1b[0m: generation {} ({} requests, {} websockets in flight)",
+            status.domain, current.generation, current.requests, current.websockets
+        ),
+        None => println!("\x1b[1;36m{}\x1b[0m: route removed", status.domain),
+    }
+    for previous in &status.previous {
+        println!(
+            "  \x1b[1;33mgeneration {}\x1b[0m: {} requests, {} websockets still in flight",
+            previous.generation, previous.requests, previous.websockets
+        );
+    }
+    if status.drained {
+        println!("\x1b[1;32mdrained\x1b[0m: previous backends are no longer in use");
+    } else {
+        println!("\x1b[1;33mdraining\x1b[0m");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_runtime_flags_override_the_config_file() {
+        let dir = std::env::temp_dir().join(format!("minipx-cli-runtime-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("minipx.json");
+        std::fs::write(&path, r#"{ "runtime": { "worker_threads": 6, "max_blocking_threads": 24 } }"#).unwrap();
+        let config = path.to_str().unwrap();
+
+        let tuning = MinipxArguments::parse_from(["minipx", "--config", config]).runtime_tuning();
+        assert_eq!((tuning.worker_threads, tuning.max_blocking_threads), (6, 24));
+        let tuning = MinipxArguments::parse_from(["minipx", "--config", config, "--worker-threads", "2"]).runtime_tuning();
+        assert_eq!((tuning.worker_threads, tuning.max_blocking_threads), (2, 24));
+        assert_eq!(tuning.build_runtime().unwrap().metrics().num_workers(), 2);
+        assert!(MinipxArguments::try_parse_from(["minipx", "--worker-threads", "0"]).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
     #[test]
     fn test_proxy_route_args_to_proxy_route() {
         let args = ProxyRouteArgs {
             host: "localhost".to_string(),
             path: "/api".to_string(),
-            port: 8080,
+            port: Some(8080),
+            unix_socket: None,
             ssl_enable: true,
             listen_port: Some(8443),
             redirect_to_https: true,
+            upstream_host_header: Some("internal.company.lan".to_string()),
+            upstream_sni: None,
+            allow_self_reference: false,
+            active_from: None,
+            active_until: None,
         };
 
-        let route: minipx::config::ProxyRoute = args.into();
+        let route: ProxyRoute = args.into();
         assert_eq!(route.get_host(), "localhost");
         assert_eq!(route.get_path(), "/api");
         assert_eq!(route.get_port(), 8080);
         assert!(route.is_ssl_enabled());
         assert_eq!(route.get_listen_port(), Some(8443));
         assert!(route.get_redirect_to_https());
+        assert_eq!(route.get_upstream_host_header(), Some("internal.company.lan"));
+    }
+
+    #[test]
+    fn test_proxy_route_args_template_overrides() {
+        let args = ProxyRouteArgs {
+            host: "localhost".to_string(),
+            path: "".to_string(),
+            port: Some(3000),
+            unix_socket: None,
+            ssl_enable: true,
+            listen_port: Some(7777),
+            redirect_to_https: false,
+            upstream_host_header: None,
+            upstream_sni: None,
+            allow_self_reference: false,
+            active_from: None,
+            active_until: None,
+        };
+        let patch = args.template_overrides();
+        assert_eq!(patch, RoutePatch { ssl_enable: Some(true), listen_port: Some(7777), ..Default::default() });
+
+        let args = ProxyRouteArgs { host: "10.0.0.2".to_string(), path: "/app".to_string(), ssl_enable: false, listen_port: None, ..args };
+        let patch = args.template_overrides();
+        assert_eq!(patch, RoutePatch { host: Some("10.0.0.2".to_string()), path: Some("/app".to_string()), ..Default::default() });
     }
 
     #[test]
@@ -270,13 +1532,19 @@ mod tests {
         let args = ProxyRouteArgs {
             host: "127.0.0.1".to_string(),
             path: "".to_string(),
-            port: 3000,
+            port: Some(3000),
+            unix_socket: None,
             ssl_enable: false,
             listen_port: None,
             redirect_to_https: false,
+            upstream_host_header: None,
+            upstream_sni: None,
+            allow_self_reference: false,
+            active_from: None,
+            active_until: None,
         };
 
-        let route: minipx::config::ProxyRoute = args.into();
+        let route: ProxyRoute = args.into();
         assert_eq!(route.get_host(), "127.0.0.1");
         assert_eq!(route.get_path(), "");
         assert_eq!(route.get_port(), 3000);
@@ -295,6 +1563,7 @@ mod tests {
             no_ssl: false,
             redirect: true,
             no_redirect: false,
+            ..Default::default()
         };
 
         let patch: RoutePatch = options.into();
@@ -307,7 +1576,16 @@ mod tests {
 
     #[test]
     fn test_update_route_options_to_route_patch_ssl_disable() {
-        let options = UpdateRouteOptions { host: None, path: None, port: None, ssl: false, no_ssl: true, redirect: false, no_redirect: false };
+        let options = UpdateRouteOptions {
+            host: None,
+            path: None,
+            port: None,
+            ssl: false,
+            no_ssl: true,
+            redirect: false,
+            no_redirect: false,
+            ..Default::default()
+        };
 
         let patch: RoutePatch = options.into();
         assert_eq!(patch.host, None);
@@ -316,7 +1594,16 @@ mod tests {
 
     #[test]
     fn test_update_route_options_to_route_patch_redirect_disable() {
-        let options = UpdateRouteOptions { host: None, path: None, port: None, ssl: false, no_ssl: false, redirect: false, no_redirect: true };
+        let options = UpdateRouteOptions {
+            host: None,
+            path: None,
+            port: None,
+            ssl: false,
+            no_ssl: false,
+            redirect: false,
+            no_redirect: true,
+            ..Default::default()
+        };
 
         let patch: RoutePatch = options.into();
         assert_eq!(patch.redirect_to_https, Some(false));
@@ -324,7 +1611,16 @@ mod tests {
 
     #[test]
     fn test_update_route_options_to_route_patch_no_changes() {
-        let options = UpdateRouteOptions { host: None, path: None, port: None, ssl: false, no_ssl: false, redirect: false, no_redirect: false };
+        let options = UpdateRouteOptions {
+            host: None,
+            path: None,
+            port: None,
+            ssl: false,
+            no_ssl: false,
+            redirect: false,
+            no_redirect: false,
+            ..Default::default()
+        };
 
         let patch: RoutePatch = options.into();
         assert_eq!(patch.host, None);
@@ -335,6 +1631,23 @@ mod tests {
         assert_eq!(patch.listen_port, None);
     }
 
+    #[test]
+    fn test_update_route_options_websocket_and_host() {
+        let options = UpdateRouteOptions { no_websocket: true, preserve_host: true, ..Default::default() };
+
+        let patch: RoutePatch = options.into();
+        assert_eq!(patch.allow_websocket, Some(false));
+        assert_eq!(patch.preserve_host, Some(true));
+    }
+
+    #[test]
+    fn test_subroute_override_options() {
+        let overrides: SubrouteOverrides = SubrouteOverrideOptions { websocket: true, no_redirect: true, ..Default::default() }.into();
+        assert_eq!(overrides.allow_websocket, Some(true));
+        assert_eq!(overrides.redirect_to_https, Some(false));
+        assert_eq!(overrides.preserve_host, None);
+    }
+
     #[test]
     fn test_update_route_options_to_route_patch_partial() {
         let options = UpdateRouteOptions {
@@ -345,6 +1658,7 @@ mod tests {
             no_ssl: false,
             redirect: false,
             no_redirect: false,
+            ..Default::default()
         };
 
         let patch: RoutePatch = options.into();
@@ -354,4 +1668,67 @@ mod tests {
         assert_eq!(patch.ssl_enable, None);
         assert_eq!(patch.redirect_to_https, None);
     }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64k"), Ok(64 * 1024));
+        assert_eq!(parse_size("64KiB"), Ok(64 * 1024));
+        assert_eq!(parse_size("1m"), Ok(1024 * 1024));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("k").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_secs() {
+        assert_eq!(parse_duration_secs("90"), Ok(90));
+        assert_eq!(parse_duration_secs("90s"), Ok(90));
+        assert_eq!(parse_duration_secs("15m"), Ok(900));
+        assert_eq!(parse_duration_secs("1h"), Ok(3600));
+        assert!(parse_duration_secs("soon").is_err());
+        assert_eq!(parse_capture_duration("168h"), Ok(MAX_CAPTURE_DURATION_SECS));
+        assert!(parse_capture_duration("169h").is_err() && parse_capture_duration("18446744073709551615").is_err());
+        assert!(parse_capture_size("64m").is_ok() && parse_capture_size("65m").is_err());
+    }
+
+    #[test]
+    fn test_describe_activation() {
+        let at = |value: &str| parse_rfc3339(value).unwrap();
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 3000, false, None, false);
+        assert_eq!(describe_activation(&route, at("2026-12-24T12:00:00Z")), None);
+
+        route.set_active_from(Some(at("2026-12-24T18:00:00Z")));
+        route.set_active_until(Some(at("2026-12-26T00:00:00+01:00")));
+        let before = describe_activation(&route, at("2026-12-24T12:00:00Z")).unwrap();
+        assert!(before.contains("inactive") && before.ends_with("turns on at 2026-12-24T18:00:00Z"), "{}", before);
+        let during = describe_activation(&route, at("2026-12-25T12:00:00Z")).unwrap();
+        assert!(during.ends_with("turns off at 2026-12-26T00:00:00+01:00"), "{}", during);
+        assert!(describe_activation(&route, at("2027-01-01T00:00:00Z")).unwrap().ends_with("no upcoming transitions"));
+
+        assert_eq!(parse_timestamp(""), Ok(String::new()));
+        assert!(parse_timestamp("next friday").is_err());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_unix_socket_replaces_the_port() {
+        let args = MinipxArguments::try_parse_from(["minipx", "routes", "add", "app.example.com", "--unix-socket", "/run/app.sock"]).unwrap();
+        let Some(MinipxCommands::Routes { command: RouteCommands::AddRoute { routes, .. } }) = args.command else {
+            panic!("expected routes add");
+        };
+        let route: ProxyRoute = routes.into();
+        assert_eq!(route.get_unix_socket(), Some(std::path::Path::new("/run/app.sock")));
+        assert_eq!(describe_backend(&route), "unix:/run/app.sock");
+
+        assert!(
+            MinipxArguments::try_parse_from(["minipx", "routes", "add", "app.example.com", "-P", "3000", "--unix-socket", "/run/app.sock"]).is_err()
+        );
+        assert!(MinipxArguments::try_parse_from(["minipx", "routes", "add", "app.example.com"]).is_err());
+    }
 }
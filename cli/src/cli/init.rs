@@ -0,0 +1,220 @@
+//! `minipx config init`: write a first config file from flags, asking for whatever is missing on a terminal
+
+use anyhow::{Result, anyhow};
+use clap::Args;
+use minipx::config::{Config, ProxyRoute};
+use minipx::preflight::{self, CheckStatus, PreflightReport};
+use minipx::utils::validation::validate_custom_port;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct InitOptions {
+    /// Where to write the config file (default: the --config path, or ./minipx.json)
+    #[arg(long = "path")]
+    pub path: Option<String>,
+    /// Email for the ACME (Let's Encrypt) account that requests HTTPS certificates
+    #[arg(long = "email")]
+    pub email: Option<String>,
+    /// Directory for certificates and the ACME account (default: ./cache, or next to an absolute --path)
+    #[arg(long = "cache-dir")]
+    pub cache_dir: Option<String>,
+    /// Domain of a first route
+    #[arg(long = "domain", requires = "port")]
+    pub domain: Option<String>,
+    /// Backend port of the first route
+    #[arg(long = "port", requires = "domain")]
+    pub port: Option<u16>,
+    /// Backend host of the first route
+    #[arg(long = "host", default_value = "localhost")]
+    pub host: String,
+    /// Serve the first route over HTTPS (HTTP is redirected to it)
+    #[arg(long = "ssl")]
+    pub ssl: bool,
+    /// Never prompt; fail when a required value is not given as a flag
+    #[arg(long = "non-interactive")]
+    pub non_interactive: bool,
+    /// Overwrite an existing config file
+    #[arg(long = "force")]
+    pub force: bool,
+}
+
+/// Write the config file described by `options` (prompting on a terminal) and print what to do next
+pub async fn run(options: &InitOptions, config_path: Option<&str>) -> Result<()> {
+    let path = Path::new(options.path.as_deref().or(config_path).unwrap_or("./minipx.json")).with_extension("json");
+    if path.exists() && !options.force {
+        return Err(anyhow!("{} already exists; pass --force to overwrite it", path.display()));
+    }
+    let options = if !options.non_interactive && std::io::stdin().is_terminal() {
+        ask(options, &path, &mut std::io::stdin().lock(), &mut std::io::stdout())?
+    } else {
+        options.clone()
+    };
+    let config = build(&path, &options).await?;
+    config.save().await?;
+    print_next_steps(&config);
+    Ok(())
+}
+
+/// The config `options` describe, refused when a value is missing or invalid
+async fn build(path: &Path, options: &InitOptions) -> Result<Config> {
+    let email = options.email.as_deref().map(str::trim).unwrap_or_default();
+    if email.is_empty() {
+        return Err(anyhow!("An email is required for HTTPS certificates: pass --email you@example.com (or run config init on a terminal)"));
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| anyhow!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let mut config = Config::new(path);
+    config.set_email(email.to_string());
+    if !config.is_email_valid() {
+        return Err(anyhow!("Not a valid email address: {}", email));
+    }
+    config.set_cache_dir(options.cache_dir.clone().unwrap_or_else(|| default_cache_dir(path)));
+    if let (Some(domain), Some(port)) = (&options.domain, options.port) {
+        if options.ssl && !Config::validate_domain(domain) {
+            return Err(anyhow!("{} cannot get a certificate; HTTPS routes need a plain public domain name", domain));
+        }
+        config.add_route(domain.clone(), ProxyRoute::new(options.host.clone(), String::new(), port, options.ssl, None, options.ssl)).await?;
+    }
+
+    let mut report = PreflightReport::default();
+    preflight::check_config(&config, &mut report);
+    report.push(preflight::check_cache_dir(Path::new(config.get_cache_dir())));
+    let failures: Vec<String> =
+        report.checks.iter().filter(|check| check.status == CheckStatus::Fail).map(|check| format!("{}: {}", check.subject, check.detail)).collect();
+    if !failures.is_empty() {
+        return Err(anyhow!("The config would not work:\n  {}", failures.join("\n  ")));
+    }
+    Ok(config)
+}
+
+// Certificates live next to a config in a fixed place (e.g. /etc/minipx/cache); a relative config keeps the default
+fn default_cache_dir(path: &Path) -> String {
+    match path.parent() {
+        Some(parent) if parent.is_absolute() => parent.join("cache").to_string_lossy().into_owned(),
+        _ => "./cache".to_string(),
+    }
+}
+
+/// Prompt for every value not given as a flag
+fn ask(options: &InitOptions, path: &Path, input: &mut impl BufRead, output: &mut impl Write) -> Result<InitOptions> {
+    let mut options = options.clone();
+    writeln!(output, "Creating {}", path.display())?;
+    while options.email.is_none() {
+        let email = prompt(input, output, "Email for Let's Encrypt certificates", None)?;
+        let mut probe = Config::default();
+        probe.set_email(email.clone());
+        if probe.is_email_valid() {
+            options.email = Some(email);
+        } else {
+            writeln!(output, "  Not a valid email address")?;
+        }
+    }
+    if options.cache_dir.is_none() {
+        options.cache_dir = Some(prompt(input, output, "Certificate cache directory", Some(&default_cache_dir(path)))?);
+    }
+    if options.domain.is_none() {
+        let domain = prompt(input, output, "Domain of a first route (blank to skip)", Some(""))?;
+        if !domain.is_empty() {
+            while options.port.is_none() {
+                let port = prompt(input, output, &format!("Backend port for {}", domain), None)?;
+                match port.parse::<u16>().map_err(|e| e.to_string()).and_then(|port| validate_custom_port(port).map(|()| port)) {
+                    Ok(port) => options.port = Some(port),
+                    Err(e) => writeln!(output, "  {}", e)?,
+                }
+            }
+            options.host = prompt(input, output, "Backend host", Some(&options.host))?;
+            let https =
+                Config::validate_domain(&domain) && prompt(input, output, "Serve it over HTTPS? [Y/n]", Some("y"))?.to_lowercase().starts_with('y');
+            options.ssl = options.ssl || https;
+            options.domain = Some(domain);
+        }
+    }
+    Ok(options)
+}
+
+// One answer, or `default` when left blank; input ending before an answer is an error rather than a loop
+fn prompt(input: &mut impl BufRead, output: &mut impl Write, question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) if !default.is_empty() => write!(output, "{} [{}]: ", question, default)?,
+        _ => write!(output, "{}: ", question)?,
+    }
+    output.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(anyhow!("Input ended before config init finished; nothing was written"));
+    }
+    let answer = line.trim();
+    Ok(match default {
+        Some(default) if answer.is_empty() => default.to_string(),
+        _ => answer.to_string(),
+    })
+}
+
+fn print_next_steps(config: &Config) {
+    let path = config.get_path().display();
+    println!("\x1b[1;32mWrote {}\x1b[0m", path);
+    println!("Next steps:");
+    println!("  Check it:        minipx -c {} preflight", path);
+    println!("  Start minipx:    minipx -c {}", path);
+    println!("  Run as service:  minipx -c {} systemd-unit > /etc/systemd/system/minipx.service", path);
+    println!("  Add routes:      minipx -c {} routes add <domain> -P <backend port> [--ssl --redirect]", path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minipx-init-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("minipx.json")
+    }
+
+    fn options(email: Option<&str>) -> InitOptions {
+        InitOptions { email: email.map(String::from), host: "localhost".to_string(), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_missing_or_invalid_email_is_refused() {
+        let path = temp_path("email");
+        let err = build(&path, &options(None)).await.unwrap_err().to_string();
+        assert!(err.contains("--email"), "{}", err);
+        assert!(build(&path, &options(Some("not-an-email"))).await.is_err());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_builds_config_with_first_route() {
+        let path = temp_path("route");
+        let first_route =
+            InitOptions { domain: Some("app.example.com".to_string()), port: Some(3000), ssl: true, ..options(Some("admin@example.com")) };
+        let config = build(&path, &first_route).await.unwrap();
+        assert_eq!(config.get_email(), "admin@example.com");
+        assert_eq!(config.get_cache_dir(), &path.parent().unwrap().join("cache").to_string_lossy());
+        let route = &config.get_routes()["app.example.com"];
+        assert!(route.is_ssl_enabled() && route.get_redirect_to_https());
+        assert_eq!(route.get_port(), 3000);
+
+        let reserved = InitOptions { domain: Some("app.example.com".to_string()), port: Some(443), ..options(Some("admin@example.com")) };
+        assert!(build(&path, &reserved).await.is_err());
+    }
+
+    #[test]
+    fn test_prompts_for_missing_values() {
+        let path = temp_path("prompt");
+        let answers = "nope\nadmin@example.com\n\napp.example.com\n80\n3000\n127.0.0.1\n\n";
+        let mut output = Vec::new();
+        let asked = ask(&options(None), &path, &mut answers.as_bytes(), &mut output).unwrap();
+        assert_eq!(asked.email.as_deref(), Some("admin@example.com"));
+        assert_eq!(asked.cache_dir, Some(default_cache_dir(&path)));
+        assert_eq!((asked.domain.as_deref(), asked.port, asked.host.as_str(), asked.ssl), (Some("app.example.com"), Some(3000), "127.0.0.1", true));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Not a valid email address"), "{}", output);
+
+        // Running out of input stops instead of writing a half-finished config
+        assert!(ask(&options(None), &path, &mut "admin@example.com\n".as_bytes(), &mut Vec::new()).is_err());
+    }
+}
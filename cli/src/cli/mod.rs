@@ -2,8 +2,12 @@
 //
 // This module contains command-line interface functionality:
 // - arguments: Command-line argument parsing and handling (renamed from command_line_arguments.rs)
+// - init: Guided first-run setup behind `config init`
+// - test_request: One request through the local proxy with a route's Host, behind `routes test-request`
 
 pub mod arguments;
+pub mod init;
+pub mod test_request;
 
 // Re-export main types for backward compatibility
 pub use arguments::MinipxArguments;
@@ -0,0 +1,179 @@
+//! `minipx routes test-request`: send one request through the local proxy with a route's Host (and SNI), as if DNS
+//! already pointed the domain at this machine
+
+use anyhow::{Context, Result, anyhow};
+use hyper::body::HttpBody;
+use hyper::client::conn;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use minipx::proxy::request_handler::{ROUTE_HEADER, UPSTREAM_HEADER};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_native_tls::native_tls;
+
+// Connecting, and waiting for the response headers, each give up after this long
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What to send
+#[derive(Debug, Clone)]
+pub struct TestRequest {
+    pub domain: String,
+    pub path: String,
+    pub method: Method,
+    pub https: bool,
+    /// Local port to connect to; 80 or 443 when unset
+    pub port: Option<u16>,
+    /// Body bytes to keep for printing; the rest is not read
+    pub max_body: usize,
+}
+
+/// What came back
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    /// Until the response headers arrived
+    pub latency: Duration,
+    /// `X-Upstream` / `X-Minipx-Route`, sent when the proxy has `debug_headers` on
+    pub upstream: Option<String>,
+    pub route: Option<String>,
+    /// Up to `max_body` bytes of the body
+    pub body: Vec<u8>,
+    /// The body was longer than what was kept
+    pub truncated: bool,
+}
+
+impl TestRequest {
+    fn addr(&self) -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, self.port.unwrap_or(if self.https { 443 } else { 80 })))
+    }
+
+    /// The URL the request stands for, for display
+    pub fn url(&self) -> String {
+        let scheme = if self.https { "https" } else { "http" };
+        match self.port {
+            Some(port) => format!("{}://{}:{}{}", scheme, self.domain, port, self.path),
+            None => format!("{}://{}{}", scheme, self.domain, self.path),
+        }
+    }
+
+    /// Connect to the local listener and send the request, with the domain as Host and (for HTTPS) SNI.
+    /// The certificate is accepted whatever it is: the point is to reach this machine's proxy, not to verify it.
+    pub async fn send(&self) -> Result<TestResponse> {
+        let addr = self.addr();
+        let tcp = tokio::time::timeout(TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to {}", addr))?
+            .with_context(|| format!("Failed to connect to {}; is minipx running?", addr))?;
+        let started = Instant::now();
+        let response = if self.https {
+            let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true).build()?;
+            let tls = tokio_native_tls::TlsConnector::from(connector)
+                .connect(&self.domain, tcp)
+                .await
+                .with_context(|| format!("TLS handshake with {} for {} failed", addr, self.domain))?;
+            self.exchange(tls).await?
+        } else {
+            self.exchange(tcp).await?
+        };
+        let latency = started.elapsed();
+
+        let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        let (upstream, route) = (header(UPSTREAM_HEADER), header(ROUTE_HEADER));
+        let status = response.status();
+        let (body, truncated) = read_prefix(response.into_body(), self.max_body).await?;
+        Ok(TestResponse { status, latency, upstream, route, body, truncated })
+    }
+
+    async fn exchange<T>(&self, io: T) -> Result<Response<Body>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut sender, connection) = conn::Builder::new().handshake(io).await?;
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .method(self.method.clone())
+            .uri(self.path.as_str())
+            .header(header::HOST, HeaderValue::from_str(&self.domain)?)
+            .header(header::USER_AGENT, concat!("minipx-test-request/", env!("CARGO_PKG_VERSION")))
+            .header(header::CONNECTION, "close")
+            .body(Body::empty())?;
+        tokio::time::timeout(TIMEOUT, sender.send_request(request))
+            .await
+            .map_err(|_| anyhow!("No response headers within {} s", TIMEOUT.as_secs()))?
+            .context("The proxy closed the connection without a response")
+    }
+}
+
+impl TestResponse {
+    /// 2xx and 3xx count as success
+    pub fn is_success(&self) -> bool {
+        self.status.is_success() || self.status.is_redirection()
+    }
+}
+
+// The first `max` bytes of `body`, and whether there was more
+async fn read_prefix(mut body: Body, max: usize) -> Result<(Vec<u8>, bool)> {
+    let mut prefix = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let room = max - prefix.len();
+        if chunk.len() > room {
+            prefix.extend_from_slice(&chunk[..room]);
+            return Ok((prefix, true));
+        }
+        prefix.extend_from_slice(&chunk);
+    }
+    Ok((prefix, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_sends_the_domain_as_host_and_keeps_a_body_prefix() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0u8; 1];
+                stream.read_exact(&mut byte).await.unwrap();
+                head.push(byte[0]);
+            }
+            let body = "0123456789";
+            let response = format!(
+                "HTTP/1.1 404 Not Found\r\ncontent-length: {}\r\nx-upstream: http://127.0.0.1:3000\r\nx-minipx-route: app.example.com\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(head).unwrap()
+        });
+
+        let request = TestRequest {
+            domain: "app.example.com".to_string(),
+            path: "/health".to_string(),
+            method: Method::GET,
+            https: false,
+            port: Some(port),
+            max_body: 4,
+        };
+        assert_eq!(request.url(), format!("http://app.example.com:{}/health", port));
+        let response = request.send().await.unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert!(!response.is_success());
+        assert_eq!(response.upstream.as_deref(), Some("http://127.0.0.1:3000"));
+        assert_eq!(response.route.as_deref(), Some("app.example.com"));
+        assert_eq!((response.body.as_slice(), response.truncated), (b"0123".as_slice(), true));
+
+        let head = server.await.unwrap().to_lowercase();
+        assert!(head.starts_with("get /health http/1.1\r\n"), "{}", head);
+        assert!(head.contains("\r\nhost: app.example.com\r\n"), "{}", head);
+    }
+}
@@ -1,42 +1,145 @@
 mod cli;
+#[cfg(windows)]
+mod service;
 
 use crate::cli::MinipxArguments;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use clap::Parser;
 use log::{LevelFilter, info, trace};
-use minipx::{config::Config, ipc, proxy, ssl_server};
+use minipx::log_capture::{self, LogCaptureOptions};
+use minipx::preflight::{self, PreflightOptions};
+use minipx::runtime_tuning::RuntimeTuning;
+use minipx::startup::StartupSummary;
+use minipx::{config::Config, handoff, ipc, proxy, ssl_server, systemd};
+use pretty_env_logger::env_logger;
+use std::path::PathBuf;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let args = MinipxArguments::parse();
 
-    // Initialize logging - Ignore any errors here,
-    // as we don't want to fail if we can't initialize logging
-    let _ = pretty_env_logger::env_logger::builder()
-        .format_timestamp(None)
-        .filter_level(if args.verbose { LevelFilter::Trace } else { LevelFilter::Info })
-        .try_init();
+    // Under the Service Control Manager there is no console: the service sets up file logging and runs the proxy itself
+    #[cfg(windows)]
+    if args.is_service_run() {
+        return service::run_as_service(args);
+    }
+
+    // Initialize logging - only a log file that cannot be opened is an error
+    let mut console = env_logger::builder();
+    console.format_timestamp(None).filter_level(if args.verbose { LevelFilter::Trace } else { LevelFilter::Info });
+    init_logging(console, args.log_file.clone())?;
+
+    // Sized before anything async runs, from the config file and the command line
+    let tuning = args.runtime_tuning();
+    tuning.build_runtime()?.block_on(run(args, tuning))
+}
+
+/// Install `console` as the logger. Its records are also captured for the web panel when it is built in, and
+/// appended to `log_file` when given; a logger that is already installed is kept.
+pub(crate) fn init_logging(mut console: env_logger::Builder, log_file: Option<PathBuf>) -> Result<()> {
+    let console = console.build();
+    let max_level = console.filter();
+    let capacity = if cfg!(feature = "webui") { log_capture::DEFAULT_CAPACITY } else { 0 };
+    if capacity == 0 && log_file.is_none() {
+        let _ = log::set_boxed_logger(Box::new(console)).map(|()| log::set_max_level(max_level));
+        return Ok(());
+    }
+    let options = LogCaptureOptions { capacity, file: log_file.clone() };
+    match log_capture::install(Box::new(console), max_level, options) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            Err(anyhow!("Failed to open the log file {}: {}", log_file.unwrap_or_default().display(), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn run(args: MinipxArguments, tuning: RuntimeTuning) -> Result<()> {
+    if args.print_config_summary {
+        let (config_path, source) = Config::resolve_config_path_with_source(args.config_path.clone()).await;
+        let config = Config::read(&config_path).await?;
+        print!("{}", StartupSummary::new(&config, source, args.watch_config, false));
+        return Ok(());
+    }
+
+    // Routes pointing at the web panel served by this binary are a loop unless they opt in
+    #[cfg(feature = "webui")]
+    minipx::config::register_listener_port(minipx_web_lib::PORT, "the web panel");
 
     // Handle command line arguments
     args.handle_arguments().await?;
 
-    info!("Starting minipx");
+    // In the foreground the proxy runs until the process is killed, or has drained after a warm restart
+    run_proxy(&args, tuning, handoff::drained()).await
+}
+
+/// Load the config and run the proxy until `shutdown` completes, on a runtime built from `tuning`
+pub(crate) async fn run_proxy(args: &MinipxArguments, tuning: RuntimeTuning, shutdown: impl Future<Output = ()>) -> Result<()> {
+    info!("Starting minipx {}", env!("CARGO_PKG_VERSION"));
     trace!("Arguments: {:#?}", args);
+    tuning.log();
 
-    let effective_config_path = Config::resolve_config_path(args.config_path.clone()).await;
-    let config = Config::try_load(&effective_config_path).await?;
+    // Started by a warm restart: serve the config the previous process served, on its listeners
+    let taking_over = handoff::adopt()?;
+    let (effective_config_path, config_source) = match &taking_over {
+        Some(state) => Config::resolve_config_path_with_source(Some(state.config_path.to_string_lossy().into_owned())).await,
+        None => Config::resolve_config_path_with_source(args.config_path.clone()).await,
+    };
+    let loaded = if args.init_default_config {
+        Config::try_load_or_init_default(&effective_config_path).await
+    } else {
+        Config::try_load(&effective_config_path).await
+    };
+    let config = match (loaded, &taking_over) {
+        (Ok(config), Some(state)) => match state.check(&config) {
+            Ok(()) => config,
+            Err(e) => {
+                handoff::reject(&e.to_string());
+                return Err(e);
+            }
+        },
+        (Ok(config), None) => config,
+        (Err(e), _) => {
+            handoff::reject(&format!("failed to load the config: {}", e));
+            return Err(e);
+        }
+    };
     if args.watch_config {
         config.watch_config_file();
     }
+    if args.fail_on_bind_error {
+        minipx::proxy::bind::set_fail_on_bind_error();
+    }
+
+    // Environment problems are reported, but never stop the proxy from starting
+    preflight::run(&config, &PreflightOptions::for_config(&config)).await.log();
 
     ipc::start_ipc_server(std::path::PathBuf::from(&effective_config_path));
+    handoff::spawn_upgrade_signal_handler(std::path::PathBuf::from(&effective_config_path));
+
+    // Once the frontends have bound (or settled into waiting), log what this instance is running with
+    let watch_config = args.watch_config;
+    tokio::spawn(async move {
+        systemd::listeners_settled().await;
+        // Serving now, so the process this one took over from can stop accepting
+        handoff::accept();
+        StartupSummary::new(&Config::get().await, config_source, watch_config, true).with_listeners().log();
+    });
 
     // Run HTTP and HTTPS servers concurrently
-    #[cfg(feature = "webui")]
-    tokio::try_join!(proxy::start_rp_server(), ssl_server::start_ssl_server(), minipx_web_lib::run())?;
+    let servers = async {
+        #[cfg(feature = "webui")]
+        tokio::try_join!(proxy::start_rp_server(), ssl_server::start_ssl_server(), minipx_web_lib::run_embedded(tuning.web_workers))?;
 
-    #[cfg(not(feature = "webui"))]
-    tokio::try_join!(proxy::start_rp_server(), ssl_server::start_ssl_server())?;
+        #[cfg(not(feature = "webui"))]
+        tokio::try_join!(proxy::start_rp_server(), ssl_server::start_ssl_server())?;
 
-    Ok(())
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        result = servers => result,
+        _ = shutdown => {
+            info!("Shutting down");
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,177 @@
+//! Windows service integration: install/uninstall/start/stop the CLI as a service and run under the
+//! Service Control Manager, where there is no console and logs go to a file next to the config.
+
+use crate::cli::MinipxArguments;
+use anyhow::{Result, anyhow};
+use log::{LevelFilter, error, info};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState,
+    ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = "minipx";
+const SERVICE_DISPLAY_NAME: &str = "minipx reverse proxy";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+const LOG_FILE_NAME: &str = "minipx-service.log";
+
+// Arguments the service was launched with, handed from `main` to the service entry point
+static SERVICE_ARGS: OnceLock<MinipxArguments> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Register the current binary as an auto-start service running with `config_path`
+pub fn install(config_path: &Path) -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![
+            OsString::from("--config"),
+            config_path.as_os_str().to_owned(),
+            OsString::from("--watch"),
+            OsString::from("service"),
+            OsString::from("run"),
+        ],
+        dependencies: vec![],
+        account_name: None, // LocalSystem
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Reverse proxy with automatic HTTPS")?;
+    info!("Installed service '{}' with config {}", SERVICE_NAME, config_path.display());
+    Ok(())
+}
+
+/// Stop (if running) and remove the service
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+    service.delete()?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    info!("Removed service '{}' (deleted once it has stopped)", SERVICE_NAME);
+    Ok(())
+}
+
+pub fn start() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::START)?;
+    service.start::<&OsStr>(&[])?;
+    info!("Started service '{}'", SERVICE_NAME);
+    Ok(())
+}
+
+/// Ask the service to stop and wait (up to 30s) for it to finish shutting down
+pub fn stop() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::STOP | ServiceAccess::QUERY_STATUS)?;
+    if service.query_status()?.current_state == ServiceState::Stopped {
+        info!("Service '{}' is not running", SERVICE_NAME);
+        return Ok(());
+    }
+    service.stop()?;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while service.query_status()?.current_state != ServiceState::Stopped {
+        if Instant::now() > deadline {
+            return Err(anyhow!("Service '{}' did not stop within 30s", SERVICE_NAME));
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    }
+    info!("Stopped service '{}'", SERVICE_NAME);
+    Ok(())
+}
+
+/// State of the installed service, or None if it is not installed
+pub fn status() -> Option<ServiceState> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).ok()?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS).ok()?;
+    service.query_status().ok().map(|s| s.current_state)
+}
+
+/// Entry point when launched by the Service Control Manager (`minipx service run`); blocks until the service stops
+pub fn run_as_service(args: MinipxArguments) -> Result<()> {
+    let log_file = log_file_path(&args);
+    init_file_logging(&log_file, args.log_file.clone())?;
+    let _ = SERVICE_ARGS.set(args);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .map_err(|e| anyhow!("Failed to start service dispatcher (not launched by the SCM?): {}", e))
+}
+
+// There is no console under the SCM, so logs go next to the config file
+fn log_file_path(args: &MinipxArguments) -> PathBuf {
+    args.config_path.as_deref().map(|path| Path::new(path).with_file_name(LOG_FILE_NAME)).unwrap_or_else(|| PathBuf::from(LOG_FILE_NAME))
+}
+
+fn init_file_logging(path: &Path, capture_file: Option<PathBuf>) -> Result<()> {
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    let mut logger = pretty_env_logger::env_logger::builder();
+    logger
+        .target(pretty_env_logger::env_logger::Target::Pipe(Box::new(file)))
+        .write_style(pretty_env_logger::env_logger::WriteStyle::Never)
+        .format_timestamp_secs()
+        .filter_level(LevelFilter::Info);
+    crate::init_logging(logger, capture_file)
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Service failed: {}", e);
+    }
+}
+
+fn set_state(handle: &service_control_handler::ServiceStatusHandle, state: ServiceState, exit_code: u32) -> Result<()> {
+    let controls_accepted =
+        if state == ServiceState::Running { ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN } else { ServiceControlAccept::empty() };
+    handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(10),
+        process_id: None,
+    })?;
+    Ok(())
+}
+
+fn run_service() -> Result<()> {
+    let args = SERVICE_ARGS.get().cloned().ok_or_else(|| anyhow!("Service arguments not set"))?;
+    let shutdown = Arc::new(tokio::sync::Notify::new());
+
+    // Stop and system shutdown both take the graceful shutdown path
+    let notify = shutdown.clone();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            notify.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let handle = service_control_handler::register(SERVICE_NAME, handler)?;
+    set_state(&handle, ServiceState::Running, 0)?;
+
+    let tuning = args.runtime_tuning();
+    let runtime = tuning.build_runtime()?;
+    let result = runtime.block_on(crate::run_proxy(&args, tuning, async move { shutdown.notified().await }));
+    set_state(&handle, ServiceState::StopPending, 0)?;
+    runtime.shutdown_timeout(Duration::from_secs(5));
+
+    if let Err(e) = &result {
+        error!("minipx stopped with an error: {}", e);
+    }
+    set_state(&handle, ServiceState::Stopped, if result.is_ok() { 0 } else { 1 })?;
+    result
+}
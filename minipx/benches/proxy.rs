@@ -0,0 +1,317 @@
+//! Proxy overhead benchmarks
+//!
+//! Measures what minipx adds on top of a no-op local upstream:
+//! - small GET latency (p50/p99 added over a direct request) and concurrent throughput
+//! - large streamed responses
+//! - websocket message echo through the tunnel
+//! - route lookup and per-request config clone cost with 10/1k/10k routes
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Run and print results
+//! cargo bench -p minipx --bench proxy
+//!
+//! # Compare against the stored baseline (regressions beyond the threshold are flagged)
+//! cargo bench -p minipx --bench proxy -- --baseline benches/baseline.json
+//!
+//! # Record a new baseline
+//! cargo bench -p minipx --bench proxy -- --save-baseline benches/baseline.json
+//! ```
+//!
+//! Paths are relative to the `minipx` crate directory. Absolute numbers are machine dependent;
+//! record the baseline on the machine you compare on.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{MockUpstream, route, spawn_ws_echo_upstream, test_config};
+use futures_util::{SinkExt, StreamExt};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Request, Response, StatusCode};
+use minipx::config::Config;
+use minipx::config::manager::config_lock;
+use minipx::proxy::request_handler::handle_request_with_scheme;
+use serde_json::{Map, Value, json};
+use std::convert::Infallible;
+use std::hint::black_box;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+const HOST: &str = "bench.example.com";
+const LARGE_HOST: &str = "large.example.com";
+const WS_HOST: &str = "ws.example.com";
+const LARGE_BODY_BYTES: usize = 8 * 1024 * 1024;
+// Relative change beyond which a metric is reported as a regression
+const REGRESSION_THRESHOLD: f64 = 0.10;
+
+/// A single measured value
+struct Metric {
+    name: String,
+    value: f64,
+    unit: &'static str,
+    lower_is_better: bool,
+}
+
+#[derive(Default)]
+struct Report {
+    metrics: Vec<Metric>,
+}
+
+impl Report {
+    fn push(&mut self, name: impl Into<String>, value: f64, unit: &'static str, lower_is_better: bool) {
+        let name = name.into();
+        println!("{:<44} {:>14.2} {}", name, value, unit);
+        self.metrics.push(Metric { name, value, unit, lower_is_better });
+    }
+
+    fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        for m in &self.metrics {
+            map.insert(m.name.clone(), json!({ "value": m.value, "unit": m.unit, "lower_is_better": m.lower_is_better }));
+        }
+        json!({ "version": env!("CARGO_PKG_VERSION"), "metrics": map })
+    }
+
+    /// Print a comparison table against a baseline JSON; returns the number of regressions
+    fn compare(&self, baseline: &Value) -> usize {
+        let mut regressions = 0;
+        println!("\n{:<44} {:>14} {:>14} {:>9}", "metric", "baseline", "current", "change");
+        for m in &self.metrics {
+            let Some(base) = baseline["metrics"][&m.name]["value"].as_f64() else {
+                println!("{:<44} {:>14} {:>14.2} {:>9}", m.name, "-", m.value, "new");
+                continue;
+            };
+            let change = if base == 0.0 { 0.0 } else { (m.value - base) / base };
+            let worse = if m.lower_is_better { change > REGRESSION_THRESHOLD } else { change < -REGRESSION_THRESHOLD };
+            if worse {
+                regressions += 1;
+            }
+            println!("{:<44} {:>14.2} {:>14.2} {:>+8.1}%{}", m.name, base, m.value, change * 100.0, if worse { "  REGRESSION" } else { "" });
+        }
+        regressions
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let idx = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[idx]
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs_f64() * 1_000_000.0
+}
+
+/// Frontend that goes through `handle_request_with_scheme`, i.e. the global config path used in production
+async fn spawn_global_proxy() -> SocketAddr {
+    let make_svc = make_service_fn(|conn: &AddrStream| {
+        let client_ip = conn.remote_addr().ip();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+                match handle_request_with_scheme("http", client_ip, req).await {
+                    Ok(resp) => Ok::<_, Infallible>(resp),
+                    Err(_) => Ok(Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap()),
+                }
+            }))
+        }
+    });
+    let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+fn get(addr: SocketAddr, host: &str, path: &str) -> Request<Body> {
+    Request::builder().uri(format!("http://{}{}", addr, path)).header("Host", host).body(Body::empty()).unwrap()
+}
+
+/// Sequential request latencies, sorted
+async fn latencies(client: &Client<hyper::client::HttpConnector>, addr: SocketAddr, host: &str, iterations: usize) -> Vec<Duration> {
+    let mut samples = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let resp = client.request(get(addr, host, "/")).await.expect("request");
+        hyper::body::to_bytes(resp.into_body()).await.expect("body");
+        samples.push(start.elapsed());
+    }
+    samples.sort();
+    samples
+}
+
+async fn bench_small_gets(report: &mut Report, upstream: SocketAddr, proxy: SocketAddr) {
+    let client = Client::new();
+    // Warm up connections on both paths
+    latencies(&client, upstream, HOST, 200).await;
+    latencies(&client, proxy, HOST, 200).await;
+
+    let direct = latencies(&client, upstream, HOST, 2000).await;
+    let proxied = latencies(&client, proxy, HOST, 2000).await;
+    report.push("small_get.added_latency_p50", micros(percentile(&proxied, 0.50).saturating_sub(percentile(&direct, 0.50))), "us", true);
+    report.push("small_get.added_latency_p99", micros(percentile(&proxied, 0.99).saturating_sub(percentile(&direct, 0.99))), "us", true);
+
+    // Concurrent throughput through the proxy
+    let tasks = 32;
+    let per_task = 250;
+    let start = Instant::now();
+    let handles: Vec<_> = (0..tasks)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                for _ in 0..per_task {
+                    let resp = client.request(get(proxy, HOST, "/")).await.expect("request");
+                    hyper::body::to_bytes(resp.into_body()).await.expect("body");
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.await.unwrap();
+    }
+    let rps = (tasks * per_task) as f64 / start.elapsed().as_secs_f64();
+    report.push("small_get.proxied_throughput", rps, "req/s", false);
+}
+
+async fn bench_large_responses(report: &mut Report, upstream: SocketAddr, proxy: SocketAddr) {
+    let client = Client::new();
+    let iterations = 20;
+    let measure = |addr: SocketAddr| {
+        let client = client.clone();
+        async move {
+            let mut samples = Vec::with_capacity(iterations);
+            for _ in 0..iterations {
+                let start = Instant::now();
+                let resp = client.request(get(addr, LARGE_HOST, "/")).await.expect("request");
+                let mut body = resp.into_body();
+                let mut total = 0usize;
+                while let Some(chunk) = body.next().await {
+                    total += chunk.expect("chunk").len();
+                }
+                assert_eq!(total, LARGE_BODY_BYTES);
+                samples.push(start.elapsed());
+            }
+            samples.sort();
+            samples
+        }
+    };
+    let direct = measure(upstream).await;
+    let proxied = measure(proxy).await;
+    let mb = LARGE_BODY_BYTES as f64 / (1024.0 * 1024.0);
+    report.push("large_response.proxied_throughput", mb / percentile(&proxied, 0.50).as_secs_f64(), "MiB/s", false);
+    report.push(
+        "large_response.added_latency_p50",
+        percentile(&proxied, 0.50).saturating_sub(percentile(&direct, 0.50)).as_secs_f64() * 1000.0,
+        "ms",
+        true,
+    );
+}
+
+async fn ws_round_trips(addr: SocketAddr, messages: usize) -> Vec<Duration> {
+    let mut req = format!("ws://{}/", addr).into_client_request().unwrap();
+    req.headers_mut().insert("Host", WS_HOST.parse().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(req).await.expect("websocket handshake");
+    let mut samples = Vec::with_capacity(messages);
+    for i in 0..messages {
+        let start = Instant::now();
+        ws.send(Message::text(format!("message {}", i))).await.unwrap();
+        ws.next().await.expect("echo").unwrap();
+        samples.push(start.elapsed());
+    }
+    let _ = ws.close(None).await;
+    samples.sort();
+    samples
+}
+
+async fn bench_websocket(report: &mut Report, upstream: SocketAddr, proxy: SocketAddr) {
+    ws_round_trips(proxy, 100).await;
+    let direct = ws_round_trips(upstream, 2000).await;
+    let proxied = ws_round_trips(proxy, 2000).await;
+    report.push("websocket_echo.added_rtt_p50", micros(percentile(&proxied, 0.50).saturating_sub(percentile(&direct, 0.50))), "us", true);
+    report.push("websocket_echo.added_rtt_p99", micros(percentile(&proxied, 0.99).saturating_sub(percentile(&direct, 0.99))), "us", true);
+}
+
+async fn config_with_routes(count: usize) -> Config {
+    let mut config = test_config();
+    for i in 0..count {
+        let domain = if i % 10 == 0 { format!("*.zone{}.example.com", i) } else { format!("host{}.example.com", i) };
+        config.add_route(domain, route(8000 + (i % 1000) as u16)).await.unwrap();
+    }
+    config
+}
+
+async fn bench_route_lookup(report: &mut Report) {
+    for count in [10usize, 1_000, 10_000] {
+        let config = config_with_routes(count).await;
+        let iterations = 200_000 / count.max(100) * 100;
+
+        let exact = format!("host{}.example.com", count - 1);
+        let start = Instant::now();
+        for _ in 0..iterations {
+            black_box(config.lookup_host(black_box(&exact)));
+        }
+        report.push(format!("route_lookup.exact.{}", count), start.elapsed().as_nanos() as f64 / iterations as f64, "ns/op", true);
+
+        let wildcard = "api.zone0.example.com";
+        let start = Instant::now();
+        for _ in 0..iterations {
+            black_box(config.lookup_host(black_box(wildcard)));
+        }
+        report.push(format!("route_lookup.wildcard.{}", count), start.elapsed().as_nanos() as f64 / iterations as f64, "ns/op", true);
+
+        let clones = (iterations / 100).max(10);
+        let start = Instant::now();
+        for _ in 0..clones {
+            black_box(config.clone());
+        }
+        report.push(format!("config_clone.{}", count), start.elapsed().as_nanos() as f64 / clones as f64, "ns/op", true);
+    }
+}
+
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned()
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // `cargo test --benches` runs bench binaries with `--test`-style flags only; skip the heavy work there
+    if !args.iter().any(|a| a == "--bench") {
+        return;
+    }
+
+    let small = MockUpstream::spawn("bench").await;
+    let large_body = hyper::body::Bytes::from(vec![b'x'; LARGE_BODY_BYTES]);
+    let large = MockUpstream::spawn_with(move |_| Response::new(Body::from(large_body.clone()))).await;
+    let (ws_upstream, _) = spawn_ws_echo_upstream().await;
+
+    let mut config = test_config();
+    config.add_route(HOST.to_string(), route(small.port())).await.unwrap();
+    config.add_route(LARGE_HOST.to_string(), route(large.port())).await.unwrap();
+    config.add_route(WS_HOST.to_string(), route(ws_upstream.port())).await.unwrap();
+    *config_lock().write().await = config;
+    let proxy = spawn_global_proxy().await;
+
+    let mut report = Report::default();
+    bench_small_gets(&mut report, small.addr, proxy).await;
+    bench_large_responses(&mut report, large.addr, proxy).await;
+    bench_websocket(&mut report, ws_upstream, proxy).await;
+    bench_route_lookup(&mut report).await;
+
+    if let Some(path) = arg_value(&args, "--save-baseline") {
+        std::fs::write(&path, serde_json::to_string_pretty(&report.to_json()).unwrap()).expect("write baseline");
+        println!("\nBaseline saved to {}", path);
+    }
+    if let Some(path) = arg_value(&args, "--baseline") {
+        let baseline: Value = serde_json::from_str(&std::fs::read_to_string(&path).expect("read baseline")).expect("parse baseline");
+        let regressions = report.compare(&baseline);
+        if regressions > 0 {
+            println!("\n{} metric(s) regressed by more than {:.0}%", regressions, REGRESSION_THRESHOLD * 100.0);
+            std::process::exit(1);
+        }
+    }
+}
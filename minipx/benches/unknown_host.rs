@@ -0,0 +1,106 @@
+//! Unknown-host fast path benchmark
+//!
+//! Measures the time and heap allocations per request of the responses given to scanner traffic once the client
+//! is known: the `404` for an unknown host, the `400` for a malformed Host and the closed connection past the
+//! per-client limit. Every one of them should allocate nothing; the run exits with 1 if any does.
+//! A full unknown-host request is measured too: `resolve_host` on its headers, then the `404`. Resolving returns
+//! the normalized host as an owned string, so that case may allocate once and no more.
+//!
+//! # Usage
+//!
+//! ```bash
+//! cargo bench -p minipx --bench unknown_host
+//! ```
+//!
+//! Without `--bench` (e.g. `cargo test --benches`) only the allocation check runs, with fewer iterations.
+
+use hyper::{Body, Request};
+use minipx::config::UnknownHostConfig;
+use minipx::proxy::request_handler::{HostRejection, resolve_host};
+use minipx::proxy::unknown_host::{reject_host, respond};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::hint::black_box;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// The system allocator, counting allocations made by the current thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Run `f` `iterations` times after a warmup; prints and returns the allocations per call
+fn measure(name: &str, iterations: u64, mut f: impl FnMut()) -> f64 {
+    for _ in 0..1_000 {
+        f();
+    }
+    let before = allocations();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let per_call = (allocations() - before) as f64 / iterations as f64;
+    println!("{:<32} {:>10.1} ns/op {:>8.2} allocs/op", name, elapsed.as_nanos() as f64 / iterations as f64, per_call);
+    per_call
+}
+
+fn main() {
+    let iterations = if std::env::args().any(|a| a == "--bench") { 1_000_000 } else { 10_000 };
+
+    let mut unlimited = UnknownHostConfig::default();
+    unlimited.set_rate_limit_burst(0);
+    let mut exhausted = UnknownHostConfig::default();
+    exhausted.set_rate_limit_burst(1);
+    exhausted.set_rate_limit_per_minute(0);
+    let scanner: IpAddr = "198.51.100.7".parse().unwrap();
+    let limited_scanner: IpAddr = "198.51.100.8".parse().unwrap();
+
+    let results = [
+        measure("unknown_host.not_found", iterations, || {
+            black_box(respond(&unlimited, scanner, black_box("scan.invalid")).ok());
+        }),
+        measure("unknown_host.bad_host", iterations, || {
+            black_box(reject_host(&unlimited, scanner, black_box(&HostRejection::MultipleHosts)).ok());
+        }),
+        measure("unknown_host.rate_limited", iterations, || {
+            black_box(respond(&exhausted, limited_scanner, black_box("scan.invalid")).err());
+        }),
+    ];
+
+    let request = Request::builder().uri("/wp-login.php").header("host", "Scan.Invalid:80").body(Body::empty()).unwrap();
+    let resolved = measure("unknown_host.resolved_not_found", iterations, || {
+        let host = resolve_host(black_box(&request), false).unwrap();
+        black_box(respond(&unlimited, scanner, &host).ok());
+    });
+
+    if results.iter().any(|&per_call| per_call > 0.0) || resolved > 1.0 {
+        println!("\nThe unknown-host path allocated after warmup");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,13 @@
+// Records the commit minipx is built from as MINIPX_GIT_HASH for the startup summary; builds outside a git checkout
+// (e.g. from crates.io) go without one.
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+    if let Ok(output) = Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output()
+        && output.status.success()
+    {
+        println!("cargo:rustc-env=MINIPX_GIT_HASH={}", String::from_utf8_lossy(&output.stdout).trim());
+    }
+}
@@ -101,16 +101,17 @@ async fn main() -> Result<()> {
 
     // 6. WebSocket server
     println!("\n6. WebSocket Server");
+    // Websocket upgrades are detected per request; paths are passed through unchanged
     let ws_route = ProxyRoute::new(
         "localhost".to_string(),
-        "/ws".to_string(),
+        "".to_string(),
         5000,
         true, // WSS (WebSocket over SSL)
         None,
         true,
     );
     config.add_route("ws.example.com".to_string(), ws_route).await?;
-    println!("   ✓ ws.example.com -> localhost:5000/ws [WSS]");
+    println!("   ✓ ws.example.com -> localhost:5000 [WSS]");
 
     // 7. Load balancer backend (multiple services)
     println!("\n7. Microservices Backend");
@@ -175,7 +176,7 @@ async fn main() -> Result<()> {
     println!("  admin.internal:8888 → 10.0.0.10:3000/admin");
     println!();
     println!("WebSockets:");
-    println!("  wss://ws.example.com → localhost:5000/ws");
+    println!("  wss://ws.example.com/socket → ws://localhost:5000/socket (TLS ends at the proxy)");
     println!();
 
     Ok(())
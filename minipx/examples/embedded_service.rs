@@ -0,0 +1,80 @@
+//! Embedded Service Example
+//!
+//! This example demonstrates how to mount the minipx request handler inside your own
+//! hyper server as a `Service`, behind custom middleware, instead of running minipx's
+//! built-in HTTP/HTTPS servers.
+//!
+//! The middleware in this example:
+//! - stores the connection's peer address in the request extensions, where `MinipxService`
+//!   picks it up as the client IP
+//! - answers `/healthz` itself and rejects anything under `/internal`
+//! - tags every response with `X-Embedded-By` and logs how long it took
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Start a backend on port 3000, then:
+//! cargo run --example embedded_service
+//! curl -H "Host: app.localhost" http://127.0.0.1:8080/
+//! ```
+
+use anyhow::Result;
+use hyper::server::conn::AddrStream;
+use hyper::service::{Service, make_service_fn, service_fn};
+use hyper::{Body, Request, Response, StatusCode};
+use minipx::config::{Config, ProxyRoute};
+use minipx::proxy::{ConnectionClosed, MinipxService};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Routes built in code; nothing is written to disk
+    let mut config = Config::new("./embedded-proxy.json");
+    config.add_route("app.localhost".to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 3000, false, None, false)).await?;
+
+    // A fixed config; use `MinipxService::new("http")` alone to follow the global (hot-reloaded) config instead
+    let minipx = MinipxService::new("http").with_config(config);
+
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let peer = conn.remote_addr();
+        let minipx = minipx.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let mut minipx = minipx.clone();
+                async move { Ok::<_, Infallible>(middleware(&mut minipx, peer, req).await) }
+            }))
+        }
+    });
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    println!("Embedded proxy listening on http://{}", addr);
+    hyper::Server::bind(&addr).serve(make_svc).await?;
+    Ok(())
+}
+
+/// Custom middleware in front of minipx
+async fn middleware(minipx: &mut MinipxService, peer: SocketAddr, mut req: Request<Body>) -> Response<Body> {
+    let started = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+
+    let mut resp = if path == "/healthz" {
+        Response::new(Body::from("ok"))
+    } else if path.starts_with("/internal") {
+        Response::builder().status(StatusCode::FORBIDDEN).body(Body::from("Forbidden")).unwrap()
+    } else {
+        // The default client IP strategy reads the peer address from the extensions
+        req.extensions_mut().insert(peer);
+        match minipx.call(req).await {
+            Ok(resp) => resp,
+            // `unknown_host.action: "close"`; a real server would drop the connection instead of answering
+            Err(ConnectionClosed) => Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap(),
+        }
+    };
+
+    resp.headers_mut().insert("x-embedded-by", "minipx-example".parse().unwrap());
+    println!("{} {} {} -> {} in {:?}", peer, method, path, resp.status(), started.elapsed());
+    resp
+}
@@ -0,0 +1,160 @@
+//! Full Stack Demo
+//!
+//! This example runs real traffic through minipx, end to end, on ephemeral ports:
+//! - starts an HTTP echo backend and a websocket echo backend
+//! - builds an in-memory config with a subroute and a websocket route
+//! - serves it with `proxy::serve_listener` on a listener bound here
+//! - sends HTTP requests and websocket round trips through the proxy and prints what the backends saw
+//!
+//! With `--assert` every result is checked and the process exits with 1 on a mismatch, so the
+//! example doubles as a smoke test in CI.
+//!
+//! # Usage
+//!
+//! ```bash
+//! cargo run --example full_stack_demo
+//! cargo run --example full_stack_demo -- --assert
+//! ```
+
+use anyhow::{Result, anyhow};
+use futures_util::{SinkExt, StreamExt};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Request, Response};
+use minipx::config::{Config, ProxyRoute};
+use minipx::proxy::{MinipxService, serve_listener};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::server::{Request as WsRequest, Response as WsResponse};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let assert = std::env::args().any(|arg| arg == "--assert");
+
+    // 1. Backends
+    let http_backend = spawn_http_echo().await?;
+    let ws_backend = spawn_ws_echo().await?;
+    println!("HTTP echo backend on {}, websocket echo backend on {}", http_backend, ws_backend);
+
+    // 2. Routes: app.localhost with a /ws subroute on the websocket backend, and chat.localhost straight to it
+    let mut config = Config::new(std::env::temp_dir().join("minipx-full-stack-demo").join("minipx.json"));
+    config.add_route("app.localhost".to_string(), local_route(http_backend.port())).await?;
+    config.add_subroute("app.localhost", "/ws".to_string(), ws_backend.port()).await?;
+    config.add_route("chat.localhost".to_string(), local_route(ws_backend.port())).await?;
+
+    // 3. The proxy, on a listener we bound ourselves
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    let proxy = listener.local_addr()?;
+    tokio::spawn(serve_listener(listener, MinipxService::new("http").with_config(config)));
+    println!("Proxy on http://{}\n", proxy);
+
+    // 4. Traffic
+    let mut checks = Checks::default();
+    checks.record("HTTP route", "GET /hello?x=1", http_get(proxy, "app.localhost", "/hello?x=1").await);
+    // Subroutes match whole path segments, so /wsx stays on the route's own backend
+    checks.record("path sharing the subroute's prefix", "GET /wsx", http_get(proxy, "app.localhost", "/wsx").await);
+    checks.record("unknown host", "404", http_status(proxy, "nobody.localhost", "/").await);
+    checks.record("websocket route", "/echo | hello", ws_round_trip(proxy, "chat.localhost", "/echo", "hello").await);
+    checks.record("websocket subroute", "/chat | ping", ws_round_trip(proxy, "app.localhost", "/ws/chat", "ping").await);
+    checks.record("websocket subroute with query", "/chat?room=lobby | hi", ws_round_trip(proxy, "app.localhost", "/ws/chat?room=lobby", "hi").await);
+    checks.record("websocket subroute root", "/ | root", ws_round_trip(proxy, "app.localhost", "/ws", "root").await);
+    checks.record("websocket subroute root with query", "/?room=lobby | q", ws_round_trip(proxy, "app.localhost", "/ws?room=lobby", "q").await);
+
+    println!("\n{} of {} checks passed", checks.passed, checks.total);
+    if assert && checks.passed != checks.total {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn local_route(port: u16) -> ProxyRoute {
+    ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), port, false, None, false)
+}
+
+/// Answers every request with its method and path
+async fn spawn_http_echo() -> Result<SocketAddr> {
+    let make_svc = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let path = req.uri().path_and_query().map(|pq| pq.as_str().to_string()).unwrap_or_default();
+            Ok::<_, Infallible>(Response::new(Body::from(format!("{} {}", req.method(), path))))
+        }))
+    });
+    let server = hyper::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    Ok(addr)
+}
+
+/// Sends the handshake's path as its first message, then echoes every text message
+#[allow(clippy::result_large_err)]
+async fn spawn_ws_echo() -> Result<SocketAddr> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut path = String::new();
+                let record_path = |req: &WsRequest, resp: WsResponse| {
+                    path = req.uri().path_and_query().map(|pq| pq.as_str().to_string()).unwrap_or_default();
+                    Ok(resp)
+                };
+                let Ok(mut ws) = tokio_tungstenite::accept_hdr_async(stream, record_path).await else {
+                    return;
+                };
+                if ws.send(Message::text(path)).await.is_err() {
+                    return;
+                }
+                while let Some(Ok(msg)) = ws.next().await {
+                    if msg.is_text() && ws.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(addr)
+}
+
+async fn http_get(proxy: SocketAddr, host: &str, path: &str) -> Result<String> {
+    let resp = Client::new().request(Request::builder().uri(format!("http://{}{}", proxy, path)).header("Host", host).body(Body::empty())?).await?;
+    Ok(String::from_utf8(hyper::body::to_bytes(resp.into_body()).await?.to_vec())?)
+}
+
+async fn http_status(proxy: SocketAddr, host: &str, path: &str) -> Result<String> {
+    let resp = Client::new().request(Request::builder().uri(format!("http://{}{}", proxy, path)).header("Host", host).body(Body::empty())?).await?;
+    Ok(resp.status().as_u16().to_string())
+}
+
+/// Connect through the proxy, then report `<path the backend saw> | <echo of payload>`
+async fn ws_round_trip(proxy: SocketAddr, host: &str, path: &str, payload: &str) -> Result<String> {
+    let mut req = format!("ws://{}{}", proxy, path).into_client_request()?;
+    req.headers_mut().insert("Host", host.parse()?);
+    let (mut ws, _) = tokio_tungstenite::connect_async(req).await?;
+    let seen = ws.next().await.ok_or_else(|| anyhow!("backend closed the websocket"))??.into_text()?.to_string();
+    ws.send(Message::text(payload)).await?;
+    let echo = ws.next().await.ok_or_else(|| anyhow!("no echo"))??.into_text()?.to_string();
+    let _ = ws.close(None).await;
+    Ok(format!("{} | {}", seen, echo))
+}
+
+#[derive(Default)]
+struct Checks {
+    total: usize,
+    passed: usize,
+}
+
+impl Checks {
+    fn record(&mut self, name: &str, expected: &str, actual: Result<String>) {
+        self.total += 1;
+        match actual {
+            Ok(actual) if actual == expected => {
+                self.passed += 1;
+                println!("  ✓ {}: {}", name, actual);
+            }
+            Ok(actual) => println!("  ✗ {}: expected {:?}, got {:?}", name, expected, actual),
+            Err(e) => println!("  ✗ {}: expected {:?}, failed: {}", name, expected, e),
+        }
+    }
+}
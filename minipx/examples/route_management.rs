@@ -10,7 +10,7 @@
 //! ```
 
 use anyhow::Result;
-use minipx::config::{Config, ProxyRoute, RoutePatch};
+use minipx::config::{Config, ProxyPathRoute, ProxyRoute, RoutePatch};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -79,6 +79,15 @@ async fn main() -> Result<()> {
         ssl_enable: None,                  // Keep existing SSL setting
         redirect_to_https: Some(false),    // Disable redirect
         listen_port: None,                 // Keep existing listen port
+        allow_websocket: None,             // Keep existing websocket setting
+        preserve_host: None,               // Keep existing Host forwarding
+        match_apex: None,                  // Only meaningful for wildcard routes
+        upstream_host_header: None,        // Keep the Host sent to the backend
+        upstream_sni: None,                // Keep the backend TLS name
+        allow_self_reference: None,        // Keep the self-reference check as it is
+        active_from: None,                 // Keep the activation window
+        active_until: None,
+        unix_socket: None, // Keep proxying to host:port
     };
 
     config.update_route("api.example.com", patch).await?;
@@ -102,13 +111,18 @@ async fn main() -> Result<()> {
     // Demonstrate subroute structure
     if let Some(route) = config.lookup_host("web.example.com") {
         println!("\nSubroutes for web.example.com:");
-        // Note: subroutes field is private, this is for demonstration
-        // In real code, you'd typically just configure and let the proxy handle it
         println!("  Main: {}:{}", route.get_host(), route.get_port());
-        println!("  /api -> port 3000 (strips /api prefix)");
-        println!("  /static -> port 8081 (strips /static prefix)");
+        for subroute in route.get_subroutes() {
+            println!("  {} -> port {} (strips {} prefix)", subroute.get_path(), subroute.get_port(), subroute.get_path());
+        }
     }
 
+    // A route can also be built with its subroutes in one go
+    let docs = ProxyRoute::new("localhost".to_string(), String::new(), 4000, false, None, false)
+        .with_subroutes(vec![ProxyPathRoute::new("/search", 4001)?, ProxyPathRoute::new("/assets", 4002)?]);
+    config.add_route("docs.example.com".to_string(), docs).await?;
+    println!("✓ Added route with 2 subroutes: docs.example.com");
+
     println!("\n=== Removing a Route ===");
 
     config.remove_route("admin.example.com").await?;
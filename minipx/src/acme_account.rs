@@ -0,0 +1,300 @@
+//! ACME account keys in `cache_dir`.
+//!
+//! rustls-acme keeps one account key per (contact, directory) pair in the cache directory, in a file named
+//! `cached_account_<hash>` that holds the PKCS#8 (DER) P-256 key. A new `cache_dir`, or a new email, therefore
+//! silently registers a new account. This module finds the key the HTTPS server will use, fingerprints it, and
+//! exports/imports it so an account can move between machines.
+
+use anyhow::{Result, anyhow};
+use aws_lc_rs::digest::{SHA256, digest};
+use aws_lc_rs::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use rustls_acme::acme::{Account, Directory, LETS_ENCRYPT_PRODUCTION_DIRECTORY};
+use rustls_acme::futures_rustls::rustls::crypto::aws_lc_rs as rustls_aws_lc_rs;
+use rustls_acme::futures_rustls::rustls::{ClientConfig, RootCertStore};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// ACME directory the HTTPS server registers with
+pub const DIRECTORY_URL: &str = LETS_ENCRYPT_PRODUCTION_DIRECTORY;
+
+const ACCOUNT_FILE_PREFIX: &str = "cached_account_";
+
+/// The contact list the HTTPS server registers for `email`
+pub fn contact_for(email: &str) -> Vec<String> {
+    vec![format!("mailto:{}", email)]
+}
+
+/// Name of the cache file holding the account key for `contact` at `directory_url` (same scheme as rustls-acme's `DirCache`)
+pub fn account_file_name(contact: &[String], directory_url: &str) -> String {
+    let mut input = Vec::new();
+    for entry in contact {
+        input.extend_from_slice(entry.as_bytes());
+        input.push(0);
+    }
+    input.extend_from_slice(directory_url.as_bytes());
+    format!("{}{}", ACCOUNT_FILE_PREFIX, URL_SAFE_NO_PAD.encode(digest(&SHA256, &input)))
+}
+
+/// A parsed account key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountKey {
+    der: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+impl AccountKey {
+    /// Parse a PKCS#8 (DER) P-256 key as stored by rustls-acme
+    pub fn from_pkcs8(der: &[u8]) -> Result<Self> {
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der).map_err(|e| anyhow!("Not a P-256 PKCS#8 account key: {}", e))?;
+        Ok(Self { der: der.to_vec(), public_key: key_pair.public_key().as_ref().to_vec() })
+    }
+
+    pub fn as_der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// RFC 7638 JWK thumbprint of the public key (base64url); identifies the account to the ACME server
+    pub fn thumbprint(&self) -> String {
+        // Uncompressed point: 0x04 || x || y
+        let (x, y) = self.public_key[1..].split_at(32);
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, URL_SAFE_NO_PAD.encode(x), URL_SAFE_NO_PAD.encode(y));
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, jwk.as_bytes()))
+    }
+}
+
+/// The account key the HTTPS server uses for the configured email, plus any other account keys in the cache
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub directory: String,
+    pub contact: Vec<String>,
+    /// Cache file of the configured contact's key; None until the first registration
+    pub file: Option<PathBuf>,
+    pub thumbprint: Option<String>,
+    /// Set by `lookup_account_url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_url: Option<String>,
+    /// Account keys registered for other contacts (e.g. a previous email)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_accounts: Vec<PathBuf>,
+}
+
+impl AccountInfo {
+    /// Warning when the cache only has accounts for other contacts, i.e. the configured email does not
+    /// match the registered one and the next issuance registers a new account
+    pub fn contact_warning(&self) -> Option<String> {
+        if self.file.is_some() || self.other_accounts.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "cache_dir has {} ACME account(s) registered for a different contact than {}; a new account will be registered",
+            self.other_accounts.len(),
+            self.contact.join(", ")
+        ))
+    }
+}
+
+/// Portable form of an account key (`minipx acme account export`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountExport {
+    pub contact: Vec<String>,
+    pub directory: String,
+    pub thumbprint: String,
+    /// Base64 PKCS#8 (DER) private key
+    pub key: String,
+}
+
+impl AccountExport {
+    pub fn account_key(&self) -> Result<AccountKey> {
+        let der = STANDARD.decode(self.key.trim()).map_err(|e| anyhow!("Invalid account key encoding: {}", e))?;
+        let key = AccountKey::from_pkcs8(&der)?;
+        if key.thumbprint() != self.thumbprint {
+            return Err(anyhow!("Account key does not match its thumbprint {}", self.thumbprint));
+        }
+        Ok(key)
+    }
+}
+
+/// Result of `import`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Imported(PathBuf),
+    /// The cache already held this key
+    Unchanged(PathBuf),
+}
+
+/// Paths of all account keys in `cache_dir`, sorted
+pub fn cached_account_files(cache_dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(cache_dir.as_ref()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", cache_dir.as_ref().display(), e)),
+    };
+    let mut files: Vec<PathBuf> =
+        entries.filter_map(|e| e.ok()).filter(|e| e.file_name().to_string_lossy().starts_with(ACCOUNT_FILE_PREFIX)).map(|e| e.path()).collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Describe the account the HTTPS server uses for `email` (offline; see `lookup_account_url` for the URL)
+pub fn account_info(cache_dir: impl AsRef<Path>, email: &str) -> Result<AccountInfo> {
+    let cache_dir = cache_dir.as_ref();
+    let contact = contact_for(email);
+    let expected = cache_dir.join(account_file_name(&contact, DIRECTORY_URL));
+    let mut info =
+        AccountInfo { directory: DIRECTORY_URL.to_string(), contact, file: None, thumbprint: None, account_url: None, other_accounts: Vec::new() };
+    for file in cached_account_files(cache_dir)? {
+        if file == expected {
+            let key = AccountKey::from_pkcs8(&std::fs::read(&file)?).map_err(|e| anyhow!("{}: {}", file.display(), e))?;
+            info.thumbprint = Some(key.thumbprint());
+            info.file = Some(file);
+        } else {
+            info.other_accounts.push(file);
+        }
+    }
+    Ok(info)
+}
+
+/// Export the account key the HTTPS server uses for `email`
+pub fn export(cache_dir: impl AsRef<Path>, email: &str) -> Result<AccountExport> {
+    let contact = contact_for(email);
+    let file = cache_dir.as_ref().join(account_file_name(&contact, DIRECTORY_URL));
+    let der = match std::fs::read(&file) {
+        Ok(der) => der,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("No ACME account for {} in {} yet", contact.join(", "), cache_dir.as_ref().display()));
+        }
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", file.display(), e)),
+    };
+    let key = AccountKey::from_pkcs8(&der)?;
+    Ok(AccountExport { contact, directory: DIRECTORY_URL.to_string(), thumbprint: key.thumbprint(), key: STANDARD.encode(key.as_der()) })
+}
+
+/// Store an exported key as the account the HTTPS server uses for `email`. A different existing key is only
+/// replaced with `force`. The running HTTPS server keeps its loaded key until it restarts.
+pub fn import(cache_dir: impl AsRef<Path>, email: &str, account: &AccountExport, force: bool) -> Result<ImportOutcome> {
+    let key = account.account_key()?;
+    if account.directory != DIRECTORY_URL {
+        return Err(anyhow!("Account belongs to {}, but minipx uses {}", account.directory, DIRECTORY_URL));
+    }
+    let cache_dir = cache_dir.as_ref();
+    let file = cache_dir.join(account_file_name(&contact_for(email), DIRECTORY_URL));
+    match std::fs::read(&file) {
+        Ok(existing) if existing == key.as_der() => return Ok(ImportOutcome::Unchanged(file)),
+        Ok(existing) if !force => {
+            let thumbprint = AccountKey::from_pkcs8(&existing).map(|k| k.thumbprint()).unwrap_or_else(|_| "unreadable".to_string());
+            return Err(anyhow!("{} already holds a different account key ({}); use --force to replace it", file.display(), thumbprint));
+        }
+        _ => {}
+    }
+    std::fs::create_dir_all(cache_dir)?;
+    std::fs::write(&file, key.as_der())?;
+    Ok(ImportOutcome::Imported(file))
+}
+
+/// Ask the ACME directory for the account URL of `key`. ACME answers a registration with an existing key with
+/// that account, so this registers nothing for a key minipx has used before.
+pub async fn lookup_account_url(key: &AccountKey, contact: &[String]) -> Result<String> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let client_config = Arc::new(
+        ClientConfig::builder_with_provider(Arc::new(rustls_aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| anyhow!("Failed to build TLS config: {}", e))?
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    );
+    let directory = Directory::discover(&client_config, DIRECTORY_URL).await.map_err(|e| anyhow!("ACME directory: {}", e))?;
+    let account = Account::create_with_keypair(&client_config, directory, contact, key.as_der()).await.map_err(|e| anyhow!("ACME account: {}", e))?;
+    Ok(account.kid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minipx-acme-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn generate_key() -> Vec<u8> {
+        Account::generate_key_pair()
+    }
+
+    #[tokio::test]
+    async fn test_account_file_name_is_read_by_dir_cache() {
+        use rustls_acme::AccountCache;
+        use rustls_acme::caches::DirCache;
+        let dir = temp_dir("dircache");
+        let contact = contact_for("admin@example.com");
+        std::fs::write(dir.join(account_file_name(&contact, DIRECTORY_URL)), b"key").unwrap();
+        let cache = DirCache::new(dir.clone());
+        assert_eq!(cache.load_account(&contact, DIRECTORY_URL).await.unwrap(), Some(b"key".to_vec()));
+        assert_eq!(cache.load_account(&contact_for("other@example.com"), DIRECTORY_URL).await.unwrap(), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage_and_thumbprint_is_stable() {
+        assert!(AccountKey::from_pkcs8(b"not a key").is_err());
+        let der = generate_key();
+        let key = AccountKey::from_pkcs8(&der).unwrap();
+        assert_eq!(key.thumbprint(), AccountKey::from_pkcs8(&der).unwrap().thumbprint());
+        assert_eq!(key.thumbprint().len(), 43);
+        assert_ne!(key.thumbprint(), AccountKey::from_pkcs8(&generate_key()).unwrap().thumbprint());
+    }
+
+    #[test]
+    fn test_info_export_import_round_trip() {
+        let source = temp_dir("source");
+        let target = temp_dir("target");
+        assert!(export(&source, "admin@example.com").is_err());
+
+        let der = generate_key();
+        std::fs::write(source.join(account_file_name(&contact_for("admin@example.com"), DIRECTORY_URL)), &der).unwrap();
+        let info = account_info(&source, "admin@example.com").unwrap();
+        assert!(info.file.is_some());
+        assert_eq!(info.contact_warning(), None);
+
+        let exported = export(&source, "admin@example.com").unwrap();
+        assert_eq!(Some(exported.thumbprint.clone()), info.thumbprint);
+        let json = serde_json::to_string(&exported).unwrap();
+        let parsed: AccountExport = serde_json::from_str(&json).unwrap();
+
+        assert!(matches!(import(&target, "admin@example.com", &parsed, false).unwrap(), ImportOutcome::Imported(_)));
+        assert!(matches!(import(&target, "admin@example.com", &parsed, false).unwrap(), ImportOutcome::Unchanged(_)));
+        assert_eq!(account_info(&target, "admin@example.com").unwrap().thumbprint, info.thumbprint);
+
+        // A different key is only replaced with force
+        let other = AccountKey::from_pkcs8(&generate_key()).unwrap();
+        let other = AccountExport { key: STANDARD.encode(other.as_der()), thumbprint: other.thumbprint(), ..parsed.clone() };
+        assert!(import(&target, "admin@example.com", &other, false).unwrap_err().to_string().contains("--force"));
+        assert!(matches!(import(&target, "admin@example.com", &other, true).unwrap(), ImportOutcome::Imported(_)));
+        assert_eq!(account_info(&target, "admin@example.com").unwrap().thumbprint, Some(other.thumbprint.clone()));
+
+        // Tampered files are refused
+        let tampered = AccountExport { thumbprint: "x".to_string(), ..other };
+        assert!(import(&target, "admin@example.com", &tampered, true).is_err());
+
+        let _ = std::fs::remove_dir_all(&source);
+        let _ = std::fs::remove_dir_all(&target);
+    }
+
+    #[test]
+    fn test_contact_mismatch_is_reported() {
+        let dir = temp_dir("mismatch");
+        assert_eq!(account_info(&dir, "new@example.com").unwrap().contact_warning(), None);
+        std::fs::write(dir.join(account_file_name(&contact_for("old@example.com"), DIRECTORY_URL)), generate_key()).unwrap();
+        let info = account_info(&dir, "new@example.com").unwrap();
+        assert!(info.file.is_none());
+        assert_eq!(info.other_accounts.len(), 1);
+        assert!(info.contact_warning().unwrap().contains("mailto:new@example.com"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
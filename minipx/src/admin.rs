@@ -0,0 +1,354 @@
+//! Embedded admin REST API (feature `admin-api`).
+//!
+//! A small loopback-only HTTP surface for automation, much lighter than the web panel. Reads are answered by the
+//! same handler as the IPC socket; route edits and reloads are only served here, where the token guards them:
+//!
+//! - `GET /routes`, `POST /routes` (`{"domain": ..., "route": {...}}`)
+//! - `PUT /routes/{domain}` (a `RoutePatch`), `DELETE /routes/{domain}`
+//! - `GET /status`, `POST /reload`, `GET /metrics` (Prometheus text format)
+//!
+//! All requests need `Authorization: Bearer <admin_api.token>`.
+
+use crate::cert_resolver::fallback_stats;
+use crate::config::manager::config_lock;
+use crate::config::{Config, ProxyRoute, RoutePatch};
+use crate::ipc::{IpcRequest, IpcResponse, handle_request};
+use crate::proxy::forwarder::BindingState;
+use crate::proxy::limiter::RouteLimitStats;
+use crate::proxy::unknown_host::{rate_limited_requests, unknown_host_requests};
+use crate::proxy::upstream::stale_retries;
+use crate::systemd;
+use crate::utils::host::normalize_host;
+use anyhow::{Result, anyhow};
+use hyper::body::HttpBody;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode, header};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::Mutex;
+
+// Route definitions are small; anything bigger is a mistake or abuse
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+// Serializes edits so concurrent calls don't overwrite each other's changes to the config file
+static EDITS: OnceLock<Mutex<()>> = OnceLock::new();
+
+// Per-route metric: name, Prometheus type, and how to read it from the route's stats
+type RouteSeries = (&'static str, &'static str, fn(&RouteLimitStats) -> u64);
+
+// A route edit made through the API
+enum Edit {
+    Add(Box<AddRouteBody>),
+    Update(String, RoutePatch),
+    Remove(String),
+}
+
+// Why an edit or reload was not applied: a bad request, an unknown route, or the config file itself
+enum EditError {
+    Invalid(String),
+    NotFound(String),
+    Failed(String),
+}
+
+/// Body of `POST /routes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddRouteBody {
+    pub domain: String,
+    pub route: ProxyRoute,
+}
+
+/// Start the admin API if the config enables it (read at startup, like the health port)
+pub async fn start_admin_api(config: &Config) {
+    let admin = config.get_admin_api();
+    if !admin.is_enabled() {
+        return;
+    }
+    if admin.get_token().trim().is_empty() {
+        warn!("Admin API is enabled but has no token configured; not starting it");
+        return;
+    }
+    if let Err(e) = spawn_admin_api(admin.get_port(), admin.get_token().to_string()) {
+        error!("Failed to start admin API on 127.0.0.1:{}: {}", admin.get_port(), e);
+    }
+}
+
+/// Bind the admin API on `127.0.0.1:port` (0 for an ephemeral port) and serve it in the background
+pub fn spawn_admin_api(port: u16, token: String) -> Result<SocketAddr> {
+    let token: Arc<str> = token.into();
+    let make_svc = make_service_fn(move |_conn: &AddrStream| {
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let token = token.clone();
+                async move {
+                    match handle_admin_request(req, &token).await {
+                        Ok(resp) => Ok::<_, Infallible>(resp),
+                        Err(e) => {
+                            error!("Admin API error: {}", e);
+                            Ok::<_, Infallible>(json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))
+                        }
+                    }
+                }
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, port)))?.serve(make_svc);
+    let addr = server.local_addr();
+    info!("Admin API running on {}", addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Admin API server error: {}", e);
+        }
+    });
+    Ok(addr)
+}
+
+async fn handle_admin_request(req: Request<Body>, token: &str) -> Result<Response<Body>> {
+    if !is_authorized(&req, token) {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"error":"Unauthorized"}"#))?);
+    }
+
+    let config_path = config_lock().read().await.get_path().clone();
+    let method = req.method().clone();
+    let path = req.uri().path().trim_end_matches('/').to_string();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let (outcome, success) = match (&method, segments.as_slice()) {
+        (&Method::GET, ["routes"]) => return query(IpcRequest::ListRoutes, &config_path).await,
+        (&Method::GET, ["status"]) => return query(IpcRequest::Status, &config_path).await,
+        (&Method::GET, ["metrics"]) => return metrics_response(&config_path).await,
+        (&Method::POST, ["routes"]) => match read_json::<AddRouteBody>(req.into_body()).await {
+            Ok(body) => (edit(Edit::Add(Box::new(body))).await, StatusCode::CREATED),
+            Err(resp) => return Ok(resp),
+        },
+        (&Method::PUT, ["routes", domain]) => {
+            let domain = domain.to_string();
+            match read_json::<RoutePatch>(req.into_body()).await {
+                Ok(patch) => (edit(Edit::Update(domain, patch)).await, StatusCode::OK),
+                Err(resp) => return Ok(resp),
+            }
+        }
+        (&Method::DELETE, ["routes", domain]) => (edit(Edit::Remove(domain.to_string())).await, StatusCode::NO_CONTENT),
+        (&Method::POST, ["reload"]) => (reload(&config_path).await, StatusCode::OK),
+        (_, ["routes"]) | (_, ["routes", _]) | (_, ["status"]) | (_, ["reload"]) | (_, ["metrics"]) => {
+            return Ok(json_error(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"));
+        }
+        _ => return Ok(json_error(StatusCode::NOT_FOUND, "Not Found")),
+    };
+
+    Ok(match outcome {
+        Ok(()) => {
+            info!("Admin API: {} {} applied", method, path);
+            if success == StatusCode::NO_CONTENT {
+                Response::builder().status(success).body(Body::empty())?
+            } else {
+                json_response(success, &serde_json::json!({ "status": "ok" }))?
+            }
+        }
+        Err(EditError::NotFound(domain)) => json_error(StatusCode::NOT_FOUND, &format!("Route not found: {}", domain)),
+        Err(EditError::Invalid(message)) => json_error(StatusCode::BAD_REQUEST, &message),
+        Err(EditError::Failed(message)) => json_error(StatusCode::INTERNAL_SERVER_ERROR, &message),
+    })
+}
+
+// Read-only calls are answered exactly like the IPC socket
+async fn query(request: IpcRequest, config_path: &Path) -> Result<Response<Body>> {
+    match handle_request(request, config_path).await {
+        IpcResponse::Routes { routes } => json_response(StatusCode::OK, &routes),
+        IpcResponse::Status { report, .. } => json_response(StatusCode::OK, &report),
+        other => Err(anyhow!("Unexpected response: {:?}", other)),
+    }
+}
+
+// Edit the running config, save it and apply it, one edit at a time
+async fn edit(edit: Edit) -> std::result::Result<(), EditError> {
+    let _guard = edits().lock().await;
+    let mut config = Config::get().await;
+    let result = match edit {
+        Edit::Add(body) => {
+            let AddRouteBody { domain, route } = *body;
+            config.add_route(domain, route).await
+        }
+        Edit::Update(domain, patch) => {
+            if config.get_routes().get(normalize_host(&domain).as_ref()).is_none() {
+                return Err(EditError::NotFound(domain));
+            }
+            config.update_route(&domain, patch).await
+        }
+        Edit::Remove(domain) => {
+            if config.get_routes().get(normalize_host(&domain).as_ref()).is_none() {
+                return Err(EditError::NotFound(domain));
+            }
+            config.remove_route(&domain).await
+        }
+    };
+    result.map_err(|e| EditError::Invalid(e.to_string()))?;
+    // Applied from memory; the file watcher, if on, skips its reload of this write
+    systemd::reloading();
+    let result = config.save_and_apply().await.map_err(|e| EditError::Failed(format!("Failed to save config: {}", e)));
+    systemd::reloaded();
+    result.map(|_| ())
+}
+
+async fn reload(config_path: &Path) -> std::result::Result<(), EditError> {
+    let _guard = edits().lock().await;
+    systemd::reloading();
+    let result = Config::reload(config_path).await.map_err(|e| EditError::Failed(format!("Failed to reload config: {}", e)));
+    systemd::reloaded();
+    result.map(|_| ())
+}
+
+fn edits() -> &'static Mutex<()> {
+    EDITS.get_or_init(|| Mutex::new(()))
+}
+
+// Compare without short-circuiting so the token can't be guessed byte by byte from response times
+fn is_authorized(req: &Request<Body>, token: &str) -> bool {
+    let Some(presented) = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    let (presented, token) = (presented.trim().as_bytes(), token.as_bytes());
+    presented.len() == token.len() && presented.iter().zip(token).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn read_json<T: DeserializeOwned>(mut body: Body) -> std::result::Result<T, Response<Body>> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| json_error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(json_error(StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large"));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&bytes).map_err(|e| json_error(StatusCode::BAD_REQUEST, &format!("Invalid JSON body: {}", e)))
+}
+
+async fn metrics_response(config_path: &Path) -> Result<Response<Body>> {
+    let IpcResponse::Status { report, tls: certs, forwarders, .. } = handle_request(IpcRequest::Status, config_path).await else {
+        return Err(anyhow!("Status unavailable"));
+    };
+    let IpcResponse::RouteStats { routes, malformed, websockets, transfer } =
+        handle_request(IpcRequest::RouteStats { reset: false }, config_path).await
+    else {
+        return Err(anyhow!("Route stats unavailable"));
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "# TYPE minipx_uptime_seconds gauge\nminipx_uptime_seconds {}", report.uptime_secs);
+    let _ = writeln!(out, "# TYPE minipx_routes gauge\nminipx_routes {}", report.routes);
+    let _ = writeln!(out, "# TYPE minipx_listeners gauge\nminipx_listeners {}", report.listeners.len());
+    let _ = writeln!(out, "# TYPE minipx_unknown_host_requests_total counter\nminipx_unknown_host_requests_total {}", unknown_host_requests());
+    let _ =
+        writeln!(out, "# TYPE minipx_unknown_host_rate_limited_total counter\nminipx_unknown_host_rate_limited_total {}", rate_limited_requests());
+    let _ = writeln!(out, "# TYPE minipx_config_reload_failed gauge\nminipx_config_reload_failed {}", u8::from(report.config_reload_failed));
+    let tls = fallback_stats();
+    let _ = writeln!(out, "# TYPE minipx_tls_fallback_handshakes_total counter");
+    let _ = writeln!(out, "minipx_tls_fallback_handshakes_total{{reason=\"no_sni\"}} {}", tls.no_sni);
+    let _ = writeln!(out, "minipx_tls_fallback_handshakes_total{{reason=\"unknown_sni\"}} {}", tls.unknown_sni);
+    let _ = writeln!(out, "# TYPE minipx_tls_fallback_rejected_total counter\nminipx_tls_fallback_rejected_total {}", tls.rejected);
+    let _ = writeln!(out, "# TYPE minipx_tls_handshake_errors_total counter\nminipx_tls_handshake_errors_total {}", certs.handshake_errors);
+    let _ = writeln!(out, "# TYPE minipx_tls_supervisor_state gauge\nminipx_tls_supervisor_state{{state=\"{}\"}} 1", certs.state.as_str());
+    let _ = writeln!(out, "# TYPE minipx_acme_certificate_expiry_timestamp_seconds gauge");
+    for cert in &certs.certificates {
+        if let Some(not_after) = cert.not_after {
+            let _ = writeln!(out, "minipx_acme_certificate_expiry_timestamp_seconds{{domain=\"{}\"}} {}", label(&cert.domain), not_after);
+        }
+    }
+    let _ = writeln!(out, "# TYPE minipx_acme_certificate_days_to_expiry gauge");
+    for cert in &certs.certificates {
+        if let Some(days) = cert.days_to_expiry {
+            let _ = writeln!(out, "minipx_acme_certificate_days_to_expiry{{domain=\"{}\"}} {}", label(&cert.domain), days);
+        }
+    }
+    let _ = writeln!(out, "# TYPE minipx_acme_renewals_total counter");
+    for cert in &certs.certificates {
+        let _ = writeln!(out, "minipx_acme_renewals_total{{domain=\"{}\",result=\"success\"}} {}", label(&cert.domain), cert.renewals_succeeded);
+        let _ = writeln!(out, "minipx_acme_renewals_total{{domain=\"{}\",result=\"failure\"}} {}", label(&cert.domain), cert.renewals_failed);
+    }
+    let _ = writeln!(out, "# TYPE minipx_frontend_bind_failures gauge");
+    for frontend in &report.unbound_frontends {
+        let _ = writeln!(out, "minipx_frontend_bind_failures{{listener=\"{}\",port=\"{}\"}} {}", frontend.kind, frontend.port, frontend.attempts);
+    }
+    let _ = writeln!(out, "# TYPE minipx_forwarder_bound gauge");
+    for forwarder in &forwarders {
+        let bound = u8::from(forwarder.state == BindingState::Bound);
+        let _ = writeln!(out, "minipx_forwarder_bound{{protocol=\"{}\",port=\"{}\"}} {}", forwarder.protocol, forwarder.listen_port, bound);
+    }
+    let _ = writeln!(out, "# TYPE minipx_upstream_stale_retries_total counter");
+    for (upstream, retries) in stale_retries() {
+        let _ = writeln!(out, "minipx_upstream_stale_retries_total{{upstream=\"{}\"}} {}", label(&upstream), retries);
+    }
+    let series: [RouteSeries; 4] = [
+        ("minipx_route_concurrency_limit", "gauge", |s| s.limit as u64),
+        ("minipx_route_active_requests", "gauge", |s| s.active as u64),
+        ("minipx_route_queued_requests", "gauge", |s| s.queued as u64),
+        ("minipx_route_rejected_requests_total", "counter", |s| s.rejected),
+    ];
+    for (name, kind, value) in series {
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for stats in &routes {
+            let _ = writeln!(out, "{}{{domain=\"{}\"}} {}", name, label(&stats.domain), value(stats));
+        }
+    }
+    let _ = writeln!(out, "# TYPE minipx_route_malformed_requests_total counter");
+    for (domain, count) in &malformed {
+        let _ = writeln!(out, "minipx_route_malformed_requests_total{{domain=\"{}\"}} {}", label(domain), count);
+    }
+    let _ = writeln!(out, "# TYPE minipx_websocket_active gauge");
+    for (domain, stats) in &websockets {
+        let _ = writeln!(out, "minipx_websocket_active{{domain=\"{}\"}} {}", label(domain), stats.active);
+    }
+    let _ = writeln!(out, "# TYPE minipx_websocket_sessions_total counter");
+    for (domain, stats) in &websockets {
+        for (reason, count) in [("eof", stats.closed_eof), ("io_error", stats.closed_error), ("idle_timeout", stats.closed_idle)] {
+            let _ = writeln!(out, "minipx_websocket_sessions_total{{domain=\"{}\",reason=\"{}\"}} {}", label(domain), reason, count);
+        }
+    }
+    let _ = writeln!(out, "# TYPE minipx_websocket_bytes_total counter");
+    for (domain, stats) in &websockets {
+        let _ = writeln!(out, "minipx_websocket_bytes_total{{domain=\"{}\",direction=\"to_upstream\"}} {}", label(domain), stats.bytes_to_upstream);
+        let _ = writeln!(out, "minipx_websocket_bytes_total{{domain=\"{}\",direction=\"to_client\"}} {}", label(domain), stats.bytes_to_client);
+    }
+    let _ = writeln!(out, "# TYPE minipx_route_requests_total counter");
+    for (domain, stats) in &transfer.routes {
+        let _ = writeln!(out, "minipx_route_requests_total{{domain=\"{}\"}} {}", label(domain), stats.requests);
+    }
+    let _ = writeln!(out, "# TYPE minipx_route_bytes_total counter");
+    for (domain, stats) in &transfer.routes {
+        let _ = writeln!(out, "minipx_route_bytes_total{{domain=\"{}\",direction=\"request\"}} {}", label(domain), stats.request_bytes);
+        let _ = writeln!(out, "minipx_route_bytes_total{{domain=\"{}\",direction=\"response\"}} {}", label(domain), stats.response_bytes);
+    }
+    // Clients drop out of the top list, so these are gauges
+    let _ = writeln!(out, "# TYPE minipx_top_client_bytes gauge");
+    for client in &transfer.top_clients {
+        let _ = writeln!(out, "minipx_top_client_bytes{{client=\"{}\"}} {}", client.ip, client.bytes);
+    }
+
+    Ok(Response::builder().status(StatusCode::OK).header(header::CONTENT_TYPE, "text/plain; version=0.0.4").body(Body::from(out))?)
+}
+
+// Escape a Prometheus label value
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Result<Response<Body>> {
+    Ok(Response::builder().status(status).header(header::CONTENT_TYPE, "application/json").body(Body::from(serde_json::to_vec(value)?))?)
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::json!({ "error": message }).to_string();
+    Response::builder().status(status).header(header::CONTENT_TYPE, "application/json").body(Body::from(body)).unwrap()
+}
@@ -0,0 +1,560 @@
+//! Dynamic certificate resolver for the HTTPS server.
+//!
+//! Every SSL domain gets its own ACME state, driven by a background task that issues, caches and renews
+//! its certificate. The resolver picks the state by SNI, so domains can be added or removed while port 443
+//! keeps serving: a new domain starts answering as soon as its certificate is deployed, and connections to
+//! other domains are never touched. Handshakes with a missing or unknown SNI follow `tls_fallback`.
+//! A domain without a certificate of its own serves the shared one issued before per-domain states, until it is renewed.
+//! Deployed certificates and order outcomes are reported to the `tls_events` monitor.
+
+use crate::config::{TlsCertFiles, TlsFallback};
+use crate::tls_events::{self, not_after_from_pem};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use rustls_acme::caches::DirCache;
+use rustls_acme::futures_rustls::pki_types::pem::PemObject;
+use rustls_acme::futures_rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls_acme::futures_rustls::rustls::crypto::aws_lc_rs;
+use rustls_acme::futures_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use rustls_acme::futures_rustls::rustls::sign::CertifiedKey;
+use rustls_acme::{AccountCache, AcmeConfig, AcmeState, CertCache, EventError, EventOk, OrderError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use tokio::task::AbortHandle;
+use tokio_stream::StreamExt;
+use x509_parser::extensions::GeneralName;
+
+// Fallback handshakes since startup (kept across HTTPS restarts)
+static NO_SNI: AtomicU64 = AtomicU64::new(0);
+static UNKNOWN_SNI: AtomicU64 = AtomicU64::new(0);
+static REJECTED: AtomicU64 = AtomicU64::new(0);
+// Self-signed certificate for `default_cert` without configured files, generated once
+static PLACEHOLDER: OnceLock<Arc<CertifiedKey>> = OnceLock::new();
+
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    certs: RwLock<HashMap<String, DomainCert>>,
+    fallback: RwLock<Fallback>,
+}
+
+#[derive(Debug)]
+struct DomainCert {
+    resolver: Arc<dyn ResolvesServerCert>,
+    issuer: AbortHandle,
+}
+
+#[derive(Debug, Clone, Default)]
+enum Fallback {
+    #[default]
+    Reject,
+    Cert(Arc<CertifiedKey>),
+    Route(String),
+}
+
+/// How a handshake with a missing or unknown SNI is answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackAction {
+    Reject,
+    DefaultCert,
+    Route(String),
+}
+
+/// Counters of handshakes that did not name a served domain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsFallbackStats {
+    pub no_sni: u64,
+    pub unknown_sni: u64,
+    pub rejected: u64,
+}
+
+/// Fallback handshakes counted since startup
+pub fn fallback_stats() -> TlsFallbackStats {
+    TlsFallbackStats {
+        no_sni: NO_SNI.load(Ordering::Relaxed),
+        unknown_sni: UNKNOWN_SNI.load(Ordering::Relaxed),
+        rejected: REJECTED.load(Ordering::Relaxed),
+    }
+}
+
+impl Drop for DomainCert {
+    fn drop(&mut self) {
+        self.issuer.abort();
+    }
+}
+
+impl CertResolver {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Make the served set exactly `domains`: start issuance for new ones and drop removed ones.
+    /// Returns the (added, removed) domains.
+    pub fn sync(&self, domains: &[String], email: &str, cache_dir: &str) -> (Vec<String>, Vec<String>) {
+        self.sync_with(domains, |domain| {
+            let state = AcmeConfig::new([domain])
+                .contact_push(format!("mailto:{}", email))
+                .cache(ObservedCache { inner: DirCache::new(cache_dir.to_string()), dir: cache_dir.to_string(), domain: domain.to_string() })
+                .directory_lets_encrypt(true)
+                .state();
+            DomainCert { resolver: state.resolver(), issuer: tokio::spawn(drive_issuance(domain.to_string(), state)).abort_handle() }
+        })
+    }
+
+    fn sync_with(&self, domains: &[String], mut start: impl FnMut(&str) -> DomainCert) -> (Vec<String>, Vec<String>) {
+        let mut certs = self.certs.write().unwrap();
+        let removed: Vec<String> = certs.keys().filter(|d| !domains.contains(d)).cloned().collect();
+        for domain in &removed {
+            certs.remove(domain);
+        }
+        let mut added = Vec::new();
+        for domain in domains {
+            if !certs.contains_key(domain) {
+                certs.insert(domain.clone(), start(domain));
+                added.push(domain.clone());
+            }
+        }
+        (added, removed)
+    }
+
+    /// Domains currently served, sorted
+    pub fn domains(&self) -> Vec<String> {
+        let mut domains: Vec<String> = self.certs.read().unwrap().keys().cloned().collect();
+        domains.sort();
+        domains
+    }
+
+    /// Apply the `tls_fallback` setting. A default certificate that fails to load is replaced by the placeholder.
+    pub fn set_fallback(&self, fallback: &TlsFallback, cert_files: Option<&TlsCertFiles>) {
+        let fallback = match fallback {
+            TlsFallback::Reject => Fallback::Reject,
+            TlsFallback::Route(domain) => Fallback::Route(domain.clone()),
+            TlsFallback::DefaultCert => match cert_files.map(load_cert_files) {
+                Some(Ok(cert)) => Fallback::Cert(cert),
+                Some(Err(e)) => {
+                    warn!("Failed to load tls_fallback_cert, serving a self-signed placeholder instead: {}", e);
+                    placeholder_cert().map(Fallback::Cert).unwrap_or_default()
+                }
+                None => placeholder_cert().map(Fallback::Cert).unwrap_or_default(),
+            },
+        };
+        *self.fallback.write().unwrap() = fallback;
+    }
+
+    /// Count and classify a handshake. Returns None when the SNI names a served domain.
+    pub fn unmatched(&self, server_name: Option<&str>) -> Option<FallbackAction> {
+        if self.lookup(server_name).is_some() {
+            return None;
+        }
+        let counter = if server_name.is_some() { &UNKNOWN_SNI } else { &NO_SNI };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let action = match &*self.fallback.read().unwrap() {
+            Fallback::Reject => FallbackAction::Reject,
+            Fallback::Cert(_) => FallbackAction::DefaultCert,
+            Fallback::Route(domain) if self.certs.read().unwrap().contains_key(domain) => FallbackAction::Route(domain.clone()),
+            // The fallback route has no certificate (not an SSL route), so there is nothing to serve
+            Fallback::Route(_) => FallbackAction::Reject,
+        };
+        if action == FallbackAction::Reject {
+            REJECTED.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(action)
+    }
+
+    fn lookup(&self, server_name: Option<&str>) -> Option<Arc<dyn ResolvesServerCert>> {
+        let name = server_name?.trim_end_matches('.').to_ascii_lowercase();
+        self.certs.read().unwrap().get(&name).map(|cert| cert.resolver.clone())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        // Also answers TLS-ALPN-01 validation for the domain
+        if let Some(resolver) = self.lookup(client_hello.server_name()) {
+            return resolver.resolve(client_hello);
+        }
+        let fallback = self.fallback.read().unwrap().clone();
+        match fallback {
+            Fallback::Reject => {
+                debug!("No certificate for SNI {:?}", client_hello.server_name());
+                None
+            }
+            Fallback::Cert(cert) => Some(cert),
+            Fallback::Route(domain) => self.lookup(Some(&domain))?.resolve(client_hello),
+        }
+    }
+}
+
+// The ACME cache sees every certificate the state deploys (loaded at startup, stored after an order),
+// which is where their expiry is read
+struct ObservedCache {
+    inner: DirCache<String>,
+    dir: String,
+    domain: String,
+}
+
+#[async_trait]
+impl CertCache for ObservedCache {
+    type EC = std::io::Error;
+
+    async fn load_cert(&self, domains: &[String], directory_url: &str) -> std::result::Result<Option<Vec<u8>>, Self::EC> {
+        let mut pem = self.inner.load_cert(domains, directory_url).await?;
+        if pem.is_none() {
+            pem = shared_cert(Path::new(&self.dir), &self.domain, tls_events::now_secs()).await;
+            if pem.is_some() {
+                info!("ACME {}: serving the cached multi-domain certificate until its renewal", self.domain);
+            }
+        }
+        if let Some(not_after) = pem.as_deref().and_then(not_after_from_pem) {
+            tls_events::monitor().certificate_deployed(&self.domain, not_after, false, tls_events::now_secs());
+        }
+        Ok(pem)
+    }
+
+    async fn store_cert(&self, domains: &[String], directory_url: &str, cert: &[u8]) -> std::result::Result<(), Self::EC> {
+        if let Some(not_after) = not_after_from_pem(cert) {
+            tls_events::monitor().certificate_deployed(&self.domain, not_after, true, tls_events::now_secs());
+        }
+        self.inner.store_cert(domains, directory_url, cert).await
+    }
+}
+
+#[async_trait]
+impl AccountCache for ObservedCache {
+    type EA = std::io::Error;
+
+    async fn load_account(&self, contact: &[String], directory_url: &str) -> std::result::Result<Option<Vec<u8>>, Self::EA> {
+        self.inner.load_account(contact, directory_url).await
+    }
+
+    async fn store_account(&self, contact: &[String], directory_url: &str, account: &[u8]) -> std::result::Result<(), Self::EA> {
+        self.inner.store_account(contact, directory_url, account).await
+    }
+}
+
+// Before each domain had its own ACME state, all SSL domains shared one certificate. A domain without a
+// certificate of its own keeps serving the newest unexpired shared one that names it, and orders its own when
+// that one is due for renewal, so upgrading does not place an order for every domain at once.
+async fn shared_cert(dir: &Path, domain: &str, now: u64) -> Option<Vec<u8>> {
+    let mut entries = tokio::fs::read_dir(dir).await.ok()?;
+    let mut newest: Option<(u64, Vec<u8>)> = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry.file_name().to_string_lossy().starts_with("cached_cert_") {
+            continue;
+        }
+        let Ok(pem) = tokio::fs::read(entry.path()).await else {
+            continue;
+        };
+        let Some(not_after) = not_after_from_pem(&pem) else {
+            continue;
+        };
+        if not_after > now && newest.as_ref().is_none_or(|(newest, _)| not_after > *newest) && names_domain(&pem, domain) {
+            newest = Some((not_after, pem));
+        }
+    }
+    newest.map(|(_, pem)| pem)
+}
+
+// Whether the first certificate in `pem` lists `domain` among its subject alternative names
+fn names_domain(pem: &[u8], domain: &str) -> bool {
+    let Some(der) = CertificateDer::pem_slice_iter(pem).find_map(|cert| cert.ok()) else {
+        return false;
+    };
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(&der) else {
+        return false;
+    };
+    let Ok(Some(names)) = cert.subject_alternative_name() else {
+        return false;
+    };
+    names.value.general_names.iter().any(|name| matches!(name, GeneralName::DNSName(name) if name.eq_ignore_ascii_case(domain)))
+}
+
+// Poll the ACME state for as long as the domain is served; it sleeps between renewals
+async fn drive_issuance<EC: Debug + 'static, EA: Debug + 'static>(domain: String, mut state: AcmeState<EC, EA>) {
+    let monitor = tls_events::monitor();
+    while let Some(event) = state.next().await {
+        match event {
+            Ok(ok) => {
+                info!("ACME {}: {:?}", domain, ok);
+                if matches!(ok, EventOk::DeployedNewCert) {
+                    monitor.renewal_succeeded(&domain);
+                }
+            }
+            Err(err) => {
+                error!("ACME {}: {:?}", domain, err);
+                match &err {
+                    EventError::Order(_) | EventError::NewCertParse(_) => monitor.renewal_failed(&domain, error_class(&err)),
+                    _ => monitor.record_error(&domain, error_class(&err)),
+                }
+            }
+        }
+    }
+}
+
+// Stable, low-cardinality name of an ACME error for metrics and alerts
+fn error_class<EC: Debug, EA: Debug>(err: &EventError<EC, EA>) -> &'static str {
+    match err {
+        EventError::CertCacheLoad(_) => "cert_cache_load",
+        EventError::AccountCacheLoad(_) => "account_cache_load",
+        EventError::CertCacheStore(_) => "cert_cache_store",
+        EventError::AccountCacheStore(_) => "account_cache_store",
+        EventError::CachedCertParse(_) => "cached_cert_parse",
+        EventError::NewCertParse(_) => "new_cert_parse",
+        EventError::Order(OrderError::Acme(_)) => "order:acme",
+        EventError::Order(OrderError::Rcgen(_)) => "order:csr",
+        EventError::Order(OrderError::BadOrder(_)) => "order:bad_order",
+        EventError::Order(OrderError::BadAuth(_)) => "order:bad_auth",
+        EventError::Order(OrderError::TooManyAttemptsAuth(_)) => "order:too_many_auth_attempts",
+        EventError::Order(OrderError::ProcessingTimeout(_)) => "order:processing_timeout",
+    }
+}
+
+fn load_cert_files(files: &TlsCertFiles) -> Result<Arc<CertifiedKey>> {
+    let chain = CertificateDer::pem_slice_iter(&std::fs::read(&files.cert)?)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Invalid certificate {}: {:?}", files.cert, e))?;
+    if chain.is_empty() {
+        return Err(anyhow!("No certificate found in {}", files.cert));
+    }
+    let key = PrivateKeyDer::from_pem_slice(&std::fs::read(&files.key)?).map_err(|e| anyhow!("Invalid private key {}: {:?}", files.key, e))?;
+    let key = aws_lc_rs::sign::any_supported_type(&key).map_err(|e| anyhow!("Unsupported private key {}: {}", files.key, e))?;
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+fn placeholder_cert() -> Option<Arc<CertifiedKey>> {
+    if let Some(cert) = PLACEHOLDER.get() {
+        return Some(cert.clone());
+    }
+    let generated = rcgen::generate_simple_self_signed(vec!["minipx.invalid".to_string()]).map_err(|e| anyhow!(e)).and_then(|generated| {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der()));
+        let key = aws_lc_rs::sign::any_supported_type(&key).map_err(|e| anyhow!(e))?;
+        Ok(Arc::new(CertifiedKey::new(vec![generated.cert.der().clone()], key)))
+    });
+    match generated {
+        Ok(cert) => Some(PLACEHOLDER.get_or_init(|| cert).clone()),
+        Err(e) => {
+            error!("Failed to generate the placeholder TLS certificate, rejecting instead: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls_acme::futures_rustls::pki_types::{ServerName, UnixTime};
+    use rustls_acme::futures_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls_acme::futures_rustls::rustls::{
+        ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig, ServerConnection, SignatureScheme,
+    };
+
+    #[derive(Debug)]
+    struct FixedCert(Arc<CertifiedKey>);
+
+    impl ResolvesServerCert for FixedCert {
+        fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+            Some(self.0.clone())
+        }
+    }
+
+    // The tests check which certificate is served, not whether it is trusted
+    #[derive(Debug)]
+    struct AcceptAnyCert;
+
+    impl ServerCertVerifier for AcceptAnyCert {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> std::result::Result<ServerCertVerified, rustls_acme::futures_rustls::rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> std::result::Result<HandshakeSignatureValid, rustls_acme::futures_rustls::rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> std::result::Result<HandshakeSignatureValid, rustls_acme::futures_rustls::rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            aws_lc_rs::default_provider().signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    fn self_signed(name: &str) -> Arc<CertifiedKey> {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der()));
+        Arc::new(CertifiedKey::new(vec![generated.cert.der().clone()], aws_lc_rs::sign::any_supported_type(&key).unwrap()))
+    }
+
+    fn fixed_cert(cert: Arc<CertifiedKey>) -> DomainCert {
+        DomainCert { resolver: Arc::new(FixedCert(cert)), issuer: tokio::spawn(std::future::pending::<()>()).abort_handle() }
+    }
+
+    fn idle_cert(domain: &str) -> DomainCert {
+        let state = AcmeConfig::new([domain]).state();
+        DomainCert { resolver: state.resolver(), issuer: tokio::spawn(std::future::pending::<()>()).abort_handle() }
+    }
+
+    fn domains(names: &[&str]) -> Vec<String> {
+        names.iter().map(|d| d.to_string()).collect()
+    }
+
+    // In-memory handshake with a rustls client; an IP address as server name means no SNI is sent.
+    // Returns the certificate the server presented.
+    fn handshake(resolver: Arc<CertResolver>, server_name: &str) -> std::result::Result<CertificateDer<'static>, String> {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let server_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+        let client_config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+            .with_no_client_auth();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        let mut client = ClientConnection::new(Arc::new(client_config), ServerName::try_from(server_name.to_string()).unwrap()).unwrap();
+
+        for _ in 0..10 {
+            let mut buf = Vec::new();
+            while client.wants_write() {
+                client.write_tls(&mut buf).unwrap();
+            }
+            server.read_tls(&mut buf.as_slice()).unwrap();
+            server.process_new_packets().map_err(|e| e.to_string())?;
+            let mut buf = Vec::new();
+            while server.wants_write() {
+                server.write_tls(&mut buf).unwrap();
+            }
+            client.read_tls(&mut buf.as_slice()).unwrap();
+            client.process_new_packets().map_err(|e| e.to_string())?;
+            if !client.is_handshaking() && !server.is_handshaking() {
+                return Ok(client.peer_certificates().unwrap()[0].clone());
+            }
+        }
+        Err("handshake did not complete".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_sync_only_starts_new_domains_and_drops_removed() {
+        let resolver = CertResolver::default();
+        let (added, removed) = resolver.sync_with(&domains(&["a.example.com", "b.example.com"]), idle_cert);
+        assert_eq!((added.len(), removed.len()), (2, 0));
+        let a_before = resolver.lookup(Some("a.example.com")).unwrap();
+
+        let (added, removed) = resolver.sync_with(&domains(&["a.example.com", "c.example.com"]), idle_cert);
+        assert_eq!(added, domains(&["c.example.com"]));
+        assert_eq!(removed, domains(&["b.example.com"]));
+        assert_eq!(resolver.domains(), domains(&["a.example.com", "c.example.com"]));
+        // Unchanged domains keep their state (and certificate)
+        assert!(Arc::ptr_eq(&a_before, &resolver.lookup(Some("a.example.com")).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_removed_domain_stops_issuance() {
+        let resolver = CertResolver::default();
+        let handle = tokio::spawn(std::future::pending::<()>());
+        let issuer = handle.abort_handle();
+        resolver.sync_with(&domains(&["a.example.com"]), |d| DomainCert { resolver: idle_cert(d).resolver.clone(), issuer: issuer.clone() });
+        resolver.sync_with(&[], idle_cert);
+        assert!(handle.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_normalizes_sni() {
+        let resolver = CertResolver::default();
+        resolver.sync_with(&domains(&["a.example.com"]), idle_cert);
+        assert!(resolver.lookup(Some("A.Example.COM.")).is_some());
+        assert!(resolver.lookup(Some("b.example.com")).is_none());
+        assert!(resolver.lookup(None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reject_fallback_fails_handshakes_without_a_matching_sni() {
+        let resolver = CertResolver::new();
+        let cert = self_signed("a.example.com");
+        resolver.sync_with(&domains(&["a.example.com"]), |_| fixed_cert(cert.clone()));
+
+        assert_eq!(handshake(resolver.clone(), "a.example.com").unwrap(), cert.cert[0]);
+        assert!(handshake(resolver.clone(), "127.0.0.1").is_err());
+        assert!(handshake(resolver.clone(), "other.example.com").is_err());
+
+        let before = fallback_stats();
+        assert_eq!(resolver.unmatched(Some("a.example.com")), None);
+        assert_eq!(resolver.unmatched(None), Some(FallbackAction::Reject));
+        assert_eq!(resolver.unmatched(Some("other.example.com")), Some(FallbackAction::Reject));
+        let after = fallback_stats();
+        assert!(after.no_sni > before.no_sni);
+        assert!(after.unknown_sni > before.unknown_sni);
+        assert!(after.rejected >= before.rejected + 2);
+    }
+
+    #[tokio::test]
+    async fn test_default_cert_fallback_serves_placeholder() {
+        let resolver = CertResolver::new();
+        let cert = self_signed("a.example.com");
+        resolver.sync_with(&domains(&["a.example.com"]), |_| fixed_cert(cert.clone()));
+        resolver.set_fallback(&TlsFallback::DefaultCert, None);
+
+        let presented = handshake(resolver.clone(), "127.0.0.1").unwrap();
+        assert_eq!(presented, placeholder_cert().unwrap().cert[0]);
+        assert_ne!(presented, cert.cert[0]);
+        assert_eq!(handshake(resolver.clone(), "a.example.com").unwrap(), cert.cert[0]);
+        assert_eq!(resolver.unmatched(None), Some(FallbackAction::DefaultCert));
+    }
+
+    #[tokio::test]
+    async fn test_route_fallback_serves_the_route_certificate() {
+        let resolver = CertResolver::new();
+        let cert = self_signed("a.example.com");
+        resolver.sync_with(&domains(&["a.example.com"]), |_| fixed_cert(cert.clone()));
+        resolver.set_fallback(&TlsFallback::Route("a.example.com".to_string()), None);
+
+        assert_eq!(handshake(resolver.clone(), "127.0.0.1").unwrap(), cert.cert[0]);
+        assert_eq!(handshake(resolver.clone(), "other.example.com").unwrap(), cert.cert[0]);
+        assert_eq!(resolver.unmatched(None), Some(FallbackAction::Route("a.example.com".to_string())));
+
+        // A fallback route without a certificate can only reject
+        resolver.set_fallback(&TlsFallback::Route("plain.example.com".to_string()), None);
+        assert!(handshake(resolver.clone(), "127.0.0.1").is_err());
+        assert_eq!(resolver.unmatched(None), Some(FallbackAction::Reject));
+    }
+
+    #[tokio::test]
+    async fn test_domains_without_their_own_certificate_reuse_the_shared_one() {
+        let dir = std::env::temp_dir().join(format!("minipx-shared-cert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let generated = rcgen::generate_simple_self_signed(domains(&["a.example.com", "b.example.com"])).unwrap();
+        let pem = format!("{}{}", generated.key_pair.serialize_pem(), generated.cert.pem());
+        std::fs::write(dir.join("cached_cert_shared"), &pem).unwrap();
+        std::fs::write(dir.join("cached_account_shared"), "not a certificate").unwrap();
+
+        assert_eq!(shared_cert(&dir, "B.example.com", 0).await, Some(pem.into_bytes()));
+        assert_eq!(shared_cert(&dir, "c.example.com", 0).await, None);
+        // An expired shared certificate is not reused, the domain orders its own right away
+        assert_eq!(shared_cert(&dir, "a.example.com", u64::MAX).await, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+//! Finds what the forgiving deserializers let through silently: keys no config struct knows (usually typos such
+//! as `prot` or `ssl_enabled`) and values of the wrong type that were replaced by their default.
+
+use crate::config::types::{
+    AdminApiConfig, CacheControlRule, Config, DebugCaptureConfig, HealthEndpointConfig, ProxyPathRoute, ProxyRoute, ResolverConfig, RouteQueue,
+    RuntimeConfig, TlsCertFiles, UnknownHostConfig,
+};
+use log::warn;
+use serde::de::{DeserializeOwned, Visitor};
+use serde::{Deserializer, forward_to_deserialize_any};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::fmt;
+
+thread_local! {
+    // Set while `collect_fallbacks` runs: fallbacks are recorded here instead of being logged
+    static FALLBACKS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// A config key that was ignored or whose value was replaced by a default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Where in the file, e.g. `routes["example.com"].prot`
+    pub path: String,
+    pub kind: ConfigIssueKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigIssueKind {
+    /// No field has this name, so the key is ignored
+    UnknownKey,
+    /// The value could not be used; the message says what was used instead
+    FellBack(String),
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ConfigIssueKind::UnknownKey => write!(f, "{}: unknown key, ignored", self.path),
+            ConfigIssueKind::FellBack(message) => write!(f, "{}: {}", self.path, message),
+        }
+    }
+}
+
+/// Called by the forgiving deserializers when a value falls back to its default
+pub(crate) fn fallback(message: String) {
+    let recorded = FALLBACKS.with(|fallbacks| fallbacks.borrow_mut().as_mut().map(|fallbacks| fallbacks.push(message.clone())).is_some());
+    if !recorded {
+        warn!("{}", message);
+    }
+}
+
+pub(crate) fn collect_fallbacks<R>(f: impl FnOnce() -> R) -> (R, Vec<String>) {
+    FALLBACKS.with(|fallbacks| *fallbacks.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let fallbacks = FALLBACKS.with(|fallbacks| fallbacks.borrow_mut().take()).unwrap_or_default();
+    (result, fallbacks)
+}
+
+/// Audit a config file's JSON (after migrations)
+pub fn audit(raw: &Value) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let Some(object) = raw.as_object() else {
+        return issues;
+    };
+    audit_object::<Config>(
+        object,
+        "",
+        &["routes", "health_endpoint", "admin_api", "unknown_host", "tls_fallback_cert", "resolver", "runtime"],
+        &mut issues,
+    );
+    audit_child::<HealthEndpointConfig>(object, "health_endpoint", "health_endpoint", &mut issues);
+    audit_child::<AdminApiConfig>(object, "admin_api", "admin_api", &mut issues);
+    audit_child::<UnknownHostConfig>(object, "unknown_host", "unknown_host", &mut issues);
+    audit_child::<TlsCertFiles>(object, "tls_fallback_cert", "tls_fallback_cert", &mut issues);
+    audit_child::<ResolverConfig>(object, "resolver", "resolver", &mut issues);
+    audit_child::<RuntimeConfig>(object, "runtime", "runtime", &mut issues);
+
+    for (domain, route) in object.get("routes").and_then(Value::as_object).into_iter().flatten() {
+        let Some(route) = route.as_object() else {
+            continue;
+        };
+        let prefix = format!("routes[{:?}]", domain);
+        audit_object::<ProxyRoute>(route, &prefix, &["subroutes", "queue", "debug_capture", "cache_control"], &mut issues);
+        audit_child::<RouteQueue>(route, "queue", &format!("{}.queue", prefix), &mut issues);
+        audit_child::<DebugCaptureConfig>(route, "debug_capture", &format!("{}.debug_capture", prefix), &mut issues);
+        for (index, subroute) in route.get("subroutes").and_then(Value::as_array).into_iter().flatten().enumerate() {
+            if let Some(subroute) = subroute.as_object() {
+                audit_object::<ProxyPathRoute>(subroute, &format!("{}.subroutes[{}]", prefix, index), &[], &mut issues);
+            }
+        }
+        for (index, rule) in route.get("cache_control").and_then(Value::as_array).into_iter().flatten().enumerate() {
+            if let Some(rule) = rule.as_object() {
+                audit_object::<CacheControlRule>(rule, &format!("{}.cache_control[{}]", prefix, index), &[], &mut issues);
+            }
+        }
+    }
+    issues
+}
+
+fn audit_child<T: DeserializeOwned>(parent: &Map<String, Value>, key: &str, path: &str, issues: &mut Vec<ConfigIssue>) {
+    if let Some(object) = parent.get(key).and_then(Value::as_object) {
+        audit_object::<T>(object, path, &[], issues);
+    }
+}
+
+// Unknown keys of `object`, and keys whose value `T` replaces by a default. Each key is deserialized on its own so a
+// fallback can be pinned to it; `nested` keys are audited field by field by the caller instead.
+fn audit_object<T: DeserializeOwned>(object: &Map<String, Value>, path: &str, nested: &[&str], issues: &mut Vec<ConfigIssue>) {
+    let fields = field_names::<T>();
+    for (key, value) in object {
+        let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+        if !fields.contains(&key.as_str()) {
+            issues.push(ConfigIssue { path, kind: ConfigIssueKind::UnknownKey });
+            continue;
+        }
+        if nested.contains(&key.as_str()) || value.is_null() {
+            continue;
+        }
+        let single = Value::Object(Map::from_iter([(key.clone(), value.clone())]));
+        let (_, fallbacks) = collect_fallbacks(|| serde_json::from_value::<T>(single));
+        issues.extend(fallbacks.into_iter().map(|message| ConfigIssue { path: path.clone(), kind: ConfigIssueKind::FellBack(message) }));
+    }
+}
+
+/// Field names a derived `Deserialize` accepts, as serde passes them to `deserialize_struct`
+fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut probe = FieldProbe::default();
+    let _ = T::deserialize(&mut probe);
+    probe.fields
+}
+
+#[derive(Default)]
+struct FieldProbe {
+    fields: &'static [&'static str],
+}
+
+impl<'de> Deserializer<'de> for &mut FieldProbe {
+    type Error = serde::de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(serde::de::Error::custom("field probe"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], _visitor: V) -> Result<V::Value, Self::Error> {
+        self.fields = fields;
+        Err(serde::de::Error::custom("field probe"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+        newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn messages(raw: Value) -> Vec<String> {
+        audit(&raw).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_field_names_come_from_serde() {
+        let fields = field_names::<ProxyPathRoute>();
+        assert!(fields.contains(&"path") && fields.contains(&"port") && fields.contains(&"preserve_host"));
+        // Skipped fields are not accepted keys
+        assert!(!field_names::<Config>().contains(&"generation"));
+    }
+
+    #[test]
+    fn test_typos_and_defaulted_values_are_reported() {
+        let raw = json!({
+            "email": "admin@example.com",
+            "emial": "typo@example.com",
+            "health_endpoint": {"enabled": "yes"},
+            "routes": {
+                "example.com": {
+                    "host": "localhost",
+                    "prot": 8080,
+                    "ssl_enabled": true,
+                    "port": "8080",
+                    "listen_port": 0,
+                    "queue": {"size": 10, "timeout": 5},
+                    "subroutes": [{"path": "/api", "port": 9000, "websocket": true}]
+                }
+            }
+        });
+        let issues = messages(raw);
+        assert_eq!(issues.len(), 7, "{:#?}", issues);
+        assert_eq!(issues[0], "emial: unknown key, ignored");
+        assert!(issues[1].starts_with("health_endpoint.enabled: Failed to deserialize bool value"), "{}", issues[1]);
+        assert!(issues.contains(&r#"routes["example.com"].prot: unknown key, ignored"#.to_string()));
+        assert!(issues.contains(&r#"routes["example.com"].ssl_enabled: unknown key, ignored"#.to_string()));
+        assert!(issues.iter().any(|issue| issue.starts_with(r#"routes["example.com"].port: Failed to deserialize u16 value"#)));
+        assert!(issues.contains(&r#"routes["example.com"].queue.timeout: unknown key, ignored"#.to_string()));
+        assert!(issues.contains(&r#"routes["example.com"].subroutes[0].websocket: unknown key, ignored"#.to_string()));
+    }
+
+    #[test]
+    fn test_out_of_range_capture_limits_are_reported() {
+        let raw = json!({
+            "routes": {
+                "example.com": {
+                    "host": "localhost",
+                    "port": 8080,
+                    "debug_capture": {"enabled": true, "duration_secs": u64::MAX, "max_bytes": 1u64 << 40, "max_total_bytes": 1}
+                }
+            }
+        });
+        let issues = messages(raw.clone());
+        assert_eq!(issues.len(), 2, "{:#?}", issues);
+        assert!(issues.iter().any(|issue| issue.starts_with(r#"routes["example.com"].debug_capture.duration_secs: debug_capture duration_secs"#)));
+        assert!(issues.iter().any(|issue| issue.starts_with(r#"routes["example.com"].debug_capture.max_bytes: debug_capture max_bytes"#)));
+        let config: Config = serde_json::from_value(raw).unwrap();
+        assert_eq!(config.lookup_host("example.com").unwrap().get_debug_capture().unwrap().get_duration_secs(), 15 * 60);
+    }
+
+    #[test]
+    fn test_clean_config_has_no_issues() {
+        let config = Config::new(std::env::temp_dir().join("minipx-audit-test.json"));
+        assert_eq!(audit(&serde_json::to_value(&config).unwrap()), Vec::new());
+        let raw = json!({"strict_http": true, "routes": {"a.example.com": {"port": 3000, "listen_port": null, "subroutes": []}}});
+        assert_eq!(audit(&raw), Vec::new());
+    }
+
+    #[test]
+    fn test_fallbacks_outside_an_audit_are_only_logged() {
+        fallback("logged".to_string());
+        let ((), recorded) = collect_fallbacks(|| fallback("recorded".to_string()));
+        assert_eq!(recorded, ["recorded"]);
+        fallback("logged again".to_string());
+        assert!(FALLBACKS.with(|fallbacks| fallbacks.borrow().is_none()));
+    }
+}
@@ -0,0 +1,471 @@
+//! Import of the common Caddyfile subset: site blocks with one or more (possibly wildcard) hostnames,
+//! `reverse_proxy`, `redir` to HTTPS, `handle_path` subroutes and the ACME email from `tls` or the global
+//! options. Anything else is reported as a warning with its line number instead of failing the import.
+
+use crate::config::types::{ProxyPathRoute, ProxyRoute};
+use crate::utils::host::{normalize_host, wildcard_suffix};
+use crate::utils::validation::validate_custom_port;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Routes and settings read from a Caddyfile
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaddyImport {
+    /// Routes by normalized hostname; a site block with several hostnames yields one route per hostname
+    pub routes: BTreeMap<String, ProxyRoute>,
+    /// From `tls email@example.com` or the global `email` option
+    pub email: Option<String>,
+    pub warnings: Vec<CaddyWarning>,
+}
+
+/// Something in the Caddyfile that was not imported, or was imported differently than Caddy would serve it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaddyWarning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CaddyWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// A directive with its arguments and optional block, e.g. `reverse_proxy localhost:8080 { ... }`
+#[derive(Debug)]
+struct Directive {
+    line: usize,
+    args: Vec<String>,
+    block: Vec<Directive>,
+}
+
+impl Directive {
+    fn name(&self) -> &str {
+        self.args.first().map(String::as_str).unwrap_or_default()
+    }
+}
+
+/// Parse a Caddyfile; only unbalanced braces or unterminated quotes are errors
+pub fn parse(input: &str) -> Result<CaddyImport> {
+    let lines = tokenize(input)?;
+    let mut lines = lines.into_iter().peekable();
+    let mut top = Vec::new();
+    let mut stack: Vec<Directive> = Vec::new();
+    while let Some((line, mut tokens)) = lines.next() {
+        if tokens == ["}"] {
+            let block = stack.pop().ok_or_else(|| anyhow!("line {}: unexpected '}}'", line))?;
+            match stack.last_mut() {
+                Some(parent) => parent.block.push(block),
+                None => top.push(block),
+            }
+            continue;
+        }
+        let opens = tokens.last().is_some_and(|t| t == "{");
+        if opens {
+            tokens.pop();
+        }
+        // A Caddyfile with a single site may leave out the braces: every later line belongs to it
+        if !opens && stack.is_empty() && top.is_empty() && is_site_address_line(&tokens) && lines.peek().is_some() {
+            let block = lines.by_ref().map(|(line, args)| Directive { line, args, block: Vec::new() }).collect();
+            top.push(Directive { line, args: tokens, block });
+            break;
+        }
+        let directive = Directive { line, args: tokens, block: Vec::new() };
+        if opens {
+            stack.push(directive);
+        } else {
+            match stack.last_mut() {
+                Some(parent) => parent.block.push(directive),
+                None => top.push(directive),
+            }
+        }
+    }
+    if let Some(open) = stack.last() {
+        return Err(anyhow!("line {}: block is never closed", open.line));
+    }
+
+    let mut import = CaddyImport::default();
+    for directive in top {
+        if directive.args.is_empty() {
+            import_global_options(&directive, &mut import);
+        } else if directive.name().starts_with('(') {
+            import.warn(directive.line, format!("snippet {} is not supported; inline it into the sites that import it", directive.name()));
+        } else if directive.block.is_empty() && !is_site_address_line(&directive.args) {
+            import.warn(directive.line, format!("unsupported top-level directive `{}`", directive.name()));
+        } else {
+            import_site(&directive, &mut import);
+        }
+    }
+    Ok(import)
+}
+
+impl CaddyImport {
+    fn warn(&mut self, line: usize, message: impl Into<String>) {
+        self.warnings.push(CaddyWarning { line, message: message.into() });
+    }
+
+    fn set_email(&mut self, line: usize, email: &str) {
+        match &self.email {
+            Some(existing) if existing != email => self.warn(line, format!("ACME email {} ignored, already using {}", email, existing)),
+            _ => self.email = Some(email.to_string()),
+        }
+    }
+}
+
+fn import_global_options(options: &Directive, import: &mut CaddyImport) {
+    for option in &options.block {
+        match (option.name(), option.args.get(1)) {
+            ("email", Some(email)) => import.set_email(option.line, email),
+            (name, _) => import.warn(option.line, format!("global option `{}` is not supported", name)),
+        }
+    }
+}
+
+// One site: the hostnames on its first line and the directives of its block
+fn import_site(site: &Directive, import: &mut CaddyImport) {
+    let mut hosts = Vec::new();
+    for address in site.args.iter().flat_map(|arg| arg.split(',')).filter(|a| !a.is_empty()) {
+        match parse_site_address(address) {
+            Ok(host) => hosts.push(host),
+            Err(message) => import.warn(site.line, message),
+        }
+    }
+    if hosts.is_empty() {
+        return;
+    }
+
+    let mut backend: Option<(String, u16)> = None;
+    let mut redirect_to_https = false;
+    let mut subroutes: Vec<(usize, String, String, u16)> = Vec::new();
+    for directive in &site.block {
+        match directive.name() {
+            "reverse_proxy" => {
+                let args = &directive.args[1..];
+                match args.first().filter(|arg| arg.starts_with('/')) {
+                    Some(matcher) => {
+                        if let Some((host, port)) = parse_proxy_directive(directive, &args[1..], import) {
+                            import.warn(
+                                directive.line,
+                                format!("reverse_proxy {} imported as a subroute, which strips the prefix before forwarding", matcher),
+                            );
+                            subroutes.push((directive.line, path_prefix(matcher), host, port));
+                        }
+                    }
+                    None if args.first().is_some_and(|arg| arg.starts_with('@')) => {
+                        import.warn(directive.line, "reverse_proxy with a named matcher is not supported");
+                    }
+                    None => {
+                        if backend.is_some() {
+                            import.warn(directive.line, "only the first reverse_proxy without a matcher is used");
+                        } else {
+                            backend = parse_proxy_directive(directive, args, import);
+                        }
+                    }
+                }
+            }
+            "handle_path" => {
+                let Some(matcher) = directive.args.get(1).filter(|arg| arg.starts_with('/')) else {
+                    import.warn(directive.line, "handle_path needs a path prefix such as /api/*");
+                    continue;
+                };
+                let mut proxied = None;
+                for inner in &directive.block {
+                    match inner.name() {
+                        "reverse_proxy"
+                            if proxied.is_none() && inner.args.get(1).is_some_and(|arg| !arg.starts_with('/') && !arg.starts_with('@')) =>
+                        {
+                            proxied = parse_proxy_directive(inner, &inner.args[1..], import);
+                        }
+                        name => import.warn(inner.line, format!("`{}` inside handle_path is not supported", name)),
+                    }
+                }
+                match proxied {
+                    Some((host, port)) => subroutes.push((directive.line, path_prefix(matcher), host, port)),
+                    None => import.warn(directive.line, format!("handle_path {} has no usable reverse_proxy; skipped", matcher)),
+                }
+            }
+            "redir" => {
+                let target = directive.args.iter().skip(1).find(|arg| !arg.starts_with('/') && !arg.starts_with('@'));
+                if target.is_some_and(|target| target.starts_with("https://{host}") || target.starts_with("https://{hostport}")) {
+                    redirect_to_https = true;
+                } else {
+                    import.warn(directive.line, "only `redir https://{host}{uri}` is supported (imported as redirect_to_https)");
+                }
+            }
+            "tls" => match directive.args.get(1) {
+                Some(email) if email.contains('@') && directive.args.len() == 2 => import.set_email(directive.line, email),
+                _ => import.warn(directive.line, "only `tls <email>` is supported; certificates are issued by minipx's ACME client"),
+            },
+            name => import.warn(directive.line, format!("unsupported directive `{}`", name)),
+        }
+    }
+
+    let Some((backend_host, backend_port)) = backend else {
+        import.warn(site.line, format!("site {} has no reverse_proxy without a matcher; skipped", site.args.join(" ")));
+        return;
+    };
+    let mut path_routes: Vec<ProxyPathRoute> = Vec::new();
+    for (line, path, host, port) in subroutes {
+        if host != backend_host {
+            import.warn(
+                line,
+                format!("subroute {} goes to {} instead of {}: minipx subroutes share the route's backend host", path, host, backend_host),
+            );
+        }
+        if port == backend_port {
+            import.warn(line, format!("subroute {} uses the route's own port {}; skipped", path, port));
+        } else if path_routes.iter().any(|sub| sub.path == path) {
+            import.warn(line, format!("duplicate subroute {}; skipped", path));
+        } else {
+            path_routes.push(ProxyPathRoute { path, port, allow_websocket: None, redirect_to_https: None, preserve_host: None });
+        }
+    }
+
+    for (host, ssl_enable) in hosts {
+        if import.routes.contains_key(&host) {
+            import.warn(site.line, format!("{} is defined more than once; the first site wins", host));
+            continue;
+        }
+        // Caddy redirects HTTP to HTTPS for every HTTPS site; an `http://` site that redirects is served over HTTPS the same way
+        let https = ssl_enable || redirect_to_https;
+        let mut route = ProxyRoute::new(backend_host.clone(), String::new(), backend_port, https, None, https);
+        route.subroutes = path_routes.clone();
+        import.routes.insert(host, route);
+    }
+}
+
+// `reverse_proxy` arguments to the backend host and port; extra upstreams and options are warned about
+fn parse_proxy_directive(directive: &Directive, upstreams: &[String], import: &mut CaddyImport) -> Option<(String, u16)> {
+    if !directive.block.is_empty() {
+        import.warn(directive.line, "reverse_proxy options are not imported");
+    }
+    let Some(upstream) = upstreams.first() else {
+        import.warn(directive.line, "reverse_proxy without an upstream; skipped");
+        return None;
+    };
+    if upstreams.len() > 1 {
+        import.warn(directive.line, format!("load balancing is not supported; only {} is used", upstream));
+    }
+    match parse_upstream(upstream) {
+        Ok(backend) => Some(backend),
+        Err(message) => {
+            import.warn(directive.line, message);
+            None
+        }
+    }
+}
+
+fn parse_upstream(upstream: &str) -> std::result::Result<(String, u16), String> {
+    if upstream.starts_with("unix/") || upstream.starts_with('{') {
+        return Err(format!("upstream {} is not supported; skipped", upstream));
+    }
+    let (rest, default_port) = if let Some(rest) = upstream.strip_prefix("http://") {
+        (rest, 80)
+    } else if let Some(rest) = upstream.strip_prefix("https://") {
+        return Err(format!("HTTPS upstream {} is not supported, minipx connects to backends over HTTP; skipped", rest));
+    } else {
+        (upstream, 80)
+    };
+    let (host, port) = split_port(rest.trim_end_matches('/')).map_err(|_| format!("invalid upstream {}", upstream))?;
+    let host = if host.is_empty() { "localhost".to_string() } else { host.to_string() };
+    let port = port.unwrap_or(default_port);
+    validate_custom_port(port).map_err(|err| format!("upstream {}: {}", upstream, err))?;
+    Ok((host, port))
+}
+
+// A site address to its normalized hostname and whether Caddy would serve it over HTTPS
+fn parse_site_address(address: &str) -> std::result::Result<(String, bool), String> {
+    let (rest, scheme) = match address.split_once("://") {
+        Some((scheme, rest)) => (rest, Some(scheme)),
+        None => (address, None),
+    };
+    if rest.contains('/') {
+        return Err(format!("site address {} has a path, which is not supported; skipped", address));
+    }
+    let (host, port) = split_port(rest).map_err(|_| format!("invalid site address {}", address))?;
+    if host.is_empty() {
+        return Err(format!("site address {} has no hostname; skipped", address));
+    }
+    if port.is_some_and(|port| port != 80 && port != 443) {
+        return Err(format!("site address {} listens on a custom port, minipx serves sites on 80/443 only; skipped", address));
+    }
+    let host = normalize_host(host).into_owned();
+    if host.starts_with('*') && wildcard_suffix(&host).is_none() {
+        return Err(format!("wildcard {} is not supported, only a leading `*.`; skipped", address));
+    }
+    let ssl = scheme != Some("http") && port != Some(80);
+    Ok((host, ssl))
+}
+
+fn split_port(address: &str) -> std::result::Result<(&str, Option<u16>), ()> {
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => port.parse().map(|port| (host, Some(port))).map_err(|_| ()),
+        Some(_) => Err(()),
+        None => Ok((address, None)),
+    }
+}
+
+// `/api/*` and `/api/` both become the subroute path `/api`
+fn path_prefix(matcher: &str) -> String {
+    let prefix = matcher.trim_end_matches('*').trim_end_matches('/');
+    if prefix.is_empty() { "/".to_string() } else { prefix.to_string() }
+}
+
+// Every argument looks like an address (`example.com`, `*.example.com,`, `http://a.example.com`)
+fn is_site_address_line(tokens: &[String]) -> bool {
+    !tokens.is_empty()
+        && tokens.iter().all(|token| {
+            let token = token.trim_end_matches(',');
+            token.contains("://") || token.starts_with(':') || token.contains('.') || token == "localhost"
+        })
+}
+
+// Lines of whitespace-separated tokens with their line numbers; comments are dropped and quotes removed
+fn tokenize(input: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut lines = Vec::new();
+    for (index, text) in input.lines().enumerate() {
+        let mut tokens = Vec::new();
+        let mut chars = text.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' || c == '`' {
+                chars.next();
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') if c == '"' => token.extend(chars.next()),
+                        Some(next) if next == c => break,
+                        Some(next) => token.push(next),
+                        None => return Err(anyhow!("line {}: unterminated quote", index + 1)),
+                    }
+                }
+                tokens.push(token);
+            } else {
+                let mut token = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+        if !tokens.is_empty() {
+            lines.push((index + 1, tokens));
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines_of(import: &CaddyImport) -> Vec<usize> {
+        import.warnings.iter().map(|w| w.line).collect()
+    }
+
+    #[test]
+    fn test_multiple_hostnames_share_a_site() {
+        let import = parse(
+            r#"
+{
+    email admin@example.com
+}
+
+example.com, www.example.com {
+    reverse_proxy localhost:8080
+}
+
+http://plain.example.com {
+    reverse_proxy 10.0.0.5:3000
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(import.email.as_deref(), Some("admin@example.com"));
+        assert_eq!(import.routes.keys().collect::<Vec<_>>(), ["example.com", "plain.example.com", "www.example.com"]);
+        let route = &import.routes["www.example.com"];
+        assert_eq!((route.get_host(), route.get_port(), route.is_ssl_enabled()), ("localhost", 8080, true));
+        let plain = &import.routes["plain.example.com"];
+        assert_eq!((plain.get_host(), plain.get_port(), plain.is_ssl_enabled()), ("10.0.0.5", 3000, false));
+        assert!(route.get_redirect_to_https() && !plain.get_redirect_to_https());
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+    }
+
+    #[test]
+    fn test_handle_path_redir_and_tls_email() {
+        let import = parse(
+            r#"
+http://*.apps.example.com {
+    tls ops@example.com
+    redir https://{host}{uri} permanent
+    handle_path /api/* {
+        reverse_proxy app:9000
+    }
+    handle_path /ws/* {
+        reverse_proxy other:9001
+    }
+    reverse_proxy http://app:8000
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(import.email.as_deref(), Some("ops@example.com"));
+        let route = &import.routes["*.apps.example.com"];
+        assert_eq!((route.get_host(), route.get_port()), ("app", 8000));
+        assert!(route.is_ssl_enabled() && route.get_redirect_to_https());
+        let subroutes: Vec<(&str, u16)> = route.get_subroutes().iter().map(|s| (s.path.as_str(), s.port)).collect();
+        assert_eq!(subroutes, [("/api", 9000), ("/ws", 9001)]);
+        // The /ws subroute's different host cannot be kept
+        assert_eq!(lines_of(&import), [8]);
+    }
+
+    #[test]
+    fn test_unsupported_directives_are_warnings_with_line_numbers() {
+        let import = parse(
+            r#"(common) {
+    encode gzip
+}
+example.com {
+    import common
+    encode zstd gzip
+    header X-Frame-Options DENY
+    reverse_proxy localhost:8080 localhost:8081 {
+        header_up Host {upstream_hostport}
+    }
+    redir https://elsewhere.example.com
+}
+static.example.com {
+    file_server
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(import.routes.keys().collect::<Vec<_>>(), ["example.com"]);
+        assert_eq!(lines_of(&import), [1, 5, 6, 7, 8, 8, 11, 14, 13]);
+        assert!(import.warnings[1].message.contains("`import`"), "{}", import.warnings[1]);
+        assert_eq!(import.warnings[7].to_string(), "line 14: unsupported directive `file_server`");
+    }
+
+    #[test]
+    fn test_single_site_without_braces_and_bad_input() {
+        let import = parse("# one site\nexample.com\nreverse_proxy :3000\n").unwrap();
+        let route = &import.routes["example.com"];
+        assert_eq!((route.get_host(), route.get_port()), ("localhost", 3000));
+
+        let import = parse("example.com:8443 {\n reverse_proxy localhost:80\n}\nfoo.example.com {\n reverse_proxy localhost:80\n}").unwrap();
+        assert!(import.routes.is_empty());
+        assert_eq!(lines_of(&import), [1, 5, 4]);
+
+        assert!(parse("example.com {\n reverse_proxy localhost:8080\n").is_err());
+        assert!(parse("}\n").is_err());
+        assert!(parse("example.com {\n respond \"unterminated\n}").is_err());
+    }
+}
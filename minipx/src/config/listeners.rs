@@ -0,0 +1,173 @@
+//! The ports minipx itself listens on, and the check that keeps a route from pointing back at one of them.
+//!
+//! A route whose backend is `localhost:80` (or the listen_port of a forwarder) sends each request straight back into
+//! the proxy, which proxies it again until connections run out. Routes that really mean to reach another listener of
+//! this process, like the web panel on 6671, set `allow_self_reference`.
+
+use crate::config::types::{Config, ProxyRoute};
+use crate::proxy::forwarder::forwarder_port;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+
+// Listeners of this process the config does not describe (the web panel), registered by the binary serving them
+static EXTRA: OnceLock<Mutex<BTreeMap<u16, String>>> = OnceLock::new();
+
+fn extra() -> &'static Mutex<BTreeMap<u16, String>> {
+    EXTRA.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Register a port this process listens on outside the config, so routes pointing at it are caught as well
+pub fn register_listener_port(port: u16, name: impl Into<String>) {
+    extra().lock().unwrap().insert(port, name.into());
+}
+
+/// The ports minipx listens on, each with the listener that owns it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListenerPorts {
+    ports: BTreeMap<u16, String>,
+}
+
+impl ListenerPorts {
+    /// HTTP and HTTPS, the forwarders, the dedicated health and admin API ports of `config`, and the registered extras
+    pub fn for_config(config: &Config) -> Self {
+        Self::for_config_except(config, "")
+    }
+
+    /// Like `for_config`, without the forwarder of the route of `domain` (which is being replaced)
+    pub fn for_config_except(config: &Config, domain: &str) -> Self {
+        let mut ports = extra().lock().unwrap().clone();
+        ports.insert(80, "the HTTP frontend".to_string());
+        ports.insert(443, "the HTTPS frontend".to_string());
+        let health = config.get_health_endpoint();
+        if let Some(port) = health.get_port().filter(|_| health.is_enabled()) {
+            ports.insert(port, "the health endpoint".to_string());
+        }
+        if config.get_admin_api().is_enabled() {
+            ports.insert(config.get_admin_api().get_port(), "the admin API".to_string());
+        }
+        let mut domains: Vec<&String> = config.get_routes().keys().filter(|key| key.as_str() != domain).collect();
+        domains.sort();
+        for domain in domains {
+            if let Some(port) = forwarder_port(&config.get_routes()[domain]) {
+                ports.entry(port).or_insert_with(|| format!("the forwarder of {}", domain));
+            }
+        }
+        Self { ports }
+    }
+
+    /// The listener on `port`, if minipx owns it
+    pub fn owner(&self, port: u16) -> Option<&str> {
+        self.ports.get(&port).map(String::as_str)
+    }
+}
+
+/// True if `host` is this machine: loopback and unspecified addresses, `localhost` and its subdomains, and
+/// `resolver.static_hosts` entries pointing at those. Other names are not looked up.
+pub fn is_local_host(config: &Config, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']').trim_end_matches('.').to_ascii_lowercase();
+    let is_local_ip = |ip: IpAddr| ip.is_loopback() || ip.is_unspecified();
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return is_local_ip(ip);
+    }
+    if let Some(ip) = config.get_resolver().static_host(&host) {
+        return is_local_ip(ip);
+    }
+    host == "localhost" || host.ends_with(".localhost")
+}
+
+impl Config {
+    /// Fail if `route` (the route of `domain`) or one of its subroutes sends requests to a port minipx listens on
+    /// this machine, unless the route sets `allow_self_reference`
+    pub fn check_self_reference(&self, domain: &str, route: &ProxyRoute, listeners: &ListenerPorts) -> Result<()> {
+        if route.is_allow_self_reference() || !is_local_host(self, route.get_host()) {
+            return Ok(());
+        }
+        // The route's own forwarder counts even before the route is in the config
+        let own = forwarder_port(route).map(|port| (port, format!("the forwarder of {}", domain)));
+        // A route on a unix socket only reaches this machine's ports through its subroutes
+        let own_port = Some(route.get_port()).filter(|_| route.get_unix_socket().is_none());
+        let ports = own_port.into_iter().chain(route.get_subroutes().iter().map(|subroute| subroute.get_port()));
+        for port in ports {
+            let owner = match &own {
+                Some((own_port, name)) if *own_port == port => Some(name.as_str()),
+                _ => listeners.owner(port),
+            };
+            if let Some(owner) = owner {
+                return Err(anyhow!(
+                    "Backend {}:{} of {} is {} of this minipx; requests would be proxied back to the proxy in a loop. \
+                     Set allow_self_reference (--allow-self-reference) if this is intended",
+                    route.get_host(),
+                    port,
+                    domain,
+                    owner
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ResolverConfig;
+
+    fn route(host: &str, port: u16) -> ProxyRoute {
+        ProxyRoute::new(host.to_string(), "".to_string(), port, false, None, false)
+    }
+
+    #[tokio::test]
+    async fn test_routes_to_the_frontends_are_rejected() {
+        let mut config = Config::default();
+        let err = config.add_route("loop.example.com".to_string(), route("localhost", 80)).await.unwrap_err().to_string();
+        assert!(err.contains("localhost:80") && err.contains("the HTTP frontend") && err.contains("loop"), "{}", err);
+        let err = config.add_route("loop.example.com".to_string(), route("127.0.0.1", 443)).await.unwrap_err().to_string();
+        assert!(err.contains("the HTTPS frontend"), "{}", err);
+        // The same ports on another machine are fine as far as loops go (but still reserved)
+        let err = config.add_route("remote.example.com".to_string(), route("10.0.0.5", 80)).await.unwrap_err().to_string();
+        assert!(!err.contains("loop"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_forwarder_ports_and_updates_are_checked() {
+        let mut config = Config::default();
+        let game = ProxyRoute::new("10.0.0.7".to_string(), "".to_string(), 25565, false, Some(25565), false);
+        config.add_route("game.example.com".to_string(), game).await.unwrap();
+        config.add_route("app.example.com".to_string(), route("127.0.0.1", 3000)).await.unwrap();
+
+        let err = config.add_route("mc.example.com".to_string(), route("[::1]", 25565)).await.unwrap_err().to_string();
+        assert!(err.contains("the forwarder of game.example.com"), "{}", err);
+
+        let patch = crate::config::RoutePatch { port: Some(25565), ..Default::default() };
+        assert!(config.update_route("app.example.com", patch).await.is_err());
+        assert_eq!(config.get_routes()["app.example.com"].get_port(), 3000);
+    }
+
+    #[tokio::test]
+    async fn test_registered_listener_with_override() {
+        register_listener_port(6671, "the web panel");
+        let mut config = Config::default();
+        let err = config.add_route("panel.example.com".to_string(), route("localhost", 6671)).await.unwrap_err().to_string();
+        assert!(err.contains("the web panel"), "{}", err);
+
+        let mut panel = route("localhost", 6671);
+        panel.set_allow_self_reference(true);
+        config.add_route("panel.example.com".to_string(), panel).await.unwrap();
+        assert!(config.get_routes()["panel.example.com"].is_allow_self_reference());
+    }
+
+    #[test]
+    fn test_local_hosts() {
+        let mut config = Config::default();
+        let hosts = [("db.internal", "127.0.0.2"), ("api.internal", "10.0.0.3")].map(|(name, ip)| (name.to_string(), ip.parse().unwrap()));
+        config.set_resolver(ResolverConfig::new(Default::default(), Vec::new(), hosts.into_iter().collect()));
+        for host in ["localhost", "LOCALHOST.", "app.localhost", "127.0.0.1", "127.1.2.3", "::1", "[::1]", "0.0.0.0", "db.internal"] {
+            assert!(is_local_host(&config, host), "{}", host);
+        }
+        for host in ["10.0.0.3", "api.internal", "example.com", "notlocalhost"] {
+            assert!(!is_local_host(&config, host), "{}", host);
+        }
+    }
+}
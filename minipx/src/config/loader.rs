@@ -1,78 +1,184 @@
-use crate::config::manager::{broadcaster, config_lock};
+use crate::config::audit::{audit, collect_fallbacks};
+use crate::config::manager::{advance_generations, broadcaster, config_lock, record_applied_content, set_reload_failure};
+use crate::config::migrations::migrate;
 use crate::config::types::Config;
 use crate::ipc;
 use crate::utils::validation::is_empty_or_whitespace;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use log::{debug, error, trace, warn};
+use std::fmt;
 use std::path::Path;
 
+/// Where `resolve_config_path` found the config path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// `--config`
+    CommandLine,
+    /// Asked a running instance over IPC
+    RunningInstance,
+    /// Neither: `./minipx.json`
+    Default,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConfigSource::CommandLine => "--config",
+            ConfigSource::RunningInstance => "running instance (IPC)",
+            ConfigSource::Default => "default",
+        })
+    }
+}
+
 impl Config {
     /// Resolve the config path from a command line argument or running instance
     pub async fn resolve_config_path(arg: Option<String>) -> String {
+        Self::resolve_config_path_with_source(arg).await.0
+    }
+
+    /// `resolve_config_path`, also saying where the path came from
+    pub async fn resolve_config_path_with_source(arg: Option<String>) -> (String, ConfigSource) {
         #[allow(clippy::collapsible_if)]
         if let Some(s) = arg {
             if !is_empty_or_whitespace(&s) {
-                return s;
+                return (s, ConfigSource::CommandLine);
             }
         }
         if let Some(path) = ipc::get_running_config_path().await {
-            return path;
+            return (path, ConfigSource::RunningInstance);
+        }
+        ("./minipx.json".to_string(), ConfigSource::Default)
+    }
+
+    /// Read and parse a config file (running migrations) without touching the file or the running config
+    pub async fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = tokio::fs::read_to_string(path).await.map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let mut raw = serde_json::from_str::<serde_json::Value>(&content);
+        // Anything but an object is reported as a parse error below
+        #[allow(clippy::collapsible_if)]
+        if let Ok(value) = &mut raw {
+            if value.is_object() {
+                migrate(value)?;
+            }
+        }
+        let issues = raw.as_ref().map(audit).unwrap_or_default();
+        // The fallbacks are listed together below instead of one warning each
+        let (parsed, _) = collect_fallbacks(|| raw.and_then(serde_json::from_value::<Config>));
+        let mut config = parsed.map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+        config.path = path.to_owned();
+        if !issues.is_empty() {
+            let list: Vec<String> = issues.iter().map(|issue| format!("  - {}", issue)).collect();
+            if config.strict_config {
+                return Err(anyhow!(
+                    "{} has {} ignored or invalid settings (strict_config is on):\n{}",
+                    path.display(),
+                    issues.len(),
+                    list.join("\n")
+                ));
+            }
+            warn!(
+                "{} has {} settings that were ignored or replaced by defaults; check for typos (set \"strict_config\": true to refuse such files):\n{}",
+                path.display(),
+                issues.len(),
+                list.join("\n")
+            );
         }
-        "./minipx.json".to_string()
+        config.issues = issues;
+        Ok(config)
     }
 
-    /// Load configuration from a file, updating global state and broadcasting changes
+    /// Load configuration from a file, updating global state and broadcasting changes.
+    /// A missing file is created with the defaults; a file that fails to parse is an error and is left as it is.
     pub async fn try_load(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
         debug!("Loading config from: {}", path.display());
         let config = if path.exists() {
-            let content = tokio::fs::read_to_string(path).await?;
-            let result = serde_json::from_str::<Config>(&content);
-            if let Err(e) = result {
-                error!("Failed to parse config file: {}", e);
-                // Move the corrupted config file to a backup
-                let mut number_of_coruptions = 1;
-                let mut backup_path = path.with_extension(format!("corrupted.{}", number_of_coruptions));
-
-                while backup_path.exists() {
-                    backup_path = path.with_extension(format!("corrupted.{}", number_of_coruptions));
-                    number_of_coruptions += 1;
-                }
-                std::fs::rename(path, backup_path)?;
-
-                warn!("Config file corrupted, using default config");
-                Self::save_default(path).await?;
-                Self::new(path)
-            } else {
-                let mut cfg = result?;
-                cfg.path = path.to_owned();
-                cfg
-            }
+            Self::read(path).await?
         } else {
             warn!("Config file not found, using default config");
             Self::save_default(path).await?;
             Self::new(path)
         };
-        trace!("Loaded config: {:#?}", config);
+        Ok(Self::apply(config).await)
+    }
 
-        {
-            let mut guard = config_lock().write().await;
-            *guard = config.clone();
+    /// Like `try_load`, but a file that fails to parse is moved to `<name>.corrupted.<n>` and replaced by a
+    /// default config. Only meant for the initial load (`--init-default-config`), when nothing is being served yet.
+    pub async fn try_load_or_init_default(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::try_load(path).await;
+        }
+        if let Err(e) = Self::read(path).await {
+            error!("{}", e);
+            let mut number_of_corruptions = 1;
+            let mut backup_path = path.with_extension(format!("corrupted.{}", number_of_corruptions));
+            while backup_path.exists() {
+                number_of_corruptions += 1;
+                backup_path = path.with_extension(format!("corrupted.{}", number_of_corruptions));
+            }
+            std::fs::rename(path, &backup_path)?;
+            warn!("Config file corrupted, moved it to {} and using default config", backup_path.display());
         }
+        Self::try_load(path).await
+    }
 
-        let _ = broadcaster().send(config.clone());
+    /// Reload the running config from `path`. A file that cannot be read or parsed changes nothing: the file is
+    /// left as it is, the last-known-good config keeps serving, and the failure is reported by `reload_failure`
+    /// (status, metrics) until a reload succeeds.
+    pub async fn reload(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        debug!("Reloading config from: {}", path.display());
+        match Self::read(path).await {
+            Ok(config) => {
+                set_reload_failure(None);
+                Ok(Self::apply(config).await)
+            }
+            Err(e) => {
+                error!("CONFIG RELOAD FAILED, still serving the last-known-good config: {}", e);
+                set_reload_failure(Some(e.to_string()));
+                Err(e)
+            }
+        }
+    }
 
-        Ok(config)
+    // Make `config` the running one and broadcast it
+    async fn apply(config: Config) -> Self {
+        trace!("Loaded config: {:#?}", config);
+        let config = {
+            let mut guard = config_lock().write().await;
+            let mut config = config;
+            advance_generations(&guard, &mut config);
+            *guard = config.clone();
+            config
+        };
+        let _ = broadcaster().send(config.clone());
+        config
     }
 
     /// Save the current configuration to its file
     pub async fn save(&self) -> Result<()> {
+        self.write(&serde_json::to_string_pretty(self)?).await
+    }
+
+    /// Save this configuration to its file and make it the running one, advancing the generation once: the file
+    /// watcher recognizes the write as already applied instead of reloading it
+    pub async fn save_and_apply(self) -> Result<Self> {
+        let content = serde_json::to_string_pretty(&self)?;
+        // Recorded before writing, since the watcher may see the write before it returns
+        record_applied_content(&self.path, content.clone());
+        self.write(&content).await?;
+        set_reload_failure(None);
+        Ok(Self::apply(self).await)
+    }
+
+    async fn write(&self, content: &str) -> Result<()> {
         debug!("Saving config to: {}", self.path.display());
         if !self.path.exists() {
             std::fs::create_dir_all(self.path.parent().ok_or(anyhow::anyhow!("Failed to create parent directory for config file"))?)?;
             tokio::fs::File::create(&self.path).await?;
         }
-        let content = serde_json::to_string_pretty(self)?;
         tokio::fs::write(&self.path, content).await?;
         Ok(())
     }
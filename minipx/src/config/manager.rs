@@ -1,11 +1,22 @@
 use crate::config::types::Config;
-use std::sync::OnceLock;
+use crate::utils::host::normalize_host;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use tokio::sync::RwLock;
 use tokio::sync::broadcast;
 
 // Global state management with OnceLock
 static LOADED_CONFIG: OnceLock<RwLock<Config>> = OnceLock::new();
 static CONFIG_TX: OnceLock<broadcast::Sender<Config>> = OnceLock::new();
+// Why the config file last failed to reload, while the last-known-good config keeps serving
+static RELOAD_FAILURE: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+// What each watched config file held when the running config was last loaded from it or saved to it
+static APPLIED_CONTENT: OnceLock<Mutex<HashMap<PathBuf, String>>> = OnceLock::new();
+// Generations replaced by a reload, kept until nothing references them anymore
+static RETIRED_GENERATIONS: OnceLock<Mutex<HashMap<String, Vec<Arc<RouteGeneration>>>>> = OnceLock::new();
 
 /// Get the global config lock
 pub fn config_lock() -> &'static RwLock<Config> {
@@ -20,6 +31,145 @@ pub fn broadcaster() -> &'static broadcast::Sender<Config> {
     })
 }
 
+fn retired_generations() -> &'static Mutex<HashMap<String, Vec<Arc<RouteGeneration>>>> {
+    RETIRED_GENERATIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// One generation of a route: bumped whenever the route's backend definition changes on reload.
+/// Requests pin the generation of the config snapshot they started with.
+#[derive(Debug)]
+pub struct RouteGeneration {
+    generation: u64,
+    requests: AtomicU64,
+    websockets: AtomicU64,
+}
+
+/// Keeps a request or websocket counted against its route generation until dropped
+#[derive(Debug)]
+pub struct InFlightGuard {
+    generation: Arc<RouteGeneration>,
+    websocket: bool,
+}
+
+impl RouteGeneration {
+    fn new(generation: u64) -> Self {
+        Self { generation, requests: AtomicU64::new(0), websockets: AtomicU64::new(0) }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn in_flight_requests(&self) -> u64 {
+        self.requests.load(Ordering::Acquire)
+    }
+
+    pub fn in_flight_websockets(&self) -> u64 {
+        self.websockets.load(Ordering::Acquire)
+    }
+
+    /// Count a request (or websocket tunnel) against this generation until the guard is dropped
+    pub fn track(self: &Arc<Self>, websocket: bool) -> InFlightGuard {
+        let counter = if websocket { &self.websockets } else { &self.requests };
+        counter.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard { generation: self.clone(), websocket }
+    }
+
+    fn is_drained(&self) -> bool {
+        self.in_flight_requests() == 0 && self.in_flight_websockets() == 0
+    }
+
+    fn in_flight(&self) -> GenerationInFlight {
+        GenerationInFlight { generation: self.generation, requests: self.in_flight_requests(), websockets: self.in_flight_websockets() }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let counter = if self.websocket { &self.generation.websockets } else { &self.generation.requests };
+        counter.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// In-flight counts for a single route generation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenerationInFlight {
+    pub generation: u64,
+    pub requests: u64,
+    pub websockets: u64,
+}
+
+/// Drain state of a route: what is still pinned to generations replaced by a reload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrainStatus {
+    pub domain: String,
+    /// Current generation, or None if the route has been removed
+    pub current: Option<GenerationInFlight>,
+    /// Previous generations that still have requests or websockets in flight
+    pub previous: Vec<GenerationInFlight>,
+    /// True when nothing is pinned to a previous generation, i.e. the old backend can be stopped
+    pub drained: bool,
+}
+
+fn reload_failure_slot() -> &'static Mutex<Option<String>> {
+    RELOAD_FAILURE.get_or_init(|| Mutex::new(None))
+}
+
+/// The error of the last config reload if it failed (and no reload has succeeded since), i.e. the running config
+/// no longer matches the file
+pub fn reload_failure() -> Option<String> {
+    reload_failure_slot().lock().unwrap().clone()
+}
+
+pub(crate) fn set_reload_failure(error: Option<String>) {
+    *reload_failure_slot().lock().unwrap() = error;
+}
+
+fn applied_content() -> &'static Mutex<HashMap<PathBuf, String>> {
+    APPLIED_CONTENT.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Note that the running config matches `content` of the file at `path`, so the watcher need not reload it
+pub(crate) fn record_applied_content(path: &Path, content: String) {
+    applied_content().lock().unwrap().insert(path.to_owned(), content);
+}
+
+/// Whether the file at `path` holding `content` is already what the running config was loaded from or saved as
+pub(crate) fn is_applied_content(path: &Path, content: &str) -> bool {
+    applied_content().lock().unwrap().get(path).is_some_and(|applied| applied == content)
+}
+
+/// Carry route generations over from `previous` into `next`: unchanged routes keep their generation
+/// (and in-flight counters), changed or new routes get the next generation, and replaced or removed
+/// generations are retired so their remaining in-flight requests can still be reported.
+pub(crate) fn advance_generations(previous: &Config, next: &mut Config) {
+    let mut generations = HashMap::with_capacity(next.routes.len());
+    let mut retired = retired_generations().lock().unwrap();
+    for (domain, route) in &next.routes {
+        let prev_generation = previous.generations.get(domain);
+        match prev_generation {
+            Some(generation) if previous.routes.get(domain) == Some(route) => {
+                generations.insert(domain.clone(), generation.clone());
+            }
+            _ => {
+                let retired_max = retired.get(domain).and_then(|gens| gens.iter().map(|g| g.generation).max()).unwrap_or(0);
+                let number = prev_generation.map(|g| g.generation).unwrap_or(0).max(retired_max) + 1;
+                if let Some(old) = prev_generation {
+                    retired.entry(domain.clone()).or_default().push(old.clone());
+                }
+                generations.insert(domain.clone(), Arc::new(RouteGeneration::new(number)));
+            }
+        }
+    }
+    for (domain, generation) in previous.generations.iter() {
+        if !next.routes.contains_key(domain) {
+            retired.entry(domain.clone()).or_default().push(generation.clone());
+        }
+    }
+    next.generations = Arc::new(generations);
+    next.generation = previous.generation + 1;
+}
+
 impl Config {
     /// Get a clone of the current global configuration
     pub async fn get() -> Self {
@@ -30,4 +180,116 @@ impl Config {
     pub fn subscribe() -> broadcast::Receiver<Config> {
         broadcaster().subscribe()
     }
+
+    /// Number of this config snapshot: incremented by every load or reload that applied it
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The generation this config snapshot assigned to a route, keyed by route key
+    pub fn route_generation(&self, domain: &str) -> Option<&Arc<RouteGeneration>> {
+        self.generations.get(domain)
+    }
+
+    /// Requests and websocket tunnels in flight on every route, including generations retired by reloads
+    pub fn total_in_flight(&self) -> GenerationInFlight {
+        let retired = retired_generations().lock().unwrap();
+        let generations = self.generations.values().chain(retired.values().flatten());
+        let (requests, websockets) =
+            generations.fold((0, 0), |(requests, websockets), g| (requests + g.in_flight_requests(), websockets + g.in_flight_websockets()));
+        GenerationInFlight { generation: self.generation, requests, websockets }
+    }
+
+    /// Report what is still in flight against previous generations of a route (by route key)
+    pub fn drain_status(&self, domain: &str) -> Option<DrainStatus> {
+        let domain = normalize_host(domain).into_owned();
+        let current = self.generations.get(&domain).map(|g| g.in_flight());
+
+        let mut retired = retired_generations().lock().unwrap();
+        let previous: Vec<GenerationInFlight> = match retired.get_mut(&domain) {
+            Some(gens) => {
+                // Forget generations nothing can reach anymore: no snapshot or guard holds them and they are idle
+                gens.retain(|g| Arc::strong_count(g) > 1 || !g.is_drained());
+                gens.iter().filter(|g| !g.is_drained()).map(|g| g.in_flight()).collect()
+            }
+            None => Vec::new(),
+        };
+        if retired.get(&domain).is_some_and(|gens| gens.is_empty()) {
+            retired.remove(&domain);
+        }
+
+        if current.is_none() && previous.is_empty() {
+            return None;
+        }
+        let drained = previous.is_empty();
+        Some(DrainStatus { domain, current, previous, drained })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::types::ProxyRoute;
+
+    fn config_with(domain: &str, port: u16) -> Config {
+        let mut config = Config::default();
+        config.routes.insert(domain.to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), port, false, None, false));
+        config
+    }
+
+    #[test]
+    fn test_generation_kept_when_route_unchanged() {
+        let mut first = config_with("same.drain.test", 8080);
+        advance_generations(&Config::default(), &mut first);
+        let mut second = config_with("same.drain.test", 8080);
+        advance_generations(&first, &mut second);
+
+        let a = first.route_generation("same.drain.test").unwrap();
+        let b = second.route_generation("same.drain.test").unwrap();
+        assert_eq!(a.generation(), 1);
+        assert!(Arc::ptr_eq(a, b));
+        // The config itself still moves to a new generation
+        assert_eq!((first.generation(), second.generation()), (1, 2));
+    }
+
+    #[test]
+    fn test_drain_status_tracks_previous_generation() {
+        let mut first = config_with("port.drain.test", 8080);
+        advance_generations(&Config::default(), &mut first);
+        let request = first.route_generation("port.drain.test").unwrap().track(false);
+        let websocket = first.route_generation("port.drain.test").unwrap().track(true);
+
+        let mut second = config_with("port.drain.test", 9090);
+        advance_generations(&first, &mut second);
+        drop(first);
+
+        let status = second.drain_status("port.drain.test").unwrap();
+        assert_eq!(status.current, Some(GenerationInFlight { generation: 2, requests: 0, websockets: 0 }));
+        assert_eq!(status.previous, vec![GenerationInFlight { generation: 1, requests: 1, websockets: 1 }]);
+        assert!(!status.drained);
+
+        drop(request);
+        drop(websocket);
+        let status = second.drain_status("PORT.drain.test").unwrap();
+        assert!(status.previous.is_empty());
+        assert!(status.drained);
+    }
+
+    #[test]
+    fn test_removed_route_reports_until_drained() {
+        let mut first = config_with("gone.drain.test", 8080);
+        advance_generations(&Config::default(), &mut first);
+        let request = first.route_generation("gone.drain.test").unwrap().track(false);
+
+        let mut second = Config::default();
+        advance_generations(&first, &mut second);
+        drop(first);
+
+        let status = second.drain_status("gone.drain.test").unwrap();
+        assert!(status.current.is_none());
+        assert!(!status.drained);
+
+        drop(request);
+        assert!(second.drain_status("gone.drain.test").is_none());
+    }
 }
@@ -0,0 +1,90 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use serde_json::Value;
+
+/// Schema version written to the `version` field of saved config files
+pub const CONFIG_VERSION: u32 = 1;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+// Migration from version `n` (the index) to `n + 1`; files without a version field are version 0
+const MIGRATIONS: &[Migration] = &[
+    // 0 -> 1: the version field itself was introduced; nothing else changed
+    |_| Ok(()),
+];
+
+/// Bring a parsed config file up to `CONFIG_VERSION`, returning the version the file was written with.
+/// Files from a newer minipx are loaded as-is, on a best-effort basis.
+pub fn migrate(config: &mut Value) -> Result<u32> {
+    migrate_with(config, MIGRATIONS)
+}
+
+fn migrate_with(config: &mut Value, migrations: &[Migration]) -> Result<u32> {
+    let object = config.as_object_mut().ok_or_else(|| anyhow!("Config file must contain a JSON object"))?;
+    let found = match object.get("version") {
+        None | Some(Value::Null) => 0,
+        Some(v) => v.as_u64().and_then(|v| u32::try_from(v).ok()).ok_or_else(|| anyhow!("Invalid config version: {}", v))?,
+    };
+    let current = migrations.len() as u32;
+    if found > current {
+        warn!("Config file has version {}, newer than this minipx understands ({}); unknown settings will be ignored", found, current);
+        return Ok(found);
+    }
+    for (from, migration) in migrations.iter().enumerate().skip(found as usize) {
+        migration(config).map_err(|e| anyhow!("Failed to migrate config from version {} to {}: {}", from, from + 1, e))?;
+    }
+    if found < current {
+        info!("Migrated config file from version {} to {}", found, current);
+        config["version"] = Value::from(current);
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrations_cover_current_version() {
+        assert_eq!(MIGRATIONS.len() as u32, CONFIG_VERSION);
+    }
+
+    #[test]
+    fn test_unversioned_config_is_stamped() {
+        let mut config = json!({ "email": "a@example.com" });
+        assert_eq!(migrate(&mut config).unwrap(), 0);
+        assert_eq!(config["version"], CONFIG_VERSION);
+    }
+
+    #[test]
+    fn test_migrations_run_in_order_from_file_version() {
+        let steps: &[Migration] = &[
+            |c| {
+                c["steps"] = json!(["0->1"]);
+                Ok(())
+            },
+            |c| {
+                c["steps"].as_array_mut().unwrap().push(json!("1->2"));
+                Ok(())
+            },
+        ];
+        let mut old = json!({});
+        assert_eq!(migrate_with(&mut old, steps).unwrap(), 0);
+        assert_eq!(old, json!({ "steps": ["0->1", "1->2"], "version": 2 }));
+
+        let mut partial = json!({ "version": 1, "steps": ["0->1"] });
+        migrate_with(&mut partial, steps).unwrap();
+        assert_eq!(partial["steps"], json!(["0->1", "1->2"]));
+    }
+
+    #[test]
+    fn test_newer_and_invalid_versions() {
+        let mut newer = json!({ "version": 99, "future": true });
+        assert_eq!(migrate(&mut newer).unwrap(), 99);
+        assert_eq!(newer["version"], 99);
+
+        assert!(migrate(&mut json!({ "version": "one" })).is_err());
+        assert!(migrate(&mut json!([1, 2])).is_err());
+    }
+}
@@ -2,16 +2,34 @@
 //
 // This module contains all configuration-related functionality split into focused submodules:
 // - types: Core configuration structures and types
+// - audit: Unknown keys and defaulted values the forgiving deserializers let through
+// - caddyfile: Route import from the common Caddyfile subset
+// - listeners: minipx's own listen ports and the check against routes looping back into them
 // - loader: Configuration file loading and saving
 // - validator: Configuration validation logic
 // - manager: Global state management and broadcasting
+// - migrations: Schema version and upgrades of older config files
+// - templates: Reusable route templates (built-in and from the config file)
 // - watcher: File watching functionality
 
+pub mod audit;
+pub mod caddyfile;
+pub mod listeners;
 pub mod loader;
 pub mod manager;
+pub mod migrations;
+pub mod templates;
 pub mod types;
 pub mod validator;
 pub mod watcher;
 
 // Re-export main types for backward compatibility
-pub use types::{Config, ProxyRoute, RoutePatch};
+pub use audit::{ConfigIssue, ConfigIssueKind};
+pub use listeners::{ListenerPorts, register_listener_port};
+pub use loader::ConfigSource;
+pub use templates::{RouteTemplate, SubrouteTemplate};
+pub use types::{
+    AdminApiConfig, BindFailurePolicy, CacheControlRule, Config, DebugCaptureConfig, HeaderLimitPolicy, HealthEndpointConfig, InactiveResponse,
+    MAX_CAPTURE_BYTES, MAX_CAPTURE_DURATION_SECS, MAX_CAPTURE_TOTAL_BYTES, ProxyPathRoute, ProxyRoute, RequestLogMode, ResolverConfig, ResolverMode,
+    RoutePatch, RouteQueue, RuntimeConfig, SubrouteOverrides, SubroutePatch, TlsCertFiles, TlsFallback, UnknownHostAction, UnknownHostConfig,
+};
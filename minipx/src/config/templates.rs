@@ -0,0 +1,303 @@
+use crate::config::audit::fallback;
+use crate::config::types::{Config, ProxyRoute, RoutePatch, SubrouteOverrides};
+use crate::utils::host::normalize_host;
+use anyhow::{Result, anyhow};
+use log::info;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+
+/// Reusable shape of a route. Unset fields keep the usual route defaults; port fields may use the
+/// `{port}` placeholder, optionally with an offset (`{port+1}`), filled in from the port given when adding.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RouteTemplate {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssl_enable: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_to_https: Option<bool>,
+    #[serde(deserialize_with = "port_template_option", default, skip_serializing_if = "Option::is_none")]
+    pub listen_port: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_websocket: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_host: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subroutes: Vec<SubrouteTemplate>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SubrouteTemplate {
+    pub path: String,
+    #[serde(deserialize_with = "port_template")]
+    pub port: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_websocket: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_to_https: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_host: Option<bool>,
+}
+
+/// Subroute produced by instantiating a template
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateSubroute {
+    pub path: String,
+    pub port: u16,
+    pub overrides: SubrouteOverrides,
+}
+
+/// Templates shipped with minipx; a template of the same name in the config file replaces them
+pub fn builtin_templates() -> BTreeMap<String, RouteTemplate> {
+    let api_subroute = SubrouteTemplate { path: "/api".to_string(), port: "{port+1}".to_string(), ..Default::default() };
+    BTreeMap::from([
+        (
+            "spa".to_string(),
+            RouteTemplate {
+                description: "Single-page app on {port} with its API under /api on {port+1}".to_string(),
+                subroutes: vec![api_subroute],
+                ..Default::default()
+            },
+        ),
+        (
+            "websocket".to_string(),
+            RouteTemplate {
+                description: "Websocket app on {port}, forwarding the client's Host header".to_string(),
+                allow_websocket: Some(true),
+                preserve_host: Some(true),
+                ..Default::default()
+            },
+        ),
+        (
+            "game-server".to_string(),
+            RouteTemplate {
+                description: "Game server forwarded on its own port ({port} -> {port})".to_string(),
+                listen_port: Some("{port}".to_string()),
+                ..Default::default()
+            },
+        ),
+    ])
+}
+
+impl RouteTemplate {
+    /// Fill in the placeholders for `port`; the result still has to pass the usual route validation
+    pub fn instantiate(&self, port: u16) -> Result<(ProxyRoute, Vec<TemplateSubroute>)> {
+        let mut route = ProxyRoute::new(
+            self.host.clone().unwrap_or_else(|| "localhost".to_string()),
+            self.path.clone().unwrap_or_default(),
+            port,
+            self.ssl_enable.unwrap_or(false),
+            self.listen_port.as_deref().map(|p| expand_port(p, port)).transpose()?,
+            self.redirect_to_https.unwrap_or(false),
+        );
+        if let Some(allow) = self.allow_websocket {
+            route.set_allow_websocket(allow);
+        }
+        route.set_preserve_host(self.preserve_host);
+        route.set_max_concurrent_requests(self.max_concurrent_requests);
+
+        let subroutes = self
+            .subroutes
+            .iter()
+            .map(|s| {
+                Ok(TemplateSubroute {
+                    path: s.path.clone(),
+                    port: expand_port(&s.port, port)?,
+                    overrides: SubrouteOverrides {
+                        allow_websocket: s.allow_websocket,
+                        redirect_to_https: s.redirect_to_https,
+                        preserve_host: s.preserve_host,
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((route, subroutes))
+    }
+}
+
+/// Expand `{port}`, `{port+N}`, `{port-N}` or a literal port number
+pub fn expand_port(value: &str, port: u16) -> Result<u16> {
+    let value = value.trim();
+    let Some(inner) = value.strip_prefix('{').and_then(|v| v.strip_suffix('}')) else {
+        return value.parse::<u16>().map_err(|_| anyhow!("Invalid port in template: {}", value));
+    };
+    let inner: String = inner.chars().filter(|c| !c.is_whitespace()).collect();
+    let Some(offset) = inner.strip_prefix("port") else {
+        return Err(anyhow!("Unknown template placeholder: {}", value));
+    };
+    let expanded = if offset.is_empty() {
+        Some(port)
+    } else if let Some(n) = offset.strip_prefix('+') {
+        n.parse::<u16>().ok().and_then(|n| port.checked_add(n))
+    } else if let Some(n) = offset.strip_prefix('-') {
+        n.parse::<u16>().ok().and_then(|n| port.checked_sub(n))
+    } else {
+        None
+    };
+    expanded.filter(|p| *p != 0).ok_or_else(|| anyhow!("Invalid port placeholder {} for port {}", value, port))
+}
+
+impl Config {
+    pub fn get_templates(&self) -> &BTreeMap<String, RouteTemplate> {
+        &self.templates
+    }
+
+    /// Built-in templates merged with the config's own (which win on name clashes)
+    pub fn available_templates(&self) -> BTreeMap<String, RouteTemplate> {
+        let mut templates = builtin_templates();
+        templates.extend(self.templates.iter().map(|(name, template)| (name.clone(), template.clone())));
+        templates
+    }
+
+    pub fn find_template(&self, name: &str) -> Option<RouteTemplate> {
+        self.templates.get(name).cloned().or_else(|| builtin_templates().remove(name))
+    }
+
+    /// Add a route from a template, then apply `overrides` on top. Goes through the same validation as
+    /// `add_route`/`update_route`/`add_subroute_with`, and leaves the config untouched if any step fails.
+    pub async fn add_route_from_template(&mut self, domain: String, template: &str, port: u16, overrides: RoutePatch) -> Result<()> {
+        let found = self.find_template(template).ok_or_else(|| anyhow!("Unknown route template: {}", template))?;
+        let (mut route, subroutes) = found.instantiate(port)?;
+        // Needed by the first add_route already, when the template's backend is local
+        route.allow_self_reference = overrides.allow_self_reference.unwrap_or(route.allow_self_reference);
+        let domain = normalize_host(&domain).into_owned();
+        info!("Adding route {} from template '{}'", domain, template);
+
+        let mut staged = self.clone();
+        staged.add_route(domain.clone(), route).await?;
+        if overrides != RoutePatch::default() {
+            staged.update_route(&domain, overrides).await?;
+        }
+        for subroute in subroutes {
+            staged.add_subroute_with(&domain, subroute.path, subroute.port, subroute.overrides).await?;
+        }
+        *self = staged;
+        Ok(())
+    }
+}
+
+// Port fields accept a number or a placeholder string
+fn port_template<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        serde_json::Value::String(s) => Ok(s),
+        other => Err(serde::de::Error::custom(format!("expected a port or placeholder, got {}", other))),
+    }
+}
+
+fn port_template_option<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(value) => port_template(value).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+// Templates that fail to parse are dropped with a warning instead of failing the whole config
+pub(crate) fn templates_or_default<'de, D>(deserializer: D) -> std::result::Result<BTreeMap<String, RouteTemplate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = match BTreeMap::<String, serde_json::Value>::deserialize(deserializer) {
+        Ok(raw) => raw,
+        Err(e) => {
+            fallback(format!("Failed to deserialize route templates: {}, ignoring them", e));
+            return Ok(BTreeMap::new());
+        }
+    };
+    Ok(raw
+        .into_iter()
+        .filter_map(|(name, value)| match serde_json::from_value::<RouteTemplate>(value) {
+            Ok(template) => Some((name, template)),
+            Err(e) => {
+                fallback(format!("Ignoring invalid route template '{}': {}", name, e));
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_template(name: &str, json: serde_json::Value) -> Config {
+        let mut config = Config::new(std::env::temp_dir().join("minipx-templates-test.json"));
+        config.templates.insert(name.to_string(), serde_json::from_value(json).unwrap());
+        config
+    }
+
+    #[test]
+    fn test_expand_port() {
+        assert_eq!(expand_port("{port}", 3000).unwrap(), 3000);
+        assert_eq!(expand_port("{port+1}", 3000).unwrap(), 3001);
+        assert_eq!(expand_port("{ port - 10 }", 3000).unwrap(), 2990);
+        assert_eq!(expand_port("8080", 3000).unwrap(), 8080);
+        assert!(expand_port("{port+1}", 65535).is_err());
+        assert!(expand_port("{port-3000}", 3000).is_err());
+        assert!(expand_port("{host}", 3000).is_err());
+        assert!(expand_port("abc", 3000).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_builtin_spa_fills_placeholders() {
+        let mut config = Config::new(std::env::temp_dir().join("minipx-templates-spa.json"));
+        config.add_route_from_template("App.Example.com".to_string(), "spa", 3000, RoutePatch::default()).await.unwrap();
+        let route = config.lookup_host("app.example.com").unwrap();
+        assert_eq!(route.get_port(), 3000);
+        assert_eq!(route.get_subroutes().len(), 1);
+        assert_eq!(route.get_subroutes()[0].path, "/api");
+        assert_eq!(route.get_subroutes()[0].port, 3001);
+    }
+
+    #[tokio::test]
+    async fn test_flags_override_template() {
+        let mut config = config_with_template(
+            "backend",
+            serde_json::json!({ "host": "10.0.0.5", "listen_port": "{port}", "allow_websocket": false, "subroutes": [{ "path": "ws", "port": "{port+5}", "allow_websocket": true }] }),
+        );
+        let overrides = RoutePatch { host: Some("10.0.0.9".to_string()), ssl_enable: Some(true), ..Default::default() };
+        config.add_route_from_template("example.com".to_string(), "backend", 4000, overrides).await.unwrap();
+        let route = config.lookup_host("example.com").unwrap();
+        assert_eq!(route.get_host(), "10.0.0.9");
+        assert!(route.is_ssl_enabled());
+        assert_eq!(route.get_listen_port(), Some(4000));
+        assert!(!route.is_websocket_allowed());
+        assert_eq!(route.get_subroutes()[0].path, "/ws");
+        assert_eq!(route.get_subroutes()[0].port, 4005);
+        assert_eq!(route.get_subroutes()[0].allow_websocket, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_expansion_leaves_config_untouched() {
+        // The subroute lands on the parent's port, which validation rejects
+        let mut config = config_with_template("clash", serde_json::json!({ "subroutes": [{ "path": "/api", "port": "{port}" }] }));
+        assert!(config.add_route_from_template("example.com".to_string(), "clash", 3000, RoutePatch::default()).await.is_err());
+        assert!(config.lookup_host("example.com").is_none());
+
+        // Port 443 is rejected like for any other route
+        assert!(config.add_route_from_template("example.com".to_string(), "spa", 443, RoutePatch::default()).await.is_err());
+        assert!(config.add_route_from_template("example.com".to_string(), "missing", 3000, RoutePatch::default()).await.is_err());
+    }
+
+    #[test]
+    fn test_config_templates_replace_builtins_and_skip_invalid() {
+        let json = r#"{ "templates": { "spa": { "description": "mine" }, "broken": { "subroutes": "nope" } } }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.get_templates().len(), 1);
+        assert_eq!(config.find_template("spa").unwrap().description, "mine");
+        assert!(config.find_template("websocket").is_some());
+        assert!(config.available_templates().contains_key("game-server"));
+    }
+}
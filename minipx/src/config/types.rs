@@ -1,28 +1,327 @@
+use crate::config::audit::{ConfigIssue, fallback};
+use crate::config::listeners::ListenerPorts;
+use crate::config::manager::RouteGeneration;
+use crate::config::migrations::CONFIG_VERSION;
+use crate::config::templates::{RouteTemplate, templates_or_default};
+use crate::proxy::cache_control::path_glob;
+use crate::proxy::header_limits::HeaderLimits;
+use crate::utils::cidr::IpCidr;
+use crate::utils::host::{check_wildcard_key, is_subdomain_of, normalize_host, wildcard_suffix};
 use crate::utils::path::trim_trailing_slash;
-use crate::utils::validation::validate_custom_port;
+use crate::utils::timestamp::{format_rfc3339, parse_rfc3339};
+use crate::utils::validation::{validate_custom_port, validate_upstream_name};
 use anyhow::Result;
 use log::warn;
-use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Path prefix of ACME HTTP-01 challenge requests, never redirected to HTTPS
+pub const ACME_CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(skip)]
     pub(crate) path: PathBuf,
+    // Schema version of the file (see `migrations`); files without one predate versioning
+    #[serde(default)]
+    pub(crate) version: u32,
     // Email address used for ssl certificate
     #[serde(deserialize_with = "string_or_default", default = "String::new")]
     pub(crate) email: String,
     // Directory to store cached files
     #[serde(deserialize_with = "string_or_default", default = "default_cache_dir")]
     pub(crate) cache_dir: String,
-    // Host to route to
-    #[serde(default)]
+    // Host to route to, keyed by normalized host (see `normalize_host`)
+    #[serde(deserialize_with = "normalized_routes", serialize_with = "sorted_routes", default)]
     pub(crate) routes: HashMap<String, ProxyRoute>,
+    // Built-in health endpoint answered by the proxy itself
+    #[serde(default, skip_serializing_if = "HealthEndpointConfig::is_default")]
+    pub(crate) health_endpoint: HealthEndpointConfig,
+    // Reject absolute-form requests whose URI authority disagrees with the Host header (400)
+    #[serde(deserialize_with = "bool_or_default", default = "default_true", skip_serializing_if = "is_true")]
+    pub(crate) strict_host_authority: bool,
+    // Reject requests with ambiguous framing (Content-Length plus Transfer-Encoding, differing Content-Lengths) or malformed headers (400)
+    #[serde(deserialize_with = "bool_or_default", default = "default_true", skip_serializing_if = "is_true")]
+    pub(crate) strict_http: bool,
+    // Largest request header block (each field counted as `name: value` plus CRLF) and most header fields a client may
+    // send; beyond either it gets 431. hyper refuses more than 100 fields whatever is configured. Routes can override both
+    #[serde(deserialize_with = "usize_or_default", default = "default_max_header_bytes", skip_serializing_if = "is_default_max_header_bytes")]
+    pub(crate) max_header_bytes: usize,
+    #[serde(deserialize_with = "usize_or_default", default = "default_max_header_count", skip_serializing_if = "is_default_max_header_count")]
+    pub(crate) max_header_count: usize,
+    // What happens when the headers forwarded upstream (the client's plus the X-Forwarded-* ones) exceed those limits
+    #[serde(deserialize_with = "header_limit_policy_or_default", default, skip_serializing_if = "HeaderLimitPolicy::is_default")]
+    pub(crate) upstream_header_policy: HeaderLimitPolicy,
+    // Tell loopback clients which route and backend answered, in X-Minipx-Route and X-Upstream response headers
+    // (used by `minipx routes test-request`)
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) debug_headers: bool,
+    // Tell every client which route, subroute and backend answered and what minipx decided, in X-Minipx-Route,
+    // X-Minipx-Subroute, X-Minipx-Upstream and X-Minipx-Decision response headers. Names internal backends: off by default
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) debug_routing_headers: bool,
+    // Refuse to load a file with unknown keys or values that fall back to defaults, instead of warning about them
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) strict_config: bool,
+    // Keep retrying a listen_port's UDP forwarder forever when its port cannot be bound, instead of giving up on it
+    // after a few attempts while the TCP forwarder keeps working
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) forwarder_udp_required: bool,
+    // What happens when port 80 or 443 cannot be bound: keep retrying while reporting the frontend as unbound, or exit
+    // with an error after bind_failure_attempts tries
+    #[serde(deserialize_with = "bind_failure_policy_or_default", default, skip_serializing_if = "BindFailurePolicy::is_default")]
+    pub(crate) bind_failure_policy: BindFailurePolicy,
+    #[serde(
+        deserialize_with = "u64_or_default",
+        default = "default_bind_failure_attempts",
+        skip_serializing_if = "is_default_bind_failure_attempts"
+    )]
+    pub(crate) bind_failure_attempts: u64,
+    // How long a backend may take to send response headers (or answer a websocket handshake) before the client gets 504;
+    // 0 waits forever. Streaming the body afterwards has no time limit. Routes can override it.
+    #[serde(
+        deserialize_with = "u64_or_default",
+        default = "default_response_header_timeout_ms",
+        skip_serializing_if = "is_default_response_header_timeout"
+    )]
+    pub(crate) response_header_timeout_ms: u64,
+    // Console/journal logging of proxied requests: a line each, a summary line per route every
+    // request_log_interval_secs, or nothing. 5xx responses are logged one by one in every mode.
+    #[serde(deserialize_with = "request_log_mode_or_default", default, skip_serializing_if = "RequestLogMode::is_default")]
+    pub(crate) request_log_mode: RequestLogMode,
+    #[serde(
+        deserialize_with = "u64_or_default",
+        default = "default_request_log_interval_secs",
+        skip_serializing_if = "is_default_request_log_interval"
+    )]
+    pub(crate) request_log_interval_secs: u64,
+    // After a warm restart handed the listeners to a new process, how long the old one waits for its in-flight
+    // requests and websocket tunnels to finish before exiting anyway
+    #[serde(deserialize_with = "u64_or_default", default = "default_drain_timeout_secs", skip_serializing_if = "is_default_drain_timeout")]
+    pub(crate) drain_timeout_secs: u64,
+    // Peers (e.g. a load balancer) whose X-Forwarded-For is trusted to carry the real client IP
+    #[serde(deserialize_with = "cidr_list_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) trusted_proxies: Vec<IpCidr>,
+    // Request path globs served over plain HTTP even by routes that redirect to HTTPS (ACME HTTP-01 challenges and
+    // the health endpoint always are); routes can add their own
+    #[serde(deserialize_with = "string_vec_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) redirect_exempt_paths: Vec<String>,
+    // Loopback-only admin REST API (requires the `admin-api` feature)
+    #[serde(default, skip_serializing_if = "AdminApiConfig::is_default")]
+    pub(crate) admin_api: AdminApiConfig,
+    // What HTTPS clients get when their SNI is missing or matches no route
+    #[serde(deserialize_with = "tls_fallback_or_default", default, skip_serializing_if = "TlsFallback::is_default")]
+    pub(crate) tls_fallback: TlsFallback,
+    // PEM certificate served by `tls_fallback: "default_cert"`; a self-signed placeholder when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tls_fallback_cert: Option<TlsCertFiles>,
+    // What requests for a Host that matches no route get, how many a client may send and how often they are logged
+    #[serde(default, skip_serializing_if = "UnknownHostConfig::is_default")]
+    pub(crate) unknown_host: UnknownHostConfig,
+    // How backend host names are resolved for proxied requests, websockets and forwarders
+    #[serde(default, skip_serializing_if = "ResolverConfig::is_default")]
+    pub(crate) resolver: ResolverConfig,
+    // Thread counts of the proxy's runtime and the web panel; unset ones follow the machine's cores. Read at startup only
+    #[serde(default, skip_serializing_if = "RuntimeConfig::is_default")]
+    pub(crate) runtime: RuntimeConfig,
+    // URL POSTed a JSON event when a certificate renewal fails or a certificate nears expiry
+    #[serde(deserialize_with = "string_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) alert_webhook: Option<String>,
+    // URL answering with this machine's public IP in plain text; lets preflight check where ACME domains point
+    #[serde(deserialize_with = "string_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) public_ip_check_url: Option<String>,
+    // Named route shapes for `routes add --template`, in addition to the built-in ones
+    #[serde(deserialize_with = "templates_or_default", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) templates: BTreeMap<String, RouteTemplate>,
+    // Route generations of this snapshot, assigned when the config becomes the global one
+    #[serde(skip)]
+    pub(crate) generations: Arc<HashMap<String, Arc<RouteGeneration>>>,
+    // Bumped each time a config becomes the global one (startup is 1); 0 for snapshots never applied
+    #[serde(skip)]
+    pub(crate) generation: u64,
+    // Ignored keys and defaulted values found when the file was read
+    #[serde(skip)]
+    pub(crate) issues: Vec<ConfigIssue>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthEndpointConfig {
+    #[serde(deserialize_with = "bool_or_default", default = "default_true")]
+    pub(crate) enabled: bool,
+
+    #[serde(deserialize_with = "string_or_default", default = "default_health_path")]
+    pub(crate) path: String,
+
+    // When set, the endpoint is only served on this dedicated port instead of the frontends
+    #[serde(deserialize_with = "u16_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) port: Option<u16>,
+
+    // Log health probes at info like regular requests (debug otherwise)
+    #[serde(deserialize_with = "bool_or_default", default)]
+    pub(crate) log_requests: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminApiConfig {
+    #[serde(deserialize_with = "bool_or_default", default)]
+    pub(crate) enabled: bool,
+
+    // Always bound on 127.0.0.1
+    #[serde(deserialize_with = "u16_or_default", default = "default_admin_api_port")]
+    pub(crate) port: u16,
+
+    // Bearer token required on every request; the API refuses to start without one
+    #[serde(deserialize_with = "string_or_default", default)]
+    pub(crate) token: String,
+}
+
+/// Handling of TLS handshakes whose SNI is missing or names no route
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsFallback {
+    /// Fail the handshake (logged with the client IP and requested name)
+    #[default]
+    Reject,
+    /// Serve a placeholder certificate; the request then gets the unknown-host 404
+    DefaultCert,
+    /// Serve the given route's certificate and send requests for unknown hosts to it
+    Route(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnknownHostConfig {
+    #[serde(deserialize_with = "unknown_host_action_or_default", default)]
+    pub(crate) action: UnknownHostAction,
+
+    // Unknown-host and malformed-Host requests a client IP may send in a burst; past it the connection is closed
+    // without a response. 0 turns the limit off
+    #[serde(deserialize_with = "u64_or_default", default = "default_unknown_host_burst")]
+    pub(crate) rate_limit_burst: u64,
+
+    // How many of those requests a client IP earns back per minute
+    #[serde(deserialize_with = "u64_or_default", default = "default_unknown_host_per_minute")]
+    pub(crate) rate_limit_per_minute: u64,
+
+    // At most one line per client IP per interval is logged; the rest are counted into a summary line
+    #[serde(deserialize_with = "u64_or_default", default = "default_unknown_host_log_interval")]
+    pub(crate) log_interval_secs: u64,
+}
+
+/// Response to a request whose Host matches no route
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownHostAction {
+    /// A plain `404 Not Found`
+    #[default]
+    NotFound,
+    /// Close the connection without sending a response
+    Close,
+    /// A `302 Found` to the given URL
+    Redirect(String),
+}
+
+/// What the HTTP/HTTPS frontends do when their port cannot be bound
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BindFailurePolicy {
+    /// Keep retrying; the frontend is reported as unbound (status, metrics, systemd STATUS) meanwhile
+    #[default]
+    Retry,
+    /// Exit with an error naming the port after `bind_failure_attempts` failed attempts
+    Fail,
+}
+
+/// What is forwarded when the headers sent upstream exceed the route's header limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderLimitPolicy {
+    /// Nothing: the client gets 431
+    #[default]
+    Reject,
+    /// The request, with the client's headers dropped (last first) until it fits; headers minipx sets are kept
+    Truncate,
+}
+
+/// What a route answers outside its activation window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InactiveResponse {
+    /// 404, as if the route did not exist
+    #[default]
+    NotFound,
+    /// 503 maintenance page, with Retry-After when the route has a later start
+    Maintenance,
+}
+
+/// How proxied requests are logged to the console/journal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestLogMode {
+    /// One info line per request
+    #[default]
+    PerRequest,
+    /// Counts, status classes and latency percentiles per route, one info line per route per interval
+    Summary,
+    /// No request lines; 5xx responses and proxy failures are still logged
+    Off,
+}
+
+/// Name resolution for backend hosts. `static_hosts` answer first in every mode; in `custom` mode the remaining names
+/// are queried from `servers` (skipping /etc/hosts), which needs the `dns-resolver` feature.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResolverConfig {
+    #[serde(deserialize_with = "resolver_mode_or_default", default, skip_serializing_if = "ResolverMode::is_default")]
+    pub(crate) mode: ResolverMode,
+
+    // DNS servers for `custom` mode, e.g. "127.0.0.1:8600"; port 53 when left out
+    #[serde(deserialize_with = "dns_servers_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) servers: Vec<SocketAddr>,
+
+    // Names pinned to an address, keyed lowercase
+    #[serde(deserialize_with = "static_hosts_or_default", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) static_hosts: BTreeMap<String, IpAddr>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolverMode {
+    /// The operating system's resolver (/etc/hosts, then /etc/resolv.conf)
+    #[default]
+    System,
+    /// Only the configured `servers`
+    Custom,
+}
+
+/// Thread counts for the proxy's tokio runtime and the web panel's actix server (see `runtime_tuning`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeConfig {
+    // Threads running the proxy's requests and connections
+    #[serde(deserialize_with = "usize_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) worker_threads: Option<usize>,
+
+    // Most threads the proxy starts for blocking work (file reads, system DNS lookups)
+    #[serde(deserialize_with = "usize_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_blocking_threads: Option<usize>,
+
+    // Actix workers of the web panel; MINIPX_WEB_WORKERS takes precedence
+    #[serde(deserialize_with = "usize_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) web_workers: Option<usize>,
+}
+
+/// PEM files for a certificate chain and its private key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsCertFiles {
+    pub cert: String,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProxyRoute {
     #[serde(deserialize_with = "string_or_default", default = "default_host")]
     pub(crate) host: String,
@@ -44,18 +343,221 @@ pub struct ProxyRoute {
 
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub(crate) subroutes: Vec<ProxyPathRoute>,
+
+    // Upper bound on concurrent requests (and websocket tunnels) proxied to this route
+    #[serde(deserialize_with = "usize_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_concurrent_requests: Option<usize>,
+
+    // Requests allowed to wait for a slot once max_concurrent_requests is reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) queue: Option<RouteQueue>,
+
+    // Aggregate cap on bytes sent to clients (response bodies and forwarded TCP), in kilobits per second
+    #[serde(deserialize_with = "u64_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) bandwidth_limit_kbps: Option<u64>,
+
+    // Point 3xx Locations that name the backend (host:port) at the public address instead
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) rewrite_redirects: bool,
+
+    // Replace Set-Cookie `Domain` attributes that name the backend host with the public domain
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) rewrite_cookie_domain: bool,
+
+    // Wildcard routes only: also serve the bare domain (`*.example.com` matches `example.com`)
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) match_apex: bool,
+
+    // Accept websocket upgrades for this route (subroutes may override)
+    #[serde(deserialize_with = "bool_or_default", default = "default_true", skip_serializing_if = "is_true")]
+    pub(crate) allow_websocket: bool,
+
+    // Origins allowed to open websockets (e.g. "https://app.example.com"); empty allows any
+    #[serde(deserialize_with = "string_vec_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) ws_allowed_origins: Vec<String>,
+
+    // Subprotocols a websocket may negotiate (e.g. "graphql-ws"); empty allows any
+    #[serde(deserialize_with = "string_vec_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) ws_allowed_protocols: Vec<String>,
+
+    // Close websocket tunnels after this many seconds without traffic in either direction; unset or 0 never does
+    #[serde(deserialize_with = "u64_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ws_idle_timeout_secs: Option<u64>,
+
+    // Forward the client's Host header (true) or the backend's host:port (false).
+    // Unset keeps the historical behavior: preserved for HTTP, rewritten for websocket handshakes.
+    #[serde(deserialize_with = "bool_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) preserve_host: Option<bool>,
+
+    // Host header sent to the backend instead of the client's (or the backend's host:port); the connection still
+    // goes to host:port. Beats preserve_host, for the route and its subroutes.
+    #[serde(deserialize_with = "string_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) upstream_host_header: Option<String>,
+
+    // TLS server name for an https backend instead of `host`. Backends are reached over plain HTTP for now, so it is
+    // validated and stored but not used yet.
+    #[serde(deserialize_with = "string_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) upstream_sni: Option<String>,
+
+    // Debug-only capture of request/response bodies to disk; never enabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) debug_capture: Option<DebugCaptureConfig>,
+
+    // Requests matching no subroute go to the route's backend; when false (and `path` is empty) the proxy answers 404
+    #[serde(deserialize_with = "bool_or_default", default = "default_true", skip_serializing_if = "is_true")]
+    pub(crate) not_found_passthrough: bool,
+
+    // Send the client's negotiated TLS version, cipher and SNI upstream as X-TLS-Version, X-TLS-Cipher and X-TLS-SNI
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) forward_tls_info: bool,
+
+    // Overrides the global response_header_timeout_ms for this route (0 waits forever)
+    #[serde(deserialize_with = "timeout_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) response_header_timeout_ms: Option<u64>,
+
+    // Cache-Control values added to responses by Content-Type or request path; the first matching rule applies
+    #[serde(deserialize_with = "cache_control_rules_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) cache_control: Vec<CacheControlRule>,
+
+    // Allow a backend that is one of minipx's own listeners on this machine (e.g. the web panel on localhost:6671)
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) allow_self_reference: bool,
+
+    // RFC 3339 bounds of the time the route is served (from inclusive, until exclusive); outside them it answers
+    // with inactive_response. Unset bounds leave that side open.
+    #[serde(
+        deserialize_with = "timestamp_option_or_default",
+        serialize_with = "serialize_timestamp_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) active_from: Option<OffsetDateTime>,
+
+    #[serde(
+        deserialize_with = "timestamp_option_or_default",
+        serialize_with = "serialize_timestamp_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) active_until: Option<OffsetDateTime>,
+
+    #[serde(deserialize_with = "inactive_response_or_default", default, skip_serializing_if = "InactiveResponse::is_default")]
+    pub(crate) inactive_response: InactiveResponse,
+
+    // Backend listening on a unix domain socket instead of host:port (unix only); subroutes keep using their ports
+    #[serde(deserialize_with = "path_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) unix_socket: Option<PathBuf>,
+
+    // Request path globs served over plain HTTP despite redirect_to_https, in addition to the global ones
+    #[serde(deserialize_with = "string_vec_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) redirect_exempt_paths: Vec<String>,
+
+    // Override the global max_header_bytes / max_header_count for this route
+    #[serde(deserialize_with = "usize_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_header_bytes: Option<usize>,
+    #[serde(deserialize_with = "usize_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_header_count: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteQueue {
+    #[serde(deserialize_with = "usize_or_default", default)]
+    pub(crate) size: usize,
+
+    #[serde(deserialize_with = "u64_or_default", default = "default_queue_timeout_ms")]
+    pub(crate) timeout_ms: u64,
+}
+
+/// Most bytes of each body a capture may keep (they are buffered until the file is written)
+pub const MAX_CAPTURE_BYTES: usize = 64 * 1024 * 1024;
+/// Longest a capture may run before it turns itself off
+pub const MAX_CAPTURE_DURATION_SECS: u64 = 7 * 24 * 60 * 60;
+/// Largest disk cap a route's captures may have
+pub const MAX_CAPTURE_TOTAL_BYTES: u64 = 1024 * 1024 * 1024 * 1024;
+
+/// Tee the first bytes of each request and response body (with redacted headers) into files for debugging
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugCaptureConfig {
+    #[serde(deserialize_with = "bool_or_default", default)]
+    pub(crate) enabled: bool,
+
+    // Bytes of each body written to the capture file; the rest is streamed through untouched
+    #[serde(deserialize_with = "capture_max_bytes_or_default", default = "default_capture_max_bytes")]
+    pub(crate) max_bytes: usize,
+
+    #[serde(deserialize_with = "string_or_default", default = "default_capture_dir")]
+    pub(crate) dir: String,
+
+    // Capturing turns itself off this long after it was enabled
+    #[serde(deserialize_with = "capture_duration_secs_or_default", default = "default_capture_duration_secs")]
+    pub(crate) duration_secs: u64,
+
+    // Hard cap on the size of everything captured for the route, including files from earlier sessions
+    #[serde(deserialize_with = "capture_max_total_bytes_or_default", default = "default_capture_max_total_bytes")]
+    pub(crate) max_total_bytes: u64,
+}
+
+/// A `Cache-Control` value added to the responses a route's backend sends without one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheControlRule {
+    // Response media types, `*` matching anything (e.g. "image/*"); parameters such as charset are ignored
+    #[serde(deserialize_with = "string_vec_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) content_types: Vec<String>,
+
+    // Request path globs: `*` stays within a segment, `**` spans segments (e.g. "/static/**")
+    #[serde(deserialize_with = "string_vec_or_default", default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) paths: Vec<String>,
+
+    // The Cache-Control header value, e.g. "public, max-age=86400"; rules without one are dropped
+    #[serde(deserialize_with = "header_value_or_default", default)]
+    pub(crate) value: String,
+
+    // Replace a Cache-Control (and Expires) the backend set instead of leaving the response alone
+    #[serde(rename = "override", deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) override_existing: bool,
+
+    // Also send Expires, max-age seconds from now, for HTTP/1.0 clients and caches
+    #[serde(deserialize_with = "bool_or_default", default, skip_serializing_if = "is_false")]
+    pub(crate) expires: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProxyPathRoute {
     #[serde(deserialize_with = "string_or_default", default = "default_path")]
-    pub path: String,
+    pub(crate) path: String,
 
     #[serde(deserialize_with = "u16_or_default", default = "default_port")]
-    pub port: u16,
+    pub(crate) port: u16,
+
+    // Overrides of the parent route's settings; None inherits from the parent
+    #[serde(deserialize_with = "bool_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) allow_websocket: Option<bool>,
+
+    #[serde(deserialize_with = "bool_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) redirect_to_https: Option<bool>,
+
+    #[serde(deserialize_with = "bool_option_or_default", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) preserve_host: Option<bool>,
+}
+
+/// Optional per-subroute overrides of the parent route's behavior
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SubrouteOverrides {
+    pub allow_websocket: Option<bool>,
+    pub redirect_to_https: Option<bool>,
+    pub preserve_host: Option<bool>,
+}
+
+/// Partial update of an existing subroute; only provided fields are applied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SubroutePatch {
+    pub port: Option<u16>,
+    pub allow_websocket: Option<bool>,
+    pub redirect_to_https: Option<bool>,
+    pub preserve_host: Option<bool>,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct RoutePatch {
     pub host: Option<String>,
     pub path: Option<String>,
@@ -63,6 +565,20 @@ pub struct RoutePatch {
     pub ssl_enable: Option<bool>,
     pub redirect_to_https: Option<bool>,
     pub listen_port: Option<u16>,
+    pub allow_websocket: Option<bool>,
+    pub preserve_host: Option<bool>,
+    pub match_apex: Option<bool>,
+    /// An empty string removes the override
+    pub upstream_host_header: Option<String>,
+    /// An empty string removes the override
+    pub upstream_sni: Option<String>,
+    pub allow_self_reference: Option<bool>,
+    /// RFC 3339; an empty string removes the bound
+    pub active_from: Option<String>,
+    /// RFC 3339; an empty string removes the bound
+    pub active_until: Option<String>,
+    /// An empty string removes the socket (the backend is host:port again); setting `port` alone does the same
+    pub unix_socket: Option<String>,
 }
 
 impl Default for Config {
@@ -77,7 +593,43 @@ impl Config {
         std::fs::create_dir_all(path.parent().unwrap()).unwrap();
         let path = path.with_extension("json");
 
-        Self { path, email: String::new(), cache_dir: "./cache".to_string(), routes: HashMap::new() }
+        Self {
+            path,
+            version: CONFIG_VERSION,
+            email: String::new(),
+            cache_dir: "./cache".to_string(),
+            routes: HashMap::new(),
+            health_endpoint: HealthEndpointConfig::default(),
+            strict_host_authority: true,
+            strict_http: true,
+            max_header_bytes: default_max_header_bytes(),
+            max_header_count: default_max_header_count(),
+            upstream_header_policy: HeaderLimitPolicy::default(),
+            debug_headers: false,
+            debug_routing_headers: false,
+            strict_config: false,
+            forwarder_udp_required: false,
+            bind_failure_policy: BindFailurePolicy::default(),
+            bind_failure_attempts: default_bind_failure_attempts(),
+            response_header_timeout_ms: default_response_header_timeout_ms(),
+            request_log_mode: RequestLogMode::default(),
+            request_log_interval_secs: default_request_log_interval_secs(),
+            drain_timeout_secs: default_drain_timeout_secs(),
+            trusted_proxies: Vec::new(),
+            redirect_exempt_paths: Vec::new(),
+            admin_api: AdminApiConfig::default(),
+            tls_fallback: TlsFallback::default(),
+            tls_fallback_cert: None,
+            unknown_host: UnknownHostConfig::default(),
+            resolver: ResolverConfig::default(),
+            runtime: RuntimeConfig::default(),
+            alert_webhook: None,
+            public_ip_check_url: None,
+            templates: BTreeMap::new(),
+            generations: Arc::default(),
+            generation: 0,
+            issues: Vec::new(),
+        }
     }
 
     pub fn set_email(&mut self, email: String) {
@@ -92,6 +644,15 @@ impl Config {
         &self.cache_dir
     }
 
+    pub fn set_cache_dir(&mut self, cache_dir: String) {
+        self.cache_dir = cache_dir;
+    }
+
+    /// Schema version the config file was written with
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
     pub fn get_path(&self) -> &PathBuf {
         &self.path
     }
@@ -100,25 +661,303 @@ impl Config {
         &self.routes
     }
 
+    pub fn get_health_endpoint(&self) -> &HealthEndpointConfig {
+        &self.health_endpoint
+    }
+
+    pub fn set_health_endpoint(&mut self, health_endpoint: HealthEndpointConfig) {
+        self.health_endpoint = health_endpoint;
+    }
+
+    pub fn is_strict_host_authority(&self) -> bool {
+        self.strict_host_authority
+    }
+
+    pub fn set_strict_host_authority(&mut self, strict: bool) {
+        self.strict_host_authority = strict;
+    }
+
+    /// Global response header timeout in milliseconds; 0 means none
+    pub fn get_response_header_timeout_ms(&self) -> u64 {
+        self.response_header_timeout_ms
+    }
+
+    pub fn set_response_header_timeout_ms(&mut self, timeout_ms: u64) {
+        self.response_header_timeout_ms = timeout_ms;
+    }
+
+    pub fn get_request_log_mode(&self) -> RequestLogMode {
+        self.request_log_mode
+    }
+
+    pub fn set_request_log_mode(&mut self, mode: RequestLogMode) {
+        self.request_log_mode = mode;
+    }
+
+    /// Time between summary lines in summary mode (at least a second)
+    pub fn get_request_log_interval(&self) -> Duration {
+        Duration::from_secs(self.request_log_interval_secs.max(1))
+    }
+
+    pub fn set_request_log_interval_secs(&mut self, secs: u64) {
+        self.request_log_interval_secs = secs;
+    }
+
+    /// How long a process that handed its listeners over keeps draining before it exits
+    pub fn get_drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+
+    pub fn set_drain_timeout_secs(&mut self, secs: u64) {
+        self.drain_timeout_secs = secs;
+    }
+
+    /// The response header timeout for `route`: its own setting, else the global one; `None` when disabled
+    pub fn response_header_timeout(&self, route: &ProxyRoute) -> Option<Duration> {
+        let timeout_ms = route.get_response_header_timeout_ms().unwrap_or(self.response_header_timeout_ms);
+        (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms))
+    }
+
+    pub fn is_strict_http(&self) -> bool {
+        self.strict_http
+    }
+
+    pub fn set_strict_http(&mut self, strict: bool) {
+        self.strict_http = strict;
+    }
+
+    pub fn get_max_header_bytes(&self) -> usize {
+        self.max_header_bytes
+    }
+
+    pub fn set_max_header_bytes(&mut self, max_header_bytes: usize) {
+        self.max_header_bytes = max_header_bytes;
+    }
+
+    pub fn get_max_header_count(&self) -> usize {
+        self.max_header_count
+    }
+
+    pub fn set_max_header_count(&mut self, max_header_count: usize) {
+        self.max_header_count = max_header_count;
+    }
+
+    pub fn get_upstream_header_policy(&self) -> HeaderLimitPolicy {
+        self.upstream_header_policy
+    }
+
+    pub fn set_upstream_header_policy(&mut self, policy: HeaderLimitPolicy) {
+        self.upstream_header_policy = policy;
+    }
+
+    pub fn is_debug_headers(&self) -> bool {
+        self.debug_headers
+    }
+
+    pub fn set_debug_headers(&mut self, debug_headers: bool) {
+        self.debug_headers = debug_headers;
+    }
+
+    pub fn is_debug_routing_headers(&self) -> bool {
+        self.debug_routing_headers
+    }
+
+    pub fn set_debug_routing_headers(&mut self, debug_routing_headers: bool) {
+        self.debug_routing_headers = debug_routing_headers;
+    }
+
+    /// The header limits for requests to `route`: its overrides, else the global ones
+    pub fn header_limits(&self, route: &ProxyRoute) -> HeaderLimits {
+        HeaderLimits {
+            bytes: route.get_max_header_bytes().unwrap_or(self.max_header_bytes),
+            count: route.get_max_header_count().unwrap_or(self.max_header_count),
+        }
+    }
+
+    /// The largest header block any route accepts, which is what a connection must be able to read before the
+    /// request's route is known
+    pub fn largest_header_limit(&self) -> usize {
+        self.routes.values().filter_map(ProxyRoute::get_max_header_bytes).fold(self.max_header_bytes, usize::max)
+    }
+
+    pub fn is_strict_config(&self) -> bool {
+        self.strict_config
+    }
+
+    pub fn set_strict_config(&mut self, strict: bool) {
+        self.strict_config = strict;
+    }
+
+    pub fn is_forwarder_udp_required(&self) -> bool {
+        self.forwarder_udp_required
+    }
+
+    pub fn set_forwarder_udp_required(&mut self, required: bool) {
+        self.forwarder_udp_required = required;
+    }
+
+    pub fn get_bind_failure_policy(&self) -> BindFailurePolicy {
+        self.bind_failure_policy
+    }
+
+    pub fn set_bind_failure_policy(&mut self, policy: BindFailurePolicy) {
+        self.bind_failure_policy = policy;
+    }
+
+    /// Failed binds of a frontend port before `BindFailurePolicy::Fail` gives up; never less than 1
+    pub fn get_bind_failure_attempts(&self) -> u64 {
+        self.bind_failure_attempts.max(1)
+    }
+
+    pub fn set_bind_failure_attempts(&mut self, attempts: u64) {
+        self.bind_failure_attempts = attempts;
+    }
+
+    /// Unknown keys and values that fell back to defaults when the file was read
+    pub fn get_issues(&self) -> &[ConfigIssue] {
+        &self.issues
+    }
+
+    pub fn get_trusted_proxies(&self) -> &[IpCidr] {
+        &self.trusted_proxies
+    }
+
+    pub fn set_trusted_proxies(&mut self, trusted_proxies: Vec<IpCidr>) {
+        self.trusted_proxies = trusted_proxies;
+    }
+
+    pub fn get_redirect_exempt_paths(&self) -> &[String] {
+        &self.redirect_exempt_paths
+    }
+
+    pub fn set_redirect_exempt_paths(&mut self, paths: Vec<String>) {
+        self.redirect_exempt_paths = paths;
+    }
+
+    /// True if a plain HTTP request for `path` is served by `route` even when it redirects to HTTPS: ACME HTTP-01
+    /// challenges, the health endpoint's path, and the global and route `redirect_exempt_paths`
+    pub fn is_redirect_exempt(&self, route: &ProxyRoute, path: &str) -> bool {
+        path.starts_with(ACME_CHALLENGE_PREFIX)
+            || (self.health_endpoint.enabled && self.health_endpoint.matches(path))
+            || self.redirect_exempt_paths.iter().chain(&route.redirect_exempt_paths).any(|pattern| path_glob(pattern, path))
+    }
+
+    pub fn get_admin_api(&self) -> &AdminApiConfig {
+        &self.admin_api
+    }
+
+    pub fn set_admin_api(&mut self, admin_api: AdminApiConfig) {
+        self.admin_api = admin_api;
+    }
+
+    pub fn get_tls_fallback(&self) -> &TlsFallback {
+        &self.tls_fallback
+    }
+
+    pub fn set_tls_fallback(&mut self, tls_fallback: TlsFallback) {
+        self.tls_fallback = tls_fallback;
+    }
+
+    pub fn get_tls_fallback_cert(&self) -> Option<&TlsCertFiles> {
+        self.tls_fallback_cert.as_ref()
+    }
+
+    pub fn set_tls_fallback_cert(&mut self, tls_fallback_cert: Option<TlsCertFiles>) {
+        self.tls_fallback_cert = tls_fallback_cert;
+    }
+
+    pub fn get_unknown_host(&self) -> &UnknownHostConfig {
+        &self.unknown_host
+    }
+
+    pub fn set_unknown_host(&mut self, unknown_host: UnknownHostConfig) {
+        self.unknown_host = unknown_host;
+    }
+
+    pub fn get_resolver(&self) -> &ResolverConfig {
+        &self.resolver
+    }
+
+    pub fn set_resolver(&mut self, resolver: ResolverConfig) {
+        self.resolver = resolver;
+    }
+
+    pub fn get_runtime(&self) -> &RuntimeConfig {
+        &self.runtime
+    }
+
+    pub fn set_runtime(&mut self, runtime: RuntimeConfig) {
+        self.runtime = runtime;
+    }
+
+    pub fn get_alert_webhook(&self) -> Option<&str> {
+        self.alert_webhook.as_deref()
+    }
+
+    pub fn set_alert_webhook(&mut self, alert_webhook: Option<String>) {
+        self.alert_webhook = alert_webhook.filter(|url| !url.trim().is_empty());
+    }
+
+    pub fn get_public_ip_check_url(&self) -> Option<&str> {
+        self.public_ip_check_url.as_deref()
+    }
+
+    pub fn set_public_ip_check_url(&mut self, public_ip_check_url: Option<String>) {
+        self.public_ip_check_url = public_ip_check_url.filter(|url| !url.trim().is_empty());
+    }
+
     pub fn lookup_host(&self, key: impl AsRef<str>) -> Option<&ProxyRoute> {
-        let host = key.as_ref();
-        if let Some(route) = self.routes.get(host) {
-            return Some(route);
+        self.lookup_route(key).map(|(_, route)| route)
+    }
+
+    /// Like `lookup_host`, but also returns the matched route key (e.g. `*.example.com` for a wildcard match).
+    /// An exact key wins; otherwise the most specific wildcard covering the host does.
+    pub fn lookup_route(&self, key: impl AsRef<str>) -> Option<(&String, &ProxyRoute)> {
+        let host = normalize_host(key.as_ref());
+        let host = host.as_ref();
+        if let Some(entry) = self.routes.get_key_value(host) {
+            return Some(entry);
         }
-        self.routes.iter().find(|(k, _)| k.starts_with("*.") && host.ends_with(&k[1..])).map(|(_, v)| v)
+        self.routes
+            .iter()
+            .filter_map(|(k, route)| wildcard_suffix(k).map(|suffix| (suffix, (k, route))))
+            .filter(|(suffix, (_, route))| is_subdomain_of(host, suffix) || (route.match_apex && host == *suffix))
+            .max_by_key(|(suffix, _)| suffix.len())
+            .map(|(_, entry)| entry)
+    }
+
+    /// Wildcard route keys in the order `lookup_route` prefers them: most specific (longest suffix) first.
+    /// A host is served by the first key in this list that covers it.
+    pub fn wildcard_order(&self) -> Vec<&String> {
+        let mut keys: Vec<&String> = self.routes.keys().filter(|k| wildcard_suffix(k).is_some()).collect();
+        keys.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        keys
     }
 
     pub async fn add_route(&mut self, domain: String, route: impl Into<ProxyRoute>) -> Result<()> {
         use log::{info, warn};
 
+        let domain = normalize_host(&domain).into_owned();
         let mut route = route.into();
         info!("Adding route: {} -> {}:{}{}", domain, route.host, route.port, route.path);
         if self.routes.contains_key(&domain) {
             return Err(anyhow::anyhow!("Route already exists: {}", domain));
         }
-        if let Err(err) = validate_custom_port(route.port) {
+        check_wildcard_key(&domain).map_err(|err| anyhow::anyhow!(err))?;
+        if route.match_apex && wildcard_suffix(&domain).is_none() {
+            return Err(anyhow::anyhow!("match_apex only applies to wildcard routes (e.g. '*.example.com'): {}", domain));
+        }
+        // Before the port check, so a loop back to port 80/443 is explained as such
+        self.check_self_reference(&domain, &route, &ListenerPorts::for_config(self))?;
+        route.check_unix_socket()?;
+        if route.unix_socket.is_none()
+            && let Err(err) = validate_custom_port(route.port)
+        {
             return Err(anyhow::anyhow!(err));
         }
+        route.check_upstream_names()?;
+        route.check_subroutes()?;
+        route.check_activation_window()?;
         if route.path.ends_with('/') {
             route.path = trim_trailing_slash(route.path);
             warn!("Path should not end with '/', will be stripped: {}", route.path);
@@ -131,7 +970,7 @@ impl Config {
         use log::{info, warn};
 
         info!("Removing route: {}", host.as_ref());
-        if self.routes.remove(host.as_ref()).is_none() {
+        if self.routes.remove(normalize_host(host.as_ref()).as_ref()).is_none() {
             warn!("Route not found: {}", host.as_ref());
         }
         Ok(())
@@ -141,8 +980,40 @@ impl Config {
     pub async fn update_route(&mut self, domain: &str, patch: RoutePatch) -> Result<()> {
         use log::warn;
 
-        let route = self.routes.get_mut(domain).ok_or_else(|| anyhow::anyhow!(format!("Route not found: {}", domain)))?;
+        let key = normalize_host(domain);
+        let current = self.routes.get(key.as_ref()).ok_or_else(|| anyhow::anyhow!(format!("Route not found: {}", domain)))?;
+        // Check the backend the route would have after the update
+        let mut updated = current.clone();
+        updated.host = patch.host.clone().unwrap_or(updated.host);
+        updated.port = patch.port.unwrap_or(updated.port);
+        updated.listen_port = patch.listen_port.map_or(updated.listen_port, |lp| Some(lp).filter(|&lp| lp != 0));
+        updated.allow_self_reference = patch.allow_self_reference.unwrap_or(updated.allow_self_reference);
+        let active_from = patch.active_from.as_deref().map(window_bound).transpose()?;
+        let active_until = patch.active_until.as_deref().map(window_bound).transpose()?;
+        updated.active_from = active_from.unwrap_or(updated.active_from);
+        updated.active_until = active_until.unwrap_or(updated.active_until);
+        updated.check_activation_window()?;
+        updated.unix_socket = match (&patch.unix_socket, patch.port) {
+            (Some(socket), _) => Some(PathBuf::from(socket.trim())).filter(|socket| !socket.as_os_str().is_empty()),
+            (None, Some(_)) => None,
+            (None, None) => updated.unix_socket,
+        };
+        updated.check_unix_socket()?;
+        if updated.unix_socket.is_none() && current.unix_socket.is_some() && patch.port.is_none() {
+            return Err(anyhow::anyhow!("Removing unix_socket needs a backend port (--port) for {}", domain));
+        }
+        self.check_self_reference(&key, &updated, &ListenerPorts::for_config_except(self, &key))?;
+        let route = self.routes.get_mut(key.as_ref()).expect("route exists");
 
+        if patch.match_apex == Some(true) && wildcard_suffix(&key).is_none() {
+            return Err(anyhow::anyhow!("match_apex only applies to wildcard routes (e.g. '*.example.com'): {}", domain));
+        }
+        if let Some(host) = patch.upstream_host_header.as_deref().filter(|host| !host.is_empty()) {
+            validate_upstream_name(host, true).map_err(|err| anyhow::anyhow!("upstream_host_header: {}", err))?;
+        }
+        if let Some(sni) = patch.upstream_sni.as_deref().filter(|sni| !sni.is_empty()) {
+            validate_upstream_name(sni, false).map_err(|err| anyhow::anyhow!("upstream_sni: {}", err))?;
+        }
         if let Some(host) = patch.host {
             route.host = host;
         }
@@ -167,6 +1038,15 @@ impl Config {
         if let Some(redir) = patch.redirect_to_https {
             route.redirect_to_https = redir;
         }
+        if let Some(allow) = patch.allow_websocket {
+            route.allow_websocket = allow;
+        }
+        if let Some(preserve) = patch.preserve_host {
+            route.preserve_host = Some(preserve);
+        }
+        if let Some(match_apex) = patch.match_apex {
+            route.match_apex = match_apex;
+        }
         if let Some(lp) = patch.listen_port {
             // Treat 0 as "unset"
             if lp == 0 {
@@ -175,148 +1055,1465 @@ impl Config {
                 route.listen_port = Some(lp);
             }
         }
+        if let Some(host) = patch.upstream_host_header {
+            route.upstream_host_header = Some(host).filter(|host| !host.is_empty());
+        }
+        if let Some(sni) = patch.upstream_sni {
+            route.upstream_sni = Some(sni).filter(|sni| !sni.is_empty());
+        }
+        if let Some(allow) = patch.allow_self_reference {
+            route.allow_self_reference = allow;
+        }
+        if let Some(active_from) = active_from {
+            route.active_from = active_from;
+        }
+        if let Some(active_until) = active_until {
+            route.active_until = active_until;
+        }
+        route.unix_socket = updated.unix_socket;
         Ok(())
     }
 
     // Add a subroute to an existing route
     pub async fn add_subroute(&mut self, domain: &str, path: String, port: u16) -> Result<()> {
-        use log::{info, warn};
+        self.add_subroute_with(domain, path, port, SubrouteOverrides::default()).await
+    }
 
-        let route = self.routes.get_mut(domain).ok_or_else(|| anyhow::anyhow!(format!("Route not found: {}", domain)))?;
+    // Add a subroute to an existing route, overriding some of the parent route's behavior
+    pub async fn add_subroute_with(&mut self, domain: &str, path: String, port: u16, overrides: SubrouteOverrides) -> Result<()> {
+        use log::info;
+
+        let key = normalize_host(domain);
+        let route = self.routes.get(key.as_ref()).ok_or_else(|| anyhow::anyhow!(format!("Route not found: {}", domain)))?;
+
+        let subroute = ProxyPathRoute::new(path, port)?.with_overrides(overrides);
+        let path = subroute.path.clone();
+        let mut updated = route.clone();
+        updated.subroutes.push(subroute);
+        updated.check_subroutes()?;
+        self.check_self_reference(&key, &updated, &ListenerPorts::for_config(self))?;
+        self.routes.insert(key.into_owned(), updated);
+        info!("Added subroute to {}: {} -> port {}", domain, path, port);
+        Ok(())
+    }
 
-        // Validate port
-        if let Err(err) = validate_custom_port(port) {
-            return Err(anyhow::anyhow!(err));
+    // Apply a partial update to an existing subroute identified by domain and path
+    pub async fn update_subroute(&mut self, domain: &str, path: &str, patch: SubroutePatch) -> Result<()> {
+        let key = normalize_host(domain);
+        let path = trim_trailing_slash(path.to_string());
+        let path = if path.starts_with('/') { path } else { format!("/{}", path) };
+        if let (Some(port), Some(route)) = (patch.port, self.routes.get(key.as_ref())) {
+            let mut updated = route.clone();
+            updated.subroutes.iter_mut().filter(|s| s.path == path).for_each(|s| s.port = port);
+            self.check_self_reference(&key, &updated, &ListenerPorts::for_config(self))?;
         }
 
-        // Check if port conflicts with parent route
-        if port == route.port {
-            return Err(anyhow::anyhow!("Subroute port cannot be the same as the parent route port: {}", port));
+        let route = self.routes.get_mut(key.as_ref()).ok_or_else(|| anyhow::anyhow!(format!("Route not found: {}", domain)))?;
+        let parent_port = route.port;
+        let subroute =
+            route.subroutes.iter_mut().find(|s| s.path == path).ok_or_else(|| anyhow::anyhow!("Subroute not found: {}{}", domain, path))?;
+
+        if let Some(port) = patch.port {
+            if let Err(err) = validate_custom_port(port) {
+                return Err(anyhow::anyhow!(err));
+            }
+            if port == parent_port {
+                return Err(anyhow::anyhow!("Subroute port cannot be the same as the parent route port: {}", port));
+            }
+            subroute.port = port;
+        }
+        if patch.allow_websocket.is_some() {
+            subroute.allow_websocket = patch.allow_websocket;
+        }
+        if patch.redirect_to_https.is_some() {
+            subroute.redirect_to_https = patch.redirect_to_https;
+        }
+        if patch.preserve_host.is_some() {
+            subroute.preserve_host = patch.preserve_host;
         }
+        Ok(())
+    }
+}
 
-        // Clean up path
-        let mut clean_path = path;
-        if clean_path.ends_with('/') {
-            clean_path = trim_trailing_slash(clean_path);
-            warn!("Path should not end with '/', will be stripped: {}", clean_path);
+impl ProxyRoute {
+    pub fn new(host: String, path: String, port: u16, ssl_enable: bool, listen_port: Option<u16>, redirect_to_https: bool) -> Self {
+        Self {
+            host,
+            path,
+            port,
+            ssl_enable,
+            listen_port,
+            redirect_to_https,
+            subroutes: Vec::new(),
+            max_concurrent_requests: None,
+            queue: None,
+            bandwidth_limit_kbps: None,
+            rewrite_redirects: false,
+            rewrite_cookie_domain: false,
+            match_apex: false,
+            allow_websocket: true,
+            ws_allowed_origins: Vec::new(),
+            ws_allowed_protocols: Vec::new(),
+            ws_idle_timeout_secs: None,
+            preserve_host: None,
+            upstream_host_header: None,
+            upstream_sni: None,
+            debug_capture: None,
+            not_found_passthrough: true,
+            forward_tls_info: false,
+            response_header_timeout_ms: None,
+            cache_control: Vec::new(),
+            allow_self_reference: false,
+            active_from: None,
+            active_until: None,
+            inactive_response: InactiveResponse::default(),
+            unix_socket: None,
+            redirect_exempt_paths: Vec::new(),
+            max_header_bytes: None,
+            max_header_count: None,
         }
-        if !clean_path.starts_with('/') {
-            clean_path = format!("/{}", clean_path);
-            info!("Path should start with '/', prepended: {}", clean_path);
+    }
+
+    pub fn get_rewrite_redirects(&self) -> bool {
+        self.rewrite_redirects
+    }
+
+    pub fn set_rewrite_redirects(&mut self, rewrite_redirects: bool) {
+        self.rewrite_redirects = rewrite_redirects;
+    }
+
+    pub fn get_rewrite_cookie_domain(&self) -> bool {
+        self.rewrite_cookie_domain
+    }
+
+    pub fn set_rewrite_cookie_domain(&mut self, rewrite_cookie_domain: bool) {
+        self.rewrite_cookie_domain = rewrite_cookie_domain;
+    }
+
+    pub fn is_match_apex(&self) -> bool {
+        self.match_apex
+    }
+
+    pub fn set_match_apex(&mut self, match_apex: bool) {
+        self.match_apex = match_apex;
+    }
+
+    pub fn is_websocket_allowed(&self) -> bool {
+        self.allow_websocket
+    }
+
+    pub fn set_allow_websocket(&mut self, allow_websocket: bool) {
+        self.allow_websocket = allow_websocket;
+    }
+
+    pub fn get_ws_allowed_origins(&self) -> &[String] {
+        &self.ws_allowed_origins
+    }
+
+    pub fn set_ws_allowed_origins(&mut self, origins: Vec<String>) {
+        self.ws_allowed_origins = origins;
+    }
+
+    pub fn get_ws_allowed_protocols(&self) -> &[String] {
+        &self.ws_allowed_protocols
+    }
+
+    pub fn set_ws_allowed_protocols(&mut self, protocols: Vec<String>) {
+        self.ws_allowed_protocols = protocols;
+    }
+
+    /// How long a websocket tunnel may go without traffic before it is closed
+    pub fn get_ws_idle_timeout(&self) -> Option<Duration> {
+        self.ws_idle_timeout_secs.filter(|&secs| secs > 0).map(Duration::from_secs)
+    }
+
+    pub fn set_ws_idle_timeout_secs(&mut self, secs: Option<u64>) {
+        self.ws_idle_timeout_secs = secs;
+    }
+
+    pub fn get_preserve_host(&self) -> Option<bool> {
+        self.preserve_host
+    }
+
+    pub fn set_preserve_host(&mut self, preserve_host: Option<bool>) {
+        self.preserve_host = preserve_host;
+    }
+
+    pub fn get_upstream_host_header(&self) -> Option<&str> {
+        self.upstream_host_header.as_deref()
+    }
+
+    pub fn set_upstream_host_header(&mut self, host: Option<String>) {
+        self.upstream_host_header = host;
+    }
+
+    pub fn get_upstream_sni(&self) -> Option<&str> {
+        self.upstream_sni.as_deref()
+    }
+
+    pub fn set_upstream_sni(&mut self, sni: Option<String>) {
+        self.upstream_sni = sni;
+    }
+
+    /// The route with `subroutes` in place of its current ones. `Config::add_route` refuses the route when a subroute
+    /// reuses the route's port or another subroute's path, like `Config::add_subroute` does.
+    ///
+    /// ```
+    /// use minipx::config::{ProxyPathRoute, ProxyRoute};
+    ///
+    /// let route = ProxyRoute::new("localhost".to_string(), String::new(), 3000, false, None, false)
+    ///     .with_subroutes(vec![ProxyPathRoute::new("/api", 3001)?, ProxyPathRoute::new("static/", 3002)?]);
+    /// let paths: Vec<&str> = route.get_subroutes().iter().map(|sub| sub.get_path()).collect();
+    /// assert_eq!(paths, ["/api", "/static"]);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn with_subroutes(mut self, subroutes: Vec<ProxyPathRoute>) -> Self {
+        self.subroutes = subroutes;
+        self
+    }
+
+    // Subroutes must not reuse the route's port or each other's paths
+    pub(crate) fn check_subroutes(&self) -> Result<()> {
+        for (i, subroute) in self.subroutes.iter().enumerate() {
+            if subroute.port == self.port {
+                return Err(anyhow::anyhow!("Subroute port cannot be the same as the parent route port: {}", subroute.port));
+            }
+            if self.subroutes[..i].iter().any(|earlier| earlier.path == subroute.path) {
+                return Err(anyhow::anyhow!("Subroute already exists for path: {}", subroute.path));
+            }
+        }
+        Ok(())
+    }
+
+    // The upstream Host and SNI overrides must be names a backend can be addressed by
+    pub(crate) fn check_upstream_names(&self) -> Result<()> {
+        if let Some(host) = &self.upstream_host_header {
+            validate_upstream_name(host, true).map_err(|err| anyhow::anyhow!("upstream_host_header: {}", err))?;
+        }
+        if let Some(sni) = &self.upstream_sni {
+            validate_upstream_name(sni, false).map_err(|err| anyhow::anyhow!("upstream_sni: {}", err))?;
+        }
+        Ok(())
+    }
+
+    pub fn get_debug_capture(&self) -> Option<&DebugCaptureConfig> {
+        self.debug_capture.as_ref()
+    }
+
+    pub fn set_debug_capture(&mut self, debug_capture: Option<DebugCaptureConfig>) {
+        self.debug_capture = debug_capture;
+    }
+
+    pub fn is_not_found_passthrough(&self) -> bool {
+        self.not_found_passthrough
+    }
+
+    pub fn set_not_found_passthrough(&mut self, not_found_passthrough: bool) {
+        self.not_found_passthrough = not_found_passthrough;
+    }
+
+    pub fn is_forward_tls_info(&self) -> bool {
+        self.forward_tls_info
+    }
+
+    pub fn set_forward_tls_info(&mut self, forward_tls_info: bool) {
+        self.forward_tls_info = forward_tls_info;
+    }
+
+    /// This route's response header timeout override in milliseconds (0 disables it); None uses the global one
+    pub fn get_response_header_timeout_ms(&self) -> Option<u64> {
+        self.response_header_timeout_ms
+    }
+
+    pub fn set_response_header_timeout_ms(&mut self, timeout_ms: Option<u64>) {
+        self.response_header_timeout_ms = timeout_ms;
+    }
+
+    pub fn get_cache_control(&self) -> &[CacheControlRule] {
+        &self.cache_control
+    }
+
+    pub fn set_cache_control(&mut self, rules: Vec<CacheControlRule>) {
+        self.cache_control = rules;
+    }
+
+    pub fn get_redirect_exempt_paths(&self) -> &[String] {
+        &self.redirect_exempt_paths
+    }
+
+    pub fn set_redirect_exempt_paths(&mut self, paths: Vec<String>) {
+        self.redirect_exempt_paths = paths;
+    }
+
+    pub fn get_max_header_bytes(&self) -> Option<usize> {
+        self.max_header_bytes
+    }
+
+    pub fn set_max_header_bytes(&mut self, max_header_bytes: Option<usize>) {
+        self.max_header_bytes = max_header_bytes;
+    }
+
+    pub fn get_max_header_count(&self) -> Option<usize> {
+        self.max_header_count
+    }
+
+    pub fn set_max_header_count(&mut self, max_header_count: Option<usize>) {
+        self.max_header_count = max_header_count;
+    }
+
+    pub fn is_allow_self_reference(&self) -> bool {
+        self.allow_self_reference
+    }
+
+    pub fn set_allow_self_reference(&mut self, allow: bool) {
+        self.allow_self_reference = allow;
+    }
+
+    pub fn get_active_from(&self) -> Option<OffsetDateTime> {
+        self.active_from
+    }
+
+    pub fn set_active_from(&mut self, active_from: Option<OffsetDateTime>) {
+        self.active_from = active_from;
+    }
+
+    pub fn get_active_until(&self) -> Option<OffsetDateTime> {
+        self.active_until
+    }
+
+    pub fn set_active_until(&mut self, active_until: Option<OffsetDateTime>) {
+        self.active_until = active_until;
+    }
+
+    pub fn get_inactive_response(&self) -> InactiveResponse {
+        self.inactive_response
+    }
+
+    pub fn set_inactive_response(&mut self, inactive_response: InactiveResponse) {
+        self.inactive_response = inactive_response;
+    }
+
+    /// True if `now` is inside the activation window; routes without one are always active
+    pub fn is_active_at(&self, now: OffsetDateTime) -> bool {
+        self.active_from.is_none_or(|from| now >= from) && self.active_until.is_none_or(|until| now < until)
+    }
+
+    /// The next time after `now` the route turns on or off
+    pub fn next_transition(&self, now: OffsetDateTime) -> Option<OffsetDateTime> {
+        [self.active_from, self.active_until].into_iter().flatten().filter(|&at| at > now).min()
+    }
+
+    /// Fail if the activation window ends before it starts
+    pub fn check_activation_window(&self) -> Result<()> {
+        if let (Some(from), Some(until)) = (self.active_from, self.active_until)
+            && until < from
+        {
+            return Err(anyhow::anyhow!("active_until ({}) is earlier than active_from ({})", format_rfc3339(until), format_rfc3339(from)));
+        }
+        Ok(())
+    }
+
+    pub fn get_unix_socket(&self) -> Option<&Path> {
+        self.unix_socket.as_deref()
+    }
+
+    pub fn set_unix_socket(&mut self, unix_socket: Option<PathBuf>) {
+        self.unix_socket = unix_socket;
+    }
+
+    /// Fail if `unix_socket` cannot be used: sockets need a unix platform and an absolute path, and forwarders
+    /// (`listen_port`) only relay to host:port
+    pub fn check_unix_socket(&self) -> Result<()> {
+        let Some(socket) = &self.unix_socket else {
+            return Ok(());
+        };
+        if cfg!(not(unix)) {
+            return Err(anyhow::anyhow!("unix_socket backends are only supported on unix platforms: {}", socket.display()));
+        }
+        if !socket.is_absolute() {
+            return Err(anyhow::anyhow!("unix_socket must be an absolute path: {}", socket.display()));
+        }
+        if self.listen_port.is_some() {
+            return Err(anyhow::anyhow!("listen_port forwards to host:port and cannot be combined with unix_socket"));
+        }
+        Ok(())
+    }
+
+    pub fn get_subroutes(&self) -> &[ProxyPathRoute] {
+        &self.subroutes
+    }
+
+    pub fn get_max_concurrent_requests(&self) -> Option<usize> {
+        self.max_concurrent_requests
+    }
+
+    pub fn set_max_concurrent_requests(&mut self, max_concurrent_requests: Option<usize>) {
+        self.max_concurrent_requests = max_concurrent_requests.filter(|&n| n > 0);
+    }
+
+    pub fn get_bandwidth_limit_kbps(&self) -> Option<u64> {
+        self.bandwidth_limit_kbps
+    }
+
+    pub fn set_bandwidth_limit_kbps(&mut self, bandwidth_limit_kbps: Option<u64>) {
+        self.bandwidth_limit_kbps = bandwidth_limit_kbps.filter(|&n| n > 0);
+    }
+
+    pub fn get_queue(&self) -> Option<&RouteQueue> {
+        self.queue.as_ref()
+    }
+
+    pub fn set_queue(&mut self, queue: Option<RouteQueue>) {
+        self.queue = queue;
+    }
+
+    pub fn is_ssl_enabled(&self) -> bool {
+        self.ssl_enable
+    }
+
+    pub fn get_redirect_to_https(&self) -> bool {
+        self.redirect_to_https
+    }
+
+    pub fn get_listen_port(&self) -> Option<u16> {
+        self.listen_port
+    }
+
+    // New getters for the host, port, and path to avoid accessing private fields from other modules
+    pub fn get_host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Default for HealthEndpointConfig {
+    fn default() -> Self {
+        Self { enabled: true, path: default_health_path(), port: None, log_requests: false }
+    }
+}
+
+impl HealthEndpointConfig {
+    pub fn new(enabled: bool, path: String, port: Option<u16>, log_requests: bool) -> Self {
+        Self { enabled, path, port, log_requests }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_port(&self) -> Option<u16> {
+        self.port
+    }
+
+    pub fn get_log_requests(&self) -> bool {
+        self.log_requests
+    }
+
+    /// True if the endpoint should be answered on the regular HTTP/HTTPS frontends
+    pub fn is_served_on_frontends(&self) -> bool {
+        self.enabled && self.port.is_none()
+    }
+
+    /// True if the request path targets the health endpoint
+    pub fn matches(&self, path: &str) -> bool {
+        !self.path.is_empty() && path == self.path
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for AdminApiConfig {
+    fn default() -> Self {
+        Self { enabled: false, port: default_admin_api_port(), token: String::new() }
+    }
+}
+
+impl AdminApiConfig {
+    pub fn new(enabled: bool, port: u16, token: String) -> Self {
+        Self { enabled, port, token }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn get_token(&self) -> &str {
+        &self.token
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl TlsFallback {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl HeaderLimitPolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl BindFailurePolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl InactiveResponse {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl RequestLogMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for UnknownHostConfig {
+    fn default() -> Self {
+        Self::new(UnknownHostAction::default())
+    }
+}
+
+impl UnknownHostConfig {
+    pub fn new(action: UnknownHostAction) -> Self {
+        Self {
+            action,
+            rate_limit_burst: default_unknown_host_burst(),
+            rate_limit_per_minute: default_unknown_host_per_minute(),
+            log_interval_secs: default_unknown_host_log_interval(),
+        }
+    }
+
+    pub fn get_action(&self) -> &UnknownHostAction {
+        &self.action
+    }
+
+    /// Requests a client IP may send in a burst; 0 when unlimited
+    pub fn get_rate_limit_burst(&self) -> u64 {
+        self.rate_limit_burst
+    }
+
+    pub fn set_rate_limit_burst(&mut self, burst: u64) {
+        self.rate_limit_burst = burst;
+    }
+
+    pub fn get_rate_limit_per_minute(&self) -> u64 {
+        self.rate_limit_per_minute
+    }
+
+    pub fn set_rate_limit_per_minute(&mut self, per_minute: u64) {
+        self.rate_limit_per_minute = per_minute;
+    }
+
+    /// How long a client IP's requests are summarized before the next log line; never less than a second
+    pub fn get_log_interval(&self) -> Duration {
+        Duration::from_secs(self.log_interval_secs.max(1))
+    }
+
+    pub fn set_log_interval_secs(&mut self, secs: u64) {
+        self.log_interval_secs = secs;
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl ProxyPathRoute {
+    /// A subroute sending requests under `path` to `port` on its route's host, inheriting the route's behavior.
+    /// The path gets a leading '/' and loses a trailing one; reserved ports (80, 443) are refused.
+    ///
+    /// ```
+    /// use minipx::config::{ProxyPathRoute, SubrouteOverrides};
+    ///
+    /// let ws = ProxyPathRoute::new("ws/", 9000)?.with_overrides(SubrouteOverrides { allow_websocket: Some(true), ..Default::default() });
+    /// assert_eq!((ws.get_path(), ws.get_port()), ("/ws", 9000));
+    /// assert!(ProxyPathRoute::new("/api", 443).is_err());
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn new(path: impl Into<String>, port: u16) -> Result<Self> {
+        use log::{info, warn};
+
+        validate_custom_port(port).map_err(|err| anyhow::anyhow!(err))?;
+        let mut path = path.into();
+        if path.ends_with('/') {
+            path = trim_trailing_slash(path);
+            warn!("Path should not end with '/', will be stripped: {}", path);
+        }
+        if !path.starts_with('/') {
+            path = format!("/{}", path);
+            info!("Path should start with '/', prepended: {}", path);
+        }
+        Ok(Self { path, port, allow_websocket: None, redirect_to_https: None, preserve_host: None })
+    }
+
+    pub fn with_overrides(mut self, overrides: SubrouteOverrides) -> Self {
+        self.allow_websocket = overrides.allow_websocket;
+        self.redirect_to_https = overrides.redirect_to_https;
+        self.preserve_host = overrides.preserve_host;
+        self
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+
+    /// The route settings this subroute overrides; None fields are inherited
+    pub fn get_overrides(&self) -> SubrouteOverrides {
+        SubrouteOverrides { allow_websocket: self.allow_websocket, redirect_to_https: self.redirect_to_https, preserve_host: self.preserve_host }
+    }
+}
+
+impl CacheControlRule {
+    /// A rule setting `Cache-Control: value`. It matches every response until narrowed down with
+    /// `with_content_types` or `with_paths`; with both, a response has to match each.
+    ///
+    /// ```
+    /// use minipx::config::CacheControlRule;
+    ///
+    /// let assets = CacheControlRule::new("public, max-age=86400")?.with_content_types(["image/*", "text/css"]).with_expires(true);
+    /// assert_eq!(assets.get_max_age(), Some(86400));
+    /// assert!(CacheControlRule::new("no-store\nX-Injected: 1").is_err());
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn new(value: impl Into<String>) -> Result<Self> {
+        let value = value.into().trim().to_string();
+        if value.is_empty() || hyper::header::HeaderValue::from_str(&value).is_err() {
+            return Err(anyhow::anyhow!("Invalid Cache-Control value: {:?}", value));
+        }
+        Ok(Self { content_types: Vec::new(), paths: Vec::new(), value, override_existing: false, expires: false })
+    }
+
+    pub fn with_content_types<S: Into<String>>(mut self, content_types: impl IntoIterator<Item = S>) -> Self {
+        self.content_types = content_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_paths<S: Into<String>>(mut self, paths: impl IntoIterator<Item = S>) -> Self {
+        self.paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_override(mut self, override_existing: bool) -> Self {
+        self.override_existing = override_existing;
+        self
+    }
+
+    pub fn with_expires(mut self, expires: bool) -> Self {
+        self.expires = expires;
+        self
+    }
+
+    pub fn get_content_types(&self) -> &[String] {
+        &self.content_types
+    }
+
+    pub fn get_paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn is_override(&self) -> bool {
+        self.override_existing
+    }
+
+    pub fn is_expires(&self) -> bool {
+        self.expires
+    }
+
+    /// The `max-age` directive of the value, in seconds
+    pub fn get_max_age(&self) -> Option<u64> {
+        self.value.split(',').find_map(|directive| {
+            let (name, seconds) = directive.split_once('=')?;
+            name.trim().eq_ignore_ascii_case("max-age").then(|| seconds.trim().trim_matches('"').parse().ok()).flatten()
+        })
+    }
+}
+
+impl ResolverConfig {
+    pub fn new(mode: ResolverMode, servers: Vec<SocketAddr>, static_hosts: BTreeMap<String, IpAddr>) -> Self {
+        let static_hosts = static_hosts.into_iter().map(|(name, ip)| (name.to_ascii_lowercase(), ip)).collect();
+        Self { mode, servers, static_hosts }
+    }
+
+    pub fn get_mode(&self) -> ResolverMode {
+        self.mode
+    }
+
+    pub fn get_servers(&self) -> &[SocketAddr] {
+        &self.servers
+    }
+
+    pub fn get_static_hosts(&self) -> &BTreeMap<String, IpAddr> {
+        &self.static_hosts
+    }
+
+    /// The pinned address of `name`, if any (names are case-insensitive)
+    pub fn static_host(&self, name: &str) -> Option<IpAddr> {
+        self.static_hosts.get(name.trim_end_matches('.').to_ascii_lowercase().as_str()).copied()
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl RuntimeConfig {
+    pub fn new(worker_threads: Option<usize>, max_blocking_threads: Option<usize>, web_workers: Option<usize>) -> Self {
+        let positive = |n: Option<usize>| n.filter(|&n| n > 0);
+        Self { worker_threads: positive(worker_threads), max_blocking_threads: positive(max_blocking_threads), web_workers: positive(web_workers) }
+    }
+
+    pub fn get_worker_threads(&self) -> Option<usize> {
+        self.worker_threads
+    }
+
+    pub fn set_worker_threads(&mut self, worker_threads: Option<usize>) {
+        self.worker_threads = worker_threads.filter(|&n| n > 0);
+    }
+
+    pub fn get_max_blocking_threads(&self) -> Option<usize> {
+        self.max_blocking_threads
+    }
+
+    pub fn set_max_blocking_threads(&mut self, max_blocking_threads: Option<usize>) {
+        self.max_blocking_threads = max_blocking_threads.filter(|&n| n > 0);
+    }
+
+    pub fn get_web_workers(&self) -> Option<usize> {
+        self.web_workers
+    }
+
+    pub fn set_web_workers(&mut self, web_workers: Option<usize>) {
+        self.web_workers = web_workers.filter(|&n| n > 0);
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl ResolverMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl RouteQueue {
+    pub fn new(size: usize, timeout_ms: u64) -> Self {
+        Self { size, timeout_ms }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_timeout_ms(&self) -> u64 {
+        self.timeout_ms
+    }
+}
+
+impl Default for DebugCaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: default_capture_max_bytes(),
+            dir: default_capture_dir(),
+            duration_secs: default_capture_duration_secs(),
+            max_total_bytes: default_capture_max_total_bytes(),
+        }
+    }
+}
+
+impl DebugCaptureConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn get_max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+    }
+
+    pub fn get_dir(&self) -> &str {
+        &self.dir
+    }
+
+    pub fn set_dir(&mut self, dir: impl Into<String>) {
+        self.dir = dir.into();
+    }
+
+    pub fn get_duration_secs(&self) -> u64 {
+        self.duration_secs
+    }
+
+    pub fn set_duration_secs(&mut self, duration_secs: u64) {
+        self.duration_secs = duration_secs;
+    }
+
+    pub fn get_max_total_bytes(&self) -> u64 {
+        self.max_total_bytes
+    }
+
+    pub fn set_max_total_bytes(&mut self, max_total_bytes: u64) {
+        self.max_total_bytes = max_total_bytes;
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        writeln!(f, "{}", json)
+    }
+}
+
+// Helper functions for deserialization
+fn string_or_default<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match String::deserialize(deserializer) {
+        Ok(s) => Ok(s),
+        Err(e) => {
+            fallback(format!("Failed to deserialize string value: {}, using default", e));
+            Ok(String::default())
+        }
+    }
+}
+
+fn string_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer) {
+        Ok(s) => Ok(s.map(|s| s.trim().to_string()).filter(|s| !s.is_empty())),
+        Err(e) => {
+            fallback(format!("Failed to deserialize string option value: {}, using default None", e));
+            Ok(None)
+        }
+    }
+}
+
+// A single string is accepted as a one-entry list; non-string entries are skipped with a warning
+fn header_value_or_default<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match String::deserialize(deserializer) {
+        Ok(value) if hyper::header::HeaderValue::from_str(value.trim()).is_ok() => Ok(value.trim().to_string()),
+        Ok(value) => {
+            fallback(format!("Invalid header value {:?}, using default", value));
+            Ok(String::new())
+        }
+        Err(e) => {
+            fallback(format!("Failed to deserialize header value: {}, using default", e));
+            Ok(String::new())
+        }
+    }
+}
+
+// Rules without a valid value are dropped; the rest of the list still applies
+fn cache_control_rules_or_default<'de, D>(deserializer: D) -> std::result::Result<Vec<CacheControlRule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = match serde_json::Value::deserialize(deserializer) {
+        Ok(serde_json::Value::Array(entries)) => entries,
+        Ok(other) => {
+            fallback(format!("Expected a list of cache_control rules, got {}, using default", other));
+            Vec::new()
+        }
+        Err(e) => {
+            fallback(format!("Failed to deserialize cache_control: {}, using default", e));
+            Vec::new()
+        }
+    };
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| match serde_json::from_value::<CacheControlRule>(entry.clone()) {
+            Ok(rule) if !rule.value.is_empty() => Some(rule),
+            Ok(_) => {
+                fallback(format!("Ignoring cache_control rule without a value: {}", entry));
+                None
+            }
+            Err(e) => {
+                fallback(format!("Ignoring cache_control rule {}: {}", entry, e));
+                None
+            }
+        })
+        .collect())
+}
+
+fn string_vec_or_default<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = match serde_json::Value::deserialize(deserializer) {
+        Ok(serde_json::Value::String(entry)) => vec![serde_json::Value::String(entry)],
+        Ok(serde_json::Value::Array(entries)) => entries,
+        Ok(other) => {
+            fallback(format!("Expected a list of strings, got {}, using default", other));
+            Vec::new()
+        }
+        Err(e) => {
+            fallback(format!("Failed to deserialize string list: {}, using default", e));
+            Vec::new()
+        }
+    };
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            serde_json::Value::String(entry) if !entry.trim().is_empty() => Some(entry.trim().to_string()),
+            other => {
+                fallback(format!("Ignoring invalid list entry {}", other));
+                None
+            }
+        })
+        .collect())
+}
+
+// Routes keyed by normalized host; when keys collapse to the same host the already-normalized spelling wins
+fn normalized_routes<'de, D>(deserializer: D) -> std::result::Result<HashMap<String, ProxyRoute>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = BTreeMap::<String, ProxyRoute>::deserialize(deserializer)?;
+    let mut routes = HashMap::with_capacity(raw.len());
+    for (domain, mut route) in raw {
+        let key = normalize_host(&domain).into_owned();
+        if let Err(err) = check_wildcard_key(&key) {
+            warn!("{}, ignoring the route", err);
+            continue;
+        }
+        if routes.contains_key(&key) && key != domain {
+            warn!("Duplicate route for host '{}' after normalization, ignoring '{}'", key, domain);
+            continue;
+        }
+        if route.match_apex && wildcard_suffix(&key).is_none() {
+            warn!("match_apex only applies to wildcard routes, ignoring it on '{}'", key);
+            route.match_apex = false;
+        }
+        routes.insert(key, route);
+    }
+    Ok(routes)
+}
+
+fn tls_fallback_or_default<'de, D>(deserializer: D) -> std::result::Result<TlsFallback, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match TlsFallback::deserialize(deserializer) {
+        Ok(TlsFallback::Route(domain)) => Ok(TlsFallback::Route(normalize_host(&domain).into_owned())),
+        Ok(fallback) => Ok(fallback),
+        Err(e) => {
+            fallback(format!("Failed to deserialize tls_fallback: {}, using default (reject)", e));
+            Ok(TlsFallback::default())
+        }
+    }
+}
+
+fn header_limit_policy_or_default<'de, D>(deserializer: D) -> std::result::Result<HeaderLimitPolicy, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match HeaderLimitPolicy::deserialize(deserializer) {
+        Ok(policy) => Ok(policy),
+        Err(e) => {
+            fallback(format!("Failed to deserialize upstream_header_policy: {}, using default (reject)", e));
+            Ok(HeaderLimitPolicy::default())
+        }
+    }
+}
+
+fn bind_failure_policy_or_default<'de, D>(deserializer: D) -> std::result::Result<BindFailurePolicy, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match BindFailurePolicy::deserialize(deserializer) {
+        Ok(policy) => Ok(policy),
+        Err(e) => {
+            fallback(format!("Failed to deserialize bind_failure_policy: {}, using default (retry)", e));
+            Ok(BindFailurePolicy::default())
+        }
+    }
+}
+
+fn request_log_mode_or_default<'de, D>(deserializer: D) -> std::result::Result<RequestLogMode, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match RequestLogMode::deserialize(deserializer) {
+        Ok(mode) => Ok(mode),
+        Err(e) => {
+            fallback(format!("Failed to deserialize request_log_mode: {}, using default (per_request)", e));
+            Ok(RequestLogMode::default())
+        }
+    }
+}
+
+fn resolver_mode_or_default<'de, D>(deserializer: D) -> std::result::Result<ResolverMode, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match ResolverMode::deserialize(deserializer) {
+        Ok(mode) => Ok(mode),
+        Err(e) => {
+            fallback(format!("Failed to deserialize resolver mode: {}, using default (system)", e));
+            Ok(ResolverMode::default())
+        }
+    }
+}
+
+fn dns_servers_or_default<'de, D>(deserializer: D) -> std::result::Result<Vec<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Vec::<String>::deserialize(deserializer) {
+        Ok(entries) => Ok(entries
+            .into_iter()
+            .filter_map(|entry| match entry.parse::<SocketAddr>().or_else(|_| entry.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53))) {
+                Ok(server) => Some(server),
+                Err(e) => {
+                    fallback(format!("Ignoring invalid DNS server '{}': {}", entry, e));
+                    None
+                }
+            })
+            .collect()),
+        Err(e) => {
+            fallback(format!("Failed to deserialize resolver servers: {}, using default", e));
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn static_hosts_or_default<'de, D>(deserializer: D) -> std::result::Result<BTreeMap<String, IpAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match BTreeMap::<String, String>::deserialize(deserializer) {
+        Ok(entries) => Ok(entries
+            .into_iter()
+            .filter_map(|(name, ip)| match ip.parse::<IpAddr>() {
+                Ok(ip) => Some((name.trim_end_matches('.').to_ascii_lowercase(), ip)),
+                Err(e) => {
+                    fallback(format!("Ignoring static host '{}': '{}' is not an IP address ({})", name, ip, e));
+                    None
+                }
+            })
+            .collect()),
+        Err(e) => {
+            fallback(format!("Failed to deserialize resolver static_hosts: {}, using default", e));
+            Ok(BTreeMap::new())
+        }
+    }
+}
+
+fn unknown_host_action_or_default<'de, D>(deserializer: D) -> std::result::Result<UnknownHostAction, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match UnknownHostAction::deserialize(deserializer) {
+        Ok(UnknownHostAction::Redirect(url)) if url.trim().is_empty() => {
+            fallback("unknown_host redirect has no URL, using default (not_found)".to_string());
+            Ok(UnknownHostAction::default())
+        }
+        Ok(action) => Ok(action),
+        Err(e) => {
+            fallback(format!("Failed to deserialize unknown_host action: {}, using default (not_found)", e));
+            Ok(UnknownHostAction::default())
+        }
+    }
+}
+
+// Routes are written sorted by host so saved files are stable across runs
+fn sorted_routes<S>(routes: &HashMap<String, ProxyRoute>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(routes.iter().collect::<BTreeMap<_, _>>())
+}
+
+fn default_cache_dir() -> String {
+    "./cache".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// An activation window bound from a RoutePatch: "" removes it
+fn window_bound(value: &str) -> Result<Option<OffsetDateTime>> {
+    if value.trim().is_empty() { Ok(None) } else { parse_rfc3339(value).map(Some) }
+}
+
+// A blank path means no socket
+fn path_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    string_option_or_default(deserializer).map(|path| path.map(PathBuf::from))
+}
+
+fn timestamp_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<OffsetDateTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer) {
+        Ok(None) => Ok(None),
+        Ok(Some(value)) => match parse_rfc3339(&value) {
+            Ok(at) => Ok(Some(at)),
+            Err(e) => {
+                fallback(format!("{}, ignoring it", e));
+                Ok(None)
+            }
+        },
+        Err(e) => {
+            fallback(format!("Failed to deserialize timestamp: {}, using default None", e));
+            Ok(None)
+        }
+    }
+}
+
+fn serialize_timestamp_option<S>(value: &Option<OffsetDateTime>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    value.map(format_rfc3339).serialize(serializer)
+}
+
+fn inactive_response_or_default<'de, D>(deserializer: D) -> std::result::Result<InactiveResponse, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match InactiveResponse::deserialize(deserializer) {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            fallback(format!("Failed to deserialize inactive_response: {}, using default (not_found)", e));
+            Ok(InactiveResponse::default())
         }
+    }
+}
 
-        // Check for duplicate subroute paths
-        for existing_subroute in &route.subroutes {
-            if existing_subroute.path == clean_path {
-                return Err(anyhow::anyhow!("Subroute already exists for path: {}", clean_path));
-            }
-        }
+fn is_true(value: &bool) -> bool {
+    *value
+}
 
-        let subroute = ProxyPathRoute { path: clean_path.clone(), port };
+fn is_false(value: &bool) -> bool {
+    !*value
+}
 
-        route.subroutes.push(subroute);
-        info!("Added subroute to {}: {} -> port {}", domain, clean_path, port);
-        Ok(())
+fn default_health_path() -> String {
+    "/__minipx/health".to_string()
+}
+
+// Forgiving bool: non-bool types fall back to false.
+fn bool_or_default<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match bool::deserialize(deserializer) {
+        Ok(b) => Ok(b),
+        Err(e) => {
+            fallback(format!("Failed to deserialize bool value: {}, using false", e));
+            Ok(false)
+        }
     }
 }
 
-impl ProxyRoute {
-    pub fn new(host: String, path: String, port: u16, ssl_enable: bool, listen_port: Option<u16>, redirect_to_https: bool) -> Self {
-        Self { host, path, port, ssl_enable, listen_port, redirect_to_https, subroutes: Vec::new() }
+// Forgiving u16: non-integer or out-of-range types fall back to default (typically 0 here).
+fn u16_or_default<'de, D>(deserializer: D) -> std::result::Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u16::deserialize(deserializer) {
+        Ok(n) => Ok(n),
+        Err(e) => {
+            fallback(format!("Failed to deserialize u16 value: {}, using default", e));
+            Ok(u16::default())
+        }
     }
+}
 
-    pub fn is_ssl_enabled(&self) -> bool {
-        self.ssl_enable
+fn u16_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<u16>::deserialize(deserializer) {
+        Ok(Some(n)) if n > u16::MIN && n < u16::MAX => Ok(Some(n)),
+        // 0 is how an unset port is written
+        Ok(None) | Ok(Some(0)) => Ok(None),
+        Ok(Some(n)) => {
+            fallback(format!("Invalid u16 value {}, using default None", n));
+            Ok(None)
+        }
+        Err(e) => {
+            fallback(format!("Failed to deserialize u16 option value: {}, using default None", e));
+            Ok(None)
+        }
     }
+}
 
-    pub fn get_redirect_to_https(&self) -> bool {
-        self.redirect_to_https
+// Invalid entries are skipped with a warning instead of rejecting the whole config
+fn cidr_list_or_default<'de, D>(deserializer: D) -> std::result::Result<Vec<IpCidr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Vec::<String>::deserialize(deserializer) {
+        Ok(entries) => Ok(entries
+            .into_iter()
+            .filter_map(|entry| match entry.parse::<IpCidr>() {
+                Ok(cidr) => Some(cidr),
+                Err(e) => {
+                    fallback(format!("Ignoring invalid trusted proxy '{}': {}", entry, e));
+                    None
+                }
+            })
+            .collect()),
+        Err(e) => {
+            fallback(format!("Failed to deserialize trusted proxies: {}, using default", e));
+            Ok(Vec::new())
+        }
     }
+}
 
-    pub fn get_listen_port(&self) -> Option<u16> {
-        self.listen_port
+fn bool_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<bool>::deserialize(deserializer) {
+        Ok(b) => Ok(b),
+        Err(e) => {
+            fallback(format!("Failed to deserialize bool option value: {}, using default None", e));
+            Ok(None)
+        }
     }
+}
 
-    // New getters for the host, port, and path to avoid accessing private fields from other modules
-    pub fn get_host(&self) -> &str {
-        &self.host
+fn usize_or_default<'de, D>(deserializer: D) -> std::result::Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match usize::deserialize(deserializer) {
+        Ok(n) => Ok(n),
+        Err(e) => {
+            fallback(format!("Failed to deserialize usize value: {}, using default", e));
+            Ok(usize::default())
+        }
     }
+}
 
-    pub fn get_port(&self) -> u16 {
-        self.port
+fn u64_or_default<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u64::deserialize(deserializer) {
+        Ok(n) => Ok(n),
+        Err(e) => {
+            fallback(format!("Failed to deserialize u64 value: {}, using default", e));
+            Ok(u64::default())
+        }
     }
+}
 
-    pub fn get_path(&self) -> &str {
-        &self.path
+// Zero means "no limit"
+fn u64_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<u64>::deserialize(deserializer) {
+        Ok(n) => Ok(n.filter(|&n| n > 0)),
+        Err(e) => {
+            fallback(format!("Failed to deserialize u64 option value: {}, using default None", e));
+            Ok(None)
+        }
     }
 }
 
-impl Display for Config {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let json = serde_json::to_string_pretty(self).unwrap();
-        writeln!(f, "{}", json)
+fn usize_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<usize>::deserialize(deserializer) {
+        Ok(n) => Ok(n.filter(|&n| n > 0)),
+        Err(e) => {
+            fallback(format!("Failed to deserialize usize option value: {}, using default None", e));
+            Ok(None)
+        }
     }
 }
 
-// Helper functions for deserialization
-fn string_or_default<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
+// Unlike `u64_option_or_default`, zero is kept: it disables a timeout that would otherwise be inherited
+fn timeout_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    match String::deserialize(deserializer) {
-        Ok(s) => Ok(s),
+    match Option::<u64>::deserialize(deserializer) {
+        Ok(n) => Ok(n),
         Err(e) => {
-            warn!("Failed to deserialize string value: {}, using default", e);
-            Ok(String::default())
+            fallback(format!("Failed to deserialize timeout value: {}, using default None", e));
+            Ok(None)
         }
     }
 }
 
-fn default_cache_dir() -> String {
-    "./cache".to_string()
+fn default_response_header_timeout_ms() -> u64 {
+    30_000
 }
 
-// Forgiving bool: non-bool types fall back to false.
-fn bool_or_default<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
+fn is_default_response_header_timeout(timeout_ms: &u64) -> bool {
+    *timeout_ms == default_response_header_timeout_ms()
+}
+
+fn default_max_header_bytes() -> usize {
+    64 * 1024
+}
+
+fn is_default_max_header_bytes(bytes: &usize) -> bool {
+    *bytes == default_max_header_bytes()
+}
+
+fn default_max_header_count() -> usize {
+    100
+}
+
+fn is_default_max_header_count(count: &usize) -> bool {
+    *count == default_max_header_count()
+}
+
+fn default_bind_failure_attempts() -> u64 {
+    5
+}
+
+fn is_default_bind_failure_attempts(attempts: &u64) -> bool {
+    *attempts == default_bind_failure_attempts()
+}
+
+fn default_request_log_interval_secs() -> u64 {
+    60
+}
+
+fn is_default_request_log_interval(secs: &u64) -> bool {
+    *secs == default_request_log_interval_secs()
+}
+
+fn default_drain_timeout_secs() -> u64 {
+    30
+}
+
+fn is_default_drain_timeout(secs: &u64) -> bool {
+    *secs == default_drain_timeout_secs()
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_admin_api_port() -> u16 {
+    9180
+}
+
+fn default_capture_max_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_capture_dir() -> String {
+    "./captures".to_string()
+}
+
+fn default_capture_duration_secs() -> u64 {
+    15 * 60
+}
+
+fn default_capture_max_total_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn capture_max_bytes_or_default<'de, D>(deserializer: D) -> std::result::Result<usize, D::Error>
 where
     D: Deserializer<'de>,
 {
-    match bool::deserialize(deserializer) {
-        Ok(b) => Ok(b),
+    match usize::deserialize(deserializer) {
+        Ok(n) if n <= MAX_CAPTURE_BYTES => Ok(n),
+        Ok(n) => {
+            fallback(format!("debug_capture max_bytes {} is over the limit of {}, using default", n, MAX_CAPTURE_BYTES));
+            Ok(default_capture_max_bytes())
+        }
         Err(e) => {
-            warn!("Failed to deserialize bool value: {}, using false", e);
-            Ok(false)
+            fallback(format!("Failed to deserialize usize value: {}, using default", e));
+            Ok(default_capture_max_bytes())
         }
     }
 }
 
-// Forgiving u16: non-integer or out-of-range types fall back to default (typically 0 here).
-fn u16_or_default<'de, D>(deserializer: D) -> std::result::Result<u16, D::Error>
+fn capture_duration_secs_or_default<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
 where
     D: Deserializer<'de>,
 {
-    match u16::deserialize(deserializer) {
-        Ok(n) => Ok(n),
+    match u64::deserialize(deserializer) {
+        Ok(n) if n <= MAX_CAPTURE_DURATION_SECS => Ok(n),
+        Ok(n) => {
+            fallback(format!("debug_capture duration_secs {} is over the limit of {}, using default", n, MAX_CAPTURE_DURATION_SECS));
+            Ok(default_capture_duration_secs())
+        }
         Err(e) => {
-            warn!("Failed to deserialize u16 value: {}, using default", e);
-            Ok(u16::default())
+            fallback(format!("Failed to deserialize u64 value: {}, using default", e));
+            Ok(default_capture_duration_secs())
         }
     }
 }
 
-fn u16_option_or_default<'de, D>(deserializer: D) -> std::result::Result<Option<u16>, D::Error>
+fn capture_max_total_bytes_or_default<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
 where
     D: Deserializer<'de>,
 {
-    match Option::<u16>::deserialize(deserializer) {
-        Ok(Some(n)) if n > u16::MIN && n < u16::MAX => Ok(Some(n)),
-        Ok(_) => {
-            warn!("Invalid u16 value, using default None");
-            Ok(None)
+    match u64::deserialize(deserializer) {
+        Ok(n) if n <= MAX_CAPTURE_TOTAL_BYTES => Ok(n),
+        Ok(n) => {
+            fallback(format!("debug_capture max_total_bytes {} is over the limit of {}, using default", n, MAX_CAPTURE_TOTAL_BYTES));
+            Ok(default_capture_max_total_bytes())
         }
         Err(e) => {
-            warn!("Failed to deserialize u16 option value: {}, using default None", e);
-            Ok(None)
+            fallback(format!("Failed to deserialize u64 value: {}, using default", e));
+            Ok(default_capture_max_total_bytes())
         }
     }
 }
@@ -326,6 +2523,18 @@ fn default_host() -> String {
     "localhost".to_string()
 }
 
+fn default_unknown_host_burst() -> u64 {
+    20
+}
+
+fn default_unknown_host_per_minute() -> u64 {
+    60
+}
+
+fn default_unknown_host_log_interval() -> u64 {
+    60
+}
+
 fn default_path() -> String {
     "".to_string()
 }
@@ -346,6 +2555,27 @@ mod tests {
         assert!(config.routes.is_empty());
     }
 
+    #[test]
+    fn test_serialization_is_deterministic() {
+        let domains: Vec<String> = (0..32).map(|i| format!("app{}.example.com", i)).collect();
+        let build = |order: &mut dyn Iterator<Item = &String>| {
+            let mut config = Config::new("./test_config.json");
+            for domain in order {
+                config.routes.insert(domain.clone(), ProxyRoute::new("localhost".to_string(), "".to_string(), 3000, false, None, false));
+            }
+            serde_json::to_string_pretty(&config).unwrap()
+        };
+        let forward = build(&mut domains.iter());
+        let backward = build(&mut domains.iter().rev());
+        assert_eq!(forward, backward);
+        assert!(forward.find("app0.example.com").unwrap() < forward.find("app1.example.com").unwrap());
+        assert!(forward.contains(&format!("\"version\": {}", CONFIG_VERSION)));
+
+        // Loading and saving again gives the same bytes
+        let reloaded: Config = serde_json::from_str(&forward).unwrap();
+        assert_eq!(serde_json::to_string_pretty(&reloaded).unwrap(), forward);
+    }
+
     #[test]
     fn test_config_set_email() {
         let mut config = Config::default();
@@ -421,10 +2651,252 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("already exists"));
     }
 
+    #[tokio::test]
+    async fn test_add_route_normalizes_domain() {
+        let mut config = Config::default();
+        let route = ProxyRoute::new("localhost".to_string(), "".to_string(), 8080, false, None, false);
+        config.add_route("API.Example.com.".to_string(), route.clone()).await.unwrap();
+        assert!(config.routes.contains_key("api.example.com"));
+        assert!(config.lookup_host("Api.EXAMPLE.com").is_some());
+
+        // The same host in another spelling is a duplicate
+        assert!(config.add_route("api.example.com".to_string(), route).await.is_err());
+        config.remove_route("API.example.com.").await.unwrap();
+        assert!(config.routes.is_empty());
+    }
+
+    #[test]
+    fn test_trusted_proxies_skip_invalid_entries() {
+        let config: Config = serde_json::from_str(r#"{"trusted_proxies":["10.0.0.0/8","bogus","2001:db8::/32"]}"#).unwrap();
+        let trusted: Vec<String> = config.get_trusted_proxies().iter().map(|c| c.to_string()).collect();
+        assert_eq!(trusted, vec!["10.0.0.0/8", "2001:db8::/32"]);
+    }
+
+    #[test]
+    fn test_resolver_section_skips_invalid_entries() {
+        let config: Config = serde_json::from_str(
+            r#"{"resolver":{"mode":"custom","servers":["127.0.0.1:8600","10.0.0.2","dns.example.com"],"static_hosts":{"DB.Internal":"10.0.0.5","bad.internal":"nope"}}}"#,
+        )
+        .unwrap();
+        let resolver = config.get_resolver();
+        assert_eq!(resolver.get_mode(), ResolverMode::Custom);
+        assert_eq!(resolver.get_servers(), [SocketAddr::from(([127, 0, 0, 1], 8600)), SocketAddr::from(([10, 0, 0, 2], 53))]);
+        assert_eq!(resolver.static_host("db.internal"), Some(IpAddr::from([10, 0, 0, 5])));
+        assert_eq!(resolver.get_static_hosts().len(), 1);
+
+        let defaults: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(defaults.get_resolver().get_mode(), ResolverMode::System);
+        assert!(!serde_json::to_string(&defaults).unwrap().contains("resolver"));
+    }
+
+    #[test]
+    fn test_cache_control_rules_skip_invalid_entries() {
+        let route: ProxyRoute = serde_json::from_str(
+            r#"{"port":3000,"cache_control":[{"content_types":"image/*","value":" public, max-age=86400 ","expires":true},{"paths":["/a"]},{"value":"bad\nvalue"},{"paths":["/b"],"value":"no-cache","override":true}]}"#,
+        )
+        .unwrap();
+        let rules = route.get_cache_control();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            (rules[0].get_content_types(), rules[0].get_value(), rules[0].get_max_age()),
+            (&["image/*".to_string()][..], "public, max-age=86400", Some(86400))
+        );
+        assert!(rules[0].is_expires() && !rules[0].is_override());
+        assert!(rules[1].is_override() && rules[1].get_max_age().is_none());
+        assert!(serde_json::to_string(&route).unwrap().contains(r#""override":true"#));
+    }
+
+    #[test]
+    fn test_bind_failure_policy_parsing() {
+        let config: Config = serde_json::from_str(r#"{"bind_failure_policy":"fail","bind_failure_attempts":0}"#).unwrap();
+        assert_eq!(config.get_bind_failure_policy(), BindFailurePolicy::Fail);
+        // At least one attempt is always made
+        assert_eq!(config.get_bind_failure_attempts(), 1);
+
+        let invalid: Config = serde_json::from_str(r#"{"bind_failure_policy":"explode"}"#).unwrap();
+        assert_eq!(invalid.get_bind_failure_policy(), BindFailurePolicy::Retry);
+        assert_eq!(invalid.get_bind_failure_attempts(), 5);
+        assert!(!serde_json::to_string(&invalid).unwrap().contains("bind_failure"));
+    }
+
+    #[tokio::test]
+    async fn test_activation_window_parsing_and_validation() {
+        let config: Config = serde_json::from_str(
+            r#"{"routes":{"event.test":{"port":3000,"active_from":"2026-12-24T19:00:00+01:00","active_until":"2026-12-26T00:00:00Z","inactive_response":"maintenance"},"typo.test":{"port":3001,"active_from":"24.12.2026"}}}"#,
+        )
+        .unwrap();
+        let event = config.lookup_host("event.test").unwrap();
+        assert_eq!(event.get_inactive_response(), InactiveResponse::Maintenance);
+        assert_eq!(event.get_active_from().unwrap().unix_timestamp(), parse_rfc3339("2026-12-24T18:00:00Z").unwrap().unix_timestamp());
+        // An unreadable bound is dropped rather than taking the route down
+        assert!(config.lookup_host("typo.test").unwrap().get_active_from().is_none());
+        let saved = serde_json::to_value(&config).unwrap();
+        assert_eq!(saved["routes"]["event.test"]["active_from"], "2026-12-24T19:00:00+01:00");
+        assert!(saved["routes"]["typo.test"].get("active_from").is_none() && saved["routes"]["typo.test"].get("inactive_response").is_none());
+
+        let mut config = Config::default();
+        let mut inverted = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 3000, false, None, false);
+        inverted.set_active_from(Some(parse_rfc3339("2026-12-26T00:00:00Z").unwrap()));
+        inverted.set_active_until(Some(parse_rfc3339("2026-12-24T00:00:00Z").unwrap()));
+        let err = config.add_route("event.test".to_string(), inverted.clone()).await.unwrap_err().to_string();
+        assert!(err.contains("active_until (2026-12-24T00:00:00Z) is earlier than active_from"), "{}", err);
+
+        inverted.set_active_until(None);
+        config.add_route("event.test".to_string(), inverted).await.unwrap();
+        let patch = RoutePatch { active_until: Some("2026-12-25T00:00:00Z".to_string()), ..Default::default() };
+        assert!(config.update_route("event.test", patch).await.is_err());
+        let patch = RoutePatch { active_from: Some(String::new()), active_until: Some("2026-12-25T00:00:00Z".to_string()), ..Default::default() };
+        config.update_route("event.test", patch).await.unwrap();
+        let event = config.lookup_host("event.test").unwrap();
+        assert!(event.get_active_from().is_none() && event.get_active_until().is_some());
+        let patch = RoutePatch { active_until: Some("soon".to_string()), ..Default::default() };
+        assert!(config.update_route("event.test", patch).await.unwrap_err().to_string().contains("RFC 3339"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_routes() {
+        let config: Config =
+            serde_json::from_str(r#"{"routes":{"sock.test":{"port":0,"unix_socket":"/run/app.sock"},"tcp.test":{"port":3000,"unix_socket":""}}}"#)
+                .unwrap();
+        assert_eq!(config.lookup_host("sock.test").unwrap().get_unix_socket(), Some(Path::new("/run/app.sock")));
+        // Configs without the field (or with a blank one) keep proxying to host:port and save as before
+        assert!(config.lookup_host("tcp.test").unwrap().get_unix_socket().is_none());
+        let saved = serde_json::to_value(&config).unwrap();
+        assert_eq!(saved["routes"]["sock.test"]["unix_socket"], "/run/app.sock");
+        assert!(saved["routes"]["tcp.test"].get("unix_socket").is_none());
+
+        let mut config = Config::default();
+        let mut route = ProxyRoute::new("localhost".to_string(), "".to_string(), 0, false, None, false);
+        route.set_unix_socket(Some(PathBuf::from("run/app.sock")));
+        assert!(config.add_route("sock.test".to_string(), route.clone()).await.unwrap_err().to_string().contains("absolute path"));
+        route.set_unix_socket(Some(PathBuf::from("/run/app.sock")));
+        config.add_route("sock.test".to_string(), route).await.unwrap();
+
+        // Removing the socket needs a port to go back to; setting one removes it
+        let patch = RoutePatch { unix_socket: Some(String::new()), ..Default::default() };
+        assert!(config.update_route("sock.test", patch).await.unwrap_err().to_string().contains("port"));
+        config.update_route("sock.test", RoutePatch { port: Some(3000), ..Default::default() }).await.unwrap();
+        let route = config.lookup_host("sock.test").unwrap();
+        assert!(route.get_unix_socket().is_none() && route.get_port() == 3000);
+        let patch = RoutePatch { unix_socket: Some("/run/other.sock".to_string()), ..Default::default() };
+        config.update_route("sock.test", patch).await.unwrap();
+        assert_eq!(config.lookup_host("sock.test").unwrap().get_unix_socket(), Some(Path::new("/run/other.sock")));
+    }
+
+    #[test]
+    fn test_response_header_timeout_falls_back_to_global() {
+        let config: Config = serde_json::from_str(
+            r#"{"response_header_timeout_ms":5000,"routes":{"a.test":{"port":1},"b.test":{"port":2,"response_header_timeout_ms":250},"c.test":{"port":3,"response_header_timeout_ms":0}}}"#,
+        )
+        .unwrap();
+        let timeout = |host: &str| config.response_header_timeout(config.lookup_host(host).unwrap());
+        assert_eq!(timeout("a.test"), Some(Duration::from_millis(5000)));
+        assert_eq!(timeout("b.test"), Some(Duration::from_millis(250)));
+        // An explicit 0 disables the inherited timeout
+        assert_eq!(timeout("c.test"), None);
+
+        let defaults: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(defaults.get_response_header_timeout_ms(), 30_000);
+        assert!(!serde_json::to_string(&defaults).unwrap().contains("response_header_timeout_ms"));
+    }
+
+    #[test]
+    fn test_loaded_route_keys_are_normalized() {
+        let json = r#"{"routes":{"App.Example.com.":{"port":8080},"[::1]":{"port":8081},"app.example.com":{"port":8082}}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.routes.len(), 2);
+        // The already-normalized spelling wins over one that collapses onto it
+        assert_eq!(config.lookup_host("app.example.com").unwrap().get_port(), 8082);
+        assert_eq!(config.lookup_host("::1").unwrap().get_port(), 8081);
+    }
+
+    #[test]
+    fn test_lookup_host_wildcard_respects_label_boundaries() {
+        let mut config = Config::default();
+        config.routes.insert("*.example.com".to_string(), ProxyRoute::new("localhost".to_string(), "".to_string(), 8080, false, None, false));
+        assert!(config.lookup_host("evilexample.com").is_none());
+        assert!(config.lookup_host("api.evilexample.com").is_none());
+        assert!(config.lookup_host("example.com.evil.net").is_none());
+        assert!(config.lookup_host("a.b.example.com").is_some());
+    }
+
+    #[test]
+    fn test_lookup_host_most_specific_wildcard_wins() {
+        let mut config = Config::default();
+        config.routes.insert("*.example.com".to_string(), ProxyRoute::new("localhost".to_string(), "".to_string(), 8080, false, None, false));
+        config.routes.insert("*.dev.example.com".to_string(), ProxyRoute::new("localhost".to_string(), "".to_string(), 8081, false, None, false));
+        assert_eq!(config.lookup_host("api.dev.example.com").unwrap().get_port(), 8081);
+        assert_eq!(config.lookup_host("dev.example.com").unwrap().get_port(), 8080);
+        assert_eq!(config.lookup_host("api.example.com").unwrap().get_port(), 8080);
+    }
+
+    #[test]
+    fn test_lookup_host_match_apex() {
+        let mut config = Config::default();
+        let mut wildcard = ProxyRoute::new("localhost".to_string(), "".to_string(), 8080, false, None, false);
+        wildcard.set_match_apex(true);
+        config.routes.insert("*.example.com".to_string(), wildcard);
+        assert_eq!(config.lookup_route("example.com").unwrap().0, "*.example.com");
+        assert!(config.lookup_host("api.example.com").is_some());
+        assert!(config.lookup_host("evilexample.com").is_none());
+
+        // A route for the apex itself still takes precedence
+        config.routes.insert("example.com".to_string(), ProxyRoute::new("localhost".to_string(), "".to_string(), 9090, false, None, false));
+        assert_eq!(config.lookup_host("example.com").unwrap().get_port(), 9090);
+    }
+
+    #[tokio::test]
+    async fn test_add_route_rejects_malformed_wildcards() {
+        let mut config = Config::default();
+        for key in ["*example.com", "api.*.example.com", "*.*.example.com", "*"] {
+            let route = ProxyRoute::new("localhost".to_string(), "".to_string(), 8080, false, None, false);
+            let err = config.add_route(key.to_string(), route).await.unwrap_err();
+            assert!(err.to_string().contains("*.example.com"), "{}: {}", key, err);
+        }
+        assert!(config.routes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_match_apex_requires_a_wildcard_route() {
+        let mut config = Config::default();
+        let mut route = ProxyRoute::new("localhost".to_string(), "".to_string(), 8080, false, None, false);
+        route.set_match_apex(true);
+        assert!(config.add_route("example.com".to_string(), route.clone()).await.is_err());
+        config.add_route("*.example.com".to_string(), route).await.unwrap();
+
+        config
+            .add_route("app.example.com".to_string(), ProxyRoute::new("localhost".to_string(), "".to_string(), 8081, false, None, false))
+            .await
+            .unwrap();
+        let patch = RoutePatch { match_apex: Some(true), ..Default::default() };
+        assert!(config.update_route("app.example.com", patch.clone()).await.is_err());
+        let off = RoutePatch { match_apex: Some(false), ..Default::default() };
+        config.update_route("*.example.com", off).await.unwrap();
+        assert!(config.lookup_host("example.com").is_none());
+        config.update_route("*.example.com", patch).await.unwrap();
+        assert!(config.lookup_host("example.com").is_some());
+    }
+
+    #[test]
+    fn test_loading_skips_malformed_wildcards() {
+        let json = r#"{"routes":{"*example.com":{"port":8080},"*.example.com":{"port":8081,"match_apex":true},"app.example.net":{"port":8082,"match_apex":true}}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.routes.len(), 2);
+        assert!(config.lookup_host("evilexample.com").is_none());
+        assert_eq!(config.lookup_host("example.com").unwrap().get_port(), 8081);
+        // match_apex on an exact route is dropped
+        assert!(!config.lookup_host("app.example.net").unwrap().is_match_apex());
+        let saved = serde_json::to_value(&config).unwrap();
+        assert_eq!(saved["routes"]["*.example.com"]["match_apex"], true);
+        assert!(saved["routes"]["app.example.net"].get("match_apex").is_none());
+    }
+
     #[tokio::test]
     async fn test_add_route_invalid_port() {
         let mut config = Config::default();
-        let route = ProxyRoute::new("localhost".to_string(), "/api".to_string(), 80, true, None, false);
+        // A remote backend; localhost:80 is reported as a loop instead
+        let route = ProxyRoute::new("10.0.0.5".to_string(), "/api".to_string(), 80, true, None, false);
         let result = config.add_route("api.example.com".to_string(), route).await;
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("reserved"));
@@ -487,6 +2959,24 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_upstream_names_are_validated_and_cleared() {
+        let mut config = Config::default();
+        let mut route = ProxyRoute::new("10.0.0.8".to_string(), "".to_string(), 8080, false, None, false);
+        route.set_upstream_host_header(Some("bad host".to_string()));
+        assert!(config.add_route("api.example.com".to_string(), route.clone()).await.is_err());
+        route.set_upstream_host_header(Some("internal.company.lan:8080".to_string()));
+        config.add_route("api.example.com".to_string(), route).await.unwrap();
+
+        let patch = RoutePatch { upstream_sni: Some("internal.company.lan:443".to_string()), ..Default::default() };
+        assert!(config.update_route("api.example.com", patch).await.unwrap_err().to_string().contains("upstream_sni"));
+        let patch =
+            RoutePatch { upstream_host_header: Some(String::new()), upstream_sni: Some("internal.company.lan".to_string()), ..Default::default() };
+        config.update_route("api.example.com", patch).await.unwrap();
+        let route = &config.get_routes()["api.example.com"];
+        assert_eq!((route.get_upstream_host_header(), route.get_upstream_sni()), (None, Some("internal.company.lan")));
+    }
+
     #[tokio::test]
     async fn test_update_route_not_found() {
         let mut config = Config::default();
@@ -524,6 +3014,23 @@ mod tests {
         assert_eq!(route.subroutes[0].path, "/metrics");
     }
 
+    #[tokio::test]
+    async fn test_route_built_with_subroutes_is_checked_on_add() {
+        let mut config = Config::default();
+        let subroutes = vec![ProxyPathRoute::new("api/", 9090).unwrap(), ProxyPathRoute::new("/ws", 9091).unwrap()];
+        let route = ProxyRoute::new("localhost".to_string(), String::new(), 8080, false, None, false).with_subroutes(subroutes.clone());
+        config.add_route("api.example.com".to_string(), route).await.unwrap();
+        assert_eq!(config.lookup_host("api.example.com").unwrap().get_subroutes(), subroutes.as_slice());
+        assert_eq!(subroutes[0].get_path(), "/api");
+
+        let same_port = ProxyRoute::new("localhost".to_string(), String::new(), 9090, false, None, false).with_subroutes(subroutes.clone());
+        assert!(config.add_route("b.example.com".to_string(), same_port).await.unwrap_err().to_string().contains("parent route port"));
+        let duplicate = ProxyRoute::new("localhost".to_string(), String::new(), 8080, false, None, false)
+            .with_subroutes(vec![subroutes[0].clone(), ProxyPathRoute::new("/api", 9092).unwrap()]);
+        assert!(config.add_route("c.example.com".to_string(), duplicate).await.unwrap_err().to_string().contains("already exists"));
+        assert!(ProxyPathRoute::new("/api", 80).is_err());
+    }
+
     #[tokio::test]
     async fn test_add_subroute_duplicate_path() {
         let mut config = Config::default();
@@ -547,6 +3054,36 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("same as the parent"));
     }
 
+    #[tokio::test]
+    async fn test_update_subroute_overrides() {
+        let mut config = Config::default();
+        config
+            .add_route("api.example.com".to_string(), ProxyRoute::new("localhost".to_string(), "".to_string(), 8080, false, None, false))
+            .await
+            .unwrap();
+        config
+            .add_subroute_with("api.example.com", "/ws".to_string(), 9000, SubrouteOverrides { allow_websocket: Some(true), ..Default::default() })
+            .await
+            .unwrap();
+
+        config
+            .update_subroute("api.example.com", "ws/", SubroutePatch { port: Some(9001), redirect_to_https: Some(false), ..Default::default() })
+            .await
+            .unwrap();
+        let sub = &config.lookup_host("api.example.com").unwrap().get_subroutes()[0];
+        assert_eq!(sub.port, 9001);
+        assert_eq!(sub.allow_websocket, Some(true));
+        assert_eq!(sub.redirect_to_https, Some(false));
+        assert_eq!(sub.preserve_host, None);
+
+        // Unset overrides are not serialized
+        let json = serde_json::to_string(sub).unwrap();
+        assert_eq!(json, r#"{"path":"/ws","port":9001,"allow_websocket":true,"redirect_to_https":false}"#);
+
+        assert!(config.update_subroute("api.example.com", "/missing", SubroutePatch::default()).await.is_err());
+        assert!(config.update_subroute("api.example.com", "/ws", SubroutePatch { port: Some(8080), ..Default::default() }).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_add_subroute_invalid_port() {
         let mut config = Config::default();
@@ -569,4 +3106,25 @@ mod tests {
         assert_eq!(route.get_listen_port(), Some(8443));
         assert!(route.get_redirect_to_https());
     }
+
+    #[tokio::test]
+    async fn test_corrupt_config_is_only_replaced_when_asked() {
+        let dir = std::env::temp_dir().join(format!("minipx-corrupt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("minipx.json");
+        std::fs::write(&path, "{ not json").unwrap();
+
+        // Loading and reading fail, leaving the file alone
+        assert!(Config::read(&path).await.is_err());
+        assert!(Config::try_load(&path).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{ not json");
+
+        // The explicit init path backs it up and starts from the defaults
+        let config = Config::try_load_or_init_default(&path).await.unwrap();
+        assert!(config.routes.is_empty());
+        assert_eq!(std::fs::read_to_string(dir.join("minipx.corrupted.1")).unwrap(), "{ not json");
+        assert!(Config::read(&path).await.is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -1,6 +1,8 @@
 use crate::config::types::Config;
+use crate::utils::host::wildcard_suffix;
 use crate::utils::validation::validate_hostname_chars;
 use std::collections::BTreeSet;
+use time::OffsetDateTime;
 
 impl Config {
     /// Check if SSL is enabled for any route
@@ -63,13 +65,19 @@ impl Config {
         true
     }
 
-    /// Returns (valid_domains, invalid_domains) for ACME based on current routes.
+    /// Returns (valid_domains, invalid_domains) for ACME based on current routes. Routes outside their activation
+    /// window are left out of both.
     pub fn get_valid_domains_for_acme(&self) -> (Vec<String>, Vec<String>) {
         let mut valid_set: BTreeSet<String> = BTreeSet::new();
         let mut invalid: Vec<String> = Vec::new();
-        for (domain, route) in &self.routes {
-            if domain.starts_with("*.") {
+        let now = OffsetDateTime::now_utc();
+        for (domain, route) in self.routes.iter().filter(|(_, route)| route.is_active_at(now)) {
+            if let Some(apex) = wildcard_suffix(domain) {
                 invalid.push(domain.clone());
+                // The bare domain of a match_apex wildcard is an ordinary name and can get its own certificate
+                if route.is_ssl_enabled() && route.is_match_apex() && Self::validate_domain(apex) {
+                    valid_set.insert(apex.to_string());
+                }
                 continue;
             }
             // Only consider routes that intend to serve HTTPS at the frontend
@@ -243,6 +251,23 @@ mod tests {
         assert!(invalid.contains(&"localhost".to_string()));
     }
 
+    #[test]
+    fn test_match_apex_wildcard_requests_a_certificate_for_the_apex() {
+        let mut config = Config::default();
+        config.set_email("admin@example.com".to_string());
+        let mut wildcard = ProxyRoute::new("localhost".to_string(), "/".to_string(), 8080, true, None, false);
+        config.routes.insert("*.example.com".to_string(), wildcard.clone());
+        assert!(config.get_valid_domains_for_acme().0.is_empty());
+
+        wildcard.set_match_apex(true);
+        config.routes.insert("*.example.com".to_string(), wildcard);
+        let (valid, invalid) = config.get_valid_domains_for_acme();
+        assert_eq!(valid, vec!["example.com".to_string()]);
+        assert_eq!(invalid, vec!["*.example.com".to_string()]);
+        assert!(config.can_serve_tls_for_host("example.com"));
+        assert!(!config.can_serve_tls_for_host("api.example.com"));
+    }
+
     #[test]
     fn test_can_serve_tls_for_host() {
         let mut config = Config::default();
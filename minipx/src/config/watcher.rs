@@ -1,4 +1,6 @@
+use crate::config::manager::{is_applied_content, record_applied_content, set_reload_failure};
 use crate::config::types::Config;
+use crate::systemd;
 use log::{debug, trace, warn};
 
 impl Config {
@@ -7,17 +9,35 @@ impl Config {
         use notify::{Config as NotifyConfig, RecommendedWatcher, RecursiveMode, Watcher};
         let path = self.path.clone();
         tokio::spawn(async move {
-            let (tx, rx) = std::sync::mpsc::channel();
-            let mut watcher = RecommendedWatcher::new(tx, NotifyConfig::default()).unwrap();
+            // Events are forwarded to an async channel so waiting for them never blocks a runtime thread
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut watcher = RecommendedWatcher::new(
+                move |res| {
+                    let _ = tx.send(res);
+                },
+                NotifyConfig::default(),
+            )
+            .unwrap();
             watcher.watch(&path, RecursiveMode::NonRecursive).unwrap();
-            for res in rx {
+            while let Some(res) = rx.recv().await {
                 if let Ok(event) = res {
                     if event.kind.is_modify() || event.kind.is_create() || event.kind.is_remove() {
                         trace!("Config file changed: {:?}", event);
+                        let content = tokio::fs::read_to_string(&path).await.ok();
+                        // Our own `save_and_apply` (or a second event for a change already reloaded)
+                        if content.as_deref().is_some_and(|content| is_applied_content(&path, content)) {
+                            trace!("Config file holds the running config, not reloading");
+                            // An event seen halfway through that write may have failed to reload the partial file
+                            set_reload_failure(None);
+                            continue;
+                        }
                         debug!("Config file changed, reloading");
-                        if let Err(e) = Self::try_load(&path).await {
-                            warn!("Failed to reload config: {}", e);
+                        systemd::reloading();
+                        // Failures are logged and reported by `reload`; the running config stays as it is
+                        if let (Ok(_), Some(content)) = (Self::reload(&path).await, content) {
+                            record_applied_content(&path, content);
                         }
+                        systemd::reloaded();
                     } else {
                         trace!("Config file event: {:?}", event);
                         continue; // ignore other events
@@ -0,0 +1,463 @@
+//! Warm restart: hand the HTTP and HTTPS listening sockets to a freshly started minipx (e.g. an upgraded binary)
+//! so ports 80 and 443 never close.
+//!
+//! The running process (`hand_off`) starts the new one with the listener descriptors inherited and a JSON
+//! [`HandoffState`] in `MINIPX_HANDOFF`, then waits on a socket pair for its answer. The new process adopts the
+//! descriptors (`adopt`), starts serving on them and replies with `accept`, or with `reject` when it cannot take
+//! over (an unsupported state version, a different `cache_dir`); a rejected, failed or silent successor leaves the
+//! old process serving as before. Once accepted, the old process stops accepting, lets its in-flight requests and
+//! websocket tunnels finish (`drained`, up to `drain_timeout_secs`) and exits. Tunnels are not transferred: they
+//! stay with the old process until they close or the drain timeout ends them.
+//!
+//! Only the frontends are handed over; forwarders, the dedicated health port and the IPC socket are bound by the
+//! new process once the old one releases them. Handoff needs a unix target; elsewhere `hand_off` fails and
+//! `adopt` finds nothing.
+
+use crate::config::Config;
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Version of the [`HandoffState`] layout; a process only takes over from states it understands
+pub const HANDOFF_VERSION: u32 = 1;
+
+/// Environment variable carrying the [`HandoffState`] to the new process
+pub const HANDOFF_ENV: &str = "MINIPX_HANDOFF";
+
+/// How long the new process may take to start serving before the handoff is abandoned
+pub const HANDOFF_TIMEOUT: Duration = Duration::from_secs(30);
+
+static HANDED_OFF: AtomicBool = AtomicBool::new(false);
+static HANDING_OFF: AtomicBool = AtomicBool::new(false);
+static ADOPT_ATTEMPTED: AtomicBool = AtomicBool::new(false);
+static HANDED_OFF_NOTIFY: Notify = Notify::const_new();
+// Duplicates of the listeners being served, by kind, so they can be passed on
+#[cfg(unix)]
+static LISTENERS: OnceLock<Mutex<HashMap<String, std::os::fd::OwnedFd>>> = OnceLock::new();
+// What this process inherited from the process it took over from
+static ADOPTED: OnceLock<Mutex<Option<Adopted>>> = OnceLock::new();
+
+/// What the old process tells the new one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandoffState {
+    /// Layout version, [`HANDOFF_VERSION`] of the sending binary
+    pub version: u32,
+    /// minipx version of the sending binary, for the logs
+    pub minipx_version: String,
+    /// Config file the old process was serving; the new one loads the same file
+    pub config_path: PathBuf,
+    /// ACME cache of the old process; certificates are only reused if the new one has the same
+    pub cache_dir: String,
+    /// Inherited listener descriptors by kind ("http", "https")
+    #[serde(default)]
+    pub listeners: BTreeMap<String, i32>,
+    /// Descriptor the new process sends its [`HandoffReply`] on
+    #[serde(default)]
+    pub reply_fd: i32,
+}
+
+/// The new process's answer to a handoff
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandoffReply {
+    pub accepted: bool,
+    /// Why the handoff was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl HandoffState {
+    /// State for handing off a process serving `config_path` with ACME certificates in `cache_dir`; the
+    /// listeners and reply descriptor are filled in by `hand_off`
+    pub fn new(config_path: impl Into<PathBuf>, cache_dir: impl Into<String>) -> Self {
+        Self {
+            version: HANDOFF_VERSION,
+            minipx_version: env!("CARGO_PKG_VERSION").to_string(),
+            config_path: config_path.into(),
+            cache_dir: cache_dir.into(),
+            listeners: BTreeMap::new(),
+            reply_fd: -1,
+        }
+    }
+
+    /// Whether a process running `config` can take over: the certificates in `cache_dir` must carry over
+    pub fn check(&self, config: &Config) -> Result<()> {
+        if *config.get_cache_dir() != self.cache_dir {
+            return Err(anyhow!("cache_dir changed from {} to {}; restart minipx instead", self.cache_dir, config.get_cache_dir()));
+        }
+        Ok(())
+    }
+}
+
+struct Adopted {
+    state: HandoffState,
+    listeners: HashMap<String, TcpListener>,
+    #[cfg(unix)]
+    reply: Option<std::os::unix::net::UnixStream>,
+}
+
+fn adopted() -> &'static Mutex<Option<Adopted>> {
+    ADOPTED.get_or_init(|| Mutex::new(None))
+}
+
+/// Keep a duplicate of a listener being served as `kind`, to pass on if this process is handed off
+#[cfg(unix)]
+pub fn register_listener(kind: &str, listener: &impl std::os::fd::AsFd) {
+    match listener.as_fd().try_clone_to_owned() {
+        Ok(fd) => {
+            LISTENERS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap().insert(kind.to_string(), fd);
+        }
+        Err(e) => warn!("Failed to keep the {} listener for warm restarts: {}", kind, e),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn register_listener<T>(_kind: &str, _listener: &T) {}
+
+/// Forget the listener of `kind` once it is no longer served
+pub fn unregister_listener(kind: &str) {
+    #[cfg(unix)]
+    if let Some(listeners) = LISTENERS.get() {
+        listeners.lock().unwrap().remove(kind);
+    }
+    #[cfg(not(unix))]
+    let _ = kind;
+}
+
+/// True once a new process has taken over the listeners
+pub fn is_handed_off() -> bool {
+    HANDED_OFF.load(Ordering::Acquire)
+}
+
+/// Resolves once a new process has taken over the listeners: servers stop accepting and finish what they have
+pub async fn handed_off() {
+    loop {
+        let notified = HANDED_OFF_NOTIFY.notified();
+        if is_handed_off() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Wait for a handoff, then for the in-flight requests and websocket tunnels of the global config to finish, at
+/// most `drain_timeout_secs`. Never resolves in a process that is not handed off.
+pub async fn drained() {
+    handed_off().await;
+    let config = Config::get().await;
+    let timeout = config.get_drain_timeout();
+    let started = tokio::time::Instant::now();
+    loop {
+        let in_flight = Config::get().await.total_in_flight();
+        if in_flight.requests == 0 && in_flight.websockets == 0 {
+            info!("Drained after handing off to the new process");
+            return;
+        }
+        if started.elapsed() >= timeout {
+            warn!(
+                "Exiting with {} requests and {} websocket tunnels still open after draining for {}s",
+                in_flight.requests,
+                in_flight.websockets,
+                timeout.as_secs()
+            );
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Start `command` (usually this binary, see `upgrade`) as the successor of this process and hand it the
+/// registered listeners. Returns the new process once it has accepted and is serving; this process then stops
+/// accepting. If the successor rejects the handoff, exits or does not answer within `HANDOFF_TIMEOUT`, nothing
+/// changes here and the error says why.
+#[cfg(unix)]
+pub async fn hand_off(mut command: std::process::Command, mut state: HandoffState) -> Result<std::process::Child> {
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::os::unix::process::CommandExt;
+    use tokio::io::AsyncBufReadExt;
+
+    if is_handed_off() {
+        return Err(anyhow!("This process has already been handed off"));
+    }
+    if HANDING_OFF.swap(true, Ordering::AcqRel) {
+        return Err(anyhow!("A handoff is already in progress"));
+    }
+    struct InProgress;
+    impl Drop for InProgress {
+        fn drop(&mut self) {
+            HANDING_OFF.store(false, Ordering::Release);
+        }
+    }
+    let _in_progress = InProgress;
+
+    let listeners: Vec<(String, std::os::fd::OwnedFd)> = {
+        let registered = LISTENERS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+        registered.iter().map(|(kind, fd)| fd.try_clone().map(|fd| (kind.clone(), fd))).collect::<std::io::Result<_>>()?
+    };
+    if listeners.is_empty() {
+        return Err(anyhow!("No listeners to hand off"));
+    }
+    let (reply, successor_end) = std::os::unix::net::UnixStream::pair()?;
+    state.listeners = listeners.iter().map(|(kind, fd)| (kind.clone(), fd.as_raw_fd())).collect();
+    state.reply_fd = successor_end.as_raw_fd();
+
+    let inherited: Vec<RawFd> = state.listeners.values().copied().chain([state.reply_fd]).collect();
+    command.env(HANDOFF_ENV, serde_json::to_string(&state)?);
+    // SAFETY: only fcntl, which is async-signal-safe, runs between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &inherited {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().map_err(|e| anyhow!("Failed to start the new process: {}", e))?;
+    // The successor holds its own copies now
+    drop(successor_end);
+    drop(listeners);
+    info!("Handing the listeners off to the new process (pid {})", child.id());
+
+    reply.set_nonblocking(true)?;
+    let mut reply = tokio::io::BufReader::new(tokio::net::UnixStream::from_std(reply)?);
+    let mut line = String::new();
+    let answer = match tokio::time::timeout(HANDOFF_TIMEOUT, reply.read_line(&mut line)).await {
+        Err(_) => Err(anyhow!("The new process did not take over within {}s", HANDOFF_TIMEOUT.as_secs())),
+        Ok(Err(e)) => Err(anyhow!("Failed to read the new process's answer: {}", e)),
+        Ok(Ok(0)) => Err(anyhow!("The new process exited before taking over")),
+        Ok(Ok(_)) => match serde_json::from_str::<HandoffReply>(line.trim()) {
+            Ok(HandoffReply { accepted: true, .. }) => Ok(()),
+            Ok(HandoffReply { reason, .. }) => Err(anyhow!("The new process rejected the handoff: {}", reason.unwrap_or_default())),
+            Err(e) => Err(anyhow!("Invalid answer from the new process: {}", e)),
+        },
+    };
+    if let Err(e) = answer {
+        // A successor that is still starting would otherwise compete for the listeners
+        let _ = child.kill();
+        let _ = child.wait();
+        warn!("Handoff abandoned, still serving: {}", e);
+        return Err(e);
+    }
+
+    info!("The new process (pid {}) took over; no longer accepting connections", child.id());
+    HANDED_OFF.store(true, Ordering::Release);
+    HANDED_OFF_NOTIFY.notify_waiters();
+    Ok(child)
+}
+
+#[cfg(not(unix))]
+pub async fn hand_off(_command: std::process::Command, _state: HandoffState) -> Result<std::process::Child> {
+    Err(anyhow!("Warm restarts are only supported on unix"))
+}
+
+/// Hand this process off to the binary now installed at its path, started with the same arguments. Used by
+/// `minipx upgrade` and SIGUSR2; returns the new process's pid.
+pub async fn upgrade(config_path: &Path) -> Result<u32> {
+    let exe = std::env::current_exe()?;
+    // Linux reports a replaced binary as "/usr/bin/minipx (deleted)"; the new one is at the original path
+    let exe = match exe.to_str().and_then(|path| path.strip_suffix(" (deleted)")) {
+        Some(path) => PathBuf::from(path),
+        None => exe,
+    };
+    let mut command = std::process::Command::new(&exe);
+    command.args(std::env::args_os().skip(1));
+    let state = HandoffState::new(config_path, Config::get().await.get_cache_dir().clone());
+    let child = hand_off(command, state).await?;
+    Ok(child.id())
+}
+
+/// Hand off to the installed binary on SIGUSR2 (unix)
+pub fn spawn_upgrade_signal_handler(config_path: PathBuf) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut signals = match signal(SignalKind::user_defined2()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                warn!("Failed to listen for SIGUSR2, warm restarts are only available through `minipx upgrade`: {}", e);
+                return;
+            }
+        };
+        while signals.recv().await.is_some() {
+            info!("Received SIGUSR2, upgrading");
+            if upgrade(&config_path).await.is_ok() {
+                return;
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = config_path;
+}
+
+/// Take over from the process that started this one, if it did so through `hand_off`: the inherited listeners
+/// become available through `inherited_listener`. Returns the state it sent, or None without a handoff. A state
+/// this build cannot use is rejected (the old process keeps serving) and returned as an error.
+pub fn adopt() -> Result<Option<HandoffState>> {
+    let mut adopted = adopted().lock().unwrap();
+    // The descriptors can only be adopted once
+    if ADOPT_ATTEMPTED.swap(true, Ordering::AcqRel) {
+        return Ok(adopted.as_ref().map(|adopted| adopted.state.clone()));
+    }
+    let Ok(raw) = std::env::var(HANDOFF_ENV) else {
+        return Ok(None);
+    };
+    let state: HandoffState = serde_json::from_str(&raw).map_err(|e| anyhow!("Invalid {}: {}", HANDOFF_ENV, e))?;
+    let mut taken = adopt_descriptors(&state);
+    let checked = if state.version != HANDOFF_VERSION {
+        Err(anyhow!("handoff state version {} is not supported by this build (version {})", state.version, HANDOFF_VERSION))
+    } else {
+        taken.listeners.take().unwrap_or_else(|| Err(anyhow!("no listeners were passed")))
+    };
+    match checked {
+        Ok(listeners) => {
+            info!("Taking over {} from minipx {}", listeners.keys().cloned().collect::<Vec<_>>().join(" and "), state.minipx_version);
+            *adopted = Some(Adopted {
+                state: state.clone(),
+                listeners,
+                #[cfg(unix)]
+                reply: taken.reply,
+            });
+            Ok(Some(state))
+        }
+        Err(e) => {
+            #[cfg(unix)]
+            send_reply(taken.reply, &HandoffReply { accepted: false, reason: Some(e.to_string()) });
+            Err(anyhow!("Refusing the handoff: {}", e))
+        }
+    }
+}
+
+/// True while this process was started by a handoff
+pub fn is_taking_over() -> bool {
+    adopted().lock().unwrap().is_some()
+}
+
+/// A listener inherited for `kind` ("http" or "https") through a handoff. Like socket-activated listeners, the
+/// adopted socket stays owned here and a duplicate is returned.
+pub fn inherited_listener(kind: &str) -> Option<TcpListener> {
+    let adopted = adopted().lock().unwrap();
+    let listener = adopted.as_ref()?.listeners.get(kind)?;
+    match listener.try_clone() {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            warn!("Failed to duplicate the inherited {} listener: {}", kind, e);
+            None
+        }
+    }
+}
+
+/// Tell the old process this one is serving, so it stops accepting and drains
+pub fn accept() {
+    #[cfg(unix)]
+    if let Some(reply) = adopted().lock().unwrap().as_mut().and_then(|adopted| adopted.reply.take()) {
+        crate::systemd::main_pid(std::process::id());
+        send_reply(Some(reply), &HandoffReply { accepted: true, reason: None });
+        info!("Took over the listeners; the previous process is draining");
+    }
+}
+
+/// Tell the old process this one cannot take over (e.g. its config is incompatible); it keeps serving
+pub fn reject(reason: &str) {
+    #[cfg(unix)]
+    if let Some(reply) = adopted().lock().unwrap().take().and_then(|adopted| adopted.reply) {
+        send_reply(Some(reply), &HandoffReply { accepted: false, reason: Some(reason.to_string()) });
+    }
+    #[cfg(not(unix))]
+    let _ = reason;
+}
+
+struct Descriptors {
+    listeners: Option<Result<HashMap<String, TcpListener>>>,
+    #[cfg(unix)]
+    reply: Option<std::os::unix::net::UnixStream>,
+}
+
+#[cfg(unix)]
+fn adopt_descriptors(state: &HandoffState) -> Descriptors {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // SAFETY: the old process passed these descriptors to this one; each is adopted exactly once
+    let own = |fd: i32| {
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // Not passed on to processes this one starts, unless it hands off in turn
+        unsafe { libc::fcntl(std::os::fd::AsRawFd::as_raw_fd(&fd), libc::F_SETFD, libc::FD_CLOEXEC) };
+        fd
+    };
+    let reply = (state.reply_fd >= 0).then(|| std::os::unix::net::UnixStream::from(own(state.reply_fd)));
+    let mut listeners = HashMap::new();
+    let mut error = None;
+    for (kind, fd) in &state.listeners {
+        let listener = TcpListener::from(own(*fd));
+        match listener.local_addr().and_then(|_| listener.set_nonblocking(true)) {
+            Ok(()) => {
+                listeners.insert(kind.clone(), listener);
+            }
+            Err(e) => error = Some(anyhow!("descriptor {} for {} is not a TCP listener: {}", fd, kind, e)),
+        }
+    }
+    let listeners = match error {
+        Some(e) => Some(Err(e)),
+        None if listeners.is_empty() => None,
+        None => Some(Ok(listeners)),
+    };
+    Descriptors { listeners, reply }
+}
+
+#[cfg(not(unix))]
+fn adopt_descriptors(_state: &HandoffState) -> Descriptors {
+    Descriptors { listeners: None }
+}
+
+#[cfg(unix)]
+fn send_reply(reply: Option<std::os::unix::net::UnixStream>, answer: &HandoffReply) {
+    use std::io::Write;
+    let Some(mut reply) = reply else {
+        return;
+    };
+    let line = serde_json::to_string(answer).unwrap_or_default();
+    if let Err(e) = reply.write_all(format!("{}\n", line).as_bytes()) {
+        warn!("Failed to answer the handoff: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_wire_format() {
+        let mut state = HandoffState::new("/etc/minipx/minipx.json", "./cache");
+        state.listeners.insert("http".to_string(), 5);
+        state.reply_fd = 7;
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains(r#""version":1"#) && json.contains(r#""listeners":{"http":5}"#), "{}", json);
+        assert_eq!(serde_json::from_str::<HandoffState>(&json).unwrap(), state);
+
+        let reply: HandoffReply = serde_json::from_str(r#"{"accepted":true}"#).unwrap();
+        assert_eq!(reply, HandoffReply { accepted: true, reason: None });
+    }
+
+    #[test]
+    fn test_check_requires_the_same_cache_dir() {
+        let mut config = Config::default();
+        config.set_cache_dir("./cache".to_string());
+        assert!(HandoffState::new("minipx.json", "./cache").check(&config).is_ok());
+        let error = HandoffState::new("minipx.json", "/var/lib/minipx").check(&config).unwrap_err();
+        assert!(error.to_string().contains("cache_dir changed"), "{}", error);
+    }
+
+    #[test]
+    fn test_nothing_is_adopted_without_a_handoff() {
+        assert!(adopt().unwrap().is_none());
+        assert!(inherited_listener("http").is_none());
+        assert!(!is_handed_off());
+    }
+}
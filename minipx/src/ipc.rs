@@ -1,12 +1,137 @@
+use crate::config::ProxyRoute;
+use crate::config::manager::{DrainStatus, config_lock, reload_failure};
+use crate::handoff;
+use crate::proxy::capture::{CaptureOptions, CaptureStatus, capture_status, set_capture};
+use crate::proxy::forwarder::{ForwarderStatus, forwarder_statuses};
+use crate::proxy::framing::malformed_requests;
+use crate::proxy::health::{HealthReport, health_report};
+use crate::proxy::limiter::{RouteLimitStats, route_limit_stats};
+use crate::proxy::route_table::{Resolution, RouteTable, resolve, route_table};
+use crate::proxy::transfer::{TransferStats, transfer_stats};
+use crate::proxy::ws_stats::{WebsocketStats, websocket_stats};
+use crate::ssl_server;
+use crate::tls_events::{TlsStatus, tls_status};
 use interprocess::local_socket::prelude::{LocalSocketListener, LocalSocketStream};
 use interprocess::local_socket::traits::{ListenerExt, Stream as _};
 use interprocess::local_socket::{GenericNamespaced, ListenerOptions, Name, ToNsName};
 use log::{debug, trace, warn};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::runtime::Handle;
 
 const SOCKET_NAME: &str = "minipx_ipc_socket";
+// How long a client has to send its request line
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
 
-pub async fn get_running_config_path() -> Option<String> {
+/// A request sent to the running instance: one JSON object per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum IpcRequest {
+    /// Path of the config file the running instance was started with
+    ConfigPath,
+    /// In-flight requests still pinned to previous generations of a route
+    DrainStatus { domain: String },
+    /// Occupancy of routes with a concurrency limit and per-route counters; `reset` starts the transfer totals over
+    RouteStats {
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        reset: bool,
+    },
+    /// Debug capture state of a route; `enabled` toggles it (with `options`), None only reports
+    Capture {
+        domain: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        enabled: Option<bool>,
+        #[serde(flatten)]
+        options: CaptureOptions,
+    },
+    /// All configured routes
+    ListRoutes,
+    /// The in-memory routing table with derived per-route state
+    RouteTable,
+    /// Where a request for `host` and `path` would be proxied to, without sending it
+    Resolve {
+        host: String,
+        #[serde(default = "default_resolve_path")]
+        path: String,
+    },
+    /// Health report of the running instance
+    Status,
+    /// HTTPS supervisor state and certificate expiry/renewal state per domain
+    Certificates,
+    /// Restart the serving HTTPS server so every domain reloads its ACME state (e.g. an imported account key)
+    RestartHttps,
+    /// Warm restart: hand the listeners to the binary now installed at this process's path and drain
+    Upgrade,
+}
+
+/// The running instance's answer to an `IpcRequest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum IpcResponse {
+    ConfigPath {
+        path: String,
+    },
+    DrainStatus {
+        status: Option<DrainStatus>,
+    },
+    RouteStats {
+        routes: Vec<RouteLimitStats>,
+        // Requests refused by the strict_http checks, per route
+        #[serde(default)]
+        malformed: BTreeMap<String, u64>,
+        // Websocket tunnel counters, per route
+        #[serde(default)]
+        websockets: BTreeMap<String, WebsocketStats>,
+        // Body bytes per route and the top clients by bytes
+        #[serde(default)]
+        transfer: TransferStats,
+    },
+    Capture {
+        status: Option<CaptureStatus>,
+    },
+    Routes {
+        routes: BTreeMap<String, ProxyRoute>,
+    },
+    RouteTable {
+        table: RouteTable,
+    },
+    /// None if no route serves the host
+    Resolution {
+        resolution: Option<Resolution>,
+    },
+    Status {
+        report: HealthReport,
+        // Missing when answered by an older instance
+        #[serde(default)]
+        tls: TlsStatus,
+        /// Why the last config reload failed, while `report.config_reload_failed` is set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config_reload_error: Option<String>,
+        /// Binding state of the TCP/UDP forwarders
+        #[serde(default)]
+        forwarders: Vec<ForwarderStatus>,
+    },
+    Certificates {
+        tls: TlsStatus,
+    },
+    /// False when HTTPS was not serving; it loads the current state whenever it starts
+    HttpsRestart {
+        restarted: bool,
+    },
+    /// The process that took over the listeners; this one is draining
+    Upgraded {
+        pid: u32,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Send a request to the running instance; None if no instance is running or the answer is unreadable
+pub async fn send_request(request: IpcRequest) -> Option<IpcResponse> {
     // Prefer namespaced name for Windows/Linux abstract namespace; falls back as per crate.
     let name: Name = match SOCKET_NAME.to_ns_name::<GenericNamespaced>() {
         Ok(n) => n,
@@ -14,14 +139,24 @@ pub async fn get_running_config_path() -> Option<String> {
     };
     tokio::task::spawn_blocking(move || match LocalSocketStream::connect(name) {
         Ok(mut stream) => {
-            use std::io::Read;
+            let mut line = serde_json::to_string(&request).ok()?;
+            line.push('\n');
+            if let Err(e) = stream.write_all(line.as_bytes()).and_then(|_| stream.flush()) {
+                warn!("IPC write error: {}", e);
+                return None;
+            }
             let mut buf = Vec::with_capacity(256);
             if let Err(e) = stream.read_to_end(&mut buf) {
                 warn!("IPC read error: {}", e);
                 return None;
             }
-            let s = String::from_utf8_lossy(&buf).trim().to_string();
-            if s.is_empty() { None } else { Some(s) }
+            match serde_json::from_slice::<IpcResponse>(&buf) {
+                Ok(response) => Some(response),
+                Err(e) => {
+                    warn!("IPC response could not be parsed (running instance may be an older version): {}", e);
+                    None
+                }
+            }
         }
         Err(_e) => None,
     })
@@ -30,28 +165,174 @@ pub async fn get_running_config_path() -> Option<String> {
     .flatten()
 }
 
+pub async fn get_running_config_path() -> Option<String> {
+    match send_request(IpcRequest::ConfigPath).await? {
+        IpcResponse::ConfigPath { path } if !path.trim().is_empty() => Some(path.trim().to_string()),
+        _ => None,
+    }
+}
+
+/// Answer a single request against the current global state.
+/// Shared by the IPC server and the admin API's read-only calls. The socket is not authenticated, so nothing here
+/// edits or reloads the config file; route edits are only served by the admin API, behind its token.
+pub async fn handle_request(request: IpcRequest, config_path: &Path) -> IpcResponse {
+    match request {
+        IpcRequest::ConfigPath => IpcResponse::ConfigPath { path: config_path.to_string_lossy().into_owned() },
+        IpcRequest::DrainStatus { domain } => IpcResponse::DrainStatus { status: config_lock().read().await.drain_status(&domain) },
+        IpcRequest::RouteStats { reset } => IpcResponse::RouteStats {
+            routes: route_limit_stats(),
+            malformed: malformed_requests(),
+            websockets: websocket_stats(),
+            transfer: transfer_stats(reset),
+        },
+        IpcRequest::Capture { domain, enabled, options } => {
+            let config = config_lock().read().await;
+            let status = match config.lookup_route(&domain) {
+                Some((route_key, route)) => match enabled {
+                    Some(enabled) => match set_capture(route_key, route, enabled, options) {
+                        Ok(status) => Some(status),
+                        Err(e) => return IpcResponse::Error { message: e.to_string() },
+                    },
+                    None => Some(capture_status(route_key, route)),
+                },
+                None => None,
+            };
+            IpcResponse::Capture { status }
+        }
+        IpcRequest::ListRoutes => {
+            IpcResponse::Routes { routes: config_lock().read().await.get_routes().iter().map(|(k, v)| (k.clone(), v.clone())).collect() }
+        }
+        IpcRequest::RouteTable => IpcResponse::RouteTable { table: route_table(&*config_lock().read().await) },
+        IpcRequest::Resolve { host, path } => IpcResponse::Resolution { resolution: resolve(&*config_lock().read().await, &host, &path) },
+        IpcRequest::Status => IpcResponse::Status {
+            report: health_report(&*config_lock().read().await),
+            tls: tls_status(),
+            config_reload_error: reload_failure(),
+            forwarders: forwarder_statuses(),
+        },
+        IpcRequest::Certificates => IpcResponse::Certificates { tls: tls_status() },
+        IpcRequest::RestartHttps => IpcResponse::HttpsRestart { restarted: ssl_server::request_restart() },
+        IpcRequest::Upgrade => match handoff::upgrade(config_path).await {
+            Ok(pid) => IpcResponse::Upgraded { pid },
+            Err(e) => IpcResponse::Error { message: format!("Upgrade failed, still running: {}", e) },
+        },
+    }
+}
+
+fn default_resolve_path() -> String {
+    "/".to_string()
+}
+
+fn handle_connection(stream: LocalSocketStream, config_path: &Path, runtime: &Handle) {
+    let privileged = restrict_connection(&stream);
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    let response = match reader.read_line(&mut line) {
+        Ok(_) => match serde_json::from_str::<IpcRequest>(line.trim()) {
+            Ok(IpcRequest::Upgrade | IpcRequest::RestartHttps) if !privileged => {
+                IpcResponse::Error { message: "upgrade and restart_https are only accepted from root or the user minipx runs as".to_string() }
+            }
+            Ok(request) => {
+                trace!("IPC request: {:?}", request);
+                // The IPC server runs on its own threads, outside the runtime, so it can block on the handler
+                runtime.block_on(handle_request(request, config_path))
+            }
+            Err(e) => IpcResponse::Error { message: format!("Invalid IPC request: {}", e) },
+        },
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            debug!("IPC client sent no request within {:?}, closing", READ_TIMEOUT);
+            return;
+        }
+        Err(e) => IpcResponse::Error { message: format!("Failed to read IPC request: {}", e) },
+    };
+    let mut stream = reader.into_inner();
+    if let Ok(payload) = serde_json::to_vec(&response) {
+        let _ = stream.write_all(&payload);
+        let _ = stream.flush();
+    }
+}
+
+// The socket is reachable by every local user (an abstract socket on Linux), so a client that never sends its
+// request is cut off after READ_TIMEOUT, and only root or the user minipx runs as may upgrade or restart HTTPS.
+// Returns whether the peer is one of them.
+#[cfg(unix)]
+fn restrict_connection(stream: &LocalSocketStream) -> bool {
+    use std::os::fd::{AsFd, AsRawFd};
+    let LocalSocketStream::UdSocket(socket) = stream;
+    let fd = socket.as_fd().as_raw_fd();
+    let timeout = libc::timeval { tv_sec: READ_TIMEOUT.as_secs() as libc::time_t, tv_usec: 0 };
+    let size = std::mem::size_of::<libc::timeval>() as libc::socklen_t;
+    // SAFETY: fd stays open for the call, and timeout is a timeval of the given size
+    if unsafe { libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_RCVTIMEO, (&timeout as *const libc::timeval).cast(), size) } == -1 {
+        warn!("Failed to set the IPC read timeout: {}", std::io::Error::last_os_error());
+    }
+    match peer_uid(fd) {
+        // SAFETY: geteuid has no preconditions
+        Some(uid) => uid == 0 || uid == unsafe { libc::geteuid() },
+        None => false,
+    }
+}
+
+// On Windows the named pipe's default access only lets the creator's account, administrators and SYSTEM write a
+// request, so those peers are trusted; a client that never sends its request only holds its own thread.
+#[cfg(not(unix))]
+fn restrict_connection(_stream: &LocalSocketStream) -> bool {
+    true
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(fd: std::os::fd::RawFd) -> Option<u32> {
+    let mut credentials = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut size = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: credentials is a ucred of the given size that getsockopt fills in
+    let result = unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_PEERCRED, (&mut credentials as *mut libc::ucred).cast(), &mut size) };
+    (result == 0).then_some(credentials.uid)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(fd: std::os::fd::RawFd) -> Option<u32> {
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: getpeereid only writes the two ids
+    (unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } == 0).then_some(uid)
+}
+
+/// Start the IPC server; must be called from within the Tokio runtime that serves the proxy
 pub fn start_ipc_server(config_path: PathBuf) {
+    let runtime = match Handle::try_current() {
+        Ok(handle) => handle,
+        Err(e) => {
+            warn!("IPC server not started: no Tokio runtime ({})", e);
+            return;
+        }
+    };
     std::thread::spawn(move || {
         let name: Name = match SOCKET_NAME.to_ns_name::<GenericNamespaced>() {
             Ok(n) => n,
             Err(_) => return,
         };
-        let listener: LocalSocketListener = match ListenerOptions::new().name(name.borrow()).create_sync() {
-            Ok(l) => l,
-            Err(e) => {
-                warn!("IPC server bind failed (likely already running): {}", e);
-                return;
+        let listener: LocalSocketListener = loop {
+            match ListenerOptions::new().name(name.borrow()).create_sync() {
+                Ok(l) => break l,
+                // The process this one took over from keeps the socket until it has drained
+                Err(e) if handoff::is_taking_over() => {
+                    debug!("IPC socket still held by the previous process: {}", e);
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+                Err(e) => {
+                    warn!("IPC server bind failed (likely already running): {}", e);
+                    return;
+                }
             }
         };
         debug!("IPC server listening on '{}'", SOCKET_NAME);
         for conn in listener.incoming() {
             match conn {
-                Ok(mut stream) => {
-                    use std::io::Write;
-                    trace!("IPC client connected, sending config path");
-                    let payload = config_path.to_string_lossy().into_owned();
-                    let _ = stream.write_all(payload.as_bytes());
-                    let _ = stream.flush();
+                Ok(stream) => {
+                    trace!("IPC client connected");
+                    // One thread per client so a client that never sends its request cannot block others
+                    let config_path = config_path.clone();
+                    let runtime = runtime.clone();
+                    std::thread::spawn(move || handle_connection(stream, &config_path, &runtime));
                 }
                 Err(e) => {
                     warn!("IPC accept failed: {}", e);
@@ -61,3 +342,76 @@ pub fn start_ipc_server(config_path: PathBuf) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_wire_format() {
+        assert_eq!(serde_json::to_string(&IpcRequest::ConfigPath).unwrap(), r#"{"command":"config_path"}"#);
+        let request: IpcRequest = serde_json::from_str(r#"{"command":"drain_status","domain":"example.com"}"#).unwrap();
+        assert_eq!(request, IpcRequest::DrainStatus { domain: "example.com".to_string() });
+        // Older clients send route_stats without `reset`
+        let request: IpcRequest = serde_json::from_str(r#"{"command":"route_stats"}"#).unwrap();
+        assert_eq!(request, IpcRequest::RouteStats { reset: false });
+        assert_eq!(serde_json::to_string(&IpcRequest::RouteStats { reset: true }).unwrap(), r#"{"command":"route_stats","reset":true}"#);
+    }
+
+    #[test]
+    fn test_capture_request_wire_format() {
+        let request: IpcRequest = serde_json::from_str(r#"{"command":"capture","domain":"example.com","enabled":true,"max_bytes":65536}"#).unwrap();
+        let options = CaptureOptions { max_bytes: Some(65536), ..Default::default() };
+        assert_eq!(request, IpcRequest::Capture { domain: "example.com".to_string(), enabled: Some(true), options });
+        // The capture directory only comes from the config file
+        let request: IpcRequest = serde_json::from_str(r#"{"command":"capture","domain":"example.com","enabled":true,"dir":"/etc"}"#).unwrap();
+        assert_eq!(request, IpcRequest::Capture { domain: "example.com".to_string(), enabled: Some(true), options: CaptureOptions::default() });
+    }
+
+    #[test]
+    fn test_resolve_request_defaults_to_root_path() {
+        let request: IpcRequest = serde_json::from_str(r#"{"command":"resolve","host":"example.com"}"#).unwrap();
+        assert_eq!(request, IpcRequest::Resolve { host: "example.com".to_string(), path: "/".to_string() });
+    }
+
+    #[tokio::test]
+    async fn test_handle_config_path_request() {
+        let response = handle_request(IpcRequest::ConfigPath, Path::new("/etc/minipx/minipx.json")).await;
+        assert_eq!(response, IpcResponse::ConfigPath { path: "/etc/minipx/minipx.json".to_string() });
+    }
+
+    #[test]
+    fn test_config_edits_are_not_accepted_over_the_socket() {
+        for line in [r#"{"command":"remove_route","domain":"example.com"}"#, r#"{"command":"reload"}"#] {
+            assert!(serde_json::from_str::<IpcRequest>(line).is_err(), "{}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_resolve_unknown_host() {
+        let request = IpcRequest::Resolve { host: "unknown.ipc.test".to_string(), path: "/".to_string() };
+        assert_eq!(handle_request(request, Path::new("")).await, IpcResponse::Resolution { resolution: None });
+    }
+
+    #[tokio::test]
+    async fn test_handle_drain_status_unknown_route() {
+        let response = handle_request(IpcRequest::DrainStatus { domain: "unknown.ipc.test".to_string() }, Path::new("")).await;
+        assert_eq!(response, IpcResponse::DrainStatus { status: None });
+    }
+
+    #[tokio::test]
+    async fn test_restart_https_when_not_serving() {
+        // No HTTPS server runs in unit tests
+        assert_eq!(handle_request(IpcRequest::RestartHttps, Path::new("")).await, IpcResponse::HttpsRestart { restarted: false });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_connections_get_a_read_timeout_and_the_own_user_is_privileged() {
+        let (server, _client) = std::os::unix::net::UnixStream::pair().unwrap();
+        let observed = server.try_clone().unwrap();
+        let stream = LocalSocketStream::from(interprocess::os::unix::uds_local_socket::Stream::from(server));
+        assert!(restrict_connection(&stream));
+        assert_eq!(observed.read_timeout().unwrap(), Some(READ_TIMEOUT));
+    }
+}
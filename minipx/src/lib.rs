@@ -1,5 +1,16 @@
+pub mod acme_account;
+#[cfg(feature = "admin-api")]
+pub mod admin;
+pub mod cert_resolver;
 pub mod config;
+pub mod handoff;
 pub mod ipc;
+pub mod log_capture;
+pub mod preflight;
 pub mod proxy;
+pub mod runtime_tuning;
 pub mod ssl_server;
+pub mod startup;
+pub mod systemd;
+pub mod tls_events;
 pub mod utils;
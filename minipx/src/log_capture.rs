@@ -0,0 +1,386 @@
+//! A `log` backend that keeps a copy of the process's log records, so the web panel can show them without SSH.
+//!
+//! Every record the console logger accepts is also sent, without blocking, over a bounded channel to a collector
+//! thread. The collector keeps the most recent records in a ring buffer, broadcasts them to live subscribers and
+//! appends them to an optional file, one `<time> <LEVEL> <target>: <message>` line each, which `tail_file` and
+//! `FileFollower` read back when the panel runs in another process.
+
+use crate::utils::timestamp::{format_rfc3339, parse_rfc3339};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+
+/// Records kept in memory by default
+pub const DEFAULT_CAPACITY: usize = 2000;
+/// Most of a log file `tail_file` reads, from its end
+pub const TAIL_READ_BYTES: u64 = 4 * 1024 * 1024;
+// Records waiting for the collector; more are dropped (and counted) rather than slowing down the logging thread
+const CHANNEL_CAPACITY: usize = 4096;
+// Records a live subscriber may fall behind before it skips ahead
+const SUBSCRIBER_CAPACITY: usize = 256;
+
+static CAPTURE: OnceLock<Arc<LogBuffer>> = OnceLock::new();
+
+/// One captured log record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// RFC 3339, UTC
+    pub time: String,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogRecord {
+    pub fn new(level: Level, target: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { time: format_rfc3339(OffsetDateTime::now_utc()), level, target: target.into(), message: message.into() }
+    }
+
+    // The first line of a record as written to the log file, or None for a continuation line of a multi-line message
+    fn parse_line(line: &str) -> Option<Self> {
+        let (time, rest) = line.split_once(' ')?;
+        parse_rfc3339(time).ok()?;
+        let (level, rest) = rest.trim_start().split_once(' ')?;
+        let level = Level::from_str(level).ok()?;
+        let (target, message) = rest.trim_start().split_once(": ")?;
+        Some(Self { time: time.to_string(), level, target: target.to_string(), message: message.to_string() })
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:<5} {}: {}", self.time, self.level, self.target, self.message)
+    }
+}
+
+/// Records parsed from log file lines; lines that do not start a record are continuation lines of the previous
+/// one, and are dropped when there is none
+pub fn parse_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<LogRecord> {
+    let mut records: Vec<LogRecord> = Vec::new();
+    for line in lines {
+        match (LogRecord::parse_line(line), records.last_mut()) {
+            (Some(record), _) => records.push(record),
+            (None, Some(last)) => {
+                last.message.push('\n');
+                last.message.push_str(line);
+            }
+            (None, None) => {}
+        }
+    }
+    records
+}
+
+/// The last `lines` records at or above `level` in the log file at `path`, from at most its last `TAIL_READ_BYTES`
+pub fn tail_file(path: impl AsRef<Path>, lines: usize, level: LevelFilter) -> io::Result<Vec<LogRecord>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(TAIL_READ_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut content = Vec::new();
+    file.take(TAIL_READ_BYTES).read_to_end(&mut content)?;
+    let content = String::from_utf8_lossy(&content);
+    // Reading from the middle of the file starts in the middle of a line
+    let content = if start > 0 { content.split_once('\n').map(|(_, rest)| rest).unwrap_or_default() } else { &content };
+    Ok(last_at_level(parse_lines(content.lines()), lines, level))
+}
+
+/// Records appended to a log file since the follower was created, read on each `poll`
+#[derive(Debug)]
+pub struct FileFollower {
+    path: PathBuf,
+    offset: u64,
+    partial: String,
+}
+
+impl FileFollower {
+    /// Follow `path` from its current end (or its start, once it exists, when it does not yet)
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let offset = std::fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or_default();
+        Self { path, offset, partial: String::new() }
+    }
+
+    /// Complete records appended since the last poll. A file that shrank was rotated or truncated, and is read
+    /// again from its start.
+    pub fn poll(&mut self) -> io::Result<Vec<LogRecord>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+        if len == self.offset {
+            return Ok(Vec::new());
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut content = Vec::new();
+        file.take(len - self.offset).read_to_end(&mut content)?;
+        self.offset += content.len() as u64;
+        self.partial.push_str(&String::from_utf8_lossy(&content));
+
+        // Only whole lines; the rest waits for the next poll
+        let Some(end) = self.partial.rfind('\n') else {
+            return Ok(Vec::new());
+        };
+        let complete: String = self.partial.drain(..=end).collect();
+        Ok(parse_lines(complete.lines()))
+    }
+}
+
+/// The most recent records, and their live subscribers
+#[derive(Debug)]
+pub struct LogBuffer {
+    records: Mutex<VecDeque<LogRecord>>,
+    capacity: usize,
+    sender: broadcast::Sender<LogRecord>,
+    file: Option<PathBuf>,
+    dropped: AtomicU64,
+}
+
+impl LogBuffer {
+    /// A buffer keeping up to `capacity` records, not attached to any logger
+    pub fn new(capacity: usize) -> Self {
+        Self::with_file(capacity, None)
+    }
+
+    fn with_file(capacity: usize, file: Option<PathBuf>) -> Self {
+        let (sender, _) = broadcast::channel(SUBSCRIBER_CAPACITY);
+        Self { records: Mutex::new(VecDeque::with_capacity(capacity.min(DEFAULT_CAPACITY))), capacity, sender, file, dropped: AtomicU64::new(0) }
+    }
+
+    /// Keep `record`, forgetting the oldest one when full, and send it to the subscribers
+    pub fn push(&self, record: LogRecord) {
+        if self.capacity > 0 {
+            let mut records = self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record.clone());
+        }
+        let _ = self.sender.send(record);
+    }
+
+    /// The last `lines` records at or above `level`, oldest first
+    pub fn tail(&self, lines: usize, level: LevelFilter) -> Vec<LogRecord> {
+        let records = self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        last_at_level(records.iter().cloned(), lines, level)
+    }
+
+    /// Records pushed from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<LogRecord> {
+        self.sender.subscribe()
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// File the records are also appended to
+    pub fn get_file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Records lost because the collector fell behind
+    pub fn get_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// What `install` captures records into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogCaptureOptions {
+    /// Records kept in memory; 0 keeps none (live subscribers still get them)
+    pub capacity: usize,
+    /// File every record is appended to
+    pub file: Option<PathBuf>,
+}
+
+impl Default for LogCaptureOptions {
+    fn default() -> Self {
+        Self { capacity: DEFAULT_CAPACITY, file: None }
+    }
+}
+
+/// Make `inner` (e.g. the console logger) the process's logger, capturing what it accepts as `options` say.
+/// Fails when a logger is already set. The log file is opened here, so a bad path is reported before anything runs.
+pub fn install(inner: Box<dyn Log>, max_level: LevelFilter, options: LogCaptureOptions) -> io::Result<Arc<LogBuffer>> {
+    let (logger, buffer) = CaptureLogger::new(inner, options)?;
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| io::Error::new(io::ErrorKind::AlreadyExists, e))?;
+    log::set_max_level(max_level);
+    let _ = CAPTURE.set(buffer.clone());
+    Ok(buffer)
+}
+
+/// The records captured by `install`, or None when this process logs without capturing (e.g. a standalone panel)
+pub fn captured() -> Option<Arc<LogBuffer>> {
+    CAPTURE.get().cloned()
+}
+
+// Records are copied to the collector only after `inner` accepted them, so the capture logs what the console does
+struct CaptureLogger {
+    inner: Box<dyn Log>,
+    sender: SyncSender<LogRecord>,
+    buffer: Arc<LogBuffer>,
+}
+
+impl CaptureLogger {
+    fn new(inner: Box<dyn Log>, options: LogCaptureOptions) -> io::Result<(Self, Arc<LogBuffer>)> {
+        let mut file = match &options.file {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        let buffer = Arc::new(LogBuffer::with_file(options.capacity, options.file));
+        let (sender, receiver) = mpsc::sync_channel::<LogRecord>(CHANNEL_CAPACITY);
+
+        let collected = buffer.clone();
+        std::thread::Builder::new().name("minipx-log-capture".to_string()).spawn(move || {
+            let mut file_failed = false;
+            for record in receiver {
+                // Logging the failure would feed it back into this channel
+                if let Some(out) = &mut file
+                    && let Err(e) = writeln!(out, "{}", record)
+                    && !file_failed
+                {
+                    eprintln!("minipx: failed to write the log file: {}", e);
+                    file_failed = true;
+                }
+                collected.push(record);
+            }
+        })?;
+        Ok((Self { inner, sender, buffer: buffer.clone() }, buffer))
+    }
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        self.inner.log(record);
+        let captured = LogRecord::new(record.level(), record.target(), record.args().to_string());
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(captured) {
+            self.buffer.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// The last `lines` of `records` at or above `level`, oldest first
+fn last_at_level(records: impl IntoIterator<Item = LogRecord, IntoIter: DoubleEndedIterator>, lines: usize, level: LevelFilter) -> Vec<LogRecord> {
+    let mut tail: Vec<_> = records.into_iter().rev().filter(|record| record.level <= level).take(lines).collect();
+    tail.reverse();
+    tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    struct Console;
+
+    impl Log for Console {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Info
+        }
+
+        fn log(&self, _: &Record) {}
+
+        fn flush(&self) {}
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minipx-log-capture-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_records_round_trip_through_file_lines() {
+        let warn = LogRecord::new(Level::Warn, "minipx::proxy", "502 for app.test/ from 10.0.0.1");
+        let multi = LogRecord::new(Level::Error, "minipx", "Config reload failed:\n  at line 3");
+        let text = format!("garbage before the first record\n{}\n{}\n", warn, multi);
+        assert_eq!(parse_lines(text.lines()), vec![warn, multi]);
+    }
+
+    #[test]
+    fn test_tail_keeps_the_most_recent_records_at_a_level() {
+        let buffer = LogBuffer::new(3);
+        for (i, level) in [Level::Error, Level::Info, Level::Warn, Level::Debug, Level::Warn].into_iter().enumerate() {
+            buffer.push(LogRecord::new(level, "minipx", format!("record {}", i)));
+        }
+        let messages = |records: Vec<LogRecord>| records.into_iter().map(|record| record.message).collect::<Vec<_>>();
+        assert_eq!(messages(buffer.tail(10, LevelFilter::Trace)), ["record 2", "record 3", "record 4"]);
+        assert_eq!(messages(buffer.tail(10, LevelFilter::Warn)), ["record 2", "record 4"]);
+        assert_eq!(messages(buffer.tail(1, LevelFilter::Warn)), ["record 4"]);
+        assert!(buffer.tail(10, LevelFilter::Error).is_empty());
+    }
+
+    #[test]
+    fn test_logged_records_reach_the_buffer_subscribers_and_file() {
+        let dir = temp_dir("logger");
+        let path = dir.join("minipx.log");
+        let (logger, buffer) = CaptureLogger::new(Box::new(Console), LogCaptureOptions { capacity: 10, file: Some(path.clone()) }).unwrap();
+        let mut live = buffer.subscribe();
+
+        for (level, message) in [(Level::Info, "started"), (Level::Debug, "not shown on the console"), (Level::Warn, "backend slow")] {
+            logger.log(&Record::builder().level(level).target("minipx::proxy").args(format_args!("{}", message)).build());
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while buffer.tail(10, LevelFilter::Trace).len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let captured = buffer.tail(10, LevelFilter::Trace);
+        assert_eq!(captured.iter().map(|record| record.message.as_str()).collect::<Vec<_>>(), ["started", "backend slow"]);
+        assert_eq!(live.try_recv().unwrap().message, "started");
+        assert_eq!(tail_file(&path, 1, LevelFilter::Trace).unwrap(), captured[1..]);
+        assert_eq!(tail_file(&path, 10, LevelFilter::Warn).unwrap(), captured[1..]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_follower_returns_complete_records_appended_since_the_last_poll() {
+        let dir = temp_dir("follow");
+        let path = dir.join("minipx.log");
+        let old = LogRecord::new(Level::Info, "minipx", "before following");
+        std::fs::write(&path, format!("{}\n", old)).unwrap();
+        let mut follower = FileFollower::new(&path);
+        assert!(follower.poll().unwrap().is_empty());
+
+        let new = LogRecord::new(Level::Warn, "minipx", "after");
+        let line = format!("{}\n", new);
+        let (first, second) = line.split_at(10);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(first.as_bytes()).unwrap();
+        assert!(follower.poll().unwrap().is_empty());
+        file.write_all(second.as_bytes()).unwrap();
+        assert_eq!(follower.poll().unwrap(), vec![new.clone()]);
+
+        // Truncated (rotated): read again from the start
+        std::fs::write(&path, format!("{}\n", old)).unwrap();
+        assert_eq!(follower.poll().unwrap(), vec![old]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
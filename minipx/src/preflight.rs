@@ -0,0 +1,648 @@
+//! Environment checks for the things that keep a correct config from working.
+//!
+//! `minipx preflight` runs them on demand and the daemon runs them (non-fatally) at startup: whether the HTTP,
+//! HTTPS, forwarder, health and admin ports can be bound, whether `cache_dir` is writable, whether backend hosts
+//! and ACME domains resolve (and, when `public_ip_check_url` is set, whether the domains point at this machine),
+//! clock skew, the ACME email and the semantic checks the config loader does not reject outright. Every check
+//! ends up as a pass/warn/fail row with a remediation hint.
+
+use crate::acme_account;
+use crate::config::{Config, ConfigIssueKind, ListenerPorts, ResolverConfig, ResolverMode, TlsFallback};
+use crate::handoff;
+use crate::proxy::forwarder::forwarder_port;
+use crate::proxy::resolver::Resolver;
+use crate::systemd;
+use crate::utils::validation::validate_custom_port;
+use anyhow::{Result, anyhow};
+use futures_util::future::join_all;
+use hyper::{Body, Client, Request, header};
+use hyper_tls::HttpsConnector;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use time::OffsetDateTime;
+
+/// How long a single DNS lookup or the public IP request may take
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Clock skew against the public IP service's `Date` header beyond which ACME is likely to fail
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+const PRIVILEGED_PORT_HINT: &str = "Ports below 1024 need root or CAP_NET_BIND_SERVICE (sudo setcap cap_net_bind_service=+ep $(which minipx))";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        }
+    }
+}
+
+/// One row of the preflight report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    /// What was checked: `config`, `email`, `cache_dir`, `port`, `backend_dns`, `acme_dns`, `public_ip` or `clock`
+    pub check: String,
+    /// The port, host or setting the check is about
+    pub subject: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// How to fix a warning or failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn new(status: CheckStatus, check: &str, subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { check: check.to_string(), subject: subject.into(), status, detail: detail.into(), hint: None }
+    }
+
+    pub fn pass(check: &str, subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(CheckStatus::Pass, check, subject, detail)
+    }
+
+    pub fn warn(check: &str, subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(CheckStatus::Warn, check, subject, detail)
+    }
+
+    pub fn fail(check: &str, subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(CheckStatus::Fail, check, subject, detail)
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PreflightReport {
+    pub checks: Vec<Check>,
+}
+
+impl PreflightReport {
+    pub fn push(&mut self, check: Check) {
+        self.checks.push(check);
+    }
+
+    pub fn count(&self, status: CheckStatus) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.count(CheckStatus::Fail) > 0
+    }
+
+    /// Log the warnings and failures plus a one-line summary (the startup form of the report)
+    pub fn log(&self) {
+        for check in &self.checks {
+            let hint = check.hint.as_deref().map(|hint| format!(" ({})", hint)).unwrap_or_default();
+            match check.status {
+                CheckStatus::Pass => {}
+                CheckStatus::Warn => warn!("Preflight {} {}: {}{}", check.check, check.subject, check.detail, hint),
+                CheckStatus::Fail => error!("Preflight {} {}: {}{}", check.check, check.subject, check.detail, hint),
+            }
+        }
+        info!(
+            "Preflight: {} passed, {} warnings, {} failures",
+            self.count(CheckStatus::Pass),
+            self.count(CheckStatus::Warn),
+            self.count(CheckStatus::Fail)
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PreflightOptions {
+    /// Try to bind the ports the proxy will listen on; skip when they are held by a running instance
+    pub check_ports: bool,
+    /// Overrides the config's `public_ip_check_url`
+    pub public_ip_url: Option<String>,
+    pub timeout: Duration,
+}
+
+impl PreflightOptions {
+    pub fn for_config(config: &Config) -> Self {
+        Self { check_ports: true, public_ip_url: config.get_public_ip_check_url().map(String::from), timeout: DEFAULT_TIMEOUT }
+    }
+}
+
+/// Run every check against `config`
+pub async fn run(config: &Config, options: &PreflightOptions) -> PreflightReport {
+    let mut report = PreflightReport::default();
+    check_config(config, &mut report);
+    report.push(check_email(config));
+    report.push(check_cache_dir(Path::new(config.get_cache_dir())));
+    if let Some(check) = check_acme_account(config) {
+        report.push(check);
+    }
+    if options.check_ports {
+        for port in planned_ports(config) {
+            report.push(check_port(&port));
+        }
+    }
+    check_backend_dns(config, options.timeout, &mut report).await;
+    check_acme_dns(config, options, &mut report).await;
+    report
+}
+
+/// A socket the proxy will bind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedPort {
+    /// What listens there, e.g. `http` or `forwarder for game.example.com`
+    pub owner: String,
+    pub addr: SocketAddr,
+    pub udp: bool,
+    /// Passed in by systemd socket activation or a warm restart, so there is nothing to bind
+    pub activated: bool,
+}
+
+impl PlannedPort {
+    fn new(owner: impl Into<String>, addr: SocketAddr, udp: bool) -> Self {
+        Self { owner: owner.into(), addr, udp, activated: false }
+    }
+
+    fn label(&self) -> String {
+        format!("{}/{}", self.addr.port(), if self.udp { "udp" } else { "tcp" })
+    }
+}
+
+/// The sockets the proxy binds for `config`, in the same places the servers bind them
+pub fn planned_ports(config: &Config) -> Vec<PlannedPort> {
+    let mut ports = vec![PlannedPort {
+        activated: handoff::inherited_listener("http").is_some() || systemd::activated_listener("http").is_some(),
+        ..PlannedPort::new("http", SocketAddr::from(([0, 0, 0, 0], 80)), false)
+    }];
+    if config.is_ssl_enabled() {
+        let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, 443));
+        let activated = handoff::inherited_listener("https").is_some() || systemd::activated_listener("https").is_some();
+        ports.push(PlannedPort { activated, ..PlannedPort::new("https", addr, false) });
+    }
+    // One forwarder per listen port, like `setup_forwarders`
+    let mut forwarders: BTreeMap<u16, &String> = BTreeMap::new();
+    for (domain, route) in config.get_routes() {
+        if let Some(port) = forwarder_port(route) {
+            forwarders.entry(port).and_modify(|d| *d = (*d).min(domain)).or_insert(domain);
+        }
+    }
+    for (port, domain) in forwarders {
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        ports.push(PlannedPort::new(format!("forwarder for {}", domain), addr, false));
+        ports.push(PlannedPort::new(format!("forwarder for {}", domain), addr, true));
+    }
+    if let Some(port) = config.get_health_endpoint().get_port() {
+        ports.push(PlannedPort::new("health endpoint", SocketAddr::from(([0, 0, 0, 0], port)), false));
+    }
+    if cfg!(feature = "admin-api") && config.get_admin_api().is_enabled() {
+        ports.push(PlannedPort::new("admin api", SocketAddr::from((Ipv4Addr::LOCALHOST, config.get_admin_api().get_port())), false));
+    }
+    ports
+}
+
+/// Bind and immediately release `port`
+pub fn check_port(port: &PlannedPort) -> Check {
+    let subject = port.label();
+    if port.activated {
+        return Check::pass("port", subject, format!("{} socket passed in by systemd or a warm restart", port.owner));
+    }
+    let bound = if port.udp { std::net::UdpSocket::bind(port.addr).map(drop) } else { std::net::TcpListener::bind(port.addr).map(drop) };
+    match bound {
+        Ok(()) => Check::pass("port", subject, format!("{} can bind {}", port.owner, port.addr)),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            Check::fail("port", subject, format!("{} cannot bind {}: already in use", port.owner, port.addr))
+                .with_hint(format!("Another process (e.g. apache or nginx) holds port {}; stop it or move it to another port", port.addr.port()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            let check = Check::fail("port", subject, format!("{} cannot bind {}: permission denied", port.owner, port.addr));
+            if port.addr.port() < 1024 { check.with_hint(PRIVILEGED_PORT_HINT) } else { check }
+        }
+        Err(e) => Check::fail("port", subject, format!("{} cannot bind {}: {}", port.owner, port.addr, e)),
+    }
+}
+
+/// Whether files can be created in `dir`, or in its nearest existing ancestor when it does not exist yet.
+/// Leaves nothing behind.
+pub fn check_cache_dir(dir: &Path) -> Check {
+    let subject = dir.display().to_string();
+    let hint = "Point cache_dir at a directory the minipx user can write to";
+    let existing = dir.ancestors().find(|p| p.as_os_str().is_empty() || p.exists()).unwrap_or(dir);
+    let probe_dir = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+    if !probe_dir.is_dir() {
+        return Check::fail("cache_dir", subject, format!("{} is not a directory", probe_dir.display())).with_hint(hint);
+    }
+    let probe = probe_dir.join(format!(".minipx-preflight-{}", std::process::id()));
+    match std::fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            let detail = if existing == dir { "writable".to_string() } else { format!("does not exist yet; {} is writable", probe_dir.display()) };
+            Check::pass("cache_dir", subject, detail)
+        }
+        Err(e) => Check::fail("cache_dir", subject, format!("cannot write to {}: {}", probe_dir.display(), e)).with_hint(hint),
+    }
+}
+
+pub fn check_email(config: &Config) -> Check {
+    let email = config.get_email();
+    let hint = "Set it with: minipx config email you@example.com";
+    match (config.is_ssl_enabled(), email.is_empty(), config.is_email_valid()) {
+        (_, false, true) => Check::pass("email", email.as_str(), "valid"),
+        (true, true, _) => Check::fail("email", "(unset)", "HTTPS routes need an email for the ACME account").with_hint(hint),
+        (true, false, false) => Check::fail("email", email.as_str(), "not a valid email address; certificates cannot be requested").with_hint(hint),
+        (false, true, _) => Check::pass("email", "(unset)", "not needed without HTTPS routes"),
+        (false, false, false) => {
+            Check::warn("email", email.as_str(), "not a valid email address (unused until a route enables HTTPS)").with_hint(hint)
+        }
+    }
+}
+
+/// The ACME account in cache_dir must belong to the configured email, or issuance silently registers a new one
+pub fn check_acme_account(config: &Config) -> Option<Check> {
+    if !config.is_ssl_enabled() || !config.is_email_valid() {
+        return None;
+    }
+    let subject = "cache_dir";
+    Some(match acme_account::account_info(config.get_cache_dir(), config.get_email()) {
+        Ok(info) => match (info.contact_warning(), &info.thumbprint) {
+            (Some(warning), _) => Check::warn("acme account", subject, warning)
+                .with_hint("Keep the old email, or move the account with: minipx acme account export / import"),
+            (None, Some(thumbprint)) => Check::pass("acme account", subject, format!("registered for {} (key {})", config.get_email(), thumbprint)),
+            (None, None) => Check::pass("acme account", subject, "none yet; one is registered with the first certificate"),
+        },
+        Err(e) => Check::fail("acme account", subject, e.to_string()),
+    })
+}
+
+/// Semantic problems the loader accepts but that make a route misbehave
+pub fn check_config(config: &Config, report: &mut PreflightReport) {
+    let before = report.checks.len();
+    let mut domains: Vec<&String> = config.get_routes().keys().collect();
+    domains.sort();
+
+    for issue in config.get_issues() {
+        let detail = match &issue.kind {
+            ConfigIssueKind::UnknownKey => "unknown key, ignored".to_string(),
+            ConfigIssueKind::FellBack(message) => message.clone(),
+        };
+        report.push(Check::fail("config", issue.path.as_str(), detail).with_hint("Fix the key or value; it is currently not in effect"));
+    }
+
+    if domains.is_empty() {
+        report.push(
+            Check::warn("config", "routes", "no routes configured; every request gets a 404")
+                .with_hint("Add one with: minipx routes add <domain> -P <port>"),
+        );
+    }
+    let mut forwarders: BTreeMap<u16, Vec<&String>> = BTreeMap::new();
+    let listeners = ListenerPorts::for_config(config);
+    for domain in &domains {
+        let route = &config.get_routes()[*domain];
+        if let Err(err) = config.check_self_reference(domain, route, &listeners) {
+            report.push(
+                Check::fail("config", domain.as_str(), err.to_string()).with_hint(format!("minipx routes update {} --port <backend port>", domain)),
+            );
+        } else if let Err(err) = route.check_unix_socket() {
+            report.push(Check::fail("config", domain.as_str(), err.to_string()));
+        } else if route.get_unix_socket().is_none()
+            && let Err(err) = validate_custom_port(route.get_port())
+        {
+            report.push(Check::fail("config", domain.as_str(), format!("backend port: {}", err)));
+        }
+        if route.get_redirect_to_https() && !route.is_ssl_enabled() {
+            report.push(
+                Check::warn("config", domain.as_str(), "redirect_to_https is ignored because the route does not enable HTTPS")
+                    .with_hint(format!("minipx routes update {} --ssl", domain)),
+            );
+        }
+        if route.is_ssl_enabled() && domain.starts_with("*.") {
+            report.push(
+                Check::warn("config", domain.as_str(), "no certificate can be issued for a wildcard name; HTTPS requests for it fail the handshake")
+                    .with_hint("Add the subdomains that need HTTPS as their own routes, or use tls_fallback"),
+            );
+        } else if route.is_ssl_enabled() && !Config::validate_domain(domain) {
+            report.push(Check::fail("config", domain.as_str(), "not a valid domain name for a certificate"));
+        }
+        if let Err(err) = route.check_upstream_names() {
+            report.push(Check::fail("config", domain.as_str(), err.to_string()));
+        }
+        if let Err(err) = route.check_activation_window() {
+            report.push(Check::fail("config", domain.as_str(), format!("{}; the route is never served", err)));
+        } else if route.get_active_until().is_some_and(|until| until <= OffsetDateTime::now_utc()) {
+            report.push(
+                Check::warn("config", domain.as_str(), "active_until has passed; the route is no longer served")
+                    .with_hint(format!("minipx routes update {} --active-until \"\"", domain)),
+            );
+        }
+        if let Some(port) = forwarder_port(route) {
+            forwarders.entry(port).or_default().push(domain);
+        }
+    }
+    for (port, domains) in forwarders {
+        let backends: BTreeSet<(&str, u16)> =
+            domains.iter().map(|d| (config.get_routes()[*d].get_host(), config.get_routes()[*d].get_port())).collect();
+        if backends.len() > 1 {
+            report.push(
+                Check::warn(
+                    "config",
+                    format!("listen_port {}", port),
+                    format!("shared by {} with different backends; only one of them is forwarded", join(&domains)),
+                )
+                .with_hint("Give each forwarded route its own listen_port"),
+            );
+        }
+    }
+
+    // Two servers on one socket: the later one never binds
+    let mut sockets: BTreeMap<(u16, bool), Vec<String>> = BTreeMap::new();
+    for port in planned_ports(config) {
+        sockets.entry((port.addr.port(), port.udp)).or_default().push(port.owner);
+    }
+    for ((port, udp), owners) in sockets {
+        if owners.len() > 1 {
+            let label = format!("{}/{}", port, if udp { "udp" } else { "tcp" });
+            report.push(Check::fail("config", label, format!("used by both {}", owners.join(" and "))).with_hint("Move one of them to another port"));
+        }
+    }
+
+    #[allow(clippy::collapsible_if)]
+    if let TlsFallback::Route(domain) = config.get_tls_fallback() {
+        if config.lookup_host(domain).is_none() {
+            report.push(Check::fail("config", "tls_fallback", format!("routes to '{}', which has no route", domain)));
+        }
+    }
+    if cfg!(feature = "admin-api") && config.get_admin_api().is_enabled() && config.get_admin_api().get_token().is_empty() {
+        report.push(Check::fail("config", "admin_api", "enabled without a token; the admin API refuses to start").with_hint("Set admin_api.token"));
+    }
+
+    let resolver = config.get_resolver();
+    if resolver.get_mode() == ResolverMode::Custom {
+        if cfg!(not(feature = "dns-resolver")) {
+            report.push(
+                Check::warn("config", "resolver", "mode custom needs the dns-resolver feature; the system resolver is used instead")
+                    .with_hint("Build with --features dns-resolver"),
+            );
+        } else if resolver.get_servers().is_empty() {
+            report
+                .push(Check::fail("config", "resolver", "mode custom without servers; only static_hosts resolve").with_hint("Add resolver.servers"));
+        }
+    }
+
+    if report.checks.len() == before {
+        report.push(Check::pass("config", config.get_path().display().to_string(), format!("{} routes, no problems found", domains.len())));
+    }
+}
+
+fn join(domains: &[&String]) -> String {
+    domains.iter().map(|d| d.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+async fn resolve(resolver: &Resolver, host: &str, timeout: Duration) -> Result<Vec<IpAddr>> {
+    match tokio::time::timeout(timeout, resolver.lookup(host, 0)).await {
+        Ok(Ok(addrs)) => {
+            let ips: BTreeSet<IpAddr> = addrs.into_iter().map(|addr| addr.ip()).collect();
+            if ips.is_empty() { Err(anyhow!("no addresses")) } else { Ok(ips.into_iter().collect()) }
+        }
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Err(anyhow!("timed out after {:?}", timeout)),
+    }
+}
+
+fn list(ips: &[IpAddr]) -> String {
+    ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Backend hosts must resolve (through the config's `resolver`), or their routes answer 502
+async fn check_backend_dns(config: &Config, timeout: Duration, report: &mut PreflightReport) {
+    let mut hosts: BTreeMap<&str, Vec<&String>> = BTreeMap::new();
+    for (domain, route) in config.get_routes() {
+        if route.get_host().parse::<IpAddr>().is_err() {
+            hosts.entry(route.get_host()).or_default().push(domain);
+        }
+    }
+    let resolver = Resolver::new(config.get_resolver());
+    let lookups = hosts.keys().map(|host| resolve(&resolver, host, timeout));
+    for ((host, domains), result) in hosts.iter().zip(join_all(lookups).await) {
+        let mut domains = domains.clone();
+        domains.sort();
+        report.push(match result {
+            Ok(ips) => Check::pass("backend_dns", *host, format!("resolves to {}", list(&ips))),
+            Err(e) => Check::fail("backend_dns", *host, format!("does not resolve ({}); {} will answer 502", e, join(&domains)))
+                .with_hint("Check the backend host name, or pin it in resolver.static_hosts"),
+        });
+    }
+}
+
+/// ACME domains must resolve, and should point at this machine for TLS-ALPN-01 validation to reach it
+async fn check_acme_dns(config: &Config, options: &PreflightOptions, report: &mut PreflightReport) {
+    if !config.is_ssl_enabled() {
+        return;
+    }
+    let (domains, _) = config.get_valid_domains_for_acme();
+    if domains.is_empty() {
+        return;
+    }
+
+    let public_ip = match &options.public_ip_url {
+        Some(url) => match fetch_public_ip(url, options.timeout).await {
+            Ok((ip, date)) => {
+                report.push(Check::pass("public_ip", ip.to_string(), format!("reported by {}", url)));
+                if let Some(date) = date {
+                    report.push(check_clock(SystemTime::now(), date));
+                }
+                Some(ip)
+            }
+            Err(e) => {
+                report.push(Check::warn("public_ip", url.as_str(), format!("could not determine this machine's public IP: {}", e)));
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Public names: what the ACME server sees, not the backend resolver
+    let resolver = Resolver::new(&ResolverConfig::default());
+    let lookups = domains.iter().map(|domain| resolve(&resolver, domain, options.timeout));
+    for (domain, result) in domains.iter().zip(join_all(lookups).await) {
+        report.push(match (result, public_ip) {
+            (Err(e), _) => Check::fail("acme_dns", domain.as_str(), format!("does not resolve ({}); no certificate can be issued", e))
+                .with_hint("Create an A/AAAA record pointing at this machine"),
+            (Ok(ips), Some(public_ip)) if !ips.contains(&public_ip) => {
+                Check::warn("acme_dns", domain.as_str(), format!("resolves to {}, not this machine ({})", list(&ips), public_ip))
+                    .with_hint("Point the A/AAAA record at this machine, or forward 443 to it from the address it resolves to")
+            }
+            (Ok(ips), Some(_)) => Check::pass("acme_dns", domain.as_str(), format!("resolves to {} (this machine)", list(&ips))),
+            (Ok(ips), None) => Check::pass("acme_dns", domain.as_str(), format!("resolves to {}", list(&ips))),
+        });
+    }
+}
+
+/// Compare the local clock with a server's `Date` header
+pub fn check_clock(now: SystemTime, server_date: SystemTime) -> Check {
+    let (skew, direction) = match now.duration_since(server_date) {
+        Ok(ahead) => (ahead, "ahead"),
+        Err(behind) => (behind.duration(), "behind"),
+    };
+    let detail = format!("{}s {} of the public IP service", skew.as_secs(), direction);
+    if skew > MAX_CLOCK_SKEW {
+        Check::fail("clock", "system clock", detail)
+            .with_hint("Synchronize the clock (e.g. timedatectl set-ntp true); ACME requests fail with a skewed clock")
+    } else {
+        Check::pass("clock", "system clock", detail)
+    }
+}
+
+/// GET `url`, expecting the caller's IP as plain text; also returns the response's `Date`
+async fn fetch_public_ip(url: &str, timeout: Duration) -> Result<(IpAddr, Option<SystemTime>)> {
+    let request = Request::get(url).header(header::USER_AGENT, concat!("minipx/", env!("CARGO_PKG_VERSION"))).body(Body::empty())?;
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let fetch = async {
+        let response = client.request(request).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("answered {}", response.status()));
+        }
+        let date = response.headers().get(header::DATE).and_then(|v| v.to_str().ok()).and_then(|v| httpdate::parse_http_date(v).ok());
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let ip = std::str::from_utf8(&body)?.trim().parse::<IpAddr>().map_err(|_| anyhow!("answer is not an IP address"))?;
+        Ok((ip, date))
+    };
+    tokio::time::timeout(timeout, fetch).await.map_err(|_| anyhow!("timed out after {:?}", timeout))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProxyRoute;
+
+    fn route(host: &str, port: u16) -> ProxyRoute {
+        ProxyRoute::new(host.to_string(), "".to_string(), port, false, None, false)
+    }
+
+    fn statuses(report: &PreflightReport, check: &str) -> Vec<(String, CheckStatus)> {
+        report.checks.iter().filter(|c| c.check == check).map(|c| (c.subject.clone(), c.status)).collect()
+    }
+
+    #[test]
+    fn test_port_in_use_fails_and_free_port_passes() {
+        let held = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = held.local_addr().unwrap();
+        let check = check_port(&PlannedPort::new("forwarder for a.test", addr, false));
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("already in use"), "{}", check.detail);
+        assert!(check.hint.is_some());
+
+        drop(held);
+        assert_eq!(check_port(&PlannedPort::new("forwarder for a.test", addr, false)).status, CheckStatus::Pass);
+    }
+
+    #[test]
+    fn test_cache_dir_writable_missing_and_not_a_directory() {
+        let root = std::env::temp_dir().join(format!("minipx-preflight-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        assert_eq!(check_cache_dir(&root).status, CheckStatus::Pass);
+
+        let missing = check_cache_dir(&root.join("not/yet"));
+        assert_eq!(missing.status, CheckStatus::Pass);
+        assert!(missing.detail.contains("does not exist yet"));
+        assert!(!root.join("not").exists(), "the check must not create the directory");
+
+        let file = root.join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(check_cache_dir(&file.join("cache")).status, CheckStatus::Fail);
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 1, "probe files are removed");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_email_is_required_only_with_https_routes() {
+        let mut config = Config::default();
+        assert_eq!(check_email(&config).status, CheckStatus::Pass);
+
+        let mut secure = route("127.0.0.1", 8080);
+        secure.ssl_enable = true;
+        config.routes.insert("app.example.com".to_string(), secure);
+        assert_eq!(check_email(&config).status, CheckStatus::Fail);
+
+        config.set_email("not-an-email".to_string());
+        assert_eq!(check_email(&config).status, CheckStatus::Fail);
+        config.set_email("admin@example.com".to_string());
+        assert_eq!(check_email(&config).status, CheckStatus::Pass);
+    }
+
+    #[test]
+    fn test_config_semantic_checks() {
+        let mut report = PreflightReport::default();
+        check_config(&Config::default(), &mut report);
+        assert_eq!(statuses(&report, "config"), vec![("routes".to_string(), CheckStatus::Warn)]);
+
+        let typo = Config { issues: crate::config::audit::audit(&serde_json::json!({"emial": "admin@example.com"})), ..Config::default() };
+        let mut report = PreflightReport::default();
+        check_config(&typo, &mut report);
+        assert!(statuses(&report, "config").contains(&("emial".to_string(), CheckStatus::Fail)));
+
+        let mut config = Config::default();
+        let mut redirecting = route("127.0.0.1", 8080);
+        redirecting.redirect_to_https = true;
+        config.routes.insert("plain.example.com".to_string(), redirecting);
+        let mut game = route("127.0.0.1", 7000);
+        game.listen_port = Some(25565);
+        config.routes.insert("game.example.com".to_string(), game);
+        let mut other = route("127.0.0.1", 7001);
+        other.listen_port = Some(25565);
+        config.routes.insert("other.example.com".to_string(), other);
+        // Points at the forwarder above
+        config.routes.insert("loop.example.com".to_string(), route("localhost", 25565));
+        let mut inverted = route("127.0.0.1", 7002);
+        inverted.active_from = Some(OffsetDateTime::now_utc());
+        inverted.active_until = Some(OffsetDateTime::now_utc() - Duration::from_secs(3600));
+        config.routes.insert("window.example.com".to_string(), inverted);
+        config.set_tls_fallback(TlsFallback::Route("missing.example.com".to_string()));
+        config.health_endpoint.port = Some(25565);
+
+        let mut report = PreflightReport::default();
+        check_config(&config, &mut report);
+        let found = statuses(&report, "config");
+        assert!(found.contains(&("plain.example.com".to_string(), CheckStatus::Warn)), "{:?}", found);
+        assert!(found.contains(&("listen_port 25565".to_string(), CheckStatus::Warn)), "{:?}", found);
+        assert!(found.contains(&("25565/tcp".to_string(), CheckStatus::Fail)), "{:?}", found);
+        assert!(found.contains(&("tls_fallback".to_string(), CheckStatus::Fail)), "{:?}", found);
+        assert!(found.contains(&("loop.example.com".to_string(), CheckStatus::Fail)), "{:?}", found);
+        assert!(found.contains(&("window.example.com".to_string(), CheckStatus::Fail)), "{:?}", found);
+        assert!(report.has_failures());
+    }
+
+    #[tokio::test]
+    async fn test_backend_dns() {
+        let mut config = Config::default();
+        config.routes.insert("a.example.com".to_string(), route("localhost", 8080));
+        config.routes.insert("b.example.com".to_string(), route("backend.invalid", 8080));
+        config.routes.insert("c.example.com".to_string(), route("10.0.0.5", 8080));
+
+        let mut report = PreflightReport::default();
+        check_backend_dns(&config, DEFAULT_TIMEOUT, &mut report).await;
+        assert_eq!(
+            statuses(&report, "backend_dns"),
+            vec![("backend.invalid".to_string(), CheckStatus::Fail), ("localhost".to_string(), CheckStatus::Pass)]
+        );
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let now = SystemTime::now();
+        assert_eq!(check_clock(now, now - Duration::from_secs(3)).status, CheckStatus::Pass);
+        let skewed = check_clock(now, now + Duration::from_secs(600));
+        assert_eq!(skewed.status, CheckStatus::Fail);
+        assert!(skewed.detail.contains("behind"), "{}", skewed.detail);
+    }
+}
@@ -0,0 +1,136 @@
+//! Route activation windows (`active_from` / `active_until`).
+//!
+//! Requests and forwarded connections check the window themselves. The timer task only notices routes crossing a
+//! bound and re-broadcasts the running config, so everything that follows config changes (the HTTPS domain list in
+//! particular) reacts as it would to a reload.
+
+use crate::config::manager::{broadcaster, config_lock};
+use crate::config::{Config, InactiveResponse, ProxyRoute};
+use anyhow::Result;
+use hyper::header::{CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Body, Response, StatusCode};
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// How often the timer re-evaluates the activation windows
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Whether each route of `config` is inside its activation window at `now`, by route key
+pub fn activation_states(config: &Config, now: OffsetDateTime) -> BTreeMap<String, bool> {
+    config.get_routes().iter().map(|(domain, route)| (domain.clone(), route.is_active_at(now))).collect()
+}
+
+/// Routes that turned on and routes that turned off between two `activation_states`. Routes added or removed in
+/// between did not transition.
+pub fn transitions(before: &BTreeMap<String, bool>, after: &BTreeMap<String, bool>) -> (Vec<String>, Vec<String>) {
+    let changed = after.iter().filter(|(domain, active)| before.get(*domain).is_some_and(|was| was != *active));
+    let (activated, deactivated): (Vec<_>, Vec<_>) = changed.partition(|(_, active)| **active);
+    (activated.into_iter().map(|(domain, _)| domain.clone()).collect(), deactivated.into_iter().map(|(domain, _)| domain.clone()).collect())
+}
+
+/// Re-evaluate the running config's activation windows every `CHECK_INTERVAL` and broadcast the config when a route
+/// turned on or off
+pub fn spawn_activation_timer() {
+    tokio::spawn(async {
+        let mut states = activation_states(&Config::get().await, OffsetDateTime::now_utc());
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let config = Config::get().await;
+            let current = activation_states(&config, OffsetDateTime::now_utc());
+            let (activated, deactivated) = transitions(&states, &current);
+            states = current;
+            if activated.is_empty() && deactivated.is_empty() {
+                continue;
+            }
+            for domain in &activated {
+                info!("Route {} entered its activation window", domain);
+            }
+            for domain in &deactivated {
+                info!("Route {} left its activation window", domain);
+            }
+            let _ = broadcaster().send(config);
+        }
+    });
+}
+
+/// True unless the route of `route_key` in the running config is outside its activation window
+pub async fn is_route_active(route_key: &str) -> bool {
+    config_lock().read().await.get_routes().get(route_key).is_none_or(|route| route.is_active_at(OffsetDateTime::now_utc()))
+}
+
+/// What `route` answers at `now`, outside its activation window: 404, or 503 with Retry-After until a later start
+pub fn inactive_response(route: &ProxyRoute, domain: &str, now: OffsetDateTime) -> Result<Response<Body>> {
+    debug!("Route {} is outside its activation window, answering {:?}", domain, route.get_inactive_response());
+    let response = match route.get_inactive_response() {
+        InactiveResponse::NotFound => {
+            Response::builder().status(StatusCode::NOT_FOUND).header(CONTENT_TYPE, "text/plain").body(Body::from("Not Found"))?
+        }
+        InactiveResponse::Maintenance => {
+            let mut builder = Response::builder().status(StatusCode::SERVICE_UNAVAILABLE).header(CONTENT_TYPE, "text/plain");
+            if let Some(start) = route.get_active_from().filter(|&from| from > now) {
+                builder = builder.header(RETRY_AFTER, (start - now).whole_seconds().max(1));
+            }
+            builder.body(Body::from("Down for maintenance"))?
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::timestamp::parse_rfc3339;
+
+    fn at(value: &str) -> OffsetDateTime {
+        parse_rfc3339(value).unwrap()
+    }
+
+    fn event_route(from: Option<&str>, until: Option<&str>) -> ProxyRoute {
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 3000, false, None, false);
+        route.set_active_from(from.map(at));
+        route.set_active_until(until.map(at));
+        route
+    }
+
+    #[test]
+    fn test_transitions_at_injected_times() {
+        let mut config = Config::default();
+        config.routes.insert("event.example.com".to_string(), event_route(Some("2026-12-24T18:00:00Z"), Some("2026-12-26T00:00:00Z")));
+        config.routes.insert("always.example.com".to_string(), event_route(None, None));
+
+        let before = activation_states(&config, at("2026-12-24T17:59:00Z"));
+        let during = activation_states(&config, at("2026-12-24T18:00:00Z"));
+        let after = activation_states(&config, at("2026-12-26T00:00:30Z"));
+        assert!(!before["event.example.com"]);
+        assert_eq!(transitions(&before, &during), (vec!["event.example.com".to_string()], vec![]));
+        assert_eq!(transitions(&during, &during), (vec![], vec![]));
+        assert_eq!(transitions(&during, &after), (vec![], vec!["event.example.com".to_string()]));
+
+        // A route added while outside its window did not transition
+        let mut added = config.clone();
+        added.routes.insert("later.example.com".to_string(), event_route(Some("2027-01-01T00:00:00Z"), None));
+        assert_eq!(transitions(&during, &activation_states(&added, at("2026-12-24T18:01:00Z"))), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_inactive_responses() {
+        let now = at("2026-12-24T17:00:00Z");
+        let mut route = event_route(Some("2026-12-24T18:00:00Z"), None);
+        assert_eq!(inactive_response(&route, "event.example.com", now).unwrap().status(), StatusCode::NOT_FOUND);
+
+        route.set_inactive_response(InactiveResponse::Maintenance);
+        let response = inactive_response(&route, "event.example.com", now).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "3600");
+
+        // Over for good: nothing to retry after
+        let mut ended = event_route(None, Some("2026-12-24T16:00:00Z"));
+        ended.set_inactive_response(InactiveResponse::Maintenance);
+        assert!(!inactive_response(&ended, "event.example.com", now).unwrap().headers().contains_key(RETRY_AFTER));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::config::ProxyRoute;
+use hyper::Body;
+use hyper::body::{Bytes, HttpBody};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+// Buckets outlive config reloads so the cap is shared by connections that started under an older snapshot
+static BUCKETS: OnceLock<Mutex<HashMap<String, Arc<RouteBandwidth>>>> = OnceLock::new();
+
+fn buckets() -> &'static Mutex<HashMap<String, Arc<RouteBandwidth>>> {
+    BUCKETS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Largest slice a connection may send per turn; keeps turns short so concurrent connections interleave
+const MAX_QUANTUM: usize = 16 * 1024;
+
+/// Aggregate outbound bandwidth cap for a single route (by route key), shared by all of its connections
+#[derive(Debug)]
+pub struct RouteBandwidth {
+    bytes_per_sec: AtomicU64,
+    // Connections queue on this lock for their turn; tokio's mutex is FIFO, so turns go round-robin
+    bucket: tokio::sync::Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RouteBandwidth {
+    fn new(bytes_per_sec: u64) -> Self {
+        let bucket = Bucket { tokens: capacity(bytes_per_sec as f64), refilled: Instant::now() };
+        Self { bytes_per_sec: AtomicU64::new(bytes_per_sec), bucket: tokio::sync::Mutex::new(bucket) }
+    }
+
+    fn rate(&self) -> f64 {
+        self.bytes_per_sec.load(Ordering::Relaxed) as f64
+    }
+
+    fn capacity(&self) -> f64 {
+        capacity(self.rate())
+    }
+
+    fn quantum(&self) -> usize {
+        (self.capacity() as usize).min(MAX_QUANTUM)
+    }
+
+    /// Wait for this connection's turn and take up to `want` bytes of budget; returns how many may be sent now
+    pub async fn take(&self, want: usize) -> usize {
+        let want = want.min(self.quantum()).max(1);
+        let mut bucket = self.bucket.lock().await;
+        loop {
+            let now = Instant::now();
+            let rate = self.rate();
+            bucket.tokens = (bucket.tokens + now.duration_since(bucket.refilled).as_secs_f64() * rate).min(self.capacity());
+            bucket.refilled = now;
+            if bucket.tokens >= want as f64 {
+                bucket.tokens -= want as f64;
+                return want;
+            }
+            tokio::time::sleep(Duration::from_secs_f64((want as f64 - bucket.tokens) / rate)).await;
+        }
+    }
+
+    /// Stream `body` at the route's rate; chunks are sliced (not copied) into turn-sized pieces
+    pub fn throttle(self: Arc<Self>, body: Body) -> Body {
+        let stream = futures_util::stream::unfold((body, Bytes::new(), self), |(mut body, mut pending, bandwidth)| async move {
+            while pending.is_empty() {
+                match body.data().await? {
+                    Ok(chunk) => pending = chunk,
+                    Err(e) => return Some((Err(e), (body, pending, bandwidth))),
+                }
+            }
+            let n = bandwidth.take(pending.len()).await;
+            let slice = pending.split_to(n);
+            Some((Ok(slice), (body, pending, bandwidth)))
+        });
+        Body::wrap_stream(stream)
+    }
+
+    /// Copy `reader` to `writer` at the route's rate, shutting down the writer on EOF; returns bytes copied
+    pub async fn copy<R, W>(&self, reader: &mut R, writer: &mut W) -> std::io::Result<u64>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut buf = vec![0u8; MAX_QUANTUM];
+        let mut copied = 0u64;
+        loop {
+            let read = reader.read(&mut buf).await?;
+            if read == 0 {
+                writer.shutdown().await?;
+                return Ok(copied);
+            }
+            let mut sent = 0;
+            while sent < read {
+                let n = self.take(read - sent).await;
+                writer.write_all(&buf[sent..sent + n]).await?;
+                sent += n;
+            }
+            copied += read as u64;
+        }
+    }
+}
+
+// Burst allowance: 50ms worth of traffic, but never less than a small packet
+fn capacity(bytes_per_sec: f64) -> f64 {
+    (bytes_per_sec / 20.0).max(1024.0)
+}
+
+/// Bandwidth cap for a route, created on first use and updated to the route's current limit (hot reload).
+/// None if the route has no `bandwidth_limit_kbps`.
+pub fn bandwidth_for(route_key: &str, route: &ProxyRoute) -> Option<Arc<RouteBandwidth>> {
+    let bytes_per_sec = (route.get_bandwidth_limit_kbps()? * 1000 / 8).max(1);
+    let bandwidth = buckets().lock().unwrap().entry(route_key.to_string()).or_insert_with(|| Arc::new(RouteBandwidth::new(bytes_per_sec))).clone();
+    bandwidth.bytes_per_sec.store(bytes_per_sec, Ordering::Relaxed);
+    Some(bandwidth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited_route(kbps: u64) -> ProxyRoute {
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 8080, false, None, false);
+        route.set_bandwidth_limit_kbps(Some(kbps));
+        route
+    }
+
+    #[test]
+    fn test_unlimited_route_has_no_bandwidth_cap() {
+        let route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 8080, false, None, false);
+        assert!(bandwidth_for("unlimited.bandwidth.test", &route).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_throttle_slices_without_reordering() {
+        let bandwidth = bandwidth_for("slices.bandwidth.test", &limited_route(8_000_000)).unwrap();
+        let payload: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let body = bandwidth.throttle(Body::from(payload.clone()));
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_copy_forwards_everything_and_closes() {
+        let bandwidth = bandwidth_for("copy.bandwidth.test", &limited_route(8_000_000)).unwrap();
+        let payload: Vec<u8> = (0..50_000u32).map(|i| i as u8).collect();
+        let (mut writer, mut reader) = tokio::io::duplex(4096);
+        let (mut sink, mut received) = tokio::io::duplex(4096);
+        let source = payload.clone();
+        tokio::spawn(async move {
+            writer.write_all(&source).await.unwrap();
+        });
+        let collect = tokio::spawn(async move {
+            let mut out = Vec::new();
+            received.read_to_end(&mut out).await.unwrap();
+            out
+        });
+        assert_eq!(bandwidth.copy(&mut reader, &mut sink).await.unwrap(), payload.len() as u64);
+        drop(sink);
+        assert_eq!(collect.await.unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_connections_share_the_cap_fairly() {
+        // 800 kbps = 100 KB/s shared by two connections
+        let bandwidth = bandwidth_for("fair.bandwidth.test", &limited_route(800)).unwrap();
+        let start = Instant::now();
+        let send = |bandwidth: Arc<RouteBandwidth>| async move {
+            let mut left = 50_000usize;
+            while left > 0 {
+                left -= bandwidth.take(left).await;
+            }
+            start.elapsed()
+        };
+        let (a, b) = tokio::join!(send(bandwidth.clone()), send(bandwidth.clone()));
+        // Both finish around 1s: neither connection starves the other
+        for elapsed in [a, b] {
+            assert!(elapsed > Duration::from_millis(850) && elapsed < Duration::from_millis(1150), "took {:?}", elapsed);
+        }
+    }
+}
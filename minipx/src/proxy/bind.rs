@@ -0,0 +1,115 @@
+//! Binding the HTTP and HTTPS frontend ports under `bind_failure_policy`.
+//!
+//! With `retry` a frontend whose port is taken keeps trying every few seconds and is listed as unbound by the health
+//! report, the metrics and the systemd STATUS line, so a host that serves nothing on port 80 does not look healthy.
+//! With `fail` (or `--fail-on-bind-error`) it gives up after `bind_failure_attempts` and the error, naming the port,
+//! ends the proxy with a non-zero exit code.
+
+use crate::config::{BindFailurePolicy, Config};
+use crate::systemd;
+use anyhow::{Result, anyhow};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Time between attempts to bind a frontend port
+pub const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+static FAIL_ON_BIND_ERROR: AtomicBool = AtomicBool::new(false);
+// Frontends whose port is not bound right now, keyed by kind
+static UNBOUND: OnceLock<Mutex<BTreeMap<String, UnboundFrontend>>> = OnceLock::new();
+
+fn unbound() -> &'static Mutex<BTreeMap<String, UnboundFrontend>> {
+    UNBOUND.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// A frontend that failed to bind its port and has not bound it since
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnboundFrontend {
+    /// "http" or "https"
+    pub kind: String,
+    pub port: u16,
+    pub attempts: u64,
+    pub last_error: String,
+}
+
+/// Use `BindFailurePolicy::Fail` whatever the config says (`--fail-on-bind-error`)
+pub fn set_fail_on_bind_error() {
+    FAIL_ON_BIND_ERROR.store(true, Ordering::Release);
+}
+
+/// The policy in effect with `config`
+pub fn bind_failure_policy(config: &Config) -> BindFailurePolicy {
+    if FAIL_ON_BIND_ERROR.load(Ordering::Acquire) { BindFailurePolicy::Fail } else { config.get_bind_failure_policy() }
+}
+
+/// Frontends currently unable to bind their port
+pub fn unbound_frontends() -> Vec<UnboundFrontend> {
+    unbound().lock().unwrap().values().cloned().collect()
+}
+
+/// Record a failed bind of the `kind` frontend and return its failed attempts so far. The first failure and any
+/// change of error are logged as errors, repeats only at debug.
+pub fn bind_failed(kind: &str, addr: SocketAddr, e: &io::Error) -> u64 {
+    let mut unbound = unbound().lock().unwrap();
+    let frontend = unbound.entry(kind.to_string()).or_insert_with(|| UnboundFrontend {
+        kind: kind.to_string(),
+        port: addr.port(),
+        attempts: 0,
+        last_error: String::new(),
+    });
+    frontend.attempts += 1;
+    if frontend.last_error == e.to_string() {
+        debug!("Failed to bind {} on {} (attempt {}): {}", kind.to_uppercase(), addr, frontend.attempts, e);
+    } else {
+        error!("Failed to bind {} on {}: {}", kind.to_uppercase(), addr, e);
+        frontend.last_error = e.to_string();
+    }
+    systemd::status(&format!("Degraded: {} port {} is not bound ({})", kind.to_uppercase(), addr.port(), e));
+    frontend.attempts
+}
+
+/// Record that the `kind` frontend bound its port
+pub fn bound(kind: &str) {
+    if let Some(frontend) = unbound().lock().unwrap().remove(kind) {
+        info!("{} bound port {} after {} failed attempts", kind.to_uppercase(), frontend.port, frontend.attempts);
+    }
+}
+
+/// Bind `addr` for the `kind` frontend with `bind`, trying again every `retry_delay` until it works. Under
+/// `BindFailurePolicy::Fail` it gives up after `attempts` failures with an error naming the port.
+pub async fn bind_with_policy<T>(
+    kind: &str,
+    addr: SocketAddr,
+    policy: BindFailurePolicy,
+    attempts: u64,
+    retry_delay: Duration,
+    mut bind: impl FnMut() -> io::Result<T>,
+) -> Result<T> {
+    loop {
+        match bind() {
+            Ok(listener) => {
+                bound(kind);
+                return Ok(listener);
+            }
+            Err(e) => {
+                let failed = bind_failed(kind, addr, &e);
+                if policy == BindFailurePolicy::Fail && failed >= attempts {
+                    return Err(anyhow!(
+                        "Could not bind {} port {} after {} attempts: {} (is another server using it?); exiting because bind_failure_policy is fail",
+                        kind.to_uppercase(),
+                        addr.port(),
+                        failed,
+                        e
+                    ));
+                }
+                tokio::time::sleep(retry_delay).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Per-route `cache_control` rules: a `Cache-Control` header (and optionally `Expires`) added to backend responses
+//! matched by Content-Type or request path, for backends that never send one. minipx itself caches nothing.
+
+use crate::config::CacheControlRule;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, EXPIRES, HeaderValue};
+use hyper::{Body, Response, StatusCode};
+use log::debug;
+use std::time::{Duration, SystemTime};
+
+/// Apply the first of `rules` matching a response to a request for `path`. Only successful and 304 responses are
+/// touched, and headers the backend set are kept unless the rule overrides them.
+pub fn apply(rules: &[CacheControlRule], path: &str, response: &mut Response<Body>) {
+    if !(response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED) {
+        return;
+    }
+    let content_type = response.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(media_type);
+    let Some(rule) = rules.iter().find(|rule| matches(rule, content_type.as_deref(), path)) else {
+        return;
+    };
+    let headers = response.headers_mut();
+    if headers.contains_key(CACHE_CONTROL) && !rule.is_override() {
+        return;
+    }
+    let Ok(value) = HeaderValue::from_str(rule.get_value()) else {
+        return;
+    };
+    debug!("Added Cache-Control {:?} to the response for {}", value, path);
+    headers.insert(CACHE_CONTROL, value);
+    if let Some(max_age) = rule.get_max_age().filter(|_| rule.is_expires())
+        && (rule.is_override() || !headers.contains_key(EXPIRES))
+    {
+        let expires = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(max_age));
+        headers.insert(EXPIRES, HeaderValue::from_str(&expires).expect("HTTP dates are valid header values"));
+    }
+}
+
+/// True if `rule` applies to a response of `content_type` (its media type) for `path`
+pub fn matches(rule: &CacheControlRule, content_type: Option<&str>, path: &str) -> bool {
+    let type_matches = rule.get_content_types().is_empty()
+        || content_type.is_some_and(|content_type| {
+            rule.get_content_types().iter().any(|pattern| glob(pattern.to_ascii_lowercase().as_bytes(), content_type.as_bytes(), None))
+        });
+    let path_matches = rule.get_paths().is_empty() || rule.get_paths().iter().any(|pattern| path_glob(pattern, path));
+    type_matches && path_matches
+}
+
+// "text/css; charset=utf-8" -> "text/css"
+fn media_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+/// Match `path` against a path glob: `*` stays within a segment and `**` spans segments. A pattern without a
+/// leading '/' matches the end of the path, so `*.css` is any stylesheet.
+pub fn path_glob(pattern: &str, path: &str) -> bool {
+    if pattern.starts_with('/') {
+        glob(pattern.as_bytes(), path.as_bytes(), Some(b'/'))
+    } else {
+        glob(format!("**/{}", pattern).as_bytes(), path.as_bytes(), Some(b'/'))
+    }
+}
+
+// `*` matches any run of bytes not crossing `separator`; `**` (or any `*` without a separator) matches anything
+fn glob(pattern: &[u8], text: &[u8], separator: Option<u8>) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => {
+            let (crosses, rest) = match rest.split_first() {
+                Some((b'*', rest)) => (true, rest),
+                _ => (separator.is_none(), rest),
+            };
+            (0..=text.len()).take_while(|&i| i == 0 || crosses || Some(text[i - 1]) != separator).any(|i| glob(rest, &text[i..], separator))
+        }
+        Some((&c, rest)) => text.first() == Some(&c) && glob(rest, &text[1..], separator),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(content_type: &str) -> Response<Body> {
+        Response::builder().header(CONTENT_TYPE, content_type).body(Body::empty()).unwrap()
+    }
+
+    fn cache_control(response: &Response<Body>) -> Option<&str> {
+        response.headers().get(CACHE_CONTROL).and_then(|v| v.to_str().ok())
+    }
+
+    #[test]
+    fn test_path_globs() {
+        assert!(path_glob("/static/**", "/static/css/site.css"));
+        assert!(path_glob("/static/*.js", "/static/app.js"));
+        assert!(!path_glob("/static/*.js", "/static/vendor/app.js"));
+        assert!(path_glob("*.css", "/a/b/site.css"));
+        assert!(path_glob("/favicon.ico", "/favicon.ico"));
+        assert!(!path_glob("/favicon.ico", "/favicon.ico.bak"));
+        assert!(!path_glob("/static/**", "/api/static"));
+    }
+
+    #[test]
+    fn test_match_by_content_type() {
+        let rules = [CacheControlRule::new("public, max-age=86400").unwrap().with_content_types(["image/*", "text/css", "application/javascript"])];
+        let mut image = response("image/png");
+        apply(&rules, "/logo", &mut image);
+        assert_eq!(cache_control(&image), Some("public, max-age=86400"));
+
+        let mut css = response("Text/CSS; charset=utf-8");
+        apply(&rules, "/site", &mut css);
+        assert_eq!(cache_control(&css), Some("public, max-age=86400"));
+
+        let mut html = response("text/html");
+        apply(&rules, "/index.png", &mut html);
+        assert_eq!(cache_control(&html), None);
+    }
+
+    #[test]
+    fn test_match_by_path_and_first_rule_wins() {
+        let rules = [
+            CacheControlRule::new("no-cache").unwrap().with_paths(["/static/index.html"]),
+            CacheControlRule::new("public, max-age=3600").unwrap().with_paths(["/static/**"]),
+        ];
+        let mut asset = response("application/octet-stream");
+        apply(&rules, "/static/fonts/a.woff2", &mut asset);
+        assert_eq!(cache_control(&asset), Some("public, max-age=3600"));
+
+        let mut index = response("text/html");
+        apply(&rules, "/static/index.html", &mut index);
+        assert_eq!(cache_control(&index), Some("no-cache"));
+
+        // Errors are never made cacheable
+        let mut missing = response("text/html");
+        *missing.status_mut() = StatusCode::NOT_FOUND;
+        apply(&rules, "/static/gone.js", &mut missing);
+        assert_eq!(cache_control(&missing), None);
+    }
+
+    #[test]
+    fn test_backend_value_is_kept_unless_overridden() {
+        let rule = CacheControlRule::new("public, max-age=60").unwrap().with_expires(true);
+        let mut response = response("image/png");
+        response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+        apply(std::slice::from_ref(&rule), "/a.png", &mut response);
+        assert_eq!(cache_control(&response), Some("private"));
+        assert!(!response.headers().contains_key(EXPIRES));
+
+        apply(&[rule.with_override(true)], "/a.png", &mut response);
+        assert_eq!(cache_control(&response), Some("public, max-age=60"));
+        let expires = httpdate::parse_http_date(response.headers()[EXPIRES].to_str().unwrap()).unwrap();
+        let ahead = expires.duration_since(SystemTime::now()).unwrap_or_default();
+        assert!(ahead > Duration::from_secs(50) && ahead <= Duration::from_secs(60), "{:?}", ahead);
+    }
+}
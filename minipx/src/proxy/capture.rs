@@ -0,0 +1,449 @@
+use crate::config::{DebugCaptureConfig, MAX_CAPTURE_BYTES, MAX_CAPTURE_DURATION_SECS, ProxyRoute};
+use anyhow::{Result, anyhow};
+use hyper::body::Bytes;
+use hyper::{Body, HeaderMap, Request, Response};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_stream::StreamExt;
+
+// Headers whose values never reach a capture file
+const REDACTED_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie", "set-cookie", "x-api-key", "x-auth-token"];
+const REDACTED: &str = "[redacted]";
+
+// Capture state by route key; survives config reloads so an IPC toggle isn't undone by an unrelated edit
+static SESSIONS: OnceLock<Mutex<HashMap<String, CaptureEntry>>> = OnceLock::new();
+
+fn sessions() -> &'static Mutex<HashMap<String, CaptureEntry>> {
+    SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+struct CaptureEntry {
+    // The route's `debug_capture` when this entry was created; a different value on reload starts over
+    configured: Option<DebugCaptureConfig>,
+    // None when capturing is off (disabled over IPC, or never enabled)
+    session: Option<Arc<CaptureSession>>,
+}
+
+/// An active capture for one route: where files go, how much has been written, and when it ends
+#[derive(Debug)]
+pub struct CaptureSession {
+    domain: String,
+    settings: DebugCaptureConfig,
+    dir: PathBuf,
+    expires_at: Instant,
+    used_bytes: AtomicU64,
+    sequence: AtomicU64,
+    stopped: AtomicBool,
+}
+
+/// Capture state of a route as reported over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureStatus {
+    pub domain: String,
+    pub enabled: bool,
+    pub max_bytes: usize,
+    pub dir: String,
+    /// Seconds until capturing turns itself off
+    pub remaining_secs: u64,
+    /// Bytes on disk for this route, counted against `max_total_bytes`
+    pub captured_bytes: u64,
+    pub max_total_bytes: u64,
+}
+
+/// Settings to apply when capture is toggled at runtime; unset fields keep the route's configured values.
+/// Files always go to the route's configured `debug_capture.dir`: the toggle can't point them anywhere else.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+}
+
+impl CaptureSession {
+    fn start(domain: &str, settings: DebugCaptureConfig) -> Self {
+        // Wildcard and port characters aren't valid in file names everywhere
+        let dir = Path::new(settings.get_dir()).join(domain.replace(['*', ':'], "_"));
+        let used = directory_size(&dir);
+        warn!(
+            "Debug capture enabled for {domain}: up to {max} bytes of each body are written to {dir} for {secs}s",
+            domain = domain,
+            max = settings.get_max_bytes(),
+            dir = dir.display(),
+            secs = settings.get_duration_secs()
+        );
+        // Clamped: a huge duration would overflow `Instant` and panic while the sessions lock is held
+        let now = Instant::now();
+        let expires_at = now.checked_add(Duration::from_secs(settings.get_duration_secs().min(MAX_CAPTURE_DURATION_SECS))).unwrap_or(now);
+        Self {
+            domain: domain.to_string(),
+            expires_at,
+            settings,
+            dir,
+            used_bytes: AtomicU64::new(used),
+            sequence: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        !self.stopped.load(Ordering::Acquire)
+            && Instant::now() < self.expires_at
+            && self.used_bytes.load(Ordering::Acquire) < self.settings.get_max_total_bytes()
+    }
+
+    fn status(&self) -> CaptureStatus {
+        CaptureStatus {
+            domain: self.domain.clone(),
+            enabled: self.is_active(),
+            max_bytes: self.settings.get_max_bytes(),
+            dir: self.dir.to_string_lossy().into_owned(),
+            remaining_secs: self.expires_at.saturating_duration_since(Instant::now()).as_secs(),
+            captured_bytes: self.used_bytes.load(Ordering::Acquire),
+            max_total_bytes: self.settings.get_max_total_bytes(),
+        }
+    }
+
+    /// Start capturing one request/response pair; both files share a timestamped name
+    pub fn exchange(self: &Arc<Self>) -> CaptureExchange {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::AcqRel);
+        CaptureExchange { session: self.clone(), stem: format!("{}-{:06}", millis, sequence) }
+    }
+
+    // Account for a file against the disk cap; once the cap is hit the session stops for good
+    fn reserve(&self, len: u64) -> bool {
+        let used = self.used_bytes.fetch_add(len, Ordering::AcqRel) + len;
+        if used <= self.settings.get_max_total_bytes() {
+            return true;
+        }
+        self.used_bytes.fetch_sub(len, Ordering::AcqRel);
+        if !self.stopped.swap(true, Ordering::AcqRel) {
+            warn!("Debug capture for {} stopped: {} byte disk cap reached", self.domain, self.settings.get_max_total_bytes());
+        }
+        false
+    }
+
+    fn write(&self, path: PathBuf, contents: Vec<u8>) {
+        if !self.reserve(contents.len() as u64) {
+            return;
+        }
+        let dir = self.dir.clone();
+        let write = move || {
+            if let Err(e) = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, contents)) {
+                warn!("Failed to write capture file {}: {}", path.display(), e);
+            } else {
+                debug!("Wrote capture file {}", path.display());
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(write);
+            }
+            Err(_) => write(),
+        }
+    }
+}
+
+/// One captured request/response pair
+#[derive(Debug, Clone)]
+pub struct CaptureExchange {
+    session: Arc<CaptureSession>,
+    stem: String,
+}
+
+impl CaptureExchange {
+    /// Capture the request's head and the first `max_bytes` of its body as it streams to the backend
+    pub fn tee_request(&self, req: Request<Body>) -> Request<Body> {
+        let (parts, body) = req.into_parts();
+        let head = format!("{} {} {:?}\n{}", parts.method, parts.uri, parts.version, format_headers(&parts.headers));
+        Request::from_parts(parts, self.tee(head, "request", body))
+    }
+
+    /// Capture the response's head and the first `max_bytes` of its body as it streams to the client
+    pub fn tee_response(&self, response: Response<Body>) -> Response<Body> {
+        let (parts, body) = response.into_parts();
+        let head = format!("{:?} {}\n{}", parts.version, parts.status, format_headers(&parts.headers));
+        Response::from_parts(parts, self.tee(head, "response", body))
+    }
+
+    fn tee(&self, head: String, kind: &str, body: Body) -> Body {
+        let mut file = CaptureFile {
+            session: self.session.clone(),
+            path: self.session.dir.join(format!("{}-{}.txt", self.stem, kind)),
+            head,
+            body: Vec::new(),
+            truncated: false,
+        };
+        Body::wrap_stream(body.map(move |chunk| {
+            if let Ok(bytes) = &chunk {
+                file.push(bytes);
+            }
+            chunk
+        }))
+    }
+}
+
+// Written when the body finishes streaming (or is dropped early)
+struct CaptureFile {
+    session: Arc<CaptureSession>,
+    path: PathBuf,
+    head: String,
+    body: Vec<u8>,
+    truncated: bool,
+}
+
+impl CaptureFile {
+    fn push(&mut self, bytes: &Bytes) {
+        let room = self.session.settings.get_max_bytes().saturating_sub(self.body.len());
+        if bytes.len() > room {
+            self.truncated = true;
+        }
+        self.body.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+impl Drop for CaptureFile {
+    fn drop(&mut self) {
+        let mut contents = std::mem::take(&mut self.head).into_bytes();
+        contents.push(b'\n');
+        contents.append(&mut self.body);
+        if self.truncated {
+            contents.extend_from_slice(format!("\n[truncated after {} bytes]\n", self.session.settings.get_max_bytes()).as_bytes());
+        }
+        self.session.write(std::mem::take(&mut self.path), contents);
+    }
+}
+
+fn format_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS.contains(&name.as_str()) { REDACTED.into() } else { String::from_utf8_lossy(value.as_bytes()) };
+            format!("{}: {}\n", name, value)
+        })
+        .collect()
+}
+
+fn directory_size(dir: &Path) -> u64 {
+    std::fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).filter_map(|e| e.metadata().ok()).filter(|m| m.is_file()).map(|m| m.len()).sum())
+        .unwrap_or(0)
+}
+
+/// The active capture session for a route, if any.
+/// A route's `debug_capture` config starts a session the first time it is seen (or whenever it changes on reload);
+/// sessions end on their own once their duration or disk cap runs out.
+pub fn capture_session(route_key: &str, route: &ProxyRoute) -> Option<Arc<CaptureSession>> {
+    let configured = route.get_debug_capture();
+    let mut sessions = sessions().lock().unwrap();
+    match sessions.get(route_key) {
+        Some(entry) if entry.configured.as_ref() == configured => return entry.session.clone().filter(|s| s.is_active()),
+        None if configured.is_none() => return None,
+        _ => {}
+    }
+    let session = configured.filter(|c| c.is_enabled()).map(|c| Arc::new(CaptureSession::start(route_key, c.clone())));
+    sessions.insert(route_key.to_string(), CaptureEntry { configured: configured.cloned(), session: session.clone() });
+    session
+}
+
+/// Turn capture on or off for a route without touching the config file.
+/// The toggle holds until it expires or the route's `debug_capture` config is changed. Only routes with a
+/// `debug_capture` block in the config file can be toggled: the block is what opts a route in.
+pub fn set_capture(route_key: &str, route: &ProxyRoute, enabled: bool, options: CaptureOptions) -> Result<CaptureStatus> {
+    let Some(configured) = route.get_debug_capture() else {
+        return Err(anyhow!("{} has no debug_capture block in the config file, so it can't be captured", route_key));
+    };
+    let mut settings = configured.clone();
+    settings.set_enabled(enabled);
+    if let Some(max_bytes) = options.max_bytes {
+        if max_bytes > MAX_CAPTURE_BYTES {
+            return Err(anyhow!("max_bytes {} is over the limit of {}", max_bytes, MAX_CAPTURE_BYTES));
+        }
+        settings.set_max_bytes(max_bytes);
+    }
+    if let Some(duration_secs) = options.duration_secs {
+        if duration_secs > MAX_CAPTURE_DURATION_SECS {
+            return Err(anyhow!("duration_secs {} is over the limit of {}", duration_secs, MAX_CAPTURE_DURATION_SECS));
+        }
+        settings.set_duration_secs(duration_secs);
+    }
+
+    let session = enabled.then(|| Arc::new(CaptureSession::start(route_key, settings.clone())));
+    let status = match &session {
+        Some(session) => session.status(),
+        None => {
+            debug!("Debug capture disabled for {}", route_key);
+            disabled_status(route_key, &settings)
+        }
+    };
+    sessions().lock().unwrap().insert(route_key.to_string(), CaptureEntry { configured: Some(configured.clone()), session });
+    Ok(status)
+}
+
+/// Current capture state of a route
+pub fn capture_status(route_key: &str, route: &ProxyRoute) -> CaptureStatus {
+    match capture_session(route_key, route) {
+        Some(session) => session.status(),
+        None => disabled_status(route_key, &route.get_debug_capture().cloned().unwrap_or_default()),
+    }
+}
+
+fn disabled_status(route_key: &str, settings: &DebugCaptureConfig) -> CaptureStatus {
+    let dir = Path::new(settings.get_dir()).join(route_key.replace(['*', ':'], "_"));
+    CaptureStatus {
+        domain: route_key.to_string(),
+        enabled: false,
+        max_bytes: settings.get_max_bytes(),
+        captured_bytes: directory_size(&dir),
+        dir: dir.to_string_lossy().into_owned(),
+        remaining_secs: 0,
+        max_total_bytes: settings.get_max_total_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+
+    fn capture_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minipx-capture-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn capturing_route(dir: &Path, max_bytes: usize, max_total_bytes: u64) -> ProxyRoute {
+        let mut capture = DebugCaptureConfig::default();
+        capture.set_enabled(true);
+        capture.set_dir(dir.to_string_lossy());
+        capture.set_max_bytes(max_bytes);
+        capture.set_max_total_bytes(max_total_bytes);
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 8080, false, None, false);
+        route.set_debug_capture(Some(capture));
+        route
+    }
+
+    fn read_files(dir: &Path) -> Vec<(String, String)> {
+        let mut files: Vec<(String, String)> = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| (e.file_name().to_string_lossy().into_owned(), std::fs::read_to_string(e.path()).unwrap()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_route_without_capture_has_no_session() {
+        let route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 8080, false, None, false);
+        assert!(capture_session("none.capture.test", &route).is_none());
+        assert!(!capture_status("none.capture.test", &route).enabled);
+    }
+
+    #[tokio::test]
+    async fn test_tee_truncates_and_redacts() {
+        let dir = capture_dir("tee");
+        let route = capturing_route(&dir, 4, 1024 * 1024);
+        let exchange = capture_session("tee.capture.test", &route).unwrap().exchange();
+
+        let req = Request::builder()
+            .uri("/upload")
+            .header("authorization", "Bearer secret")
+            .header("x-trace", "abc")
+            .body(Body::from("0123456789"))
+            .unwrap();
+        let req = exchange.tee_request(req);
+        assert_eq!(hyper::body::to_bytes(req.into_body()).await.unwrap(), "0123456789");
+
+        let response = Response::builder().status(StatusCode::OK).header("set-cookie", "session=secret").body(Body::from("ok")).unwrap();
+        let response = exchange.tee_response(response);
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "ok");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let files = read_files(&dir.join("tee.capture.test"));
+        assert_eq!(files.len(), 2);
+        let (request_name, request) = &files[0];
+        assert!(request_name.ends_with("-request.txt"));
+        assert!(request.starts_with("GET /upload HTTP/1.1\n"));
+        assert!(request.contains("authorization: [redacted]") && !request.contains("secret"));
+        assert!(request.contains("x-trace: abc"));
+        assert!(request.contains("\n0123\n[truncated after 4 bytes]"));
+        let (response_name, response) = &files[1];
+        assert!(response_name.ends_with("-response.txt"));
+        assert!(response.contains("set-cookie: [redacted]") && response.ends_with("\nok"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cap_stops_session() {
+        let dir = capture_dir("cap");
+        let route = capturing_route(&dir, 1024, 64);
+        let session = capture_session("cap.capture.test", &route).unwrap();
+        let req = session.exchange().tee_request(Request::new(Body::from("x".repeat(100))));
+        hyper::body::to_bytes(req.into_body()).await.unwrap();
+
+        assert!(capture_session("cap.capture.test", &route).is_none());
+        assert!(read_files(&dir.join("cap.capture.test")).is_empty());
+    }
+
+    #[test]
+    fn test_ipc_toggle_holds_until_config_changes() {
+        let dir = capture_dir("toggle");
+        let route = capturing_route(&dir, 16, 1024);
+        assert!(capture_session("toggle.capture.test", &route).is_some());
+
+        let status = set_capture("toggle.capture.test", &route, false, CaptureOptions::default()).unwrap();
+        assert!(!status.enabled);
+        assert!(capture_session("toggle.capture.test", &route).is_none());
+
+        // Re-enabling over IPC applies the overrides on top of the configured settings
+        let options = CaptureOptions { max_bytes: Some(8), duration_secs: Some(60) };
+        let status = set_capture("toggle.capture.test", &route, true, options).unwrap();
+        assert!(status.enabled);
+        assert_eq!(status.max_bytes, 8);
+        assert!(status.remaining_secs > 0 && status.remaining_secs <= 60);
+        assert!(Path::new(&status.dir).starts_with(&dir));
+
+        // An expired toggle turns itself off
+        set_capture("toggle.capture.test", &route, true, CaptureOptions { duration_secs: Some(0), ..Default::default() }).unwrap();
+        assert!(capture_session("toggle.capture.test", &route).is_none());
+
+        // A changed config on reload starts a fresh session from the file
+        let changed = capturing_route(&dir, 32, 1024);
+        assert_eq!(capture_session("toggle.capture.test", &changed).unwrap().status().max_bytes, 32);
+    }
+
+    #[test]
+    fn test_routes_without_a_capture_block_cannot_be_toggled() {
+        let route = ProxyRoute::new("127.0.0.1".to_string(), String::new(), 3000, false, None, false);
+        assert!(set_capture("optout.capture.test", &route, true, CaptureOptions::default()).is_err());
+        assert!(capture_session("optout.capture.test", &route).is_none());
+    }
+
+    #[test]
+    fn test_out_of_range_durations_are_refused_or_clamped() {
+        let dir = capture_dir("range");
+        let route = capturing_route(&dir, 16, 1024);
+        let options = CaptureOptions { duration_secs: Some(u64::MAX), ..Default::default() };
+        assert!(set_capture("range.capture.test", &route, true, options).is_err());
+        let options = CaptureOptions { max_bytes: Some(MAX_CAPTURE_BYTES + 1), ..Default::default() };
+        assert!(set_capture("range.capture.test", &route, true, options).is_err());
+
+        // Set in code, past the validation of the config file: clamped instead of overflowing
+        let mut capture = route.get_debug_capture().unwrap().clone();
+        capture.set_duration_secs(u64::MAX);
+        let mut huge = route.clone();
+        huge.set_debug_capture(Some(capture));
+        let session = capture_session("range.capture.test", &huge).unwrap();
+        assert!(session.status().remaining_secs <= MAX_CAPTURE_DURATION_SECS);
+    }
+}
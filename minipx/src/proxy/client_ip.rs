@@ -0,0 +1,135 @@
+use crate::utils::cidr::IpCidr;
+use hyper::HeaderMap;
+use std::fmt::Display;
+use std::net::IpAddr;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The addresses of a request's client: the TCP peer and the effective client behind any trusted proxies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp {
+    /// Immediate TCP peer; this is what gets appended to X-Forwarded-For
+    pub peer: IpAddr,
+    /// Client address used for logging, X-Real-IP and any per-client policy
+    pub effective: IpAddr,
+}
+
+impl ClientIp {
+    /// A client connecting directly, with no proxy in between
+    pub fn direct(peer: IpAddr) -> Self {
+        Self { peer, effective: peer }
+    }
+}
+
+impl Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.peer == self.effective { write!(f, "{}", self.effective) } else { write!(f, "{} (via {})", self.effective, self.peer) }
+    }
+}
+
+fn is_trusted(ip: IpAddr, trusted_proxies: &[IpCidr]) -> bool {
+    trusted_proxies.iter().any(|cidr| cidr.contains(ip))
+}
+
+/// Resolve the effective client IP and sanitize X-Forwarded-For in place.
+///
+/// If the peer is a trusted proxy, the effective client is the right-most X-Forwarded-For entry that is
+/// not itself a trusted proxy, and the header is kept so the peer can be appended to the chain.
+/// Otherwise the header is spoofable: it is removed and the peer is the client.
+pub fn resolve_client_ip(peer: IpAddr, headers: &mut HeaderMap, trusted_proxies: &[IpCidr]) -> ClientIp {
+    let peer = peer.to_canonical();
+    if !is_trusted(peer, trusted_proxies) {
+        headers.remove(X_FORWARDED_FOR);
+        return ClientIp::direct(peer);
+    }
+
+    let entries: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    // Walk right to left from the peer; stop at the first untrusted hop or anything that isn't an IP
+    let mut effective = peer;
+    for entry in entries.iter().rev() {
+        let Ok(ip) = entry.parse::<IpAddr>().map(|ip| ip.to_canonical()) else {
+            break;
+        };
+        effective = ip;
+        if !is_trusted(ip, trusted_proxies) {
+            break;
+        }
+    }
+    ClientIp { peer, effective }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted() -> Vec<IpCidr> {
+        vec!["10.0.0.0/8".parse().unwrap(), "2001:db8::/32".parse().unwrap()]
+    }
+
+    fn headers(xff: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in xff {
+            headers.append(X_FORWARDED_FOR, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_and_strips_header() {
+        let mut h = headers(&["1.2.3.4"]);
+        let client = resolve_client_ip(ip("203.0.113.7"), &mut h, &trusted());
+        assert_eq!(client, ClientIp::direct(ip("203.0.113.7")));
+        assert!(h.get(X_FORWARDED_FOR).is_none());
+    }
+
+    #[test]
+    fn test_trusted_peer_uses_forwarded_client() {
+        let mut h = headers(&["198.51.100.9"]);
+        let client = resolve_client_ip(ip("10.0.0.2"), &mut h, &trusted());
+        assert_eq!(client.effective, ip("198.51.100.9"));
+        assert_eq!(client.peer, ip("10.0.0.2"));
+        assert_eq!(h.get(X_FORWARDED_FOR).unwrap(), "198.51.100.9");
+    }
+
+    #[test]
+    fn test_chained_header_takes_rightmost_untrusted() {
+        // A spoofed left-most entry is ignored: the right-most untrusted hop is the real client
+        let mut h = headers(&["6.6.6.6, 198.51.100.9, 10.1.1.1", "2001:db8::5"]);
+        let client = resolve_client_ip(ip("10.0.0.2"), &mut h, &trusted());
+        assert_eq!(client.effective, ip("198.51.100.9"));
+    }
+
+    #[test]
+    fn test_all_trusted_chain_uses_leftmost() {
+        let mut h = headers(&["10.9.9.9, 10.1.1.1"]);
+        let client = resolve_client_ip(ip("10.0.0.2"), &mut h, &trusted());
+        assert_eq!(client.effective, ip("10.9.9.9"));
+    }
+
+    #[test]
+    fn test_garbage_entry_stops_the_walk() {
+        let mut h = headers(&["198.51.100.9, not-an-ip, 10.1.1.1"]);
+        let client = resolve_client_ip(ip("10.0.0.2"), &mut h, &trusted());
+        assert_eq!(client.effective, ip("10.1.1.1"));
+    }
+
+    #[test]
+    fn test_no_trusted_proxies_configured() {
+        let mut h = headers(&["198.51.100.9"]);
+        let client = resolve_client_ip(ip("10.0.0.2"), &mut h, &[]);
+        assert_eq!(client, ClientIp::direct(ip("10.0.0.2")));
+        assert!(h.get(X_FORWARDED_FOR).is_none());
+    }
+}
@@ -1,46 +1,194 @@
-use crate::config::Config;
-use log::{error, info};
+use crate::config::manager::config_lock;
+use crate::config::{Config, ProxyRoute};
+use crate::proxy::{activation, bandwidth, health, resolver};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+// First retry delay after a failed bind; doubled per failure up to MAX_RETRY_DELAY
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+// Failed binds after which a UDP forwarder is given up on, unless forwarder_udp_required is set
+const UDP_BIND_ATTEMPTS: u32 = 5;
+
+// Binding state of every forwarder, keyed by (protocol, listen port)
+static BINDINGS: OnceLock<Mutex<BTreeMap<(String, u16), ForwarderStatus>>> = OnceLock::new();
+
+fn bindings() -> &'static Mutex<BTreeMap<(String, u16), ForwarderStatus>> {
+    BINDINGS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Binding state of one TCP or UDP forwarder
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForwarderStatus {
+    /// `tcp` or `udp`
+    pub protocol: String,
+    pub listen_port: u16,
+    /// `host:port` the forwarder sends traffic to
+    pub target: String,
+    #[serde(flatten)]
+    pub state: BindingState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum BindingState {
+    Bound,
+    /// Binding failed `attempts` times in a row; the next attempt is due in `retry_in_secs`
+    Retrying {
+        attempts: u32,
+        last_error: String,
+        retry_in_secs: u64,
+    },
+    /// Binding failed in a way retrying cannot fix (e.g. permission denied), or an optional UDP forwarder ran out of
+    /// attempts; restart minipx once it is resolved
+    Disabled {
+        reason: String,
+    },
+}
+
+/// Binding state of every forwarder, sorted by protocol and port
+pub fn forwarder_statuses() -> Vec<ForwarderStatus> {
+    bindings().lock().unwrap().values().cloned().collect()
+}
+
+/// Binding state of the forwarder for `protocol` on `listen_port`, if one was started
+pub fn forwarder_status(protocol: &str, listen_port: u16) -> Option<ForwarderStatus> {
+    bindings().lock().unwrap().get(&(protocol.to_string(), listen_port)).cloned()
+}
+
+// Exponential backoff: 2s, 4s, 8s, ... capped at a minute
+fn retry_delay(attempts: u32) -> Duration {
+    INITIAL_RETRY_DELAY.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1))).min(MAX_RETRY_DELAY)
+}
+
+// Errors that will not go away by waiting, unlike a port still held by another process
+fn is_permanent(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::AddrNotAvailable)
+}
+
+fn set_binding(protocol: &str, listen_port: u16, target: &str, state: BindingState) -> Option<BindingState> {
+    let status = ForwarderStatus { protocol: protocol.to_string(), listen_port, target: target.to_string(), state };
+    bindings().lock().unwrap().insert((protocol.to_string(), listen_port), status).map(|previous| previous.state)
+}
+
+fn mark_bound(protocol: &str, listen_port: u16, target: &str) {
+    if let Some(BindingState::Retrying { attempts, .. }) = set_binding(protocol, listen_port, target, BindingState::Bound) {
+        info!("{} forwarder on port {} is bound after {} failed attempts", protocol.to_uppercase(), listen_port, attempts);
+    }
+}
+
+/// Record a failed bind; returns how long to wait before the next attempt, or None when the forwarder is disabled.
+/// The first failure and any change of error are logged as errors, repeats only at debug. A forwarder with
+/// `give_up_after` is optional: its failures stay at debug until it is disabled after that many, logged once as a warning
+fn bind_failed(protocol: &str, listen_port: u16, target: &str, e: &std::io::Error, give_up_after: Option<u32>) -> Option<Duration> {
+    let previous = forwarder_status(protocol, listen_port).map(|status| status.state);
+    let (attempts, repeated) = match &previous {
+        Some(BindingState::Retrying { attempts, last_error, .. }) => (attempts + 1, *last_error == e.to_string()),
+        _ => (1, false),
+    };
+    if is_permanent(e) {
+        error!("Failed to bind {} forwarder on port {}: {}; not retrying until minipx restarts", protocol.to_uppercase(), listen_port, e);
+        set_binding(protocol, listen_port, target, BindingState::Disabled { reason: e.to_string() });
+        return None;
+    }
+    if let Some(limit) = give_up_after.filter(|&limit| attempts >= limit) {
+        warn!(
+            "Failed to bind {} forwarder on port {} after {} attempts: {}; giving up on it (set forwarder_udp_required to keep retrying)",
+            protocol.to_uppercase(),
+            listen_port,
+            limit,
+            e
+        );
+        set_binding(protocol, listen_port, target, BindingState::Disabled { reason: format!("gave up after {} attempts: {}", limit, e) });
+        return None;
+    }
+    let delay = retry_delay(attempts);
+    if repeated || give_up_after.is_some() {
+        debug!("Failed to bind {} forwarder on port {} (attempt {}): {}", protocol.to_uppercase(), listen_port, attempts, e);
+    } else {
+        error!("Failed to bind {} forwarder on port {}: {}; retrying with backoff", protocol.to_uppercase(), listen_port, e);
+    }
+    set_binding(protocol, listen_port, target, BindingState::Retrying { attempts, last_error: e.to_string(), retry_in_secs: delay.as_secs() });
+    Some(delay)
+}
 
 /// Set up TCP/UDP forwarders for routes with custom listen ports
 pub async fn setup_forwarders() {
     let config = Config::get().await;
-    let mut listeners: BTreeMap<u16, (String, u16)> = BTreeMap::new();
+    let mut listeners: BTreeMap<u16, (String, String, u16)> = BTreeMap::new();
+    let udp_give_up_after = (!config.is_forwarder_udp_required()).then_some(UDP_BIND_ATTEMPTS);
 
     // Collect unique listen ports (excluding 80/443)
-    for route in config.get_routes().values() {
-        #[allow(clippy::collapsible_if)]
-        if let Some(lp) = route.get_listen_port() {
-            if lp != 0 && lp != 80 && lp != 443 {
-                listeners.entry(lp).or_insert((route.get_host().to_string(), route.get_port()));
-            }
+    for (domain, route) in config.get_routes() {
+        if let Some(lp) = forwarder_port(route) {
+            listeners.entry(lp).or_insert((domain.clone(), route.get_host().to_string(), route.get_port()));
         }
     }
 
     // Start forwarders for each unique port
-    for (listen_port, (target_host, target_port)) in listeners {
-        start_tcp_forwarder(listen_port, target_host.clone(), target_port);
-        start_udp_forwarder(listen_port, target_host, target_port);
+    for (listen_port, (route_key, target_host, target_port)) in listeners {
+        start_tcp_forwarder(listen_port, route_key.clone(), target_host.clone(), target_port);
+        start_udp_forwarder(listen_port, route_key, target_host, target_port, udp_give_up_after);
     }
 }
 
+/// Port of a route's TCP/UDP forwarder: its listen_port, unless unset or one of the HTTP(S) ports
+pub fn forwarder_port(route: &ProxyRoute) -> Option<u16> {
+    route.get_listen_port().filter(|&lp| lp != 0 && lp != 80 && lp != 443)
+}
+
+/// True while a TCP forwarder is bound on `listen_port`
+pub fn is_forwarder_listening(listen_port: u16) -> bool {
+    health::is_listening("tcp", SocketAddr::from(([0, 0, 0, 0], listen_port)))
+}
+
+/// Copy both directions until each side has finished; traffic towards the client is paced when the route is bandwidth-limited
+async fn forward_tcp(route_key: &str, inbound: &mut TcpStream, outbound: &mut TcpStream) -> std::io::Result<()> {
+    // Look the route up per connection so limit changes apply without restarting the forwarder
+    let bandwidth = config_lock().read().await.get_routes().get(route_key).and_then(|route| bandwidth::bandwidth_for(route_key, route));
+    let Some(bandwidth) = bandwidth else {
+        return tokio::io::copy_bidirectional(inbound, outbound).await.map(|_| ());
+    };
+    let (mut client_read, mut client_write) = inbound.split();
+    let (mut upstream_read, mut upstream_write) = outbound.split();
+    let to_upstream = async {
+        tokio::io::copy(&mut client_read, &mut upstream_write).await?;
+        upstream_write.shutdown().await
+    };
+    tokio::try_join!(to_upstream, bandwidth.copy(&mut upstream_read, &mut client_write))?;
+    Ok(())
+}
+
 /// Start a TCP forwarder that forwards connections from listen_port to target_host: target_port
-fn start_tcp_forwarder(listen_port: u16, target_host: String, target_port: u16) {
+fn start_tcp_forwarder(listen_port: u16, route_key: String, target_host: String, target_port: u16) {
     tokio::spawn(async move {
         let addr = SocketAddr::from(([0, 0, 0, 0], listen_port));
         loop {
             match tokio::net::TcpListener::bind(addr).await {
                 Ok(listener) => {
                     info!("TCP forwarder listening on {} -> {}:{}", addr, target_host, target_port);
+                    mark_bound("tcp", listen_port, &format!("{}:{}", target_host, target_port));
+                    health::register_listener("tcp", addr);
                     loop {
                         match listener.accept().await {
                             Ok((mut inbound, peer)) => {
                                 let host = target_host.clone();
+                                let route_key = route_key.clone();
                                 tokio::spawn(async move {
-                                    match tokio::net::TcpStream::connect((host.as_str(), target_port)).await {
+                                    if !activation::is_route_active(&route_key).await {
+                                        debug!("Closed TCP connection from {} to {}: the route is outside its activation window", peer, route_key);
+                                        return;
+                                    }
+                                    match resolver::connect(&host, target_port).await {
                                         Ok(mut outbound) => {
-                                            let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                                            let _ = forward_tcp(&route_key, &mut inbound, &mut outbound).await;
                                         }
                                         Err(e) => {
                                             error!("TCP forward connect failed from {} to {}:{}: {}", peer, host, target_port, e);
@@ -55,30 +203,45 @@ fn start_tcp_forwarder(listen_port: u16, target_host: String, target_port: u16)
                         }
                     }
                 }
-                Err(e) => {
-                    error!("Failed to bind TCP forwarder on {}: {}", addr, e);
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                    continue;
-                }
+                Err(e) => match bind_failed("tcp", listen_port, &format!("{}:{}", target_host, target_port), &e, None) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return,
+                },
             }
         }
     });
 }
 
-/// Start a UDP forwarder that forwards packets from listen_port to target_host: target_port
-fn start_udp_forwarder(listen_port: u16, target_host: String, target_port: u16) {
+/// Start a UDP forwarder that forwards packets from listen_port to target_host: target_port.
+/// With `give_up_after` it stops trying to bind after that many failures
+fn start_udp_forwarder(listen_port: u16, route_key: String, target_host: String, target_port: u16, give_up_after: Option<u32>) {
     tokio::spawn(async move {
         let bind_addr = SocketAddr::from(([0, 0, 0, 0], listen_port));
         loop {
             match tokio::net::UdpSocket::bind(bind_addr).await {
                 Ok(socket) => {
                     info!("UDP forwarder listening on {} -> {}:{}", bind_addr, target_host, target_port);
-                    let upstream = (target_host.as_str(), target_port);
+                    mark_bound("udp", listen_port, &format!("{}:{}", target_host, target_port));
+                    health::register_listener("udp", bind_addr);
                     let mut buf = vec![0u8; 65535];
                     loop {
                         match socket.recv_from(&mut buf).await {
                             Ok((n, src)) => {
-                                // send it to upstream
+                                if !activation::is_route_active(&route_key).await {
+                                    continue;
+                                }
+                                // send it to upstream (resolved per packet so resolver changes apply)
+                                let upstream = match resolver::resolve(&target_host, target_port).await.map(|addrs| addrs.first().copied()) {
+                                    Ok(Some(upstream)) => upstream,
+                                    Ok(None) => {
+                                        error!("UDP upstream {}:{} resolved to no addresses", target_host, target_port);
+                                        continue;
+                                    }
+                                    Err(e) => {
+                                        error!("UDP upstream {}:{} did not resolve: {}", target_host, target_port, e);
+                                        continue;
+                                    }
+                                };
                                 if let Err(e) = socket.send_to(&buf[..n], upstream).await {
                                     error!("UDP send_to upstream failed: {}", e);
                                     continue;
@@ -98,12 +261,95 @@ fn start_udp_forwarder(listen_port: u16, target_host: String, target_port: u16)
                         }
                     }
                 }
-                Err(e) => {
-                    error!("Failed to bind UDP forwarder on {}: {}", bind_addr, e);
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                    continue;
-                }
+                Err(e) => match bind_failed("udp", listen_port, &format!("{}:{}", target_host, target_port), &e, give_up_after) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return,
+                },
             }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_the_cap() {
+        let delays: Vec<u64> = (1..=7).map(|attempts| retry_delay(attempts).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_binding_state_follows_failures_and_recovery() {
+        let in_use = std::io::Error::from(ErrorKind::AddrInUse);
+        assert_eq!(bind_failed("tcp", 1, "127.0.0.1:9", &in_use, None), Some(Duration::from_secs(2)));
+        assert_eq!(bind_failed("tcp", 1, "127.0.0.1:9", &in_use, None), Some(Duration::from_secs(4)));
+        let Some(BindingState::Retrying { attempts: 2, last_error, retry_in_secs: 4 }) = forwarder_status("tcp", 1).map(|s| s.state) else {
+            panic!("expected a retrying forwarder: {:?}", forwarder_status("tcp", 1));
+        };
+        assert_eq!(last_error, in_use.to_string());
+
+        mark_bound("tcp", 1, "127.0.0.1:9");
+        assert_eq!(forwarder_status("tcp", 1).unwrap().state, BindingState::Bound);
+        // A later failure starts the backoff over
+        assert_eq!(bind_failed("tcp", 1, "127.0.0.1:9", &in_use, None), Some(Duration::from_secs(2)));
+
+        let denied = std::io::Error::from(ErrorKind::PermissionDenied);
+        assert_eq!(bind_failed("udp", 1, "127.0.0.1:9", &denied, None), None);
+        assert!(matches!(forwarder_status("udp", 1).unwrap().state, BindingState::Disabled { .. }));
+    }
+
+    #[test]
+    fn test_optional_forwarder_gives_up_after_its_attempts() {
+        let in_use = std::io::Error::from(ErrorKind::AddrInUse);
+        assert_eq!(bind_failed("udp", 2, "127.0.0.1:9", &in_use, Some(3)), Some(Duration::from_secs(2)));
+        assert_eq!(bind_failed("udp", 2, "127.0.0.1:9", &in_use, Some(3)), Some(Duration::from_secs(4)));
+        assert_eq!(bind_failed("udp", 2, "127.0.0.1:9", &in_use, Some(3)), None);
+        let BindingState::Disabled { reason } = forwarder_status("udp", 2).unwrap().state else {
+            panic!("expected a disabled forwarder: {:?}", forwarder_status("udp", 2));
+        };
+        assert!(reason.starts_with("gave up after 3 attempts"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn test_occupied_udp_port_is_given_up_while_tcp_keeps_forwarding() {
+        use tokio::io::AsyncReadExt;
+
+        let occupied = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+        let listen_port = occupied.local_addr().unwrap().port();
+        let echo = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = echo.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+
+        start_udp_forwarder(listen_port, "udp-degraded.test".to_string(), "127.0.0.1".to_string(), echo_port, Some(1));
+        start_tcp_forwarder(listen_port, "udp-degraded.test".to_string(), "127.0.0.1".to_string(), echo_port);
+        let states = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let (Some(udp), Some(tcp)) = (forwarder_status("udp", listen_port), forwarder_status("tcp", listen_port)) {
+                    break (udp.state, tcp.state);
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("both forwarders report a state");
+        assert!(matches!(states.0, BindingState::Disabled { .. }), "{:?}", states.0);
+        assert_eq!(states.1, BindingState::Bound);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", listen_port)).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+        drop(occupied);
+    }
+}
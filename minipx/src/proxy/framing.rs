@@ -0,0 +1,175 @@
+use hyper::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use hyper::{HeaderMap, Version};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+// Requests rejected as malformed, keyed by route
+static REJECTIONS: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+
+fn rejection_counts() -> &'static Mutex<BTreeMap<String, u64>> {
+    REJECTIONS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Why a request's framing or headers were rejected under `strict_http`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FramingRejection {
+    /// Both Content-Length and Transfer-Encoding, which a backend may resolve differently than the proxy
+    ContentLengthWithTransferEncoding,
+    /// Several Content-Length values that disagree
+    ConflictingContentLength,
+    /// A Content-Length that is not a plain decimal number
+    InvalidContentLength,
+    /// A Transfer-Encoding whose final coding is not `chunked`, that repeats `chunked`, or on an HTTP/1.0 request
+    InvalidTransferEncoding,
+    /// A header value continued onto another line (obsolete line folding) or carrying control characters
+    InvalidHeaderValue(String),
+    /// A header name with characters outside the RFC 9110 token set
+    InvalidHeaderName(String),
+}
+
+impl fmt::Display for FramingRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FramingRejection::ContentLengthWithTransferEncoding => f.write_str("both Content-Length and Transfer-Encoding are present"),
+            FramingRejection::ConflictingContentLength => f.write_str("conflicting Content-Length values"),
+            FramingRejection::InvalidContentLength => f.write_str("invalid Content-Length"),
+            FramingRejection::InvalidTransferEncoding => f.write_str("unsupported Transfer-Encoding"),
+            FramingRejection::InvalidHeaderValue(name) => write!(f, "line folding or control characters in the {} header", name),
+            FramingRejection::InvalidHeaderName(name) => write!(f, "invalid characters in header name {:?}", name),
+        }
+    }
+}
+
+/// Check a request's headers for ambiguous message framing and header syntax hyper may let through
+pub fn check(headers: &HeaderMap, version: Version) -> Result<(), FramingRejection> {
+    for (name, value) in headers {
+        if !name.as_str().bytes().all(is_token_char) {
+            return Err(FramingRejection::InvalidHeaderName(name.as_str().to_string()));
+        }
+        if is_folded_or_ctl(value.as_bytes()) {
+            return Err(FramingRejection::InvalidHeaderValue(name.as_str().to_string()));
+        }
+    }
+
+    let mut content_length = None;
+    for value in headers.get_all(CONTENT_LENGTH) {
+        for part in value.to_str().map_err(|_| FramingRejection::InvalidContentLength)?.split(',') {
+            let part = part.trim();
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(FramingRejection::InvalidContentLength);
+            }
+            let length: u64 = part.parse().map_err(|_| FramingRejection::InvalidContentLength)?;
+            if content_length.is_some_and(|previous| previous != length) {
+                return Err(FramingRejection::ConflictingContentLength);
+            }
+            content_length = Some(length);
+        }
+    }
+
+    let mut codings = Vec::new();
+    for value in headers.get_all(TRANSFER_ENCODING) {
+        let value = value.to_str().map_err(|_| FramingRejection::InvalidTransferEncoding)?;
+        codings.extend(value.split(',').map(|coding| coding.trim().to_ascii_lowercase()).filter(|coding| !coding.is_empty()));
+    }
+    if headers.contains_key(TRANSFER_ENCODING) {
+        if content_length.is_some() {
+            return Err(FramingRejection::ContentLengthWithTransferEncoding);
+        }
+        let chunked = codings.iter().filter(|coding| coding.as_str() == "chunked").count();
+        if version == Version::HTTP_10 || chunked != 1 || codings.last().map(String::as_str) != Some("chunked") {
+            return Err(FramingRejection::InvalidTransferEncoding);
+        }
+    }
+
+    Ok(())
+}
+
+/// The lenient (`strict_http: false`) fix-up: Transfer-Encoding wins over Content-Length, so the latter is dropped
+/// before forwarding rather than letting the backend pick one
+pub fn drop_conflicting_length(headers: &mut HeaderMap) {
+    if headers.contains_key(TRANSFER_ENCODING) {
+        headers.remove(CONTENT_LENGTH);
+    }
+}
+
+pub(crate) fn record_rejection(route: &str) {
+    *rejection_counts().lock().unwrap().entry(route.to_string()).or_default() += 1;
+}
+
+/// Requests rejected as malformed since startup, per route
+pub fn malformed_requests() -> BTreeMap<String, u64> {
+    rejection_counts().lock().unwrap().clone()
+}
+
+// httparse and `HeaderValue` refuse these today; checked again so a parser or embedder change cannot smuggle them through
+fn is_folded_or_ctl(value: &[u8]) -> bool {
+    value.iter().any(|&b| b == b'\r' || b == b'\n' || b == 0)
+}
+
+// tchar from RFC 9110 section 5.6.2
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_well_formed_requests_pass() {
+        assert_eq!(check(&headers(&[("content-length", "5")]), Version::HTTP_11), Ok(()));
+        assert_eq!(check(&headers(&[("content-length", "5"), ("content-length", "5, 5")]), Version::HTTP_11), Ok(()));
+        assert_eq!(check(&headers(&[("transfer-encoding", "gzip, chunked")]), Version::HTTP_11), Ok(()));
+        assert_eq!(check(&headers(&[("transfer-encoding", "gzip"), ("transfer-encoding", "Chunked")]), Version::HTTP_11), Ok(()));
+        assert_eq!(check(&HeaderMap::new(), Version::HTTP_10), Ok(()));
+    }
+
+    #[test]
+    fn test_ambiguous_framing_is_rejected() {
+        let both = headers(&[("content-length", "5"), ("transfer-encoding", "chunked")]);
+        assert_eq!(check(&both, Version::HTTP_11), Err(FramingRejection::ContentLengthWithTransferEncoding));
+
+        let differing = headers(&[("content-length", "5"), ("content-length", "6")]);
+        assert_eq!(check(&differing, Version::HTTP_11), Err(FramingRejection::ConflictingContentLength));
+        assert_eq!(check(&headers(&[("content-length", "5, 6")]), Version::HTTP_11), Err(FramingRejection::ConflictingContentLength));
+        assert_eq!(check(&headers(&[("content-length", "+5")]), Version::HTTP_11), Err(FramingRejection::InvalidContentLength));
+        assert_eq!(check(&headers(&[("content-length", "")]), Version::HTTP_11), Err(FramingRejection::InvalidContentLength));
+
+        for te in ["identity", "chunked, gzip", "chunked, chunked", "xchunked"] {
+            assert_eq!(check(&headers(&[("transfer-encoding", te)]), Version::HTTP_11), Err(FramingRejection::InvalidTransferEncoding), "{}", te);
+        }
+        assert_eq!(check(&headers(&[("transfer-encoding", "chunked")]), Version::HTTP_10), Err(FramingRejection::InvalidTransferEncoding));
+    }
+
+    #[test]
+    fn test_folded_values_and_bad_names_are_rejected() {
+        assert!(is_folded_or_ctl(b"first\r\n second"));
+        assert!(is_folded_or_ctl(b"first\n\tsecond"));
+        assert!(is_folded_or_ctl(b"a\0b"));
+        assert!(!is_folded_or_ctl(b"a\tb, c"));
+
+        assert!(is_token_char(b'-') && is_token_char(b'x') && is_token_char(b'~'));
+        assert!(![b' ', b':', b'(', b'"', b'\t', 0x7f, 0xc3].into_iter().any(is_token_char));
+    }
+
+    #[test]
+    fn test_lenient_mode_drops_content_length() {
+        let mut both = headers(&[("content-length", "5"), ("transfer-encoding", "chunked")]);
+        drop_conflicting_length(&mut both);
+        assert!(!both.contains_key(CONTENT_LENGTH));
+
+        let mut length_only = headers(&[("content-length", "5")]);
+        drop_conflicting_length(&mut length_only);
+        assert!(length_only.contains_key(CONTENT_LENGTH));
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use hyper::header::{self, HeaderName};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use std::fmt;
+
+// Room for the request line on top of the header block when sizing a connection's read buffer
+const REQUEST_LINE_ALLOWANCE: usize = 8 * 1024;
+// hyper refuses a smaller read buffer
+const MIN_CONNECTION_BUFFER: usize = 8 * 1024;
+
+// Headers minipx sets on forwarded requests (or needs for framing); truncation never drops them
+const KEPT_UPSTREAM: [&str; 9] = [
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "x-real-ip",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+    "x-tls-version",
+    "x-tls-cipher",
+    "x-tls-sni",
+];
+
+/// The most header bytes and header fields a request may carry (`max_header_bytes` / `max_header_count`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderLimits {
+    pub bytes: usize,
+    pub count: usize,
+}
+
+/// Which header limit a request exceeds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderLimitViolation {
+    TooLarge { bytes: usize, limit: usize },
+    TooMany { count: usize, limit: usize },
+}
+
+impl fmt::Display for HeaderLimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderLimitViolation::TooLarge { bytes, limit } => write!(f, "{} header bytes, over the limit of {}", bytes, limit),
+            HeaderLimitViolation::TooMany { count, limit } => write!(f, "{} header fields, over the limit of {}", count, limit),
+        }
+    }
+}
+
+impl HeaderLimits {
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), HeaderLimitViolation> {
+        let count = headers.len();
+        if count > self.count {
+            return Err(HeaderLimitViolation::TooMany { count, limit: self.count });
+        }
+        let bytes = block_size(headers);
+        if bytes > self.bytes {
+            return Err(HeaderLimitViolation::TooLarge { bytes, limit: self.bytes });
+        }
+        Ok(())
+    }
+
+    /// Drop headers, last first, until `headers` fits; the ones minipx sets are kept. Returns the names dropped,
+    /// or the violation still left when only kept headers remain.
+    pub fn truncate(&self, headers: &mut HeaderMap) -> Result<Vec<HeaderName>, HeaderLimitViolation> {
+        let mut dropped = Vec::new();
+        let droppable: Vec<HeaderName> = headers.keys().filter(|name| !KEPT_UPSTREAM.contains(&name.as_str())).cloned().collect();
+        let mut droppable = droppable.into_iter().rev();
+        while let Err(violation) = self.check(headers) {
+            let Some(name) = droppable.next() else {
+                return Err(violation);
+            };
+            headers.remove(&name);
+            dropped.push(name);
+        }
+        Ok(dropped)
+    }
+}
+
+/// Bytes the header block takes on the wire: `name: value` plus CRLF per field
+pub fn block_size(headers: &HeaderMap) -> usize {
+    headers.iter().map(|(name, value)| name.as_str().len() + value.len() + 4).sum()
+}
+
+/// Read buffer for an HTTP/1 connection: enough for the request line and the largest header block any route
+/// accepts. hyper answers requests whose head does not fit with 431 before they reach the handler.
+pub fn connection_buffer_size(largest_header_limit: usize) -> usize {
+    largest_header_limit.saturating_add(REQUEST_LINE_ALLOWANCE).max(MIN_CONNECTION_BUFFER)
+}
+
+pub fn too_large_response() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from("Request Header Fields Too Large"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(fields: &[(&'static str, &str)]) -> HeaderMap {
+        fields.iter().map(|(name, value)| (HeaderName::from_static(name), value.parse().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_check_counts_fields_and_wire_bytes() {
+        let map = headers(&[("host", "a.test"), ("accept", "*/*")]);
+        assert_eq!(block_size(&map), "host: a.test\r\n".len() + "accept: */*\r\n".len());
+        assert!(HeaderLimits { bytes: block_size(&map), count: 2 }.check(&map).is_ok());
+        assert_eq!(HeaderLimits { bytes: block_size(&map) - 1, count: 2 }.check(&map), Err(HeaderLimitViolation::TooLarge { bytes: 27, limit: 26 }));
+        assert_eq!(HeaderLimits { bytes: 1024, count: 1 }.check(&map), Err(HeaderLimitViolation::TooMany { count: 2, limit: 1 }));
+    }
+
+    #[test]
+    fn test_truncate_drops_client_headers_last_first() {
+        let mut map = headers(&[("host", "a.test"), ("x-first", "1"), ("x-second", "2"), ("x-real-ip", "192.0.2.1")]);
+        let dropped = HeaderLimits { bytes: 1024, count: 3 }.truncate(&mut map).unwrap();
+        assert_eq!(dropped, vec![HeaderName::from_static("x-second")]);
+        assert!(map.contains_key("x-first") && map.contains_key("x-real-ip"));
+
+        let err = HeaderLimits { bytes: 1024, count: 1 }.truncate(&mut map).unwrap_err();
+        assert_eq!(err, HeaderLimitViolation::TooMany { count: 2, limit: 1 });
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_connection_buffer_has_room_for_the_request_line() {
+        assert_eq!(connection_buffer_size(64 * 1024), 72 * 1024);
+        assert_eq!(connection_buffer_size(0), 8 * 1024);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::config::Config;
+use crate::config::manager::reload_failure;
+use crate::proxy::bind::{UnboundFrontend, unbound_frontends};
+use anyhow::Result;
+use hyper::{Body, Request, Response, StatusCode, header};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+// Process start time and the set of listeners currently bound by this instance
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+static LISTENERS: OnceLock<Mutex<BTreeSet<String>>> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: String,
+    pub version: String,
+    pub uptime_secs: u64,
+    pub routes: usize,
+    pub listeners: Vec<String>,
+    /// The last config reload failed and the previous config is still serving
+    #[serde(default)]
+    pub config_reload_failed: bool,
+    /// Frontends still trying to bind their port (bind_failure_policy retry); the status is "degraded" meanwhile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unbound_frontends: Vec<UnboundFrontend>,
+}
+
+/// Mark the process start time; called once when the servers start (idempotent)
+pub fn mark_started() {
+    STARTED_AT.get_or_init(Instant::now);
+}
+
+/// Seconds since `mark_started` was first called
+pub fn uptime_secs() -> u64 {
+    STARTED_AT.get_or_init(Instant::now).elapsed().as_secs()
+}
+
+fn listeners() -> &'static Mutex<BTreeSet<String>> {
+    LISTENERS.get_or_init(|| Mutex::new(BTreeSet::new()))
+}
+
+/// Record a listener as bound, e.g. `register_listener("http", addr)`
+pub fn register_listener(kind: &str, addr: SocketAddr) {
+    listeners().lock().unwrap().insert(format!("{}://{}", kind, addr));
+}
+
+/// Remove a listener previously recorded with `register_listener`
+pub fn unregister_listener(kind: &str, addr: SocketAddr) {
+    listeners().lock().unwrap().remove(&format!("{}://{}", kind, addr));
+}
+
+/// True if the listener is currently recorded as bound
+pub fn is_listening(kind: &str, addr: SocketAddr) -> bool {
+    listeners().lock().unwrap().contains(&format!("{}://{}", kind, addr))
+}
+
+/// Snapshot of the listeners currently bound
+pub fn bound_listeners() -> Vec<String> {
+    listeners().lock().unwrap().iter().cloned().collect()
+}
+
+/// Build the health report for the given config
+pub fn health_report(config: &Config) -> HealthReport {
+    let unbound_frontends = unbound_frontends();
+    HealthReport {
+        status: if unbound_frontends.is_empty() { "ok" } else { "degraded" }.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: uptime_secs(),
+        routes: config.get_routes().len(),
+        listeners: bound_listeners(),
+        config_reload_failed: reload_failure().is_some(),
+        unbound_frontends,
+    }
+}
+
+/// Build the JSON health response; never forwarded upstream
+pub fn health_response(config: &Config) -> Result<Response<Body>> {
+    let body = serde_json::to_vec(&health_report(config))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(body))?)
+}
+
+/// Answer a request on the dedicated health port: the health path or 404
+pub fn handle_dedicated_request(config: &Config, req: &Request<Body>) -> Result<Response<Body>> {
+    let health = config.get_health_endpoint();
+    if health.is_enabled() && health.matches(req.uri().path()) {
+        return health_response(config);
+    }
+    Ok(Response::builder().status(StatusCode::NOT_FOUND).header("Content-Type", "text/plain").body(Body::from("Not Found"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_registry() {
+        let addr: SocketAddr = "127.0.0.1:18080".parse().unwrap();
+        register_listener("test", addr);
+        assert!(bound_listeners().contains(&"test://127.0.0.1:18080".to_string()));
+        unregister_listener("test", addr);
+        assert!(!bound_listeners().contains(&"test://127.0.0.1:18080".to_string()));
+    }
+
+    #[test]
+    fn test_health_report_counts_routes() {
+        let config = Config::default();
+        let report = health_report(&config);
+        assert_eq!(report.status, "ok");
+        assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(report.routes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_dedicated_request_other_path_is_not_found() {
+        let config = Config::default();
+        let req = Request::builder().uri("/other").body(Body::empty()).unwrap();
+        let resp = handle_dedicated_request(&config, &req).unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = Request::builder().uri("/__minipx/health").body(Body::empty()).unwrap();
+        let resp = handle_dedicated_request(&config, &req).unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -1,5 +1,12 @@
+use crate::config::Config;
+use crate::handoff;
+use crate::proxy::activation;
+use crate::proxy::bind;
 use crate::proxy::forwarder::setup_forwarders;
-use crate::proxy::request_handler::handle_request_with_scheme;
+use crate::proxy::header_limits;
+use crate::proxy::health;
+use crate::proxy::service::MinipxService;
+use crate::systemd;
 use anyhow::Result;
 use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
@@ -10,9 +17,23 @@ use std::net::SocketAddr;
 
 /// Start the reverse proxy server with HTTP support on port 80
 pub async fn start_rp_server() -> Result<()> {
+    health::mark_started();
+
     // Set up TCP/UDP forwarders for custom listen ports
     setup_forwarders().await;
 
+    // Routes with an activation window turn on and off while running
+    activation::spawn_activation_timer();
+
+    // Serve the health endpoint on its dedicated port if one is configured (read at startup)
+    if let Some(port) = Config::get().await.get_health_endpoint().get_port() {
+        tokio::spawn(start_health_server(port));
+    }
+
+    // Loopback-only admin API, if compiled in and enabled (read at startup)
+    #[cfg(feature = "admin-api")]
+    crate::admin::start_admin_api(&Config::get().await).await;
+
     // Start an HTTP server on port 80
     start_http_server().await
 }
@@ -22,41 +43,99 @@ async fn start_http_server() -> Result<()> {
     loop {
         let addr = SocketAddr::from(([0, 0, 0, 0], 80));
 
+        let service = MinipxService::new("http");
         let make_svc = make_service_fn(move |conn: &AddrStream| {
-            let remote_addr = conn.remote_addr().ip();
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                    let client_ip = remote_addr;
-                    async move {
-                        match handle_request_with_scheme("http", client_ip, req).await {
-                            Ok(resp) => Ok::<_, Infallible>(resp),
-                            Err(e) => {
-                                error!("handle_request error from {}: {}", client_ip, e);
-                                Ok::<_, Infallible>(Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap())
-                            }
-                        }
-                    }
-                }))
-            }
+            let service = service.for_peer(conn.remote_addr().ip());
+            async move { Ok::<_, Infallible>(service) }
         });
 
-        let builder = match hyper::Server::try_bind(&addr) {
-            Ok(b) => b,
+        // A socket passed on by a warm restart or by systemd (socket activation) takes the place of binding port 80
+        let listener = match handoff::inherited_listener("http").or_else(|| systemd::activated_listener("http")) {
+            Some(listener) => listener,
+            None => {
+                let config = Config::get().await;
+                let policy = bind::bind_failure_policy(&config);
+                bind::bind_with_policy("http", addr, policy, config.get_bind_failure_attempts(), bind::RETRY_DELAY, || {
+                    std::net::TcpListener::bind(addr)
+                })
+                .await?
+            }
+        };
+        handoff::register_listener("http", &listener);
+        let builder = match hyper::Server::from_tcp(listener) {
+            // Header blocks larger than any route accepts are answered with 431 before they are buffered in full
+            Ok(b) => b.http1_max_buf_size(header_limits::connection_buffer_size(Config::get().await.largest_header_limit())),
             Err(e) => {
-                error!("Failed to bind reverse proxy on {}: {}", addr, e);
-                // No config port to wait for; sleep and retry
-                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                error!("Failed to start reverse proxy on {}: {}", addr, e);
+                tokio::time::sleep(bind::RETRY_DELAY).await;
                 continue;
             }
         };
 
         let server = builder.serve(make_svc);
+        let addr = server.local_addr();
+        // After a warm restart hands the socket over, stop accepting and finish the requests in progress
+        let server = server.with_graceful_shutdown(handoff::handed_off());
 
         info!("Reverse Proxy Server running on {}", addr);
+        health::register_listener("http", addr);
+        systemd::listener_settled("http");
 
         if let Err(e) = server.await {
             error!("Server error: {}", e);
             // Loop will retry bind/start
         }
+        health::unregister_listener("http", addr);
+        handoff::unregister_listener("http");
+        if handoff::is_handed_off() {
+            return Ok(());
+        }
+    }
+}
+
+/// Serve `service` over plain HTTP on a listener bound by the caller, e.g. on an ephemeral port in tests or
+/// an inherited socket. Resolves when the server stops, or has finished its requests after a warm restart handed
+/// the process off; websocket upgrades are supported.
+pub async fn serve_listener(listener: std::net::TcpListener, service: MinipxService) -> Result<()> {
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let service = service.for_peer(conn.remote_addr().ip());
+        async move { Ok::<_, Infallible>(service) }
+    });
+    hyper::Server::from_tcp(listener)?.serve(make_svc).with_graceful_shutdown(handoff::handed_off()).await?;
+    Ok(())
+}
+
+/// Start the dedicated health endpoint server; it never proxies
+async fn start_health_server(port: u16) {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    loop {
+        let make_svc = make_service_fn(|_conn: &AddrStream| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let config = Config::get().await;
+                match health::handle_dedicated_request(&config, &req) {
+                    Ok(resp) => Ok::<_, Infallible>(resp),
+                    Err(e) => {
+                        error!("Health endpoint error: {}", e);
+                        Ok::<_, Infallible>(Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap())
+                    }
+                }
+            }))
+        });
+
+        let builder = match hyper::Server::try_bind(&addr) {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Failed to bind health endpoint on {}: {}", addr, e);
+                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                continue;
+            }
+        };
+
+        info!("Health endpoint running on {}", addr);
+        health::register_listener("health", addr);
+        if let Err(e) = builder.serve(make_svc).await {
+            error!("Health endpoint server error: {}", e);
+        }
+        health::unregister_listener("health", addr);
     }
 }
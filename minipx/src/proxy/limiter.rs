@@ -0,0 +1,235 @@
+use crate::config::ProxyRoute;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// Limiters outlive config reloads so that permits held by in-flight requests are never dropped
+static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<RouteLimiter>>>> = OnceLock::new();
+
+fn limiters() -> &'static Mutex<HashMap<String, Arc<RouteLimiter>>> {
+    LIMITERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Concurrency limiter for a single route (by route key)
+#[derive(Debug)]
+pub struct RouteLimiter {
+    semaphore: Arc<Semaphore>,
+    // Limit currently reflected in the semaphore's capacity; guarded so resizes don't interleave
+    limit: Mutex<usize>,
+    active: AtomicUsize,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+/// Why a request could not get a slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitRejection {
+    /// Saturated and the queue is full (or no queue is configured)
+    QueueFull,
+    /// Waited in the queue for the configured timeout
+    Timeout,
+}
+
+/// A slot held for the duration of a request or websocket tunnel
+#[derive(Debug)]
+pub struct RoutePermit {
+    _permit: OwnedSemaphorePermit,
+    limiter: Arc<RouteLimiter>,
+}
+
+/// Current occupancy of a limited route
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteLimitStats {
+    pub domain: String,
+    pub limit: usize,
+    pub active: usize,
+    pub queued: usize,
+    pub rejected: u64,
+}
+
+impl RouteLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            limit: Mutex::new(limit),
+            active: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Grow or shrink capacity to `limit` without touching permits already handed out
+    fn resize(&self, limit: usize) {
+        let mut current = self.limit.lock().unwrap();
+        if *current == limit {
+            return;
+        }
+        if limit > *current {
+            self.semaphore.add_permits(limit - *current);
+        } else {
+            let excess = *current - limit;
+            let forgotten = self.semaphore.forget_permits(excess);
+            if forgotten < excess {
+                // The rest are held by in-flight requests: retire them as they are released
+                let semaphore = self.semaphore.clone();
+                let remaining = (excess - forgotten) as u32;
+                tokio::spawn(async move {
+                    if let Ok(permits) = semaphore.acquire_many_owned(remaining).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+        *current = limit;
+    }
+
+    fn permit(self: &Arc<Self>, permit: OwnedSemaphorePermit) -> RoutePermit {
+        self.active.fetch_add(1, Ordering::AcqRel);
+        RoutePermit { _permit: permit, limiter: self.clone() }
+    }
+
+    /// Take a slot, waiting in the route's queue when saturated
+    pub async fn acquire(self: &Arc<Self>, route: &ProxyRoute) -> Result<RoutePermit, LimitRejection> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(self.permit(permit));
+        }
+        let Some(queue) = route.get_queue().filter(|q| q.get_size() > 0) else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(LimitRejection::QueueFull);
+        };
+        if self.queued.fetch_add(1, Ordering::AcqRel) >= queue.get_size() {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(LimitRejection::QueueFull);
+        }
+        let waited = tokio::time::timeout(Duration::from_millis(queue.get_timeout_ms()), self.semaphore.clone().acquire_owned()).await;
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        match waited {
+            Ok(Ok(permit)) => Ok(self.permit(permit)),
+            _ => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(LimitRejection::Timeout)
+            }
+        }
+    }
+
+    fn stats(&self, domain: &str) -> RouteLimitStats {
+        RouteLimitStats {
+            domain: domain.to_string(),
+            limit: *self.limit.lock().unwrap(),
+            active: self.active.load(Ordering::Acquire),
+            queued: self.queued.load(Ordering::Acquire),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for RoutePermit {
+    fn drop(&mut self) {
+        self.limiter.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Limiter for a route, created on first use and resized to the route's current limit (hot reload).
+/// None if the route has no `max_concurrent_requests`.
+pub fn limiter_for(route_key: &str, route: &ProxyRoute) -> Option<Arc<RouteLimiter>> {
+    let limit = route.get_max_concurrent_requests()?;
+    let limiter = limiters().lock().unwrap().entry(route_key.to_string()).or_insert_with(|| Arc::new(RouteLimiter::new(limit))).clone();
+    limiter.resize(limit);
+    Some(limiter)
+}
+
+/// Seconds a rejected client should wait before retrying (`Retry-After`)
+pub fn retry_after_secs(route: &ProxyRoute) -> u64 {
+    route.get_queue().map(|q| q.get_timeout_ms().div_ceil(1000)).unwrap_or(0).max(1)
+}
+
+/// Occupancy of every route that has been limited since startup
+pub fn route_limit_stats() -> Vec<RouteLimitStats> {
+    let mut stats: Vec<RouteLimitStats> = limiters().lock().unwrap().iter().map(|(domain, limiter)| limiter.stats(domain)).collect();
+    stats.sort_by(|a, b| a.domain.cmp(&b.domain));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RouteQueue;
+
+    fn limited_route(limit: usize, queue: Option<RouteQueue>) -> ProxyRoute {
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 8080, false, None, false);
+        route.set_max_concurrent_requests(Some(limit));
+        route.set_queue(queue);
+        route
+    }
+
+    #[test]
+    fn test_unlimited_route_has_no_limiter() {
+        let route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 8080, false, None, false);
+        assert!(limiter_for("unlimited.limit.test", &route).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_saturated_without_queue_is_rejected() {
+        let route = limited_route(1, None);
+        let limiter = limiter_for("noqueue.limit.test", &route).unwrap();
+        let held = limiter.acquire(&route).await.unwrap();
+        assert_eq!(limiter.acquire(&route).await.unwrap_err(), LimitRejection::QueueFull);
+        drop(held);
+        assert!(limiter.acquire(&route).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_waits_then_times_out() {
+        let route = limited_route(1, Some(RouteQueue::new(1, 50)));
+        let limiter = limiter_for("queue.limit.test", &route).unwrap();
+        let held = limiter.acquire(&route).await.unwrap();
+
+        // One request may wait; a second concurrent waiter finds the queue full
+        let waiter = {
+            let limiter = limiter.clone();
+            let route = route.clone();
+            tokio::spawn(async move { limiter.acquire(&route).await.map(|_| ()) })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limiter.acquire(&route).await.unwrap_err(), LimitRejection::QueueFull);
+        assert_eq!(waiter.await.unwrap().unwrap_err(), LimitRejection::Timeout);
+
+        // A queued request gets the slot once it is released
+        let waiter = {
+            let limiter = limiter.clone();
+            let route = route.clone();
+            tokio::spawn(async move { limiter.acquire(&route).await.map(|_| ()) })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(held);
+        assert!(waiter.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resize_keeps_existing_permits() {
+        let route = limited_route(2, None);
+        let limiter = limiter_for("resize.limit.test", &route).unwrap();
+        let first = limiter.acquire(&route).await.unwrap();
+        let second = limiter.acquire(&route).await.unwrap();
+
+        // Shrinking below the current occupancy keeps both permits but admits nothing new
+        let shrunk = limited_route(1, None);
+        let limiter = limiter_for("resize.limit.test", &shrunk).unwrap();
+        assert!(limiter.acquire(&shrunk).await.is_err());
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(limiter.acquire(&shrunk).await.is_err());
+        drop(second);
+        tokio::task::yield_now().await;
+        let third = limiter.acquire(&shrunk).await.unwrap();
+        assert!(limiter.acquire(&shrunk).await.is_err());
+
+        let stats = route_limit_stats().into_iter().find(|s| s.domain == "resize.limit.test").unwrap();
+        assert_eq!((stats.limit, stats.active), (1, 1));
+        drop(third);
+    }
+}
@@ -1,16 +1,63 @@
 // Proxy module
 //
 // This module contains all reverse proxy functionality split into focused submodules:
+// - activation: Route activation windows: inactive responses and the timer re-broadcasting the config on transitions
+// - bind: Binding the HTTP/HTTPS frontend ports under bind_failure_policy, and the unbound-frontend state
+// - bandwidth: Per-route outbound bandwidth caps (token bucket shared by the route's connections)
+// - cache_control: Per-route Cache-Control/Expires injection for responses the backend left without one
+// - capture: Opt-in request/response body capture for debugging a route
+// - client_ip: Effective client IP resolution behind trusted proxies
 // - http_server: HTTP server setup and management
 // - https_server: HTTPS/SSL server functionality (from ssl_server.rs)
 // - request_handler: HTTP request processing logic
+// - request_log: Per-route request summaries (counts, status classes, latency percentiles) for request_log_mode
+// - resolver: Backend name resolution (static hosts, system or custom DNS servers) for every upstream connection
+// - rewrite: Opt-in rewriting of backend addresses in redirects and cookies
+// - service: The request handler as a hyper/tower Service, for embedding and for the built-in servers
+// - unix_socket: Backends on a unix domain socket: `unix://` upstream targets and the stream/connector for them
+// - upstream: Pooled HTTP client for proxied requests, with a retry for stale keep-alive connections
 // - websocket: WebSocket handling logic
+// - ws_stats: Websocket tunnel byte counts, durations and close reasons (session log line and per-route counters)
 // - forwarder: TCP/UDP forwarding logic
+// - framing: Request smuggling checks (conflicting Content-Length/Transfer-Encoding, malformed headers) for strict_http
+// - header_limits: max_header_bytes / max_header_count checks for requests and what is forwarded upstream, and the connection buffer size
+// - health: Built-in health endpoint and listener bookkeeping
+// - transfer: Request/response body bytes per route and the top clients by bytes, counted as the bodies stream
+// - tls_info: Negotiated TLS version/cipher/ALPN/SNI per HTTPS connection, for access logs and X-TLS-* headers
+// - unknown_host: Allocation-free responses, per-client limits and aggregated logging for unknown or malformed hosts
+// - limiter: Per-route concurrency limits and queueing
+// - route_table: Dump of the in-memory routing table and dry-run resolution of a host/path
+// - routing_trace: The route, subroute, backend and decision behind each response, for the access log and debug_routing_headers
 
+pub mod activation;
+pub mod bandwidth;
+pub mod bind;
+pub mod cache_control;
+pub mod capture;
+pub mod client_ip;
 pub mod forwarder;
+pub mod framing;
+pub mod header_limits;
+pub mod health;
 pub mod http_server;
+pub mod limiter;
 pub mod request_handler;
+pub mod request_log;
+pub mod resolver;
+pub mod rewrite;
+pub mod route_table;
+pub mod routing_trace;
+pub mod service;
+pub mod tls_info;
+pub mod transfer;
+pub mod unix_socket;
+pub mod unknown_host;
+pub mod upstream;
 pub mod websocket;
+pub mod ws_stats;
 
 // Re-export main function for backward compatibility
-pub use http_server::start_rp_server;
+pub use http_server::{serve_listener, start_rp_server};
+pub use service::{ClientIpSource, ConfigHandle, MinipxService};
+pub use tls_info::TlsInfo;
+pub use unknown_host::ConnectionClosed;
@@ -1,49 +1,349 @@
-use crate::config::Config;
-use crate::config::types::ProxyPathRoute;
+use crate::config::manager::InFlightGuard;
+use crate::config::types::{ProxyPathRoute, ProxyRoute};
+use crate::config::{Config, HeaderLimitPolicy, InactiveResponse, RequestLogMode};
+use crate::proxy::activation;
+use crate::proxy::bandwidth::bandwidth_for;
+use crate::proxy::cache_control;
+use crate::proxy::capture::capture_session;
+use crate::proxy::client_ip::{ClientIp, resolve_client_ip};
+use crate::proxy::framing;
+use crate::proxy::header_limits;
+use crate::proxy::health;
+use crate::proxy::limiter::{RoutePermit, limiter_for, retry_after_secs};
+use crate::proxy::request_log;
+use crate::proxy::rewrite::ResponseRewrite;
+use crate::proxy::routing_trace::{Decision, RoutingTrace};
+use crate::proxy::tls_info::TlsInfo;
+use crate::proxy::transfer;
+use crate::proxy::unix_socket;
+use crate::proxy::unknown_host;
+use crate::proxy::upstream;
 use crate::proxy::websocket::{is_websocket, proxy_websocket};
+use crate::utils::host::{is_valid_authority, normalize_host, strip_port};
 use anyhow::{Result, anyhow};
-use hyper::{Body, Request, Response, StatusCode, header};
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Request, Response, StatusCode, header};
 use log::{debug, error, info, warn};
+use std::fmt::Display;
 use std::net::IpAddr;
+use std::path::Path;
+use std::time::Instant;
+use time::OffsetDateTime;
+use tokio_stream::StreamExt;
 
-/// Extract the host from the request URI or Host header
+/// Extract the normalized host from the request URI or Host header
+/// (lowercase, without port, trailing dot, or IPv6 brackets)
 pub fn extract_host(req: &Request<Body>) -> Option<String> {
     if let Some(authority) = req.uri().authority() {
-        return Some(authority.host().to_string());
+        return Some(normalize_host(authority.host()).into_owned());
     }
 
     #[allow(clippy::collapsible_if)]
     if let Some(hv) = req.headers().get(header::HOST) {
         if let Ok(host) = hv.to_str() {
-            let host_only = host.split(':').next().unwrap_or(host);
-            return Some(host_only.to_string());
+            return Some(normalize_host(strip_port(host)).into_owned());
         }
     }
-    req.uri().host().map(|h| h.to_string())
+    req.uri().host().map(|h| normalize_host(h).into_owned())
+}
+
+/// What a proxied request holds until it completes: its route generation pin and concurrency slot
+#[derive(Debug, Default)]
+pub struct InFlight {
+    _generation: Option<InFlightGuard>,
+    permit: Option<RoutePermit>,
+}
+
+/// Why a request's host could not be determined unambiguously
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostRejection {
+    /// Neither the URI nor a Host header carries a host
+    Missing,
+    /// Several Host headers with different values
+    MultipleHosts,
+    /// A Host header or authority that is not a valid hostname / IP literal
+    Invalid(String),
+    /// Absolute-form URI whose authority disagrees with the Host header
+    AuthorityMismatch { authority: String, host: String },
+}
+
+impl Display for HostRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostRejection::Missing => write!(f, "no host in URI or Host header"),
+            HostRejection::MultipleHosts => write!(f, "multiple differing Host headers"),
+            HostRejection::Invalid(value) => write!(f, "malformed host {:?}", value),
+            HostRejection::AuthorityMismatch { authority, host } => write!(f, "URI authority '{}' does not match Host header '{}'", authority, host),
+        }
+    }
+}
+
+/// Resolve the normalized host of a request, rejecting ambiguous or malformed hosts.
+/// Unlike `extract_host`, nothing is resolved by "first one wins": every Host header must agree,
+/// and with `strict_authority` an absolute-form URI authority must match the Host header.
+pub fn resolve_host(req: &Request<Body>, strict_authority: bool) -> std::result::Result<String, HostRejection> {
+    let mut header_host: Option<String> = None;
+    for value in req.headers().get_all(header::HOST) {
+        let raw = value.to_str().map_err(|_| HostRejection::Invalid(String::from_utf8_lossy(value.as_bytes()).into_owned()))?;
+        if !is_valid_authority(raw) {
+            return Err(HostRejection::Invalid(raw.to_string()));
+        }
+        let host = normalize_host(strip_port(raw)).into_owned();
+        match &header_host {
+            Some(existing) if *existing != host => return Err(HostRejection::MultipleHosts),
+            Some(_) => {}
+            None => header_host = Some(host),
+        }
+    }
+
+    let authority_host = match req.uri().authority() {
+        Some(authority) => {
+            if !is_valid_authority(authority.as_str()) {
+                return Err(HostRejection::Invalid(authority.to_string()));
+            }
+            Some(normalize_host(authority.host()).into_owned())
+        }
+        None => None,
+    };
+
+    match (authority_host, header_host) {
+        (Some(authority), Some(host)) if strict_authority && authority != host => Err(HostRejection::AuthorityMismatch { authority, host }),
+        // The URI authority takes precedence, as in `extract_host`
+        (Some(authority), _) => Ok(authority),
+        (None, Some(host)) => Ok(host),
+        (None, None) => Err(HostRejection::Missing),
+    }
+}
+
+/// A route's behavior for one request path, after applying the matching subroute's overrides
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteSelection<'a> {
+    pub subroute: Option<&'a ProxyPathRoute>,
+    pub redirect_to_https: bool,
+    pub allow_websocket: bool,
+    /// None keeps the historical default (preserved for HTTP, rewritten for websocket handshakes)
+    pub preserve_host: Option<bool>,
+    pub upstream_port: u16,
+    /// The route's unix socket, unless a subroute (always on host:port) serves the path
+    pub unix_socket: Option<&'a Path>,
+}
+
+/// Select the subroute serving `path` (the first whose path prefixes it on a segment boundary: `/ws` serves `/ws` and
+/// `/ws/chat`, not `/wsx`) and merge its overrides into the route's settings
+pub fn select_route<'a>(route: &'a ProxyRoute, path: &str) -> RouteSelection<'a> {
+    let subroute = route.subroutes.iter().find(|r| r.path != "/" && !r.path.is_empty() && is_under(path, &r.path));
+    RouteSelection {
+        subroute,
+        redirect_to_https: subroute.and_then(|s| s.redirect_to_https).unwrap_or(route.get_redirect_to_https()),
+        allow_websocket: subroute.and_then(|s| s.allow_websocket).unwrap_or(route.is_websocket_allowed()),
+        preserve_host: subroute.and_then(|s| s.preserve_host).or(route.get_preserve_host()),
+        upstream_port: subroute.map(|s| s.port).unwrap_or(route.get_port()),
+        unix_socket: route.get_unix_socket().filter(|_| subroute.is_none()),
+    }
+}
+
+// `path` is `prefix` itself or below it
+fn is_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Base URL requests are proxied to, e.g. `http://127.0.0.1:3000`
+pub fn upstream_target(upstream_scheme: &str, route: &ProxyRoute, upstream_port: u16) -> String {
+    format!("{}://{}:{}", upstream_scheme, route.get_host(), upstream_port)
+}
+
+/// Path and query sent to a subroute's backend: the subroute prefix is stripped (`/api/users?x=1` -> `/users?x=1`)
+pub fn subroute_upstream_path(subroute_path: &str, path: &str, query: Option<&str>) -> String {
+    let stripped = match path.strip_prefix(subroute_path) {
+        Some(rest) if !rest.is_empty() => rest,
+        _ => "/",
+    };
+    match query {
+        Some(query) => format!("{}?{}", stripped, query),
+        None => stripped.to_string(),
+    }
 }
 
 /// Handle HTTP/HTTPS request with the specified frontend scheme
 pub async fn handle_request_with_scheme(frontend_scheme: &str, client_ip: IpAddr, req: Request<Body>) -> Result<Response<Body>> {
+    let config = Config::get().await;
+    handle_request_with_config(&config, frontend_scheme, client_ip, req).await
+}
+
+/// Methods the proxy advertises for `OPTIONS *`
+pub const SUPPORTED_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS";
+
+/// Response header naming the backend that answered, with `debug_headers` on and a loopback client
+pub const UPSTREAM_HEADER: &str = "x-upstream";
+/// Response header naming the route that answered, with `debug_headers` on and a loopback client
+pub use crate::proxy::routing_trace::ROUTE_HEADER;
+
+/// Handle HTTP/HTTPS request against an explicit config snapshot
+pub async fn handle_request_with_config(config: &Config, frontend_scheme: &str, client_ip: IpAddr, req: Request<Body>) -> Result<Response<Body>> {
+    let head = req.method() == Method::HEAD;
+    let response = route_request(config, frontend_scheme, client_ip, req).await?;
+    Ok(if head { without_body(response) } else { response })
+}
+
+/// Drop the body of a response to a HEAD request, keeping the Content-Length a GET would have had.
+/// Responses proxied from a backend already come without one and keep the backend's headers.
+pub fn without_body(mut response: Response<Body>) -> Response<Body> {
+    let length = response.body().size_hint().exact().filter(|&length| length > 0);
+    let headers = response.headers_mut();
+    if let Some(length) = length
+        && !headers.contains_key(header::CONTENT_LENGTH)
+        && !headers.contains_key(header::TRANSFER_ENCODING)
+    {
+        headers.insert(header::CONTENT_LENGTH, header::HeaderValue::from(length));
+    }
+    *response.body_mut() = Body::empty();
+    response
+}
+
+fn add_debug_headers(response: &mut Response<Body>, route_key: &str, backend: &str) {
+    let headers = response.headers_mut();
+    if let Ok(value) = header::HeaderValue::from_str(backend) {
+        headers.insert(UPSTREAM_HEADER, value);
+    }
+    if let Ok(value) = header::HeaderValue::from_str(route_key) {
+        headers.insert(ROUTE_HEADER, value);
+    }
+}
+
+// The X-Minipx-* routing headers, only ever with debug_routing_headers on
+fn with_trace(config: &Config, mut response: Response<Body>, trace: &RoutingTrace) -> Response<Body> {
+    if config.is_debug_routing_headers() {
+        trace.apply(&mut response);
+    }
+    response
+}
+
+async fn route_request(config: &Config, frontend_scheme: &str, client_ip: IpAddr, req: Request<Body>) -> Result<Response<Body>> {
+    // `OPTIONS *` asks about the server itself, which no backend can answer for
+    if req.method() == Method::OPTIONS && req.uri() == "*" {
+        debug!("Answered OPTIONS * from {}", client_ip);
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::ALLOW, SUPPORTED_METHODS)
+            .header(header::CONTENT_LENGTH, 0)
+            .body(Body::empty())?);
+    }
+
     let mut req = req;
     let uri = req.uri().clone();
-    let domain = extract_host(&req).ok_or(anyhow!("No host in URI or Host header"))?;
+    // Set by the HTTPS server for each request on a TLS connection
+    let tls_info = req.extensions().get::<TlsInfo>().cloned();
 
-    let config = Config::get().await;
-    let route = config.lookup_host(&domain);
+    // Resolve the real client behind any trusted proxies; X-Forwarded-For from untrusted peers is dropped
+    let client = resolve_client_ip(client_ip, req.headers_mut(), config.get_trusted_proxies());
+    let client_ip = client.effective;
+
+    // The health endpoint is answered by the proxy itself on any Host
+    let health_endpoint = config.get_health_endpoint();
+    if health_endpoint.is_served_on_frontends() && health_endpoint.matches(uri.path()) {
+        if health_endpoint.get_log_requests() {
+            info!("Health probe from {ip} over {fs}", ip = client_ip, fs = frontend_scheme);
+        } else {
+            debug!("Health probe from {ip} over {fs}", ip = client_ip, fs = frontend_scheme);
+        }
+        return health::health_response(config);
+    }
+
+    let domain = match resolve_host(&req, config.is_strict_host_authority()) {
+        Ok(domain) => domain,
+        Err(HostRejection::Missing) => return Err(anyhow!("No host in URI or Host header")),
+        Err(rejection) => {
+            let response = unknown_host::reject_host(config.get_unknown_host(), client_ip, &rejection)?;
+            return Ok(with_trace(config, response, &RoutingTrace::unrouted(Decision::Blocked)));
+        }
+    };
+
+    let Some((route_key, route)) = config.lookup_route(&domain) else {
+        let response = unknown_host::respond(config.get_unknown_host(), client_ip, &domain)?;
+        let decision = if response.status().is_redirection() { Decision::Redirected } else { Decision::NotFound };
+        return Ok(with_trace(config, response, &RoutingTrace::unrouted(decision)));
+    };
+
+    let started = Instant::now();
+    let mut trace = RoutingTrace::new(route_key);
+    let now = OffsetDateTime::now_utc();
+    let result = if route.is_active_at(now) {
+        proxy_to_route(config, frontend_scheme, client, tls_info, req, &domain, route_key, route, &mut trace).await
+    } else {
+        trace.decision = match route.get_inactive_response() {
+            InactiveResponse::NotFound => Decision::NotFound,
+            InactiveResponse::Maintenance => Decision::Maintenance,
+        };
+        activation::inactive_response(route, &domain, now)
+    };
+    // Errors are logged by the caller, which answers them with a 500
+    let status = result.as_ref().map_or(StatusCode::INTERNAL_SERVER_ERROR, Response::status);
+    request_log::finished(config, &trace, client_ip, uri.path(), status, started.elapsed());
+    result.map(|response| with_trace(config, response, &trace))
+}
 
-    if route.is_none() {
-        warn!("Received request from {ip} for unknown host {host}", ip = client_ip, host = domain);
+/// Proxy a request whose host resolved to `route`, recording in `trace` what became of it
+#[allow(clippy::too_many_arguments)]
+async fn proxy_to_route<'a>(
+    config: &Config,
+    frontend_scheme: &str,
+    client: ClientIp,
+    tls_info: Option<TlsInfo>,
+    mut req: Request<Body>,
+    domain: &str,
+    route_key: &str,
+    route: &'a ProxyRoute,
+    trace: &mut RoutingTrace<'a>,
+) -> Result<Response<Body>> {
+    let uri = req.uri().clone();
+    let client_ip = client.effective;
+
+    // Check for matching subroute based on request path; its overrides take precedence over the route's settings
+    let RouteSelection { subroute: sub_route, redirect_to_https, allow_websocket, preserve_host, upstream_port, unix_socket: backend_socket } =
+        select_route(route, uri.path());
+    trace.subroute = sub_route.map(|sub| sub.path.as_str());
+
+    // Refuse requests a backend could frame differently than we do (request smuggling) before anything is forwarded
+    if config.is_strict_http() {
+        if let Err(rejection) = framing::check(req.headers(), req.version()) {
+            framing::record_rejection(route_key);
+            warn!("Rejected malformed request from {ip} for {host}: {reason}", ip = client_ip, host = domain, reason = rejection);
+            trace.decision = Decision::Blocked;
+            return Ok(Response::builder().status(StatusCode::BAD_REQUEST).header("Content-Type", "text/plain").body(Body::from("Bad Request"))?);
+        }
+    } else {
+        framing::drop_conflicting_length(req.headers_mut());
+    }
+
+    // hyper already refused header blocks larger than any route accepts; this route may accept less
+    let header_limits = config.header_limits(route);
+    if let Err(violation) = header_limits.check(req.headers()) {
+        warn!("Rejected request from {ip} for {host}: {reason}", ip = client_ip, host = domain, reason = violation);
+        trace.decision = Decision::Blocked;
+        return header_limits::too_large_response();
+    }
+
+    // Routes that only exist to hold subroutes can refuse everything else
+    if sub_route.is_none() && !route.is_not_found_passthrough() && route.get_path().is_empty() {
+        debug!("No subroute of {host} serves {path}, answering 404", host = domain, path = uri.path());
+        trace.decision = Decision::NotFound;
         return Ok(Response::builder().status(StatusCode::NOT_FOUND).header("Content-Type", "text/plain").body(Body::from("Not Found"))?);
     }
 
-    let route = route.unwrap();
+    // Pin this request to the route generation of the snapshot it started with, so reloads can report draining
+    let mut in_flight =
+        InFlight { _generation: config.route_generation(route_key).map(|generation| generation.track(is_websocket(&req))), permit: None };
 
     // If the client sent HTTP and the route requires HTTPS,
-    // redirect only if TLS can be served for this host.
-    if frontend_scheme.eq_ignore_ascii_case("http") && route.get_redirect_to_https() {
-        if config.can_serve_tls_for_host(&domain) {
+    // redirect only if TLS can be served for this host. ACME challenges, health probes and
+    // redirect_exempt_paths are served over HTTP regardless.
+    if frontend_scheme.eq_ignore_ascii_case("http") && redirect_to_https {
+        if config.is_redirect_exempt(route, uri.path()) {
+            debug!("Serving {host}{path} over HTTP: exempt from the HTTPS redirect", host = domain, path = uri.path());
+        } else if config.can_serve_tls_for_host(domain) {
             let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
             let location = format!("https://{}{}", domain, path_and_query);
+            trace.decision = Decision::Redirected;
             return Ok(Response::builder().status(StatusCode::MOVED_PERMANENTLY).header(header::LOCATION, location).body(Body::empty())?);
         } else {
             warn!(
@@ -53,6 +353,33 @@ pub async fn handle_request_with_scheme(frontend_scheme: &str, client_ip: IpAddr
         }
     }
 
+    if is_websocket(&req) && !allow_websocket {
+        warn!("Rejected websocket upgrade from {ip} for {host}{path}: websockets are disabled", ip = client_ip, host = domain, path = uri.path());
+        trace.decision = Decision::Blocked;
+        return Ok(Response::builder().status(StatusCode::FORBIDDEN).header("Content-Type", "text/plain").body(Body::from("Forbidden"))?);
+    }
+
+    // Take a concurrency slot for the route (queueing if configured); websockets hold it for the tunnel's lifetime
+    if let Some(limiter) = limiter_for(route_key, route) {
+        match limiter.acquire(route).await {
+            Ok(permit) => in_flight.permit = Some(permit),
+            Err(rejection) => {
+                warn!(
+                    "Rejected request from {ip} for {host}: concurrency limit reached ({reason:?})",
+                    ip = client_ip,
+                    host = domain,
+                    reason = rejection
+                );
+                trace.decision = Decision::Blocked;
+                return Ok(Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .header(header::RETRY_AFTER, retry_after_secs(route))
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from("Service Unavailable"))?);
+            }
+        }
+    }
+
     // Determine upstream scheme based on request type and frontend scheme.
     let upstream_scheme = {
         if is_websocket(&req) {
@@ -64,17 +391,18 @@ pub async fn handle_request_with_scheme(frontend_scheme: &str, client_ip: IpAddr
         }
     };
 
-    // Check for matching subroute based on request path
-    let sub_route: Option<ProxyPathRoute> =
-        route.subroutes.iter().find(|r| r.path != "/" && !r.path.is_empty() && uri.path().starts_with(r.path.as_str())).cloned();
-
-    let target = if let Some(sub) = &sub_route {
+    let target = match backend_socket {
+        Some(socket) => unix_socket::target(socket),
+        None => upstream_target(upstream_scheme, route, upstream_port),
+    };
+    // How the backend appears in logs: the socket path rather than its encoded target
+    let backend = backend_socket.map(unix_socket::describe).unwrap_or_else(|| target.clone());
+    let header_timeout = config.response_header_timeout(route);
+    if let Some(sub) = sub_route {
         // For non-WebSocket requests, rewrite the request URI to strip the subroute base path
         if !is_websocket(&req) {
             debug!("Original Route: {req:?}", req = req);
-            let stripped_path = uri.path().strip_prefix(sub.path.as_str()).unwrap_or("/");
-            let queries = uri.path_and_query().and_then(|pq| pq.query()).map(|q| format!("?{}", q)).unwrap_or_default();
-            let stripped_path = format!("{stripped_path}{queries}");
+            let stripped_path = subroute_upstream_path(&sub.path, uri.path(), uri.query());
 
             // Build new request with modified URI
             let og_headers = req.headers().clone();
@@ -87,42 +415,56 @@ pub async fn handle_request_with_scheme(frontend_scheme: &str, client_ip: IpAddr
         } else {
             debug!("WebSocket request - keeping original URI: {req:?}", req = req);
         }
-        format!("{protocol}://{domain}:{port}", protocol = upstream_scheme, domain = route.get_host(), port = sub.port)
     } else {
         debug!("Original Route: {req:?}", req = req);
-        format!("{}://{}:{}", upstream_scheme, route.get_host(), route.get_port())
-    };
+    }
 
-    info!(
-        "Received request from {ip} for {fs}://{host}{path} -> {route}{path}",
-        fs = frontend_scheme,
-        ip = client_ip,
-        host = domain,
-        route = target,
-        path = uri.path()
-    );
+    if config.get_request_log_mode() == RequestLogMode::PerRequest {
+        info!(
+            "Received request from {ip} for {fs}://{host}{path} -> {route}{path}{tls}",
+            fs = frontend_scheme,
+            ip = client_ip,
+            host = domain,
+            route = backend,
+            path = uri.path(),
+            tls = tls_info.as_ref().map(|tls| format!(" [{}]", tls)).unwrap_or_default()
+        );
+    }
+    if route.is_forward_tls_info() {
+        TlsInfo::forward_headers(tls_info.as_ref(), req.headers_mut());
+    }
     debug!("Request details: {req:?}", req = req);
 
     if is_websocket(&req) {
-        debug!("WebSocket upgrade detected: frontend={fs}, upstream={up}", fs = frontend_scheme, up = target);
-        let (ws_host, ws_port) = if let Some(sub) = sub_route.clone() { (route.get_host(), sub.port) } else { (route.get_host(), route.get_port()) };
-
-        let subroute_path = sub_route.map(|s| s.path).unwrap_or_default();
-        return proxy_websocket(client_ip, req, upstream_scheme, ws_host, ws_port, &subroute_path, &domain, frontend_scheme).await;
+        debug!("WebSocket upgrade detected: frontend={fs}, upstream={up}", fs = frontend_scheme, up = backend);
+        let subroute_path = sub_route.map(|s| s.path.clone()).unwrap_or_default();
+        // Websocket handshakes historically carry the backend's host:port unless preserve_host is set
+        let preserve_host = preserve_host.unwrap_or(false);
+        trace.upstream = Some(backend);
+        return proxy_websocket(
+            client,
+            req,
+            upstream_scheme,
+            route.get_host(),
+            upstream_port,
+            backend_socket,
+            &subroute_path,
+            domain,
+            frontend_scheme,
+            preserve_host,
+            route,
+            header_timeout,
+            in_flight,
+        )
+        .await;
     }
 
-    // Add proper forwarding headers
+    // Add proper forwarding headers, after dropping the client's hop-by-hop headers so its Connection header
+    // cannot name (and so remove) the ones added here
     let headers = req.headers_mut();
+    upstream::remove_hop_headers(headers);
 
-    // Set X-Forwarded-For header (append client IP if header exists, otherwise create new)
-    if let Some(xff) = headers.get("x-forwarded-for") {
-        if let Ok(xff_str) = xff.to_str() {
-            let new_xff = format!("{}, {}", xff_str, client_ip);
-            headers.insert("x-forwarded-for", new_xff.parse().unwrap());
-        }
-    } else {
-        headers.insert("x-forwarded-for", client_ip.to_string().parse().unwrap());
-    }
+    // X-Forwarded-For is appended by upstream::forward; appending here too would duplicate the client IP
 
     // Set X-Real-IP header (client's actual IP)
     headers.insert("x-real-ip", client_ip.to_string().parse().unwrap());
@@ -133,13 +475,123 @@ pub async fn handle_request_with_scheme(frontend_scheme: &str, client_ip: IpAddr
     // Set X-Forwarded-Host header (original Host header)
     headers.insert("x-forwarded-host", domain.parse().unwrap());
 
-    debug!("Added forwarding headers: X-Forwarded-For={}, X-Real-IP={}, X-Forwarded-Proto={}, X-Forwarded-Host={}",
-           client_ip, client_ip, frontend_scheme, domain);
+    // The client's Host is forwarded unless the route names one for the backend or asks for the backend's own host:port
+    if let Some(host) = route.get_upstream_host_header() {
+        headers.insert(header::HOST, host.parse()?);
+    } else if preserve_host == Some(false) && backend_socket.is_some() {
+        headers.insert(header::HOST, route.get_host().parse()?);
+    } else if preserve_host == Some(false) {
+        headers.insert(header::HOST, format!("{}:{}", route.get_host(), upstream_port).parse()?);
+    }
+
+    debug!(
+        "Added forwarding headers: X-Forwarded-For=+{}, X-Real-IP={}, X-Forwarded-Proto={}, X-Forwarded-Host={}",
+        client.peer, client_ip, frontend_scheme, domain
+    );
+
+    // The forwarding headers must not push the request past the limits the backend is protected by
+    if let Err(violation) = header_limits.check(headers) {
+        match config.get_upstream_header_policy() {
+            HeaderLimitPolicy::Reject => {
+                warn!(
+                    "Rejected request from {ip} for {host}: forwarded headers would carry {reason}",
+                    ip = client_ip,
+                    host = domain,
+                    reason = violation
+                );
+                trace.decision = Decision::Blocked;
+                return header_limits::too_large_response();
+            }
+            HeaderLimitPolicy::Truncate => match header_limits.truncate(headers) {
+                Ok(dropped) => warn!(
+                    "Dropped {count} header(s) {names:?} from a request for {host}: {reason}",
+                    count = dropped.len(),
+                    names = dropped,
+                    host = domain,
+                    reason = violation
+                ),
+                Err(violation) => {
+                    warn!(
+                        "Rejected request from {ip} for {host}: forwarded headers would carry {reason}",
+                        ip = client_ip,
+                        host = domain,
+                        reason = violation
+                    );
+                    trace.decision = Decision::Blocked;
+                    return header_limits::too_large_response();
+                }
+            },
+        }
+    }
 
-    match hyper_reverse_proxy::call(client_ip, target.as_str(), req).await {
-        Ok(response) => Ok(response),
+    // Debug capture (opt-in per route) sees the request as the backend will, minus X-Forwarded-For
+    let capture = capture_session(route_key, route).map(|session| session.exchange());
+    if let Some(exchange) = &capture {
+        req = exchange.tee_request(req);
+    }
+    // Body bytes in both directions count toward the route's and the client's transfer totals
+    let transfer = transfer::exchange(route_key, client_ip);
+    let (parts, body) = req.into_parts();
+    req = Request::from_parts(parts, transfer.count_request(body));
+
+    trace.upstream = Some(backend.clone());
+    // The peer (not the effective client) is appended to the X-Forwarded-For chain
+    let Some(result) = upstream::forward(client.peer, target.as_str(), req, header_timeout).await.transpose() else {
+        let timeout = header_timeout.unwrap_or_default();
+        warn!("Backend {target} for {host} sent no response headers within {ms} ms", target = backend, host = domain, ms = timeout.as_millis());
+        return upstream::gateway_timeout("response headers", timeout);
+    };
+    match result {
+        Ok(response) => {
+            let mut response = match &capture {
+                Some(exchange) => exchange.tee_response(response),
+                None => response,
+            };
+            if route.get_rewrite_redirects() || route.get_rewrite_cookie_domain() {
+                let rewrite = ResponseRewrite {
+                    backend_host: route.get_host(),
+                    backend_port: upstream_port,
+                    frontend_scheme,
+                    domain,
+                    mount: sub_route.map(|s| s.path.as_str()).unwrap_or_default(),
+                };
+                rewrite.apply(route, &mut response);
+            }
+            if !route.get_cache_control().is_empty() {
+                cache_control::apply(route.get_cache_control(), uri.path(), &mut response);
+            }
+            // Loopback clients such as `minipx routes test-request` learn which route and backend answered
+            if config.is_debug_headers() && client.peer.is_loopback() {
+                add_debug_headers(&mut response, route_key, &backend);
+            }
+            // 204 and 304 responses cannot carry a body, so none of the body wrappers below apply
+            if matches!(response.status(), StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED) {
+                *response.body_mut() = Body::empty();
+                return Ok(response);
+            }
+            // Bandwidth-limited routes pace the body through the route's shared token bucket
+            let response = match bandwidth_for(route_key, route) {
+                Some(bandwidth) => {
+                    let (parts, body) = response.into_parts();
+                    Response::from_parts(parts, bandwidth.throttle(body))
+                }
+                None => response,
+            };
+            // Count the body as it streams, and keep the request in flight until it has been fully streamed (or dropped)
+            let (parts, body) = response.into_parts();
+            Ok(Response::from_parts(
+                parts,
+                Body::wrap_stream(body.map(move |chunk| {
+                    let _ = &in_flight;
+                    if let Ok(data) = &chunk {
+                        transfer.add_response_bytes(data.len());
+                    }
+                    chunk
+                })),
+            ))
+        }
         Err(error) => {
-            error!("HTTP proxy error for {host} -> {target}: {err:?}", host = domain, target = target, err = error);
+            error!("HTTP proxy error for {host} -> {target}: {err:?}", host = domain, target = backend, err = error);
             Ok(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .header("Content-Type", "text/plain")
@@ -151,7 +603,40 @@ pub async fn handle_request_with_scheme(frontend_scheme: &str, client_ip: IpAddr
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{HealthEndpointConfig, ProxyRoute, SubrouteOverrides};
+    use hyper::service::{make_service_fn, service_fn};
     use hyper::{Body, Request};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    // Spawn a tiny upstream that echoes the request path in its body
+    async fn spawn_upstream() -> SocketAddr {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                Ok::<_, Infallible>(Response::new(Body::from(format!("upstream:{}", req.uri().path()))))
+            }))
+        });
+        let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn config_with_upstream(upstream: SocketAddr) -> Config {
+        let mut config = Config::default();
+        config
+            .routes
+            .insert("app.example.com".to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), upstream.port(), false, None, false));
+        config
+    }
+
+    async fn body_string(resp: Response<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(resp.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    fn client() -> IpAddr {
+        IpAddr::from([127, 0, 0, 1])
+    }
 
     #[test]
     fn test_extract_host_from_uri_authority() {
@@ -186,6 +671,21 @@ mod tests {
         assert_eq!(host, Some("uri.example.com".to_string()));
     }
 
+    #[test]
+    fn test_extract_host_normalizes_case_and_trailing_dot() {
+        let req = Request::builder().uri("/path").header("Host", "API.Example.COM.:8443").body(Body::empty()).unwrap();
+        assert_eq!(extract_host(&req), Some("api.example.com".to_string()));
+    }
+
+    #[test]
+    fn test_extract_host_ipv6_with_port() {
+        let req = Request::builder().uri("/path").header("Host", "[::1]:8080").body(Body::empty()).unwrap();
+        assert_eq!(extract_host(&req), Some("::1".to_string()));
+
+        let req = Request::builder().uri("http://[::1]:8080/path").body(Body::empty()).unwrap();
+        assert_eq!(extract_host(&req), Some("::1".to_string()));
+    }
+
     #[test]
     fn test_extract_host_none() {
         let req = Request::builder().uri("/path").body(Body::empty()).unwrap();
@@ -193,4 +693,453 @@ mod tests {
         let host = extract_host(&req);
         assert_eq!(host, None);
     }
+
+    #[test]
+    fn test_resolve_host_multiple_differing_hosts() {
+        let req = Request::builder().uri("/").header("Host", "a.example.com").header("Host", "b.example.com").body(Body::empty()).unwrap();
+        assert_eq!(resolve_host(&req, true), Err(HostRejection::MultipleHosts));
+
+        // Repeated identical values are not ambiguous
+        let req = Request::builder().uri("/").header("Host", "a.example.com").header("Host", "A.example.com").body(Body::empty()).unwrap();
+        assert_eq!(resolve_host(&req, true), Ok("a.example.com".to_string()));
+    }
+
+    #[test]
+    fn test_resolve_host_invalid_syntax() {
+        for bad in ["app.example.com extra", " app.example.com", "app.example.com:port", "*.example.com", "a/b"] {
+            let req = Request::builder().uri("/").header("Host", bad).body(Body::empty()).unwrap();
+            assert!(matches!(resolve_host(&req, true), Err(HostRejection::Invalid(_))), "{bad:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_resolve_host_authority_mismatch() {
+        let req = Request::builder().uri("http://a.example.com/").header("Host", "b.example.com").body(Body::empty()).unwrap();
+        assert!(matches!(resolve_host(&req, true), Err(HostRejection::AuthorityMismatch { .. })));
+        // Lenient mode keeps the URI authority
+        assert_eq!(resolve_host(&req, false), Ok("a.example.com".to_string()));
+
+        let req = Request::builder().uri("http://A.example.com:8080/").header("Host", "a.example.com").body(Body::empty()).unwrap();
+        assert_eq!(resolve_host(&req, true), Ok("a.example.com".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_hosts_are_bad_requests() {
+        let upstream = spawn_upstream().await;
+        let mut config = config_with_upstream(upstream);
+
+        let requests = [
+            Request::builder().uri("/").header("Host", "app.example.com").header("Host", "other.example.com").body(Body::empty()).unwrap(),
+            Request::builder().uri("/").header("Host", "app.example.com\tx").body(Body::empty()).unwrap(),
+            Request::builder().uri("http://app.example.com/").header("Host", "other.example.com").body(Body::empty()).unwrap(),
+        ];
+        for req in requests {
+            let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        config.set_strict_host_authority(false);
+        let req = Request::builder().uri("http://app.example.com/x").header("Host", "other.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/x");
+    }
+
+    #[tokio::test]
+    async fn test_ambiguous_framing_is_rejected_before_forwarding() {
+        let upstream = spawn_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        let rejected = || crate::proxy::framing::malformed_requests().get("app.example.com").copied().unwrap_or_default();
+        let before = rejected();
+
+        let smuggling = || {
+            Request::builder()
+                .method("POST")
+                .uri("/x")
+                .header("Host", "app.example.com")
+                .header(header::CONTENT_LENGTH, "5")
+                .header(header::TRANSFER_ENCODING, "chunked")
+                .body(Body::from("hello"))
+                .unwrap()
+        };
+        let resp = handle_request_with_config(&config, "http", client(), smuggling()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = Request::builder()
+            .uri("/x")
+            .header("Host", "app.example.com")
+            .header(header::CONTENT_LENGTH, "0")
+            .header(header::CONTENT_LENGTH, "7")
+            .body(Body::empty())
+            .unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(rejected() >= before + 2);
+
+        // Lenient mode lets Transfer-Encoding win and forwards the request
+        config.set_strict_http(false);
+        let resp = handle_request_with_config(&config, "http", client(), smuggling()).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/x");
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_request_counted_until_body_consumed() {
+        let upstream = spawn_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        crate::config::manager::advance_generations(&Config::default(), &mut config);
+        let generation = config.route_generation("app.example.com").unwrap().clone();
+
+        let req = Request::builder().uri("/x").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(generation.in_flight_requests(), 1);
+        assert_eq!(body_string(resp).await, "upstream:/x");
+        assert_eq!(generation.in_flight_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_returns_503_with_retry_after() {
+        let upstream = spawn_upstream().await;
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), upstream.port(), false, None, false);
+        route.set_max_concurrent_requests(Some(1));
+        let mut config = Config::default();
+        config.routes.insert("limited.example.com".to_string(), route);
+
+        // The first response holds the only slot until its body is consumed
+        let req = Request::builder().uri("/a").header("Host", "limited.example.com").body(Body::empty()).unwrap();
+        let first = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+
+        let req = Request::builder().uri("/b").header("Host", "limited.example.com").body(Body::empty()).unwrap();
+        let rejected = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rejected.headers().get(header::RETRY_AFTER).unwrap(), "1");
+
+        assert_eq!(body_string(first).await, "upstream:/a");
+        let req = Request::builder().uri("/c").header("Host", "limited.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/c");
+    }
+
+    // Upstream that answers with the Host header it received
+    async fn spawn_host_echo_upstream() -> SocketAddr {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let host = req.headers().get(header::HOST).and_then(|h| h.to_str().ok()).unwrap_or_default().to_string();
+                Ok::<_, Infallible>(Response::new(Body::from(host)))
+            }))
+        });
+        let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn subroute(path: &str, port: u16, overrides: SubrouteOverrides) -> ProxyPathRoute {
+        ProxyPathRoute {
+            path: path.to_string(),
+            port,
+            allow_websocket: overrides.allow_websocket,
+            redirect_to_https: overrides.redirect_to_https,
+            preserve_host: overrides.preserve_host,
+        }
+    }
+
+    fn websocket_request(path: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .header("Host", "app.example.com")
+            .header(header::UPGRADE, "websocket")
+            .header(header::CONNECTION, "Upgrade")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_subroutes_match_whole_path_segments() {
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 8080, false, None, false);
+        route.subroutes.push(subroute("/ws", 9000, SubrouteOverrides::default()));
+        for path in ["/ws", "/ws/", "/ws/chat"] {
+            assert_eq!(select_route(&route, path).upstream_port, 9000, "{}", path);
+        }
+        for path in ["/wsx", "/w", "/", "/api/ws"] {
+            assert_eq!(select_route(&route, path).upstream_port, 8080, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subroute_redirect_override_beats_parent() {
+        let upstream = spawn_upstream().await;
+        let sub_upstream = spawn_upstream().await;
+        let mut config = Config::default();
+        config.set_email("admin@example.com".to_string());
+        let mut route = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), upstream.port(), true, None, true);
+        route.subroutes.push(subroute("/plain", sub_upstream.port(), SubrouteOverrides { redirect_to_https: Some(false), ..Default::default() }));
+        config.routes.insert("app.example.com".to_string(), route);
+
+        let req = Request::builder().uri("/plain/x").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/x");
+
+        let req = Request::builder().uri("/other").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+
+        // The other direction: only the subroute redirects
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.redirect_to_https = false;
+        route.subroutes = vec![subroute("/secure", sub_upstream.port(), SubrouteOverrides { redirect_to_https: Some(true), ..Default::default() })];
+
+        let req = Request::builder().uri("/secure/x?q=1").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(resp.headers().get(header::LOCATION).unwrap(), "https://app.example.com/secure/x?q=1");
+
+        let req = Request::builder().uri("/other").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/other");
+    }
+
+    #[tokio::test]
+    async fn test_subroute_websocket_override_beats_parent() {
+        let upstream = spawn_upstream().await;
+        let sub_upstream = spawn_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.allow_websocket = false;
+        route.subroutes.push(subroute("/ws", sub_upstream.port(), SubrouteOverrides { allow_websocket: Some(true), ..Default::default() }));
+
+        let resp = handle_request_with_config(&config, "http", client(), websocket_request("/other")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        // The plain HTTP upstream answers the handshake with 200, which is passed through
+        let resp = handle_request_with_config(&config, "http", client(), websocket_request("/ws/chat")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // The other direction: websockets allowed on the domain but not on the subroute
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.allow_websocket = true;
+        route.subroutes = vec![subroute("/nows", sub_upstream.port(), SubrouteOverrides { allow_websocket: Some(false), ..Default::default() })];
+
+        let resp = handle_request_with_config(&config, "http", client(), websocket_request("/nows")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = handle_request_with_config(&config, "http", client(), websocket_request("/other")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_subroute_preserve_host_override_beats_parent() {
+        let upstream = spawn_host_echo_upstream().await;
+        let sub_upstream = spawn_host_echo_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.subroutes.push(subroute("/raw", sub_upstream.port(), SubrouteOverrides { preserve_host: Some(false), ..Default::default() }));
+
+        let req = Request::builder().uri("/raw").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, format!("127.0.0.1:{}", sub_upstream.port()));
+        let req = Request::builder().uri("/").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "app.example.com");
+
+        // The other direction: the parent rewrites Host but the subroute preserves it
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.preserve_host = Some(false);
+        route.subroutes = vec![subroute("/keep", sub_upstream.port(), SubrouteOverrides { preserve_host: Some(true), ..Default::default() })];
+
+        let req = Request::builder().uri("/keep").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "app.example.com");
+        let req = Request::builder().uri("/").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, format!("127.0.0.1:{}", upstream.port()));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_host_header_is_sent_to_backend() {
+        let upstream = spawn_host_echo_upstream().await;
+        let sub_upstream = spawn_host_echo_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.upstream_host_header = Some("internal.company.lan".to_string());
+        route.preserve_host = Some(false);
+        route.subroutes.push(subroute("/sub", sub_upstream.port(), SubrouteOverrides { preserve_host: Some(true), ..Default::default() }));
+
+        // The connection still goes to host:port, for the route and its subroutes, whatever preserve_host says
+        for path in ["/", "/sub/x"] {
+            let req = Request::builder().uri(path).header("Host", "app.example.com").body(Body::empty()).unwrap();
+            let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+            assert_eq!(body_string(resp).await, "internal.company.lan", "{}", path);
+        }
+        // Websocket handshakes carry it too; the upstream answers the handshake with 200 and the Host it saw
+        let resp = handle_request_with_config(&config, "http", client(), websocket_request("/chat")).await.unwrap();
+        assert_eq!(body_string(resp).await, "internal.company.lan");
+    }
+
+    #[tokio::test]
+    async fn test_mixed_case_host_reaches_route() {
+        let upstream = spawn_upstream().await;
+        let config = config_with_upstream(upstream);
+
+        let req = Request::builder().uri("/x").header("Host", "App.Example.COM").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/x");
+    }
+
+    #[tokio::test]
+    async fn test_trailing_dot_host_reaches_route() {
+        let upstream = spawn_upstream().await;
+        let config = config_with_upstream(upstream);
+
+        let req = Request::builder().uri("/x").header("Host", "app.example.com.").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/x");
+    }
+
+    #[tokio::test]
+    async fn test_bracketed_ipv6_host_reaches_route() {
+        let upstream = spawn_upstream().await;
+        let mut config = Config::default();
+        config
+            .add_route("[::1]".to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), upstream.port(), false, None, false))
+            .await
+            .unwrap();
+
+        let req = Request::builder().uri("/v6").header("Host", "[::1]:8080").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body_string(resp).await, "upstream:/v6");
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint_answered_on_any_host() {
+        let config = Config::default();
+        let req = Request::builder().uri("/__minipx/health").header("Host", "unknown.example.com").body(Body::empty()).unwrap();
+
+        let resp = handle_request_with_config(&config, "https", client(), req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = body_string(resp).await;
+        assert!(body.contains("\"status\":\"ok\""));
+        assert!(body.contains("\"routes\":0"));
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint_disabled_does_not_shadow_backend() {
+        let upstream = spawn_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        config.set_health_endpoint(HealthEndpointConfig::new(false, "/__minipx/health".to_string(), None, false));
+
+        let req = Request::builder().uri("/__minipx/health").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body_string(resp).await, "upstream:/__minipx/health");
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint_on_dedicated_port_not_served_on_frontend() {
+        let upstream = spawn_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        config.set_health_endpoint(HealthEndpointConfig::new(true, "/__minipx/health".to_string(), Some(9901), false));
+
+        let req = Request::builder().uri("/__minipx/health").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/__minipx/health");
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint_custom_path() {
+        let upstream = spawn_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        config.set_health_endpoint(HealthEndpointConfig::new(true, "/healthz".to_string(), None, false));
+
+        let req = Request::builder().uri("/healthz").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert!(body_string(resp).await.contains("\"routes\":1"));
+
+        // The default path is no longer intercepted
+        let req = Request::builder().uri("/__minipx/health").header("Host", "app.example.com").body(Body::empty()).unwrap();
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:/__minipx/health");
+    }
+
+    // Spawn an upstream that redirects to the request's `x-location` header and sets a cookie for its own address
+    async fn spawn_redirecting_upstream() -> SocketAddr {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let location = req.headers().get("x-location").cloned().unwrap();
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header(header::LOCATION, location)
+                        .header(header::SET_COOKIE, "sid=1; Domain=127.0.0.1; Path=/")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+            }))
+        });
+        let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn redirect_through(config: &Config, path: &str, location: &str) -> Response<Body> {
+        let req = Request::builder().uri(path).header("Host", "app.example.com").header("x-location", location).body(Body::empty()).unwrap();
+        handle_request_with_config(config, "https", client(), req).await.unwrap()
+    }
+
+    fn location(resp: &Response<Body>) -> &str {
+        resp.headers()[header::LOCATION].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_redirects_pass_through_unless_rewriting_is_enabled() {
+        let upstream = spawn_redirecting_upstream().await;
+        let config = config_with_upstream(upstream);
+        let backend = format!("http://127.0.0.1:{}/login", upstream.port());
+
+        let resp = redirect_through(&config, "/", &backend).await;
+        assert_eq!(location(&resp), backend);
+        assert_eq!(resp.headers()[header::SET_COOKIE], "sid=1; Domain=127.0.0.1; Path=/");
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_redirects_to_the_backend() {
+        let upstream = spawn_redirecting_upstream().await;
+        let mut config = config_with_upstream(upstream);
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.set_rewrite_redirects(true);
+        route.set_rewrite_cookie_domain(true);
+
+        let resp = redirect_through(&config, "/", &format!("http://127.0.0.1:{}/login?next=%2Fhome", upstream.port())).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(location(&resp), "https://app.example.com/login?next=%2Fhome");
+        assert_eq!(resp.headers()[header::SET_COOKIE], "sid=1; Domain=app.example.com; Path=/");
+
+        // Relative and third-party locations are left untouched
+        let resp = redirect_through(&config, "/", "/login").await;
+        assert_eq!(location(&resp), "/login");
+        let resp = redirect_through(&config, "/", "https://accounts.example.org/auth?client=app").await;
+        assert_eq!(location(&resp), "https://accounts.example.org/auth?client=app");
+        // Same host, different port: not this backend
+        let resp = redirect_through(&config, "/", "http://127.0.0.1:1/login").await;
+        assert_eq!(location(&resp), "http://127.0.0.1:1/login");
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_redirects_restores_the_subroute_prefix() {
+        let parent = spawn_upstream().await;
+        let upstream = spawn_redirecting_upstream().await;
+        let mut config = config_with_upstream(parent);
+        let route = config.routes.get_mut("app.example.com").unwrap();
+        route.set_rewrite_redirects(true);
+        route.subroutes.push(ProxyPathRoute {
+            path: "/app".to_string(),
+            port: upstream.port(),
+            allow_websocket: None,
+            redirect_to_https: None,
+            preserve_host: None,
+        });
+
+        let resp = redirect_through(&config, "/app/private", &format!("http://127.0.0.1:{}/login", upstream.port())).await;
+        assert_eq!(location(&resp), "https://app.example.com/app/login");
+        // Cookie rewriting is a separate switch
+        assert_eq!(resp.headers()[header::SET_COOKIE], "sid=1; Domain=127.0.0.1; Path=/");
+    }
 }
@@ -0,0 +1,178 @@
+use crate::config::{Config, RequestLogMode};
+use crate::proxy::routing_trace::RoutingTrace;
+use hyper::StatusCode;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::Duration;
+
+// Upper bounds of the latency histogram buckets, in milliseconds; slower requests land in one more bucket
+const LATENCY_BUCKETS_MS: [u64; 13] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+// Requests since the last summary line, keyed by route. Entries are reset rather than removed while a route has
+// traffic, so recording a request never allocates.
+static SUMMARIES: OnceLock<Mutex<HashMap<String, RouteCounters>>> = OnceLock::new();
+static SUMMARY_TASK: Once = Once::new();
+
+fn summaries() -> &'static Mutex<HashMap<String, RouteCounters>> {
+    SUMMARIES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[derive(Debug, Default)]
+struct RouteCounters {
+    requests: u64,
+    // 1xx..5xx
+    status_classes: [u64; 5],
+    latency_buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    max_latency: Duration,
+}
+
+impl RouteCounters {
+    // Upper bound of the bucket holding the `quantile` request; None beyond the last bound
+    fn percentile(&self, quantile: f64) -> Option<u64> {
+        let rank = ((self.requests as f64) * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.latency_buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS_MS.get(i).copied();
+            }
+        }
+        None
+    }
+}
+
+/// One route's requests over a summary interval
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestSummary {
+    pub domain: String,
+    pub requests: u64,
+    /// Responses by status class, 1xx to 5xx
+    pub status_classes: [u64; 5],
+    /// Latency percentiles as histogram bucket bounds in milliseconds; None when above the last bound (10 s)
+    pub p50_ms: Option<u64>,
+    pub p90_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+    pub max: Duration,
+}
+
+impl fmt::Display for RequestSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bound = |ms: Option<u64>| ms.map(|ms| format!("<={}ms", ms)).unwrap_or_else(|| ">10000ms".to_string());
+        let [c1, c2, c3, c4, c5] = self.status_classes;
+        write!(
+            f,
+            "Requests for {}: {} (1xx={} 2xx={} 3xx={} 4xx={} 5xx={}) latency p50{} p90{} p99{} max={}ms",
+            self.domain,
+            self.requests,
+            c1,
+            c2,
+            c3,
+            c4,
+            c5,
+            bound(self.p50_ms),
+            bound(self.p90_ms),
+            bound(self.p99_ms),
+            self.max.as_millis()
+        )
+    }
+}
+
+/// Account for a finished request according to `request_log_mode`. Latency is the time until the response headers
+/// were ready; streaming the body is not included. Per-request mode already has its "Received request" info line, so
+/// the response and how it was routed are logged at debug; 5xx responses are logged individually in summary and off
+/// modes, where no per-request line names them.
+pub fn finished(config: &Config, trace: &RoutingTrace, client_ip: IpAddr, path: &str, status: StatusCode, latency: Duration) {
+    let route_key = trace.route.unwrap_or("-");
+    let mode = config.get_request_log_mode();
+    if mode == RequestLogMode::Summary {
+        record(route_key, status, latency);
+        SUMMARY_TASK.call_once(spawn_summary_task);
+    }
+    if mode == RequestLogMode::PerRequest {
+        debug!("{} for {}{} from {} after {} ms: {}", status, route_key, path, client_ip, latency.as_millis(), trace);
+    } else if status.is_server_error() {
+        warn!("{} for {}{} from {} after {} ms: {}", status, route_key, path, client_ip, latency.as_millis(), trace);
+    }
+}
+
+/// Add a request to its route's counters for the next summary line
+pub fn record(route_key: &str, status: StatusCode, latency: Duration) {
+    let bucket = LATENCY_BUCKETS_MS.iter().position(|&bound| latency.as_millis() <= bound as u128).unwrap_or(LATENCY_BUCKETS_MS.len());
+    let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+    let mut summaries = summaries().lock().unwrap();
+    let counters = match summaries.get_mut(route_key) {
+        Some(counters) => counters,
+        None => summaries.entry(route_key.to_string()).or_default(),
+    };
+    counters.requests += 1;
+    counters.status_classes[class] += 1;
+    counters.latency_buckets[bucket] += 1;
+    counters.max_latency = counters.max_latency.max(latency);
+}
+
+/// Take every route's summary since the last call and log it, one info line per route with requests.
+/// Routes without requests in the interval are forgotten.
+pub fn flush() -> Vec<RequestSummary> {
+    let mut summaries = summaries().lock().unwrap();
+    summaries.retain(|_, counters| counters.requests > 0);
+    let mut flushed: Vec<RequestSummary> = summaries
+        .iter_mut()
+        .map(|(domain, counters)| {
+            let summary = RequestSummary {
+                domain: domain.clone(),
+                requests: counters.requests,
+                status_classes: counters.status_classes,
+                p50_ms: counters.percentile(0.5),
+                p90_ms: counters.percentile(0.9),
+                p99_ms: counters.percentile(0.99),
+                max: counters.max_latency,
+            };
+            *counters = RouteCounters::default();
+            summary
+        })
+        .collect();
+    drop(summaries);
+    flushed.sort_by(|a, b| a.domain.cmp(&b.domain));
+    for summary in &flushed {
+        info!("{}", summary);
+    }
+    flushed
+}
+
+// Log the summaries every request_log_interval_secs (re-read each time so reloads apply)
+fn spawn_summary_task() {
+    tokio::spawn(async {
+        loop {
+            let interval = Config::get().await.get_request_log_interval();
+            tokio::time::sleep(interval).await;
+            flush();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_counts_classes_and_percentiles() {
+        for ms in 1..=100 {
+            let status = if ms % 10 == 0 { StatusCode::BAD_GATEWAY } else { StatusCode::OK };
+            record("percentiles.request-log.test", status, Duration::from_millis(ms));
+        }
+        record("percentiles.request-log.test", StatusCode::NOT_FOUND, Duration::from_secs(12));
+
+        let summary = flush().into_iter().find(|s| s.domain == "percentiles.request-log.test").unwrap();
+        assert_eq!(summary.requests, 101);
+        assert_eq!(summary.status_classes, [0, 90, 0, 1, 10]);
+        assert_eq!((summary.p50_ms, summary.p90_ms, summary.p99_ms), (Some(100), Some(100), Some(100)));
+        assert_eq!(summary.max, Duration::from_secs(12));
+        assert!(summary.to_string().ends_with("p50<=100ms p90<=100ms p99<=100ms max=12000ms"), "{}", summary);
+
+        // The counters start over, and a route without requests is dropped from the next flush
+        assert!(!flush().iter().any(|s| s.domain == "percentiles.request-log.test"));
+    }
+}
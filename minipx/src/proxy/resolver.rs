@@ -0,0 +1,192 @@
+//! Backend name resolution shared by the HTTP client, the websocket client and the TCP/UDP forwarders.
+//!
+//! `resolver.static_hosts` answer first, then either the system resolver or, in `custom` mode, the configured DNS
+//! servers (`dns-resolver` feature). The resolver follows the running config, so reloads apply to the next lookup.
+
+use crate::config::manager::config_lock;
+use crate::config::{ResolverConfig, ResolverMode};
+use hyper::client::HttpConnector;
+use hyper::client::connect::dns::Name;
+use hyper::service::Service;
+use log::warn;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use tokio::net::TcpStream;
+
+// Built from the running config's `resolver` section; replaced when a reload changes that section
+static CURRENT: OnceLock<Mutex<Option<Arc<Resolver>>>> = OnceLock::new();
+
+/// Resolves backend host names according to one `resolver` config section
+pub struct Resolver {
+    config: ResolverConfig,
+    #[cfg(feature = "dns-resolver")]
+    dns: Option<hickory_resolver::TokioAsyncResolver>,
+}
+
+impl Resolver {
+    pub fn new(config: &ResolverConfig) -> Self {
+        let custom = config.get_mode() == ResolverMode::Custom;
+        if custom && config.get_servers().is_empty() {
+            warn!("resolver mode is custom but no servers are configured; only static_hosts will resolve");
+        }
+        if custom && cfg!(not(feature = "dns-resolver")) {
+            warn!("resolver mode custom needs a build with the dns-resolver feature; using the system resolver instead");
+        }
+        Self {
+            config: config.clone(),
+            #[cfg(feature = "dns-resolver")]
+            dns: custom.then(|| custom_resolver(config.get_servers())),
+        }
+    }
+
+    /// Addresses of `host` (a name or an IP literal) with `port`
+    pub async fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        if let Some(ip) = self.config.static_host(host) {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        #[cfg(feature = "dns-resolver")]
+        if let Some(dns) = &self.dns {
+            let ips = dns.lookup_ip(host).await.map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("failed to resolve {}: {}", host, e)))?;
+            return Ok(ips.iter().map(|ip| SocketAddr::new(ip, port)).collect());
+        }
+        Ok(tokio::net::lookup_host((host, port)).await?.collect())
+    }
+}
+
+// Only the configured servers are asked (UDP, then TCP for truncated answers); /etc/hosts is not consulted
+#[cfg(feature = "dns-resolver")]
+fn custom_resolver(servers: &[SocketAddr]) -> hickory_resolver::TokioAsyncResolver {
+    use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig as DnsConfig, ResolverOpts};
+    let name_servers: Vec<NameServerConfig> =
+        servers.iter().flat_map(|&server| [NameServerConfig::new(server, Protocol::Udp), NameServerConfig::new(server, Protocol::Tcp)]).collect();
+    let mut options = ResolverOpts::default();
+    options.use_hosts_file = false;
+    hickory_resolver::TokioAsyncResolver::tokio(DnsConfig::from_parts(None, vec![], name_servers), options)
+}
+
+/// The resolver of the running config
+pub async fn current() -> Arc<Resolver> {
+    let config = config_lock().read().await;
+    let mut current = CURRENT.get_or_init(|| Mutex::new(None)).lock().unwrap();
+    match current.as_ref() {
+        Some(resolver) if resolver.config == *config.get_resolver() => resolver.clone(),
+        _ => current.insert(Arc::new(Resolver::new(config.get_resolver()))).clone(),
+    }
+}
+
+/// Addresses of `host` with `port`, using the running config's resolver
+pub async fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    current().await.lookup(host, port).await
+}
+
+/// Connect to the first address of `host` that accepts
+pub async fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let addrs = resolve(host, port).await?;
+    TcpStream::connect(&addrs[..]).await
+}
+
+/// `HttpConnector` whose name lookups go through the running config's resolver
+pub fn http_connector() -> HttpConnector<UpstreamResolver> {
+    HttpConnector::new_with_resolver(UpstreamResolver)
+}
+
+/// hyper resolver answering through `resolve`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpstreamResolver;
+
+impl Service<Name> for UpstreamResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        // The connector fills in the port
+        Box::pin(async move { Ok(resolve(name.as_str(), 0).await?.into_iter()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn static_hosts(hosts: &[(&str, &str)]) -> BTreeMap<String, IpAddr> {
+        hosts.iter().map(|(name, ip)| (name.to_string(), ip.parse().unwrap())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_static_hosts_answer_before_the_system_resolver() {
+        let config =
+            ResolverConfig::new(ResolverMode::System, Vec::new(), static_hosts(&[("Backend.Internal", "10.1.2.3"), ("localhost", "10.0.0.9")]));
+        let resolver = Resolver::new(&config);
+        assert_eq!(resolver.lookup("backend.internal", 8080).await.unwrap(), [SocketAddr::from(([10, 1, 2, 3], 8080))]);
+        // Even over /etc/hosts
+        assert_eq!(resolver.lookup("LOCALHOST.", 80).await.unwrap(), [SocketAddr::from(([10, 0, 0, 9], 80))]);
+        assert_eq!(resolver.lookup("[::1]", 80).await.unwrap(), [SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 80))]);
+
+        let system = Resolver::new(&ResolverConfig::default());
+        assert!(system.lookup("localhost", 80).await.unwrap().iter().all(|addr| addr.ip().is_loopback()));
+    }
+
+    #[cfg(feature = "dns-resolver")]
+    mod custom {
+        use super::*;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::net::UdpSocket;
+
+        // DNS server answering every A query with `answer` (other types get an empty answer); counts the queries
+        async fn spawn_mock_dns(answer: [u8; 4]) -> (SocketAddr, Arc<AtomicUsize>) {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = socket.local_addr().unwrap();
+            let queries = Arc::new(AtomicUsize::new(0));
+            let counter = queries.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 512];
+                loop {
+                    let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    // Question: the name's labels up to the root, then type and class
+                    let name_end = 12 + buf[12..n].iter().position(|&b| b == 0).unwrap() + 1;
+                    let question = &buf[12..name_end + 4];
+                    let is_a = question[question.len() - 4..question.len() - 2] == [0, 1];
+                    let mut response = vec![buf[0], buf[1], 0x81, 0x80, 0, 1, 0, u8::from(is_a), 0, 0, 0, 0];
+                    response.extend_from_slice(question);
+                    if is_a {
+                        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                        response.extend_from_slice(&answer);
+                    }
+                    socket.send_to(&response, peer).await.unwrap();
+                }
+            });
+            (addr, queries)
+        }
+
+        #[tokio::test]
+        async fn test_custom_servers_answer_and_static_hosts_win() {
+            let (server, queries) = spawn_mock_dns([10, 9, 8, 7]).await;
+            let config = ResolverConfig::new(ResolverMode::Custom, vec![server], static_hosts(&[("pinned.internal", "10.1.1.1")]));
+            let resolver = Resolver::new(&config);
+
+            assert_eq!(resolver.lookup("backend.service.consul", 3000).await.unwrap(), [SocketAddr::from(([10, 9, 8, 7], 3000))]);
+            let asked = queries.load(Ordering::SeqCst);
+            assert!(asked >= 1);
+
+            assert_eq!(resolver.lookup("pinned.internal", 3000).await.unwrap(), [SocketAddr::from(([10, 1, 1, 1], 3000))]);
+            assert_eq!(queries.load(Ordering::SeqCst), asked);
+            assert_eq!(resolver.lookup("db.service.consul", 80).await.unwrap(), [SocketAddr::from(([10, 9, 8, 7], 80))]);
+            assert!(queries.load(Ordering::SeqCst) > asked);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Opt-in rewriting of responses that leak the backend's internal address: `Location` headers of redirects
+//! (`rewrite_redirects`) and `Domain` attributes of cookies (`rewrite_cookie_domain`).
+
+use crate::config::ProxyRoute;
+use crate::utils::host::normalize_host;
+use hyper::header::{HeaderValue, LOCATION, SET_COOKIE};
+use hyper::{Body, Response, Uri};
+use log::debug;
+
+/// The backend a response came from and the public address it is rewritten to
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseRewrite<'a> {
+    pub backend_host: &'a str,
+    pub backend_port: u16,
+    pub frontend_scheme: &'a str,
+    /// Public host the client asked for
+    pub domain: &'a str,
+    /// Path prefix stripped before proxying (a subroute's path), restored on rewritten redirects
+    pub mount: &'a str,
+}
+
+impl ResponseRewrite<'_> {
+    /// Apply the rewrites enabled on `route` to a backend response
+    pub fn apply(&self, route: &ProxyRoute, response: &mut Response<Body>) {
+        if route.get_rewrite_redirects() && response.status().is_redirection() {
+            let rewritten = response.headers().get(LOCATION).and_then(|v| v.to_str().ok()).and_then(|location| self.location(location));
+            if let Some(location) = rewritten.and_then(|l| HeaderValue::from_str(&l).ok()) {
+                debug!("Rewrote redirect to the backend into {:?}", location);
+                response.headers_mut().insert(LOCATION, location);
+            }
+        }
+        if route.get_rewrite_cookie_domain() {
+            let cookies: Vec<HeaderValue> = response
+                .headers()
+                .get_all(SET_COOKIE)
+                .iter()
+                .map(|value| match value.to_str().ok().and_then(|cookie| self.cookie(cookie)) {
+                    Some(cookie) => HeaderValue::from_str(&cookie).unwrap_or_else(|_| value.clone()),
+                    None => value.clone(),
+                })
+                .collect();
+            if !cookies.is_empty() {
+                response.headers_mut().remove(SET_COOKIE);
+                for cookie in cookies {
+                    response.headers_mut().append(SET_COOKIE, cookie);
+                }
+            }
+        }
+    }
+
+    /// Point a `Location` naming the backend (host and port) at the public address, keeping path, query and fragment.
+    /// None for relative locations and other hosts, which are left untouched.
+    pub fn location(&self, location: &str) -> Option<String> {
+        let (target, fragment) = match location.split_once('#') {
+            Some((target, fragment)) => (target, Some(fragment)),
+            None => (location, None),
+        };
+        let uri: Uri = target.parse().ok()?;
+        let authority = uri.authority()?;
+        let default_port = match uri.scheme_str()? {
+            "http" => 80,
+            "https" => 443,
+            _ => return None,
+        };
+        if normalize_host(authority.host()) != normalize_host(self.backend_host) || authority.port_u16().unwrap_or(default_port) != self.backend_port
+        {
+            return None;
+        }
+        let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        let fragment = fragment.map(|f| format!("#{}", f)).unwrap_or_default();
+        Some(format!("{}://{}{}{}{}", self.frontend_scheme, self.domain, self.mount, path_and_query, fragment))
+    }
+
+    /// Replace a `Domain` attribute naming the backend host with the public domain; None if the cookie is left untouched
+    pub fn cookie(&self, set_cookie: &str) -> Option<String> {
+        let mut changed = false;
+        let attributes: Vec<String> = set_cookie
+            .split(';')
+            .enumerate()
+            .map(|(i, attribute)| {
+                // The first pair is the cookie itself
+                let names_backend = i > 0
+                    && attribute.split_once('=').is_some_and(|(name, value)| {
+                        name.trim().eq_ignore_ascii_case("domain")
+                            && normalize_host(value.trim().trim_start_matches('.')) == normalize_host(self.backend_host)
+                    });
+                if names_backend {
+                    changed = true;
+                    format!(" Domain={}", self.domain)
+                } else {
+                    attribute.to_string()
+                }
+            })
+            .collect();
+        changed.then(|| attributes.join(";"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(mount: &'static str) -> ResponseRewrite<'static> {
+        ResponseRewrite { backend_host: "localhost", backend_port: 8080, frontend_scheme: "https", domain: "app.example.com", mount }
+    }
+
+    #[test]
+    fn test_location_naming_the_backend_is_rewritten() {
+        let root = rewrite("");
+        assert_eq!(root.location("http://localhost:8080/login?next=%2F").as_deref(), Some("https://app.example.com/login?next=%2F"));
+        assert_eq!(root.location("http://LOCALHOST:8080").as_deref(), Some("https://app.example.com/"));
+        assert_eq!(root.location("http://localhost:8080/a#top").as_deref(), Some("https://app.example.com/a#top"));
+        // Requests through a subroute had its prefix stripped; redirects get it back
+        assert_eq!(rewrite("/app").location("http://localhost:8080/login").as_deref(), Some("https://app.example.com/app/login"));
+    }
+
+    #[test]
+    fn test_other_locations_are_left_alone() {
+        let rewrite = rewrite("");
+        assert_eq!(rewrite.location("/login"), None);
+        assert_eq!(rewrite.location("login"), None);
+        assert_eq!(rewrite.location("http://localhost:9090/login"), None);
+        assert_eq!(rewrite.location("http://localhost/login"), None);
+        assert_eq!(rewrite.location("https://accounts.example.org/auth"), None);
+        assert_eq!(rewrite.location("ftp://localhost:8080/file"), None);
+    }
+
+    #[test]
+    fn test_cookie_domain_naming_the_backend_is_rewritten() {
+        let rewrite = rewrite("");
+        assert_eq!(
+            rewrite.cookie("sid=abc; Path=/; domain=.localhost; HttpOnly").as_deref(),
+            Some("sid=abc; Path=/; Domain=app.example.com; HttpOnly")
+        );
+        assert_eq!(rewrite.cookie("sid=abc; Path=/"), None);
+        assert_eq!(rewrite.cookie("sid=abc; Domain=example.org"), None);
+        // A cookie whose value looks like an attribute is not touched
+        assert_eq!(rewrite.cookie("domain=localhost; Path=/"), None);
+    }
+}
@@ -0,0 +1,207 @@
+//! What the running proxy actually routes with: the in-memory routing table (after key normalization,
+//! migrations and hot reloads) with its derived per-route state, and a dry run of the handler's
+//! host/path selection. Both are built from the same lookup functions the request handler uses.
+
+use crate::config::{Config, ProxyRoute};
+use crate::proxy::forwarder::{BindingState, forwarder_port, forwarder_status, is_forwarder_listening};
+use crate::proxy::request_handler::{select_route, subroute_upstream_path, upstream_target};
+use crate::proxy::unix_socket;
+use crate::utils::host::{normalize_host, strip_port, wildcard_suffix};
+use serde::{Deserialize, Serialize};
+
+/// The routing table of one config snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteTable {
+    /// Config generation: incremented by every load or reload that was applied
+    pub generation: u64,
+    /// Wildcard keys in the order hosts are matched against them (most specific first)
+    pub wildcard_order: Vec<String>,
+    /// Routes sorted by key
+    pub routes: Vec<RouteTableEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteTableEntry {
+    /// Normalized route key
+    pub key: String,
+    pub route: ProxyRoute,
+    /// HTTPS can be served for the key (`Config::can_serve_tls_for_host`)
+    pub tls_servable: bool,
+    pub forwarder: Option<ForwarderState>,
+    /// The route's generation and what is in flight against it (None before the config was applied)
+    pub generation: Option<u64>,
+    pub in_flight_requests: u64,
+    pub in_flight_websockets: u64,
+    /// Requests or websockets are still pinned to a previous generation of the route
+    pub draining: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForwarderState {
+    pub listen_port: u16,
+    /// A TCP forwarder is bound on the port (it may belong to another route with the same port)
+    pub listening: bool,
+    /// Binding state of the TCP forwarder, None when none was started for the port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binding: Option<BindingState>,
+}
+
+/// How a host matched its route
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteMatch {
+    Exact,
+    Wildcard,
+    /// The bare domain of a `match_apex` wildcard route
+    Apex,
+}
+
+/// Where a request for a host and path would be proxied to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolution {
+    /// Normalized host that was looked up
+    pub host: String,
+    pub route_key: String,
+    pub matched: RouteMatch,
+    /// Path of the subroute serving the request, if any
+    pub subroute: Option<String>,
+    /// Full upstream URL of a plain HTTP request (websocket handshakes keep the original path)
+    pub upstream: String,
+    /// Plain HTTP requests are answered with a redirect to HTTPS instead
+    pub redirects_to_https: bool,
+    pub allow_websocket: bool,
+    pub preserve_host: Option<bool>,
+}
+
+/// Build the routing table of `config`, including the live state of its forwarders and route generations
+pub fn route_table(config: &Config) -> RouteTable {
+    let mut routes: Vec<RouteTableEntry> = config
+        .get_routes()
+        .iter()
+        .map(|(key, route)| {
+            let generation = config.route_generation(key);
+            RouteTableEntry {
+                key: key.clone(),
+                route: route.clone(),
+                tls_servable: config.can_serve_tls_for_host(key),
+                forwarder: forwarder_port(route).map(|listen_port| ForwarderState {
+                    listen_port,
+                    listening: is_forwarder_listening(listen_port),
+                    binding: forwarder_status("tcp", listen_port).map(|status| status.state),
+                }),
+                generation: generation.map(|g| g.generation()),
+                in_flight_requests: generation.map(|g| g.in_flight_requests()).unwrap_or_default(),
+                in_flight_websockets: generation.map(|g| g.in_flight_websockets()).unwrap_or_default(),
+                draining: config.drain_status(key).is_some_and(|status| !status.drained),
+            }
+        })
+        .collect();
+    routes.sort_by(|a, b| a.key.cmp(&b.key));
+    RouteTable { generation: config.generation(), wildcard_order: config.wildcard_order().into_iter().cloned().collect(), routes }
+}
+
+/// Resolve `host` (port optional) and `path` (query optional) the way the request handler would, without sending anything
+pub fn resolve(config: &Config, host: &str, path: &str) -> Option<Resolution> {
+    let host = normalize_host(strip_port(host.trim())).into_owned();
+    let (route_key, route) = config.lookup_route(&host)?;
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path.as_str(), None),
+    };
+    let selection = select_route(route, path);
+    let forwarded_path = match selection.subroute {
+        Some(sub) => subroute_upstream_path(&sub.path, path, query),
+        None => query.map(|query| format!("{}?{}", path, query)).unwrap_or_else(|| path.to_string()),
+    };
+    let matched = if *route_key == host {
+        RouteMatch::Exact
+    } else if wildcard_suffix(route_key) == Some(host.as_str()) {
+        RouteMatch::Apex
+    } else {
+        RouteMatch::Wildcard
+    };
+    Some(Resolution {
+        route_key: route_key.clone(),
+        matched,
+        subroute: selection.subroute.map(|sub| sub.path.clone()),
+        upstream: match selection.unix_socket {
+            Some(socket) => format!("{}{}", unix_socket::describe(socket), forwarded_path),
+            None => format!("{}{}", upstream_target("http", route, selection.upstream_port), forwarded_path),
+        },
+        redirects_to_https: selection.redirect_to_https && config.can_serve_tls_for_host(&host),
+        allow_websocket: selection.allow_websocket,
+        preserve_host: selection.preserve_host,
+        host,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProxyPathRoute;
+
+    fn route(port: u16) -> ProxyRoute {
+        ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), port, false, None, false)
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        let mut app = route(3000);
+        app.subroutes.push(ProxyPathRoute {
+            path: "/api".to_string(),
+            port: 3001,
+            allow_websocket: Some(false),
+            redirect_to_https: None,
+            preserve_host: None,
+        });
+        config.routes.insert("app.example.com".to_string(), app);
+        let mut wildcard = route(4000);
+        wildcard.set_match_apex(true);
+        config.routes.insert("*.example.com".to_string(), wildcard);
+        config.routes.insert("*.dev.example.com".to_string(), route(5000));
+        let mut forwarded = route(6000);
+        forwarded.listen_port = Some(25565);
+        config.routes.insert("mc.example.org".to_string(), forwarded);
+        config
+    }
+
+    #[test]
+    fn test_resolve_exact_route_and_subroute() {
+        let config = config();
+        let resolution = resolve(&config, "App.Example.com:8080", "/api/users?page=2").unwrap();
+        assert_eq!((resolution.host.as_str(), resolution.route_key.as_str()), ("app.example.com", "app.example.com"));
+        assert_eq!(resolution.matched, RouteMatch::Exact);
+        assert_eq!(resolution.subroute.as_deref(), Some("/api"));
+        assert_eq!(resolution.upstream, "http://127.0.0.1:3001/users?page=2");
+        assert!(!resolution.allow_websocket);
+
+        let resolution = resolve(&config, "app.example.com", "index.html").unwrap();
+        assert_eq!((resolution.subroute, resolution.upstream.as_str()), (None, "http://127.0.0.1:3000/index.html"));
+        assert!(resolution.allow_websocket);
+    }
+
+    #[test]
+    fn test_resolve_wildcards_and_apex() {
+        let config = config();
+        let resolution = resolve(&config, "api.dev.example.com", "/").unwrap();
+        assert_eq!((resolution.route_key.as_str(), resolution.matched), ("*.dev.example.com", RouteMatch::Wildcard));
+        let resolution = resolve(&config, "example.com", "/").unwrap();
+        assert_eq!((resolution.route_key.as_str(), resolution.matched), ("*.example.com", RouteMatch::Apex));
+        assert!(resolve(&config, "evilexample.com", "/").is_none());
+    }
+
+    #[test]
+    fn test_route_table_lists_derived_state() {
+        let table = route_table(&config());
+        assert_eq!(table.generation, 0);
+        assert_eq!(table.wildcard_order, ["*.dev.example.com", "*.example.com"]);
+        let keys: Vec<&str> = table.routes.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, ["*.dev.example.com", "*.example.com", "app.example.com", "mc.example.org"]);
+        let forwarded = &table.routes[3];
+        assert_eq!(forwarded.forwarder, Some(ForwarderState { listen_port: 25565, listening: false, binding: None }));
+        assert!(!forwarded.tls_servable && !forwarded.draining);
+        assert_eq!(forwarded.generation, None);
+        assert!(table.routes[2].forwarder.is_none());
+    }
+}
@@ -0,0 +1,99 @@
+//! Why a request got the response it did: the route and subroute that matched, the backend it went to, and what
+//! the handler decided. Logged with every routed request; sent back as `X-Minipx-*` headers only with
+//! `debug_routing_headers` on, since they name internal backends.
+
+use hyper::header::HeaderValue;
+use hyper::{Body, Response};
+use std::fmt;
+
+/// Matched route key
+pub const ROUTE_HEADER: &str = "x-minipx-route";
+/// Matched subroute path, `-` for none
+pub const SUBROUTE_HEADER: &str = "x-minipx-subroute";
+/// Backend the request was sent to, `-` when it was answered by minipx
+pub const UPSTREAM_HEADER: &str = "x-minipx-upstream";
+/// The handler's decision
+pub const DECISION_HEADER: &str = "x-minipx-decision";
+
+/// What the handler did with a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Sent to the backend, whatever it answered
+    Proxied,
+    /// Redirected to HTTPS, or by `unknown_host`
+    Redirected,
+    /// Outside the route's activation window, answered with the maintenance page
+    Maintenance,
+    /// Refused by minipx: malformed, over a limit, or a websocket the route does not allow
+    Blocked,
+    /// No route or subroute serves it
+    NotFound,
+}
+
+impl Decision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Decision::Proxied => "proxied",
+            Decision::Redirected => "redirected",
+            Decision::Maintenance => "maintenance",
+            Decision::Blocked => "blocked",
+            Decision::NotFound => "not_found",
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How one request was routed, filled in by the handler as it goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingTrace<'a> {
+    pub route: Option<&'a str>,
+    pub subroute: Option<&'a str>,
+    pub upstream: Option<String>,
+    pub decision: Decision,
+}
+
+impl<'a> RoutingTrace<'a> {
+    /// A request for `route`, proxied unless the handler decides otherwise
+    pub fn new(route: &'a str) -> Self {
+        Self { route: Some(route), subroute: None, upstream: None, decision: Decision::Proxied }
+    }
+
+    /// A request no route serves
+    pub fn unrouted(decision: Decision) -> Self {
+        Self { route: None, subroute: None, upstream: None, decision }
+    }
+
+    /// Set the `X-Minipx-*` headers on `response`; a value that is not a valid header value is left out
+    pub fn apply(&self, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        let fields = [
+            (ROUTE_HEADER, self.route.unwrap_or("-")),
+            (SUBROUTE_HEADER, self.subroute.unwrap_or("-")),
+            (UPSTREAM_HEADER, self.upstream.as_deref().unwrap_or("-")),
+            (DECISION_HEADER, self.decision.as_str()),
+        ];
+        for (name, value) in fields {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+impl fmt::Display for RoutingTrace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "route={} subroute={} upstream={} decision={}",
+            self.route.unwrap_or("-"),
+            self.subroute.unwrap_or("-"),
+            self.upstream.as_deref().unwrap_or("-"),
+            self.decision
+        )
+    }
+}
@@ -0,0 +1,245 @@
+use crate::config::Config;
+use crate::proxy::request_handler::handle_request_with_config;
+use crate::proxy::unknown_host::ConnectionClosed;
+use hyper::service::Service;
+use hyper::{Body, Request, Response, StatusCode};
+use log::error;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Where a [`MinipxService`] reads its routes from
+#[derive(Clone)]
+pub enum ConfigHandle {
+    /// The process-wide config (`Config::try_load`), snapshotted per request so hot reloads and IPC edits apply
+    Global,
+    /// A fixed config owned by the embedder; reload it by building a new service
+    Fixed(Arc<Config>),
+}
+
+impl ConfigHandle {
+    async fn snapshot(&self) -> Arc<Config> {
+        match self {
+            ConfigHandle::Global => Arc::new(Config::get().await),
+            ConfigHandle::Fixed(config) => config.clone(),
+        }
+    }
+}
+
+type ClientIpFn = dyn Fn(&Request<Body>) -> Option<IpAddr> + Send + Sync;
+
+/// How a [`MinipxService`] learns the address of the peer that sent a request.
+/// The result is the connection peer; `trusted_proxies` handling still applies on top of it.
+#[derive(Clone)]
+pub enum ClientIpSource {
+    /// A fixed address, typically the remote address of the connection the service was created for
+    Peer(IpAddr),
+    /// A `SocketAddr` or `IpAddr` stored in the request extensions by the embedding server or middleware
+    Extension,
+    /// Any other strategy
+    Custom(Arc<ClientIpFn>),
+}
+
+impl ClientIpSource {
+    /// Build a custom strategy from a closure
+    pub fn custom<F>(extract: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        ClientIpSource::Custom(Arc::new(extract))
+    }
+
+    fn extract(&self, req: &Request<Body>) -> Option<IpAddr> {
+        match self {
+            ClientIpSource::Peer(ip) => Some(*ip),
+            ClientIpSource::Extension => {
+                req.extensions().get::<SocketAddr>().map(|addr| addr.ip()).or_else(|| req.extensions().get::<IpAddr>().copied())
+            }
+            ClientIpSource::Custom(extract) => extract(req),
+        }
+    }
+}
+
+impl fmt::Debug for ClientIpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIpSource::Peer(ip) => f.debug_tuple("Peer").field(ip).finish(),
+            ClientIpSource::Extension => f.write_str("Extension"),
+            ClientIpSource::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// The minipx request handler as a hyper (tower) `Service`, for mounting inside another server.
+///
+/// Handler errors are logged and answered with a `500`; the only service error is [`ConnectionClosed`],
+/// for requests the config says to drop without a response (hyper then closes the connection).
+/// A request whose client IP cannot be determined is handled as coming from `0.0.0.0`.
+#[derive(Clone)]
+pub struct MinipxService {
+    config: ConfigHandle,
+    frontend_scheme: &'static str,
+    client_ip: ClientIpSource,
+}
+
+impl MinipxService {
+    /// A service on the global config that takes the client IP from the request extensions
+    pub fn new(frontend_scheme: &'static str) -> Self {
+        Self { config: ConfigHandle::Global, frontend_scheme, client_ip: ClientIpSource::Extension }
+    }
+
+    /// Serve from a fixed config instead of the global one
+    pub fn with_config(mut self, config: impl Into<Arc<Config>>) -> Self {
+        self.config = ConfigHandle::Fixed(config.into());
+        self
+    }
+
+    pub fn with_client_ip(mut self, client_ip: ClientIpSource) -> Self {
+        self.client_ip = client_ip;
+        self
+    }
+
+    /// A copy of this service for one connection, attributing its requests to `peer`
+    pub fn for_peer(&self, peer: IpAddr) -> Self {
+        self.clone().with_client_ip(ClientIpSource::Peer(peer))
+    }
+
+    pub fn get_config(&self) -> &ConfigHandle {
+        &self.config
+    }
+
+    pub fn get_frontend_scheme(&self) -> &'static str {
+        self.frontend_scheme
+    }
+
+    pub fn get_client_ip(&self) -> &ClientIpSource {
+        &self.client_ip
+    }
+
+    /// Handle one request; the same as `call` without needing `&mut self`
+    pub async fn handle(&self, req: Request<Body>) -> Result<Response<Body>, ConnectionClosed> {
+        let client_ip = self.client_ip.extract(&req).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let config = self.config.snapshot().await;
+        match handle_request_with_config(&config, self.frontend_scheme, client_ip, req).await {
+            Ok(resp) => Ok(resp),
+            Err(e) if e.is::<ConnectionClosed>() => Err(ConnectionClosed),
+            Err(e) => {
+                error!("{} handle_request error from {}: {}", self.frontend_scheme.to_uppercase(), client_ip, e);
+                Ok(Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MinipxService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = match self.config {
+            ConfigHandle::Global => "Global",
+            ConfigHandle::Fixed(_) => "Fixed",
+        };
+        f.debug_struct("MinipxService")
+            .field("config", &config)
+            .field("frontend_scheme", &self.frontend_scheme)
+            .field("client_ip", &self.client_ip)
+            .finish()
+    }
+}
+
+impl Service<Request<Body>> for MinipxService {
+    type Response = Response<Body>;
+    type Error = ConnectionClosed;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, ConnectionClosed>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move { service.handle(req).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProxyRoute;
+    use hyper::header;
+    use hyper::server::conn::AddrStream;
+    use hyper::service::{make_service_fn, service_fn};
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+
+    // Upstream that answers with the X-Forwarded-For header it saw
+    async fn spawn_upstream() -> u16 {
+        let make_svc = make_service_fn(|_conn: &AddrStream| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let forwarded = req.headers().get("x-forwarded-for").and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+                Ok::<_, Infallible>(Response::new(Body::from(format!("{} {}", req.uri().path(), forwarded))))
+            }))
+        });
+        let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let port = server.local_addr().port();
+        tokio::spawn(server);
+        port
+    }
+
+    fn config_for(port: u16) -> Config {
+        let mut config = Config::default();
+        config.routes.insert("service.test".to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), port, false, None, false));
+        config
+    }
+
+    fn request(host: &str, path: &str) -> Request<Body> {
+        Request::builder().uri(path).header(header::HOST, host).body(Body::empty()).unwrap()
+    }
+
+    async fn body_text(resp: Response<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(resp.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_call_proxies_with_the_peer_address() {
+        let port = spawn_upstream().await;
+        let mut service = MinipxService::new("http").with_config(config_for(port)).for_peer("10.1.2.3".parse().unwrap());
+        let resp = service.call(request("service.test", "/hello")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body_text(resp).await, "/hello 10.1.2.3");
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_from_extension_and_custom_strategy() {
+        let port = spawn_upstream().await;
+        let service = MinipxService::new("http").with_config(config_for(port));
+
+        let mut req = request("service.test", "/");
+        req.extensions_mut().insert(SocketAddr::from(([10, 0, 0, 7], 4444)));
+        assert_eq!(body_text(service.handle(req).await.unwrap()).await, "/ 10.0.0.7");
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let service = service.with_client_ip(ClientIpSource::custom(move |req| {
+            recorded.lock().unwrap().push(req.uri().path().to_string());
+            req.headers().get("x-test-peer")?.to_str().ok()?.parse().ok()
+        }));
+        let mut req = request("service.test", "/custom");
+        req.headers_mut().insert("x-test-peer", "192.0.2.9".parse().unwrap());
+        assert_eq!(body_text(service.handle(req).await.unwrap()).await, "/custom 192.0.2.9");
+        assert_eq!(*seen.lock().unwrap(), vec!["/custom".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_host_and_handler_errors() {
+        let service = MinipxService::new("http").with_config(config_for(1));
+
+        let resp = service.handle(request("nobody.test", "/")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // No host at all is a handler error, answered with a 500 rather than a service error
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        assert_eq!(service.handle(req).await.unwrap().status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,146 @@
+use hyper::HeaderMap;
+use hyper::header::HeaderValue;
+use rustls_acme::futures_rustls::rustls::{ProtocolVersion, ServerConnection};
+use std::fmt;
+
+/// Headers set upstream for routes with `forward_tls_info`
+pub const TLS_VERSION_HEADER: &str = "x-tls-version";
+pub const TLS_CIPHER_HEADER: &str = "x-tls-cipher";
+pub const TLS_SNI_HEADER: &str = "x-tls-sni";
+
+/// What an HTTPS client negotiated, captured once after the handshake and attached to every request
+/// on that connection as a request extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// e.g. `TLSv1.3`
+    pub version: String,
+    /// IANA-style suite name, e.g. `TLS13_AES_256_GCM_SHA384`
+    pub cipher: String,
+    pub alpn: Option<String>,
+    pub sni: Option<String>,
+}
+
+impl TlsInfo {
+    pub fn from_connection(connection: &ServerConnection) -> Self {
+        let version = match connection.protocol_version() {
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+            Some(other) => format!("{:?}", other),
+            None => "unknown".to_string(),
+        };
+        let cipher = connection
+            .negotiated_cipher_suite()
+            .map(|suite| suite.suite().as_str().map(str::to_string).unwrap_or_else(|| format!("{:?}", suite.suite())))
+            .unwrap_or_else(|| "unknown".to_string());
+        Self {
+            version,
+            cipher,
+            alpn: connection.alpn_protocol().map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+            sni: connection.server_name().map(str::to_string),
+        }
+    }
+
+    /// Replace any client-sent X-TLS-* headers with this connection's values
+    pub fn forward_headers(info: Option<&TlsInfo>, headers: &mut HeaderMap) {
+        for name in [TLS_VERSION_HEADER, TLS_CIPHER_HEADER, TLS_SNI_HEADER] {
+            headers.remove(name);
+        }
+        let Some(info) = info else {
+            return;
+        };
+        let values = [(TLS_VERSION_HEADER, Some(&info.version)), (TLS_CIPHER_HEADER, Some(&info.cipher)), (TLS_SNI_HEADER, info.sni.as_ref())];
+        for (name, value) in values {
+            if let Some(value) = value.and_then(|value| HeaderValue::from_str(value).ok()) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+// The access-log fields: `TLSv1.3 TLS13_AES_256_GCM_SHA384 sni=app.example.com alpn=http/1.1`
+impl fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} sni={} alpn={}", self.version, self.cipher, self.sni.as_deref().unwrap_or("-"), self.alpn.as_deref().unwrap_or("-"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls_acme::futures_rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+    use rustls_acme::futures_rustls::rustls::crypto::aws_lc_rs;
+    use rustls_acme::futures_rustls::rustls::version::{TLS12, TLS13};
+    use rustls_acme::futures_rustls::rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, SupportedProtocolVersion};
+    use std::sync::Arc;
+
+    // In-memory handshake with a rustls client limited to `version`; returns what the server side captured
+    fn negotiate(version: &'static SupportedProtocolVersion) -> TlsInfo {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let generated = rcgen::generate_simple_self_signed(vec!["tls.test".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der()));
+        let mut server_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![generated.cert.der().clone()], key)
+            .unwrap();
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let mut roots = RootCertStore::empty();
+        roots.add(generated.cert.der().clone()).unwrap();
+        let mut client_config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[version])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        let mut client = ClientConnection::new(Arc::new(client_config), ServerName::try_from("tls.test").unwrap()).unwrap();
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut buf = Vec::new();
+            while client.wants_write() {
+                client.write_tls(&mut buf).unwrap();
+            }
+            server.read_tls(&mut buf.as_slice()).unwrap();
+            server.process_new_packets().unwrap();
+            let mut buf = Vec::new();
+            while server.wants_write() {
+                server.write_tls(&mut buf).unwrap();
+            }
+            client.read_tls(&mut buf.as_slice()).unwrap();
+            client.process_new_packets().unwrap();
+        }
+        TlsInfo::from_connection(&server)
+    }
+
+    #[test]
+    fn test_captures_tls12_and_tls13_sessions() {
+        let tls13 = negotiate(&TLS13);
+        assert_eq!(tls13.version, "TLSv1.3");
+        assert!(tls13.cipher.starts_with("TLS13_"), "{}", tls13.cipher);
+        assert_eq!(tls13.sni.as_deref(), Some("tls.test"));
+        assert_eq!(tls13.alpn.as_deref(), Some("http/1.1"));
+
+        let tls12 = negotiate(&TLS12);
+        assert_eq!(tls12.version, "TLSv1.2");
+        assert!(tls12.cipher.starts_with("TLS_ECDHE_"), "{}", tls12.cipher);
+        assert_eq!(tls12.to_string(), format!("TLSv1.2 {} sni=tls.test alpn=http/1.1", tls12.cipher));
+    }
+
+    #[test]
+    fn test_forward_headers_replace_client_values() {
+        let info = TlsInfo { version: "TLSv1.3".to_string(), cipher: "TLS13_AES_128_GCM_SHA256".to_string(), alpn: None, sni: None };
+        let mut headers = HeaderMap::new();
+        headers.insert(TLS_VERSION_HEADER, HeaderValue::from_static("SSLv3"));
+        headers.insert(TLS_SNI_HEADER, HeaderValue::from_static("spoofed.test"));
+
+        TlsInfo::forward_headers(Some(&info), &mut headers);
+        assert_eq!(headers[TLS_VERSION_HEADER], "TLSv1.3");
+        assert_eq!(headers[TLS_CIPHER_HEADER], "TLS13_AES_128_GCM_SHA256");
+        assert!(!headers.contains_key(TLS_SNI_HEADER));
+
+        TlsInfo::forward_headers(None, &mut headers);
+        assert!(headers.is_empty());
+    }
+}
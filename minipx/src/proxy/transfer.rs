@@ -0,0 +1,224 @@
+//! Bytes moved through the proxy, for "which domain (or client) is eating the bandwidth" questions.
+//!
+//! Request and response bodies of proxied requests are counted per route as they stream, since Content-Length is
+//! often missing (chunked responses, streamed uploads). Each exchange's total is also added to its client IP once
+//! both bodies are done. The client table keeps at most `CLIENT_CAPACITY` addresses: when a new one arrives at a full
+//! table it replaces the address with the fewest bytes and inherits that count (the space-saving algorithm), so the
+//! heavy clients stay in the table and a reported total overestimates by at most the entry's `error`.
+//!
+//! Websocket tunnels are counted by `ws_stats`.
+
+use hyper::Body;
+use hyper::body::HttpBody;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio_stream::StreamExt;
+
+/// Client addresses tracked at most
+pub const CLIENT_CAPACITY: usize = 1024;
+
+/// Clients reported by `transfer_stats`
+pub const TOP_CLIENTS: usize = 20;
+
+static ROUTES: OnceLock<Mutex<HashMap<String, Arc<RouteCounters>>>> = OnceLock::new();
+static CLIENTS: OnceLock<Mutex<ClientTable>> = OnceLock::new();
+
+fn routes() -> &'static Mutex<HashMap<String, Arc<RouteCounters>>> {
+    ROUTES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn clients() -> &'static Mutex<ClientTable> {
+    CLIENTS.get_or_init(|| Mutex::new(ClientTable::default()))
+}
+
+/// Transfer totals of one route since startup (or the last reset)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteTransfer {
+    pub requests: u64,
+    /// Request body bytes sent to the backend
+    pub request_bytes: u64,
+    /// Response body bytes sent to clients
+    pub response_bytes: u64,
+}
+
+/// Request and response body bytes of one client IP
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientTransfer {
+    pub ip: IpAddr,
+    pub bytes: u64,
+    /// How much of `bytes` may belong to addresses this one replaced in the table (0: exact)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub error: u64,
+}
+
+/// Transfer totals per route, and the clients that moved the most bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferStats {
+    pub routes: BTreeMap<String, RouteTransfer>,
+    /// Heaviest first, at most `TOP_CLIENTS`
+    pub top_clients: Vec<ClientTransfer>,
+}
+
+#[derive(Default)]
+struct RouteCounters {
+    requests: AtomicU64,
+    request_bytes: AtomicU64,
+    response_bytes: AtomicU64,
+}
+
+impl RouteCounters {
+    fn read(&self, reset: bool) -> RouteTransfer {
+        let read = |counter: &AtomicU64| if reset { counter.swap(0, Ordering::AcqRel) } else { counter.load(Ordering::Acquire) };
+        RouteTransfer { requests: read(&self.requests), request_bytes: read(&self.request_bytes), response_bytes: read(&self.response_bytes) }
+    }
+}
+
+#[derive(Default)]
+struct ClientTable {
+    entries: HashMap<IpAddr, ClientTransfer>,
+}
+
+impl ClientTable {
+    fn record(&mut self, ip: IpAddr, bytes: u64) {
+        if let Some(entry) = self.entries.get_mut(&ip) {
+            entry.bytes += bytes;
+            return;
+        }
+        let mut error = 0;
+        if self.entries.len() >= CLIENT_CAPACITY
+            && let Some(lightest) = self.entries.values().min_by_key(|entry| entry.bytes).map(|entry| entry.ip)
+        {
+            error = self.entries.remove(&lightest).map(|entry| entry.bytes).unwrap_or_default();
+        }
+        self.entries.insert(ip, ClientTransfer { ip, bytes: error + bytes, error });
+    }
+
+    fn top(&self, n: usize) -> Vec<ClientTransfer> {
+        let mut top: Vec<ClientTransfer> = self.entries.values().cloned().collect();
+        top.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.ip.cmp(&b.ip)));
+        top.truncate(n);
+        top
+    }
+}
+
+/// The byte counts of one proxied request; clones share them. The client's total is recorded when the last clone
+/// drops, i.e. once both bodies have been streamed (or abandoned).
+#[derive(Clone)]
+pub struct Exchange {
+    inner: Arc<ExchangeInner>,
+}
+
+struct ExchangeInner {
+    route: Arc<RouteCounters>,
+    client: IpAddr,
+    bytes: AtomicU64,
+}
+
+impl Drop for ExchangeInner {
+    fn drop(&mut self) {
+        let bytes = *self.bytes.get_mut();
+        if bytes > 0 {
+            clients().lock().unwrap().record(self.client, bytes);
+        }
+    }
+}
+
+/// Start counting a request of `client` proxied by the route `route_key`
+pub fn exchange(route_key: &str, client: IpAddr) -> Exchange {
+    let route = {
+        let mut routes = routes().lock().unwrap();
+        match routes.get(route_key) {
+            Some(route) => route.clone(),
+            None => routes.entry(route_key.to_string()).or_default().clone(),
+        }
+    };
+    route.requests.fetch_add(1, Ordering::Relaxed);
+    Exchange { inner: Arc::new(ExchangeInner { route, client, bytes: AtomicU64::new(0) }) }
+}
+
+impl Exchange {
+    pub fn add_request_bytes(&self, bytes: usize) {
+        self.inner.route.request_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.inner.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_response_bytes(&self, bytes: usize) {
+        self.inner.route.response_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.inner.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// `body` counting its bytes as request bytes while it streams to the backend. Empty bodies are returned as they
+    /// are, so requests without one stay replayable.
+    pub fn count_request(&self, body: Body) -> Body {
+        if body.is_end_stream() {
+            return body;
+        }
+        let exchange = self.clone();
+        Body::wrap_stream(body.map(move |chunk| {
+            if let Ok(data) = &chunk {
+                exchange.add_request_bytes(data.len());
+            }
+            chunk
+        }))
+    }
+}
+
+/// Transfer totals per route and the top clients; `reset` starts all counts over (exchanges in flight keep counting)
+pub fn transfer_stats(reset: bool) -> TransferStats {
+    let routes = routes().lock().unwrap().iter().map(|(key, counters)| (key.clone(), counters.read(reset))).collect();
+    let mut clients = clients().lock().unwrap();
+    let top_clients = clients.top(TOP_CLIENTS);
+    if reset {
+        clients.entries.clear();
+    }
+    TransferStats { routes, top_clients }
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([198, 51, 100, last])
+    }
+
+    #[tokio::test]
+    async fn test_exchange_counts_bodies_per_route_and_client() {
+        let exchange = exchange("transfer-test.example.com", ip(1));
+        let body = exchange.count_request(Body::from("0123456789"));
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap().len(), 10);
+        exchange.add_response_bytes(90);
+        drop(exchange);
+
+        let stats = transfer_stats(false);
+        assert_eq!(stats.routes["transfer-test.example.com"], RouteTransfer { requests: 1, request_bytes: 10, response_bytes: 90 });
+        assert!(stats.top_clients.iter().any(|client| client.ip == ip(1) && client.bytes >= 100));
+
+        // Empty request bodies are not wrapped
+        let empty = crate::proxy::transfer::exchange("transfer-test.example.com", ip(1)).count_request(Body::empty());
+        assert!(empty.is_end_stream());
+    }
+
+    #[test]
+    fn test_client_table_keeps_heavy_clients_within_capacity() {
+        let mut table = ClientTable::default();
+        table.record(ip(1), 1_000_000);
+        for n in 0..CLIENT_CAPACITY * 3 {
+            table.record(IpAddr::from([10, 0, (n / 256) as u8, (n % 256) as u8]), 100);
+        }
+        table.record(ip(1), 1);
+        assert_eq!(table.entries.len(), CLIENT_CAPACITY);
+
+        let top = table.top(3);
+        assert_eq!(top[0], ClientTransfer { ip: ip(1), bytes: 1_000_001, error: 0 });
+        // Newcomers at a full table inherit the count of the address they replaced
+        assert!(top[1].error > 0 && top[1].bytes > top[1].error);
+    }
+}
@@ -0,0 +1,147 @@
+//! Backends on a unix domain socket (`unix_socket` on a route) instead of host:port.
+//!
+//! The socket path travels hex-encoded as the host of a `unix://` URI (`unix://2f72756e2f6170702e736f636b` for
+//! `/run/app.sock`), so it goes everywhere an upstream URI goes: the request path and query are appended to it, the
+//! connection pools are keyed by it and the connectors below tell it apart from TCP upstreams by its scheme.
+
+use hyper::Uri;
+use hyper::client::connect::{Connected, Connection};
+use hyper::service::Service;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+/// Scheme of upstream URIs naming a unix socket
+pub const SCHEME: &str = "unix";
+
+/// Base URL requests to the backend on `socket` are proxied to
+pub fn target(socket: &Path) -> String {
+    let hex: String = socket.to_string_lossy().bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("{}://{}", SCHEME, hex)
+}
+
+/// The socket a `unix://` URI made by `target` names; None for any other URI
+pub fn socket_path(uri: &Uri) -> Option<PathBuf> {
+    if uri.scheme_str() != Some(SCHEME) {
+        return None;
+    }
+    let hex = uri.host()?.as_bytes();
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = hex.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()).collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// How a socket backend is shown in logs and listings, e.g. `unix:/run/app.sock`
+pub fn describe(socket: &Path) -> String {
+    format!("{}:{}", SCHEME, socket.display())
+}
+
+/// A connection to a backend, over TCP or a unix domain socket
+pub enum UpstreamStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+/// Connect to the backend listening on `socket`
+pub async fn connect(socket: &Path) -> io::Result<UpstreamStream> {
+    #[cfg(unix)]
+    {
+        tokio::net::UnixStream::connect(socket).await.map(UpstreamStream::Unix)
+    }
+    #[cfg(not(unix))]
+    {
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("unix sockets are not supported on this platform: {}", socket.display())))
+    }
+}
+
+/// Connector for clients that only talk to unix socket backends (the websocket handshake)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnixConnector;
+
+impl Service<Uri> for UnixConnector {
+    type Response = UpstreamStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<UpstreamStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        Box::pin(async move {
+            let socket =
+                socket_path(&uri).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("not a unix socket target: {}", uri)))?;
+            connect(&socket).await
+        })
+    }
+}
+
+impl Connection for UpstreamStream {
+    fn connected(&self) -> Connected {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.connected(),
+            #[cfg(unix)]
+            UpstreamStream::Unix(_) => Connected::new(),
+        }
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_path_round_trips_through_the_target() {
+        let target = target(Path::new("/run/app.sock"));
+        assert_eq!(target, "unix://2f72756e2f6170702e736f636b");
+        let uri: Uri = format!("{}/api/users?page=2", target).parse().unwrap();
+        assert_eq!(socket_path(&uri), Some(PathBuf::from("/run/app.sock")));
+        assert_eq!(uri.path_and_query().unwrap().as_str(), "/api/users?page=2");
+        assert_eq!(socket_path(&"http://127.0.0.1:3000/".parse().unwrap()), None);
+        assert_eq!(socket_path(&"unix://2f7/".parse().unwrap()), None);
+        assert_eq!(describe(Path::new("/run/app.sock")), "unix:/run/app.sock");
+    }
+}
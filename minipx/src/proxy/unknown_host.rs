@@ -0,0 +1,350 @@
+//! The cheap path for requests that name no route or carry a malformed Host, which is mostly scanner traffic.
+//! TLS handshakes rejected for their SNI share the aggregated logging.
+//!
+//! Nothing here allocates once a client is known: responses are built from static parts, each client IP has a
+//! token bucket past which its connections are closed without a response, and logging is aggregated to at most
+//! one line per client IP per `log_interval_secs` plus a summary with the count of the rest.
+
+use crate::config::{UnknownHostAction, UnknownHostConfig};
+use crate::proxy::request_handler::HostRejection;
+use hyper::header::HeaderValue;
+use hyper::{Body, Response, StatusCode, header};
+use log::warn;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Requests for a Host that matched no route, since startup
+static UNKNOWN_HOST_REQUESTS: AtomicU64 = AtomicU64::new(0);
+// Unknown-host and malformed-Host requests whose connection was closed by the per-client limit, since startup
+static RATE_LIMITED: AtomicU64 = AtomicU64::new(0);
+// Client IPs tracked at once; later ones share a single bucket until idle clients are swept out
+const CLIENT_CAPACITY: usize = 1024;
+// How often clients whose log interval ran out are summarized, and idle ones forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+static CLIENTS: OnceLock<Mutex<Clients>> = OnceLock::new();
+
+fn clients() -> &'static Mutex<Clients> {
+    // Twice the capacity, so removals never make the table rehash into a new allocation
+    CLIENTS.get_or_init(|| Mutex::new(Clients { by_ip: HashMap::with_capacity(CLIENT_CAPACITY * 2), overflow: None, swept: Instant::now() }))
+}
+
+/// Returned (inside the handler's `anyhow::Error`) when the request should get no response at all;
+/// `MinipxService` surfaces it as its service error, which makes hyper close the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionClosed;
+
+impl fmt::Display for ConnectionClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("connection closed without a response")
+    }
+}
+
+impl std::error::Error for ConnectionClosed {}
+
+/// Unknown-host requests seen since startup
+pub fn unknown_host_requests() -> u64 {
+    UNKNOWN_HOST_REQUESTS.load(Ordering::Relaxed)
+}
+
+/// Unknown-host and malformed-Host requests dropped by the per-client limit since startup
+pub fn rate_limited_requests() -> u64 {
+    RATE_LIMITED.load(Ordering::Relaxed)
+}
+
+/// Count an unknown-host request against its client and build the configured response, or close the connection
+/// once the client is over its limit
+pub fn respond(config: &UnknownHostConfig, client_ip: IpAddr, host: &str) -> Result<Response<Body>, ConnectionClosed> {
+    UNKNOWN_HOST_REQUESTS.fetch_add(1, Ordering::Relaxed);
+    if !admit(config, client_ip, Offense::UnknownHost(host), Instant::now()) {
+        return Err(ConnectionClosed);
+    }
+
+    match config.get_action() {
+        UnknownHostAction::NotFound => Ok(static_response(StatusCode::NOT_FOUND, "Not Found")),
+        UnknownHostAction::Close => Err(ConnectionClosed),
+        UnknownHostAction::Redirect(url) => {
+            // A URL that is not a valid header value cannot be sent; such a config gets the plain 404
+            let Ok(location) = HeaderValue::from_str(url) else {
+                return Ok(static_response(StatusCode::NOT_FOUND, "Not Found"));
+            };
+            let mut response = static_response(StatusCode::FOUND, "");
+            response.headers_mut().insert(header::LOCATION, location);
+            Ok(response)
+        }
+    }
+}
+
+/// Count a request whose Host was refused against its client and answer `400`, or close the connection once the
+/// client is over its limit
+pub fn reject_host(config: &UnknownHostConfig, client_ip: IpAddr, rejection: &HostRejection) -> Result<Response<Body>, ConnectionClosed> {
+    if !admit(config, client_ip, Offense::BadHost(rejection), Instant::now()) {
+        return Err(ConnectionClosed);
+    }
+    Ok(static_response(StatusCode::BAD_REQUEST, "Bad Request"))
+}
+
+/// Log a TLS handshake rejected for a missing or unknown SNI through the client's aggregated log line. The handshake
+/// fails either way, so it is not counted against the client's rate limit.
+pub fn reject_handshake(config: &UnknownHostConfig, client_ip: IpAddr, server_name: Option<&str>) {
+    admit(config, client_ip, Offense::UnknownSni(server_name), Instant::now());
+}
+
+// A response with a static body and no headers (hyper adds Content-Length); building it does not allocate
+fn static_response(status: StatusCode, body: &'static str) -> Response<Body> {
+    let mut response = Response::new(if body.is_empty() { Body::empty() } else { Body::from(body) });
+    *response.status_mut() = status;
+    response
+}
+
+// What got the request here; only formatted when a line is actually logged
+#[derive(Clone, Copy)]
+enum Offense<'a> {
+    UnknownHost(&'a str),
+    BadHost(&'a HostRejection),
+    UnknownSni(Option<&'a str>),
+}
+
+// The client a log line is about
+#[derive(Clone, Copy)]
+enum Who {
+    Ip(IpAddr),
+    // Clients that arrived while the table was full
+    Overflow,
+}
+
+impl fmt::Display for Who {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Who::Ip(ip) => ip.fmt(f),
+            Who::Overflow => f.write_str("untracked clients"),
+        }
+    }
+}
+
+struct Clients {
+    by_ip: HashMap<IpAddr, Client>,
+    overflow: Option<Client>,
+    swept: Instant,
+}
+
+impl Clients {
+    // Summarize clients whose log interval ran out and forget the ones with nothing left to remember
+    fn sweep(&mut self, config: &UnknownHostConfig, now: Instant) {
+        self.swept = now;
+        let interval = config.get_log_interval();
+        self.by_ip.retain(|ip, client| {
+            client.roll_window(Who::Ip(*ip), interval, now);
+            !client.is_idle(config, now)
+        });
+        if let Some(client) = &mut self.overflow {
+            client.roll_window(Who::Overflow, interval, now);
+        }
+    }
+}
+
+// A client's bucket and the log state of its current interval
+#[derive(Debug)]
+struct Client {
+    tokens: f64,
+    refilled: Instant,
+    window_start: Instant,
+    // The first request of the interval was logged; later ones are only counted
+    logged: bool,
+    suppressed: u64,
+    closed: u64,
+}
+
+impl Client {
+    fn new(config: &UnknownHostConfig, now: Instant) -> Self {
+        Self { tokens: config.get_rate_limit_burst() as f64, refilled: now, window_start: now, logged: false, suppressed: 0, closed: 0 }
+    }
+
+    // Take a token; false when the client is over its limit
+    fn take(&mut self, config: &UnknownHostConfig, now: Instant) -> bool {
+        let burst = config.get_rate_limit_burst();
+        if burst == 0 {
+            return true;
+        }
+        self.refill(config, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&mut self, config: &UnknownHostConfig, now: Instant) {
+        let rate = config.get_rate_limit_per_minute() as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(config.get_rate_limit_burst() as f64);
+        self.refilled = now;
+    }
+
+    // Start a new interval once the current one is over, logging the summary of what went unlogged in it
+    fn roll_window(&mut self, who: Who, interval: Duration, now: Instant) {
+        if now.saturating_duration_since(self.window_start) < interval {
+            return;
+        }
+        if self.suppressed > 0 {
+            warn!(
+                "{who}: {count} more unknown-host, malformed-Host or unknown-SNI requests in the last {secs}s, {closed} of them closed by the rate limit",
+                who = who,
+                count = self.suppressed,
+                secs = interval.as_secs(),
+                closed = self.closed
+            );
+        }
+        self.window_start = now;
+        self.logged = false;
+        self.suppressed = 0;
+        self.closed = 0;
+    }
+
+    // Nothing to log and a full bucket: forgetting the client changes nothing
+    fn is_idle(&mut self, config: &UnknownHostConfig, now: Instant) -> bool {
+        if self.logged || self.suppressed > 0 {
+            return false;
+        }
+        self.refill(config, now);
+        self.tokens >= config.get_rate_limit_burst() as f64
+    }
+
+    // Record a request; true if it may be answered
+    fn record(&mut self, config: &UnknownHostConfig, who: Who, offense: Offense, now: Instant) -> bool {
+        self.roll_window(who, config.get_log_interval(), now);
+        let allowed = matches!(offense, Offense::UnknownSni(_)) || self.take(config, now);
+        if !allowed {
+            RATE_LIMITED.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.logged {
+            self.suppressed += 1;
+            self.closed += u64::from(!allowed);
+            return allowed;
+        }
+        self.logged = true;
+        let closing = if allowed { "" } else { ", closing the connection (rate limited)" };
+        match offense {
+            Offense::UnknownHost(host) => {
+                warn!("Received request from {ip} for unknown host {host}{closing}", ip = who, host = host, closing = closing)
+            }
+            Offense::BadHost(rejection) => warn!("Rejected request from {ip}: {reason}{closing}", ip = who, reason = rejection, closing = closing),
+            Offense::UnknownSni(server_name) => {
+                warn!("Rejected TLS handshake from {ip} for {sni}", ip = who, sni = server_name.unwrap_or("<no SNI>"))
+            }
+        }
+        allowed
+    }
+}
+
+// Count the request against its client; false if the connection should be closed without a response
+fn admit(config: &UnknownHostConfig, client_ip: IpAddr, offense: Offense, now: Instant) -> bool {
+    let mut clients = clients().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if now.saturating_duration_since(clients.swept) >= SWEEP_INTERVAL {
+        clients.sweep(config, now);
+    }
+    let Clients { by_ip, overflow, .. } = &mut *clients;
+    if by_ip.len() < CLIENT_CAPACITY || by_ip.contains_key(&client_ip) {
+        let client = by_ip.entry(client_ip).or_insert_with(|| Client::new(config, now));
+        client.record(config, Who::Ip(client_ip), offense, now)
+    } else {
+        let client = overflow.get_or_insert_with(|| Client::new(config, now));
+        client.record(config, Who::Overflow, offense, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(burst: u64, per_minute: u64) -> UnknownHostConfig {
+        let mut config = UnknownHostConfig::default();
+        config.set_rate_limit_burst(burst);
+        config.set_rate_limit_per_minute(per_minute);
+        config
+    }
+
+    #[test]
+    fn test_bucket_refills_at_the_configured_rate() {
+        let config = limited(2, 60);
+        let start = Instant::now();
+        let mut client = Client::new(&config, start);
+        assert!(client.take(&config, start));
+        assert!(client.take(&config, start));
+        assert!(!client.take(&config, start));
+        assert!(!client.take(&config, start + Duration::from_millis(500)));
+        assert!(client.take(&config, start + Duration::from_millis(1100)));
+
+        let unlimited = limited(0, 0);
+        let mut client = Client::new(&unlimited, start);
+        assert!((0..100).all(|_| client.take(&unlimited, start)));
+    }
+
+    #[test]
+    fn test_one_line_per_interval_then_a_summary() {
+        let config = limited(3, 0);
+        let who = Who::Ip("192.0.2.50".parse().unwrap());
+        let offense = Offense::UnknownHost("scan.test");
+        let start = Instant::now();
+        let mut client = Client::new(&config, start);
+        let answered: Vec<bool> = (0..5).map(|_| client.record(&config, who, offense, start)).collect();
+        assert_eq!(answered, vec![true, true, true, false, false]);
+        assert!(client.logged);
+        assert_eq!((client.suppressed, client.closed), (4, 2));
+        assert!(!client.is_idle(&config, start));
+
+        let later = start + config.get_log_interval();
+        client.roll_window(who, config.get_log_interval(), later);
+        assert!(!client.logged);
+        assert_eq!((client.suppressed, client.closed), (0, 0));
+        // Still out of tokens, so the client is remembered
+        assert!(!client.is_idle(&config, later));
+    }
+
+    #[test]
+    fn test_rejected_handshakes_are_logged_once_per_interval_without_taking_tokens() {
+        let config = limited(1, 0);
+        let who = Who::Ip("192.0.2.51".parse().unwrap());
+        let start = Instant::now();
+        let mut client = Client::new(&config, start);
+        assert!((0..5).all(|_| client.record(&config, who, Offense::UnknownSni(Some("scan.test")), start)));
+        assert_eq!((client.logged, client.suppressed, client.closed), (true, 4, 0));
+        // The request that follows still has its token
+        assert!(client.record(&config, who, Offense::UnknownHost("scan.test"), start));
+    }
+
+    #[tokio::test]
+    async fn test_respond_modes_and_counters() {
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let before = unknown_host_requests();
+
+        let resp = respond(&UnknownHostConfig::default(), client, "scan.test").unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let redirect = UnknownHostConfig::new(UnknownHostAction::Redirect("https://example.com/".to_string()));
+        let resp = respond(&redirect, client, "scan.test").unwrap();
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(resp.headers()[header::LOCATION], "https://example.com/");
+
+        let close = UnknownHostConfig::new(UnknownHostAction::Close);
+        assert_eq!(respond(&close, client, "scan.test").unwrap_err(), ConnectionClosed);
+
+        assert!(unknown_host_requests() >= before + 3);
+
+        // Past the burst the connection is closed, for unknown and malformed hosts alike; other clients are unaffected
+        let scanner: IpAddr = "192.0.2.2".parse().unwrap();
+        let config = limited(2, 0);
+        let rate_limited = rate_limited_requests();
+        assert!(respond(&config, scanner, "a.test").is_ok());
+        assert_eq!(reject_host(&config, scanner, &HostRejection::MultipleHosts).unwrap().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(respond(&config, scanner, "b.test").unwrap_err(), ConnectionClosed);
+        assert_eq!(reject_host(&config, scanner, &HostRejection::MultipleHosts).unwrap_err(), ConnectionClosed);
+        assert!(rate_limited_requests() >= rate_limited + 2);
+        assert!(respond(&config, "192.0.2.3".parse().unwrap(), "a.test").is_ok());
+    }
+}
@@ -0,0 +1,495 @@
+//! HTTP client for proxied requests. Each upstream (host:port, or a unix socket) has its own keep-alive connection pool.
+//!
+//! A pooled connection can die while idle, typically when the backend restarts during a deploy; the next request
+//! on it fails with a reset or EOF before any response arrives. Such a request was never processed, so it is
+//! retried once on a fresh connection (whatever its method) and the upstream's pool is evicted. A connection that
+//! closes after part of a response arrived did see the request, so that failure is never retried. Retries are counted
+//! per upstream so deploy-time bursts show up in the metrics instead of as 502s.
+
+use crate::proxy::resolver::{self, UpstreamResolver};
+use crate::proxy::unix_socket::{self, UpstreamStream};
+use anyhow::{Result, anyhow};
+use hyper::body::{Bytes, HttpBody};
+use hyper::client::HttpConnector;
+use hyper::client::connect::{Connected, Connection};
+use hyper::header::{CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, HeaderMap, HeaderName, HeaderValue, UPGRADE};
+use hyper::http::request::Parts;
+use hyper::service::Service;
+use hyper::{Body, Client, Request, Response, StatusCode, Uri};
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Request bodies up to this size are buffered so the request can be replayed after a stale connection
+const MAX_REPLAY_BYTES: u64 = 64 * 1024;
+
+static POOLS: OnceLock<Mutex<HashMap<String, Arc<UpstreamPool>>>> = OnceLock::new();
+static STALE_RETRIES: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+
+fn pools() -> &'static Mutex<HashMap<String, Arc<UpstreamPool>>> {
+    POOLS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn stale_retry_counts() -> &'static Mutex<BTreeMap<String, u64>> {
+    STALE_RETRIES.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+// Headers that describe a single connection and are never forwarded (besides the ones `Connection` names)
+const HOP_HEADERS: [&str; 10] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "trailers",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Connection pool of one upstream; counts the connections it opens and the responses cut off on them, so a failure
+/// can be attributed to a reused connection that died before answering
+struct UpstreamPool {
+    client: Client<CountingConnector, Body>,
+    connects: Arc<AtomicU64>,
+    cut_responses: Arc<AtomicU64>,
+}
+
+impl UpstreamPool {
+    fn new() -> Self {
+        let connects = Arc::new(AtomicU64::new(0));
+        let cut_responses = Arc::new(AtomicU64::new(0));
+        let connector = CountingConnector { inner: resolver::http_connector(), connects: connects.clone(), cut_responses: cut_responses.clone() };
+        Self { client: Client::builder().build(connector), connects, cut_responses }
+    }
+
+    // Unchanged across a failed request: no connection was opened for it and none closed in the middle of a response
+    fn counters(&self) -> (u64, u64) {
+        (self.connects.load(Ordering::Acquire), self.cut_responses.load(Ordering::Acquire))
+    }
+}
+
+#[derive(Clone)]
+struct CountingConnector {
+    inner: HttpConnector<UpstreamResolver>,
+    connects: Arc<AtomicU64>,
+    cut_responses: Arc<AtomicU64>,
+}
+
+impl Service<Uri> for CountingConnector {
+    type Response = PooledStream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<PooledStream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        self.connects.fetch_add(1, Ordering::AcqRel);
+        let cut_responses = self.cut_responses.clone();
+        if let Some(socket) = unix_socket::socket_path(&uri) {
+            return Box::pin(async move { unix_socket::connect(&socket).await.map(|s| PooledStream::new(s, cut_responses)).map_err(Into::into) });
+        }
+        let connecting = self.inner.call(uri);
+        Box::pin(async move { connecting.await.map(|s| PooledStream::new(UpstreamStream::Tcp(s), cut_responses)).map_err(Into::into) })
+    }
+}
+
+/// A pooled connection that counts it against its pool when it ends (EOF or error) after part of a response arrived
+struct PooledStream {
+    inner: UpstreamStream,
+    // Bytes were read since the last request was written
+    mid_response: bool,
+    cut_responses: Arc<AtomicU64>,
+}
+
+impl PooledStream {
+    fn new(inner: UpstreamStream, cut_responses: Arc<AtomicU64>) -> Self {
+        Self { inner, mid_response: false, cut_responses }
+    }
+}
+
+impl Connection for PooledStream {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
+
+impl AsyncRead for PooledStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        match &result {
+            Poll::Ready(Ok(())) if buf.filled().len() > filled => this.mid_response = true,
+            Poll::Ready(_) if this.mid_response => {
+                this.mid_response = false;
+                this.cut_responses.fetch_add(1, Ordering::AcqRel);
+            }
+            _ => {}
+        }
+        result
+    }
+}
+
+impl AsyncWrite for PooledStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if matches!(result, Poll::Ready(Ok(n)) if n > 0) {
+            this.mid_response = false;
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+fn pool_for(upstream: &str) -> Arc<UpstreamPool> {
+    pools().lock().unwrap().entry(upstream.to_string()).or_insert_with(|| Arc::new(UpstreamPool::new())).clone()
+}
+
+/// Drop the upstream's pool (idle connections close once in-flight requests on them finish) and start a new one
+fn evict(upstream: &str) -> Arc<UpstreamPool> {
+    let pool = Arc::new(UpstreamPool::new());
+    pools().lock().unwrap().insert(upstream.to_string(), pool.clone());
+    pool
+}
+
+/// Stale-connection retries per upstream (`host:port`) since startup
+pub fn stale_retries() -> BTreeMap<String, u64> {
+    stale_retry_counts().lock().unwrap().clone()
+}
+
+/// Proxy `request` to `target` (e.g. `http://127.0.0.1:3000`): the request path and query are appended to the target,
+/// hop-by-hop headers are dropped in both directions and `client_ip` is appended to X-Forwarded-For.
+/// None if the backend sent no response headers within `header_timeout`; the client's body is read before that clock starts.
+pub async fn forward(client_ip: IpAddr, target: &str, request: Request<Body>, header_timeout: Option<Duration>) -> Result<Option<Response<Body>>> {
+    let (mut parts, body) = request.into_parts();
+    parts.uri = forward_uri(target, &parts.uri)?;
+    remove_hop_headers(&mut parts.headers);
+    let forwarded_for = match parts.headers.get("x-forwarded-for").map(|v| v.to_str()) {
+        Some(Ok(chain)) => format!("{}, {}", chain, client_ip),
+        Some(Err(_)) => return Err(anyhow!("Invalid X-Forwarded-For header")),
+        None => client_ip.to_string(),
+    };
+    parts.headers.insert(HeaderName::from_static("x-forwarded-for"), HeaderValue::from_str(&forwarded_for)?);
+
+    let upstream = parts.uri.authority().map(|a| a.to_string()).ok_or_else(|| anyhow!("Upstream target without host: {}", target))?;
+    let pool = pool_for(&upstream);
+    let response = if is_replayable(&parts, &body) {
+        let body = hyper::body::to_bytes(body).await?;
+        within_header_timeout(header_timeout, async {
+            let counters = pool.counters();
+            match pool.client.request(rebuild(&parts, body.clone())).await {
+                // No connection was opened and no response cut off while the request failed: it went out on a reused one
+                // that turned out dead before answering
+                Err(e) if is_stale_connection(&e) && pool.counters() == counters => {
+                    let retries = {
+                        let mut counts = stale_retry_counts().lock().unwrap();
+                        let count = counts.entry(upstream.clone()).or_default();
+                        *count += 1;
+                        *count
+                    };
+                    warn!("Pooled connection to {} failed on reuse ({}); retrying on a fresh connection (stale retry #{})", upstream, e, retries);
+                    evict(&upstream).client.request(rebuild(&parts, body)).await
+                }
+                result => result,
+            }
+        })
+        .await
+    } else {
+        within_header_timeout(header_timeout, pool.client.request(Request::from_parts(parts, body))).await
+    };
+    let Some(response) = response else {
+        return Ok(None);
+    };
+    let mut response = response?;
+    remove_hop_headers(response.headers_mut());
+    Ok(Some(response))
+}
+
+/// Wait at most `timeout` (None: forever) for `response`, a request in flight to a backend that resolves once the
+/// response headers arrive; None if they did not. Only the headers are timed, never the body streamed afterwards.
+pub async fn within_header_timeout<T>(timeout: Option<Duration>, response: impl Future<Output = T>) -> Option<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, response).await.ok(),
+        None => Some(response.await),
+    }
+}
+
+/// `504 Gateway Timeout` whose body names the phase that timed out, e.g. "response headers"
+pub fn gateway_timeout(phase: &str, timeout: Duration) -> Result<Response<Body>> {
+    Ok(Response::builder().status(StatusCode::GATEWAY_TIMEOUT).header(CONTENT_TYPE, "text/plain").body(Body::from(format!(
+        "Gateway Timeout: the backend sent no {} within {} ms",
+        phase,
+        timeout.as_millis()
+    )))?)
+}
+
+fn forward_uri(target: &str, uri: &Uri) -> Result<Uri> {
+    let uri = match uri.query() {
+        Some(query) => format!("{}{}?{}", target, uri.path(), query),
+        None => format!("{}{}", target, uri.path()),
+    };
+    Ok(uri.parse()?)
+}
+
+/// Drop hop-by-hop headers: the fixed set plus every header listed in `Connection` (RFC 9110, section 7.6.1).
+/// `Host` is kept even when listed, so a client cannot change what the backend sees through `Connection: host`.
+pub fn remove_hop_headers(headers: &mut HeaderMap) {
+    for name in connection_options(headers) {
+        if name != HOST {
+            headers.remove(name);
+        }
+    }
+    for name in HOP_HEADERS {
+        headers.remove(name);
+    }
+}
+
+/// `remove_hop_headers` for a websocket handshake: `Upgrade` survives and `Connection` is reduced to `upgrade`
+pub fn remove_hop_headers_for_upgrade(headers: &mut HeaderMap) {
+    let upgrade = headers.remove(UPGRADE);
+    remove_hop_headers(headers);
+    if let Some(upgrade) = upgrade {
+        headers.insert(UPGRADE, upgrade);
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    }
+}
+
+// Header names listed in every Connection header; options such as `close` name no real header, so removing them is a no-op
+fn connection_options(headers: &HeaderMap) -> Vec<HeaderName> {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|token| HeaderName::from_bytes(token.trim().as_bytes()).ok())
+        .collect()
+}
+
+// Requests without a body, or with a small one of known length, can be sent a second time
+fn is_replayable(parts: &Parts, body: &Body) -> bool {
+    if body.is_end_stream() {
+        return true;
+    }
+    let length = parts.headers.get(CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+    length.is_some_and(|length| length <= MAX_REPLAY_BYTES)
+}
+
+fn rebuild(parts: &Parts, body: Bytes) -> Request<Body> {
+    let mut request = Request::new(Body::from(body));
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+// The connection was closed or reset (but it was established). Hyper reports a response cut off after its first bytes
+// the same way, so the caller also checks that none of the pool's connections ended mid-response.
+fn is_stale_connection(error: &hyper::Error) -> bool {
+    if error.is_connect() {
+        return false;
+    }
+    if error.is_incomplete_message() {
+        return true;
+    }
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::UnexpectedEof
+            );
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Method;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // Read one request head (and its Content-Length body); false if the peer closed first
+    async fn read_request(stream: &mut TcpStream) -> bool {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&data[..end]).to_ascii_lowercase();
+                let length: usize = head.lines().find_map(|l| l.strip_prefix("content-length:")).map(|v| v.trim().parse().unwrap()).unwrap_or(0);
+                while data.len() < end + 4 + length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                }
+                return true;
+            }
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return false,
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    // Upstream whose connections answer `answers` requests each and then drop the connection on the next one after
+    // sending `partial`, like a backend that restarted (nothing sent) or crashed mid-response
+    async fn spawn_flaky_upstream(answers: usize, partial: &'static [u8]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    for _ in 0..answers {
+                        if !read_request(&mut stream).await {
+                            return;
+                        }
+                        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.unwrap();
+                    }
+                    // Take the next request and hang up without answering it in full
+                    if read_request(&mut stream).await {
+                        let _ = stream.write_all(partial).await;
+                    }
+                });
+            }
+        });
+        (format!("http://{}", addr), connections)
+    }
+
+    fn post(body: &'static str) -> Request<Body> {
+        Request::builder().method(Method::POST).uri("/submit").header(CONTENT_LENGTH, body.len()).body(Body::from(body)).unwrap()
+    }
+
+    async fn send(target: &str, request: Request<Body>) -> Result<String> {
+        let response = forward(IpAddr::from([127, 0, 0, 1]), target, request, None).await?.unwrap();
+        Ok(String::from_utf8(hyper::body::to_bytes(response.into_body()).await?.to_vec())?)
+    }
+
+    #[tokio::test]
+    async fn test_dead_pooled_connection_is_retried_on_a_fresh_one() {
+        let (target, connections) = spawn_flaky_upstream(1, b"").await;
+        let upstream = target.trim_start_matches("http://").to_string();
+        assert_eq!(send(&target, Request::new(Body::empty())).await.unwrap(), "ok");
+
+        // The pooled connection is dead; the POST is replayed on a new connection
+        assert_eq!(send(&target, post("payload")).await.unwrap(), "ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(stale_retries().get(&upstream), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_failure_on_a_fresh_connection_is_not_retried() {
+        let (target, connections) = spawn_flaky_upstream(0, b"").await;
+        let upstream = target.trim_start_matches("http://").to_string();
+        assert!(send(&target, post("payload")).await.is_err());
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(stale_retries().get(&upstream), None);
+    }
+
+    #[tokio::test]
+    async fn test_response_cut_off_after_the_status_line_is_not_retried() {
+        let (target, connections) = spawn_flaky_upstream(1, b"HTTP/1.1 200 OK\r\nContent-Le").await;
+        let upstream = target.trim_start_matches("http://").to_string();
+        assert_eq!(send(&target, Request::new(Body::empty())).await.unwrap(), "ok");
+
+        // The backend saw the POST and started answering: replaying it could apply it twice
+        assert!(send(&target, post("payload")).await.is_err());
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(stale_retries().get(&upstream), None);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_bodies_are_not_replayed() {
+        let (target, connections) = spawn_flaky_upstream(1, b"").await;
+        assert_eq!(send(&target, Request::new(Body::empty())).await.unwrap(), "ok");
+
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            let _ = sender.send_data(Bytes::from_static(b"streamed")).await;
+        });
+        assert!(send(&target, Request::builder().method(Method::POST).uri("/upload").body(body).unwrap()).await.is_err());
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_slow_upload_is_not_counted_against_the_header_timeout() {
+        let (target, _) = spawn_flaky_upstream(1, b"").await;
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let _ = sender.send_data(Bytes::from_static(b"payload")).await;
+        });
+        let request = Request::builder().method(Method::POST).uri("/submit").header(CONTENT_LENGTH, 7).body(body).unwrap();
+        let response = forward(IpAddr::from([127, 0, 0, 1]), &target, request, Some(Duration::from_millis(200))).await.unwrap();
+        assert_eq!(response.map(|response| response.status()), Some(StatusCode::OK));
+    }
+
+    #[test]
+    fn test_forward_uri_appends_path_and_query() {
+        let uri: Uri = "/a/b?x=1".parse().unwrap();
+        assert_eq!(forward_uri("http://127.0.0.1:3000", &uri).unwrap(), "http://127.0.0.1:3000/a/b?x=1");
+    }
+
+    fn crafted_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("app.example.com"));
+        headers.append(CONNECTION, HeaderValue::from_static("close, X-Hop , host"));
+        headers.append(CONNECTION, HeaderValue::from_static("x-other-hop, Upgrade"));
+        headers.insert("x-hop", HeaderValue::from_static("1"));
+        headers.insert("x-other-hop", HeaderValue::from_static("1"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5, max=100"));
+        headers.insert("proxy-connection", HeaderValue::from_static("keep-alive"));
+        headers.insert("te", HeaderValue::from_static("trailers"));
+        headers.insert("trailer", HeaderValue::from_static("x-checksum"));
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert("x-end-to-end", HeaderValue::from_static("kept"));
+        headers
+    }
+
+    #[test]
+    fn test_hop_headers_and_connection_options_are_removed() {
+        let mut headers = crafted_headers();
+        remove_hop_headers(&mut headers);
+        let mut names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["host", "x-end-to-end"]);
+    }
+
+    #[test]
+    fn test_upgrade_handshake_keeps_upgrade() {
+        let mut headers = crafted_headers();
+        remove_hop_headers_for_upgrade(&mut headers);
+        assert_eq!(headers[UPGRADE], "websocket");
+        assert_eq!(headers.get_all(CONNECTION).iter().collect::<Vec<_>>(), vec!["upgrade"]);
+        assert!(headers.get("x-hop").is_none() && headers.get("keep-alive").is_none() && headers.get("te").is_none());
+        assert_eq!(headers[HOST], "app.example.com");
+    }
+}
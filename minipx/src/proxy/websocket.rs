@@ -1,13 +1,20 @@
+use crate::config::ProxyRoute;
+use crate::proxy::client_ip::ClientIp;
+use crate::proxy::request_handler::{InFlight, subroute_upstream_path};
+use crate::proxy::resolver;
+use crate::proxy::unix_socket::{self, UnixConnector};
+use crate::proxy::upstream::{gateway_timeout, remove_hop_headers, remove_hop_headers_for_upgrade, within_header_timeout};
+use crate::proxy::ws_stats;
 use anyhow::Result;
 use hyper::Client;
 use hyper::body::to_bytes;
 use hyper::http::Version;
 use hyper::upgrade;
-use hyper::{Body, Request, Response, StatusCode, header};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode, header};
 use hyper_tls::HttpsConnector;
 use log::{debug, error, warn};
-use std::net::IpAddr;
-use std::time::Instant;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 /// Check if the request is a WebSocket upgrade request
 pub fn is_websocket(req: &Request<Body>) -> bool {
@@ -18,66 +25,136 @@ pub fn is_websocket(req: &Request<Body>) -> bool {
     has_upgrade_ws && has_connection_upgrade
 }
 
+// Subprotocols offered by the client, in order, across every Sec-WebSocket-Protocol header
+fn offered_protocols(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// Check the handshake against the route's origin and subprotocol allow-lists.
+/// Returns the subprotocols to offer upstream (disallowed offers are dropped), or why the handshake is refused.
+fn check_allow_lists(route: &ProxyRoute, headers: &HeaderMap) -> std::result::Result<Vec<String>, String> {
+    let allowed_origins = route.get_ws_allowed_origins();
+    if !allowed_origins.is_empty() {
+        // Browsers always send Origin; a handshake without one is not from an allowed page
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return Err("missing Origin header".to_string());
+        };
+        let origin = origin.to_str().unwrap_or_default().trim_end_matches('/');
+        if !allowed_origins.iter().any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin)) {
+            return Err(format!("origin '{}' is not allowed", origin));
+        }
+    }
+
+    let offered = offered_protocols(headers);
+    let allowed_protocols = route.get_ws_allowed_protocols();
+    if allowed_protocols.is_empty() {
+        return Ok(offered);
+    }
+    let permitted: Vec<String> = offered.iter().filter(|p| allowed_protocols.contains(p)).cloned().collect();
+    if permitted.is_empty() {
+        return Err(format!("none of the offered subprotocols {:?} is allowed", offered));
+    }
+    Ok(permitted)
+}
+
 /// Handle WebSocket proxy requests with upgrade and bidirectional tunneling
+#[allow(clippy::too_many_arguments)]
 pub async fn proxy_websocket(
-    client_ip: IpAddr,
+    client_ip: ClientIp,
     req: Request<Body>,
     upstream_scheme: &str,
     upstream_host: &str,
     upstream_port: u16,
+    backend_socket: Option<&Path>,
     subroute_path: &str,
     domain: &str,
     frontend_scheme: &str,
+    preserve_host: bool,
+    route: &ProxyRoute,
+    header_timeout: Option<Duration>,
+    in_flight: InFlight,
 ) -> Result<Response<Body>> {
-    // Build upstream URI: strip subroute path if present, then add requested path_and_query
-    let suffix = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    // Enforce the route's allow-lists before anything reaches the backend
+    let protocols = match check_allow_lists(route, req.headers()) {
+        Ok(protocols) => protocols,
+        Err(reason) => {
+            warn!("Rejected websocket upgrade from {ip} for {domain}: {reason}", ip = client_ip, domain = domain, reason = reason);
+            return Ok(Response::builder().status(StatusCode::FORBIDDEN).header("Content-Type", "text/plain").body(Body::from("Forbidden"))?);
+        }
+    };
 
-    let upstream_path =
-        if !subroute_path.is_empty() && suffix.starts_with(subroute_path) { suffix.strip_prefix(subroute_path).unwrap_or("/") } else { suffix };
+    // Build upstream URI: strip the subroute path the same way as for plain HTTP requests, then add the query
+    let upstream_path = if subroute_path.is_empty() {
+        req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/").to_string()
+    } else {
+        subroute_upstream_path(subroute_path, req.uri().path(), req.uri().query())
+    };
 
     // For WebSocket upgrades, always use http:// for upstream connections
-    // TLS is terminated at the proxy, so backend connections are plain HTTP
-    let upstream_uri = format!("http://{}:{}{}", upstream_host, upstream_port, upstream_path);
+    // TLS is terminated at the proxy, so backend connections are plain HTTP. Socket backends are logged by path.
+    let (request_uri, upstream_uri) = match backend_socket {
+        Some(socket) => (format!("{}{}", unix_socket::target(socket), upstream_path), format!("{}{}", unix_socket::describe(socket), upstream_path)),
+        None => {
+            let uri = format!("http://{}:{}{}", upstream_host, upstream_port, upstream_path);
+            (uri.clone(), uri)
+        }
+    };
 
     // Extract the request body before moving req to preserve it for both upstream and client upgrade
     let (req_parts, req_body) = req.into_parts();
     let req = Request::from_parts(req_parts, req_body);
 
     // Prepare a WebSocket handshake request to upstream (force HTTP/1.1)
-    let mut builder = Request::builder().method(req.method()).version(Version::HTTP_11).uri(&upstream_uri);
+    let mut builder = Request::builder().method(req.method()).version(Version::HTTP_11).uri(&request_uri);
 
     // Copy headers, but fix Host and X-Forwarded-For
     {
         let headers = req.headers();
-        for (name, value) in headers.iter() {
-            if name == header::HOST {
+        // Hop-by-hop headers stay on the client connection; the handshake keeps Upgrade with `Connection: upgrade`
+        let mut forwarded = headers.clone();
+        remove_hop_headers_for_upgrade(&mut forwarded);
+        for (name, value) in forwarded.iter() {
+            if name == header::HOST || name == header::SEC_WEBSOCKET_PROTOCOL {
                 continue;
             }
-            // Keep Upgrade/Connection and WS headers intact
             builder = builder.header(name, value);
         }
-        let host_header = format!("{}:{}", upstream_host, upstream_port);
-        builder = builder.header(header::HOST, host_header);
+        if !protocols.is_empty() {
+            builder = builder.header(header::SEC_WEBSOCKET_PROTOCOL, protocols.join(", "));
+        }
+        match (route.get_upstream_host_header(), headers.get(header::HOST)) {
+            (Some(host), _) => builder = builder.header(header::HOST, host),
+            (None, Some(original)) if preserve_host => builder = builder.header(header::HOST, original.clone()),
+            _ if backend_socket.is_some() => builder = builder.header(header::HOST, upstream_host),
+            _ => builder = builder.header(header::HOST, format!("{}:{}", upstream_host, upstream_port)),
+        }
 
         // X-Forwarded-For
         const XFF: &str = "x-forwarded-for";
         if let Some(existing) = headers.get(XFF) {
             if let Ok(existing_str) = existing.to_str() {
-                let appended = format!("{}, {}", existing_str, client_ip);
+                let appended = format!("{}, {}", existing_str, client_ip.peer);
                 builder = builder.header(XFF, appended);
             }
         } else {
-            builder = builder.header(XFF, client_ip.to_string());
+            builder = builder.header(XFF, client_ip.peer.to_string());
         }
 
         // Add other forwarding headers
-        builder = builder.header("x-real-ip", client_ip.to_string());
+        builder = builder.header("x-real-ip", client_ip.effective.to_string());
         builder = builder.header("x-forwarded-proto", frontend_scheme);
         builder = builder.header("x-forwarded-host", domain);
 
         debug!(
             "Added WS forwarding headers: X-Forwarded-For={}, X-Real-IP={}, X-Forwarded-Proto={}, X-Forwarded-Host={}",
-            client_ip, client_ip, frontend_scheme, domain
+            client_ip.peer, client_ip.effective, frontend_scheme, domain
         );
 
         // Log key incoming WS headers for diagnostics
@@ -101,19 +178,38 @@ pub async fn proxy_websocket(
 
     // HTTP/1.1 only client for WebSocket upgrades (no HTTP/2 adaptive window)
     // WebSocket upgrades require HTTP/1.1, HTTP/2 causes handshake failures
-    let https = HttpsConnector::new();
-    let client: Client<_, Body> = Client::builder().build::<_, Body>(https);
+    let method = upstream_req.method().clone();
+    let response = match backend_socket {
+        Some(_) => Client::builder().build::<_, Body>(UnixConnector).request(upstream_req),
+        None => {
+            let mut http = resolver::http_connector();
+            http.enforce_http(false);
+            let https = HttpsConnector::new_with_connector(http);
+            Client::builder().build::<_, Body>(https).request(upstream_req)
+        }
+    };
 
     debug!(
         "WS upstream request: {method} {uri} (from {client_ip} for {domain})",
-        method = upstream_req.method(),
+        method = method,
         uri = &upstream_uri,
         client_ip = client_ip,
         domain = domain
     );
 
     let start = Instant::now();
-    match client.request(upstream_req).await {
+    // A backend that accepts the connection but never answers the handshake would otherwise stall the 101 forever
+    let Some(result) = within_header_timeout(header_timeout, response).await else {
+        let timeout = header_timeout.unwrap_or_default();
+        warn!(
+            "WS upstream for {domain} -> {uri} did not answer the handshake within {ms} ms",
+            domain = domain,
+            uri = upstream_uri,
+            ms = timeout.as_millis()
+        );
+        return gateway_timeout("websocket handshake response", timeout);
+    };
+    match result {
         Ok(mut upstream_res) => {
             let elapsed = start.elapsed();
             let status = upstream_res.status();
@@ -147,7 +243,8 @@ pub async fn proxy_websocket(
                     hdrs = hdrs,
                     preview = body_preview
                 );
-                // Rebuild response to the client with same status/headers/body
+                // Rebuild response to the client with same status/end-to-end headers/body
+                remove_hop_headers(upstream_res.headers_mut());
                 let mut resp_builder = Response::builder().status(status);
                 for (k, v) in upstream_res.headers().iter() {
                     resp_builder = resp_builder.header(k, v.clone());
@@ -155,6 +252,24 @@ pub async fn proxy_websocket(
                 return Ok(resp_builder.body(Body::from(body_bytes))?);
             }
 
+            // A backend selecting a subprotocol the client never offered would break the client; don't forward it
+            if let Some(selected) = upstream_res.headers().get(header::SEC_WEBSOCKET_PROTOCOL) {
+                let selected = selected.to_str().unwrap_or_default().trim();
+                if !protocols.iter().any(|p| p == selected) {
+                    warn!(
+                        "WS upstream for {domain} -> {uri} selected subprotocol '{selected}' that was not offered ({offered:?}); rejecting handshake",
+                        domain = domain,
+                        uri = upstream_uri,
+                        selected = selected,
+                        offered = protocols
+                    );
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .header("Content-Type", "text/plain")
+                        .body(Body::from("Bad Gateway"))?);
+                }
+            }
+
             // Prepare 101 response to the client, mirroring key headers from upstream
             let mut resp_builder = Response::builder().status(StatusCode::SWITCHING_PROTOCOLS);
             for &h in [
@@ -175,16 +290,20 @@ pub async fn proxy_websocket(
             // Spawn tunnel task to bridge upgraded connections
             let domain_owned = domain.to_string();
             let uri_owned = upstream_uri.clone();
+            let idle_timeout = route.get_ws_idle_timeout();
             tokio::spawn(async move {
+                // The tunnel keeps its route generation pin and concurrency slot until it closes
+                let _in_flight = in_flight;
                 // Wait for client upgrade
                 match upgrade::on(req).await {
                     Ok(mut upgraded_client) => {
                         // Wait for upstream upgrade
                         match upgrade::on(upstream_res).await {
                             Ok(mut upgraded_upstream) => {
-                                if let Err(e) = tokio::io::copy_bidirectional(&mut upgraded_client, &mut upgraded_upstream).await {
-                                    error!("WS tunnel IO error for {domain} ({uri}): {e}", domain = domain_owned, uri = uri_owned, e = e);
-                                }
+                                // Logs the session's byte counts, duration and close reason when it ends
+                                let client_ip = client_ip.effective.to_string();
+                                ws_stats::tunnel(&mut upgraded_client, &mut upgraded_upstream, &domain_owned, &client_ip, &uri_owned, idle_timeout)
+                                    .await;
                             }
                             Err(e) => {
                                 error!("WS upstream upgrade failed for {domain} ({uri}): {e}", domain = domain_owned, uri = uri_owned, e = e);
@@ -203,7 +322,7 @@ pub async fn proxy_websocket(
             let elapsed = start.elapsed();
             // Attempt DNS resolution for diagnostics
             let mut addrs: Vec<String> = Vec::new();
-            if let Ok(iter) = tokio::net::lookup_host((upstream_host, upstream_port)).await {
+            if let Ok(iter) = resolver::resolve(upstream_host, upstream_port).await {
                 for a in iter {
                     addrs.push(a.ip().to_string());
                 }
@@ -0,0 +1,272 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Instant;
+
+// Websocket tunnel counters, keyed by route
+static STATS: OnceLock<Mutex<BTreeMap<String, WebsocketStats>>> = OnceLock::new();
+
+fn stats() -> &'static Mutex<BTreeMap<String, WebsocketStats>> {
+    STATS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Websocket tunnels of one route since startup
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebsocketStats {
+    /// Tunnels open right now
+    pub active: u64,
+    /// Tunnels that have closed, by reason
+    pub closed_eof: u64,
+    pub closed_error: u64,
+    pub closed_idle: u64,
+    pub bytes_to_upstream: u64,
+    pub bytes_to_client: u64,
+}
+
+/// Why a websocket tunnel ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Both sides finished (the websocket close handshake, or a plain disconnect)
+    Eof,
+    /// Reading or writing either side failed
+    IoError { errno: Option<i32>, message: String },
+    /// Nothing moved in either direction for the route's `ws_idle_timeout_secs`
+    IdleTimeout,
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::Eof => f.write_str("eof"),
+            CloseReason::IoError { errno: Some(errno), message } => write!(f, "io_error errno={} ({})", errno, message),
+            CloseReason::IoError { errno: None, message } => write!(f, "io_error ({})", message),
+            CloseReason::IdleTimeout => f.write_str("idle_timeout"),
+        }
+    }
+}
+
+/// One finished websocket tunnel, logged as a single line when it closes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub domain: String,
+    pub client_ip: String,
+    pub upstream: String,
+    pub duration: Duration,
+    pub bytes_to_upstream: u64,
+    pub bytes_to_client: u64,
+    pub reason: CloseReason,
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WS session closed: domain={} client={} upstream={} duration_ms={} bytes_to_upstream={} bytes_to_client={} reason={}",
+            self.domain,
+            self.client_ip,
+            self.upstream,
+            self.duration.as_millis(),
+            self.bytes_to_upstream,
+            self.bytes_to_client,
+            self.reason
+        )
+    }
+}
+
+/// Websocket tunnel counters since startup, per route
+pub fn websocket_stats() -> BTreeMap<String, WebsocketStats> {
+    stats().lock().unwrap().clone()
+}
+
+// Counts the route's tunnel as active until dropped
+struct ActiveTunnel(String);
+
+impl ActiveTunnel {
+    fn open(domain: &str) -> Self {
+        stats().lock().unwrap().entry(domain.to_string()).or_default().active += 1;
+        Self(domain.to_string())
+    }
+}
+
+impl Drop for ActiveTunnel {
+    fn drop(&mut self) {
+        if let Some(stats) = stats().lock().unwrap().get_mut(&self.0) {
+            stats.active = stats.active.saturating_sub(1);
+        }
+    }
+}
+
+/// Copy both directions until the tunnel ends, then log its summary and add it to the route's counters.
+/// With `idle_timeout` the tunnel is closed once nothing has been read from either side for that long.
+pub async fn tunnel<C, U>(
+    client: &mut C,
+    upstream: &mut U,
+    domain: &str,
+    client_ip: &str,
+    upstream_uri: &str,
+    idle_timeout: Option<Duration>,
+) -> SessionSummary
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let _active = ActiveTunnel::open(domain);
+    let start = Instant::now();
+    let activity = Arc::new(AtomicU64::new(0));
+    let mut client = Counted::new(client, start, activity.clone());
+    let mut upstream = Counted::new(upstream, start, activity.clone());
+
+    let reason = {
+        let copy = tokio::io::copy_bidirectional(&mut client, &mut upstream);
+        tokio::pin!(copy);
+        loop {
+            let last_activity = start + Duration::from_millis(activity.load(Ordering::Relaxed));
+            let idle = async {
+                match idle_timeout {
+                    Some(idle_timeout) => tokio::time::sleep_until(last_activity + idle_timeout).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                result = &mut copy => break match result {
+                    Ok(_) => CloseReason::Eof,
+                    Err(e) => CloseReason::IoError { errno: e.raw_os_error(), message: e.to_string() },
+                },
+                // Unless something was read while sleeping, which moves the deadline
+                _ = idle => {
+                    if activity.load(Ordering::Relaxed) == last_activity.duration_since(start).as_millis() as u64 {
+                        break CloseReason::IdleTimeout;
+                    }
+                }
+            }
+        }
+    };
+
+    let summary = SessionSummary {
+        domain: domain.to_string(),
+        client_ip: client_ip.to_string(),
+        upstream: upstream_uri.to_string(),
+        duration: start.elapsed(),
+        bytes_to_upstream: client.bytes,
+        bytes_to_client: upstream.bytes,
+        reason,
+    };
+    {
+        let mut stats = stats().lock().unwrap();
+        let stats = stats.entry(domain.to_string()).or_default();
+        match summary.reason {
+            CloseReason::Eof => stats.closed_eof += 1,
+            CloseReason::IoError { .. } => stats.closed_error += 1,
+            CloseReason::IdleTimeout => stats.closed_idle += 1,
+        }
+        stats.bytes_to_upstream += summary.bytes_to_upstream;
+        stats.bytes_to_client += summary.bytes_to_client;
+    }
+    match summary.reason {
+        CloseReason::IoError { .. } => warn!("{}", summary),
+        _ => info!("{}", summary),
+    }
+    summary
+}
+
+// Counts the bytes read from a stream (what it sent through the tunnel) and stamps the last activity
+struct Counted<'a, S> {
+    inner: &'a mut S,
+    bytes: u64,
+    start: Instant,
+    // Milliseconds after `start` of the last read from either side
+    activity: Arc<AtomicU64>,
+}
+
+impl<'a, S> Counted<'a, S> {
+    fn new(inner: &'a mut S, start: Instant, activity: Arc<AtomicU64>) -> Self {
+        Self { inner, bytes: 0, start, activity }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<'_, S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        if read > 0 {
+            self.bytes += read;
+            self.activity.store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<'_, S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_counts_both_directions_until_eof() {
+        let (mut client, mut client_proxy) = tokio::io::duplex(64);
+        let (mut upstream_proxy, mut upstream) = tokio::io::duplex(64);
+        let session =
+            tokio::spawn(
+                async move { tunnel(&mut client_proxy, &mut upstream_proxy, "eof.ws-stats.test", "127.0.0.1", "ws://backend/", None).await },
+            );
+
+        client.write_all(&[1; 100]).await.unwrap();
+        let mut buf = [0; 100];
+        upstream.read_exact(&mut buf).await.unwrap();
+        upstream.write_all(&[2; 30]).await.unwrap();
+        client.read_exact(&mut buf[..30]).await.unwrap();
+        client.shutdown().await.unwrap();
+        upstream.shutdown().await.unwrap();
+
+        let summary = session.await.unwrap();
+        assert_eq!((summary.bytes_to_upstream, summary.bytes_to_client, &summary.reason), (100, 30, &CloseReason::Eof));
+        assert!(summary.to_string().contains("bytes_to_upstream=100 bytes_to_client=30 reason=eof"), "{}", summary);
+        let stats = websocket_stats()["eof.ws-stats.test"].clone();
+        assert_eq!(stats, WebsocketStats { closed_eof: 1, bytes_to_upstream: 100, bytes_to_client: 30, ..Default::default() });
+    }
+
+    #[tokio::test]
+    async fn test_idle_tunnels_are_closed() {
+        let (mut client, mut client_proxy) = tokio::io::duplex(64);
+        let (mut upstream_proxy, _upstream) = tokio::io::duplex(64);
+        let session = tokio::spawn(async move {
+            tunnel(&mut client_proxy, &mut upstream_proxy, "idle.ws-stats.test", "127.0.0.1", "ws://backend/", Some(Duration::from_millis(400))).await
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(websocket_stats()["idle.ws-stats.test"].active, 1);
+
+        // Traffic pushes the deadline back
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        client.write_all(b"ping").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(!session.is_finished());
+
+        let summary = session.await.unwrap();
+        assert_eq!((summary.reason, summary.bytes_to_upstream), (CloseReason::IdleTimeout, 4));
+        let stats = websocket_stats()["idle.ws-stats.test"].clone();
+        assert_eq!((stats.active, stats.closed_idle), (0, 1));
+    }
+}
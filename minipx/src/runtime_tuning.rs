@@ -0,0 +1,134 @@
+//! How many threads the proxy's tokio runtime and the web panel's actix server run with.
+//!
+//! Counts left unset follow the machine's cores, capped so a large box does not start dozens of idle threads. When the
+//! panel runs inside the proxy process (the `webui` build), the cores are split between the two instead of each
+//! claiming all of them. The counts are read from the config file before the runtime exists, so changing them takes
+//! a restart.
+
+use crate::config::RuntimeConfig;
+use log::{info, warn};
+use std::fmt;
+use std::path::Path;
+use tokio::runtime::{Builder, Runtime};
+
+/// Most proxy worker threads started without an explicit `worker_threads`
+pub const MAX_DEFAULT_WORKER_THREADS: usize = 32;
+/// Most web panel workers started without an explicit `web_workers`
+pub const MAX_DEFAULT_WEB_WORKERS: usize = 8;
+/// Blocking thread limit without an explicit `max_blocking_threads` (tokio's own default)
+pub const DEFAULT_MAX_BLOCKING_THREADS: usize = 512;
+/// Environment variable setting the web panel's worker count, over the config file's `web_workers`
+pub const WEB_WORKERS_ENV: &str = "MINIPX_WEB_WORKERS";
+
+/// Cores this process may use, at least 1
+pub fn available_cores() -> usize {
+    std::thread::available_parallelism().map(usize::from).unwrap_or(1)
+}
+
+/// The `runtime` settings of the config file at `path`, with `MINIPX_WEB_WORKERS` applied. A missing or unreadable
+/// file gives the defaults: the full load that follows reports what is wrong with it.
+pub fn read_config(path: impl AsRef<Path>) -> RuntimeConfig {
+    let mut runtime = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .and_then(|mut value| value.get_mut("runtime").map(serde_json::Value::take))
+        .and_then(|value| serde_json::from_value::<RuntimeConfig>(value).ok())
+        .unwrap_or_default();
+    if let Ok(value) = std::env::var(WEB_WORKERS_ENV) {
+        match value.trim().parse::<usize>() {
+            Ok(workers) if workers > 0 => runtime.set_web_workers(Some(workers)),
+            _ => warn!("Ignoring {}={:?}: not a positive number", WEB_WORKERS_ENV, value),
+        }
+    }
+    runtime
+}
+
+/// Effective thread counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeTuning {
+    pub worker_threads: usize,
+    pub max_blocking_threads: usize,
+    pub web_workers: usize,
+    /// Whether the web panel shares the process (and so the cores) with the proxy
+    pub embedded_panel: bool,
+}
+
+impl RuntimeTuning {
+    /// Counts for a process on `cores` cores. Explicit settings are taken as they are; with `embedded_panel`, the
+    /// defaults give the panel a quarter of the cores (1 to 4) and the proxy the rest.
+    pub fn resolve(config: &RuntimeConfig, cores: usize, embedded_panel: bool) -> Self {
+        let cores = cores.max(1);
+        let default_web_workers = if embedded_panel { (cores / 4).clamp(1, 4) } else { cores.min(MAX_DEFAULT_WEB_WORKERS) };
+        let web_workers = config.get_web_workers().unwrap_or(default_web_workers);
+        let proxy_cores = if embedded_panel { cores.saturating_sub(web_workers).max(1) } else { cores };
+        Self {
+            worker_threads: config.get_worker_threads().unwrap_or(proxy_cores.min(MAX_DEFAULT_WORKER_THREADS)),
+            max_blocking_threads: config.get_max_blocking_threads().unwrap_or(DEFAULT_MAX_BLOCKING_THREADS),
+            web_workers,
+            embedded_panel,
+        }
+    }
+
+    /// A multi-threaded runtime with these counts, all drivers enabled
+    pub fn build_runtime(&self) -> std::io::Result<Runtime> {
+        Builder::new_multi_thread().worker_threads(self.worker_threads).max_blocking_threads(self.max_blocking_threads).enable_all().build()
+    }
+
+    pub fn log(&self) {
+        info!("Runtime: {} ({} cores available)", self, available_cores());
+    }
+}
+
+impl fmt::Display for RuntimeTuning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} worker threads, up to {} blocking threads", self.worker_threads, self.max_blocking_threads)?;
+        if self.embedded_panel {
+            write!(f, ", {} web panel workers", self.web_workers)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_follow_the_cores_and_split_them_with_an_embedded_panel() {
+        let defaults = RuntimeConfig::default();
+        let small = RuntimeTuning::resolve(&defaults, 2, false);
+        assert_eq!((small.worker_threads, small.web_workers, small.max_blocking_threads), (2, 2, DEFAULT_MAX_BLOCKING_THREADS));
+        let large = RuntimeTuning::resolve(&defaults, 64, false);
+        assert_eq!((large.worker_threads, large.web_workers), (MAX_DEFAULT_WORKER_THREADS, MAX_DEFAULT_WEB_WORKERS));
+
+        for (cores, proxy, panel) in [(1, 1, 1), (2, 1, 1), (8, 6, 2), (64, MAX_DEFAULT_WORKER_THREADS, 4)] {
+            let tuning = RuntimeTuning::resolve(&defaults, cores, true);
+            assert_eq!((tuning.worker_threads, tuning.web_workers), (proxy, panel), "{} cores", cores);
+        }
+
+        let explicit = RuntimeConfig::new(Some(3), Some(16), Some(5));
+        let tuning = RuntimeTuning::resolve(&explicit, 2, true);
+        assert_eq!((tuning.worker_threads, tuning.max_blocking_threads, tuning.web_workers), (3, 16, 5));
+        assert_eq!(RuntimeTuning::resolve(&RuntimeConfig::new(None, None, Some(6)), 8, true).worker_threads, 2);
+    }
+
+    #[test]
+    fn test_the_runtime_is_built_with_the_resolved_counts() {
+        let tuning = RuntimeTuning::resolve(&RuntimeConfig::new(Some(3), Some(4), None), available_cores(), false);
+        let runtime = tuning.build_runtime().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 3);
+        assert_eq!(runtime.block_on(async { tokio::task::spawn_blocking(|| 42).await.unwrap() }), 42);
+    }
+
+    #[test]
+    fn test_settings_are_read_from_the_runtime_key() {
+        let dir = std::env::temp_dir().join(format!("minipx-runtime-tuning-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("minipx.json");
+        std::fs::write(&path, r#"{ "routes": {}, "runtime": { "worker_threads": 6, "max_blocking_threads": "many" } }"#).unwrap();
+        let runtime = read_config(&path);
+        assert_eq!((runtime.get_worker_threads(), runtime.get_max_blocking_threads()), (Some(6), None));
+        assert_eq!(read_config(dir.join("missing.json")).get_worker_threads(), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,23 +1,49 @@
-use crate::config::Config;
-use crate::proxy::request_handler::handle_request_with_scheme;
+use crate::acme_account;
+use crate::cert_resolver::{CertResolver, FallbackAction};
+use crate::config::manager::config_lock;
+use crate::config::{BindFailurePolicy, Config};
+use crate::handoff;
+use crate::proxy::bind;
+use crate::proxy::header_limits;
+use crate::proxy::health;
+use crate::proxy::request_handler::extract_host;
+use crate::proxy::service::MinipxService;
+use crate::proxy::tls_info::TlsInfo;
+use crate::proxy::unknown_host::{self, ConnectionClosed};
+use crate::systemd;
+use crate::tls_events::{self, SupervisorState};
 use anyhow::Result;
+use anyhow::anyhow;
+use hyper::header::{self, HeaderValue};
 use hyper::service::service_fn;
-use hyper::{Body, Request, Response};
-use log::{error, info, warn};
-use rustls_acme::AcmeConfig;
-use rustls_acme::caches::DirCache;
-use tokio::net::TcpListener;
-use tokio::sync::oneshot;
-use tokio_stream::StreamExt;
-use tokio_stream::wrappers::TcpListenerStream;
+use hyper::{Body, Request};
+use log::{debug, error, info, warn};
+use rustls_acme::acme::ACME_TLS_ALPN_NAME;
+use rustls_acme::futures_rustls::LazyConfigAcceptor;
+use rustls_acme::futures_rustls::rustls::ServerConfig;
+use rustls_acme::futures_rustls::rustls::crypto::aws_lc_rs;
+use rustls_acme::futures_rustls::rustls::server::Acceptor;
+use rustls_acme::is_tls_alpn_challenge;
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Notify, oneshot};
+use tokio_util::compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+
+// Wakes the serving supervisor for a restart that no config change would trigger
+static RESTART: OnceLock<Notify> = OnceLock::new();
 
 pub async fn start_ssl_server() -> Result<()> {
+    let monitor = tls_events::monitor();
+    tls_events::spawn_background_tasks();
     loop {
         let config = Config::get().await;
 
         // Respect global SSL enable flag
         if !config.is_ssl_enabled() {
+            monitor.set_state(SupervisorState::Disabled);
             warn!("SSL is disabled via config; HTTPS server will wait for enablement");
+            systemd::listener_settled("https");
             let mut updates = Config::subscribe();
             loop {
                 // Wait for a message from the config channel
@@ -39,7 +65,9 @@ pub async fn start_ssl_server() -> Result<()> {
 
         // Validate email (global)
         if !config.is_email_valid() {
+            monitor.set_state(SupervisorState::WaitingForEmail);
             warn!("Invalid ACME email in config; HTTPS server will wait for a valid email");
+            systemd::listener_settled("https");
             let mut updates = Config::subscribe();
             loop {
                 match updates.recv().await {
@@ -63,7 +91,9 @@ pub async fn start_ssl_server() -> Result<()> {
             warn!("Invalid ACME domains will be skipped: {:?}", invalid_domains);
         }
         if valid_domains.is_empty() {
+            monitor.set_state(SupervisorState::WaitingForDomains);
             warn!("No valid domains configured for ACME; HTTPS server will wait for config updates");
+            systemd::listener_settled("https");
             let mut updates = Config::subscribe();
             loop {
                 match updates.recv().await {
@@ -92,13 +122,42 @@ pub async fn start_ssl_server() -> Result<()> {
         if let Err(e) = tokio::fs::create_dir_all(&cache_dir).await {
             warn!("Failed to create cache_dir {}: {}", cache_dir, e);
         }
+        match acme_account::account_info(&cache_dir, &email) {
+            Ok(info) => {
+                if let Some(warning) = info.contact_warning() {
+                    warn!("{}", warning);
+                }
+            }
+            Err(e) => warn!("Failed to inspect the ACME account in {}: {}", cache_dir, e),
+        }
 
-        // Bind to [::]:443 (all interfaces)
-        let addr = (std::net::Ipv6Addr::UNSPECIFIED, 443);
-        let tcp_listener = match TcpListener::bind(addr).await {
-            Ok(l) => l,
+        // Bind to [::]:443 (all interfaces), unless a warm restart or systemd (socket activation) passed the socket
+        let addr = std::net::SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 443));
+        let bound = match handoff::inherited_listener("https").or_else(|| systemd::activated_listener("https")) {
+            Some(listener) => TcpListener::from_std(listener),
+            // Fail fast gives up here after bind_failure_attempts; retry waits for a config update below
+            None if bind::bind_failure_policy(&config) == BindFailurePolicy::Fail => {
+                let listener =
+                    bind::bind_with_policy("https", addr, BindFailurePolicy::Fail, config.get_bind_failure_attempts(), bind::RETRY_DELAY, || {
+                        let listener = std::net::TcpListener::bind(addr)?;
+                        listener.set_nonblocking(true)?;
+                        Ok(listener)
+                    })
+                    .await?;
+                TcpListener::from_std(listener)
+            }
+            None => TcpListener::bind(addr).await,
+        };
+        let tcp_listener = match bound {
+            Ok(l) => {
+                bind::bound("https");
+                l
+            }
             Err(e) => {
-                error!("Failed to bind HTTPS server on [::]:443: {}", e);
+                bind::bind_failed("https", addr, &e);
+                monitor.set_state(SupervisorState::WaitingForPort);
+                // HTTP keeps serving, so startup is not held up by the HTTPS port
+                systemd::listener_settled("https");
                 let mut updates = Config::subscribe();
                 loop {
                     match updates.recv().await {
@@ -115,92 +174,113 @@ pub async fn start_ssl_server() -> Result<()> {
                 continue;
             }
         };
-        let tcp_incoming = TcpListenerStream::new(tcp_listener);
+        let bound_addr = tcp_listener.local_addr()?;
+        health::register_listener("https", bound_addr);
+        handoff::register_listener("https", &tcp_listener);
+        systemd::listener_settled("https");
 
-        // Configure ACME with Let's Encrypt production directory and DirCache, build TLS incoming stream
-        let tls_incoming = AcmeConfig::new(valid_domains.clone())
-            .contact_push(format!("mailto:{}", email))
-            .cache(DirCache::new(cache_dir.clone()))
-            .directory_lets_encrypt(true)
-            .tokio_incoming(tcp_incoming, Vec::new());
+        // One ACME state per domain behind an SNI resolver, so domains can come and go without a restart
+        let resolver = CertResolver::new();
+        resolver.sync(&valid_domains, &email, &cache_dir);
+        monitor.set_domains(&resolver.domains());
+        resolver.set_fallback(config.get_tls_fallback(), config.get_tls_fallback_cert());
+        let (tls_config, challenge_config) = match tls_configs(resolver.clone()) {
+            Ok(configs) => configs,
+            Err(e) => {
+                health::unregister_listener("https", bound_addr);
+                handoff::unregister_listener("https");
+                return Err(e);
+            }
+        };
 
-        info!("HTTPS Server (ACME) running on [::]:443 for domains: {:?}", valid_domains);
+        info!("HTTPS Server (ACME) running on {} for domains: {:?}", bound_addr, valid_domains);
+        monitor.set_state(SupervisorState::Serving);
 
         // Set up the graceful shutdown
         let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
 
-        // Service factory for HTTPS requests
-        let make_svc = |remote_ip: std::net::IpAddr, req: Request<Body>| async move {
-            match handle_request_with_scheme("https", remote_ip, req).await {
-                Ok(resp) => Ok::<Response<Body>, std::convert::Infallible>(resp),
-                Err(e) => {
-                    error!("HTTPS handle_request error from {}: {}", remote_ip, e);
-                    Ok::<Response<Body>, std::convert::Infallible>(Response::new(Body::empty()))
-                }
-            }
-        };
-
-        // Spawn accept loop (own the stream inside the task)
+        // Spawn accept loop (own the listener inside the task)
+        let accept_resolver = resolver.clone();
         let server_task = tokio::spawn(async move {
-            let mut tls_incoming = tls_incoming;
             let mut shutdown_rx = shutdown_rx;
             loop {
                 tokio::select! {
                     _ = &mut shutdown_rx => {
                         break;
                     }
-                    incoming = tls_incoming.next() => {
-                        match incoming {
-                            Some(Ok(tls)) => {
-                                // Peer address is not available via high-level API; fall back to loopback for logging/XFF
-                                let client_ip = std::net::IpAddr::from([127,0,0,1]);
-                                tokio::spawn(async move {
-                                    let service = service_fn(move |req| make_svc(client_ip, req));
-                                    let mut http = hyper::server::conn::Http::new();
-                                    http.http1_only(true);
-                                    http.http1_keep_alive(true);
-                                    let conn = http.serve_connection(tls, service).with_upgrades();
-                                    if let Err(e) = conn.await {
-                                        error!("HTTPS connection error: {}", e);
-                                    }
-                                });
+                    // The socket now belongs to the process a warm restart handed over to
+                    _ = handoff::handed_off() => {
+                        break;
+                    }
+                    accepted = tcp_listener.accept() => {
+                        match accepted {
+                            Ok((tcp, peer)) => {
+                                let (tls_config, challenge_config) = (tls_config.clone(), challenge_config.clone());
+                                tokio::spawn(serve_connection(tcp, peer.ip(), accept_resolver.clone(), tls_config, challenge_config));
                             }
-                            Some(Err(e)) => {
-                                warn!("TLS incoming error: {}", e);
+                            Err(e) => {
+                                warn!("HTTPS accept error: {}", e);
                                 tokio::time::sleep(std::time::Duration::from_millis(200)).await;
                             }
-                            None => {
-                                warn!("TLS incoming stream ended");
-                                break;
-                            }
                         }
                     }
                 }
             }
         });
 
-        // Watch for config updates that require restart (domains, email, cache_dir)
+        // Domain changes are applied in place; only email/cache_dir changes (or disabling SSL) need a restart,
+        // as does a replaced ACME account key (`request_restart`)
         let mut updates = Config::subscribe();
         loop {
-            match updates.recv().await {
+            let update = tokio::select! {
+                update = updates.recv() => update,
+                // Connections in progress finish on their own; this process is draining
+                _ = handoff::handed_off() => {
+                    let _ = server_task.await;
+                    health::unregister_listener("https", bound_addr);
+                    handoff::unregister_listener("https");
+                    return Ok(());
+                }
+                _ = restart_signal().notified() => {
+                    info!("HTTPS restart requested; restarting HTTPS server to reload the ACME account");
+                    let _ = shutdown_tx.send(());
+                    let _ = server_task.await;
+                    health::unregister_listener("https", bound_addr);
+                    handoff::unregister_listener("https");
+                    break;
+                }
+            };
+            match update {
                 Ok(updated) => {
-                    let (new_valid, _new_invalid) = updated.get_valid_domains_for_acme();
                     let should_restart = !updated.is_ssl_enabled()
                         || !updated.is_email_valid()
-                        || new_valid != valid_domains
                         || *updated.get_email() != email
                         || *updated.get_cache_dir() != cache_dir;
                     if should_restart {
                         info!("SSL config changed; restarting HTTPS server to apply updates");
                         let _ = shutdown_tx.send(());
                         let _ = server_task.await;
+                        health::unregister_listener("https", bound_addr);
+                        handoff::unregister_listener("https");
                         break;
                     }
+                    resolver.set_fallback(updated.get_tls_fallback(), updated.get_tls_fallback_cert());
+                    let (new_valid, new_invalid) = updated.get_valid_domains_for_acme();
+                    let (added, removed) = resolver.sync(&new_valid, &email, &cache_dir);
+                    monitor.set_domains(&resolver.domains());
+                    if !added.is_empty() || !removed.is_empty() {
+                        if !new_invalid.is_empty() {
+                            warn!("Invalid ACME domains will be skipped: {:?}", new_invalid);
+                        }
+                        info!("HTTPS domains updated without restart: added {:?}, removed {:?}", added, removed);
+                    }
                 }
                 Err(tokio::sync::broadcast::error::RecvError::Closed) => {
                     warn!("Config update channel closed; stopping HTTPS server supervisor");
                     let _ = shutdown_tx.send(());
                     let _ = server_task.await;
+                    health::unregister_listener("https", bound_addr);
+                    handoff::unregister_listener("https");
                     return Ok(());
                 }
                 Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
@@ -210,3 +290,135 @@ pub async fn start_ssl_server() -> Result<()> {
         }
     }
 }
+
+fn restart_signal() -> &'static Notify {
+    RESTART.get_or_init(Notify::new)
+}
+
+/// Ask a serving HTTPS server to restart, reloading every domain's ACME state (and so the account key) from
+/// cache_dir. Returns false if it is not serving, in which case the next start picks the change up anyway.
+pub fn request_restart() -> bool {
+    if tls_events::tls_status().state != SupervisorState::Serving {
+        return false;
+    }
+    restart_signal().notify_one();
+    true
+}
+
+// Regular connections and TLS-ALPN-01 validation connections share the resolver but not the ALPN list
+fn tls_configs(resolver: Arc<CertResolver>) -> Result<(Arc<ServerConfig>, Arc<ServerConfig>)> {
+    let tls_config = ServerConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| anyhow!("Failed to build TLS config: {}", e))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    let mut challenge_config = tls_config.clone();
+    challenge_config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+    Ok((Arc::new(tls_config), Arc::new(challenge_config)))
+}
+
+async fn serve_connection(
+    tcp: TcpStream,
+    remote_ip: IpAddr,
+    resolver: Arc<CertResolver>,
+    tls_config: Arc<ServerConfig>,
+    challenge_config: Arc<ServerConfig>,
+) {
+    let start = match LazyConfigAcceptor::new(Acceptor::default(), tcp.compat()).await {
+        Ok(start) => start,
+        Err(e) => {
+            debug!("TLS handshake from {} failed: {}", remote_ip, e);
+            tls_events::monitor().handshake_failed();
+            return;
+        }
+    };
+    if is_tls_alpn_challenge(&start.client_hello()) {
+        info!("Received TLS-ALPN-01 validation request for {:?}", start.client_hello().server_name());
+        if let Err(e) = start.into_stream(challenge_config).await {
+            warn!("TLS-ALPN-01 validation handshake failed: {}", e);
+            tls_events::monitor().handshake_failed();
+        }
+        return;
+    }
+
+    // Missing or unknown SNI: follow tls_fallback
+    let server_name = start.client_hello().server_name().map(str::to_string);
+    let fallback = resolver.unmatched(server_name.as_deref());
+    let requested = server_name.as_deref().unwrap_or("<no SNI>");
+    match &fallback {
+        // Scanners probe with unknown names all the time, so rejections are logged at most once per client and interval
+        Some(FallbackAction::Reject) => {
+            unknown_host::reject_handshake(config_lock().read().await.get_unknown_host(), remote_ip, server_name.as_deref())
+        }
+        Some(FallbackAction::DefaultCert) => debug!("Serving the default certificate to {} for {}", remote_ip, requested),
+        Some(FallbackAction::Route(domain)) => debug!("Routing TLS connection from {} for {} to {}", remote_ip, requested, domain),
+        None => {}
+    }
+    let (tls, tls_info) = match start.into_stream(tls_config).await {
+        Ok(tls) => {
+            let tls_info = TlsInfo::from_connection(tls.get_ref().1);
+            debug!("TLS session from {}: {}", remote_ip, tls_info);
+            (tls.compat(), tls_info)
+        }
+        // Without a certificate the handshake fails with an alert to the client; already logged above
+        Err(_) if fallback == Some(FallbackAction::Reject) => return,
+        Err(e) => {
+            warn!("TLS accept from {} failed: {}", remote_ip, e);
+            tls_events::monitor().handshake_failed();
+            return;
+        }
+    };
+    let fallback_route = match fallback {
+        Some(FallbackAction::Route(domain)) => Some(domain),
+        _ => None,
+    };
+
+    let minipx = MinipxService::new("https").for_peer(remote_ip);
+    let service = service_fn(move |mut req: Request<Body>| {
+        let fallback_route = fallback_route.clone();
+        let minipx = minipx.clone();
+        req.extensions_mut().insert(tls_info.clone());
+        async move {
+            if let Some(domain) = &fallback_route {
+                route_unknown_host(&mut req, domain).await;
+            }
+            minipx.handle(req).await
+        }
+    });
+    let mut http = hyper::server::conn::Http::new();
+    http.http1_only(true);
+    http.http1_keep_alive(true);
+    // Header blocks larger than any route accepts are answered with 431 before they are buffered in full
+    http.max_buf_size(header_limits::connection_buffer_size(config_lock().read().await.largest_header_limit()));
+    let connection = http.serve_connection(tls, service).with_upgrades();
+    tokio::pin!(connection);
+    // After a warm restart, finish the request in progress and close instead of waiting for the next one
+    let result = tokio::select! {
+        result = &mut connection => result,
+        _ = handoff::handed_off() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(e) = result {
+        // Dropping the connection for an unknown host is deliberate (and already logged, aggregated per client)
+        if !std::error::Error::source(&e).is_some_and(|cause| cause.is::<ConnectionClosed>()) {
+            error!("HTTPS connection error: {}", e);
+        }
+    }
+}
+
+// On a connection accepted through `tls_fallback: {"route": ...}`, requests for hosts without a route go to that route
+async fn route_unknown_host(req: &mut Request<Body>, domain: &str) {
+    // A lookup through the read lock; cloning the config here would copy it for every fallback-routed request
+    let unknown = match extract_host(req) {
+        Some(host) => config_lock().read().await.lookup_host(host).is_none(),
+        None => true,
+    };
+    if unknown {
+        #[allow(clippy::collapsible_if)]
+        if let Ok(value) = HeaderValue::from_str(domain) {
+            req.headers_mut().insert(header::HOST, value);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! The startup summary: what a bug report needs to know about an instance (version, config file, routes, ACME
+//! domains, listeners), logged once the frontends have settled and printed by `minipx --print-config-summary`.
+//!
+//! It is built from the config's non-secret settings only; tokens and keys never appear in it.
+
+use crate::config::{Config, ConfigSource};
+use crate::proxy::bind::unbound_frontends;
+use crate::proxy::forwarder::{BindingState, forwarder_statuses};
+use crate::proxy::health::bound_listeners;
+use log::info;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Commit minipx was built from, when built from a git checkout
+pub const GIT_HASH: Option<&str> = option_env!("MINIPX_GIT_HASH");
+
+/// The effective configuration of an instance, as logged at startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupSummary {
+    pub version: String,
+    pub git_hash: Option<String>,
+    pub config_path: String,
+    pub config_source: ConfigSource,
+    pub routes: usize,
+    pub ssl_routes: usize,
+    pub acme_domains: Vec<String>,
+    /// Domains of SSL routes no certificate can be requested for (wildcards, malformed names)
+    pub invalid_acme_domains: Vec<String>,
+    /// Ports the config asks for, e.g. "http 80", "https 443", "tcp+udp 25565"
+    pub ports: Vec<String>,
+    /// Listeners bound right now; None when the servers were not started
+    pub listeners: Option<Vec<String>>,
+    /// Listeners that failed to bind and are still retrying
+    pub unbound: Vec<String>,
+    pub cache_dir: String,
+    pub watch_config: bool,
+    pub ipc: bool,
+}
+
+impl StartupSummary {
+    /// Summary of `config`, without listener state (nothing has been started)
+    pub fn new(config: &Config, config_source: ConfigSource, watch_config: bool, ipc: bool) -> Self {
+        let routes = config.get_routes();
+        let (acme_domains, invalid_acme_domains) = config.get_valid_domains_for_acme();
+        let mut ports = vec!["http 80".to_string()];
+        if config.is_ssl_enabled() {
+            ports.push("https 443".to_string());
+        }
+        if let Some(port) = config.get_health_endpoint().get_port().filter(|_| config.get_health_endpoint().is_enabled()) {
+            ports.push(format!("health {}", port));
+        }
+        let forwarded: BTreeSet<u16> = routes.values().filter_map(|route| route.get_listen_port()).filter(|port| ![80, 443].contains(port)).collect();
+        ports.extend(forwarded.iter().map(|port| format!("tcp+udp {}", port)));
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_hash: GIT_HASH.map(str::to_string),
+            config_path: config.get_path().display().to_string(),
+            config_source,
+            routes: routes.len(),
+            ssl_routes: routes.values().filter(|route| route.is_ssl_enabled()).count(),
+            acme_domains,
+            invalid_acme_domains,
+            ports,
+            listeners: None,
+            unbound: Vec::new(),
+            cache_dir: config.get_cache_dir().clone(),
+            watch_config,
+            ipc,
+        }
+    }
+
+    /// Fill in the listeners bound (forwarders included) and failing to bind right now
+    pub fn with_listeners(mut self) -> Self {
+        let listeners = bound_listeners();
+        let mut unbound: Vec<String> =
+            unbound_frontends().iter().map(|frontend| format!("{} {} ({})", frontend.kind, frontend.port, frontend.last_error)).collect();
+        for forwarder in forwarder_statuses() {
+            let name = format!("{} {}", forwarder.protocol, forwarder.listen_port);
+            match forwarder.state {
+                BindingState::Bound => {}
+                BindingState::Retrying { last_error, .. } => unbound.push(format!("{} (retrying: {})", name, last_error)),
+                BindingState::Disabled { reason } => unbound.push(format!("{} (disabled: {})", name, reason)),
+            }
+        }
+        self.listeners = Some(listeners);
+        self.unbound = unbound;
+        self
+    }
+
+    /// The summary as `key: value` lines
+    pub fn lines(&self) -> Vec<String> {
+        let list = |items: &[String]| if items.is_empty() { "none".to_string() } else { items.join(", ") };
+        let yes_no = |on: bool| if on { "on" } else { "off" };
+        let mut lines = vec![
+            format!("version: {}{}", self.version, self.git_hash.as_ref().map(|hash| format!(" ({})", hash)).unwrap_or_default()),
+            format!("config: {} (from {})", self.config_path, self.config_source),
+            format!("routes: {} ({} with SSL)", self.routes, self.ssl_routes),
+            format!("acme domains: {}", list(&self.acme_domains)),
+        ];
+        if !self.invalid_acme_domains.is_empty() {
+            lines.push(format!("skipped acme domains: {}", list(&self.invalid_acme_domains)));
+        }
+        lines.push(format!("ports: {}", list(&self.ports)));
+        match &self.listeners {
+            Some(listeners) => lines.push(format!("listening: {}", list(listeners))),
+            None => lines.push("listening: not started".to_string()),
+        }
+        if !self.unbound.is_empty() {
+            lines.push(format!("not bound: {}", list(&self.unbound)));
+        }
+        lines.push(format!("cache_dir: {}", self.cache_dir));
+        lines.push(format!("watch: {}, ipc: {}", yes_no(self.watch_config), yes_no(self.ipc)));
+        lines
+    }
+
+    /// Log the summary, one info line per item
+    pub fn log(&self) {
+        info!("Startup summary:");
+        for line in self.lines() {
+            info!("  {}", line);
+        }
+    }
+}
+
+impl fmt::Display for StartupSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in self.lines() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AdminApiConfig, ProxyRoute};
+
+    #[tokio::test]
+    async fn test_summary_counts_routes_and_ports_without_secrets() {
+        let mut config = Config::new(std::env::temp_dir().join("minipx-startup-summary").join("minipx.json"));
+        config.set_email("admin@example.com".to_string());
+        config.routes.insert("app.example.com".to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 3000, true, None, true));
+        config.routes.insert("*.example.org".to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 3001, true, None, false));
+        config
+            .routes
+            .insert("game.example.com".to_string(), ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), 25565, false, Some(25565), false));
+        config.set_admin_api(AdminApiConfig::new(true, 9180, "s3cr3t-token".to_string()));
+
+        let summary = StartupSummary::new(&config, ConfigSource::CommandLine, true, false);
+        assert_eq!((summary.routes, summary.ssl_routes), (3, 2));
+        assert_eq!(summary.acme_domains, ["app.example.com"]);
+        assert_eq!(summary.invalid_acme_domains, ["*.example.org"]);
+        assert_eq!(summary.ports, ["http 80", "https 443", "tcp+udp 25565"]);
+
+        let text = summary.to_string();
+        assert!(text.contains("minipx-startup-summary/minipx.json (from --config)"), "{}", text);
+        assert!(text.contains("listening: not started") && text.contains("watch: on, ipc: off"), "{}", text);
+        assert!(!text.contains("s3cr3t-token") && !text.contains("admin@example.com"), "{}", text);
+    }
+}
@@ -0,0 +1,216 @@
+//! systemd integration: `Type=notify` readiness, reload notifications and socket activation.
+//!
+//! Notifications and socket activation need the `systemd` feature on a unix target; elsewhere they are
+//! no-ops and every listener is bound by the proxy itself. The reference unit files are always available.
+
+use log::{debug, warn};
+use std::collections::{BTreeSet, HashMap};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::Notify;
+
+// Frontends that must be bound (or deliberately waiting, like HTTPS without SSL) before READY=1 is sent
+const REQUIRED_LISTENERS: &[&str] = &["http", "https"];
+
+static SETTLED: OnceLock<Mutex<BTreeSet<&'static str>>> = OnceLock::new();
+static READY: AtomicBool = AtomicBool::new(false);
+static ALL_SETTLED: Notify = Notify::const_new();
+static ACTIVATED: OnceLock<Mutex<HashMap<String, TcpListener>>> = OnceLock::new();
+
+/// Record that a frontend listener is bound, or has settled into waiting for config; once all of them have,
+/// the service manager is told the proxy is ready
+pub fn listener_settled(kind: &'static str) {
+    let mut settled = SETTLED.get_or_init(|| Mutex::new(BTreeSet::new())).lock().unwrap();
+    settled.insert(kind);
+    if REQUIRED_LISTENERS.iter().all(|k| settled.contains(k)) && !READY.swap(true, Ordering::AcqRel) {
+        debug!("All listeners settled, notifying readiness");
+        notify(&["READY=1", "STATUS=Proxying"]);
+        ALL_SETTLED.notify_waiters();
+    }
+}
+
+/// Wait until every frontend listener has settled (the point READY=1 is sent)
+pub async fn listeners_settled() {
+    loop {
+        let settled = ALL_SETTLED.notified();
+        if READY.load(Ordering::Acquire) {
+            return;
+        }
+        settled.await;
+    }
+}
+
+/// Show `message` as the service's status line (e.g. in `systemctl status`)
+pub fn status(message: &str) {
+    notify(&[&format!("STATUS={}", message)]);
+}
+
+/// Tell the service manager that `pid` is the service's main process now (after a warm restart handed over to
+/// it); needs `NotifyAccess=all`, as in the reference unit
+pub fn main_pid(pid: u32) {
+    notify(&[&format!("MAINPID={}", pid)]);
+}
+
+/// Tell the service manager a config reload is starting
+pub fn reloading() {
+    notify(&["RELOADING=1", &monotonic_usec()]);
+}
+
+/// Tell the service manager a config reload has finished (successfully or not)
+pub fn reloaded() {
+    // Before the initial READY=1 a reload is part of startup; readiness is still pending
+    if READY.load(Ordering::Acquire) {
+        notify(&["READY=1"]);
+    }
+}
+
+/// A listener passed by the service manager for `kind` ("http" or "https"), if socket-activated.
+///
+/// Sockets are matched by `FileDescriptorName=`; unnamed sockets are assigned in order (first http, then https).
+/// The adopted socket stays owned here and a duplicate is returned, so a server restarting its accept loop
+/// gets the same socket again.
+pub fn activated_listener(kind: &str) -> Option<TcpListener> {
+    let listeners = ACTIVATED.get_or_init(|| Mutex::new(adopt_listen_fds())).lock().unwrap();
+    let listener = listeners.get(kind)?;
+    match listener.try_clone() {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            warn!("Failed to duplicate socket-activated {} listener: {}", kind, e);
+            None
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "systemd"))]
+fn notify(states: &[&str]) {
+    use sd_notify::NotifyState;
+    let states: Vec<NotifyState> = states.iter().filter(|s| !s.is_empty()).map(|s| NotifyState::Custom(s)).collect();
+    if let Err(e) = sd_notify::notify(false, &states) {
+        debug!("sd_notify failed: {}", e);
+    }
+}
+
+#[cfg(not(all(unix, feature = "systemd")))]
+fn notify(_states: &[&str]) {}
+
+#[cfg(all(unix, feature = "systemd"))]
+fn adopt_listen_fds() -> HashMap<String, TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    let fds: Vec<(i32, String)> = match sd_notify::listen_fds_with_names(true) {
+        Ok(fds) => fds.collect(),
+        Err(e) => {
+            warn!("Ignoring socket activation: {}", e);
+            return HashMap::new();
+        }
+    };
+    let mut listeners = HashMap::new();
+    let mut unnamed = REQUIRED_LISTENERS.iter();
+    for (fd, name) in fds {
+        let kind = match name.as_str() {
+            "http" | "https" => name,
+            _ => match unnamed.find(|k| !listeners.contains_key(**k)) {
+                Some(kind) => kind.to_string(),
+                None => {
+                    warn!("Ignoring extra socket-activated fd {} ({})", fd, name);
+                    continue;
+                }
+            },
+        };
+        // SAFETY: systemd hands these descriptors to this process; each is adopted exactly once
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        if let Err(e) = listener.set_nonblocking(true) {
+            warn!("Failed to make socket-activated fd {} non-blocking: {}", fd, e);
+            continue;
+        }
+        match listener.local_addr() {
+            Ok(addr) => log::info!("Adopted socket-activated {} listener on {}", kind, addr),
+            Err(e) => warn!("Socket-activated fd {} is not a TCP listener: {}", fd, e),
+        }
+        listeners.insert(kind, listener);
+    }
+    listeners
+}
+
+#[cfg(not(all(unix, feature = "systemd")))]
+fn adopt_listen_fds() -> HashMap<String, TcpListener> {
+    HashMap::new()
+}
+
+fn monotonic_usec() -> String {
+    #[cfg(all(unix, feature = "systemd"))]
+    if let Ok(sd_notify::NotifyState::MonotonicUsec(usec)) = sd_notify::NotifyState::monotonic_usec_now() {
+        return format!("MONOTONIC_USEC={}", usec);
+    }
+    String::new()
+}
+
+/// Reference `minipx.service` unit running `exe` with the given config file
+pub fn service_unit(exe: &Path, config_path: &Path) -> String {
+    format!(
+        "[Unit]
+Description=minipx reverse proxy
+Documentation=https://github.com/Drew-Chase/minipx
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=notify
+ExecStart={exe} --config {config} --watch
+Restart=on-failure
+# Lets the process started by `minipx upgrade` (warm restart) take over as the main process
+NotifyAccess=all
+RestartSec=2
+# Bind ports 80/443 without running as root (not needed with minipx.socket)
+AmbientCapabilities=CAP_NET_BIND_SERVICE
+CapabilityBoundingSet=CAP_NET_BIND_SERVICE
+NoNewPrivileges=true
+
+[Install]
+WantedBy=multi-user.target
+",
+        exe = exe.display(),
+        config = config_path.display()
+    )
+}
+
+/// Reference `minipx.socket` unit for socket activation: systemd binds 80 and 443 and passes them in order
+pub fn socket_unit() -> String {
+    "[Unit]
+Description=minipx reverse proxy sockets
+
+[Socket]
+ListenStream=80
+ListenStream=443
+Service=minipx.service
+
+[Install]
+WantedBy=sockets.target
+"
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_unit_fills_in_paths() {
+        let unit = service_unit(Path::new("/usr/local/bin/minipx"), Path::new("/etc/minipx/minipx.json"));
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains("ExecStart=/usr/local/bin/minipx --config /etc/minipx/minipx.json --watch\n"));
+    }
+
+    #[test]
+    fn test_socket_unit_orders_http_first() {
+        let unit = socket_unit();
+        assert!(unit.find("ListenStream=80").unwrap() < unit.find("ListenStream=443").unwrap());
+    }
+
+    #[test]
+    fn test_no_activated_listeners_without_listen_fds() {
+        assert!(activated_listener("http").is_none());
+    }
+}
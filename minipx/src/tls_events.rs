@@ -0,0 +1,424 @@
+//! Lifecycle events and metrics of the HTTPS/ACME subsystem.
+//!
+//! The HTTPS supervisor and the per-domain ACME tasks report into a process-wide `TlsMonitor`: the supervisor
+//! state, certificate expiry per domain, renewal outcomes and failed handshakes. The monitor's snapshot backs
+//! the Prometheus endpoint and the IPC `status`/`certs list` commands, and its events are broadcast to
+//! in-process subscribers. Renewal failures and certificates entering the expiry window are also POSTed to
+//! `alert_webhook` when one is configured.
+
+use crate::config::Config;
+use hyper::{Body, Client, Method, Request, header};
+use hyper_tls::HttpsConnector;
+use log::{debug, info, warn};
+use rustls_acme::futures_rustls::pki_types::CertificateDer;
+use rustls_acme::futures_rustls::pki_types::pem::PemObject;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Certificates closer than this to expiry raise an `ExpiringSoon` alert
+pub const EXPIRY_WARNING_DAYS: i64 = 14;
+
+// How often certificates are checked against the expiry window
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+static MONITOR: OnceLock<TlsMonitor> = OnceLock::new();
+static BACKGROUND: OnceLock<()> = OnceLock::new();
+
+/// What the HTTPS supervisor is currently doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SupervisorState {
+    #[default]
+    Starting,
+    /// `ssl_enabled` is off
+    Disabled,
+    WaitingForEmail,
+    WaitingForDomains,
+    /// Port 443 could not be bound; retried on the next config change
+    WaitingForPort,
+    Serving,
+}
+
+impl SupervisorState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SupervisorState::Starting => "starting",
+            SupervisorState::Disabled => "disabled",
+            SupervisorState::WaitingForEmail => "waiting_for_email",
+            SupervisorState::WaitingForDomains => "waiting_for_domains",
+            SupervisorState::WaitingForPort => "waiting_for_port",
+            SupervisorState::Serving => "serving",
+        }
+    }
+}
+
+/// Certificate and renewal state of one domain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertStatus {
+    pub domain: String,
+    /// Expiry of the deployed certificate (unix seconds); None until one is loaded or issued
+    pub not_after: Option<u64>,
+    pub days_to_expiry: Option<i64>,
+    /// Finished certificate orders (successful or not) since startup
+    pub renewals_attempted: u64,
+    pub renewals_succeeded: u64,
+    pub renewals_failed: u64,
+    /// Class of the most recent error (e.g. `order:bad_auth`), cleared by a successful renewal
+    pub last_error: Option<String>,
+}
+
+/// Snapshot of the HTTPS subsystem
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsStatus {
+    pub state: SupervisorState,
+    pub handshake_errors: u64,
+    pub certificates: Vec<CertStatus>,
+}
+
+/// A lifecycle event of the HTTPS subsystem
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TlsEvent {
+    StateChanged { state: SupervisorState },
+    CertificateIssued { domain: String, not_after: u64 },
+    RenewalFailed { domain: String, error: String },
+    ExpiringSoon { domain: String, not_after: u64, days_to_expiry: i64 },
+}
+
+impl TlsEvent {
+    /// Events that need an operator's attention (sent to the alert webhook)
+    pub fn is_alert(&self) -> bool {
+        matches!(self, TlsEvent::RenewalFailed { .. } | TlsEvent::ExpiringSoon { .. })
+    }
+}
+
+#[derive(Debug, Default)]
+struct CertRecord {
+    not_after: Option<u64>,
+    succeeded: u64,
+    failed: u64,
+    last_error: Option<String>,
+    // Set once ExpiringSoon was sent for the current certificate
+    expiry_alerted: bool,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    state: SupervisorState,
+    certs: BTreeMap<String, CertRecord>,
+}
+
+#[derive(Debug)]
+pub struct TlsMonitor {
+    inner: Mutex<MonitorState>,
+    handshake_errors: AtomicU64,
+    events: broadcast::Sender<TlsEvent>,
+}
+
+impl Default for TlsMonitor {
+    fn default() -> Self {
+        Self { inner: Mutex::default(), handshake_errors: AtomicU64::new(0), events: broadcast::channel(64).0 }
+    }
+}
+
+impl TlsMonitor {
+    pub fn subscribe(&self) -> broadcast::Receiver<TlsEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: TlsEvent) {
+        let json = serde_json::to_string(&event).unwrap_or_default();
+        if event.is_alert() {
+            warn!("TLS event: {}", json);
+        } else {
+            info!("TLS event: {}", json);
+        }
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    pub fn set_state(&self, state: SupervisorState) {
+        let changed = {
+            let mut inner = self.inner.lock().unwrap();
+            std::mem::replace(&mut inner.state, state) != state
+        };
+        if changed {
+            self.emit(TlsEvent::StateChanged { state });
+        }
+    }
+
+    /// Track exactly `domains`; domains no longer served are forgotten
+    pub fn set_domains(&self, domains: &[String]) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.certs.retain(|domain, _| domains.contains(domain));
+        for domain in domains {
+            inner.certs.entry(domain.clone()).or_default();
+        }
+    }
+
+    /// A certificate was deployed for `domain`: loaded from the cache, or freshly `issued`
+    pub fn certificate_deployed(&self, domain: &str, not_after: u64, issued: bool, now: u64) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let record = inner.certs.entry(domain.to_string()).or_default();
+            if record.not_after != Some(not_after) {
+                record.expiry_alerted = false;
+            }
+            record.not_after = Some(not_after);
+        }
+        if issued {
+            self.emit(TlsEvent::CertificateIssued { domain: domain.to_string(), not_after });
+        }
+        self.check_expiry(now);
+    }
+
+    pub fn renewal_succeeded(&self, domain: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let record = inner.certs.entry(domain.to_string()).or_default();
+        record.succeeded += 1;
+        record.last_error = None;
+    }
+
+    /// A certificate order for `domain` failed with `error` (its class, e.g. `order:acme`)
+    pub fn renewal_failed(&self, domain: &str, error: &str) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let record = inner.certs.entry(domain.to_string()).or_default();
+            record.failed += 1;
+            record.last_error = Some(error.to_string());
+        }
+        self.emit(TlsEvent::RenewalFailed { domain: domain.to_string(), error: error.to_string() });
+    }
+
+    /// A cache or parse error that is not a failed order; shown as the domain's last error only
+    pub fn record_error(&self, domain: &str, error: &str) {
+        self.inner.lock().unwrap().certs.entry(domain.to_string()).or_default().last_error = Some(error.to_string());
+    }
+
+    pub fn handshake_failed(&self) {
+        self.handshake_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Alert once for each certificate that has entered the expiry window
+    pub fn check_expiry(&self, now: u64) {
+        let mut expiring = Vec::new();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            for (domain, record) in inner.certs.iter_mut() {
+                let Some(not_after) = record.not_after else { continue };
+                let days = days_until(not_after, now);
+                if days < EXPIRY_WARNING_DAYS && !record.expiry_alerted {
+                    record.expiry_alerted = true;
+                    expiring.push(TlsEvent::ExpiringSoon { domain: domain.clone(), not_after, days_to_expiry: days });
+                }
+            }
+        }
+        for event in expiring {
+            self.emit(event);
+        }
+    }
+
+    pub fn status(&self, now: u64) -> TlsStatus {
+        let inner = self.inner.lock().unwrap();
+        TlsStatus {
+            state: inner.state,
+            handshake_errors: self.handshake_errors.load(Ordering::Relaxed),
+            certificates: inner
+                .certs
+                .iter()
+                .map(|(domain, record)| CertStatus {
+                    domain: domain.clone(),
+                    not_after: record.not_after,
+                    days_to_expiry: record.not_after.map(|not_after| days_until(not_after, now)),
+                    renewals_attempted: record.succeeded + record.failed,
+                    renewals_succeeded: record.succeeded,
+                    renewals_failed: record.failed,
+                    last_error: record.last_error.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The process-wide monitor
+pub fn monitor() -> &'static TlsMonitor {
+    MONITOR.get_or_init(TlsMonitor::default)
+}
+
+/// Snapshot of the process-wide monitor
+pub fn tls_status() -> TlsStatus {
+    monitor().status(now_secs())
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Whole days from `now` until `not_after` (both unix seconds), rounded down; negative once expired
+pub fn days_until(not_after: u64, now: u64) -> i64 {
+    (not_after as i64 - now as i64).div_euclid(24 * 60 * 60)
+}
+
+/// Expiry (unix seconds) of the first certificate in a PEM bundle; keys and other blocks are skipped
+pub fn not_after_from_pem(pem: &[u8]) -> Option<u64> {
+    let der = CertificateDer::pem_slice_iter(pem).find_map(|cert| cert.ok())?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&der).ok()?;
+    u64::try_from(cert.validity().not_after.timestamp()).ok()
+}
+
+/// Start the hourly expiry check and the alert webhook sender (once per process)
+pub fn spawn_background_tasks() {
+    if BACKGROUND.set(()).is_err() {
+        return;
+    }
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            monitor().check_expiry(now_secs());
+        }
+    });
+    let mut events = monitor().subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) if event.is_alert() => {
+                    if let Some(url) = Config::get().await.get_alert_webhook() {
+                        send_webhook(url, &event).await;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => warn!("Missed {} TLS events for the alert webhook", n),
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+async fn send_webhook(url: &str, event: &TlsEvent) {
+    let body = serde_json::json!({ "source": "minipx", "event": event }).to_string();
+    let request = match Request::builder().method(Method::POST).uri(url).header(header::CONTENT_TYPE, "application/json").body(Body::from(body)) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Invalid alert_webhook '{}': {}", url, e);
+            return;
+        }
+    };
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    match tokio::time::timeout(WEBHOOK_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => debug!("Alert webhook accepted {:?}", event),
+        Ok(Ok(response)) => warn!("Alert webhook {} answered {}", url, response.status()),
+        Ok(Err(e)) => warn!("Alert webhook {} failed: {}", url, e),
+        Err(_) => warn!("Alert webhook {} timed out", url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+    const NOW: u64 = 1_760_000_000;
+
+    fn drain(events: &mut broadcast::Receiver<TlsEvent>) -> Vec<TlsEvent> {
+        std::iter::from_fn(|| events.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn test_days_until_rounds_down_and_goes_negative() {
+        assert_eq!(days_until(NOW + 30 * DAY, NOW), 30);
+        assert_eq!(days_until(NOW + 14 * DAY - 1, NOW), 13);
+        assert_eq!(days_until(NOW, NOW), 0);
+        assert_eq!(days_until(NOW - 1, NOW), -1);
+        assert_eq!(days_until(NOW - 2 * DAY, NOW), -2);
+    }
+
+    #[test]
+    fn test_not_after_from_pem_reads_the_certificate_not_the_key() {
+        let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(2030, 1, 2);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        // rustls-acme caches the private key first, then the chain
+        let bundle = format!("{}\n{}", key.serialize_pem(), cert.pem());
+        assert_eq!(not_after_from_pem(bundle.as_bytes()), Some(1_893_542_400));
+        assert_eq!(not_after_from_pem(key.serialize_pem().as_bytes()), None);
+    }
+
+    #[test]
+    fn test_state_changes_are_emitted_once() {
+        let monitor = TlsMonitor::default();
+        let mut events = monitor.subscribe();
+        monitor.set_state(SupervisorState::WaitingForEmail);
+        monitor.set_state(SupervisorState::WaitingForEmail);
+        monitor.set_state(SupervisorState::Serving);
+        assert_eq!(
+            drain(&mut events),
+            [TlsEvent::StateChanged { state: SupervisorState::WaitingForEmail }, TlsEvent::StateChanged { state: SupervisorState::Serving }]
+        );
+        assert_eq!(monitor.status(NOW).state, SupervisorState::Serving);
+    }
+
+    #[test]
+    fn test_expiry_alert_fires_once_and_rearms_after_renewal() {
+        let monitor = TlsMonitor::default();
+        let mut events = monitor.subscribe();
+        monitor.set_domains(&["a.example.com".to_string()]);
+
+        // A cached certificate well outside the window: no alert
+        monitor.certificate_deployed("a.example.com", NOW + 30 * DAY, false, NOW);
+        assert!(drain(&mut events).is_empty());
+
+        // Time passes into the window: one alert, not repeated by later checks
+        monitor.check_expiry(NOW + 17 * DAY);
+        monitor.check_expiry(NOW + 18 * DAY);
+        assert_eq!(
+            drain(&mut events),
+            [TlsEvent::ExpiringSoon { domain: "a.example.com".to_string(), not_after: NOW + 30 * DAY, days_to_expiry: 13 }]
+        );
+
+        // A renewed certificate clears the alert; its own expiry window alerts again
+        monitor.renewal_succeeded("a.example.com");
+        monitor.certificate_deployed("a.example.com", NOW + 108 * DAY, true, NOW + 18 * DAY);
+        monitor.check_expiry(NOW + 95 * DAY);
+        assert_eq!(
+            drain(&mut events),
+            [
+                TlsEvent::CertificateIssued { domain: "a.example.com".to_string(), not_after: NOW + 108 * DAY },
+                TlsEvent::ExpiringSoon { domain: "a.example.com".to_string(), not_after: NOW + 108 * DAY, days_to_expiry: 13 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_renewal_outcomes_are_counted_per_domain() {
+        let monitor = TlsMonitor::default();
+        let mut events = monitor.subscribe();
+        monitor.set_domains(&["a.example.com".to_string(), "b.example.com".to_string()]);
+        monitor.renewal_failed("a.example.com", "order:bad_auth");
+        monitor.renewal_failed("a.example.com", "order:acme");
+        monitor.renewal_succeeded("a.example.com");
+        monitor.certificate_deployed("a.example.com", NOW + 90 * DAY, true, NOW);
+        monitor.handshake_failed();
+
+        let events = drain(&mut events);
+        assert_eq!(events.iter().filter(|e| e.is_alert()).count(), 2);
+        assert_eq!(events[0], TlsEvent::RenewalFailed { domain: "a.example.com".to_string(), error: "order:bad_auth".to_string() });
+
+        let status = monitor.status(NOW);
+        assert_eq!(status.handshake_errors, 1);
+        let [a, b] = status.certificates.as_slice() else { panic!("expected two domains: {:?}", status.certificates) };
+        assert_eq!((a.renewals_attempted, a.renewals_succeeded, a.renewals_failed), (3, 1, 2));
+        assert_eq!((a.days_to_expiry, a.last_error.as_deref()), (Some(90), None));
+        assert_eq!((b.domain.as_str(), b.not_after), ("b.example.com", None));
+
+        // Domains that are no longer served are dropped
+        monitor.set_domains(&["b.example.com".to_string()]);
+        assert_eq!(monitor.status(NOW).certificates.len(), 1);
+    }
+}
@@ -0,0 +1,117 @@
+//! IP network ranges in CIDR notation (e.g. `10.0.0.0/8`, `2001:db8::/32`, or a single address)
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpCidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, String> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(format!("Prefix length {} is too long for {}", prefix, addr));
+        }
+        Ok(Self { network: mask(addr, prefix), prefix })
+    }
+
+    /// True if `ip` lies within this range; IPv4-mapped IPv6 addresses match IPv4 ranges
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.network.is_ipv4() && mask(ip, self.prefix) == self.network
+    }
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(v4);
+            let masked = if prefix == 0 { 0 } else { bits & (u32::MAX << (32 - prefix)) };
+            IpAddr::V4(masked.into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(v6);
+            let masked = if prefix == 0 { 0 } else { bits & (u128::MAX << (128 - prefix)) };
+            IpAddr::V6(masked.into())
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("Invalid IP address in '{}'", s))?;
+        let addr = addr.to_canonical();
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().map_err(|_| format!("Invalid prefix length in '{}'", s))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(addr, prefix)
+    }
+}
+
+impl Display for IpCidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl Serialize for IpCidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpCidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!("10.1.2.3/8".parse::<IpCidr>().unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!("192.168.1.5".parse::<IpCidr>().unwrap().to_string(), "192.168.1.5/32");
+        assert_eq!("2001:db8::1/32".parse::<IpCidr>().unwrap().to_string(), "2001:db8::/32");
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("not-an-ip/8".parse::<IpCidr>().is_err());
+        assert!("10.0.0.0/x".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let cidr: IpCidr = "10.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains(ip("10.255.0.1")));
+        assert!(!cidr.contains(ip("11.0.0.1")));
+        assert!(cidr.contains(ip("::ffff:10.0.0.1")));
+        assert!(!cidr.contains(ip("::1")));
+
+        let v6: IpCidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:1::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+
+        let all: IpCidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("203.0.113.7")));
+    }
+}
@@ -0,0 +1,156 @@
+//! Host name normalization shared by request routing and route keys
+
+use crate::utils::validation::validate_hostname_chars;
+use std::borrow::Cow;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Strip the port from a Host header / authority value, keeping bracketed IPv6 literals intact
+/// e.g. `example.com:8080` -> `example.com`, `[::1]:8080` -> `[::1]`
+pub fn strip_port(authority: &str) -> &str {
+    if authority.starts_with('[') {
+        return match authority.find(']') {
+            Some(end) => &authority[..=end],
+            None => authority,
+        };
+    }
+    match authority.rsplit_once(':') {
+        // A bare IPv6 literal has several colons and no port we can safely strip
+        Some((host, _)) if !host.contains(':') => host,
+        _ => authority,
+    }
+}
+
+/// Normalize a host for route matching: lowercase, strip a single trailing dot,
+/// and strip the surrounding brackets of an IPv6 literal
+pub fn normalize_host(host: &str) -> Cow<'_, str> {
+    let trimmed = host.trim();
+    let unbracketed = trimmed.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(trimmed);
+    let stripped = unbracketed.strip_suffix('.').unwrap_or(unbracketed);
+    if stripped.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(stripped.to_ascii_lowercase())
+    } else if stripped.len() == host.len() {
+        Cow::Borrowed(host)
+    } else {
+        Cow::Borrowed(stripped)
+    }
+}
+
+/// Check the form of a route key: a `*` is only allowed as the whole leading label of a wildcard key
+/// (`*.example.com`), never as part of a label (`*example.com`) or further down the name
+pub fn check_wildcard_key(key: &str) -> Result<(), String> {
+    if !key.contains('*') {
+        return Ok(());
+    }
+    match key.strip_prefix("*.") {
+        Some(suffix) if !suffix.contains('*') && !suffix.split('.').any(str::is_empty) => Ok(()),
+        _ => Err(format!("Invalid wildcard route '{}': wildcards must have the form '*.example.com'", key)),
+    }
+}
+
+/// The domain covered by a wildcard route key (`*.example.com` -> `example.com`); None for exact keys
+pub fn wildcard_suffix(key: &str) -> Option<&str> {
+    key.strip_prefix("*.")
+}
+
+/// True if `host` is a strict subdomain of `domain`, compared on label boundaries
+/// (`api.example.com` is under `example.com`, `evilexample.com` and `example.com` itself are not)
+pub fn is_subdomain_of(host: &str, domain: &str) -> bool {
+    host.len() > domain.len() && host.ends_with(domain) && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+}
+
+/// Check that a Host header / authority value is syntactically valid: a hostname or IP literal
+/// (IPv6 in brackets) with an optional numeric port, and no whitespace
+pub fn is_valid_authority(authority: &str) -> bool {
+    if authority.is_empty() || authority.chars().any(char::is_whitespace) {
+        return false;
+    }
+    let host = strip_port(authority);
+    if let Some(port) = authority[host.len()..].strip_prefix(':') {
+        if port.parse::<u16>().is_err() {
+            return false;
+        }
+    } else if host.len() != authority.len() {
+        return false;
+    }
+    if let Some(literal) = host.strip_prefix('[') {
+        return literal.strip_suffix(']').is_some_and(|ip| ip.parse::<Ipv6Addr>().is_ok());
+    }
+    // Unbracketed IPv6 is not a valid authority, so only IPv4 literals are accepted here
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return true;
+    }
+    let host = host.strip_suffix('.').unwrap_or(host);
+    // Wildcards are only meaningful in route keys, never in a request
+    validate_hostname_chars(host) && !host.contains('*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_port() {
+        assert_eq!(strip_port("example.com"), "example.com");
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("::1"), "::1");
+    }
+
+    #[test]
+    fn test_is_valid_authority() {
+        assert!(is_valid_authority("example.com"));
+        assert!(is_valid_authority("Example.com.:8080"));
+        assert!(is_valid_authority("127.0.0.1:80"));
+        assert!(is_valid_authority("[::1]:8080"));
+        assert!(is_valid_authority("[::1]"));
+        assert!(!is_valid_authority(""));
+        assert!(!is_valid_authority("example .com"));
+        assert!(!is_valid_authority("example.com:http"));
+        assert!(!is_valid_authority("example.com:"));
+        assert!(!is_valid_authority("*.example.com"));
+        assert!(!is_valid_authority("exa_mple.com"));
+        assert!(!is_valid_authority("[::1]x"));
+        assert!(!is_valid_authority("[not-an-ip]"));
+        assert!(!is_valid_authority("::1"));
+    }
+
+    #[test]
+    fn test_check_wildcard_key() {
+        assert!(check_wildcard_key("example.com").is_ok());
+        assert!(check_wildcard_key("*.example.com").is_ok());
+        assert!(check_wildcard_key("*.com").is_ok());
+        assert!(check_wildcard_key("*example.com").is_err());
+        assert!(check_wildcard_key("api.*.example.com").is_err());
+        assert!(check_wildcard_key("*.*.example.com").is_err());
+        assert!(check_wildcard_key("*.").is_err());
+        assert!(check_wildcard_key("*.example..com").is_err());
+        assert!(check_wildcard_key("*").is_err());
+    }
+
+    #[test]
+    fn test_is_subdomain_of() {
+        assert!(is_subdomain_of("api.example.com", "example.com"));
+        assert!(is_subdomain_of("a.b.example.com", "example.com"));
+        assert!(!is_subdomain_of("example.com", "example.com"));
+        assert!(!is_subdomain_of("evilexample.com", "example.com"));
+        assert!(!is_subdomain_of("example.com.evil.net", "example.com"));
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("Example.COM"), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("[::1]"), "::1");
+        assert_eq!(normalize_host("[FE80::1]"), "fe80::1");
+        assert_eq!(normalize_host("*.Example.com."), "*.example.com");
+        // Only a single trailing dot is stripped
+        assert_eq!(normalize_host("example.com.."), "example.com.");
+    }
+
+    #[test]
+    fn test_normalize_host_borrows_when_already_normalized() {
+        assert!(matches!(normalize_host("example.com"), Cow::Borrowed(_)));
+        assert!(matches!(normalize_host("example.com."), Cow::Borrowed(_)));
+    }
+}
@@ -1,8 +1,14 @@
 // Utilities module
 //
 // This module contains common utility functions:
+// - cidr: IP ranges in CIDR notation
+// - host: Host name normalization for routing
 // - path: Path manipulation utilities
+// - timestamp: RFC 3339 parsing and formatting for config timestamps
 // - validation: Common validation helpers
 
+pub mod cidr;
+pub mod host;
 pub mod path;
+pub mod timestamp;
 pub mod validation;
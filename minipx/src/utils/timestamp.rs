@@ -0,0 +1,29 @@
+//! RFC 3339 timestamps in the config, such as the bounds of a route's activation window
+
+use anyhow::{Result, anyhow};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Parse an RFC 3339 timestamp, e.g. `2026-12-24T18:00:00Z` or `2026-12-24T19:00:00+01:00`
+pub fn parse_rfc3339(value: &str) -> Result<OffsetDateTime> {
+    OffsetDateTime::parse(value.trim(), &Rfc3339).map_err(|e| anyhow!("'{}' is not an RFC 3339 timestamp (e.g. 2026-12-24T18:00:00Z): {}", value, e))
+}
+
+/// `at` as RFC 3339, in the offset it was given in
+pub fn format_rfc3339(at: OffsetDateTime) -> String {
+    at.format(&Rfc3339).unwrap_or_else(|_| at.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_keeps_the_offset() {
+        let at = parse_rfc3339(" 2026-12-24T19:00:00+01:00 ").unwrap();
+        assert_eq!(at.unix_timestamp(), parse_rfc3339("2026-12-24T18:00:00Z").unwrap().unix_timestamp());
+        assert_eq!(format_rfc3339(at), "2026-12-24T19:00:00+01:00");
+        assert!(parse_rfc3339("2026-12-24 18:00").is_err());
+        assert!(parse_rfc3339("tomorrow").unwrap_err().to_string().contains("RFC 3339"));
+    }
+}
@@ -32,6 +32,22 @@ pub fn validate_hostname_chars(hostname: &str) -> bool {
         && !hostname.ends_with('-')
 }
 
+/// Validate a name sent to a backend instead of its address (Host header or TLS SNI): a hostname without wildcards,
+/// followed by `:port` only when `allow_port` is set
+pub fn validate_upstream_name(name: &str, allow_port: bool) -> Result<(), String> {
+    let (host, port) = match name.rsplit_once(':') {
+        Some((host, port)) if allow_port => (host, Some(port)),
+        _ => (name, None),
+    };
+    if !validate_hostname_chars(host) || host.contains('*') {
+        return Err(format!("'{}' is not a valid host name", name));
+    }
+    if port.is_some_and(|port| port.parse::<u16>().map_or(true, |port| port == 0)) {
+        return Err(format!("'{}' has an invalid port", name));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -103,4 +119,15 @@ mod tests {
         assert!(!validate_hostname_chars("exam_ple.com")); // contains underscore
         assert!(!validate_hostname_chars("exam@ple.com")); // contains @
     }
+
+    #[test]
+    fn test_validate_upstream_name() {
+        assert!(validate_upstream_name("internal.company.lan", false).is_ok());
+        assert!(validate_upstream_name("internal.company.lan:8443", true).is_ok());
+        assert!(validate_upstream_name("internal.company.lan:8443", false).is_err());
+        assert!(validate_upstream_name("internal.company.lan:0", true).is_err());
+        assert!(validate_upstream_name("*.company.lan", true).is_err());
+        assert!(validate_upstream_name("bad host", true).is_err());
+        assert!(validate_upstream_name("", true).is_err());
+    }
 }
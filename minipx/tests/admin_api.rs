@@ -0,0 +1,119 @@
+//! Admin REST API tests: real API on an ephemeral loopback port, asserting on the config file it writes.
+//! Run with `cargo test -p minipx --features admin-api`.
+#![cfg(feature = "admin-api")]
+
+use hyper::{Body, Client, Method, Request, StatusCode};
+use minipx::admin::spawn_admin_api;
+use minipx::config::Config;
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+const TOKEN: &str = "test-token";
+
+async fn call(addr: SocketAddr, method: Method, path: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, String) {
+    let mut builder = Request::builder().method(method).uri(format!("http://{}{}", addr, path));
+    if let Some(token) = token {
+        builder = builder.header("authorization", format!("Bearer {}", token));
+    }
+    let body = body.map(|b| Body::from(b.to_string())).unwrap_or_else(Body::empty);
+    let resp = Client::new().request(builder.body(body).unwrap()).await.unwrap();
+    let status = resp.status();
+    let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn config_file(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("minipx-admin-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("minipx.json")
+}
+
+fn routes_on_disk(path: &PathBuf) -> Value {
+    let content: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    content["routes"].clone()
+}
+
+#[tokio::test]
+async fn requests_without_the_token_are_rejected() {
+    let addr = spawn_admin_api(0, TOKEN.to_string()).unwrap();
+    assert!(addr.ip().is_loopback());
+
+    let (status, _) = call(addr, Method::GET, "/routes", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(addr, Method::GET, "/routes", Some("wrong-token"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(addr, Method::GET, "/nope", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn route_mutations_are_saved_and_applied() {
+    let path = config_file("crud");
+    Config::try_load(&path).await.unwrap();
+    let addr = spawn_admin_api(0, TOKEN.to_string()).unwrap();
+
+    // Add
+    let body = json!({ "domain": "App.Example.com", "route": { "host": "127.0.0.1", "port": 3000 } });
+    let (status, _) = call(addr, Method::POST, "/routes", Some(TOKEN), Some(body.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(routes_on_disk(&path)["app.example.com"]["port"], 3000);
+    assert!(Config::get().await.lookup_host("app.example.com").is_some());
+
+    let (status, _) = call(addr, Method::POST, "/routes", Some(TOKEN), Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call(addr, Method::POST, "/routes", Some(TOKEN), Some(json!("not a route"))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // List
+    let (status, body) = call(addr, Method::GET, "/routes", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let routes: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(routes["app.example.com"]["host"], "127.0.0.1");
+
+    // Update
+    let (status, _) = call(addr, Method::PUT, "/routes/app.example.com", Some(TOKEN), Some(json!({ "port": 3001, "ssl_enable": true }))).await;
+    assert_eq!(status, StatusCode::OK);
+    let on_disk = routes_on_disk(&path);
+    assert_eq!(on_disk["app.example.com"]["port"], 3001);
+    assert_eq!(on_disk["app.example.com"]["ssl_enable"], true);
+    assert_eq!(Config::get().await.lookup_host("app.example.com").unwrap().get_port(), 3001);
+
+    let (status, _) = call(addr, Method::PUT, "/routes/missing.example.com", Some(TOKEN), Some(json!({ "port": 3002 }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Status and metrics
+    let (status, body) = call(addr, Method::GET, "/status", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let report: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(report["routes"], 1);
+    let (status, body) = call(addr, Method::GET, "/metrics", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("minipx_routes 1"));
+    assert!(body.contains("minipx_config_reload_failed 0"));
+    assert!(body.contains("# TYPE minipx_unknown_host_requests_total counter"));
+    assert!(body.contains("minipx_tls_fallback_handshakes_total{reason=\"no_sni\"}"));
+    assert!(body.contains("minipx_tls_handshake_errors_total "));
+    assert!(body.contains("# TYPE minipx_upstream_stale_retries_total counter"));
+    assert!(body.contains("# TYPE minipx_route_bytes_total counter") && body.contains("# TYPE minipx_top_client_bytes gauge"));
+    assert!(body.contains("minipx_tls_supervisor_state{state=\"starting\"} 1"));
+
+    // Reload picks up an edit made outside the API
+    let mut content: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    content["routes"]["app.example.com"]["port"] = json!(4000);
+    std::fs::write(&path, content.to_string()).unwrap();
+    let (status, _) = call(addr, Method::POST, "/reload", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(Config::get().await.lookup_host("app.example.com").unwrap().get_port(), 4000);
+
+    // Delete
+    let (status, _) = call(addr, Method::DELETE, "/routes/app.example.com", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(routes_on_disk(&path).get("app.example.com").is_none());
+    assert!(Config::get().await.lookup_host("app.example.com").is_none());
+    let (status, _) = call(addr, Method::DELETE, "/routes/app.example.com", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
@@ -0,0 +1,44 @@
+//! bind_failure_policy: with a frontend port occupied, fail gives up naming the port and retry keeps trying while
+//! the frontend is reported unbound.
+
+use minipx::config::{BindFailurePolicy, Config};
+use minipx::proxy::bind::{bind_with_policy, unbound_frontends};
+use minipx::proxy::health::health_report;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+fn occupied_port() -> (TcpListener, SocketAddr) {
+    let occupier = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = occupier.local_addr().unwrap();
+    (occupier, addr)
+}
+
+#[tokio::test]
+async fn fail_policy_gives_up_naming_the_port() {
+    let (_occupier, addr) = occupied_port();
+    let result = bind_with_policy("fail-test", addr, BindFailurePolicy::Fail, 3, Duration::from_millis(10), || TcpListener::bind(addr)).await;
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains(&format!("FAIL-TEST port {} after 3 attempts", addr.port())), "{}", err);
+    assert_eq!(unbound_frontends().iter().find(|f| f.kind == "fail-test").unwrap().attempts, 3);
+}
+
+#[tokio::test]
+async fn retry_policy_reports_unbound_until_the_port_frees_up() {
+    let (occupier, addr) = occupied_port();
+    let binding = tokio::spawn(async move {
+        bind_with_policy("retry-test", addr, BindFailurePolicy::Retry, 1, Duration::from_millis(20), || TcpListener::bind(addr)).await
+    });
+    // Retry ignores the attempt limit
+    while unbound_frontends().iter().find(|f| f.kind == "retry-test").is_none_or(|f| f.attempts < 3) {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let report = health_report(&Config::default());
+    assert_eq!(report.status, "degraded");
+    assert_eq!(report.unbound_frontends.iter().find(|f| f.kind == "retry-test").unwrap().port, addr.port());
+    assert!(!binding.is_finished());
+
+    drop(occupier);
+    let listener = binding.await.unwrap().unwrap();
+    assert_eq!(listener.local_addr().unwrap(), addr);
+    assert!(!unbound_frontends().iter().any(|f| f.kind == "retry-test"));
+}
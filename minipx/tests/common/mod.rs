@@ -0,0 +1,199 @@
+//! Shared helpers for the minipx integration tests.
+//!
+//! - `MockUpstream`: a hyper backend on an ephemeral port that records every request it receives
+//! - `spawn_ws_echo_upstream`: a websocket backend that echoes messages back
+//! - `spawn_ws_echo_upstream_selecting`: the same, answering every handshake with a fixed subprotocol
+//! - `spawn_silent_upstream`: a backend that accepts connections and never answers
+//! - `spawn_proxy`: a frontend on an ephemeral port that runs the real request handler against a fixed config
+#![allow(dead_code)]
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, StatusCode};
+use minipx::config::{Config, ProxyRoute};
+use minipx::proxy::MinipxService;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+/// A request as seen by a mock upstream
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    /// First value of the named header, if present
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// All values of the named header
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
+        self.headers.iter().filter(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str()).collect()
+    }
+}
+
+/// A hyper backend that records requests and answers `200 upstream:<name>`
+pub struct MockUpstream {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockUpstream {
+    pub async fn spawn(name: &str) -> Self {
+        let name = name.to_string();
+        Self::spawn_with(move |_req| {
+            let name = name.clone();
+            Response::builder().status(StatusCode::OK).body(Body::from(format!("upstream:{}", name))).unwrap()
+        })
+        .await
+    }
+
+    /// Spawn an upstream with a custom responder
+    pub async fn spawn_with<F>(respond: F) -> Self
+    where
+        F: Fn(&RecordedRequest) -> Response<Body> + Send + Sync + 'static,
+    {
+        let requests: Arc<Mutex<Vec<RecordedRequest>>> = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+        let recorded = requests.clone();
+        let make_svc = make_service_fn(move |_conn: &AddrStream| {
+            let recorded = recorded.clone();
+            let respond = respond.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let recorded = recorded.clone();
+                    let respond = respond.clone();
+                    async move {
+                        let (parts, body) = req.into_parts();
+                        let body = hyper::body::to_bytes(body).await.unwrap_or_default().to_vec();
+                        let request = RecordedRequest {
+                            method: parts.method.to_string(),
+                            uri: parts.uri.to_string(),
+                            headers: parts.headers.iter().map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string())).collect(),
+                            body,
+                        };
+                        let response = respond(&request);
+                        recorded.lock().unwrap().push(request);
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        Self { addr, requests }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Snapshot of all requests received so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The most recent request received, panicking if none arrived
+    pub fn last_request(&self) -> RecordedRequest {
+        self.requests().pop().expect("upstream received no requests")
+    }
+}
+
+/// Spawn a websocket backend that echoes every text/binary message, recording the handshake path
+#[allow(clippy::result_large_err)]
+pub async fn spawn_ws_echo_upstream() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    spawn_ws_echo_upstream_selecting(None).await
+}
+
+/// Spawn a websocket echo backend that selects `protocol` (whatever the client offered) when given
+#[allow(clippy::result_large_err)]
+pub async fn spawn_ws_echo_upstream_selecting(protocol: Option<&'static str>) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let paths: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let recorded = paths.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            tokio::spawn(async move {
+                let callback = |req: &tokio_tungstenite::tungstenite::handshake::server::Request,
+                                mut resp: tokio_tungstenite::tungstenite::handshake::server::Response| {
+                    recorded.lock().unwrap().push(req.uri().to_string());
+                    if let Some(protocol) = protocol {
+                        resp.headers_mut().insert("Sec-WebSocket-Protocol", protocol.parse().unwrap());
+                    }
+                    Ok(resp)
+                };
+                let Ok(mut ws) = tokio_tungstenite::accept_hdr_async(stream, callback).await else {
+                    return;
+                };
+                while let Some(Ok(msg)) = ws.next().await {
+                    if msg.is_text() || msg.is_binary() {
+                        if ws.send(msg).await.is_err() {
+                            break;
+                        }
+                    } else if msg.is_close() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    (addr, paths)
+}
+
+/// Spawn a backend that accepts connections, reads whatever is sent, and never writes a byte back
+pub async fn spawn_silent_upstream() -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                while matches!(tokio::io::AsyncReadExt::read(&mut stream, &mut buf).await, Ok(n) if n > 0) {}
+            });
+        }
+    });
+    addr
+}
+
+/// Spawn a frontend on an ephemeral port that hands every request to the real handler with `config`
+pub async fn spawn_proxy(config: Config, frontend_scheme: &'static str) -> SocketAddr {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(minipx::proxy::serve_listener(listener, MinipxService::new(frontend_scheme).with_config(config)));
+    addr
+}
+
+/// Address the test requests come from (TEST-NET-3)
+pub fn client() -> IpAddr {
+    IpAddr::from([203, 0, 113, 7])
+}
+
+/// An in-memory config (never saved) with a temp path
+pub fn test_config() -> Config {
+    Config::new(std::env::temp_dir().join("minipx-integration-tests").join("minipx.json"))
+}
+
+/// A plain HTTP route to 127.0.0.1:`port`
+pub fn route(port: u16) -> ProxyRoute {
+    ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), port, false, None, false)
+}
+
+/// Build a request for `host` and `path`
+pub fn request(host: &str, path: &str) -> Request<Body> {
+    Request::builder().uri(path).header("Host", host).body(Body::empty()).unwrap()
+}
+
+/// Collect a response body into a String
+pub async fn body_string(resp: Response<Body>) -> String {
+    String::from_utf8(hyper::body::to_bytes(resp.into_body()).await.unwrap().to_vec()).unwrap()
+}
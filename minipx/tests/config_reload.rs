@@ -0,0 +1,108 @@
+//! Config reload safety: an invalid config file appearing while the file is watched must not
+//! touch the file or the running config, and a config saved by minipx itself is applied only once.
+
+use minipx::config::manager::reload_failure;
+use minipx::config::{Config, RoutePatch};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// The watched tests replace the global running config; running them together would mix up their assertions
+static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn config_file(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("minipx-reload-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("minipx.json")
+}
+
+fn write_config(path: &Path, port: u16) {
+    let content = json!({ "routes": { "app.example.com": { "host": "127.0.0.1", "port": port } } });
+    std::fs::write(path, serde_json::to_string_pretty(&content).unwrap()).unwrap();
+}
+
+async fn wait_for(mut condition: impl AsyncFnMut() -> bool) -> bool {
+    for _ in 0..100 {
+        if condition().await {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn invalid_config_during_watch_keeps_the_last_known_good_config() {
+    let _serial = SERIAL.lock().await;
+    let path = config_file("watch");
+    write_config(&path, 3000);
+    let config = Config::try_load(&path).await.unwrap();
+    config.watch_config_file();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // A half-written edit appears
+    let invalid = "{ \"routes\": { \"app.example.com\": ";
+    std::fs::write(&path, invalid).unwrap();
+    assert!(wait_for(async || reload_failure().is_some()).await, "the failed reload was not reported");
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), invalid);
+    let siblings: Vec<_> = std::fs::read_dir(path.parent().unwrap()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(siblings, vec![std::ffi::OsString::from("minipx.json")]);
+    assert_eq!(Config::get().await.lookup_host("app.example.com").unwrap().get_port(), 3000);
+
+    // Fixing the file applies it and clears the failure
+    write_config(&path, 3001);
+    let applied =
+        wait_for(async || reload_failure().is_none() && Config::get().await.lookup_host("app.example.com").is_some_and(|r| r.get_port() == 3001))
+            .await;
+    assert!(applied, "the fixed config was not applied");
+}
+
+#[tokio::test]
+async fn a_saved_edit_is_applied_once_and_not_reloaded_by_the_watcher() {
+    let _serial = SERIAL.lock().await;
+    let path = config_file("save");
+    write_config(&path, 3000);
+    let config = Config::try_load(&path).await.unwrap();
+    config.watch_config_file();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut edited = Config::get().await;
+    edited.update_route("app.example.com", RoutePatch { port: Some(3001), ..Default::default() }).await.unwrap();
+    let applied = edited.save_and_apply().await.unwrap();
+    assert_eq!(applied.generation(), config.generation() + 1);
+    assert_eq!(
+        applied.route_generation("app.example.com").unwrap().generation(),
+        config.route_generation("app.example.com").unwrap().generation() + 1
+    );
+
+    // The watcher sees the write but recognizes it
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let running = Config::get().await;
+    assert_eq!(running.generation(), applied.generation());
+    assert_eq!(running.lookup_host("app.example.com").unwrap().get_port(), 3001);
+    assert!(reload_failure().is_none());
+
+    // Edits made by anyone else are still reloaded
+    write_config(&path, 3002);
+    assert!(wait_for(async || Config::get().await.lookup_host("app.example.com").is_some_and(|r| r.get_port() == 3002)).await);
+    assert_eq!(Config::get().await.generation(), applied.generation() + 1);
+}
+
+#[tokio::test]
+async fn typos_are_reported_and_refused_under_strict_config() {
+    let path = config_file("strict");
+    let mut content = json!({ "routes": { "app.example.com": { "host": "127.0.0.1", "prot": 3000, "ssl_enable": "yes" } } });
+    std::fs::write(&path, content.to_string()).unwrap();
+
+    let config = Config::read(&path).await.unwrap();
+    let issues: Vec<String> = config.get_issues().iter().map(|issue| issue.path.clone()).collect();
+    assert_eq!(issues, [r#"routes["app.example.com"].prot"#, r#"routes["app.example.com"].ssl_enable"#]);
+    assert_eq!(config.lookup_host("app.example.com").unwrap().get_port(), 0);
+
+    content["strict_config"] = json!(true);
+    std::fs::write(&path, content.to_string()).unwrap();
+    let error = Config::read(&path).await.unwrap_err().to_string();
+    assert!(error.contains("strict_config") && error.contains(r#"routes["app.example.com"].prot: unknown key"#), "{}", error);
+}
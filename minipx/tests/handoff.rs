@@ -0,0 +1,108 @@
+//! Warm restart: the listening socket is handed to another process (this test binary, started again as the
+//! successor) while a client keeps sending requests, and none of them fail.
+#![cfg(unix)]
+
+mod common;
+
+use common::{MockUpstream, route, test_config};
+use minipx::config::Config;
+use minipx::handoff::{self, HANDOFF_VERSION, HandoffState};
+use minipx::proxy::MinipxService;
+use minipx::proxy::serve_listener;
+use std::net::SocketAddr;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const HOST: &str = "handoff.example.com";
+
+// This test binary, running only `handoff_successor`
+fn successor() -> Command {
+    let mut command = Command::new(std::env::current_exe().unwrap());
+    command.args(["--exact", "handoff_successor"]).stdout(Stdio::null()).stderr(Stdio::null());
+    command
+}
+
+/// The new process: serves the handed-over listener with the config file it was pointed at. Without a handoff
+/// (the regular test run) there is nothing to do.
+#[tokio::test]
+async fn handoff_successor() {
+    let Ok(Some(state)) = handoff::adopt() else {
+        return;
+    };
+    let config = Config::read(&state.config_path).await.unwrap();
+    let listener = handoff::inherited_listener("http").unwrap();
+    tokio::spawn(serve_listener(listener, MinipxService::new("http").with_config(config)));
+    handoff::accept();
+    // Killed by the test that started it
+    tokio::time::sleep(Duration::from_secs(30)).await;
+}
+
+async fn get(addr: SocketAddr) -> Result<String, String> {
+    let mut stream = TcpStream::connect(addr).await.map_err(|e| e.to_string())?;
+    let request = format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", HOST);
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.map_err(|e| e.to_string())?;
+    if !response.starts_with("HTTP/1.1 200") {
+        return Err(response);
+    }
+    Ok(response.rsplit("\r\n").next().unwrap_or_default().to_string())
+}
+
+#[tokio::test]
+async fn listeners_are_handed_to_a_new_process_without_dropping_requests() {
+    let old_upstream = MockUpstream::spawn("old").await;
+    let new_upstream = MockUpstream::spawn("new").await;
+
+    // The successor serves the config file, which points at the new upstream
+    let dir = std::env::temp_dir().join(format!("minipx-handoff-test-{}", std::process::id()));
+    let mut config = Config::new(dir.join("minipx.json"));
+    config.add_route(HOST.to_string(), route(new_upstream.port())).await.unwrap();
+    config.save().await.unwrap();
+    let mut current = test_config();
+    current.add_route(HOST.to_string(), route(old_upstream.port())).await.unwrap();
+
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    handoff::register_listener("http", &listener);
+    tokio::spawn(serve_listener(listener, MinipxService::new("http").with_config(current)));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let client = tokio::spawn({
+        let stop = stop.clone();
+        async move {
+            let mut responses = Vec::new();
+            while !stop.load(Ordering::Acquire) {
+                responses.push(get(addr).await);
+            }
+            responses
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // A successor that does not understand the state refuses, and nothing changes
+    let mut state = HandoffState::new(config.get_path(), config.get_cache_dir().clone());
+    state.version = HANDOFF_VERSION + 1;
+    let error = handoff::hand_off(successor(), state).await.unwrap_err();
+    assert!(error.to_string().contains("not supported"), "{}", error);
+    assert!(!handoff::is_handed_off());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut child = handoff::hand_off(successor(), HandoffState::new(config.get_path(), config.get_cache_dir().clone())).await.unwrap();
+    assert!(handoff::is_handed_off());
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    stop.store(true, Ordering::Release);
+    let responses = client.await.unwrap();
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let failures: Vec<&String> = responses.iter().filter_map(|response| response.as_ref().err()).collect();
+    assert!(failures.is_empty(), "{} of {} requests failed: {:?}", failures.len(), responses.len(), failures.first());
+    assert!(responses.iter().any(|response| response.as_deref() == Ok("upstream:old")));
+    assert_eq!(responses.last().unwrap().as_deref(), Ok("upstream:new"));
+}
@@ -0,0 +1,110 @@
+//! HEAD requests get no body from the proxy's own responses (redirects, health, error pages), `OPTIONS *` is
+//! answered without a backend, and 304/204 responses stay bodiless through the response post-processing.
+
+mod common;
+
+use common::{MockUpstream, body_string, client, request, route, spawn_proxy, test_config};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use minipx::config::{CacheControlRule, Config, ProxyRoute};
+use minipx::proxy::request_handler::handle_request_with_config;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn head(host: &str, path: &str) -> Request<Body> {
+    let mut req = request(host, path);
+    *req.method_mut() = Method::HEAD;
+    req
+}
+
+fn content_length(resp: &Response<Body>) -> Option<&str> {
+    resp.headers().get("content-length").and_then(|v| v.to_str().ok())
+}
+
+// Send a raw request and read everything until the server closes the connection
+async fn exchange(addr: SocketAddr, raw: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(raw.as_bytes()).await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    String::from_utf8(received).unwrap()
+}
+
+async fn assert_head_matches_get(config: &Config, host: &str, path: &str, status: StatusCode) {
+    let get = handle_request_with_config(config, "http", client(), request(host, path)).await.unwrap();
+    assert_eq!(get.status(), status);
+    let get_body = body_string(get).await;
+    assert!(!get_body.is_empty());
+
+    let head = handle_request_with_config(config, "http", client(), head(host, path)).await.unwrap();
+    assert_eq!(head.status(), status);
+    assert_eq!(content_length(&head), Some(get_body.len().to_string().as_str()));
+    assert_eq!(body_string(head).await, "");
+}
+
+#[tokio::test]
+async fn head_against_health_and_error_pages_has_length_but_no_body() {
+    let mut config = test_config();
+    // Nothing listens on port 1, so proxying fails with the proxy's own error page
+    config.add_route("down.example.com".to_string(), route(1)).await.unwrap();
+
+    assert_head_matches_get(&config, "anything.example.com", "/__minipx/health", StatusCode::OK).await;
+    assert_head_matches_get(&config, "unknown.example.com", "/", StatusCode::NOT_FOUND).await;
+    assert_head_matches_get(&config, "down.example.com", "/", StatusCode::INTERNAL_SERVER_ERROR).await;
+}
+
+#[tokio::test]
+async fn head_against_the_https_redirect_over_the_wire() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.set_email("admin@example.com".to_string());
+    let redirecting = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), upstream.port(), true, None, true);
+    config.add_route("secure.example.com".to_string(), redirecting).await.unwrap();
+    let addr = spawn_proxy(config, "http").await;
+
+    let response = exchange(addr, "HEAD /login?next=%2F HTTP/1.1\r\nHost: secure.example.com\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 301"), "{}", response);
+    assert!(response.to_ascii_lowercase().contains("location: https://secure.example.com/login?next=%2f"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "a body followed the headers: {:?}", response);
+    assert!(upstream.requests().is_empty());
+}
+
+#[tokio::test]
+async fn options_asterisk_is_answered_by_the_proxy() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+    let addr = spawn_proxy(config.clone(), "http").await;
+
+    let response = exchange(addr, "OPTIONS * HTTP/1.1\r\nHost: app.example.com\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.to_ascii_lowercase().contains("allow: get, head, post, put, patch, delete, options"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "{:?}", response);
+    assert!(upstream.requests().is_empty());
+
+    // OPTIONS on a path is still the backend's to answer
+    let mut req = request("app.example.com", "/resource");
+    *req.method_mut() = Method::OPTIONS;
+    let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:app");
+}
+
+#[tokio::test]
+async fn not_modified_from_upstream_stays_bodiless_through_post_processing() {
+    let upstream =
+        MockUpstream::spawn_with(|_| Response::builder().status(StatusCode::NOT_MODIFIED).header("ETag", "\"v1\"").body(Body::empty()).unwrap())
+            .await;
+    let mut config = test_config();
+    let mut limited = route(upstream.port());
+    // Bandwidth pacing and cache_control both post-process the response
+    limited.set_bandwidth_limit_kbps(Some(1_000));
+    limited.set_cache_control(vec![CacheControlRule::new("public, max-age=60").unwrap()]);
+    config.add_route("cached.example.com".to_string(), limited).await.unwrap();
+    let addr = spawn_proxy(config, "http").await;
+
+    let response = exchange(addr, "GET /logo.png HTTP/1.1\r\nHost: cached.example.com\r\nIf-None-Match: \"v1\"\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 304"), "{}", response);
+    let lower = response.to_ascii_lowercase();
+    assert!(lower.contains("cache-control: public, max-age=60") && !lower.contains("transfer-encoding"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "{:?}", response);
+}
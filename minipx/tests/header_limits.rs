@@ -0,0 +1,117 @@
+//! Header limits: requests just over `max_header_bytes` or `max_header_count` get 431 without reaching the
+//! backend, routes can override the limits, and `upstream_header_policy` decides what happens when the forwarding
+//! headers push a request over them.
+
+mod common;
+
+use common::{MockUpstream, body_string, client, request, route, spawn_proxy, test_config};
+use hyper::header::HeaderName;
+use hyper::{Body, Request, StatusCode};
+use minipx::config::HeaderLimitPolicy;
+use minipx::proxy::header_limits::block_size;
+use minipx::proxy::request_handler::handle_request_with_config;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const HOST: &str = "limits.example.com";
+
+// A request whose header block is exactly `bytes` long
+fn request_of_size(bytes: usize) -> Request<Body> {
+    let mut req = request(HOST, "/");
+    let pad = bytes - block_size(req.headers()) - "x-pad: \r\n".len();
+    req.headers_mut().insert("x-pad", "a".repeat(pad).parse().unwrap());
+    assert_eq!(block_size(req.headers()), bytes);
+    req
+}
+
+// A request with `count` header fields
+fn request_with_fields(count: usize) -> Request<Body> {
+    let mut req = request(HOST, "/");
+    for i in 1..count {
+        req.headers_mut().insert(HeaderName::try_from(format!("x-field-{}", i)).unwrap(), "1".parse().unwrap());
+    }
+    assert_eq!(req.headers().len(), count);
+    req
+}
+
+#[tokio::test]
+async fn requests_just_over_the_limits_get_431_and_never_reach_the_backend() {
+    let upstream = MockUpstream::spawn("limits").await;
+    let mut config = test_config();
+    config.set_max_header_bytes(1024);
+    config.set_max_header_count(10);
+    config.add_route(HOST.to_string(), route(upstream.port())).await.unwrap();
+
+    // The accepted ones leave room for the forwarding headers, which count toward the limits upstream
+    for (req, status) in [
+        (request_of_size(900), StatusCode::OK),
+        (request_of_size(1025), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+        (request_with_fields(7), StatusCode::OK),
+        (request_with_fields(11), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+    ] {
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(resp.status(), status);
+    }
+    assert_eq!(upstream.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn routes_override_the_global_limits() {
+    let upstream = MockUpstream::spawn("limits").await;
+    let mut config = test_config();
+    config.set_max_header_count(10);
+    let mut generous = route(upstream.port());
+    generous.set_max_header_count(Some(20));
+    generous.set_max_header_bytes(Some(512));
+    config.add_route(HOST.to_string(), generous).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request_with_fields(15)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = handle_request_with_config(&config, "http", client(), request_of_size(513)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert_eq!(upstream.requests().len(), 1);
+    assert_eq!(config.largest_header_limit(), 64 * 1024);
+}
+
+#[tokio::test]
+async fn forwarding_headers_past_the_limit_are_rejected_or_truncated() {
+    let upstream = MockUpstream::spawn("limits").await;
+    let mut config = test_config();
+    // Room for the client's fields, but not for X-Real-IP, X-Forwarded-Proto and X-Forwarded-Host on top
+    config.set_max_header_count(10);
+    config.add_route(HOST.to_string(), route(upstream.port())).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request_with_fields(9)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert!(upstream.requests().is_empty());
+
+    config.set_upstream_header_policy(HeaderLimitPolicy::Truncate);
+    let resp = handle_request_with_config(&config, "http", client(), request_with_fields(9)).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:limits");
+    let seen = upstream.last_request();
+    // The last client headers went; the forwarding headers and the first client ones stayed
+    assert_eq!(seen.header("x-forwarded-host"), Some(HOST));
+    assert_eq!(seen.header("x-field-1"), Some("1"));
+    assert_eq!(seen.header("x-field-8"), None);
+}
+
+#[tokio::test]
+async fn more_fields_than_hyper_parses_are_refused_on_the_connection() {
+    let upstream = MockUpstream::spawn("limits").await;
+    let mut config = test_config();
+    config.set_max_header_count(1000);
+    config.add_route(HOST.to_string(), route(upstream.port())).await.unwrap();
+    let addr = spawn_proxy(config, "http").await;
+
+    let mut head = format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", HOST);
+    for i in 0..100 {
+        head.push_str(&format!("X-Field-{}: 1\r\n", i));
+    }
+    head.push_str("\r\n");
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+    assert!(upstream.requests().is_empty());
+}
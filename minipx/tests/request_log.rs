@@ -0,0 +1,129 @@
+//! request_log_mode: summary lines instead of per-request lines, and an allocation-free recording path.
+
+mod common;
+
+use common::{MockUpstream, body_string, client, request, route, test_config};
+use hyper::{Body, Response, StatusCode};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use minipx::config::{Config, RequestLogMode};
+use minipx::proxy::request_handler::handle_request_with_config;
+use minipx::proxy::request_log;
+use minipx::proxy::routing_trace::RoutingTrace;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Counts allocations made by the current thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+// Keeps every log line at info and above
+struct CapturingLogger;
+
+static LINES: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+static LOGGER: CapturingLogger = CapturingLogger;
+
+impl Log for CapturingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LINES.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn logged(containing: &str) -> Vec<(Level, String)> {
+    LINES.lock().unwrap().iter().filter(|(_, line)| line.contains(containing)).cloned().collect()
+}
+
+// Both tests flush the shared summaries; running them together could remove the entry the other one measures
+static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn summary_config() -> Config {
+    let mut config = test_config();
+    config.set_request_log_mode(RequestLogMode::Summary);
+    config
+}
+
+#[tokio::test]
+async fn summary_recording_does_not_allocate() {
+    let _serial = SERIAL.lock().await;
+    let config = summary_config();
+    // The first request of a route adds its counters (and starts the summary task)
+    request_log::finished(&config, &RoutingTrace::new("hot.example.com"), client(), "/", StatusCode::OK, Duration::from_millis(3));
+
+    const REQUESTS: u32 = 100_000;
+    let before = allocations();
+    for i in 0..REQUESTS {
+        let latency = Duration::from_micros(u64::from(i % 5_000));
+        request_log::finished(&config, &RoutingTrace::new("hot.example.com"), client(), "/", StatusCode::OK, latency);
+    }
+    assert_eq!(allocations() - before, 0, "recording a request in summary mode allocated");
+}
+
+#[tokio::test]
+async fn summary_mode_logs_one_line_per_route_and_each_server_error() {
+    let _serial = SERIAL.lock().await;
+    let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Info));
+
+    let upstream = MockUpstream::spawn("summary").await;
+    let failing = MockUpstream::spawn_with(|_req| Response::builder().status(StatusCode::BAD_GATEWAY).body(Body::from("down")).unwrap()).await;
+    let mut config = summary_config();
+    config.add_route("summary.example.com".to_string(), route(upstream.port())).await.unwrap();
+    config.add_route("failing.example.com".to_string(), route(failing.port())).await.unwrap();
+
+    for i in 0..20 {
+        let resp = handle_request_with_config(&config, "http", client(), request("summary.example.com", &format!("/page/{}", i))).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:summary");
+    }
+    let resp = handle_request_with_config(&config, "http", client(), request("failing.example.com", "/broken")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+
+    assert!(logged("Received request").is_empty(), "{:?}", logged("Received request"));
+    let errors = logged("failing.example.com/broken");
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].0 == Level::Warn && errors[0].1.starts_with("502 Bad Gateway for failing.example.com/broken from 203.0.113.7"));
+    let routing = format!("route=failing.example.com subroute=- upstream=http://127.0.0.1:{} decision=proxied", failing.port());
+    assert!(errors[0].1.ends_with(&routing), "{}", errors[0].1);
+
+    let summaries = request_log::flush();
+    let summary = summaries.iter().find(|s| s.domain == "summary.example.com").unwrap();
+    assert_eq!((summary.requests, summary.status_classes), (20, [0, 20, 0, 0, 0]));
+    let lines = logged("Requests for summary.example.com: 20 (1xx=0 2xx=20 3xx=0 4xx=0 5xx=0) latency p50<=");
+    assert_eq!(lines.len(), 1, "{:?}", logged("Requests for"));
+    assert_eq!(summaries.iter().find(|s| s.domain == "failing.example.com").unwrap().status_classes[4], 1);
+
+    // per_request mode keeps one info line for every request; its response and routing are only logged at debug
+    config.set_request_log_mode(RequestLogMode::PerRequest);
+    handle_request_with_config(&config, "http", client(), request("summary.example.com", "/once")).await.unwrap();
+    assert_eq!(logged("Received request from 203.0.113.7 for http://summary.example.com/once").len(), 1);
+    let answered = logged("200 OK for summary.example.com/once");
+    assert!(answered.is_empty(), "{:?}", answered);
+}
@@ -0,0 +1,38 @@
+//! The `resolver` section: backend names pinned in static_hosts reach the backend, and reloads apply to the next lookup.
+
+mod common;
+
+use common::{MockUpstream, body_string, request};
+use minipx::config::Config;
+use minipx::proxy::request_handler::handle_request_with_config;
+use minipx::proxy::resolver;
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+fn write_config(path: &Path, backend_ip: &str, port: u16) {
+    let content = json!({
+        "resolver": { "static_hosts": { "backend.internal": backend_ip } },
+        "routes": { "app.example.com": { "host": "backend.internal", "port": port } }
+    });
+    std::fs::write(path, serde_json::to_string_pretty(&content).unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn static_hosts_route_requests_and_follow_reloads() {
+    let dir = std::env::temp_dir().join(format!("minipx-resolver-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("minipx.json");
+
+    let upstream = MockUpstream::spawn("pinned").await;
+    write_config(&path, "127.0.0.1", upstream.port());
+    let config = Config::try_load(&path).await.unwrap();
+    let resp = handle_request_with_config(&config, "http", IpAddr::from([127, 0, 0, 1]), request("app.example.com", "/")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:pinned");
+    assert_eq!(resolver::resolve("backend.internal", 80).await.unwrap(), [SocketAddr::from(([127, 0, 0, 1], 80))]);
+
+    write_config(&path, "10.20.30.40", upstream.port());
+    Config::reload(&path).await.unwrap();
+    assert_eq!(resolver::resolve("BACKEND.internal", 80).await.unwrap(), [SocketAddr::from(([10, 20, 30, 40], 80))]);
+}
@@ -0,0 +1,490 @@
+//! End-to-end routing tests: real handler, real mock upstreams on ephemeral ports.
+
+mod common;
+
+use common::{MockUpstream, body_string, client, request, route, spawn_proxy, spawn_silent_upstream, test_config};
+use hyper::StatusCode;
+use minipx::config::{UnknownHostAction, UnknownHostConfig};
+use minipx::proxy::request_handler::{ROUTE_HEADER, UPSTREAM_HEADER, handle_request_with_config};
+use minipx::proxy::{ConnectionClosed, TlsInfo};
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn exact_route_reaches_its_upstream() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("app.example.com", "/hello?x=1")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(body_string(resp).await, "upstream:app");
+
+    let seen = upstream.last_request();
+    assert_eq!(seen.method, "GET");
+    assert_eq!(seen.uri, "/hello?x=1");
+}
+
+#[tokio::test]
+async fn forward_tls_info_sends_the_connection_details_upstream() {
+    let upstream = MockUpstream::spawn("tls").await;
+    let mut config = test_config();
+    let mut tls_route = route(upstream.port());
+    tls_route.set_forward_tls_info(true);
+    config.add_route("tls.example.com".to_string(), tls_route).await.unwrap();
+    config.add_route("plain.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let info = TlsInfo {
+        version: "TLSv1.2".to_string(),
+        cipher: "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256".to_string(),
+        alpn: Some("http/1.1".to_string()),
+        sni: Some("tls.example.com".to_string()),
+    };
+    let mut req = request("tls.example.com", "/");
+    req.headers_mut().insert("x-tls-version", "spoofed".parse().unwrap());
+    req.extensions_mut().insert(info.clone());
+    handle_request_with_config(&config, "https", client(), req).await.unwrap();
+    let seen = upstream.last_request();
+    assert_eq!(seen.header("x-tls-version"), Some("TLSv1.2"));
+    assert_eq!(seen.header("x-tls-cipher"), Some("TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"));
+    assert_eq!(seen.header("x-tls-sni"), Some("tls.example.com"));
+
+    // Off by default
+    let mut req = request("plain.example.com", "/");
+    req.extensions_mut().insert(info);
+    handle_request_with_config(&config, "https", client(), req).await.unwrap();
+    assert_eq!(upstream.last_request().header("x-tls-version"), None);
+}
+
+#[tokio::test]
+async fn silent_backend_times_out_waiting_for_headers() {
+    let silent = spawn_silent_upstream().await;
+    let mut config = test_config();
+    let mut slow = route(silent.port());
+    slow.set_response_header_timeout_ms(Some(200));
+    config.add_route("silent.example.com".to_string(), slow).await.unwrap();
+
+    let started = std::time::Instant::now();
+    let resp = handle_request_with_config(&config, "http", client(), request("silent.example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(body_string(resp).await.contains("no response headers within 200 ms"));
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn slow_body_after_prompt_headers_is_not_cut_off() {
+    // Headers right away, then the body trickles in for longer than the header timeout
+    let upstream = MockUpstream::spawn_with(|_| {
+        let (mut sender, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            for chunk in ["first ", "second"] {
+                tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                let _ = sender.send_data(chunk.into()).await;
+            }
+        });
+        hyper::Response::new(body)
+    })
+    .await;
+    let mut config = test_config();
+    config.set_response_header_timeout_ms(200);
+    config.add_route("stream.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("stream.example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(body_string(resp).await, "first second");
+}
+
+#[tokio::test]
+async fn wildcard_route_matches_subdomains_but_not_apex() {
+    let upstream = MockUpstream::spawn("wild").await;
+    let mut config = test_config();
+    config.add_route("*.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("api.example.com", "/")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:wild");
+
+    let resp = handle_request_with_config(&config, "http", client(), request("example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(upstream.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn wildcard_route_with_match_apex_serves_the_bare_domain() {
+    let upstream = MockUpstream::spawn("wild").await;
+    let mut config = test_config();
+    let mut wildcard = route(upstream.port());
+    wildcard.set_match_apex(true);
+    config.add_route("*.example.com".to_string(), wildcard).await.unwrap();
+
+    for host in ["example.com", "api.example.com"] {
+        let resp = handle_request_with_config(&config, "http", client(), request(host, "/")).await.unwrap();
+        assert_eq!(body_string(resp).await, "upstream:wild");
+    }
+    // Unrelated domains sharing the suffix text stay unrouted
+    let resp = handle_request_with_config(&config, "http", client(), request("evilexample.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(upstream.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn exact_route_beats_wildcard() {
+    let wild = MockUpstream::spawn("wild").await;
+    let exact = MockUpstream::spawn("exact").await;
+    let mut config = test_config();
+    config.add_route("*.example.com".to_string(), route(wild.port())).await.unwrap();
+    config.add_route("api.example.com".to_string(), route(exact.port())).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("api.example.com", "/")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:exact");
+    assert!(wild.requests().is_empty());
+}
+
+#[tokio::test]
+async fn subroute_strips_prefix_and_keeps_query() {
+    let parent = MockUpstream::spawn("parent").await;
+    let sub = MockUpstream::spawn("sub").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(parent.port())).await.unwrap();
+    config.add_subroute("app.example.com", "/api".to_string(), sub.port()).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("app.example.com", "/api/users?page=2")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:sub");
+    assert_eq!(sub.last_request().uri, "/users?page=2");
+
+    // The subroute root maps to the upstream root
+    let resp = handle_request_with_config(&config, "http", client(), request("app.example.com", "/api")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(sub.last_request().uri, "/");
+
+    let resp = handle_request_with_config(&config, "http", client(), request("app.example.com", "/other")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:parent");
+    assert_eq!(parent.last_request().uri, "/other");
+}
+
+#[tokio::test]
+async fn forwarding_headers_are_set_once() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    handle_request_with_config(&config, "https", client(), request("app.example.com", "/")).await.unwrap();
+    let seen = upstream.last_request();
+    assert_eq!(seen.header("host"), Some("app.example.com"));
+    assert_eq!(seen.header_all("x-forwarded-for"), vec!["203.0.113.7"]);
+    assert_eq!(seen.header("x-real-ip"), Some("203.0.113.7"));
+    assert_eq!(seen.header("x-forwarded-proto"), Some("https"));
+    assert_eq!(seen.header("x-forwarded-host"), Some("app.example.com"));
+}
+
+#[tokio::test]
+async fn forwarded_for_from_untrusted_peer_is_replaced() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let mut req = request("app.example.com", "/");
+    req.headers_mut().insert("x-forwarded-for", "10.0.0.1".parse().unwrap());
+    handle_request_with_config(&config, "http", client(), req).await.unwrap();
+
+    let seen = upstream.last_request();
+    assert_eq!(seen.header_all("x-forwarded-for"), vec!["203.0.113.7"]);
+    assert_eq!(seen.header("x-real-ip"), Some("203.0.113.7"));
+}
+
+#[tokio::test]
+async fn forwarded_for_from_trusted_proxy_is_appended() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.set_trusted_proxies(vec!["203.0.113.0/24".parse().unwrap()]);
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let mut req = request("app.example.com", "/");
+    req.headers_mut().insert("x-forwarded-for", "198.51.100.9".parse().unwrap());
+    handle_request_with_config(&config, "http", client(), req).await.unwrap();
+
+    let seen = upstream.last_request();
+    assert_eq!(seen.header("x-forwarded-for"), Some("198.51.100.9, 203.0.113.7"));
+    assert_eq!(seen.header("x-real-ip"), Some("198.51.100.9"));
+}
+
+#[tokio::test]
+async fn client_hop_by_hop_headers_do_not_reach_the_upstream() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let mut req = request("app.example.com", "/");
+    let headers = req.headers_mut();
+    headers.insert("connection", "close, x-hop, x-real-ip, x-forwarded-proto".parse().unwrap());
+    headers.insert("x-hop", "1".parse().unwrap());
+    headers.insert("keep-alive", "timeout=5".parse().unwrap());
+    headers.insert("proxy-connection", "keep-alive".parse().unwrap());
+    headers.insert("te", "trailers".parse().unwrap());
+    headers.insert("trailer", "x-checksum".parse().unwrap());
+    headers.insert("x-end-to-end", "kept".parse().unwrap());
+    let resp = handle_request_with_config(&config, "https", client(), req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let seen = upstream.last_request();
+    for name in ["connection", "x-hop", "keep-alive", "proxy-connection", "te", "trailer"] {
+        assert_eq!(seen.header(name), None, "{} reached the upstream", name);
+    }
+    assert_eq!(seen.header("x-end-to-end"), Some("kept"));
+    // Headers the proxy adds cannot be stripped by naming them in Connection
+    assert_eq!(seen.header("x-real-ip"), Some("203.0.113.7"));
+    assert_eq!(seen.header("x-forwarded-proto"), Some("https"));
+}
+
+#[tokio::test]
+async fn upstream_hop_by_hop_headers_do_not_reach_the_client() {
+    let upstream = MockUpstream::spawn_with(|_req| {
+        hyper::Response::builder()
+            .header("connection", "x-backend-hop")
+            .header("x-backend-hop", "1")
+            .header("keep-alive", "timeout=5, max=100")
+            .header("proxy-connection", "keep-alive")
+            .header("x-end-to-end", "kept")
+            .body(hyper::Body::from("ok"))
+            .unwrap()
+    })
+    .await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("app.example.com", "/")).await.unwrap();
+    for name in ["connection", "x-backend-hop", "keep-alive", "proxy-connection"] {
+        assert!(resp.headers().get(name).is_none(), "{} reached the client", name);
+    }
+    assert_eq!(resp.headers()["x-end-to-end"], "kept");
+    assert_eq!(body_string(resp).await, "ok");
+}
+
+#[tokio::test]
+async fn unknown_host_is_not_found() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("other.example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(body_string(resp).await, "Not Found");
+    assert!(upstream.requests().is_empty());
+}
+
+#[tokio::test]
+async fn unknown_host_can_redirect_or_close() {
+    let mut config = test_config();
+    config.set_unknown_host(UnknownHostConfig::new(UnknownHostAction::Redirect("https://example.com/".to_string())));
+    let resp = handle_request_with_config(&config, "http", client(), request("scanner.invalid", "/wp-login.php")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert_eq!(resp.headers()["location"], "https://example.com/");
+
+    // Close: the frontend drops the connection without writing a response
+    config.set_unknown_host(UnknownHostConfig::new(UnknownHostAction::Close));
+    let err = handle_request_with_config(&config, "http", client(), request("scanner.invalid", "/")).await.unwrap_err();
+    assert!(err.is::<ConnectionClosed>());
+    let addr = spawn_proxy(config, "http").await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: scanner.invalid\r\n\r\n").await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    assert!(received.is_empty(), "got {:?}", String::from_utf8_lossy(&received));
+}
+
+#[tokio::test]
+async fn scanners_past_the_unknown_host_burst_are_disconnected() {
+    let mut config = test_config();
+    let mut unknown_host = UnknownHostConfig::default();
+    unknown_host.set_rate_limit_burst(3);
+    unknown_host.set_rate_limit_per_minute(0);
+    config.set_unknown_host(unknown_host);
+    let scanner: IpAddr = "198.51.100.80".parse().unwrap();
+
+    for host in ["a.invalid", "b.invalid"] {
+        let resp = handle_request_with_config(&config, "http", scanner, request(host, "/")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+    // Malformed Hosts draw from the same bucket
+    let resp = handle_request_with_config(&config, "http", scanner, request("bad host", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    for host in ["c.invalid", "bad host"] {
+        let err = handle_request_with_config(&config, "http", scanner, request(host, "/")).await.unwrap_err();
+        assert!(err.is::<ConnectionClosed>());
+    }
+
+    let other: IpAddr = "198.51.100.81".parse().unwrap();
+    let resp = handle_request_with_config(&config, "http", other, request("a.invalid", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn not_found_passthrough_off_answers_unmatched_paths_itself() {
+    let parent = MockUpstream::spawn("parent").await;
+    let sub = MockUpstream::spawn("sub").await;
+    let mut config = test_config();
+    let mut parent_route = route(parent.port());
+    parent_route.set_not_found_passthrough(false);
+    config.add_route("app.example.com".to_string(), parent_route).await.unwrap();
+    config.add_subroute("app.example.com", "/api".to_string(), sub.port()).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("app.example.com", "/api/users")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:sub");
+    let resp = handle_request_with_config(&config, "http", client(), request("app.example.com", "/other")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(parent.requests().is_empty());
+}
+
+#[tokio::test]
+async fn missing_host_is_an_error() {
+    let config = test_config();
+    let req = hyper::Request::builder().uri("/").body(hyper::Body::empty()).unwrap();
+    assert!(handle_request_with_config(&config, "http", client(), req).await.is_err());
+}
+
+#[tokio::test]
+async fn request_body_is_forwarded() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(upstream.port())).await.unwrap();
+
+    let req = hyper::Request::builder().method("POST").uri("/submit").header("Host", "app.example.com").body(hyper::Body::from("payload")).unwrap();
+    handle_request_with_config(&config, "http", client(), req).await.unwrap();
+
+    let seen = upstream.last_request();
+    assert_eq!(seen.method, "POST");
+    assert_eq!(seen.body, b"payload");
+}
+
+#[tokio::test]
+async fn bandwidth_limited_route_streams_at_its_cap() {
+    let payload = vec![b'x'; 200_000];
+    let body = payload.clone();
+    let upstream = MockUpstream::spawn_with(move |_| hyper::Response::new(hyper::Body::from(body.clone()))).await;
+    let mut config = test_config();
+    let mut limited = route(upstream.port());
+    // 1600 kbps = 200 KB/s, so the transfer should take about a second
+    limited.set_bandwidth_limit_kbps(Some(1600));
+    config.add_route("slow.example.com".to_string(), limited).await.unwrap();
+
+    let start = std::time::Instant::now();
+    let resp = handle_request_with_config(&config, "http", client(), request("slow.example.com", "/")).await.unwrap();
+    let received = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    let elapsed = start.elapsed().as_secs_f64();
+
+    assert_eq!(received.len(), payload.len());
+    let throughput = payload.len() as f64 / elapsed;
+    assert!((throughput - 200_000.0).abs() / 200_000.0 < 0.15, "throughput {:.0} B/s over {:.2}s", throughput, elapsed);
+}
+
+#[tokio::test]
+async fn cache_control_rules_fill_in_missing_headers_only() {
+    let upstream = MockUpstream::spawn_with(|req| {
+        let builder = hyper::Response::builder();
+        let builder = match req.uri.as_str() {
+            "/logo.png" => builder.header("Content-Type", "image/png"),
+            "/private.png" => builder.header("Content-Type", "image/png").header("Cache-Control", "private, no-store"),
+            _ => builder.header("Content-Type", "text/html"),
+        };
+        builder.body(hyper::Body::empty()).unwrap()
+    })
+    .await;
+    let mut route = route(upstream.port());
+    route.set_cache_control(vec![
+        minipx::config::CacheControlRule::new("public, max-age=86400").unwrap().with_content_types(["image/*"]).with_expires(true),
+        minipx::config::CacheControlRule::new("no-cache").unwrap().with_paths(["/assets/**"]),
+    ]);
+    let mut config = test_config();
+    config.add_route("cached.example.com".to_string(), route).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("cached.example.com", "/logo.png")).await.unwrap();
+    assert_eq!(resp.headers()["cache-control"], "public, max-age=86400");
+    assert!(resp.headers().contains_key("expires"));
+
+    let resp = handle_request_with_config(&config, "http", client(), request("cached.example.com", "/private.png")).await.unwrap();
+    assert_eq!(resp.headers()["cache-control"], "private, no-store");
+
+    let resp = handle_request_with_config(&config, "http", client(), request("cached.example.com", "/assets/app")).await.unwrap();
+    assert_eq!(resp.headers()["cache-control"], "no-cache");
+
+    let resp = handle_request_with_config(&config, "http", client(), request("cached.example.com", "/index")).await.unwrap();
+    assert!(!resp.headers().contains_key("cache-control"));
+}
+
+#[tokio::test]
+async fn routes_outside_their_activation_window_are_not_proxied() {
+    use minipx::config::InactiveResponse;
+    use minipx::utils::timestamp::parse_rfc3339;
+
+    let upstream = MockUpstream::spawn("event").await;
+    let mut upcoming = route(upstream.port());
+    upcoming.set_active_from(Some(parse_rfc3339("2999-01-01T00:00:00Z").unwrap()));
+    let mut maintenance = upcoming.clone();
+    maintenance.set_inactive_response(InactiveResponse::Maintenance);
+    let mut over = route(upstream.port());
+    over.set_active_until(Some(parse_rfc3339("2000-01-01T00:00:00Z").unwrap()));
+    let mut running = route(upstream.port());
+    running.set_active_from(Some(parse_rfc3339("2000-01-01T00:00:00Z").unwrap()));
+    running.set_active_until(Some(parse_rfc3339("2999-01-01T00:00:00Z").unwrap()));
+    let mut config = test_config();
+    config.add_route("upcoming.example.com".to_string(), upcoming).await.unwrap();
+    config.add_route("maintenance.example.com".to_string(), maintenance).await.unwrap();
+    config.add_route("over.example.com".to_string(), over).await.unwrap();
+    config.add_route("running.example.com".to_string(), running).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", client(), request("upcoming.example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = handle_request_with_config(&config, "http", client(), request("maintenance.example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(resp.headers().contains_key("retry-after"));
+    let resp = handle_request_with_config(&config, "http", client(), request("over.example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(upstream.requests().is_empty());
+
+    let resp = handle_request_with_config(&config, "http", client(), request("running.example.com", "/")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:event");
+}
+
+#[tokio::test]
+async fn exempt_paths_are_served_over_http_despite_the_https_redirect() {
+    let upstream = MockUpstream::spawn("app").await;
+    let mut config = test_config();
+    config.set_email("admin@example.com".to_string());
+    config.set_redirect_exempt_paths(vec!["/status".to_string()]);
+    let mut redirecting = minipx::config::ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), upstream.port(), true, None, true);
+    redirecting.set_redirect_exempt_paths(vec!["/public/**".to_string()]);
+    config.add_route("secure.example.com".to_string(), redirecting).await.unwrap();
+
+    for path in ["/.well-known/acme-challenge/token123", "/__minipx/health", "/status", "/public/css/site.css"] {
+        let resp = handle_request_with_config(&config, "http", client(), request("secure.example.com", path)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "{}", path);
+    }
+    // The challenge was proxied to the backend rather than answered by the proxy
+    assert_eq!(upstream.requests().iter().filter(|seen| seen.uri.starts_with("/.well-known/")).count(), 1);
+
+    for path in ["/", "/statusx", "/public", "/.well-known/security.txt"] {
+        let resp = handle_request_with_config(&config, "http", client(), request("secure.example.com", path)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn debug_headers_name_the_route_and_backend_for_loopback_clients() {
+    let upstream = MockUpstream::spawn("debug").await;
+    let mut config = test_config();
+    config.add_route("debug.example.com".to_string(), route(upstream.port())).await.unwrap();
+    let loopback = IpAddr::from([127, 0, 0, 1]);
+
+    let resp = handle_request_with_config(&config, "http", loopback, request("debug.example.com", "/")).await.unwrap();
+    assert!(resp.headers().get(UPSTREAM_HEADER).is_none());
+
+    config.set_debug_headers(true);
+    let resp = handle_request_with_config(&config, "http", loopback, request("debug.example.com", "/")).await.unwrap();
+    assert_eq!(resp.headers()[UPSTREAM_HEADER], format!("http://127.0.0.1:{}", upstream.port()));
+    assert_eq!(resp.headers()[ROUTE_HEADER], "debug.example.com");
+
+    // Remote clients never learn the backend address
+    let resp = handle_request_with_config(&config, "http", client(), request("debug.example.com", "/")).await.unwrap();
+    assert!(resp.headers().get(UPSTREAM_HEADER).is_none());
+    assert!(resp.headers().get(ROUTE_HEADER).is_none());
+}
@@ -0,0 +1,81 @@
+//! debug_routing_headers: the X-Minipx-* headers name the route, subroute, backend and decision behind a response,
+//! and are never sent with the flag off.
+
+mod common;
+
+use common::{MockUpstream, client, request, route, test_config};
+use hyper::{Body, Response, StatusCode};
+use minipx::config::{Config, InactiveResponse, ProxyPathRoute, ProxyRoute};
+use minipx::proxy::request_handler::handle_request_with_config;
+use minipx::proxy::routing_trace::{DECISION_HEADER, ROUTE_HEADER, SUBROUTE_HEADER, UPSTREAM_HEADER};
+use minipx::utils::timestamp::parse_rfc3339;
+use std::net::IpAddr;
+
+const HEADERS: [&str; 4] = [ROUTE_HEADER, SUBROUTE_HEADER, UPSTREAM_HEADER, DECISION_HEADER];
+
+// Route, subroute, upstream and decision headers of a response, or None when it carries none of them
+fn trace(resp: &Response<Body>) -> Option<[String; 4]> {
+    if HEADERS.iter().all(|name| !resp.headers().contains_key(*name)) {
+        return None;
+    }
+    Some(HEADERS.map(|name| resp.headers()[name].to_str().unwrap().to_string()))
+}
+
+async fn config_with_routes(upstream: &MockUpstream, api: &MockUpstream) -> Config {
+    let mut config = test_config();
+    config.set_email("admin@example.com".to_string());
+    let app = route(upstream.port()).with_subroutes(vec![ProxyPathRoute::new("/api".to_string(), api.port()).unwrap()]);
+    config.add_route("app.example.com".to_string(), app).await.unwrap();
+    let secure = ProxyRoute::new("127.0.0.1".to_string(), "".to_string(), upstream.port(), true, None, true);
+    config.add_route("secure.example.com".to_string(), secure).await.unwrap();
+    let mut maintenance = route(upstream.port());
+    maintenance.set_active_from(Some(parse_rfc3339("2999-01-01T00:00:00Z").unwrap()));
+    maintenance.set_inactive_response(InactiveResponse::Maintenance);
+    config.add_route("maintenance.example.com".to_string(), maintenance).await.unwrap();
+    config
+}
+
+#[tokio::test]
+async fn routing_headers_explain_each_decision() {
+    let (upstream, api) = (MockUpstream::spawn("trace").await, MockUpstream::spawn("api").await);
+    let mut config = config_with_routes(&upstream, &api).await;
+    config.set_debug_routing_headers(true);
+    let backend = format!("http://127.0.0.1:{}", upstream.port());
+    let api_backend = format!("http://127.0.0.1:{}", api.port());
+
+    let mut smuggling = request("app.example.com", "/api/users");
+    smuggling.headers_mut().insert("content-length", "4".parse().unwrap());
+    smuggling.headers_mut().insert("transfer-encoding", "chunked".parse().unwrap());
+
+    let cases = [
+        (request("app.example.com", "/api/users"), StatusCode::OK, ["app.example.com", "/api", &api_backend, "proxied"]),
+        (request("app.example.com", "/"), StatusCode::OK, ["app.example.com", "-", &backend, "proxied"]),
+        (request("secure.example.com", "/login"), StatusCode::MOVED_PERMANENTLY, ["secure.example.com", "-", "-", "redirected"]),
+        (request("maintenance.example.com", "/"), StatusCode::SERVICE_UNAVAILABLE, ["maintenance.example.com", "-", "-", "maintenance"]),
+        (smuggling, StatusCode::BAD_REQUEST, ["app.example.com", "/api", "-", "blocked"]),
+        (request("nowhere.example.com", "/"), StatusCode::NOT_FOUND, ["-", "-", "-", "not_found"]),
+    ];
+    for (req, status, expected) in cases {
+        let path = format!("{}{}", req.headers()["host"].to_str().unwrap(), req.uri());
+        let resp = handle_request_with_config(&config, "http", client(), req).await.unwrap();
+        assert_eq!(resp.status(), status, "{}", path);
+        assert_eq!(trace(&resp), Some(expected.map(str::to_string)), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn routing_headers_are_never_sent_with_the_flag_off() {
+    let (upstream, api) = (MockUpstream::spawn("trace").await, MockUpstream::spawn("api").await);
+    let config = config_with_routes(&upstream, &api).await;
+    assert!(!config.is_debug_routing_headers());
+
+    for (host, path) in
+        [("app.example.com", "/api/users"), ("secure.example.com", "/login"), ("maintenance.example.com", "/"), ("nowhere.example.com", "/")]
+    {
+        let resp = handle_request_with_config(&config, "http", client(), request(host, path)).await.unwrap();
+        assert_eq!(trace(&resp), None, "{}{}", host, path);
+    }
+    // Not even to loopback clients, which only get debug_headers' X-Minipx-Route / X-Upstream when that is on
+    let resp = handle_request_with_config(&config, "http", IpAddr::from([127, 0, 0, 1]), request("app.example.com", "/")).await.unwrap();
+    assert_eq!(trace(&resp), None);
+}
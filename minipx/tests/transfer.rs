@@ -0,0 +1,46 @@
+//! Transfer accounting: body bytes of proxied requests are counted per route and per client as they stream, with or
+//! without a Content-Length.
+
+mod common;
+
+use common::{MockUpstream, body_string, request, route, test_config};
+use hyper::{Body, Method};
+use minipx::proxy::request_handler::handle_request_with_config;
+use minipx::proxy::transfer::{ClientTransfer, RouteTransfer, transfer_stats};
+use std::net::IpAddr;
+use std::time::Duration;
+
+#[tokio::test]
+async fn streamed_bodies_are_counted_per_route_and_client() {
+    let upstream = MockUpstream::spawn("transfer").await;
+    let mut config = test_config();
+    config.add_route("transfer.example.com".to_string(), route(upstream.port())).await.unwrap();
+    let client = IpAddr::from([203, 0, 113, 77]);
+
+    // A chunked upload: no Content-Length to go by
+    let mut req = request("transfer.example.com", "/upload");
+    *req.method_mut() = Method::POST;
+    *req.body_mut() = Body::wrap_stream(futures_util::stream::iter([Ok::<_, std::io::Error>("abc"), Ok("defg")]));
+    let resp = handle_request_with_config(&config, "http", client, req).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:transfer");
+    assert_eq!(upstream.last_request().body, b"abcdefg");
+
+    let route = &transfer_stats(false).routes["transfer.example.com"];
+    assert_eq!(*route, RouteTransfer { requests: 1, request_bytes: 7, response_bytes: 17 });
+
+    // The client's total lands once the upload body has been released by the connection as well
+    let expected = ClientTransfer { ip: client, bytes: 24, error: 0 };
+    for _ in 0..100 {
+        if transfer_stats(false).top_clients.contains(&expected) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(transfer_stats(false).top_clients.contains(&expected), "{:?}", transfer_stats(false).top_clients);
+
+    // Reading with reset returns the totals and starts them over
+    assert_eq!(transfer_stats(true).routes["transfer.example.com"].request_bytes, 7);
+    let after = transfer_stats(false);
+    assert_eq!(after.routes["transfer.example.com"], RouteTransfer::default());
+    assert!(after.top_clients.is_empty());
+}
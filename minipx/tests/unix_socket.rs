@@ -0,0 +1,121 @@
+//! Routes whose backend listens on a unix domain socket: plain requests and websocket upgrades go through the
+//! socket, while subroutes of the same route keep using their TCP ports.
+#![cfg(unix)]
+
+mod common;
+
+use common::{MockUpstream, body_string, request, spawn_proxy, test_config};
+use futures_util::{SinkExt, StreamExt};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use minipx::config::ProxyRoute;
+use minipx::proxy::request_handler::handle_request_with_config;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::net::UnixListener;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+// A fresh socket path in a per-process temp directory
+fn socket_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("minipx-unix-socket-tests-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn socket_route(socket: &Path) -> ProxyRoute {
+    let mut route = ProxyRoute::new("localhost".to_string(), "".to_string(), 0, false, None, false);
+    route.set_unix_socket(Some(socket.to_path_buf()));
+    route
+}
+
+// A hyper backend on `socket` answering `upstream:socket`, recording the path and Host of every request
+async fn spawn_socket_upstream(socket: &Path) -> Arc<Mutex<Vec<(String, String)>>> {
+    let listener = UnixListener::bind(socket).unwrap();
+    let requests: Arc<Mutex<Vec<(String, String)>>> = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            let service = service_fn(move |req: Request<Body>| {
+                let host = req.headers().get("host").and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+                recorded.lock().unwrap().push((req.uri().to_string(), host));
+                async { Ok::<_, Infallible>(Response::new(Body::from("upstream:socket"))) }
+            });
+            tokio::spawn(Http::new().serve_connection(stream, service));
+        }
+    });
+    requests
+}
+
+// A websocket backend on `socket` echoing every text message
+async fn spawn_socket_ws_echo(socket: &Path) {
+    let listener = UnixListener::bind(socket).unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+                    return;
+                };
+                while let Some(Ok(msg)) = ws.next().await {
+                    if msg.is_text() && ws.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[tokio::test]
+async fn requests_are_proxied_through_the_socket() {
+    let socket = socket_path("app.sock");
+    let requests = spawn_socket_upstream(&socket).await;
+    let api = MockUpstream::spawn("api").await;
+    let mut config = test_config();
+    config.add_route("sock.example.com".to_string(), socket_route(&socket)).await.unwrap();
+    config.add_subroute("sock.example.com", "/tcp".to_string(), api.port()).await.unwrap();
+    let client = IpAddr::from([203, 0, 113, 12]);
+
+    let resp = handle_request_with_config(&config, "http", client, request("sock.example.com", "/api/users?page=2")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:socket");
+    assert_eq!(requests.lock().unwrap().as_slice(), [("/api/users?page=2".to_string(), "sock.example.com".to_string())]);
+
+    // Subroutes are still served from their TCP port
+    let resp = handle_request_with_config(&config, "http", client, request("sock.example.com", "/tcp/status")).await.unwrap();
+    assert_eq!(body_string(resp).await, "upstream:api");
+    assert_eq!(api.last_request().uri, "/status");
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn a_missing_socket_is_a_proxy_error() {
+    let socket = socket_path("missing.sock");
+    let mut config = test_config();
+    config.add_route("gone.example.com".to_string(), socket_route(&socket)).await.unwrap();
+
+    let resp = handle_request_with_config(&config, "http", IpAddr::from([203, 0, 113, 12]), request("gone.example.com", "/")).await.unwrap();
+    assert_eq!(resp.status(), 500);
+}
+
+#[tokio::test]
+async fn websocket_upgrades_go_through_the_socket() {
+    let socket = socket_path("ws.sock");
+    spawn_socket_ws_echo(&socket).await;
+    let mut config = test_config();
+    config.add_route("ws.example.com".to_string(), socket_route(&socket)).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    let mut req = format!("ws://{}/socket", proxy).into_client_request().unwrap();
+    req.headers_mut().insert("Host", "ws.example.com".parse().unwrap());
+    let (mut ws, resp) = tokio_tungstenite::connect_async(req).await.expect("websocket handshake through proxy");
+    assert_eq!(resp.status(), 101);
+    ws.send(Message::text("over the socket")).await.unwrap();
+    assert_eq!(ws.next().await.expect("echo reply").unwrap().into_text().unwrap().as_str(), "over the socket");
+    let _ = ws.close(None).await;
+}
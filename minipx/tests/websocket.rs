@@ -0,0 +1,191 @@
+//! Websocket upgrade pass-through through a real proxy frontend on an ephemeral port.
+
+mod common;
+
+use common::{route, spawn_proxy, spawn_silent_upstream, spawn_ws_echo_upstream, spawn_ws_echo_upstream_selecting, test_config};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+async fn ws_round_trip(proxy: std::net::SocketAddr, host: &str, path: &str, payload: &str) -> String {
+    let mut req = format!("ws://{}{}", proxy, path).into_client_request().unwrap();
+    req.headers_mut().insert("Host", host.parse().unwrap());
+    let (mut ws, resp) = tokio_tungstenite::connect_async(req).await.expect("websocket handshake through proxy");
+    assert_eq!(resp.status(), 101);
+
+    ws.send(Message::text(payload)).await.unwrap();
+    let reply = ws.next().await.expect("echo reply").unwrap();
+    let _ = ws.close(None).await;
+    reply.into_text().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn websocket_upgrade_is_tunneled() {
+    let (upstream, paths) = spawn_ws_echo_upstream().await;
+    let mut config = test_config();
+    config.add_route("ws.example.com".to_string(), route(upstream.port())).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    assert_eq!(ws_round_trip(proxy, "ws.example.com", "/socket", "hello").await, "hello");
+    assert_eq!(paths.lock().unwrap().as_slice(), ["/socket"]);
+}
+
+#[tokio::test]
+async fn websocket_upgrade_survives_extra_connection_options() {
+    let (upstream, paths) = spawn_ws_echo_upstream().await;
+    let mut config = test_config();
+    config.add_route("ws.example.com".to_string(), route(upstream.port())).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    let mut req = format!("ws://{}/socket", proxy).into_client_request().unwrap();
+    req.headers_mut().insert("Host", "ws.example.com".parse().unwrap());
+    req.headers_mut().insert("Connection", "keep-alive, Upgrade, x-hop".parse().unwrap());
+    req.headers_mut().insert("x-hop", "1".parse().unwrap());
+    req.headers_mut().insert("Keep-Alive", "timeout=5".parse().unwrap());
+    let (mut ws, resp) = tokio_tungstenite::connect_async(req).await.expect("websocket handshake through proxy");
+    assert_eq!(resp.status(), 101);
+    assert_eq!(resp.headers()["upgrade"].to_str().unwrap().to_ascii_lowercase(), "websocket");
+
+    ws.send(Message::text("hello")).await.unwrap();
+    assert_eq!(ws.next().await.expect("echo reply").unwrap().into_text().unwrap().as_str(), "hello");
+    let _ = ws.close(None).await;
+    assert_eq!(paths.lock().unwrap().as_slice(), ["/socket"]);
+}
+
+#[tokio::test]
+async fn websocket_subroute_strips_prefix() {
+    let (parent, _) = spawn_ws_echo_upstream().await;
+    let (sub, sub_paths) = spawn_ws_echo_upstream().await;
+    let mut config = test_config();
+    config.add_route("app.example.com".to_string(), route(parent.port())).await.unwrap();
+    config.add_subroute("app.example.com", "/ws".to_string(), sub.port()).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    assert_eq!(ws_round_trip(proxy, "app.example.com", "/ws/chat", "ping").await, "ping");
+    assert_eq!(sub_paths.lock().unwrap().as_slice(), ["/chat"]);
+}
+
+#[tokio::test]
+async fn unanswered_websocket_handshake_times_out() {
+    let silent = spawn_silent_upstream().await;
+    let mut config = test_config();
+    config.set_response_header_timeout_ms(200);
+    config.add_route("ws.example.com".to_string(), route(silent.port())).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    let mut req = format!("ws://{}/socket", proxy).into_client_request().unwrap();
+    req.headers_mut().insert("Host", "ws.example.com".parse().unwrap());
+    match tokio_tungstenite::connect_async(req).await.unwrap_err() {
+        tokio_tungstenite::tungstenite::Error::Http(resp) => {
+            assert_eq!(resp.status(), 504);
+            let body = String::from_utf8(resp.body().clone().unwrap_or_default()).unwrap();
+            assert!(body.contains("websocket handshake response within 200 ms"), "{}", body);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[tokio::test]
+async fn websocket_to_unknown_host_is_rejected() {
+    let config = test_config();
+    let proxy = spawn_proxy(config, "http").await;
+
+    let mut req = format!("ws://{}/", proxy).into_client_request().unwrap();
+    req.headers_mut().insert("Host", "nowhere.example.com".parse().unwrap());
+    let err = tokio_tungstenite::connect_async(req).await.unwrap_err();
+    match err {
+        tokio_tungstenite::tungstenite::Error::Http(resp) => assert_eq!(resp.status(), 404),
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+// Handshake through the proxy with an Origin and offered subprotocols; the HTTP status on failure
+async fn ws_handshake(proxy: std::net::SocketAddr, host: &str, origin: &str, protocols: &str) -> Result<Option<String>, u16> {
+    let mut req = format!("ws://{}/graphql", proxy).into_client_request().unwrap();
+    req.headers_mut().insert("Host", host.parse().unwrap());
+    req.headers_mut().insert("Origin", origin.parse().unwrap());
+    req.headers_mut().insert("Sec-WebSocket-Protocol", protocols.parse().unwrap());
+    match tokio_tungstenite::connect_async(req).await {
+        Ok((mut ws, resp)) => {
+            let _ = ws.close(None).await;
+            Ok(resp.headers().get("Sec-WebSocket-Protocol").map(|v| v.to_str().unwrap().to_string()))
+        }
+        Err(tokio_tungstenite::tungstenite::Error::Http(resp)) => Err(resp.status().as_u16()),
+        Err(other) => panic!("unexpected error: {other}"),
+    }
+}
+
+fn graphql_only_route(port: u16) -> minipx::config::ProxyRoute {
+    let mut route = route(port);
+    route.set_ws_allowed_origins(vec!["https://app.example.com".to_string()]);
+    route.set_ws_allowed_protocols(vec!["graphql-ws".to_string()]);
+    route
+}
+
+#[tokio::test]
+async fn websocket_allowed_origin_and_protocol_are_tunneled() {
+    let (upstream, paths) = spawn_ws_echo_upstream_selecting(Some("graphql-ws")).await;
+    let mut config = test_config();
+    config.add_route("gql.example.com".to_string(), graphql_only_route(upstream.port())).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    let selected = ws_handshake(proxy, "gql.example.com", "https://app.example.com", "graphql-ws").await;
+    assert_eq!(selected, Ok(Some("graphql-ws".to_string())));
+    assert_eq!(paths.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn websocket_blocked_origin_is_forbidden() {
+    let (upstream, paths) = spawn_ws_echo_upstream_selecting(Some("graphql-ws")).await;
+    let mut config = test_config();
+    config.add_route("gql.example.com".to_string(), graphql_only_route(upstream.port())).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    assert_eq!(ws_handshake(proxy, "gql.example.com", "https://evil.example.net", "graphql-ws").await, Err(403));
+    assert_eq!(ws_handshake(proxy, "gql.example.com", "https://app.example.com", "chat").await, Err(403));
+    assert!(paths.lock().unwrap().is_empty(), "rejected handshakes must not reach the backend");
+}
+
+#[tokio::test]
+async fn websocket_upstream_selecting_unoffered_protocol_is_bad_gateway() {
+    let (upstream, paths) = spawn_ws_echo_upstream_selecting(Some("chat")).await;
+    let mut config = test_config();
+    config.add_route("ws.example.com".to_string(), route(upstream.port())).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    assert_eq!(ws_handshake(proxy, "ws.example.com", "https://app.example.com", "graphql-ws").await, Err(502));
+    assert_eq!(paths.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn websocket_session_bytes_are_counted() {
+    let (upstream, _) = spawn_ws_echo_upstream().await;
+    let mut config = test_config();
+    config.add_route("bytes.ws.example.com".to_string(), route(upstream.port())).await.unwrap();
+    let proxy = spawn_proxy(config, "http").await;
+
+    let mut req = format!("ws://{}/socket", proxy).into_client_request().unwrap();
+    req.headers_mut().insert("Host", "bytes.ws.example.com".parse().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(req).await.expect("websocket handshake through proxy");
+    // Masked client frames carry 6 header bytes (8 from 126 bytes on), unmasked server frames 2 (4)
+    for payload in ["hello".to_string(), "x".repeat(200)] {
+        ws.send(Message::text(payload.clone())).await.unwrap();
+        assert_eq!(ws.next().await.expect("echo reply").unwrap().into_text().unwrap().as_str(), payload);
+    }
+    // Hang up without a close frame so only the data frames are counted
+    if let tokio_tungstenite::MaybeTlsStream::Plain(tcp) = ws.get_mut() {
+        tokio::io::AsyncWriteExt::shutdown(tcp).await.unwrap();
+    }
+
+    let stats = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            match minipx::proxy::ws_stats::websocket_stats().remove("bytes.ws.example.com") {
+                Some(stats) if stats.closed_eof == 1 => break stats,
+                _ => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+            }
+        }
+    })
+    .await
+    .expect("websocket session closed");
+    assert_eq!((stats.active, stats.bytes_to_upstream, stats.bytes_to_client), (0, 11 + 208, 7 + 204));
+}
@@ -1,5 +1,5 @@
 use actix_multipart::Multipart;
-use actix_web::{HttpResponse, Result as ActixResult, web};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, web};
 use chrono::Utc;
 use futures_util::StreamExt;
 use log::*;
@@ -8,8 +8,10 @@ use std::fs;
 use std::path::PathBuf;
 use uuid::Uuid;
 
+use crate::etag::{Resource, Versions, not_modified, tagged_json};
 use crate::http_error::Error;
 use crate::models::*;
+use crate::upload::{self, PemKind, UploadLimits};
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -22,16 +24,31 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
     );
 }
 
-async fn list_certificates(pool: web::Data<SqlitePool>) -> ActixResult<HttpResponse> {
+async fn list_certificates(req: HttpRequest, pool: web::Data<SqlitePool>, versions: web::Data<Versions>) -> ActixResult<HttpResponse> {
+    let etag = versions.etag(Resource::Certificates);
+    if let Some(response) = not_modified(&req, &etag) {
+        return Ok(response);
+    }
+
     let certificates = sqlx::query_as::<_, Certificate>("SELECT * FROM certificates ORDER BY created_at DESC")
         .fetch_all(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
 
-    Ok(HttpResponse::Ok().json(certificates))
+    Ok(tagged_json(etag, certificates))
 }
 
-async fn get_certificate(pool: web::Data<SqlitePool>, id: web::Path<String>) -> ActixResult<HttpResponse> {
+async fn get_certificate(
+    req: HttpRequest,
+    pool: web::Data<SqlitePool>,
+    versions: web::Data<Versions>,
+    id: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    let etag = versions.etag(Resource::Certificates);
+    if let Some(response) = not_modified(&req, &etag) {
+        return Ok(response);
+    }
+
     let certificate = sqlx::query_as::<_, Certificate>("SELECT * FROM certificates WHERE id = ?")
         .bind(id.as_str())
         .fetch_optional(pool.get_ref())
@@ -39,10 +56,14 @@ async fn get_certificate(pool: web::Data<SqlitePool>, id: web::Path<String>) ->
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?
         .ok_or_else(|| Error::from(anyhow::anyhow!("Certificate not found")))?;
 
-    Ok(HttpResponse::Ok().json(certificate))
+    Ok(tagged_json(etag, certificate))
 }
 
-async fn create_certificate(pool: web::Data<SqlitePool>, req: web::Json<CreateCertificateRequest>) -> ActixResult<HttpResponse> {
+async fn create_certificate(
+    pool: web::Data<SqlitePool>,
+    versions: web::Data<Versions>,
+    req: web::Json<CreateCertificateRequest>,
+) -> ActixResult<HttpResponse> {
     let id = Uuid::new_v4().to_string();
     let now = Utc::now().to_rfc3339();
 
@@ -65,6 +86,7 @@ async fn create_certificate(pool: web::Data<SqlitePool>, req: web::Json<CreateCe
     .execute(pool.get_ref())
     .await
     .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Certificates);
 
     let certificate = sqlx::query_as::<_, Certificate>("SELECT * FROM certificates WHERE id = ?")
         .bind(&id)
@@ -76,7 +98,7 @@ async fn create_certificate(pool: web::Data<SqlitePool>, req: web::Json<CreateCe
     Ok(HttpResponse::Created().json(certificate))
 }
 
-async fn delete_certificate(pool: web::Data<SqlitePool>, id: web::Path<String>) -> ActixResult<HttpResponse> {
+async fn delete_certificate(pool: web::Data<SqlitePool>, versions: web::Data<Versions>, id: web::Path<String>) -> ActixResult<HttpResponse> {
     let certificate = sqlx::query_as::<_, Certificate>("SELECT * FROM certificates WHERE id = ?")
         .bind(id.as_str())
         .fetch_optional(pool.get_ref())
@@ -89,6 +111,7 @@ async fn delete_certificate(pool: web::Data<SqlitePool>, id: web::Path<String>)
         .execute(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Certificates);
 
     // Delete certificate files if not Let's Encrypt
     if !certificate.is_letsencrypt {
@@ -100,7 +123,12 @@ async fn delete_certificate(pool: web::Data<SqlitePool>, id: web::Path<String>)
     Ok(HttpResponse::NoContent().finish())
 }
 
-async fn upload_certificate(pool: web::Data<SqlitePool>, mut payload: Multipart) -> ActixResult<HttpResponse> {
+async fn upload_certificate(
+    pool: web::Data<SqlitePool>,
+    versions: web::Data<Versions>,
+    limits: web::Data<UploadLimits>,
+    mut payload: Multipart,
+) -> ActixResult<HttpResponse> {
     let mut cert_id: Option<String> = None;
     let mut cert_saved = false;
     let mut key_saved = false;
@@ -114,31 +142,28 @@ async fn upload_certificate(pool: web::Data<SqlitePool>, mut payload: Multipart)
         let field_name = content_disposition.and_then(|cd| cd.get_name()).map(|s| s.to_string()).unwrap_or_default();
 
         if field_name == "certificateId" {
-            let mut data = Vec::new();
-            while let Some(chunk) = field.next().await {
-                let data_chunk = chunk.map_err(|e| Error::from(anyhow::anyhow!("Chunk read error: {}", e)))?;
-                data.extend_from_slice(&data_chunk);
-            }
-            cert_id = Some(String::from_utf8_lossy(&data).to_string());
+            // The id becomes a directory name, so it must be one of ours
+            let id = upload::parse_id(&upload::read_text(&mut field).await?)?;
+            let certificate = sqlx::query_as::<_, Certificate>("SELECT * FROM certificates WHERE id = ?")
+                .bind(&id)
+                .fetch_optional(pool.get_ref())
+                .await
+                .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?
+                .ok_or_else(|| Error::from(anyhow::anyhow!("Certificate not found")))?;
+            cert_id = Some(certificate.id);
         } else if field_name == "cert" || field_name == "key" {
-            if cert_id.is_none() {
+            let Some(cid) = &cert_id else {
                 return Err(Error::from(anyhow::anyhow!("certificateId must be provided first")).into());
-            }
+            };
 
-            let cid = cert_id.as_ref().unwrap();
             let cert_dir = PathBuf::from("certificates").join(cid);
             fs::create_dir_all(&cert_dir).map_err(|e| Error::from(anyhow::anyhow!("Failed to create directory: {}", e)))?;
 
-            let filename = if field_name == "cert" { "cert.pem" } else { "key.pem" };
-            let filepath = cert_dir.join(filename);
-
-            let mut file = fs::File::create(&filepath).map_err(|e| Error::from(anyhow::anyhow!("Failed to create file: {}", e)))?;
-
-            while let Some(chunk) = field.next().await {
-                let data = chunk.map_err(|e| Error::from(anyhow::anyhow!("Chunk read error: {}", e)))?;
-                use std::io::Write;
-                file.write_all(&data).map_err(|e| Error::from(anyhow::anyhow!("Failed to write file: {}", e)))?;
-            }
+            let (filename, kind) = if field_name == "cert" { ("cert.pem", PemKind::Certificate) } else { ("key.pem", PemKind::PrivateKey) };
+            // Parsed before anything is written, so a bad upload leaves the previous files in place
+            let data = upload::read_field(&mut field, limits.pem_bytes).await?;
+            upload::check_pem(&data, kind)?;
+            upload::write_file(&cert_dir, filename, &data)?;
 
             if field_name == "cert" {
                 cert_saved = true;
@@ -164,7 +189,98 @@ async fn upload_certificate(pool: web::Data<SqlitePool>, mut payload: Multipart)
             .execute(pool.get_ref())
             .await
             .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+        versions.bump(Resource::Certificates);
     }
 
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Certificate uploaded successfully"})))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::MAX_PEM_BYTES;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header;
+    use actix_web::{App, test};
+
+    #[actix_web::test]
+    async fn test_list_answers_304_until_a_certificate_is_added() {
+        let pool = crate::db::memory_database().await.unwrap();
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool)).app_data(web::Data::new(Versions::new())).service(web::scope("/api").configure(configure)),
+        )
+        .await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/certificates").to_request()).await;
+        let etag = resp.headers().get(header::ETAG).cloned().unwrap();
+        let certificates: Vec<Certificate> = test::read_body_json(resp).await;
+        assert!(certificates.is_empty());
+
+        let conditional = || test::TestRequest::get().uri("/api/certificates").insert_header((header::IF_NONE_MATCH, etag.clone())).to_request();
+        let resp = test::call_service(&app, conditional()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert!(test::read_body(resp).await.is_empty());
+
+        let create =
+            test::TestRequest::post().uri("/api/certificates").set_json(serde_json::json!({ "name": "app", "domain": "app.test" })).to_request();
+        assert_eq!(test::call_service(&app, create).await.status(), StatusCode::CREATED);
+
+        let resp = test::call_service(&app, conditional()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_ne!(resp.headers().get(header::ETAG), Some(&etag));
+        let certificates: Vec<Certificate> = test::read_body_json(resp).await;
+        assert_eq!(certificates.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_accepts_only_pem_within_the_limit() {
+        let id = uuid::Uuid::new_v4().to_string();
+        let pool = crate::db::memory_database().await.unwrap();
+        sqlx::query("INSERT INTO certificates (id, name, domain, cert_path, is_letsencrypt, created_at, updated_at) VALUES (?, 'app', 'app.test', '', 1, '', '')")
+            .bind(&id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Versions::new()))
+                .app_data(web::Data::new(UploadLimits::default()))
+                .service(web::scope("/api").configure(configure)),
+        )
+        .await;
+        let upload = |fields: &[(&str, &[u8])]| {
+            let mut all = vec![("certificateId", None, id.as_bytes())];
+            all.extend(fields.iter().map(|(name, data)| (*name, Some("upload.pem"), *data)));
+            let (content_type, body) = upload::multipart_body(&all);
+            test::TestRequest::post()
+                .uri("/api/certificates/upload")
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .set_payload(body)
+                .to_request()
+        };
+        let block = |tag: &str| pem::encode(&pem::Pem::new(tag, vec![1, 2, 3]));
+        let (cert, key) = (block("CERTIFICATE"), block("PRIVATE KEY"));
+        let cert_dir = PathBuf::from("certificates").join(&id);
+
+        let resp = test::call_service(&app, upload(&[("cert", b"not a certificate")])).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = test::call_service(&app, upload(&[("cert", key.as_bytes())])).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let oversized = vec![b'A'; MAX_PEM_BYTES as usize + 1];
+        let resp = test::call_service(&app, upload(&[("key", &oversized)])).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(fs::read_dir(&cert_dir).map(|entries| entries.count()).unwrap_or_default(), 0);
+
+        let resp = test::call_service(&app, upload(&[("cert", cert.as_bytes()), ("key", key.as_bytes())])).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(fs::read_to_string(cert_dir.join("cert.pem")).unwrap(), cert);
+        assert_eq!(fs::read_to_string(cert_dir.join("key.pem")).unwrap(), key);
+        assert_eq!(fs::read_dir(&cert_dir).unwrap().count(), 2);
+        let certificate = sqlx::query_as::<_, Certificate>("SELECT * FROM certificates WHERE id = ?").bind(&id).fetch_one(&pool).await.unwrap();
+        assert_eq!(certificate.key_path, Some(format!("certificates/{}/key.pem", id)));
+
+        fs::remove_dir_all(&cert_dir).unwrap();
+        let _ = fs::remove_dir("certificates");
+    }
+}
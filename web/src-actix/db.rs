@@ -14,7 +14,20 @@ pub async fn init_database() -> Result<SqlitePool> {
     let pool = SqlitePoolOptions::new().max_connections(5).connect_with(connect_options).await?;
 
     // Run migrations
-    sqlx::query(include_str!("../migrations/001_initial_schema.sql")).execute(&pool).await?;
+    migrate(&pool).await?;
 
     Ok(pool)
 }
+
+/// A fresh in-memory database with the schema, for tests; one connection, as each would get its own database
+#[cfg(test)]
+pub async fn memory_database() -> Result<SqlitePool> {
+    let pool = SqlitePoolOptions::new().max_connections(1).idle_timeout(None).max_lifetime(None).connect("sqlite::memory:").await?;
+    migrate(&pool).await?;
+    Ok(pool)
+}
+
+async fn migrate(pool: &SqlitePool) -> Result<()> {
+    sqlx::query(include_str!("../migrations/001_initial_schema.sql")).execute(pool).await?;
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! ETags for the panel's GET endpoints, so the frontend's polling gets `304 Not Modified` instead of a fresh query
+//! and JSON body while nothing changed.
+//!
+//! Database-backed resources carry a version counter bumped by every mutation; the routes endpoint uses the
+//! config generation. Tags also name the process start, so counters starting over after a restart never match a
+//! tag handed out by the previous process.
+
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::http::header::{CacheControl, CacheDirective, ETag, EntityTag, Header, IfNoneMatch};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What a version counter covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Servers,
+    Certificates,
+}
+
+/// Version counters of the database-backed resources, kept in app data
+#[derive(Debug)]
+pub struct Versions {
+    epoch: u64,
+    servers: AtomicU64,
+    certificates: AtomicU64,
+}
+
+impl Versions {
+    pub fn new() -> Self {
+        let epoch = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or_default();
+        Self { epoch, servers: AtomicU64::new(0), certificates: AtomicU64::new(0) }
+    }
+
+    fn counter(&self, resource: Resource) -> &AtomicU64 {
+        match resource {
+            Resource::Servers => &self.servers,
+            Resource::Certificates => &self.certificates,
+        }
+    }
+
+    /// Call after the mutation is committed, so a tag read before it never goes with the changed data
+    pub fn bump(&self, resource: Resource) {
+        self.counter(resource).fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Current tag of `resource`. Read it before querying, so a concurrent mutation can only make the tag older
+    /// than the body (answered again on the next poll), never newer.
+    pub fn etag(&self, resource: Resource) -> EntityTag {
+        let prefix = match resource {
+            Resource::Servers => "s",
+            Resource::Certificates => "c",
+        };
+        EntityTag::new_strong(format!("{:x}-{}{}", self.epoch, prefix, self.counter(resource).load(Ordering::Acquire)))
+    }
+
+    /// Tag of a response built from the config snapshot with this generation
+    pub fn config_etag(&self, generation: u64) -> EntityTag {
+        EntityTag::new_strong(format!("{:x}-g{}", self.epoch, generation))
+    }
+}
+
+impl Default for Versions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `304 Not Modified` when the request's `If-None-Match` names `etag` (or is `*`)
+pub fn not_modified(req: &HttpRequest, etag: &EntityTag) -> Option<HttpResponse> {
+    let matches = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false,
+    };
+    matches.then(|| HttpResponse::NotModified().insert_header(ETag(etag.clone())).insert_header(no_cache()).finish())
+}
+
+/// `200 OK` with `body` as JSON, tagged with `etag`
+pub fn tagged_json(etag: EntityTag, body: impl serde::Serialize) -> HttpResponse {
+    HttpResponse::Ok().insert_header(ETag(etag)).insert_header(no_cache()).json(body)
+}
+
+// Browsers may keep the body but must revalidate it on every request
+fn no_cache() -> CacheControl {
+    CacheControl(vec![CacheDirective::NoCache])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_if_none_match_lists_and_wildcard_match() {
+        let versions = Versions::new();
+        let etag = versions.etag(Resource::Servers);
+        let other = versions.etag(Resource::Certificates);
+        assert_ne!(etag, other);
+
+        let listed = TestRequest::default().insert_header(IfNoneMatch::Items(vec![other.clone(), etag.clone()])).to_http_request();
+        assert!(not_modified(&listed, &etag).is_some());
+        let weak = TestRequest::default().insert_header(("If-None-Match", format!("W/\"{}\"", etag.tag()))).to_http_request();
+        assert!(not_modified(&weak, &etag).is_some());
+        let any = TestRequest::default().insert_header(IfNoneMatch::Any).to_http_request();
+        assert!(not_modified(&any, &etag).is_some());
+
+        assert!(not_modified(&TestRequest::default().to_http_request(), &etag).is_none());
+        versions.bump(Resource::Servers);
+        assert!(not_modified(&listed, &versions.etag(Resource::Servers)).is_none());
+        assert_eq!(versions.etag(Resource::Certificates), other);
+    }
+}
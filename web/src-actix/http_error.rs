@@ -36,12 +36,22 @@ pub mod http_error;
     // Specific error for header parsing failures
     #[error("unable to parse headers: {0:?}")]
     HeaderParse(ToStrError),
+
+    // An upload or field over its size limit
+    #[error("{0}")]
+    TooLarge(String),
+
+    // Something the request needs is not available in this process
+    #[error("{0}")]
+    Unavailable(String),
 }
 
 impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match &self {
             Self::InternalError(_) | Self::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::BAD_REQUEST,
         }
     }
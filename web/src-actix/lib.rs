@@ -2,6 +2,7 @@ use crate::asset_endpoint::AssetsAppConfig;
 use actix_web::{App, HttpResponse, HttpServer, middleware, web};
 use anyhow::Result;
 use log::*;
+use minipx::runtime_tuning::{self, RuntimeTuning};
 use serde_json::json;
 use std::env::set_current_dir;
 use vite_actix::proxy_vite_options::ProxyViteOptions;
@@ -10,18 +11,32 @@ use vite_actix::start_vite_server;
 mod asset_endpoint;
 mod certificate_endpoint;
 mod db;
+mod etag;
 mod http_error;
 mod metrics_endpoint;
 mod models;
+mod proxy_endpoint;
 mod runtime_detector;
 mod runtime_endpoint;
 mod server_endpoint;
 mod test_endpoint;
+mod upload;
 
 pub static DEBUG: bool = cfg!(debug_assertions);
-const PORT: u16 = 6671;
+/// Port the web panel listens on
+pub const PORT: u16 = 6671;
 
+/// Run the panel on its own, with `runtime.web_workers` of ./minipx.json or `MINIPX_WEB_WORKERS` workers, else one per core
 pub async fn run() -> Result<()> {
+    serve(None).await
+}
+
+/// Run the panel inside the proxy process, with the worker count the proxy set aside for it
+pub async fn run_embedded(workers: usize) -> Result<()> {
+    serve(Some(workers)).await
+}
+
+async fn serve(workers: Option<usize>) -> Result<()> {
     // Initialize logging - Ignore any errors here,
     // as we don't want to fail if we can't initialize logging
     let _ = pretty_env_logger::env_logger::builder()
@@ -52,10 +67,29 @@ pub async fn run() -> Result<()> {
         });
     }
 
+    // Read after the working directory is settled, from the config the panel manages
+    let workers = workers.unwrap_or_else(|| {
+        let runtime = runtime_tuning::read_config("./minipx.json");
+        RuntimeTuning::resolve(&runtime, runtime_tuning::available_cores(), false).web_workers
+    });
+    info!("Web panel running with {} workers", workers);
+
     // Initialize database
     let pool = db::init_database().await?;
     info!("Database initialized successfully");
     let pool_data = web::Data::new(pool);
+    let versions_data = web::Data::new(etag::Versions::new());
+    let upload_limits = upload::UploadLimits::from_env();
+    info!("Accepting binary uploads of up to {} bytes", upload_limits.binary_bytes);
+    let upload_data = web::Data::new(upload_limits);
+
+    let log_source = proxy_endpoint::LogSource::detect();
+    info!("Proxy logs: {}", log_source);
+    let log_data = web::Data::new(log_source);
+
+    // Keep the config the routes endpoint serves in step with the file, whoever edits it
+    let config = minipx::config::Config::try_load("./minipx.json").await?;
+    config.watch_config_file();
 
     // Start background system stats refresher
     let stats_tx = metrics_endpoint::spawn_system_stats_refresher();
@@ -66,31 +100,34 @@ pub async fn run() -> Result<()> {
         App::new()
             .app_data(pool_data.clone())
             .app_data(stats_data.clone())
+            .app_data(versions_data.clone())
+            .app_data(upload_data.clone())
+            .app_data(log_data.clone())
             .wrap(middleware::Logger::default())
             .wrap(
                 middleware::DefaultHeaders::new()
                     .add(("Access-Control-Allow-Origin", "*"))
                     .add(("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE, OPTIONS"))
-                    .add(("Access-Control-Allow-Headers", "Content-Type, Authorization")),
+                    .add(("Access-Control-Allow-Headers", "Content-Type, Authorization, If-None-Match"))
+                    .add(("Access-Control-Expose-Headers", "ETag")),
             )
             .app_data(web::JsonConfig::default().limit(8192).error_handler(|err, _req| {
                 let error = json!({ "error": format!("{}", err) });
                 actix_web::error::InternalError::from_response(err, HttpResponse::BadRequest().json(error)).into()
             }))
-            .app_data(
-                actix_multipart::form::MultipartFormConfig::default().total_limit(512 * 1024 * 1024), // 512 MB limit for file uploads
-            )
+            .app_data(actix_multipart::form::MultipartFormConfig::default().total_limit(upload_limits.binary_bytes as usize))
             .service(
                 web::scope("/api")
                     .configure(test_endpoint::configure)
                     .configure(server_endpoint::configure)
                     .configure(certificate_endpoint::configure)
                     .configure(metrics_endpoint::configure)
+                    .configure(proxy_endpoint::configure)
                     .configure(runtime_endpoint::configure),
             )
             .configure_frontend_routes()
     })
-    .workers(4)
+    .workers(workers)
     .bind(format!("0.0.0.0:{port}", port = PORT))?
     .run();
 
@@ -101,3 +138,45 @@ pub async fn run() -> Result<()> {
 
     Ok(stop_result?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minipx::config::RuntimeConfig;
+    use std::time::Duration;
+
+    // Threads of this process whose name starts with `prefix` (Linux truncates names to 15 bytes)
+    #[cfg(target_os = "linux")]
+    fn threads_named(prefix: &str) -> usize {
+        std::fs::read_dir("/proc/self/task")
+            .unwrap()
+            .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+            .filter(|name| name.starts_with(prefix))
+            .count()
+    }
+
+    // Run on a plain tokio runtime, as inside the proxy, where each worker is a thread named `actix-server worker N`
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_the_panel_starts_the_configured_number_of_workers() {
+        let workers = RuntimeTuning::resolve(&RuntimeConfig::new(None, None, Some(3)), runtime_tuning::available_cores(), true).web_workers;
+        let server = HttpServer::new(App::new).workers(workers).bind(("127.0.0.1", 0)).unwrap().run();
+        let handle = server.handle();
+
+        let count = async {
+            let mut started = 0;
+            for _ in 0..100 {
+                started = threads_named("actix-server wo");
+                if started == workers {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            handle.stop(false).await;
+            started
+        };
+        let (served, started) = tokio::join!(server, count);
+        served.unwrap();
+        assert_eq!(started, 3);
+    }
+}
@@ -34,6 +34,15 @@ pub struct CreateServerRequest {
     pub startup_command: Option<String>,
     pub runtime_id: Option<String>,
     pub main_executable: Option<String>,
+    /// Path-based subroutes added to the server's route
+    #[serde(default)]
+    pub subroutes: Vec<CreateSubrouteRequest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateSubrouteRequest {
+    pub path: String,
+    pub port: u16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
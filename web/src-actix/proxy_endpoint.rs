@@ -0,0 +1,349 @@
+use actix_web::http::header::{CacheControl, CacheDirective, ContentDisposition};
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, get, web};
+use anyhow::anyhow;
+use futures_util::stream::{self, Stream, StreamExt};
+use log::{LevelFilter, warn};
+use minipx::config::manager::config_lock;
+use minipx::ipc::{self, IpcRequest, IpcResponse};
+use minipx::log_capture::{self, FileFollower, LogBuffer, LogRecord};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::etag::{Versions, not_modified, tagged_json};
+use crate::http_error::Error;
+
+/// Environment variable naming the file a separately running proxy logs to (`minipx --log-file`)
+pub const PROXY_LOG_FILE_ENV: &str = "MINIPX_WEB_PROXY_LOG_FILE";
+// Records returned when the request does not say
+const DEFAULT_LOG_LINES: usize = 500;
+// Most records one request returns
+const MAX_LOG_LINES: usize = 10_000;
+// How often a followed log file is checked for new records
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+// Longest a log stream stays silent before a keep-alive comment
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/proxy").service(proxy_status).service(list_routes).service(proxy_logs).service(download_logs).service(stream_logs));
+}
+
+/// Where the proxy's log records come from, kept in app data
+#[derive(Debug, Clone)]
+pub enum LogSource {
+    /// Captured in this process, which runs the proxy too
+    Captured(Arc<LogBuffer>),
+    /// Appended to a file by a proxy running in another process
+    File(PathBuf),
+    /// Neither: the proxy runs elsewhere and no log file was named
+    Unavailable,
+}
+
+impl LogSource {
+    /// The records captured in this process when it runs the proxy, else the file named by `MINIPX_WEB_PROXY_LOG_FILE`
+    pub fn detect() -> Self {
+        if let Some(buffer) = log_capture::captured() {
+            return Self::Captured(buffer);
+        }
+        match std::env::var_os(PROXY_LOG_FILE_ENV) {
+            Some(path) if !path.is_empty() => Self::File(PathBuf::from(path)),
+            _ => Self::Unavailable,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Captured(_) => "memory",
+            Self::File(_) => "file",
+            Self::Unavailable => "none",
+        }
+    }
+
+    // The last `lines` records at or above `level`
+    async fn tail(&self, lines: usize, level: LevelFilter) -> Result<Vec<LogRecord>, Error> {
+        match self {
+            Self::Captured(buffer) => Ok(buffer.tail(lines, level)),
+            Self::File(path) => {
+                let file = path.clone();
+                web::block(move || log_capture::tail_file(file, lines, level))
+                    .await
+                    .map_err(|e| Error::from(anyhow!("Failed to read {}: {}", path.display(), e)))?
+                    .map_err(|e| Error::from(anyhow!("Failed to read {}: {}", path.display(), e)))
+            }
+            Self::Unavailable => Err(not_in_process()),
+        }
+    }
+}
+
+fn not_in_process() -> Error {
+    Error::Unavailable(format!("The proxy is not running in this process; start minipx with --log-file and set {} to that file", PROXY_LOG_FILE_ENV))
+}
+
+impl fmt::Display for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Captured(_) => write!(f, "captured in this process"),
+            Self::File(path) => write!(f, "read from {}", path.display()),
+            Self::Unavailable => write!(f, "unavailable (the proxy is not in this process and {} is not set)", PROXY_LOG_FILE_ENV),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LogQuery {
+    lines: Option<usize>,
+    level: Option<String>,
+}
+
+impl LogQuery {
+    // Records at or above this level are returned; all of them when the request does not say
+    fn level(&self) -> Result<LevelFilter, Error> {
+        match &self.level {
+            None => Ok(LevelFilter::Trace),
+            Some(level) => LevelFilter::from_str(level)
+                .map_err(|_| Error::from(anyhow!("Invalid level {:?}: expected error, warn, info, debug or trace", level))),
+        }
+    }
+
+    fn lines(&self, default: usize) -> usize {
+        self.lines.unwrap_or(default).min(MAX_LOG_LINES)
+    }
+}
+
+/// State of the running proxy, including forwarders that failed to bind; `running` is false when no instance answers
+#[get("/status")]
+async fn proxy_status() -> ActixResult<HttpResponse> {
+    match ipc::send_request(IpcRequest::Status).await {
+        Some(IpcResponse::Status { report, config_reload_error, forwarders, .. }) => Ok(HttpResponse::Ok().json(json!({
+            "running": true,
+            "report": report,
+            "config_reload_error": config_reload_error,
+            "forwarders": forwarders,
+        }))),
+        _ => Ok(HttpResponse::Ok().json(json!({ "running": false, "forwarders": [] }))),
+    }
+}
+
+/// Routes of the config file as the panel last loaded it, tagged with the config generation
+#[get("/routes")]
+async fn list_routes(req: HttpRequest, versions: web::Data<Versions>) -> ActixResult<HttpResponse> {
+    let config = config_lock().read().await;
+    let etag = versions.config_etag(config.generation());
+    if let Some(response) = not_modified(&req, &etag) {
+        return Ok(response);
+    }
+
+    let routes: BTreeMap<_, _> = config.get_routes().iter().collect();
+    Ok(tagged_json(etag, json!({ "generation": config.generation(), "routes": routes })))
+}
+
+/// The proxy's most recent log records: `lines` of them (500 unless given) at or above `level`
+#[get("/logs")]
+async fn proxy_logs(source: web::Data<LogSource>, query: web::Query<LogQuery>) -> ActixResult<HttpResponse> {
+    let records = source.tail(query.lines(DEFAULT_LOG_LINES), query.level()?).await?;
+    Ok(HttpResponse::Ok().json(json!({ "source": source.name(), "records": records })))
+}
+
+/// The same records as a text file to save, as many as are kept unless `lines` says otherwise
+#[get("/logs/download")]
+async fn download_logs(source: web::Data<LogSource>, query: web::Query<LogQuery>) -> ActixResult<HttpResponse> {
+    let records = source.tail(query.lines(MAX_LOG_LINES), query.level()?).await?;
+    let body: String = records.iter().map(|record| format!("{}\n", record)).collect();
+    Ok(HttpResponse::Ok().content_type("text/plain; charset=utf-8").insert_header(ContentDisposition::attachment("minipx.log")).body(body))
+}
+
+/// Server-sent events: the last `lines` records (none unless given), then each new one at or above `level`
+#[get("/logs/stream")]
+async fn stream_logs(source: web::Data<LogSource>, query: web::Query<LogQuery>) -> ActixResult<HttpResponse> {
+    let (lines, level) = (query.lines(0), query.level()?);
+    // Following starts before the backlog is read, so nothing logged in between is missed
+    let events = match source.get_ref() {
+        LogSource::Captured(buffer) => {
+            let live = buffer.subscribe();
+            let backlog = source.tail(lines, level).await?;
+            stream::iter(backlog.iter().map(event).collect::<Vec<_>>()).chain(follow_buffer(live, level)).boxed_local()
+        }
+        LogSource::File(path) => {
+            let follower = FileFollower::new(path);
+            let backlog = source.tail(lines, level).await?;
+            stream::iter(backlog.iter().map(event).collect::<Vec<_>>()).chain(follow_file(follower, level)).boxed_local()
+        }
+        LogSource::Unavailable => return Err(not_in_process().into()),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(events.map(Ok::<_, actix_web::Error>)))
+}
+
+fn event(record: &LogRecord) -> Bytes {
+    Bytes::from(format!("data: {}\n\n", serde_json::to_string(record).unwrap_or_default()))
+}
+
+fn keep_alive() -> Bytes {
+    Bytes::from_static(b": keep-alive\n\n")
+}
+
+// Records at or above `level` as they are captured, until the buffer goes away
+fn follow_buffer(live: broadcast::Receiver<LogRecord>, level: LevelFilter) -> impl Stream<Item = Bytes> {
+    stream::unfold(live, move |mut live| async move {
+        loop {
+            let next = match tokio::time::timeout(KEEP_ALIVE, live.recv()).await {
+                Ok(Ok(record)) if record.level <= level => event(&record),
+                Ok(Ok(_)) => continue,
+                Ok(Err(RecvError::Lagged(skipped))) => Bytes::from(format!(": skipped {} records\n\n", skipped)),
+                Ok(Err(RecvError::Closed)) => return None,
+                Err(_) => keep_alive(),
+            };
+            return Some((next, live));
+        }
+    })
+}
+
+// Records at or above `level` as they are appended to the file, until it can no longer be read
+fn follow_file(follower: FileFollower, level: LevelFilter) -> impl Stream<Item = Bytes> {
+    let idle_polls = (KEEP_ALIVE.as_millis() / FOLLOW_INTERVAL.as_millis()) as u32;
+    stream::unfold(follower, move |mut follower| async move {
+        for _ in 0..idle_polls {
+            tokio::time::sleep(FOLLOW_INTERVAL).await;
+            let records = match follower.poll() {
+                Ok(records) => records,
+                Err(e) => {
+                    warn!("Stopped following the proxy log: {}", e);
+                    return None;
+                }
+            };
+            let events: Vec<u8> = records.iter().filter(|record| record.level <= level).flat_map(event).collect();
+            if !events.is_empty() {
+                return Some((Bytes::from(events), follower));
+            }
+        }
+        Some((keep_alive(), follower))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header;
+    use actix_web::{App, test};
+    use log::Level;
+    use minipx::config::{Config, ProxyRoute};
+
+    #[actix_web::test]
+    async fn test_routes_are_tagged_with_the_config_generation() {
+        let dir = std::env::temp_dir().join(format!("minipx-web-routes-{}", std::process::id()));
+        let path = dir.join("minipx.json");
+        let mut config = Config::try_load(&path).await.unwrap();
+        let app = test::init_service(App::new().app_data(web::Data::new(Versions::new())).service(web::scope("/api").configure(configure))).await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/proxy/routes").to_request()).await;
+        let etag = resp.headers().get(header::ETAG).cloned().unwrap();
+        let conditional = || test::TestRequest::get().uri("/api/proxy/routes").insert_header((header::IF_NONE_MATCH, etag.clone())).to_request();
+        assert_eq!(test::call_service(&app, conditional()).await.status(), StatusCode::NOT_MODIFIED);
+
+        config.add_route("app.test".to_string(), ProxyRoute::new("localhost".to_string(), String::new(), 3000, false, None, false)).await.unwrap();
+        config.save().await.unwrap();
+        Config::reload(&path).await.unwrap();
+
+        let resp = test::call_service(&app, conditional()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_ne!(resp.headers().get(header::ETAG), Some(&etag));
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["routes"]["app.test"]["port"], 3000);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    fn messages(body: &serde_json::Value) -> Vec<&str> {
+        body["records"].as_array().unwrap().iter().map(|record| record["message"].as_str().unwrap()).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_logs_are_tailed_and_filtered_by_level() {
+        let buffer = Arc::new(LogBuffer::new(10));
+        for (level, message) in [(Level::Error, "bind failed"), (Level::Info, "started"), (Level::Warn, "backend slow"), (Level::Debug, "detail")] {
+            buffer.push(LogRecord::new(level, "minipx", message));
+        }
+        let app =
+            test::init_service(App::new().app_data(web::Data::new(LogSource::Captured(buffer))).service(web::scope("/api").configure(configure)))
+                .await;
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/api/proxy/logs").to_request()).await;
+        assert_eq!(body["source"], "memory");
+        assert_eq!(messages(&body), ["bind failed", "started", "backend slow", "detail"]);
+        let request = test::TestRequest::get().uri("/api/proxy/logs?lines=1&level=warn").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(messages(&body), ["backend slow"]);
+        assert_eq!(body["records"][0]["level"], "WARN");
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/proxy/logs?level=loud").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/proxy/logs/download?level=warn").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::CONTENT_DISPOSITION).unwrap().to_str().unwrap().starts_with("attachment"));
+        let text = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.lines().last().unwrap().ends_with("WARN  minipx: backend slow"));
+    }
+
+    #[actix_web::test]
+    async fn test_logs_of_a_proxy_elsewhere_need_its_log_file() {
+        let app =
+            test::init_service(App::new().app_data(web::Data::new(LogSource::Unavailable)).service(web::scope("/api").configure(configure))).await;
+        for uri in ["/api/proxy/logs", "/api/proxy/logs/download", "/api/proxy/logs/stream"] {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert!(body["message"].as_str().unwrap().contains(PROXY_LOG_FILE_ENV), "{}", body);
+        }
+
+        let dir = std::env::temp_dir().join(format!("minipx-web-logs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("minipx.log");
+        let lines = [LogRecord::new(Level::Info, "minipx", "started"), LogRecord::new(Level::Error, "minipx", "bind failed")];
+        std::fs::write(&path, lines.iter().map(|record| format!("{}\n", record)).collect::<String>()).unwrap();
+        let app =
+            test::init_service(App::new().app_data(web::Data::new(LogSource::File(path))).service(web::scope("/api").configure(configure))).await;
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/api/proxy/logs?level=error").to_request()).await;
+        assert_eq!(body["source"], "file");
+        assert_eq!(messages(&body), ["bind failed"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[actix_web::test]
+    async fn test_log_stream_sends_the_backlog_then_new_records() {
+        let buffer = Arc::new(LogBuffer::new(10));
+        buffer.push(LogRecord::new(Level::Warn, "minipx", "before"));
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(LogSource::Captured(buffer.clone()))).service(web::scope("/api").configure(configure)),
+        )
+        .await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/proxy/logs/stream?lines=5&level=warn").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "text/event-stream");
+
+        let mut body = std::pin::pin!(resp.into_body());
+        let mut next = async || {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx));
+            let chunk = tokio::time::timeout(Duration::from_secs(5), chunk).await.unwrap().unwrap().unwrap();
+            let data = String::from_utf8(chunk.to_vec()).unwrap();
+            let record: LogRecord = serde_json::from_str(data.strip_prefix("data: ").unwrap().trim_end()).unwrap();
+            record.message
+        };
+        assert_eq!(next().await, "before");
+        buffer.push(LogRecord::new(Level::Info, "minipx", "filtered out"));
+        buffer.push(LogRecord::new(Level::Error, "minipx", "after"));
+        assert_eq!(next().await, "after");
+    }
+}
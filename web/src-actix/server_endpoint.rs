@@ -1,5 +1,5 @@
 use actix_multipart::Multipart;
-use actix_web::{HttpResponse, Result as ActixResult, delete, get, post, put, web};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, delete, get, post, put, web};
 use chrono::Utc;
 use futures_util::StreamExt;
 use log::*;
@@ -8,8 +8,10 @@ use std::fs;
 use std::path::PathBuf;
 use uuid::Uuid;
 
+use crate::etag::{Resource, Versions, not_modified, tagged_json};
 use crate::http_error::Error;
 use crate::models::*;
+use crate::upload::{self, UploadLimits};
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -27,17 +29,32 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 }
 
 #[get("")]
-async fn list_servers(pool: web::Data<SqlitePool>) -> ActixResult<HttpResponse> {
+async fn list_servers(req: HttpRequest, pool: web::Data<SqlitePool>, versions: web::Data<Versions>) -> ActixResult<HttpResponse> {
+    let etag = versions.etag(Resource::Servers);
+    if let Some(response) = not_modified(&req, &etag) {
+        return Ok(response);
+    }
+
     let servers = sqlx::query_as::<_, Server>("SELECT * FROM servers ORDER BY created_at DESC")
         .fetch_all(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
 
-    Ok(HttpResponse::Ok().json(servers))
+    Ok(tagged_json(etag, servers))
 }
 
 #[get("/{id}")]
-async fn get_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> ActixResult<HttpResponse> {
+async fn get_server(
+    req: HttpRequest,
+    pool: web::Data<SqlitePool>,
+    versions: web::Data<Versions>,
+    id: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    let etag = versions.etag(Resource::Servers);
+    if let Some(response) = not_modified(&req, &etag) {
+        return Ok(response);
+    }
+
     let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = ?")
         .bind(id.as_str())
         .fetch_optional(pool.get_ref())
@@ -45,11 +62,11 @@ async fn get_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> Actix
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?
         .ok_or_else(|| Error::from(anyhow::anyhow!("Server not found")))?;
 
-    Ok(HttpResponse::Ok().json(server))
+    Ok(tagged_json(etag, server))
 }
 
 #[post("")]
-async fn create_server(pool: web::Data<SqlitePool>, req: web::Json<CreateServerRequest>) -> ActixResult<HttpResponse> {
+async fn create_server(pool: web::Data<SqlitePool>, versions: web::Data<Versions>, req: web::Json<CreateServerRequest>) -> ActixResult<HttpResponse> {
     let id = Uuid::new_v4().to_string();
     let now = Utc::now().to_rfc3339();
 
@@ -57,6 +74,13 @@ async fn create_server(pool: web::Data<SqlitePool>, req: web::Json<CreateServerR
     let path = req.path.clone().unwrap_or_default();
     let ssl_enabled = req.ssl_enabled.unwrap_or(false);
     let redirect_to_https = req.redirect_to_https.unwrap_or(false);
+    // Checked before anything is created, so an invalid subroute leaves no server behind
+    let subroutes = req
+        .subroutes
+        .iter()
+        .map(|sub| minipx::config::ProxyPathRoute::new(sub.path.clone(), sub.port))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| Error::from(anyhow::anyhow!("Invalid subroute: {}", e)))?;
 
     // Create servers directory if it doesn't exist
     let servers_dir = PathBuf::from("servers").join(&id);
@@ -87,12 +111,14 @@ async fn create_server(pool: web::Data<SqlitePool>, req: web::Json<CreateServerR
     .execute(pool.get_ref())
     .await
     .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Servers);
 
     // Add route to minipx config
     let mut config =
         minipx::config::Config::try_load("./minipx.json").await.map_err(|e| Error::from(anyhow::anyhow!("Failed to load config: {}", e)))?;
 
-    let route = minipx::config::ProxyRoute::new(host.clone(), path.clone(), req.port, ssl_enabled, req.listen_port, redirect_to_https);
+    let route = minipx::config::ProxyRoute::new(host.clone(), path.clone(), req.port, ssl_enabled, req.listen_port, redirect_to_https)
+        .with_subroutes(subroutes);
 
     config.add_route(req.domain.clone(), route).await.map_err(|e| Error::from(anyhow::anyhow!("Failed to add route: {}", e)))?;
 
@@ -109,7 +135,12 @@ async fn create_server(pool: web::Data<SqlitePool>, req: web::Json<CreateServerR
 }
 
 #[put("/{id}")]
-async fn update_server(pool: web::Data<SqlitePool>, id: web::Path<String>, req: web::Json<UpdateServerRequest>) -> ActixResult<HttpResponse> {
+async fn update_server(
+    pool: web::Data<SqlitePool>,
+    versions: web::Data<Versions>,
+    id: web::Path<String>,
+    req: web::Json<UpdateServerRequest>,
+) -> ActixResult<HttpResponse> {
     let now = Utc::now().to_rfc3339();
 
     // Get existing server
@@ -156,6 +187,7 @@ async fn update_server(pool: web::Data<SqlitePool>, id: web::Path<String>, req:
     .execute(pool.get_ref())
     .await
     .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Servers);
 
     // Update minipx config if domain changed
     if domain != existing.domain {
@@ -183,7 +215,7 @@ async fn update_server(pool: web::Data<SqlitePool>, id: web::Path<String>, req:
 }
 
 #[delete("/{id}")]
-async fn delete_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> ActixResult<HttpResponse> {
+async fn delete_server(pool: web::Data<SqlitePool>, versions: web::Data<Versions>, id: web::Path<String>) -> ActixResult<HttpResponse> {
     let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = ?")
         .bind(id.as_str())
         .fetch_optional(pool.get_ref())
@@ -197,6 +229,7 @@ async fn delete_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> Ac
         .execute(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Servers);
 
     // Remove from minipx config
     let mut config =
@@ -214,34 +247,37 @@ async fn delete_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> Ac
 }
 
 #[post("/{id}/start")]
-async fn start_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> ActixResult<HttpResponse> {
+async fn start_server(pool: web::Data<SqlitePool>, versions: web::Data<Versions>, id: web::Path<String>) -> ActixResult<HttpResponse> {
     sqlx::query("UPDATE servers SET status = 'running' WHERE id = ?")
         .bind(id.as_str())
         .execute(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Servers);
 
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Server started"})))
 }
 
 #[post("/{id}/stop")]
-async fn stop_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> ActixResult<HttpResponse> {
+async fn stop_server(pool: web::Data<SqlitePool>, versions: web::Data<Versions>, id: web::Path<String>) -> ActixResult<HttpResponse> {
     sqlx::query("UPDATE servers SET status = 'stopped' WHERE id = ?")
         .bind(id.as_str())
         .execute(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Servers);
 
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Server stopped"})))
 }
 
 #[post("/{id}/restart")]
-async fn restart_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> ActixResult<HttpResponse> {
+async fn restart_server(pool: web::Data<SqlitePool>, versions: web::Data<Versions>, id: web::Path<String>) -> ActixResult<HttpResponse> {
     sqlx::query("UPDATE servers SET status = 'restarting' WHERE id = ?")
         .bind(id.as_str())
         .execute(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Servers);
 
     // In a real implementation, you would actually restart the server process here
 
@@ -252,53 +288,47 @@ async fn restart_server(pool: web::Data<SqlitePool>, id: web::Path<String>) -> A
         .execute(pool.get_ref())
         .await
         .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?;
+    versions.bump(Resource::Servers);
 
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Server restarted"})))
 }
 
 #[post("/upload")]
-async fn upload_binary(_pool: web::Data<SqlitePool>, mut payload: Multipart) -> ActixResult<HttpResponse> {
-    let mut server_id: Option<String> = None;
+async fn upload_binary(pool: web::Data<SqlitePool>, limits: web::Data<UploadLimits>, mut payload: Multipart) -> ActixResult<HttpResponse> {
+    let mut server_dir: Option<PathBuf> = None;
     let mut file_saved = false;
 
     while let Some(item) = payload.next().await {
         let mut field = item.map_err(|e| Error::from(anyhow::anyhow!("Multipart error: {}", e)))?;
         let content_disposition = field.content_disposition();
-        let field_name = content_disposition.and_then(|cd| cd.get_name()).unwrap_or("");
+        let field_name = content_disposition.and_then(|cd| cd.get_name()).map(|s| s.to_string()).unwrap_or_default();
 
         if field_name == "serverId" {
-            let mut data = Vec::new();
-            while let Some(chunk) = field.next().await {
-                let data_chunk = chunk.map_err(|e| Error::from(anyhow::anyhow!("Chunk read error: {}", e)))?;
-                data.extend_from_slice(&data_chunk);
-            }
-            server_id = Some(String::from_utf8_lossy(&data).to_string());
+            // The id becomes a directory name, so it must be one of ours
+            let id = upload::parse_id(&upload::read_text(&mut field).await?)?;
+            let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = ?")
+                .bind(&id)
+                .fetch_optional(pool.get_ref())
+                .await
+                .map_err(|e| Error::from(anyhow::anyhow!("Database error: {}", e)))?
+                .ok_or_else(|| Error::from(anyhow::anyhow!("Server not found")))?;
+            server_dir = Some(PathBuf::from("servers").join(server.id));
         } else if field_name == "file" {
-            let filename = content_disposition.and_then(|cd| cd.get_filename()).unwrap_or("binary");
+            let filename = upload::sanitize_filename(content_disposition.and_then(|cd| cd.get_filename()).unwrap_or("binary"))?;
 
-            if server_id.is_none() {
+            let Some(server_dir) = &server_dir else {
                 return Err(Error::from(anyhow::anyhow!("serverId must be provided before file")).into());
-            }
-
-            let sid = server_id.as_ref().unwrap();
-            let server_dir = PathBuf::from("servers").join(sid);
-            fs::create_dir_all(&server_dir).map_err(|e| Error::from(anyhow::anyhow!("Failed to create directory: {}", e)))?;
+            };
+            fs::create_dir_all(server_dir).map_err(|e| Error::from(anyhow::anyhow!("Failed to create directory: {}", e)))?;
 
-            let filepath = server_dir.join(filename);
-            let mut file = fs::File::create(&filepath).map_err(|e| Error::from(anyhow::anyhow!("Failed to create file: {}", e)))?;
-
-            while let Some(chunk) = field.next().await {
-                let data = chunk.map_err(|e| Error::from(anyhow::anyhow!("Chunk read error: {}", e)))?;
-                use std::io::Write;
-                file.write_all(&data).map_err(|e| Error::from(anyhow::anyhow!("Failed to write file: {}", e)))?;
-            }
+            let filepath = upload::save_field(&mut field, server_dir, &filename, limits.binary_bytes).await?;
 
             // Check if it's an archive and extract if needed
             let extension = filepath.extension().and_then(|s| s.to_str()).unwrap_or("");
             match extension {
                 "7z" | "zip" => {
                     // Extract 7z/zip archive to server directory
-                    sevenz_rust::decompress_file(&filepath, &server_dir)
+                    sevenz_rust::decompress_file(&filepath, server_dir)
                         .map_err(|e| Error::from(anyhow::anyhow!("Failed to extract archive: {}", e)))?;
 
                     // Remove the archive file after extraction
@@ -325,3 +355,93 @@ async fn upload_binary(_pool: web::Data<SqlitePool>, mut payload: Multipart) ->
 
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "File uploaded successfully"})))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{self, HeaderValue};
+    use actix_web::{App, test};
+
+    #[actix_web::test]
+    async fn test_list_answers_304_until_a_server_changes() {
+        let pool = crate::db::memory_database().await.unwrap();
+        sqlx::query("INSERT INTO servers (id, name, domain, port, binary_path, created_at, updated_at) VALUES ('a', 'app', 'app.test', 3000, 'servers/a', '', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool)).app_data(web::Data::new(Versions::new())).service(web::scope("/api").configure(configure)),
+        )
+        .await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/servers").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get(header::ETAG).cloned().unwrap();
+
+        let conditional =
+            |etag: &HeaderValue| test::TestRequest::get().uri("/api/servers").insert_header((header::IF_NONE_MATCH, etag.clone())).to_request();
+        let resp = test::call_service(&app, conditional(&etag)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get(header::ETAG), Some(&etag));
+        assert!(test::read_body(resp).await.is_empty());
+
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/api/servers/a/start").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&app, conditional(&etag)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_ne!(resp.headers().get(header::ETAG), Some(&etag));
+        let servers: Vec<Server> = test::read_body_json(resp).await;
+        assert_eq!(servers[0].status, "running");
+    }
+
+    #[actix_web::test]
+    async fn test_upload_stays_inside_the_server_directory() {
+        let id = uuid::Uuid::new_v4().to_string();
+        let pool = crate::db::memory_database().await.unwrap();
+        sqlx::query(
+            "INSERT INTO servers (id, name, domain, port, binary_path, created_at, updated_at) VALUES (?, 'app', 'app.test', 3000, '', '', '')",
+        )
+        .bind(&id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        let limits = UploadLimits { binary_bytes: 16, ..UploadLimits::default() };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .app_data(web::Data::new(Versions::new()))
+                .app_data(web::Data::new(limits))
+                .service(web::scope("/api").configure(configure)),
+        )
+        .await;
+        let upload = |server_id: &str, filename: &str, data: &[u8]| {
+            let (content_type, body) = upload::multipart_body(&[("serverId", None, server_id.as_bytes()), ("file", Some(filename), data)]);
+            test::TestRequest::post().uri("/api/servers/upload").insert_header((header::CONTENT_TYPE, content_type)).set_payload(body).to_request()
+        };
+        let server_dir = PathBuf::from("servers").join(&id);
+        let escaped = format!("escaped-{}.json", id);
+
+        let resp = test::call_service(&app, upload(&id, &format!("../../{}", escaped), b"{}")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(server_dir.join(&escaped).exists());
+        assert!(!PathBuf::from(&escaped).exists());
+
+        for (server_id, filename) in
+            [(id.as_str(), ".."), (id.as_str(), "bad name"), ("../../etc", "app"), (&uuid::Uuid::new_v4().to_string(), "app")]
+        {
+            let resp = test::call_service(&app, upload(server_id, filename, b"binary")).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{} {}", server_id, filename);
+        }
+
+        let resp = test::call_service(&app, upload(&id, "app", &[0; 17])).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let mut left: Vec<_> = fs::read_dir(&server_dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        left.sort();
+        assert_eq!(left, vec![escaped]);
+
+        fs::remove_dir_all(&server_dir).unwrap();
+        let _ = fs::remove_dir("servers");
+    }
+}
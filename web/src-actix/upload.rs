@@ -0,0 +1,228 @@
+//! Multipart uploads to the panel. Client-supplied filenames are reduced to a safe name inside the target directory,
+//! every field has a size limit, and a file only appears under its final name once the whole field arrived (and, for
+//! certificates and keys, parsed as PEM); until then it is a hidden temp file next to it, removed on failure.
+
+use crate::http_error::Error;
+use actix_multipart::Field;
+use anyhow::anyhow;
+use futures_util::StreamExt;
+use log::warn;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Longest accepted filename, in bytes
+pub const MAX_FILENAME_LEN: usize = 255;
+/// Largest certificate or key file
+pub const MAX_PEM_BYTES: u64 = 64 * 1024;
+/// Largest binary or archive, unless `MINIPX_WEB_MAX_BINARY_BYTES` says otherwise
+pub const DEFAULT_MAX_BINARY_BYTES: u64 = 512 * 1024 * 1024;
+/// Environment variable overriding `DEFAULT_MAX_BINARY_BYTES`, in bytes
+pub const MAX_BINARY_BYTES_ENV: &str = "MINIPX_WEB_MAX_BINARY_BYTES";
+// Largest plain text field (the ids naming what an upload belongs to)
+const MAX_TEXT_BYTES: u64 = 256;
+
+/// Size limits per kind of uploaded field, kept in app data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadLimits {
+    pub binary_bytes: u64,
+    pub pem_bytes: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self { binary_bytes: DEFAULT_MAX_BINARY_BYTES, pem_bytes: MAX_PEM_BYTES }
+    }
+}
+
+impl UploadLimits {
+    /// The defaults, with the binary limit from `MINIPX_WEB_MAX_BINARY_BYTES` when set
+    pub fn from_env() -> Self {
+        let mut limits = Self::default();
+        if let Ok(value) = std::env::var(MAX_BINARY_BYTES_ENV) {
+            match value.trim().parse::<u64>() {
+                Ok(bytes) if bytes > 0 => limits.binary_bytes = bytes,
+                _ => warn!("Ignoring {}={:?}: not a positive number of bytes", MAX_BINARY_BYTES_ENV, value),
+            }
+        }
+        limits
+    }
+}
+
+/// What a PEM field must contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PemKind {
+    /// One or more certificates (a chain)
+    Certificate,
+    /// Exactly one private key
+    PrivateKey,
+}
+
+/// The name an uploaded file is saved under: the last component of the client's filename, which must not be empty,
+/// `.`/`..` or hidden, must fit in `MAX_FILENAME_LEN` bytes and may only use letters, digits, `.`, `-` and `_`
+pub fn sanitize_filename(raw: &str) -> Result<String, Error> {
+    let name = raw.rsplit(['/', '\\']).next().unwrap_or_default();
+    if name.is_empty() || name.starts_with('.') {
+        return Err(Error::from(anyhow!("Invalid filename {:?}: empty or hidden", raw)));
+    }
+    if name.len() > MAX_FILENAME_LEN {
+        return Err(Error::from(anyhow!("Invalid filename: longer than {} bytes", MAX_FILENAME_LEN)));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')) {
+        return Err(Error::from(anyhow!("Invalid filename {:?}: only letters, digits, '.', '-' and '_' are allowed", raw)));
+    }
+    Ok(name.to_string())
+}
+
+/// An id naming a server or certificate, which becomes a directory name: it must be a UUID
+pub fn parse_id(raw: &str) -> Result<String, Error> {
+    Uuid::parse_str(raw.trim()).map(|id| id.to_string()).map_err(|_| Error::from(anyhow!("Invalid id {:?}", raw)))
+}
+
+/// A plain text field, such as an id
+pub async fn read_text(field: &mut Field) -> Result<String, Error> {
+    let data = read_field(field, MAX_TEXT_BYTES).await?;
+    Ok(String::from_utf8_lossy(&data).to_string())
+}
+
+/// The whole field in memory, refused once it grows past `limit` bytes
+pub async fn read_field(field: &mut Field, limit: u64) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| Error::from(anyhow!("Chunk read error: {}", e)))?;
+        if (data.len() + chunk.len()) as u64 > limit {
+            return Err(too_large(field, limit));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Stream the field into `dir/name`, refused once it grows past `limit` bytes. Nothing is written under `name`
+/// unless the whole field arrived.
+pub async fn save_field(field: &mut Field, dir: &Path, name: &str, limit: u64) -> Result<PathBuf, Error> {
+    let mut part = PartFile::create(dir)?;
+    let mut written = 0u64;
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| Error::from(anyhow!("Chunk read error: {}", e)))?;
+        written += chunk.len() as u64;
+        if written > limit {
+            return Err(too_large(field, limit));
+        }
+        part.write(&chunk)?;
+    }
+    part.persist(&dir.join(name))
+}
+
+/// Write `data` to `dir/name` through a temp file, so a reader never sees it half-written
+pub fn write_file(dir: &Path, name: &str, data: &[u8]) -> Result<PathBuf, Error> {
+    let mut part = PartFile::create(dir)?;
+    part.write(data)?;
+    part.persist(&dir.join(name))
+}
+
+/// Check that `data` is PEM holding what `kind` asks for
+pub fn check_pem(data: &[u8], kind: PemKind) -> Result<(), Error> {
+    let blocks = pem::parse_many(data).map_err(|e| Error::from(anyhow!("Invalid PEM: {}", e)))?;
+    match kind {
+        PemKind::Certificate if blocks.is_empty() || blocks.iter().any(|block| block.tag() != "CERTIFICATE") => {
+            Err(Error::from(anyhow!("Expected one or more PEM certificates")))
+        }
+        PemKind::PrivateKey if blocks.len() != 1 || !blocks[0].tag().ends_with("PRIVATE KEY") => {
+            Err(Error::from(anyhow!("Expected a single PEM private key")))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn too_large(field: &Field, limit: u64) -> Error {
+    Error::TooLarge(format!("Field '{}' is larger than {} bytes", field.name().unwrap_or_default(), limit))
+}
+
+// A hidden temp file in the target directory, removed unless persisted under its final name
+struct PartFile {
+    path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl PartFile {
+    fn create(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(format!(".upload-{}.part", Uuid::new_v4()));
+        let file = fs::File::create_new(&path).map_err(|e| Error::from(anyhow!("Failed to create file: {}", e)))?;
+        Ok(Self { path, file: Some(file) })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let file = self.file.as_mut().expect("written after persist");
+        file.write_all(data).map_err(|e| Error::from(anyhow!("Failed to write file: {}", e)))
+    }
+
+    fn persist(mut self, target: &Path) -> Result<PathBuf, Error> {
+        let file = self.file.take().expect("persisted twice");
+        file.sync_all().map_err(|e| Error::from(anyhow!("Failed to write file: {}", e)))?;
+        drop(file);
+        fs::rename(&self.path, target).map_err(|e| Error::from(anyhow!("Failed to move file into place: {}", e)))?;
+        self.path = PathBuf::new();
+        Ok(target.to_path_buf())
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            self.file.take();
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A `multipart/form-data` body of `(name, filename, data)` fields, and its content type
+#[cfg(test)]
+pub fn multipart_body(fields: &[(&str, Option<&str>, &[u8])]) -> (String, Vec<u8>) {
+    const BOUNDARY: &str = "minipx-test-boundary";
+    let mut body = Vec::new();
+    for (name, filename, data) in fields {
+        body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", BOUNDARY, name).as_bytes());
+        if let Some(filename) = filename {
+            body.extend_from_slice(format!("; filename=\"{}\"\r\nContent-Type: application/octet-stream", filename).as_bytes());
+        }
+        body.extend_from_slice(b"\r\n\r\n");
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    (format!("multipart/form-data; boundary={}", BOUNDARY), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filenames_are_reduced_to_a_safe_last_component() {
+        assert_eq!(sanitize_filename("app-1.2_linux.tar.gz").unwrap(), "app-1.2_linux.tar.gz");
+        assert_eq!(sanitize_filename("../../minipx.json").unwrap(), "minipx.json");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\server.exe").unwrap(), "server.exe");
+        for bad in ["", "..", "dir/", "../.env", "bad name.zip", "a;rm -rf.sh", "caf\u{e9}.zip"] {
+            assert!(sanitize_filename(bad).is_err(), "{:?}", bad);
+        }
+        assert!(sanitize_filename(&"a".repeat(MAX_FILENAME_LEN)).is_ok());
+        assert!(sanitize_filename(&"a".repeat(MAX_FILENAME_LEN + 1)).is_err());
+        assert!(parse_id("../../etc").is_err());
+    }
+
+    #[test]
+    fn test_pem_fields_must_hold_what_they_claim() {
+        let block = |tag: &str| pem::encode(&pem::Pem::new(tag, vec![1, 2, 3]));
+        let chain = format!("{}{}", block("CERTIFICATE"), block("CERTIFICATE"));
+        assert!(check_pem(chain.as_bytes(), PemKind::Certificate).is_ok());
+        assert!(check_pem(block("PRIVATE KEY").as_bytes(), PemKind::PrivateKey).is_ok());
+        assert!(check_pem(block("EC PRIVATE KEY").as_bytes(), PemKind::PrivateKey).is_ok());
+
+        assert!(check_pem(b"not a certificate", PemKind::Certificate).is_err());
+        assert!(check_pem(block("PRIVATE KEY").as_bytes(), PemKind::Certificate).is_err());
+        assert!(check_pem(chain.as_bytes(), PemKind::PrivateKey).is_err());
+        assert!(check_pem(b"-----BEGIN CERTIFICATE-----\n!!!\n-----END CERTIFICATE-----\n", PemKind::Certificate).is_err());
+    }
+}